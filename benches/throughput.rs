@@ -95,6 +95,7 @@ fn create_test_policies() -> Vec<Authorization> {
             scope: ztunnel::rbac::RbacScope::Global,
             namespace: "default".into(),
             rules: rules.clone(),
+            dry_run: false,
         });
     }
 
@@ -252,6 +253,79 @@ pub fn throughput(c: &mut Criterion) {
     });
 }
 
+// relay_throughput measures relaying plaintext TCP between two connections, with a buffered copy
+// and with splice.
+pub fn relay_throughput(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut c = c.benchmark_group("relay_throughput");
+
+    let size: usize = 10 * MB;
+    c.throughput(Throughput::Bytes(size as u64));
+
+    c.sample_size(10);
+    c.sampling_mode(SamplingMode::Flat);
+    c.measurement_time(Duration::from_secs(5));
+    for (name, splice) in [("buffered", false), ("splice", true)] {
+        c.bench_with_input(name, &size, |b, size| {
+            b.to_async(&rt).iter(|| relay(*size, splice))
+        });
+    }
+}
+
+async fn relay(size: usize, splice: bool) {
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), server.unwrap().0)
+    }
+    let (mut client, mut downstream) = tcp_pair().await;
+    let (mut upstream, mut server) = tcp_pair().await;
+    let stats = proxy::ConnectionResult::new(
+        proxy::ConnectionId::new(),
+        client.local_addr().unwrap(),
+        server.local_addr().unwrap(),
+        None,
+        std::time::Instant::now(),
+        proxy::ConnectionOpen {
+            reporter: proxy::Reporter::source,
+            source: None,
+            derived_source: None,
+            destination: None,
+            destination_service: None,
+            connection_security_policy: proxy::SecurityPolicy::unknown,
+        },
+        helpers::test_proxy_metrics(),
+        Default::default(),
+    );
+
+    let send = async {
+        client.write_all(&vec![0; size]).await.unwrap();
+        client.shutdown().await.unwrap();
+    };
+    let receive = async {
+        let n = tokio::io::copy(&mut server, &mut tokio::io::sink())
+            .await
+            .unwrap();
+        assert_eq!(n as usize, size);
+        server.shutdown().await.unwrap();
+    };
+    let relay = ztunnel::copy::copy_bidirectional_tcp(
+        &mut downstream,
+        &mut upstream,
+        &stats,
+        ztunnel::copy::CopyConfig {
+            splice,
+            ..Default::default()
+        },
+    );
+    let (_, _, res) = tokio::join!(send, receive, relay);
+    res.unwrap();
+}
+
 pub fn rbac_throughput(c: &mut Criterion) {
     let (env, rt) = initialize_environment(Mode::Read, create_test_policies());
     let mut c = c.benchmark_group("rbac_throughput");
@@ -514,7 +588,7 @@ criterion_group! {
     config = Criterion::default()
         .with_profiler(PProfProfiler::new(100, Output::Protobuf))
         .warm_up_time(Duration::from_millis(1));
-    targets = hbone_connections, latency, throughput, connections, rbac_latency, rbac_throughput, rbac_connections,
+    targets = hbone_connections, latency, throughput, relay_throughput, connections, rbac_latency, rbac_throughput, rbac_connections,
 }
 
 criterion_main!(benches);
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::env;
+use std::path::Path;
 use std::process::Command;
 
 // This build script is used to generate the rust source files that
@@ -24,6 +25,8 @@ fn main() -> Result<(), anyhow::Error> {
         "proto/authorization.proto",
         "proto/citadel.proto",
         "proto/zds.proto",
+        "proto/spire/types.proto",
+        "proto/spire/delegatedidentity.proto",
     ]
     .iter()
     .map(|name| std::env::current_dir().unwrap().join(name))
@@ -71,6 +74,36 @@ fn main() -> Result<(), anyhow::Error> {
         .nth_back(3)
         .unwrap();
 
+    // The eBPF redirection programs are C, compiled with clang as it can target BPF.
+    if env::var_os("CARGO_FEATURE_EBPF_REDIRECT").is_some() {
+        let src = "src/redirect/redirect.bpf.c";
+        let target = match env::var("CARGO_CFG_TARGET_ENDIAN").as_deref() {
+            Ok("big") => "bpfeb",
+            _ => "bpfel",
+        };
+        // The kernel headers include <asm/types.h>, which Debian based systems keep under a
+        // multiarch directory rather than /usr/include. The BPF program doesn't depend on the
+        // architecture of these headers, so the target's is used if installed, else the host's.
+        // BPF_INCLUDE_DIR overrides this, for cross builds or headers in other locations.
+        let include_dir = match env::var("BPF_INCLUDE_DIR") {
+            Ok(dir) => Some(dir),
+            Err(_) => [env::var("TARGET")?, env::var("HOST")?]
+                .iter()
+                .map(|triple| multiarch_include_dir(triple))
+                .find(|dir| Path::new(dir).is_dir()),
+        };
+        let status = Command::new(env::var("CLANG").unwrap_or_else(|_| "clang".to_string()))
+            .args(["-O2", "-g", "-target", target])
+            .args(include_dir.map(|dir| format!("-I{dir}")))
+            .args(["-c", src, "-o"])
+            .arg(format!("{out_dir}/redirect.bpf.o"))
+            .status()?;
+        anyhow::ensure!(status.success(), "failed to compile {src}");
+        println!("cargo:rerun-if-changed={src}");
+        println!("cargo:rerun-if-env-changed=CLANG");
+        println!("cargo:rerun-if-env-changed=BPF_INCLUDE_DIR");
+    }
+
     match Command::new("common/scripts/report_build_info.sh").output() {
         Ok(output) => {
             for line in String::from_utf8(output.stdout).unwrap().lines() {
@@ -95,5 +128,21 @@ fn main() -> Result<(), anyhow::Error> {
         "cargo:rustc-env=ZTUNNEL_BUILD_PROFILE_NAME={}",
         profile_name
     );
+    // Declared here rather than in [lints.rust], which needs a newer toolchain than rust-version;
+    // older cargo versions ignore this instruction.
+    println!("cargo:rustc-check-cfg=cfg(tokio_unstable)");
     Ok(())
 }
+
+// multiarch_include_dir returns the Debian multiarch include directory for the kernel headers of
+// the given target triple, for example /usr/include/x86_64-linux-gnu for x86_64-unknown-linux-musl.
+// The kernel headers are always under the glibc directory, whatever the target's libc.
+fn multiarch_include_dir(triple: &str) -> String {
+    let arch = triple.split('-').next().unwrap_or_default();
+    let multiarch = match arch {
+        "arm" | "armv7" => "arm-linux-gnueabihf".to_string(),
+        "i586" | "i686" => "i386-linux-gnu".to_string(),
+        arch => format!("{arch}-linux-gnu"),
+    };
+    format!("/usr/include/{multiarch}")
+}
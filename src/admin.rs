@@ -15,10 +15,11 @@
 use crate::config::Config;
 use crate::hyper_util::{empty_response, plaintext_response, Server};
 use crate::identity::SecretManager;
+use crate::proxy::connection_manager::{ConnectionManager, LiveConnectionDump};
 use crate::state::DemandProxyState;
 use crate::tls::Certificate;
 use crate::version::BuildInfo;
-use crate::xds::LocalConfig;
+use crate::xds::{LocalConfig, XdsRejects, XdsVersions};
 use crate::{signal, telemetry};
 
 use base64::engine::general_purpose::STANDARD;
@@ -60,12 +61,28 @@ pub trait AdminHandler2: Sync + Send {
     fn handle(&self) -> anyhow::Result<serde_json::Value>;
 }
 
+// ConnectionManagerSource provides the connection managers whose live connections are reported by
+// the /debug/connections endpoint.
+pub trait ConnectionManagerSource: Sync + Send {
+    fn connection_managers(&self) -> Vec<ConnectionManager>;
+}
+
+impl ConnectionManagerSource for ConnectionManager {
+    fn connection_managers(&self) -> Vec<ConnectionManager> {
+        vec![self.clone()]
+    }
+}
+
 struct State {
     proxy_state: DemandProxyState,
     config: Arc<Config>,
     shutdown_trigger: signal::ShutdownTrigger,
+    drain_trigger: signal::DrainTrigger,
     cert_manager: Arc<SecretManager>,
     handlers: Vec<Arc<dyn AdminHandler2>>,
+    connection_sources: Vec<Arc<dyn ConnectionManagerSource>>,
+    xds_rejects: Option<XdsRejects>,
+    xds_versions: Option<XdsVersions>,
 }
 
 pub struct Service {
@@ -106,10 +123,15 @@ impl Service {
         config: Arc<Config>,
         proxy_state: DemandProxyState,
         shutdown_trigger: signal::ShutdownTrigger,
+        drain_trigger: signal::DrainTrigger,
         drain_rx: Watch,
         cert_manager: Arc<SecretManager>,
     ) -> anyhow::Result<Self> {
-        Server::<State>::bind(
+        let tls = match &config.admin_tls {
+            Some(tls) => Some(crate::tls::admin_server_config(&config.tls_settings, tls).await?),
+            None => None,
+        };
+        let mut s = Server::<State>::bind(
             "admin",
             config.admin_addr,
             drain_rx,
@@ -117,12 +139,19 @@ impl Service {
                 config,
                 proxy_state,
                 shutdown_trigger,
+                drain_trigger,
                 cert_manager,
                 handlers: vec![],
+                connection_sources: vec![],
+                xds_rejects: None,
+                xds_versions: None,
             },
         )
-        .await
-        .map(|s| Service { s })
+        .await?;
+        if let Some(tls) = tls {
+            s.set_tls(tls);
+        }
+        Ok(Service { s })
     }
 
     pub fn address(&self) -> SocketAddr {
@@ -133,6 +162,20 @@ impl Service {
         self.s.state_mut().handlers.push(handler);
     }
 
+    pub fn add_connection_source(&mut self, source: Arc<dyn ConnectionManagerSource>) {
+        self.s.state_mut().connection_sources.push(source);
+    }
+
+    /// set_xds_rejects reports the given rejected XDS resources on /debug/xds_rejects.
+    pub fn set_xds_rejects(&mut self, rejects: XdsRejects) {
+        self.s.state_mut().xds_rejects = Some(rejects);
+    }
+
+    /// set_xds_versions reports the versions of the accepted XDS resources on /debug/xds_versions.
+    pub fn set_xds_versions(&mut self, versions: XdsVersions) {
+        self.s.state_mut().xds_versions = Some(versions);
+    }
+
     pub fn spawn(self) {
         self.s.spawn(|state, req| async move {
             match req.uri().path() {
@@ -144,6 +187,10 @@ impl Service {
                     state.config.self_termination_deadline,
                 )
                 .await),
+                "/drain" => Ok(handle_drain(&state.drain_trigger, req)),
+                "/drain/status" => {
+                    handle_drain_status(&state.drain_trigger, &state.connection_sources, req)
+                }
                 "/config_dump" => {
                     handle_config_dump(
                         &state.handlers,
@@ -157,6 +204,9 @@ impl Service {
                     )
                     .await
                 }
+                "/debug/connections" => handle_connections(&state.connection_sources),
+                "/debug/xds_rejects" => handle_xds_rejects(state.xds_rejects.as_ref()),
+                "/debug/xds_versions" => handle_xds_versions(state.xds_versions.as_ref()),
                 "/logging" => Ok(handle_logging(req).await),
                 "/" => Ok(handle_dashboard(req).await),
                 _ => Ok(empty_response(hyper::StatusCode::NOT_FOUND)),
@@ -169,14 +219,31 @@ async fn handle_dashboard(_req: Request<Incoming>) -> Response<Full<Bytes>> {
     let apis = &[
         (
             "debug/pprof/profile",
-            "build profile using the pprof profiler (if supported)",
+            "build profile using the pprof profiler (if supported); set the length with ?seconds=N",
         ),
         (
             "debug/pprof/heap",
             "collect heap profiling data (if supported, requires jmalloc)",
         ),
         ("quitquitquit", "shut down the server"),
+        ("drain", "start draining connections (POST)"),
+        (
+            "drain/status",
+            "report drain progress and the number of remaining connections",
+        ),
         ("config_dump", "dump the current Ztunnel configuration"),
+        (
+            "debug/connections",
+            "list live connections and the bytes transferred on each",
+        ),
+        (
+            "debug/xds_rejects",
+            "list the XDS resources rejected, and why",
+        ),
+        (
+            "debug/xds_versions",
+            "list the version of each XDS resource accepted, and how stale each type is",
+        ),
         ("logging", "query/changing logging levels"),
     ];
 
@@ -245,13 +312,43 @@ async fn dump_certs(cert_manager: &SecretManager) -> Vec<CertsDump> {
     dump
 }
 
-async fn handle_pprof(_req: Request<Incoming>) -> anyhow::Result<Response<Full<Bytes>>> {
+// How long a CPU profile samples for, unless set with the seconds query parameter.
+const DEFAULT_PROFILE_DURATION: Duration = Duration::from_secs(10);
+// Caps the seconds query parameter, so a forgotten request cannot profile indefinitely.
+const MAX_PROFILE_DURATION: Duration = Duration::from_secs(300);
+
+fn profile_duration(query: Option<&str>) -> Result<Duration, String> {
+    let Some(seconds) = query.and_then(|q| {
+        url::form_urlencoded::parse(q.as_bytes())
+            .find(|(k, _)| k == "seconds")
+            .map(|(_, v)| v.into_owned())
+    }) else {
+        return Ok(DEFAULT_PROFILE_DURATION);
+    };
+    let seconds: u64 = seconds
+        .parse()
+        .map_err(|_| format!("invalid seconds: {seconds}"))?;
+    let duration = Duration::from_secs(seconds);
+    if duration.is_zero() || duration > MAX_PROFILE_DURATION {
+        return Err(format!(
+            "seconds must be between 1 and {}",
+            MAX_PROFILE_DURATION.as_secs()
+        ));
+    }
+    Ok(duration)
+}
+
+async fn handle_pprof(req: Request<Incoming>) -> anyhow::Result<Response<Full<Bytes>>> {
+    let duration = match profile_duration(req.uri().query()) {
+        Ok(d) => d,
+        Err(e) => return Ok(plaintext_response(hyper::StatusCode::BAD_REQUEST, e)),
+    };
     let guard = pprof::ProfilerGuardBuilder::default()
         .frequency(1000)
         // .blocklist(&["libc", "libgcc", "pthread", "vdso"])
         .build()?;
 
-    tokio::time::sleep(Duration::from_secs(10)).await;
+    tokio::time::sleep(duration).await;
     let report = guard.report().build()?;
     let profile = report.pprof()?;
 
@@ -283,6 +380,56 @@ async fn handle_server_shutdown(
     }
 }
 
+fn handle_drain(
+    drain_trigger: &signal::DrainTrigger,
+    req: Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    match *req.method() {
+        hyper::Method::POST => {
+            if drain_trigger.start_drain() {
+                info!("drain requested through admin endpoint");
+                plaintext_response(hyper::StatusCode::ACCEPTED, "draining\n".into())
+            } else {
+                plaintext_response(hyper::StatusCode::OK, "already draining\n".into())
+            }
+        }
+        _ => empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DrainStatus {
+    draining: bool,
+    drained: bool,
+    remaining_connections: usize,
+}
+
+fn handle_drain_status(
+    drain_trigger: &signal::DrainTrigger,
+    sources: &[Arc<dyn ConnectionManagerSource>],
+    req: Request<Incoming>,
+) -> anyhow::Result<Response<Full<Bytes>>> {
+    if req.method() != hyper::Method::GET {
+        return Ok(empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let status = DrainStatus {
+        draining: drain_trigger.is_draining(),
+        drained: drain_trigger.is_drained(),
+        remaining_connections: sources
+            .iter()
+            .flat_map(|s| s.connection_managers())
+            .map(|cm| cm.live_connection_count())
+            .sum(),
+    };
+    let body = serde_json::to_string_pretty(&status)?;
+    Ok(Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("builder with known status code should not fail"))
+}
+
 async fn handle_config_dump(
     handlers: &[Arc<dyn AdminHandler2>],
     mut dump: ConfigDump,
@@ -318,6 +465,42 @@ async fn handle_config_dump(
         .expect("builder with known status code should not fail"))
 }
 
+fn handle_connections(
+    sources: &[Arc<dyn ConnectionManagerSource>],
+) -> anyhow::Result<Response<Full<Bytes>>> {
+    let connections: Vec<LiveConnectionDump> = sources
+        .iter()
+        .flat_map(|s| s.connection_managers())
+        .flat_map(|cm| cm.live_connections())
+        .collect();
+    let body = serde_json::to_string_pretty(&connections)?;
+    Ok(Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("builder with known status code should not fail"))
+}
+
+fn handle_xds_rejects(rejects: Option<&XdsRejects>) -> anyhow::Result<Response<Full<Bytes>>> {
+    let rejects = rejects.map(XdsRejects::dump).unwrap_or_default();
+    let body = serde_json::to_string_pretty(&rejects)?;
+    Ok(Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("builder with known status code should not fail"))
+}
+
+fn handle_xds_versions(versions: Option<&XdsVersions>) -> anyhow::Result<Response<Full<Bytes>>> {
+    let versions = versions.map(XdsVersions::dump).unwrap_or_default();
+    let body = serde_json::to_string_pretty(&versions)?;
+    Ok(Response::builder()
+        .status(hyper::StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("builder with known status code should not fail"))
+}
+
 //mirror envoy's behavior: https://www.envoyproxy.io/docs/envoy/latest/operations/admin#post--logging
 //NOTE: multiple query parameters is not supported, for example
 //curl -X POST http://127.0.0.1:15000/logging?"tap=debug&router=debug"
@@ -325,9 +508,11 @@ static HELP_STRING: &str = "
 usage: POST /logging\t\t\t\t\t\t(To list current level)
 usage: POST /logging?level=<level>\t\t\t\t(To change global levels)
 usage: POST /logging?level={mod1}:{level1},{mod2}:{level2}\t(To change specific mods' logging level)
+usage: POST /logging?level=<level>&scope={mod1},{mod2}\t(To change ztunnel mods' logging level)
 
 hint: loglevel:\terror|warn|info|debug|trace|off
 hint: mod_name:\tthe module name, i.e. ztunnel::proxy
+hint: scope:\tthe module name relative to ztunnel, i.e. proxy::inbound
 ";
 async fn handle_logging(req: Request<Incoming>) -> Response<Full<Bytes>> {
     match *req.method() {
@@ -341,7 +526,24 @@ async fn handle_logging(req: Request<Incoming>) -> Response<Full<Bytes>> {
                         .collect()
                 })
                 .unwrap_or_default();
-            let level = qp.get("level").cloned();
+            let level = match (qp.get("level"), qp.get("scope")) {
+                (Some(level), Some(scope)) => match scoped_log_level(scope, level) {
+                    Ok(level) => Some(level),
+                    Err(e) => {
+                        return plaintext_response(
+                            hyper::StatusCode::BAD_REQUEST,
+                            format!("Invalid scope or level provided: {e}\n{HELP_STRING}"),
+                        )
+                    }
+                },
+                (None, Some(_)) => {
+                    return plaintext_response(
+                        hyper::StatusCode::BAD_REQUEST,
+                        format!("A level is required with a scope\n{HELP_STRING}"),
+                    )
+                }
+                (level, None) => level.cloned(),
+            };
             let reset = qp.get("reset").cloned();
             if level.is_some() || reset.is_some() {
                 change_log_level(reset.is_some(), &level.unwrap_or_default())
@@ -369,6 +571,30 @@ fn list_loggers() -> Response<Full<Bytes>> {
     }
 }
 
+// scoped_log_level builds the directives setting each of the comma separated ztunnel modules in
+// `scope` to `level`.
+fn scoped_log_level(scope: &str, level: &str) -> anyhow::Result<String> {
+    if !matches!(level, "off" | "error" | "warn" | "info" | "debug" | "trace") {
+        anyhow::bail!("level {level} is invalid");
+    }
+    let directives: Vec<String> = scope
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if s == "ztunnel" || s.starts_with("ztunnel::") {
+                format!("{s}={level}")
+            } else {
+                format!("ztunnel::{s}={level}")
+            }
+        })
+        .collect();
+    if directives.is_empty() {
+        anyhow::bail!("scope is empty");
+    }
+    Ok(directives.join(","))
+}
+
 fn validate_log_level(level: &str) -> anyhow::Result<()> {
     for clause in level.split(',') {
         // We support 2 forms, compared to the underlying library
@@ -464,6 +690,7 @@ mod tests {
     use crate::xds::istio::security::Authorization as XdsAuthorization;
     use crate::xds::istio::security::Clause as XdsClause;
     use crate::xds::istio::security::Match as XdsMatch;
+    use crate::xds::istio::security::PortRange as XdsPortRange;
     use crate::xds::istio::security::Rule as XdsRule;
     use crate::xds::istio::security::StringMatch as XdsStringMatch;
     use crate::xds::istio::workload::gateway_address::Destination as XdsDestination;
@@ -474,6 +701,7 @@ mod tests {
     use crate::xds::istio::workload::Port as XdsPort;
     use crate::xds::istio::workload::PortList as XdsPortList;
     use crate::xds::istio::workload::Service as XdsService;
+    use crate::xds::istio::workload::TlsOrigination as XdsTlsOrigination;
     use crate::xds::istio::workload::Workload as XdsWorkload;
     use crate::xds::istio::workload::WorkloadType as XdsWorkloadType;
     use bytes::Bytes;
@@ -666,6 +894,11 @@ mod tests {
                 zone: "zone".to_string(),
                 subzone: "subezone".to_string(),
             }),
+            connection_limit: 100,
+            mirror_address: "127.0.0.3:8080".to_string(),
+            excluded_inbound_ports: vec![9090],
+            included_inbound_ports: vec![8080],
+            hbone_quic: true,
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -686,7 +919,14 @@ mod tests {
             load_balancing: Some(XdsLoadBalancing {
                 routing_preference: vec![1, 2],
                 mode: 1,
-            }), // ..Default::default() // intentionally don't default. we want all fields populated
+                strategy: 1,
+            }),
+            session_affinity: 1,
+            tls_origination: Some(XdsTlsOrigination {
+                sni: "svc1.example.com".to_string(),
+                root_certificates: "".to_string(),
+            }),
+            // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
         let auth = XdsAuthorization {
@@ -699,6 +939,14 @@ mod tests {
                     matches: vec![XdsMatch {
                         destination_ports: vec![80],
                         not_destination_ports: vec![8080],
+                        destination_port_ranges: vec![XdsPortRange {
+                            start: 9000,
+                            end: 9099,
+                        }],
+                        not_destination_port_ranges: vec![XdsPortRange {
+                            start: 9050,
+                            end: 9050,
+                        }],
                         source_ips: vec![XdsAddress {
                             address: Bytes::copy_from_slice(&[127, 0, 0, 2]),
                             length: 32,
@@ -734,6 +982,7 @@ mod tests {
                     }],
                 }],
             }],
+            dry_run: false,
             // ..Default::default() // intentionally don't default. we want all fields populated
         };
 
@@ -857,4 +1106,38 @@ mod tests {
         assert!(resp_str
             .contains("current log level is hickory_server::server::server_future=off,off\n"));
     }
+
+    #[test]
+    fn test_scoped_log_level() {
+        assert_eq!(
+            scoped_log_level("proxy::inbound", "debug").unwrap(),
+            "ztunnel::proxy::inbound=debug"
+        );
+        assert_eq!(
+            scoped_log_level("proxy::inbound, ztunnel::dns", "trace").unwrap(),
+            "ztunnel::proxy::inbound=trace,ztunnel::dns=trace"
+        );
+        assert!(validate_log_level(&scoped_log_level("xds", "warn").unwrap()).is_ok());
+        assert!(scoped_log_level("proxy", "verbose").is_err());
+        assert!(scoped_log_level("", "debug").is_err());
+    }
+
+    #[test]
+    fn test_profile_duration() {
+        use super::profile_duration;
+        use std::time::Duration;
+
+        assert_eq!(profile_duration(None).unwrap(), Duration::from_secs(10));
+        assert_eq!(
+            profile_duration(Some("debug=1")).unwrap(),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            profile_duration(Some("seconds=30")).unwrap(),
+            Duration::from_secs(30)
+        );
+        assert!(profile_duration(Some("seconds=0")).is_err());
+        assert!(profile_duration(Some("seconds=3600")).is_err());
+        assert!(profile_duration(Some("seconds=soon")).is_err());
+    }
 }
@@ -19,7 +19,7 @@ use crate::proxyfactory::ProxyFactory;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
 use anyhow::Context;
@@ -28,8 +28,9 @@ use tokio::task::JoinSet;
 use tracing::{warn, Instrument};
 
 use crate::identity::SecretManager;
+use crate::state::snapshot::Snapshotter;
 use crate::state::ProxyStateManager;
-use crate::{admin, config, metrics, proxy, readiness, signal};
+use crate::{admin, config, metrics, proxy, readiness, signal, telemetry};
 use crate::{dns, xds};
 
 pub async fn build_with_cert(
@@ -40,12 +41,32 @@ pub async fn build_with_cert(
     let data_plane_pool = new_data_plane_pool(config.num_worker_threads);
 
     let shutdown = signal::Shutdown::new();
+
+    // Apply the reloadable settings that aren't read on use, now and on every reload.
+    if let Some(level) = config.reloadable.get().log_level {
+        apply_log_level(&level);
+    }
+    let reloadable = config.reloadable.clone();
+    signal::on_reload(move || match reloadable.reload() {
+        Ok(prev) => {
+            let next = reloadable.get();
+            if prev.log_level != next.log_level {
+                apply_log_level(next.log_level.as_deref().unwrap_or_default());
+            }
+            tracing::info!(settings=?next, "configuration reloaded");
+        }
+        Err(e) => warn!("failed to reload configuration, keeping the previous settings: {e}"),
+    });
     // Setup a drain channel. drain_tx is used to trigger a drain, which will complete
     // once all drain_rx handlers are dropped.
     // Any component which wants time to gracefully exit should take in a drain_rx clone,
     // await drain_rx.signaled(), then cleanup.
     // Note: there is still a hard timeout if the draining takes too long
     let (drain_tx, drain_rx) = drain::channel();
+    let drain_trigger = signal::DrainTrigger::new(drain_tx);
+    // The admin server is drained separately, after everything else, so /drain/status stays
+    // available while a drain requested through /drain is in progress.
+    let (admin_drain_tx, admin_drain_rx) = drain::channel();
 
     // Register readiness tasks.
     let ready = readiness::Ready::new();
@@ -61,10 +82,18 @@ pub async fn build_with_cert(
         None
     };
 
+    // Liveness checks are registered as the components they watch are started.
+    let live = readiness::Liveness::new();
+
     // Create and start the readiness server.
-    let readiness_server = readiness::Server::new(config.clone(), drain_rx.clone(), ready.clone())
-        .await
-        .context("readiness server starts")?;
+    let readiness_server = readiness::Server::new(
+        config.clone(),
+        drain_rx.clone(),
+        ready.clone(),
+        live.clone(),
+    )
+    .await
+    .context("readiness server starts")?;
     let readiness_address = readiness_server.address();
     // Run the readiness server in the data plane worker pool.
     data_plane_pool.send(DataPlaneTask {
@@ -79,9 +108,53 @@ pub async fn build_with_cert(
     let mut registry = Registry::default();
     let istio_registry = metrics::sub_registry(&mut registry);
     let _ = metrics::meta::Metrics::new(istio_registry);
+    let process_metrics = metrics::process::Metrics::new(istio_registry);
+    process_metrics.register_runtime("main");
+    let data_plane_process_metrics = process_metrics.clone();
+    data_plane_pool.send(DataPlaneTask {
+        block_shutdown: false,
+        fut: Box::pin(async move {
+            data_plane_process_metrics.register_runtime("data_plane");
+            Ok(())
+        }),
+    })?;
+    tokio::spawn(process_metrics.run());
     let xds_metrics = xds::Metrics::new(istio_registry);
+    cert_manager.register_metrics(istio_registry);
+
+    // Keep the bundles of federated trust domains fresh.
+    if !config.federated_trust_bundles.is_empty() {
+        let fetcher = crate::tls::BundleFetcher::new(
+            &config.tls_settings,
+            config.federated_trust_bundles.clone(),
+            config.federated_trust_bundle_refresh,
+            cert_manager.trust_bundles().clone(),
+        )
+        .await
+        .context("trust bundle fetcher starts")?;
+        tokio::spawn(fetcher.run());
+    }
+
+    // Load CRLs before serving, so revoked peers are rejected from the first connection.
+    if let Some(source) = &config.crl_source {
+        let loader = crate::tls::CrlLoader::new(
+            &config.tls_settings,
+            source.clone(),
+            config.crl_refresh,
+            cert_manager.revocation_lists().clone(),
+        )
+        .await
+        .context("crl loader starts")?;
+        loader.load().await.context("crls load")?;
+        tokio::spawn(loader.run());
+    }
     let proxy_metrics = if config.proxy {
-        Some(proxy::Metrics::new(istio_registry))
+        Some(
+            proxy::Metrics::new(istio_registry).with_label_filter(
+                proxy::metrics::LabelFilter::new(&config.metrics_dropped_labels)
+                    .expect("labels validated by config"),
+            ),
+        )
     } else {
         None
     };
@@ -93,15 +166,48 @@ pub async fn build_with_cert(
 
     let (xds_tx, xds_rx) = tokio::sync::watch::channel(());
     // Create the manager that updates proxy state from XDS.
-    let state_mgr =
-        ProxyStateManager::new(config.clone(), xds_metrics, xds_tx, cert_manager.clone()).await?;
+    let state_mgr = ProxyStateManager::new(
+        config.clone(),
+        xds_metrics,
+        xds_tx,
+        &state_mgr_task,
+        cert_manager.clone(),
+    )
+    .await?
+    .with_liveness(&live, config.liveness_stall_threshold);
     let mut xds_rx_for_task = xds_rx.clone();
     tokio::spawn(async move {
         let _ = xds_rx_for_task.changed().await;
         std::mem::drop(state_mgr_task);
     });
     let state = state_mgr.state();
+    let state_snapshot = config
+        .state_snapshot_path
+        .clone()
+        .filter(|_| config.xds_address.is_some())
+        .map(|path| Snapshotter::new(path, state.clone()));
+
+    // Probe the state lock from a dedicated thread, so a deadlock on it fails liveness.
+    let probe_state = state.clone();
+    live.register_heartbeat("state lock", config.liveness_stall_threshold)
+        .spawn_probe("state", move || {
+            let _guard = probe_state.read();
+        });
+
+    // Beat from the data plane pool, so wedged workers (and with them, the listeners' accept
+    // loops) fail liveness.
+    let data_plane_heartbeat =
+        live.register_heartbeat("data plane", config.liveness_stall_threshold);
+    data_plane_pool.send(DataPlaneTask {
+        block_shutdown: false,
+        fut: Box::pin(async move {
+            data_plane_heartbeat.beat_forever().await;
+            Ok(())
+        }),
+    })?;
 
+    let xds_rejects = state_mgr.xds_rejects();
+    let xds_versions = state_mgr.xds_versions();
     // Run the XDS state manager in the current tokio worker pool.
     tokio::spawn(state_mgr.run());
 
@@ -110,11 +216,18 @@ pub async fn build_with_cert(
         config.clone(),
         state.clone(),
         shutdown.trigger(),
-        drain_rx.clone(),
+        drain_trigger.clone(),
+        admin_drain_rx,
         cert_manager.clone(),
     )
     .await
     .context("admin server starts")?;
+    if let Some(rejects) = xds_rejects {
+        admin_server.set_xds_rejects(rejects);
+    }
+    if let Some(versions) = xds_versions {
+        admin_server.set_xds_versions(versions);
+    }
     let admin_address = admin_server.address();
 
     // Optionally create the HBONE proxy.
@@ -155,6 +268,9 @@ pub async fn build_with_cert(
     } else {
         tracing::info!("proxy mode enabled");
         let proxies = proxy_gen.new_proxies().await?;
+        if let Some(cm) = proxies.connection_manager.clone() {
+            admin_server.add_connection_source(Arc::new(cm));
+        }
         match proxies.proxy {
             Some(proxy) => {
                 proxy_addresses = Some(proxy.addresses());
@@ -205,6 +321,14 @@ pub async fn build_with_cert(
     // Run the admin server in the current tokio worker pool.
     admin_server.spawn();
 
+    let registry = Arc::new(Mutex::new(registry));
+    // Push metrics to an OTLP collector as well, if configured.
+    if let Some(endpoint) = &config.otlp_metrics_endpoint {
+        metrics::otlp::Exporter::new(endpoint, config.otlp_metrics_period, registry.clone())
+            .context("OTLP metrics exporter")?
+            .spawn(drain_rx.clone());
+    }
+
     // Create and start the metrics server.
     let metrics_server = metrics::Server::new(config.clone(), drain_rx.clone(), registry)
         .await
@@ -214,7 +338,9 @@ pub async fn build_with_cert(
     metrics_server.spawn();
 
     Ok(Bound {
-        drain_tx,
+        state_snapshot,
+        drain_trigger,
+        admin_drain_tx,
         shutdown,
         readiness_address,
         admin_address,
@@ -225,6 +351,13 @@ pub async fn build_with_cert(
     })
 }
 
+// apply_log_level resets the log filter to RUST_LOG, then applies `level` on top.
+fn apply_log_level(level: &str) {
+    if let Err(e) = telemetry::set_level(true, level) {
+        warn!("failed to apply log level {level:?}: {e}");
+    }
+}
+
 struct DataPlaneTask {
     block_shutdown: bool,
     fut: Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + Sync + 'static>>,
@@ -347,7 +480,9 @@ pub struct Bound {
     pub udp_dns_proxy_address: Option<SocketAddr>,
 
     pub shutdown: signal::Shutdown,
-    drain_tx: drain::Signal,
+    drain_trigger: signal::DrainTrigger,
+    admin_drain_tx: drain::Signal,
+    state_snapshot: Option<Snapshotter>,
 }
 
 impl Bound {
@@ -355,9 +490,17 @@ impl Bound {
         // Wait for a signal to shutdown from explicit admin shutdown or signal
         self.shutdown.wait().await;
 
+        // Save the state while it is still complete, before connections are drained.
+        if let Some(snapshot) = &self.state_snapshot {
+            if let Err(e) = snapshot.save().await {
+                warn!("failed to save state snapshot: {e:#}");
+            }
+        }
+
         // Start a drain; this will attempt to end all connections
         // or itself be interrupted by a stronger TERM signal, whichever comes first.
-        self.drain_tx.drain().await;
+        self.drain_trigger.drain().await;
+        self.admin_drain_tx.drain().await;
 
         Ok(())
     }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::state::workload::Workload;
 use crate::strng::Strng;
 use hyper::{
     header::{GetAll, ToStrError},
@@ -27,6 +28,27 @@ pub struct Baggage {
     pub revision: Option<Strng>,
 }
 
+// baggage_header builds the baggage sent on HBONE CONNECT requests, describing the source workload so
+// the destination can label its metrics even when it does not know the source from XDS. Unknown
+// values are omitted.
+pub fn baggage_header(source: &Workload, cluster: &str) -> String {
+    let workload_key = format!("k8s.{}.name", source.workload_type);
+    let members = [
+        ("k8s.cluster.name", cluster),
+        ("k8s.namespace.name", source.namespace.as_str()),
+        (workload_key.as_str(), source.workload_name.as_str()),
+        ("service.name", source.canonical_name.as_str()),
+        ("service.version", source.canonical_revision.as_str()),
+    ];
+    members
+        .iter()
+        // Without a workload type, there is no key to report the workload name under
+        .filter(|(k, v)| !v.is_empty() && !k.contains(".."))
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 pub fn parse_baggage_header(headers: GetAll<HeaderValue>) -> Result<Baggage, ToStrError> {
     let mut baggage = Baggage {
         ..Default::default()
@@ -34,23 +56,24 @@ pub fn parse_baggage_header(headers: GetAll<HeaderValue>) -> Result<Baggage, ToS
     for hv in headers.iter() {
         let v = hv.to_str()?;
         v.split(',').for_each(|s| {
-            let parts: Vec<&str> = s.split('=').collect();
-            if parts.len() > 1 {
-                let val = match parts[1] {
-                    "" => None,
-                    s => Some(s.into()),
-                };
-                match parts[0] {
-                    "k8s.cluster.name" => baggage.cluster_id = val,
-                    "k8s.namespace.name" => baggage.namespace = val,
-                    "k8s.deployment.name"
-                    | "k8s.cronjob.name"
-                    | "k8s.pod.name"
-                    | "k8s.job.name" => baggage.workload_name = val,
-                    "service.name" => baggage.service_name = val,
-                    "service.version" => baggage.revision = val,
-                    _ => {}
+            // Members may carry properties after a ';', which we have no use for
+            let member = s.split(';').next().unwrap_or_default();
+            let Some((key, val)) = member.split_once('=') else {
+                return;
+            };
+            let val = match val.trim() {
+                "" => None,
+                s => Some(s.into()),
+            };
+            match key.trim() {
+                "k8s.cluster.name" => baggage.cluster_id = val,
+                "k8s.namespace.name" => baggage.namespace = val,
+                "k8s.deployment.name" | "k8s.cronjob.name" | "k8s.pod.name" | "k8s.job.name" => {
+                    baggage.workload_name = val
                 }
+                "service.name" => baggage.service_name = val,
+                "service.version" => baggage.revision = val,
+                _ => {}
             }
         });
     }
@@ -63,7 +86,8 @@ pub mod tests {
 
     use crate::proxy::BAGGAGE_HEADER;
 
-    use super::parse_baggage_header;
+    use super::{baggage_header, parse_baggage_header};
+    use crate::test_helpers;
 
     #[test]
     fn baggage_parser() -> anyhow::Result<()> {
@@ -131,4 +155,44 @@ pub mod tests {
         assert_eq!(baggage.revision, None);
         Ok(())
     }
+
+    #[test]
+    fn baggage_parser_whitespace_and_properties() -> anyhow::Result<()> {
+        let mut hm = HeaderMap::new();
+        let baggage_str = "k8s.cluster.name = K1 , k8s.namespace.name=NS1;prop=x,other=a=b";
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(baggage_str)?);
+        let baggage = parse_baggage_header(hm.get_all(BAGGAGE_HEADER))?;
+        assert_eq!(baggage.cluster_id, Some("K1".into()));
+        assert_eq!(baggage.namespace, Some("NS1".into()));
+        Ok(())
+    }
+
+    #[test]
+    fn baggage_round_trip() -> anyhow::Result<()> {
+        let mut wl = test_helpers::test_default_workload();
+        wl.namespace = "NS1".into();
+        wl.workload_type = "deployment".into();
+        wl.workload_name = "N1".into();
+        wl.canonical_name = "N2".into();
+        wl.canonical_revision = "".into();
+        let header = baggage_header(&wl, "K1");
+        assert_eq!(
+            header,
+            "k8s.cluster.name=K1,k8s.namespace.name=NS1,k8s.deployment.name=N1,service.name=N2"
+        );
+
+        let mut hm = HeaderMap::new();
+        hm.append(BAGGAGE_HEADER, HeaderValue::from_str(&header)?);
+        let baggage = parse_baggage_header(hm.get_all(BAGGAGE_HEADER))?;
+        assert_eq!(baggage.cluster_id, Some("K1".into()));
+        assert_eq!(baggage.namespace, Some("NS1".into()));
+        assert_eq!(baggage.workload_name, Some("N1".into()));
+        assert_eq!(baggage.service_name, Some("N2".into()));
+        assert_eq!(baggage.revision, None);
+
+        // Without a workload type, there is no key to report the workload name under
+        wl.workload_type = "".into();
+        assert!(!baggage_header(&wl, "K1").contains("N1"));
+        Ok(())
+    }
 }
@@ -15,10 +15,12 @@
 use crate::config;
 use crate::config::ProxyMode;
 use crate::identity::Priority::Warmup;
-use crate::identity::{Identity, Request, SecretManager};
+use crate::identity::{Identity, PrefetchResult, Request, SecretManager};
+use crate::state::events::StateEvent;
 use crate::state::workload::{Protocol, Workload};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, error, info};
 
 /// Responsible for pre-fetching certs for workloads.
@@ -47,10 +49,28 @@ pub fn new(cfg: &config::Config, cert_manager: Arc<SecretManager>) -> Arc<dyn Ce
     }
 }
 
+/// Prefetches certs for workloads as they are added to the state, until the state is dropped.
+/// Missing some events is harmless, as certs are fetched on demand anyway.
+pub async fn prefetch_on_events(
+    fetcher: Arc<dyn CertFetcher>,
+    mut events: broadcast::Receiver<StateEvent>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(StateEvent::WorkloadAdded(w)) => fetcher.prefetch_cert(&w),
+            Ok(_) => {}
+            Err(RecvError::Lagged(n)) => debug!("missed {n} state events, skipping prefetches"),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
 /// A real [CertFetcher] that asynchronously forwards cert pre-fetch requests to a [SecretManager].
 struct CertFetcherImpl {
     proxy_mode: ProxyMode,
     local_node: Option<String>,
+    // If false, certificates are only fetched on demand. They are still forgotten on removal.
+    prefetch: bool,
     tx: mpsc::Sender<Request>,
 }
 
@@ -68,13 +88,17 @@ impl CertFetcherImpl {
                             .await
                         {
                             Ok(_) => {
+                                cert_manager.record_prefetch(PrefetchResult::Success);
                                 debug!("prefetched cert for {:?}", workload_identity.to_string())
                             }
-                            Err(e) => error!(
-                                "unable to prefetch cert for {:?}, skipping, {:?}",
-                                workload_identity.to_string(),
-                                e
-                            ),
+                            Err(e) => {
+                                cert_manager.record_prefetch(PrefetchResult::Failure);
+                                error!(
+                                    "unable to prefetch cert for {:?}, skipping, {:?}",
+                                    workload_identity.to_string(),
+                                    e
+                                )
+                            }
                         }
                     }
                     Request::Forget(workload_identity) => {
@@ -87,6 +111,7 @@ impl CertFetcherImpl {
         Self {
             proxy_mode: cfg.proxy_mode,
             local_node: cfg.local_node.clone(),
+            prefetch: cfg.cert_prefetch,
             tx,
         }
     }
@@ -97,6 +122,7 @@ impl CertFetcherImpl {
     fn should_prefetch_certificate(&self, w: &Workload) -> bool {
         // Only shared mode fetches other workloads's certs
         self.proxy_mode == ProxyMode::Shared &&
+            self.prefetch &&
             // We only get certs for our own node
             Some(w.node.as_ref()) == self.local_node.as_deref() &&
             // If it doesn't support HBONE it *probably* doesn't need a cert.
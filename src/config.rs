@@ -12,11 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::{env, fs};
 
@@ -26,43 +27,159 @@ use hickory_resolver::config::{ResolverConfig, ResolverOpts};
 use hyper::http::uri::InvalidUri;
 use hyper::Uri;
 
-use crate::identity;
 use crate::strng::Strng;
+use crate::{copy, identity, proxy};
 #[cfg(any(test, feature = "testing"))]
 use {crate::test_helpers::MpscAckReceiver, crate::xds::LocalConfig, tokio::sync::Mutex};
 
+/// The config file can hold any of the settings below, keyed by the same names as their
+/// environment variables. Each setting is taken from, in order of precedence:
+///  1. The environment.
+///  2. The config file, if one is set with `--config-file <path>` or CONFIG_FILE.
+///  3. Its default.
+///
+/// Reloadable settings set in the mesh config's proxy metadata take precedence over all of these.
+const CONFIG_FILE: &str = "CONFIG_FILE";
+const CONFIG_FILE_FLAG: &str = "--config-file";
 const ENABLE_PROXY: &str = "ENABLE_PROXY";
 const KUBERNETES_SERVICE_HOST: &str = "KUBERNETES_SERVICE_HOST";
 const NETWORK: &str = "NETWORK";
 const NODE_NAME: &str = "NODE_NAME";
 const PROXY_MODE: &str = "PROXY_MODE";
+const ACCESS_LOG_FORMAT: &str = "ACCESS_LOG_FORMAT";
+const METRICS_DROPPED_LABELS: &str = "METRICS_DROPPED_LABELS";
+const INBOUND_PLAINTEXT_ADDRESSES: &str = "INBOUND_PLAINTEXT_ADDRESSES";
 const INPOD_ENABLED: &str = "INPOD_ENABLED";
 const INPOD_MARK: &str = "INPOD_MARK";
 const INPOD_UDS: &str = "INPOD_UDS";
 const INPOD_PORT_REUSE: &str = "INPOD_PORT_REUSE";
+const ENABLE_EBPF_REDIRECT: &str = "ENABLE_EBPF_REDIRECT";
+const EBPF_CGROUP_PATH: &str = "EBPF_CGROUP_PATH";
 const INSTANCE_IP: &str = "INSTANCE_IP";
+const POD_NAME: &str = "POD_NAME";
+const POD_NAMESPACE: &str = "POD_NAMESPACE";
 const CLUSTER_ID: &str = "CLUSTER_ID";
 const CLUSTER_DOMAIN: &str = "CLUSTER_DOMAIN";
 const LOCAL_XDS_PATH: &str = "LOCAL_XDS_PATH";
+const WORKLOAD_FALLBACK_PATH: &str = "WORKLOAD_FALLBACK_PATH";
+const POD_LABELS_PATH: &str = "POD_LABELS_PATH";
+const STATE_SNAPSHOT_PATH: &str = "STATE_SNAPSHOT_PATH";
+const OTLP_ENDPOINT: &str = "OTLP_ENDPOINT";
+const OTLP_METRICS_ENDPOINT: &str = "OTLP_METRICS_ENDPOINT";
+const OTLP_METRICS_PERIOD: &str = "OTLP_METRICS_PERIOD";
+const MAX_WORKLOADS: &str = "MAX_WORKLOADS";
+const MAX_SERVICES: &str = "MAX_SERVICES";
+const MAX_POLICIES: &str = "MAX_POLICIES";
+const REJECT_OVER_STATE_LIMITS: &str = "REJECT_OVER_STATE_LIMITS";
+const UNIX_SCHEME: &str = "unix://";
 const XDS_ON_DEMAND: &str = "XDS_ON_DEMAND";
+const XDS_RECONNECT_INITIAL_BACKOFF: &str = "XDS_RECONNECT_INITIAL_BACKOFF";
+const XDS_RECONNECT_MAX_BACKOFF: &str = "XDS_RECONNECT_MAX_BACKOFF";
+const XDS_RECONNECT_JITTER: &str = "XDS_RECONNECT_JITTER";
+const XDS_INITIAL_SYNC_TIMEOUT: &str = "XDS_INITIAL_SYNC_TIMEOUT";
+const XDS_PLAINTEXT: &str = "XDS_PLAINTEXT";
+const XDS_FAILOVER_THRESHOLD: &str = "XDS_FAILOVER_THRESHOLD";
+const XDS_FAILBACK_INTERVAL: &str = "XDS_FAILBACK_INTERVAL";
+const XDS_KEEPALIVE_INTERVAL: &str = "XDS_KEEPALIVE_INTERVAL";
+const XDS_KEEPALIVE_TIMEOUT: &str = "XDS_KEEPALIVE_TIMEOUT";
+const XDS_REQUEST_TIMEOUT: &str = "XDS_REQUEST_TIMEOUT";
+const CA_KEEPALIVE_INTERVAL: &str = "CA_KEEPALIVE_INTERVAL";
+const CA_KEEPALIVE_TIMEOUT: &str = "CA_KEEPALIVE_TIMEOUT";
+const CA_REQUEST_TIMEOUT: &str = "CA_REQUEST_TIMEOUT";
+const LIVENESS_STALL_THRESHOLD: &str = "LIVENESS_STALL_THRESHOLD";
 const XDS_ADDRESS: &str = "XDS_ADDRESS";
 const CA_ADDRESS: &str = "CA_ADDRESS";
+const CA_PROVIDER: &str = "CA_PROVIDER";
+const PRIVATE_KEY_STORAGE: &str = "PRIVATE_KEY_STORAGE";
+const KEY_TYPE: &str = "KEY_TYPE";
+const RSA_KEY_SIZE: &str = "RSA_KEY_SIZE";
+const PKCS11_MODULE: &str = "PKCS11_MODULE";
+const PKCS11_SLOT: &str = "PKCS11_SLOT";
+const PKCS11_PIN_FILE: &str = "PKCS11_PIN_FILE";
+const PKCS11_WORKLOAD_KEYS: &str = "PKCS11_WORKLOAD_KEYS";
+const SPIRE_ADMIN_SOCKET: &str = "SPIRE_ADMIN_SOCKET";
 const SECRET_TTL: &str = "SECRET_TTL";
+const CERT_REFRESH_FRACTION: &str = "CERT_REFRESH_FRACTION";
+const CERT_CACHE_MAX_SIZE: &str = "CERT_CACHE_MAX_SIZE";
+const CERT_PREFETCH: &str = "CERT_PREFETCH";
+const CERT_CACHE_TTL: &str = "CERT_CACHE_TTL";
 const FAKE_CA: &str = "FAKE_CA";
 const ZTUNNEL_WORKER_THREADS: &str = "ZTUNNEL_WORKER_THREADS";
 const POOL_MAX_STREAMS_PER_CONNECTION: &str = "POOL_MAX_STREAMS_PER_CONNECTION";
 const POOL_UNUSED_RELEASE_TIMEOUT: &str = "POOL_UNUSED_RELEASE_TIMEOUT";
+const TLS_SESSION_LIFETIME: &str = "TLS_SESSION_LIFETIME";
+const TLS_MIN_VERSION: &str = "TLS_MIN_VERSION";
+const OUTBOUND_UNKNOWN_DESTINATION: &str = "OUTBOUND_UNKNOWN_DESTINATION";
+const OUTBOUND_CONNECT_RETRIES: &str = "OUTBOUND_CONNECT_RETRIES";
+const OUTLIER_CONSECUTIVE_FAILURES: &str = "OUTLIER_CONSECUTIVE_FAILURES";
+const OUTLIER_EJECTION_TIME: &str = "OUTLIER_EJECTION_TIME";
+const TLS_CIPHER_SUITES: &str = "TLS_CIPHER_SUITES";
+const POOL_MAX_CONNECTIONS_PER_DESTINATION: &str = "POOL_MAX_CONNECTIONS_PER_DESTINATION";
+const WORKLOAD_CONNECTION_LIMITS: &str = "WORKLOAD_CONNECTION_LIMITS";
+const CONNECTION_RATE_LIMIT: &str = "CONNECTION_RATE_LIMIT";
+const CONNECTION_RATE_LIMIT_BURST: &str = "CONNECTION_RATE_LIMIT_BURST";
+const MAX_CONNECTIONS: &str = "MAX_CONNECTIONS";
 const ENABLE_ORIG_SRC: &str = "ENABLE_ORIG_SRC";
+const KEEPALIVE_ENABLED: &str = "KEEPALIVE_ENABLED";
+const KEEPALIVE_TIME: &str = "KEEPALIVE_TIME";
+const KEEPALIVE_INTERVAL: &str = "KEEPALIVE_INTERVAL";
+const KEEPALIVE_RETRIES: &str = "KEEPALIVE_RETRIES";
+const IDLE_TIMEOUT: &str = "IDLE_TIMEOUT";
+const LOG_LEVEL: &str = "LOG_LEVEL";
+const APP_SOCKET_NODELAY: &str = "APP_SOCKET_NODELAY";
+const APP_SOCKET_SEND_BUFFER_SIZE: &str = "APP_SOCKET_SEND_BUFFER_SIZE";
+const APP_SOCKET_RECV_BUFFER_SIZE: &str = "APP_SOCKET_RECV_BUFFER_SIZE";
+const APP_SOCKET_TOS: &str = "APP_SOCKET_TOS";
+const MESH_SOCKET_NODELAY: &str = "MESH_SOCKET_NODELAY";
+const MESH_SOCKET_SEND_BUFFER_SIZE: &str = "MESH_SOCKET_SEND_BUFFER_SIZE";
+const MESH_SOCKET_RECV_BUFFER_SIZE: &str = "MESH_SOCKET_RECV_BUFFER_SIZE";
+const MESH_SOCKET_TOS: &str = "MESH_SOCKET_TOS";
+const PROPAGATE_DSCP: &str = "PROPAGATE_DSCP";
+const ENABLE_SPLICE: &str = "ENABLE_SPLICE";
+const RELAY_BUFFER_SIZE: &str = "RELAY_BUFFER_SIZE";
+const DRAIN_TIMEOUT: &str = "DRAIN_TIMEOUT";
 const PROXY_CONFIG: &str = "PROXY_CONFIG";
 
 const UNSTABLE_ENABLE_SOCKS5: &str = "UNSTABLE_ENABLE_SOCKS5";
+const ENABLE_CONNECT_UDP: &str = "ENABLE_CONNECT_UDP";
+const ENABLE_HBONE_QUIC: &str = "ENABLE_HBONE_QUIC";
+const ENABLE_INBOUND_PROXY_PROTOCOL: &str = "ENABLE_INBOUND_PROXY_PROTOCOL";
+const PROXY_PROTOCOL_TRUSTED_CIDRS: &str = "PROXY_PROTOCOL_TRUSTED_CIDRS";
+const INBOUND_PROTOCOL_DETECTION_TIMEOUT: &str = "INBOUND_PROTOCOL_DETECTION_TIMEOUT";
+const INBOUND_FIRST_BYTE_TIMEOUT: &str = "INBOUND_FIRST_BYTE_TIMEOUT";
+const INBOUND_TLS_HANDSHAKE_TIMEOUT: &str = "INBOUND_TLS_HANDSHAKE_TIMEOUT";
+const INBOUND_HBONE_CONNECT_TIMEOUT: &str = "INBOUND_HBONE_CONNECT_TIMEOUT";
+const OUTBOUND_UDS_PATH: &str = "OUTBOUND_UDS_PATH";
+const SOCKS5_UDS_PATH: &str = "SOCKS5_UDS_PATH";
+const UDS_ALLOWED_UIDS: &str = "UDS_ALLOWED_UIDS";
+const SNI_ROUTER_ADDRESS: &str = "SNI_ROUTER_ADDRESS";
+const FORWARD_PROXY_ADDRESS: &str = "FORWARD_PROXY_ADDRESS";
+const ADMIN_TLS_CERT: &str = "ADMIN_TLS_CERT";
+const ADMIN_TLS_KEY: &str = "ADMIN_TLS_KEY";
+const ADMIN_TLS_CLIENT_CA: &str = "ADMIN_TLS_CLIENT_CA";
+const TRUST_DOMAIN_ALIASES: &str = "TRUST_DOMAIN_ALIASES";
+const FEDERATED_TRUST_BUNDLES: &str = "FEDERATED_TRUST_BUNDLES";
+const FEDERATED_TRUST_BUNDLE_REFRESH: &str = "FEDERATED_TRUST_BUNDLE_REFRESH";
+const CRL_SOURCE: &str = "CRL_SOURCE";
+const CRL_REFRESH: &str = "CRL_REFRESH";
 
 const DEFAULT_WORKER_THREADS: u16 = 2;
 const DEFAULT_ADMIN_PORT: u16 = 15000;
 const DEFAULT_READINESS_PORT: u16 = 15021;
 const DEFAULT_STATS_PORT: u16 = 15020;
 const DEFAULT_DNS_PORT: u16 = 15053;
+const DEFAULT_DNS_PROXY_CACHE_SIZE: usize = 1024;
+const DEFAULT_DNS_TLS_PORT: u16 = 853;
+const DEFAULT_OUTBOUND_CONNECT_RETRIES: usize = 2;
+const DEFAULT_OUTLIER_CONSECUTIVE_FAILURES: u32 = 5;
+const DEFAULT_OUTLIER_EJECTION_TIME: Duration = Duration::from_secs(30);
 const DEFAULT_SELFTERM_DEADLINE: Duration = Duration::from_secs(5);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_OTLP_METRICS_PERIOD: Duration = Duration::from_secs(60);
+const DEFAULT_INBOUND_PROTOCOL_DETECTION_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_INBOUND_FIRST_BYTE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_INBOUND_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_INBOUND_HBONE_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const DEFAULT_CLUSTER_ID: &str = "Kubernetes";
 const DEFAULT_CLUSTER_DOMAIN: &str = "cluster.local";
 const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24); // 24 hours
@@ -71,9 +188,41 @@ const DEFAULT_POOL_MAX_STREAMS_PER_CONNECTION: u16 = 100; //Go: 100, Hyper: 200,
 
 const DEFAULT_INPOD_MARK: u32 = 1337;
 
+const DEFAULT_XDS_RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const DEFAULT_XDS_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(15);
+const DEFAULT_XDS_RECONNECT_JITTER: f64 = 1.0;
+const DEFAULT_XDS_FAILOVER_THRESHOLD: u32 = 3;
+const DEFAULT_XDS_FAILBACK_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_LIVENESS_STALL_THRESHOLD: Duration = Duration::from_secs(60);
+// Certificates are renewed once this fraction of their lifetime has elapsed.
+const DEFAULT_CERT_REFRESH_FRACTION: f64 = 0.5;
+// SPIFFE bundle endpoints suggest a refresh hint, usually a few minutes; this is our upper bound.
+const DEFAULT_FEDERATED_TRUST_BUNDLE_REFRESH: Duration = Duration::from_secs(300);
+const DEFAULT_CRL_REFRESH: Duration = Duration::from_secs(300);
+
+// HTTP/2 keepalive defaults for control plane connections. A dead connection is detected within
+// interval + timeout, whether or not a request is in flight.
+const DEFAULT_GRPC_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_GRPC_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_GRPC_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Keepalive defaults; these are intentionally conservative so probing adds no meaningful overhead.
+const DEFAULT_KEEPALIVE_TIME: Duration = Duration::from_secs(180);
+const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(180);
+const DEFAULT_KEEPALIVE_RETRIES: u32 = 9;
+
+// Bounds for RELAY_BUFFER_SIZE. Buffers must be at least as large as the initial buffer, and are
+// allocated per connection direction, so are capped to keep memory use reasonable.
+const MIN_RELAY_BUFFER_SIZE: usize = 1024;
+const MAX_RELAY_BUFFER_SIZE: usize = 1024 * 1024;
+
 const ISTIO_META_PREFIX: &str = "ISTIO_META_";
+const ISTIO_METAJSON_PREFIX: &str = "ISTIO_METAJSON_";
 const DNS_CAPTURE_METADATA: &str = "DNS_CAPTURE";
 const DNS_PROXY_ADDR_METADATA: &str = "DNS_PROXY_ADDR";
+const DNS_PROXY_CACHE_SIZE: &str = "DNS_PROXY_CACHE_SIZE";
+const DNS_UPSTREAM_TLS: &str = "DNS_UPSTREAM_TLS";
+const DNS_UPSTREAM_TLS_FALLBACK: &str = "DNS_UPSTREAM_TLS_FALLBACK";
 
 /// Fetch the XDS/CA root cert file path based on below constants
 const XDS_ROOT_CA_ENV: &str = "XDS_ROOT_CA";
@@ -85,13 +234,142 @@ const CERT_SYSTEM: &str = "SYSTEM";
 const PROXY_MODE_DEDICATED: &str = "dedicated";
 const PROXY_MODE_SHARED: &str = "shared";
 
+const CA_PROVIDER_ISTIOD: &str = "istiod";
+const CA_PROVIDER_SPIRE: &str = "spire";
+
+const KEY_TYPE_ECDSA: &str = "ecdsa";
+const KEY_TYPE_RSA: &str = "rsa";
+const DEFAULT_RSA_KEY_SIZE: u32 = 2048;
+const RSA_KEY_SIZES: [u32; 3] = [2048, 3072, 4096];
+
+const PRIVATE_KEY_STORAGE_HEAP: &str = "heap";
+const PRIVATE_KEY_STORAGE_MEMFD_SECRET: &str = "memfd_secret";
+const DEFAULT_SPIRE_ADMIN_SOCKET: &str = "/run/spire/sockets/admin.sock";
+
+const UNKNOWN_DESTINATION_PASSTHROUGH: &str = "passthrough";
+const UNKNOWN_DESTINATION_DENY: &str = "deny";
+
+const ACCESS_LOG_FORMAT_DEFAULT: &str = "default";
+const ACCESS_LOG_FORMAT_JSON: &str = "json";
+const ACCESS_LOG_FORMAT_TEXT: &str = "text";
+
 #[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
 pub enum RootCert {
     File(PathBuf),
+    /// A directory of PEM encoded roots, which is watched for changes.
+    Directory(PathBuf),
     Static(#[serde(skip)] Bytes),
     Default,
 }
 
+/// CrlSource is where the CRLs peer certificates are checked against are loaded from.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub enum CrlSource {
+    /// A file of PEM or DER encoded CRLs.
+    File(PathBuf),
+    /// An HTTP(S) CRL distribution point.
+    Url(String),
+}
+
+/// AdminTls configures TLS for the admin and stats servers. Clients must present a certificate
+/// issued by the client CA.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct AdminTls {
+    /// The PEM encoded certificate chain served.
+    pub cert: PathBuf,
+    /// The PEM encoded private key of the certificate.
+    pub key: PathBuf,
+    /// The roots client certificates are verified against.
+    pub client_ca: RootCert,
+}
+
+/// Pkcs11Config configures the PKCS#11 token that certificate keys are generated in. Keys never
+/// leave the token; ztunnel only asks it to sign.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Pkcs11Config {
+    /// The PKCS#11 module (shared library) of the token.
+    pub module: PathBuf,
+    /// The slot holding the token.
+    pub slot: u64,
+    /// A file holding the user PIN of the token.
+    pub pin_file: PathBuf,
+    /// Whether keys are generated in the token for every workload, rather than only for
+    /// ztunnel's own identity. Required in shared mode, where ztunnel serves many workloads.
+    pub workload_keys: bool,
+}
+
+/// Reloadable holds the settings that can be changed by a reload.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Reloadable {
+    /// Log filter directives applied on top of RUST_LOG, for example `ztunnel::proxy=debug`.
+    pub log_level: Option<String>,
+    /// If set, proxied connections which transfer no bytes in either direction for this duration
+    /// will be closed. If unset, idle connections are kept open indefinitely.
+    pub idle_timeout: Option<Duration>,
+    /// The format access logs are written in.
+    pub access_log_format: AccessLogFormat,
+}
+
+/// ReloadableConfig holds the current reloadable settings. Clones share them, so every copy of a
+/// Config observes a reload.
+#[derive(Clone, Debug)]
+pub struct ReloadableConfig {
+    current: Arc<RwLock<Reloadable>>,
+    reloaded: Arc<tokio::sync::watch::Sender<()>>,
+}
+
+impl ReloadableConfig {
+    pub fn new(settings: Reloadable) -> Self {
+        let (reloaded, _) = tokio::sync::watch::channel(());
+        ReloadableConfig {
+            current: Arc::new(RwLock::new(settings)),
+            reloaded: Arc::new(reloaded),
+        }
+    }
+
+    pub fn get(&self) -> Reloadable {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.current.read().unwrap().idle_timeout
+    }
+
+    pub fn access_log_format(&self) -> AccessLogFormat {
+        self.current.read().unwrap().access_log_format
+    }
+
+    /// reload re-reads the mesh config, the environment and the config file, and applies the
+    /// reloadable settings. Returns the previous settings.
+    pub fn reload(&self) -> Result<Reloadable, Error> {
+        let settings = with_config_file(read_config_file()?, || {
+            let pc = parse_proxy_config()?;
+            parse_reloadable(&pc)
+        })?;
+        Ok(self.set(settings))
+    }
+
+    /// set replaces the settings and notifies subscribers, returning the previous settings.
+    pub fn set(&self, settings: Reloadable) -> Reloadable {
+        let prev = std::mem::replace(&mut *self.current.write().unwrap(), settings);
+        self.reloaded.send_replace(());
+        prev
+    }
+
+    /// subscribe returns a receiver notified on every reload, even if these settings are
+    /// unchanged, so components can re-read their own sources such as /etc/resolv.conf.
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<()> {
+        self.reloaded.subscribe()
+    }
+}
+
+impl serde::Serialize for ReloadableConfig {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ConfigSource {
     File(PathBuf),
@@ -118,6 +396,225 @@ pub enum ProxyMode {
     Dedicated,
 }
 
+/// CaProvider selects where workload certificates are requested from.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaProvider {
+    /// The Istio CA gRPC API, served by istiod or a compatible CA.
+    #[default]
+    Istiod,
+    /// The Delegated Identity API of a local SPIRE agent.
+    Spire,
+}
+
+/// KeyType is the type of the keys generated for workload certificates.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// ECDSA on the P-256 curve. Much cheaper to handshake with than RSA.
+    #[default]
+    EcdsaP256,
+    /// RSA, of the given size in bits.
+    Rsa { bits: u32 },
+}
+
+/// KeyStorage selects where workload private keys are kept.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyStorage {
+    /// Ordinary process memory.
+    #[default]
+    Heap,
+    /// Memory from memfd_secret(2), which is unmapped from the kernel and unreachable by other
+    /// processes, including through /proc/<pid>/mem. Keys are parsed out of it for each handshake
+    /// that signs with them, at some CPU cost. Requires Linux and the tls-ring feature.
+    MemfdSecret,
+}
+
+/// UnknownDestinationPolicy controls how outbound traffic to destinations outside the mesh is
+/// handled.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownDestinationPolicy {
+    /// Traffic is forwarded to the original destination as plain TCP.
+    #[default]
+    Passthrough,
+    /// Traffic is rejected.
+    Deny,
+}
+
+/// DnsTlsUpstream is an upstream DNS server queried over DNS-over-TLS.
+/// It is written as `ip[:port]#tls_name`, for example `1.1.1.1#cloudflare-dns.com`; the port
+/// defaults to 853.
+#[derive(serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DnsTlsUpstream {
+    pub addr: SocketAddr,
+    /// The name the upstream's certificate is verified against.
+    pub tls_name: String,
+}
+
+impl FromStr for DnsTlsUpstream {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::ProxyConfig(anyhow!("invalid DNS-over-TLS upstream {s}"));
+        let (addr, tls_name) = s.split_once('#').ok_or_else(invalid)?;
+        if tls_name.is_empty() {
+            return Err(invalid());
+        }
+        let addr = match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => SocketAddr::new(addr.parse().map_err(|_| invalid())?, DEFAULT_DNS_TLS_PORT),
+        };
+        Ok(DnsTlsUpstream {
+            addr,
+            tls_name: tls_name.to_string(),
+        })
+    }
+}
+
+/// TlsVersion is a TLS protocol version.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    #[default]
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(Error::ProxyConfig(anyhow!("unsupported TLS version {s}"))),
+        }
+    }
+}
+
+impl std::fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsVersion::Tls12 => write!(f, "1.2"),
+            TlsVersion::Tls13 => write!(f, "1.3"),
+        }
+    }
+}
+
+/// AccessLogFormat controls how access logs are written when a connection completes.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Access logs are emitted through the standard logger, following its format.
+    #[default]
+    Default,
+    /// Access logs are written to stdout as one JSON object per line.
+    Json,
+    /// Access logs are written to stdout in an Envoy-like text format.
+    Text,
+}
+
+/// SocketConfig holds settings applied to proxied TCP connections, both the accepted downstream
+/// socket and the upstream socket we open on its behalf.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketConfig {
+    /// If true, TCP keepalive probes will be sent on idle connections.
+    pub keepalive_enabled: bool,
+    /// How long a connection must be idle before the first keepalive probe is sent.
+    pub keepalive_time: Duration,
+    /// The interval between keepalive probes once probing has started.
+    pub keepalive_interval: Duration,
+    /// How many unanswered probes are sent before the connection is considered dead.
+    pub keepalive_retries: u32,
+    /// Options for sockets facing applications: connections accepted from, or opened to, workloads
+    /// without HBONE.
+    pub app: SocketPolicy,
+    /// Options for sockets facing the mesh: HBONE connections to and from other ztunnels or waypoints.
+    pub mesh: SocketPolicy,
+    /// If true, the DSCP mark of a downstream connection is copied to its upstream connection,
+    /// unless the upstream's class has a fixed TOS.
+    pub propagate_dscp: bool,
+}
+
+/// SocketPolicy holds options applied to one class of proxied TCP sockets. Unset options are left
+/// at their defaults.
+#[derive(serde::Serialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketPolicy {
+    /// Whether TCP_NODELAY is set, disabling Nagle's algorithm.
+    pub nodelay: Option<bool>,
+    /// The SO_SNDBUF size, in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// The SO_RCVBUF size, in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// The IP TOS byte (IPv6 traffic class) to mark packets with. DSCP is the upper 6 bits.
+    pub tos: Option<u8>,
+}
+
+/// GrpcConfig holds settings of a gRPC connection to the control plane.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GrpcConfig {
+    /// How often HTTP/2 pings are sent, even while no request is in flight.
+    pub keepalive_interval: Duration,
+    /// How long a ping may go unacknowledged before the connection is closed.
+    pub keepalive_timeout: Duration,
+    /// How long a request may take. For streams, this bounds how long establishing them may take.
+    pub request_timeout: Duration,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            keepalive_interval: DEFAULT_GRPC_KEEPALIVE_INTERVAL,
+            keepalive_timeout: DEFAULT_GRPC_KEEPALIVE_TIMEOUT,
+            request_timeout: DEFAULT_GRPC_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+impl GrpcConfig {
+    fn parse(
+        keepalive_interval: &str,
+        keepalive_timeout: &str,
+        timeout: &str,
+    ) -> Result<Self, Error> {
+        Ok(GrpcConfig {
+            keepalive_interval: parse_duration_default(
+                keepalive_interval,
+                DEFAULT_GRPC_KEEPALIVE_INTERVAL,
+            )?,
+            keepalive_timeout: parse_duration_default(
+                keepalive_timeout,
+                DEFAULT_GRPC_KEEPALIVE_TIMEOUT,
+            )?,
+            request_timeout: parse_duration_default(timeout, DEFAULT_GRPC_REQUEST_TIMEOUT)?,
+        })
+    }
+
+    fn validate(&self, name: &str, problems: &mut Vec<String>) {
+        if self.keepalive_interval.is_zero()
+            || self.keepalive_timeout.is_zero()
+            || self.request_timeout.is_zero()
+        {
+            problems.push(format!(
+                "{name} keepalive interval, keepalive timeout and request timeout must be non-zero"
+            ));
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        SocketConfig {
+            keepalive_enabled: true,
+            keepalive_time: DEFAULT_KEEPALIVE_TIME,
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+            keepalive_retries: DEFAULT_KEEPALIVE_RETRIES,
+            app: SocketPolicy::default(),
+            mesh: SocketPolicy::default(),
+            propagate_dscp: false,
+        }
+    }
+}
+
 #[derive(serde::Serialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
@@ -145,22 +642,141 @@ pub struct Config {
 
     pub pool_unused_release_timeout: Duration,
 
+    // The maximum number of pooled HBONE connections kept per destination (src/dst key).
+    // Once reached, additional connections are still established when needed, but are not
+    // returned to the pool; they are closed once their streams complete.
+    // If unset, the number of pooled connections is unbounded.
+    pub pool_max_conns_per_destination: Option<usize>,
+
+    // How long HBONE TLS sessions can be resumed for, avoiding full handshakes on reconnects.
+    // If unset, session resumption is disabled.
+    pub tls_session_lifetime: Option<Duration>,
+
+    // The minimum TLS version accepted by, and offered from, all TLS connections, and the cipher
+    // suites enabled for them. Set with TLS_MIN_VERSION and TLS_CIPHER_SUITES, a list of IANA suite
+    // names (for example TLS13_AES_256_GCM_SHA384); by default, the FIPS compatible suites are used.
+    pub tls_settings: crate::tls::TlsSettings,
+
+    // The maximum number of concurrent inbound connections to a workload, keyed by `namespace/name`.
+    // This overrides any limit set on the workload through XDS.
+    // Connections over the limit are rejected when accepted.
+    pub workload_connection_limits: HashMap<String, usize>,
+
+    // The maximum rate, in connections per second, at which a proxy accepts new connections across
+    // all of its listeners. Once reached, accepts are delayed until the rate allows them.
+    // If unset, accepts are not rate limited.
+    pub connection_rate_limit: Option<u32>,
+    // The number of connections that can be accepted in a burst above the connection rate limit.
+    // Defaults to the connection rate limit.
+    pub connection_rate_limit_burst: Option<u32>,
+    // The maximum number of connections a proxy handles at once, across all of its listeners.
+    // Once reached, new connections are reset as soon as they are accepted, so an overloaded proxy
+    // sheds load rather than exhausting its memory or file descriptors. If unset, there is no limit.
+    pub max_connections: Option<usize>,
+
     pub socks5_addr: Option<SocketAddr>,
+    // If set, TLS connections accepted at this address are routed, without being terminated, to an
+    // endpoint of the service named by their SNI.
+    pub sni_router_addr: Option<SocketAddr>,
+    // If set, applications configured with this address as their HTTP_PROXY can reach the mesh with
+    // HTTP CONNECT, without their traffic being captured.
+    pub forward_proxy_addr: Option<SocketAddr>,
+    /// Whether UDP traffic is tunneled over HBONE with CONNECT-UDP (RFC 9298). When enabled, the
+    /// outbound proxy also listens for redirected UDP traffic, and the inbound proxy accepts
+    /// CONNECT-UDP requests.
+    pub enable_connect_udp: bool,
+    /// Whether HBONE is also served, and used, over HTTP/3 (QUIC). When enabled, the inbound proxy
+    /// accepts QUIC on the UDP port of its HBONE address, and TCP traffic to workloads advertising
+    /// support for it is tunneled over QUIC, falling back to HTTP/2 if the QUIC connection fails.
+    /// Experimental; requires the quic feature.
+    pub enable_hbone_quic: bool,
     pub admin_addr: SocketAddr,
     pub stats_addr: SocketAddr,
+    /// If set, the spans of proxied connections are exported to this OTLP collector, over gRPC.
+    /// Exported spans carry the ids of the traceparent headers sent and received, so they
+    /// correlate with the traces of waypoints and applications.
+    pub otlp_endpoint: Option<String>,
+    /// If set, metrics are also pushed to this OTLP collector, over gRPC, every
+    /// otlp_metrics_period. The Prometheus endpoint on stats_addr is served either way.
+    pub otlp_metrics_endpoint: Option<String>,
+    pub otlp_metrics_period: Duration,
+    // If set, the admin and stats servers are served over TLS, and only to clients with a
+    // certificate from the configured CA. Otherwise, anyone able to reach them can.
+    pub admin_tls: Option<AdminTls>,
     pub readiness_addr: SocketAddr,
+    // How long an internal liveness check (state lock, XDS client, data plane runtime) may go
+    // without progress before /healthz/live fails.
+    pub liveness_stall_threshold: Duration,
     pub inbound_addr: SocketAddr,
-    pub inbound_plaintext_addr: SocketAddr,
+    // Whether inbound HBONE connections from the trusted CIDRs must start with a PROXY protocol
+    // (v1 or v2) header, such as from load balancers in front of the node. The source address it
+    // conveys is used as the connection's source, instead of the load balancer's address.
+    pub inbound_proxy_protocol: bool,
+    pub proxy_protocol_trusted_cidrs: Vec<ipnet::IpNet>,
+    // How long to wait for the first byte of an inbound HBONE connection, to check that it is a TLS
+    // handshake. Connections that send something else, or nothing at all (server-first protocols),
+    // are rejected and counted in the protocol mismatch metric. Zero disables the check.
+    pub inbound_protocol_detection_timeout: Duration,
+    // Bounds on each stage of setting up an inbound HBONE connection, so a trickle of half-open
+    // connections cannot hold resources indefinitely: from accept to the first byte received, from
+    // then to the TLS handshake completing, and from then to the first CONNECT request. Connections
+    // exceeding them are closed and counted. Zero disables a timeout.
+    pub inbound_first_byte_timeout: Duration,
+    pub inbound_tls_handshake_timeout: Duration,
+    pub inbound_hbone_connect_timeout: Duration,
+    /// The socket addresses to accept inbound plaintext traffic on. A listener is created for each.
+    pub inbound_plaintext_addr: Vec<SocketAddr>,
     pub outbound_addr: SocketAddr,
+    // If set, outbound connections are also accepted over a unix domain socket at this path, for
+    // redirection schemes that hand off connections over UDS. As the socket carries no addresses,
+    // each connection must start with a PROXY protocol header conveying its original source and
+    // destination.
+    pub outbound_uds_path: Option<PathBuf>,
+    // If set, SOCKS5 connections are also accepted over a unix domain socket at this path. Each
+    // connection must start with a PROXY protocol header conveying its original source.
+    pub socks5_uds_path: Option<PathBuf>,
+    // The uids, besides ztunnel's own, of the processes allowed to hand off connections over the
+    // unix domain socket listeners. Connections from any other peer are rejected before their PROXY
+    // header is read, as the header is trusted to convey the connection's identity.
+    pub uds_allowed_uids: Vec<u32>,
     /// The socket address for the DNS proxy. Only applies if `dns_proxy` is true.
     pub dns_proxy_addr: SocketAddr,
+    /// The maximum number of upstream answers the DNS proxy caches. Answers are cached for their TTL.
+    pub dns_proxy_cache_size: usize,
+    /// If set, the DNS proxy forwards queries it cannot answer to these upstreams over
+    /// DNS-over-TLS, instead of the system resolvers.
+    pub dns_upstream_tls: Vec<DnsTlsUpstream>,
+    /// Whether the DNS proxy falls back to the system resolvers, in plaintext, when none of the
+    /// `dns_upstream_tls` upstreams can be reached.
+    pub dns_upstream_tls_fallback: bool,
 
     /// The network of the node this ztunnel is running on.
     pub network: Strng,
     /// The name of the node this ztunnel is running as.
     pub local_node: Option<String>,
+    /// The name and namespace of our pod, which are sent to XDS in the node metadata.
+    pub pod_name: Option<String>,
+    pub pod_namespace: Option<String>,
+    /// A Downward API file of our pod's labels, which are sent to XDS in the node metadata.
+    pub pod_labels_path: Option<PathBuf>,
     /// The proxy mode of ztunnel, Shared or Dedicated, default to Shared.
     pub proxy_mode: ProxyMode,
+
+    /// Settings that are re-read on reload (SIGHUP), without restarting listeners.
+    pub reloadable: ReloadableConfig,
+    /// High cardinality labels to drop from traffic metrics, reported as "unknown" instead. Access
+    /// logs are not affected.
+    pub metrics_dropped_labels: Vec<String>,
+    /// What the outbound proxy does with traffic to destinations that are not known workloads.
+    pub outbound_unknown_destination: UnknownDestinationPolicy,
+    /// How many other endpoints of a service the outbound proxy tries when connecting to the
+    /// selected endpoint fails, before failing the connection.
+    pub outbound_connect_retries: usize,
+    /// How many connects to an endpoint must fail in a row for it to be ejected from outbound
+    /// endpoint selection. Zero disables outlier detection.
+    pub outlier_consecutive_failures: u32,
+    /// How long an endpoint stays ejected from outbound endpoint selection.
+    pub outlier_ejection_time: Duration,
     /// The local_ip we are running at.
     pub local_ip: Option<IpAddr>,
     /// The Cluster ID of the cluster that his ztunnel belongs to
@@ -173,17 +789,92 @@ pub struct Config {
     pub ca_address: Option<String>,
     /// Root cert for CA TLS verification.
     pub ca_root_cert: RootCert,
+    /// Which CA to request workload certificates from.
+    pub ca_provider: CaProvider,
+    /// Trust domains treated as equivalent to our own, when verifying peers and matching
+    /// authorization policy principals. This allows migrating workloads between trust domains.
+    pub trust_domain_aliases: Vec<Strng>,
+    /// The SPIFFE bundle endpoints of federated trust domains, keyed by trust domain. Peers from
+    /// these trust domains are verified against the bundle fetched from their endpoint.
+    pub federated_trust_bundles: HashMap<Strng, String>,
+    /// How often federated trust bundles are refreshed, unless a bundle asks for a shorter interval.
+    pub federated_trust_bundle_refresh: Duration,
+    /// Where to load CRLs from. If set, inbound peers with a revoked certificate are rejected.
+    pub crl_source: Option<CrlSource>,
+    /// How often CRLs are reloaded.
+    pub crl_refresh: Duration,
+    /// The type of the keys generated for workload certificates. Not used with SPIRE, which
+    /// generates keys itself.
+    pub key_type: KeyType,
+    /// Where workload private keys are kept.
+    pub private_key_storage: KeyStorage,
+    /// If set, certificate keys are generated in a PKCS#11 token. Requires the pkcs11 feature.
+    pub pkcs11: Option<Pkcs11Config>,
+    /// Path to the SPIRE agent admin socket, used when ca_provider is Spire.
+    pub spire_admin_socket: PathBuf,
     /// XDS address to use. If unset, XDS will not be used.
     pub xds_address: Option<String>,
+    /// XDS addresses to fail over to, by priority, when xds_address is persistently unreachable.
+    pub xds_failover_addresses: Vec<String>,
+    /// The number of consecutive attempts to establish the XDS stream that must fail before
+    /// failing over to the next address.
+    pub xds_failover_threshold: u32,
+    /// How long to stay on a failover address before trying xds_address again.
+    pub xds_failback_interval: Duration,
     /// Root cert for XDS TLS verification.
     pub xds_root_cert: RootCert,
     /// TTL for CSR requests
     pub secret_ttl: Duration,
+    /// Fraction of a certificate's lifetime after which it is renewed.
+    pub cert_refresh_fraction: f64,
+    /// Maximum number of identities whose certificates are cached. When full, the least recently
+    /// used identity is evicted. 0 means unbounded.
+    pub cert_cache_max_size: usize,
+    /// If set, certificates of identities not used for this long are evicted.
+    pub cert_cache_ttl: Option<Duration>,
+    /// If true, a shared proxy fetches the certificates of workloads on its node as soon as they
+    /// are known, rather than on their first connection.
+    pub cert_prefetch: bool,
     /// YAML config for local XDS workloads
     #[serde(skip_serializing)]
     pub local_xds_config: Option<ConfigSource>,
+    /// YAML file of workloads to fall back on when a workload is not known from XDS, even
+    /// on-demand. It has the same format as the workloads of the local XDS config.
+    #[serde(skip_serializing)]
+    pub workload_fallback_config: Option<ConfigSource>,
+    /// If set, the workloads, services and policies learned from XDS are saved to this file on
+    /// shutdown, and restored from it on startup until XDS confirms or removes them.
+    pub state_snapshot_path: Option<PathBuf>,
+    /// Maximum number of workloads XDS may add to the state. 0 means unbounded.
+    pub max_workloads: usize,
+    /// Maximum number of services XDS may add to the state. 0 means unbounded.
+    pub max_services: usize,
+    /// Maximum number of authorization policies XDS may add to the state. 0 means unbounded.
+    pub max_policies: usize,
+    /// If true, XDS resources added over one of the limits above are rejected. Otherwise they are
+    /// only logged and counted.
+    pub reject_over_state_limits: bool,
     /// If true, on-demand XDS will be used
     pub xds_on_demand: bool,
+    /// The delay before reconnecting to XDS after the stream fails. Doubles on each consecutive
+    /// failure, up to xds_reconnect_max_backoff.
+    pub xds_reconnect_initial_backoff: Duration,
+    /// The maximum delay before reconnecting to XDS.
+    pub xds_reconnect_max_backoff: Duration,
+    /// The fraction, between 0 and 1, by which reconnect delays are randomly reduced. This spreads
+    /// out reconnects when many clients lose their connection at once, such as during an istiod rollout.
+    /// The default of 1 picks each delay uniformly between zero and the backoff ("full jitter").
+    pub xds_reconnect_jitter: f64,
+    /// How long readiness waits for the initial sync of each watched XDS type before giving up and
+    /// reporting ready anyway. If unset, readiness waits indefinitely.
+    pub xds_initial_sync_timeout: Option<Duration>,
+    /// If true, XDS is fetched without TLS from an http:// address. Only meant for development
+    /// control planes.
+    pub xds_plaintext: bool,
+    /// Settings of the XDS connection.
+    pub xds_grpc: GrpcConfig,
+    /// Settings of the CA connection.
+    pub ca_grpc: GrpcConfig,
 
     /// If true, then use builtin fake CA with self-signed certificates.
     pub fake_ca: bool,
@@ -192,8 +883,13 @@ pub struct Config {
     // How long ztunnel should wait for in-flight requesthandlers to finish processing
     // before giving up when ztunnel is self-terminating (when instructed via the Admin API)
     pub self_termination_deadline: Duration,
+    // How long ztunnel should wait for in-flight proxied connections to complete once draining,
+    // before forcibly closing them.
+    pub drain_timeout: Duration,
 
     pub proxy_metadata: HashMap<String, String>,
+    /// Node metadata set as JSON with ISTIO_METAJSON_ settings, keyed without the prefix.
+    pub proxy_metadata_json: HashMap<String, String>,
 
     /// Specify the number of worker threads the Tokio Runtime will use.
     pub num_worker_threads: usize,
@@ -201,6 +897,17 @@ pub struct Config {
     // If true, then use original source proxying
     pub enable_original_source: Option<bool>,
 
+    /// Socket options applied to proxied connections.
+    pub socket_config: SocketConfig,
+
+    /// Whether plaintext TCP to TCP connections are relayed with splice(2) on Linux, moving data
+    /// between the sockets without copying it to userspace.
+    pub enable_splice: bool,
+
+    /// The size, in bytes, of each direction's buffer when relaying a high traffic connection.
+    /// Larger buffers can improve throughput of bulk transfers, at the cost of memory.
+    pub relay_buffer_size: usize,
+
     // CLI args passed to ztunnel at runtime
     pub proxy_args: String,
 
@@ -214,6 +921,13 @@ pub struct Config {
     pub inpod_uds: PathBuf,
     pub inpod_port_reuse: bool,
     pub inpod_mark: u32,
+    /// Whether ztunnel redirects the traffic of in-pod workloads to its listeners itself, with eBPF
+    /// programs, instead of relying on iptables rules installed by the CNI. Experimental; requires
+    /// the ebpf-redirect feature.
+    pub ebpf_redirect: bool,
+    /// The cgroup v2 directory the eBPF programs redirecting outbound traffic are attached to. It
+    /// must contain the cgroups of the workloads, so is usually the host's cgroup root.
+    pub ebpf_cgroup_path: PathBuf,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -224,6 +938,10 @@ pub enum Error {
     ProxyConfig(anyhow::Error),
     #[error("invalid uri: {0}")]
     InvalidUri(#[from] Arc<InvalidUri>),
+    #[error("error parsing config file {0}: {1}")]
+    ConfigFile(String, anyhow::Error),
+    #[error("invalid config:{}", .0.iter().map(|p| format!("\n  - {p}")).collect::<String>())]
+    Invalid(Vec<String>),
 }
 
 impl From<InvalidUri> for Error {
@@ -232,28 +950,240 @@ impl From<InvalidUri> for Error {
     }
 }
 
+thread_local! {
+    // The settings of the config file being parsed, see with_config_file.
+    static CONFIG_FILE_SETTINGS: RefCell<HashMap<String, String>> = RefCell::default();
+    // Settings tests set in place of environment variables, see tests::with_env.
+    #[cfg(test)]
+    static TEST_ENV: RefCell<HashMap<String, String>> = RefCell::default();
+}
+
+// with_config_file makes the config file settings visible to the parse functions while f runs.
+fn with_config_file<T>(settings: HashMap<String, String>, f: impl FnOnce() -> T) -> T {
+    // Restores the previous settings on drop, so they are restored even if f panics.
+    struct Restore(HashMap<String, String>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CONFIG_FILE_SETTINGS.set(std::mem::take(&mut self.0));
+        }
+    }
+    let _restore = Restore(CONFIG_FILE_SETTINGS.replace(settings));
+    f()
+}
+
+// var looks up a setting in the environment, and then in the config file.
+fn var(key: &str) -> Option<String> {
+    #[cfg(test)]
+    if let Some(val) = TEST_ENV.with_borrow(|env| env.get(key).cloned()) {
+        return Some(val);
+    }
+    env::var(key)
+        .ok()
+        .or_else(|| CONFIG_FILE_SETTINGS.with_borrow(|settings| settings.get(key).cloned()))
+}
+
+// vars_with_prefix returns the settings whose names start with the prefix, with the environment
+// taking precedence over the config file as in var.
+fn vars_with_prefix(prefix: &str) -> HashMap<String, String> {
+    let mut vars: HashMap<String, String> = CONFIG_FILE_SETTINGS.with_borrow(|settings| {
+        settings
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect()
+    });
+    vars.extend(env::vars().filter(|(key, _)| key.starts_with(prefix)));
+    #[cfg(test)]
+    TEST_ENV.with_borrow(|env| {
+        vars.extend(
+            env.iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, val)| (key.clone(), val.clone())),
+        )
+    });
+    vars
+}
+
+/// setting looks up a setting that is read outside of [parse_config], such as the log settings
+/// read while setting up logging, in the environment and then in the config file.
+pub fn setting(key: &str) -> Option<String> {
+    var(key).or_else(|| read_config_file().ok()?.remove(key))
+}
+
+// config_file_path returns the path set with `--config-file <path>`, `--config-file=<path>` or
+// CONFIG_FILE, in that order.
+fn config_file_path(mut args: impl Iterator<Item = String>) -> Option<PathBuf> {
+    while let Some(arg) = args.next() {
+        if arg == CONFIG_FILE_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg
+            .strip_prefix(CONFIG_FILE_FLAG)
+            .and_then(|a| a.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    env::var(CONFIG_FILE)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+}
+
+// read_config_file reads the settings of the config file, if one is set. The file is a YAML (or
+// JSON) map of setting names to values, with lists joined as they are in the environment.
+fn read_config_file() -> Result<HashMap<String, String>, Error> {
+    let Some(path) = config_file_path(env::args().skip(1)) else {
+        return Ok(HashMap::new());
+    };
+    fs::read_to_string(&path)
+        .map_err(anyhow::Error::new)
+        .and_then(|f| parse_config_file(&f))
+        .map_err(|e| Error::ConfigFile(path.display().to_string(), e))
+}
+
+fn parse_config_file(contents: &str) -> anyhow::Result<HashMap<String, String>> {
+    let scalar = |v: serde_yaml::Value| match v {
+        serde_yaml::Value::String(s) => Some(s),
+        serde_yaml::Value::Bool(b) => Some(b.to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+    let settings: Option<HashMap<String, serde_yaml::Value>> = serde_yaml::from_str(contents)?;
+    settings
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| {
+            let value = match value {
+                serde_yaml::Value::Sequence(values) => values
+                    .into_iter()
+                    .map(scalar)
+                    .collect::<Option<Vec<_>>>()
+                    .map(|values| values.join(",")),
+                value => scalar(value),
+            };
+            match value {
+                Some(value) => Ok((key, value)),
+                None => Err(anyhow!(
+                    "{key} must be a string, number, bool or list of them"
+                )),
+            }
+        })
+        .collect()
+}
+
 fn parse<T: FromStr>(env: &str) -> Result<Option<T>, Error> {
-    match env::var(env) {
-        Ok(val) => val
+    match var(env) {
+        Some(val) => val
             .parse()
             .map(|v| Some(v))
             .map_err(|_| Error::EnvVar(env.to_string(), val)),
-        Err(_) => Ok(None),
+        None => Ok(None),
     }
 }
 
+// parse_list parses a comma separated list of values
+fn parse_list<T: FromStr>(env: &str) -> Result<Option<Vec<T>>, Error> {
+    match var(env) {
+        Some(val) => val
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+            .map_err(|_| Error::EnvVar(env.to_string(), val)),
+        None => Ok(None),
+    }
+}
+
+// parse_trust_bundle_endpoints parses a comma separated list of `trust_domain=endpoint` entries,
+// where each endpoint is an https URL.
+fn parse_trust_bundle_endpoints(env: &str) -> Result<HashMap<Strng, String>, Error> {
+    let Some(entries) = parse_list::<String>(env)? else {
+        return Ok(HashMap::new());
+    };
+    entries
+        .into_iter()
+        .filter(|e| !e.is_empty())
+        .map(|e| {
+            let (trust_domain, endpoint) = e
+                .split_once('=')
+                .map(|(td, ep)| (td.trim(), ep.trim()))
+                .filter(|(td, ep)| {
+                    !td.is_empty()
+                        && ep
+                            .parse::<Uri>()
+                            .is_ok_and(|u| u.scheme_str() == Some("https") && u.host().is_some())
+                })
+                .ok_or_else(|| Error::EnvVar(env.to_string(), e.clone()))?;
+            Ok((trust_domain.into(), endpoint.to_string()))
+        })
+        .collect()
+}
+
+// parse_crl_source parses a CRL source, which is either an http(s) URL or a file path.
+fn parse_crl_source(env: &str) -> Result<Option<CrlSource>, Error> {
+    let Some(source) = parse::<String>(env)?.filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    if !source.starts_with("http://") && !source.starts_with("https://") {
+        return Ok(Some(CrlSource::File(PathBuf::from(source))));
+    }
+    match source.parse::<Uri>() {
+        Ok(u) if u.host().is_some() => Ok(Some(CrlSource::Url(source))),
+        _ => Err(Error::EnvVar(env.to_string(), source)),
+    }
+}
+
+// parse_connection_limits parses a comma separated list of `namespace/name=limit` entries
+fn parse_connection_limits(env: &str) -> Result<HashMap<String, usize>, Error> {
+    let Some(entries) = parse_list::<String>(env)? else {
+        return Ok(HashMap::new());
+    };
+    entries
+        .into_iter()
+        .filter(|e| !e.is_empty())
+        .map(|e| {
+            let (workload, limit) = e
+                .split_once('=')
+                .filter(|(w, _)| w.contains('/'))
+                .ok_or_else(|| Error::EnvVar(env.to_string(), e.clone()))?;
+            let limit = limit
+                .trim()
+                .parse()
+                .map_err(|_| Error::EnvVar(env.to_string(), e.clone()))?;
+            Ok((workload.trim().to_string(), limit))
+        })
+        .collect()
+}
+
 fn parse_default<T: FromStr>(env: &str, default: T) -> Result<T, Error> {
     parse(env).map(|v| v.unwrap_or(default))
 }
 
+fn parse_duration(env: &str) -> Result<Option<Duration>, Error> {
+    match parse::<String>(env)? {
+        Some(d) => duration_str::parse(&d)
+            .map(Some)
+            .map_err(|_| Error::EnvVar(env.to_string(), d)),
+        None => Ok(None),
+    }
+}
+
+fn parse_duration_default(env: &str, default: Duration) -> Result<Duration, Error> {
+    parse_duration(env).map(|v| v.unwrap_or(default))
+}
+
 fn parse_args() -> String {
     let cli_args: Vec<String> = env::args().collect();
     cli_args[1..].join(" ")
 }
 
 pub fn parse_config() -> Result<Config, Error> {
-    let pc = parse_proxy_config()?;
-    construct_config(pc)
+    with_config_file(read_config_file()?, || {
+        let pc = parse_proxy_config()?;
+        construct_config(pc)
+    })
 }
 
 fn parse_proxy_config() -> Result<ProxyConfig, Error> {
@@ -263,17 +1193,71 @@ fn parse_proxy_config() -> Result<ProxyConfig, Error> {
     construct_proxy_config(mesh_config_path, pc_env).map_err(Error::ProxyConfig)
 }
 
+// Reloadable settings are looked up in the mesh config's proxy metadata before the environment,
+// since unlike the environment the mesh config can change while ztunnel is running.
+fn parse_reloadable_setting(pc: &ProxyConfig, key: &str) -> Option<String> {
+    pc.proxy_metadata.get(key).cloned().or_else(|| var(key))
+}
+
+fn parse_reloadable(pc: &ProxyConfig) -> Result<Reloadable, Error> {
+    let idle_timeout = match parse_reloadable_setting(pc, IDLE_TIMEOUT) {
+        Some(d) => {
+            Some(duration_str::parse(&d).map_err(|_| Error::EnvVar(IDLE_TIMEOUT.to_string(), d))?)
+        }
+        None => None,
+    };
+    if idle_timeout.is_some_and(|t| t.is_zero()) {
+        return Err(Error::ProxyConfig(anyhow!(
+            "idle timeout must be non-zero if set"
+        )));
+    }
+    Ok(Reloadable {
+        log_level: parse_reloadable_setting(pc, LOG_LEVEL).filter(|l| !l.is_empty()),
+        idle_timeout,
+        access_log_format: match parse_reloadable_setting(pc, ACCESS_LOG_FORMAT) {
+            Some(format) => match format.as_str() {
+                ACCESS_LOG_FORMAT_DEFAULT => AccessLogFormat::Default,
+                ACCESS_LOG_FORMAT_JSON => AccessLogFormat::Json,
+                ACCESS_LOG_FORMAT_TEXT => AccessLogFormat::Text,
+                _ => return Err(Error::EnvVar(ACCESS_LOG_FORMAT.to_string(), format)),
+            },
+            None => AccessLogFormat::Default,
+        },
+    })
+}
+
 pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
     let default_istiod_address = if env::var(KUBERNETES_SERVICE_HOST).is_ok() {
         "https://istiod.istio-system.svc:15012".to_string()
     } else {
         "https://localhost:15012".to_string()
     };
-    let xds_address = validate_uri(empty_to_none(
-        parse(XDS_ADDRESS)?
-            .or(pc.discovery_address)
-            .or_else(|| Some(default_istiod_address.clone())),
-    ))?;
+    // XDS_ADDRESS may list several addresses, by priority; the ones after the first are only used
+    // for failover.
+    let (xds_address, xds_failover_addresses) = match parse_list::<String>(XDS_ADDRESS)? {
+        Some(mut addresses) => {
+            let primary = addresses.remove(0);
+            (Some(primary), addresses)
+        }
+        None => (
+            pc.discovery_address
+                .or_else(|| Some(default_istiod_address.clone())),
+            vec![],
+        ),
+    };
+    let xds_address = validate_uri(empty_to_none(xds_address))?;
+    let xds_failover_addresses = xds_failover_addresses
+        .into_iter()
+        .filter_map(|a| validate_uri(empty_to_none(Some(a))).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Collectors are usually reached over plaintext, so unlike the XDS and CA addresses there is
+    // no https default.
+    let otlp_endpoint = empty_to_none(parse::<String>(OTLP_ENDPOINT)?);
+    let otlp_metrics_endpoint = empty_to_none(parse::<String>(OTLP_METRICS_ENDPOINT)?);
+    for endpoint in otlp_endpoint.iter().chain(&otlp_metrics_endpoint) {
+        Uri::try_from(endpoint)?;
+    }
 
     let istio_meta_cluster_id = ISTIO_META_PREFIX.to_owned() + CLUSTER_ID;
     let cluster_id: String = match parse::<String>(&istio_meta_cluster_id)? {
@@ -291,7 +1275,9 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
 
     let xds_root_cert_provider =
         parse_default(XDS_ROOT_CA_ENV, DEFAULT_ROOT_CERT_PROVIDER.to_string())?;
-    let xds_root_cert = if Path::new(&xds_root_cert_provider).exists() {
+    let xds_root_cert = if Path::new(&xds_root_cert_provider).is_dir() {
+        RootCert::Directory(xds_root_cert_provider.into())
+    } else if Path::new(&xds_root_cert_provider).exists() {
         RootCert::File(xds_root_cert_provider.into())
     } else if xds_root_cert_provider.eq(&CERT_SYSTEM.to_string()) {
         // handle SYSTEM special case for xds
@@ -302,7 +1288,9 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
 
     let ca_root_cert_provider =
         parse_default(CA_ROOT_CA_ENV, DEFAULT_ROOT_CERT_PROVIDER.to_string())?;
-    let ca_root_cert = if Path::new(&ca_root_cert_provider).exists() {
+    let ca_root_cert = if Path::new(&ca_root_cert_provider).is_dir() {
+        RootCert::Directory(ca_root_cert_provider.into())
+    } else if Path::new(&ca_root_cert_provider).exists() {
         RootCert::File(ca_root_cert_provider.into())
     } else if ca_root_cert_provider.eq(&CERT_SYSTEM.to_string()) {
         // handle SYSTEM special case for ca
@@ -311,6 +1299,49 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         RootCert::Static(Bytes::from(ca_root_cert_provider))
     };
 
+    let admin_tls = match (
+        parse::<PathBuf>(ADMIN_TLS_CERT)?,
+        parse::<PathBuf>(ADMIN_TLS_KEY)?,
+    ) {
+        (Some(cert), Some(key)) => Some(AdminTls {
+            cert,
+            key,
+            // Default to the mesh root, so mesh workloads can be authorized to scrape
+            client_ca: match parse::<PathBuf>(ADMIN_TLS_CLIENT_CA)? {
+                Some(ca) if ca.is_dir() => RootCert::Directory(ca),
+                Some(ca) => RootCert::File(ca),
+                None => ca_root_cert.clone(),
+            },
+        }),
+        (None, None) => None,
+        // The certificate and key must be set together
+        (Some(_), None) => return Err(Error::EnvVar(ADMIN_TLS_KEY.to_string(), String::new())),
+        (None, Some(_)) => return Err(Error::EnvVar(ADMIN_TLS_CERT.to_string(), String::new())),
+    };
+
+    let pkcs11 = match parse::<PathBuf>(PKCS11_MODULE)? {
+        Some(module) => Some(Pkcs11Config {
+            module,
+            slot: parse(PKCS11_SLOT)?
+                .ok_or_else(|| Error::EnvVar(PKCS11_SLOT.to_string(), String::new()))?,
+            pin_file: parse(PKCS11_PIN_FILE)?
+                .ok_or_else(|| Error::EnvVar(PKCS11_PIN_FILE.to_string(), String::new()))?,
+            workload_keys: parse_default(PKCS11_WORKLOAD_KEYS, false)?,
+        }),
+        None => None,
+    };
+
+    // Aliases from the mesh config and the environment are merged, like istiod does.
+    let mut trust_domain_aliases: Vec<Strng> = pc
+        .trust_domain_aliases
+        .into_iter()
+        .chain(parse_list::<String>(TRUST_DOMAIN_ALIASES)?.unwrap_or_default())
+        .filter(|td| !td.is_empty())
+        .map(Strng::from)
+        .collect();
+    trust_domain_aliases.sort();
+    trust_domain_aliases.dedup();
+
     let auth = match std::fs::read(DEFAULT_TOKEN_PROVIDER) {
         Ok(_) => {
             identity::AuthSource::Token(PathBuf::from(DEFAULT_TOKEN_PROVIDER), cluster_id.clone())
@@ -334,6 +1365,12 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         None
     };
 
+    let tls_settings = crate::tls::TlsSettings::new(
+        parse_default(TLS_MIN_VERSION, TlsVersion::default())?,
+        &parse_list::<String>(TLS_CIPHER_SUITES)?.unwrap_or_default(),
+    )
+    .map_err(|e| Error::Invalid(vec![e.to_string()]))?;
+
     validate_config(Config {
         proxy: parse_default(ENABLE_PROXY, true)?,
         dns_proxy: pc
@@ -350,12 +1387,24 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             Some(ttl) => duration_str::parse(ttl).unwrap_or(DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT),
             None => DEFAULT_POOL_UNUSED_RELEASE_TIMEOUT,
         },
+        pool_max_conns_per_destination: parse(POOL_MAX_CONNECTIONS_PER_DESTINATION)?,
+        tls_session_lifetime: parse_duration(TLS_SESSION_LIFETIME)?.filter(|d| !d.is_zero()),
+        tls_settings,
+        workload_connection_limits: parse_connection_limits(WORKLOAD_CONNECTION_LIMITS)?,
+        connection_rate_limit: parse(CONNECTION_RATE_LIMIT)?,
+        connection_rate_limit_burst: parse(CONNECTION_RATE_LIMIT_BURST)?,
+        max_connections: parse(MAX_CONNECTIONS)?,
 
         window_size: 4 * 1024 * 1024,
         connection_window_size: 4 * 1024 * 1024,
         frame_size: 1024 * 1024,
 
         self_termination_deadline: DEFAULT_SELFTERM_DEADLINE,
+        drain_timeout: parse_duration_default(
+            DRAIN_TIMEOUT,
+            pc.termination_drain_duration
+                .unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+        )?,
 
         // admin API should only be accessible over localhost
         // todo: bind to both v4 localhost and v6
@@ -367,19 +1416,62 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             IpAddr::V6(Ipv6Addr::UNSPECIFIED),
             pc.stats_port.unwrap_or(DEFAULT_STATS_PORT),
         ),
+        otlp_endpoint,
+        otlp_metrics_endpoint,
+        otlp_metrics_period: parse_duration_default(
+            OTLP_METRICS_PERIOD,
+            DEFAULT_OTLP_METRICS_PERIOD,
+        )?,
+        admin_tls,
         readiness_addr: SocketAddr::new(
             IpAddr::V6(Ipv6Addr::UNSPECIFIED),
             DEFAULT_READINESS_PORT, // There is no config for this in ProxyConfig currently
         ),
+        liveness_stall_threshold: parse_duration_default(
+            LIVENESS_STALL_THRESHOLD,
+            DEFAULT_LIVENESS_STALL_THRESHOLD,
+        )?,
 
         socks5_addr,
+        sni_router_addr: parse(SNI_ROUTER_ADDRESS)?,
+        forward_proxy_addr: parse(FORWARD_PROXY_ADDRESS)?,
+        enable_connect_udp: parse_default(ENABLE_CONNECT_UDP, false)?,
+        enable_hbone_quic: parse_default(ENABLE_HBONE_QUIC, false)?,
         inbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15008),
-        inbound_plaintext_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15006),
+        inbound_proxy_protocol: parse_default(ENABLE_INBOUND_PROXY_PROTOCOL, false)?,
+        proxy_protocol_trusted_cidrs: parse_list(PROXY_PROTOCOL_TRUSTED_CIDRS)?.unwrap_or_default(),
+        inbound_protocol_detection_timeout: parse_duration_default(
+            INBOUND_PROTOCOL_DETECTION_TIMEOUT,
+            DEFAULT_INBOUND_PROTOCOL_DETECTION_TIMEOUT,
+        )?,
+        inbound_first_byte_timeout: parse_duration_default(
+            INBOUND_FIRST_BYTE_TIMEOUT,
+            DEFAULT_INBOUND_FIRST_BYTE_TIMEOUT,
+        )?,
+        inbound_tls_handshake_timeout: parse_duration_default(
+            INBOUND_TLS_HANDSHAKE_TIMEOUT,
+            DEFAULT_INBOUND_TLS_HANDSHAKE_TIMEOUT,
+        )?,
+        inbound_hbone_connect_timeout: parse_duration_default(
+            INBOUND_HBONE_CONNECT_TIMEOUT,
+            DEFAULT_INBOUND_HBONE_CONNECT_TIMEOUT,
+        )?,
+        inbound_plaintext_addr: parse_list(INBOUND_PLAINTEXT_ADDRESSES)?
+            .unwrap_or_else(|| vec![SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15006)]),
         outbound_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 15001),
+        outbound_uds_path: parse(OUTBOUND_UDS_PATH)?,
+        socks5_uds_path: parse(SOCKS5_UDS_PATH)?,
+        uds_allowed_uids: parse_list(UDS_ALLOWED_UIDS)?.unwrap_or_default(),
         dns_proxy_addr,
+        dns_proxy_cache_size: parse_default(DNS_PROXY_CACHE_SIZE, DEFAULT_DNS_PROXY_CACHE_SIZE)?,
+        dns_upstream_tls: parse_list(DNS_UPSTREAM_TLS)?.unwrap_or_default(),
+        dns_upstream_tls_fallback: parse_default(DNS_UPSTREAM_TLS_FALLBACK, true)?,
 
         network: parse(NETWORK)?.unwrap_or_default(),
         local_node: parse(NODE_NAME)?,
+        pod_name: parse(POD_NAME)?,
+        pod_namespace: parse(POD_NAMESPACE)?,
+        pod_labels_path: parse(POD_LABELS_PATH)?,
         proxy_mode: match parse::<String>(PROXY_MODE)? {
             Some(proxy_mode) => match proxy_mode.as_str() {
                 PROXY_MODE_DEDICATED => ProxyMode::Dedicated,
@@ -388,21 +1480,146 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
             },
             None => ProxyMode::Shared,
         },
+        reloadable: ReloadableConfig::new(parse_reloadable(&pc)?),
+        metrics_dropped_labels: {
+            let labels = parse_list::<String>(METRICS_DROPPED_LABELS)?.unwrap_or_default();
+            let labels: Vec<String> = labels.into_iter().filter(|l| !l.is_empty()).collect();
+            if let Err(label) = proxy::metrics::LabelFilter::new(&labels) {
+                return Err(Error::EnvVar(METRICS_DROPPED_LABELS.to_string(), label));
+            }
+            labels
+        },
+        outbound_unknown_destination: match parse::<String>(OUTBOUND_UNKNOWN_DESTINATION)? {
+            Some(policy) => match policy.as_str() {
+                UNKNOWN_DESTINATION_PASSTHROUGH => UnknownDestinationPolicy::Passthrough,
+                UNKNOWN_DESTINATION_DENY => UnknownDestinationPolicy::Deny,
+                _ => {
+                    return Err(Error::EnvVar(
+                        OUTBOUND_UNKNOWN_DESTINATION.to_string(),
+                        policy,
+                    ))
+                }
+            },
+            None => UnknownDestinationPolicy::Passthrough,
+        },
+        outbound_connect_retries: parse_default(
+            OUTBOUND_CONNECT_RETRIES,
+            DEFAULT_OUTBOUND_CONNECT_RETRIES,
+        )?,
+        outlier_consecutive_failures: parse_default(
+            OUTLIER_CONSECUTIVE_FAILURES,
+            DEFAULT_OUTLIER_CONSECUTIVE_FAILURES,
+        )?,
+        outlier_ejection_time: parse_duration_default(
+            OUTLIER_EJECTION_TIME,
+            DEFAULT_OUTLIER_EJECTION_TIME,
+        )?,
         local_ip: parse(INSTANCE_IP)?,
         cluster_id,
         cluster_domain,
 
         xds_address,
+        xds_failover_addresses,
+        xds_failover_threshold: parse_default(
+            XDS_FAILOVER_THRESHOLD,
+            DEFAULT_XDS_FAILOVER_THRESHOLD,
+        )?,
+        xds_failback_interval: parse_duration_default(
+            XDS_FAILBACK_INTERVAL,
+            DEFAULT_XDS_FAILBACK_INTERVAL,
+        )?,
         xds_root_cert,
         ca_address,
         ca_root_cert,
+        trust_domain_aliases,
+        federated_trust_bundles: parse_trust_bundle_endpoints(FEDERATED_TRUST_BUNDLES)?,
+        federated_trust_bundle_refresh: parse_duration(FEDERATED_TRUST_BUNDLE_REFRESH)?
+            .unwrap_or(DEFAULT_FEDERATED_TRUST_BUNDLE_REFRESH),
+        crl_source: parse_crl_source(CRL_SOURCE)?,
+        crl_refresh: parse_duration_default(CRL_REFRESH, DEFAULT_CRL_REFRESH)?,
+        ca_provider: match parse::<String>(CA_PROVIDER)? {
+            Some(provider) => match provider.as_str() {
+                CA_PROVIDER_ISTIOD => CaProvider::Istiod,
+                // The SPIRE agent is reached over a unix domain socket
+                CA_PROVIDER_SPIRE if cfg!(unix) => CaProvider::Spire,
+                _ => return Err(Error::EnvVar(CA_PROVIDER.to_string(), provider)),
+            },
+            None => CaProvider::Istiod,
+        },
+        key_type: match parse::<String>(KEY_TYPE)? {
+            Some(key_type) => match key_type.as_str() {
+                KEY_TYPE_ECDSA => KeyType::EcdsaP256,
+                KEY_TYPE_RSA => KeyType::Rsa {
+                    bits: parse_default(RSA_KEY_SIZE, DEFAULT_RSA_KEY_SIZE)?,
+                },
+                _ => return Err(Error::EnvVar(KEY_TYPE.to_string(), key_type)),
+            },
+            None => KeyType::EcdsaP256,
+        },
+        private_key_storage: match parse::<String>(PRIVATE_KEY_STORAGE)? {
+            Some(storage) => match storage.as_str() {
+                PRIVATE_KEY_STORAGE_HEAP => KeyStorage::Heap,
+                PRIVATE_KEY_STORAGE_MEMFD_SECRET
+                    if cfg!(all(target_os = "linux", feature = "tls-ring")) =>
+                {
+                    KeyStorage::MemfdSecret
+                }
+                _ => return Err(Error::EnvVar(PRIVATE_KEY_STORAGE.to_string(), storage)),
+            },
+            None => KeyStorage::Heap,
+        },
+        pkcs11,
+        spire_admin_socket: parse_default(
+            SPIRE_ADMIN_SOCKET,
+            PathBuf::from(DEFAULT_SPIRE_ADMIN_SOCKET),
+        )?,
         secret_ttl: match parse::<String>(SECRET_TTL)? {
             Some(ttl) => duration_str::parse(ttl).unwrap_or(DEFAULT_TTL),
             None => DEFAULT_TTL,
         },
+        cert_refresh_fraction: parse_default(CERT_REFRESH_FRACTION, DEFAULT_CERT_REFRESH_FRACTION)?,
+        cert_cache_max_size: parse_default(CERT_CACHE_MAX_SIZE, 0)?,
+        cert_cache_ttl: parse_duration(CERT_CACHE_TTL)?,
+        cert_prefetch: parse_default(CERT_PREFETCH, true)?,
         local_xds_config: parse::<PathBuf>(LOCAL_XDS_PATH)?.map(ConfigSource::File),
+        workload_fallback_config: parse::<PathBuf>(WORKLOAD_FALLBACK_PATH)?.map(ConfigSource::File),
+        state_snapshot_path: parse(STATE_SNAPSHOT_PATH)?,
+        max_workloads: parse_default(MAX_WORKLOADS, 0)?,
+        max_services: parse_default(MAX_SERVICES, 0)?,
+        max_policies: parse_default(MAX_POLICIES, 0)?,
+        reject_over_state_limits: parse_default(REJECT_OVER_STATE_LIMITS, false)?,
         xds_on_demand: parse_default(XDS_ON_DEMAND, false)?,
+        xds_reconnect_initial_backoff: parse_duration_default(
+            XDS_RECONNECT_INITIAL_BACKOFF,
+            DEFAULT_XDS_RECONNECT_INITIAL_BACKOFF,
+        )?,
+        xds_reconnect_max_backoff: parse_duration_default(
+            XDS_RECONNECT_MAX_BACKOFF,
+            DEFAULT_XDS_RECONNECT_MAX_BACKOFF,
+        )?,
+        xds_reconnect_jitter: parse_default(XDS_RECONNECT_JITTER, DEFAULT_XDS_RECONNECT_JITTER)?,
+        xds_initial_sync_timeout: parse_duration(XDS_INITIAL_SYNC_TIMEOUT)?,
+        xds_plaintext: parse_default(XDS_PLAINTEXT, false)?,
+        xds_grpc: GrpcConfig::parse(
+            XDS_KEEPALIVE_INTERVAL,
+            XDS_KEEPALIVE_TIMEOUT,
+            XDS_REQUEST_TIMEOUT,
+        )?,
+        ca_grpc: GrpcConfig::parse(
+            CA_KEEPALIVE_INTERVAL,
+            CA_KEEPALIVE_TIMEOUT,
+            CA_REQUEST_TIMEOUT,
+        )?,
         proxy_metadata: pc.proxy_metadata,
+        proxy_metadata_json: vars_with_prefix(ISTIO_METAJSON_PREFIX)
+            .into_iter()
+            .map(|(key, val)| {
+                (
+                    key.trim_start_matches(ISTIO_METAJSON_PREFIX).to_string(),
+                    val,
+                )
+            })
+            .collect(),
 
         fake_ca,
         auth,
@@ -413,6 +1630,30 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         )?,
 
         enable_original_source: parse(ENABLE_ORIG_SRC)?,
+        socket_config: SocketConfig {
+            keepalive_enabled: parse_default(KEEPALIVE_ENABLED, true)?,
+            keepalive_time: parse_duration_default(KEEPALIVE_TIME, DEFAULT_KEEPALIVE_TIME)?,
+            keepalive_interval: parse_duration_default(
+                KEEPALIVE_INTERVAL,
+                DEFAULT_KEEPALIVE_INTERVAL,
+            )?,
+            keepalive_retries: parse_default(KEEPALIVE_RETRIES, DEFAULT_KEEPALIVE_RETRIES)?,
+            app: SocketPolicy {
+                nodelay: parse(APP_SOCKET_NODELAY)?,
+                send_buffer_size: parse(APP_SOCKET_SEND_BUFFER_SIZE)?,
+                recv_buffer_size: parse(APP_SOCKET_RECV_BUFFER_SIZE)?,
+                tos: parse(APP_SOCKET_TOS)?,
+            },
+            mesh: SocketPolicy {
+                nodelay: parse(MESH_SOCKET_NODELAY)?,
+                send_buffer_size: parse(MESH_SOCKET_SEND_BUFFER_SIZE)?,
+                recv_buffer_size: parse(MESH_SOCKET_RECV_BUFFER_SIZE)?,
+                tos: parse(MESH_SOCKET_TOS)?,
+            },
+            propagate_dscp: parse_default(PROPAGATE_DSCP, false)?,
+        },
+        enable_splice: parse_default(ENABLE_SPLICE, false)?,
+        relay_buffer_size: parse_default(RELAY_BUFFER_SIZE, copy::DEFAULT_BUFFER_SIZE)?,
         proxy_args: parse_args(),
         dns_resolver_cfg,
         dns_resolver_opts,
@@ -420,30 +1661,369 @@ pub fn construct_config(pc: ProxyConfig) -> Result<Config, Error> {
         inpod_uds: parse_default(INPOD_UDS, PathBuf::from("/var/run/ztunnel/ztunnel.sock"))?,
         inpod_port_reuse: parse_default(INPOD_PORT_REUSE, true)?,
         inpod_mark: parse_default(INPOD_MARK, DEFAULT_INPOD_MARK)?,
+        ebpf_redirect: parse_default(ENABLE_EBPF_REDIRECT, false)?,
+        ebpf_cgroup_path: parse_default(EBPF_CGROUP_PATH, PathBuf::from("/sys/fs/cgroup"))?,
     })
 }
 
 fn validate_config(cfg: Config) -> Result<Config, Error> {
-    if cfg.dns_proxy && cfg.xds_on_demand {
-        return Err(Error::ProxyConfig(anyhow!(
-            "DNS proxy does not currently support on-demand mode"
-        )));
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+impl Config {
+    /// validate cross-checks the settings, reporting every problem found at once rather than
+    /// just the first. It is run when the config is constructed, before any listener is bound.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+
+        if self.dns_proxy && self.xds_on_demand {
+            problems.push("DNS proxy does not currently support on-demand mode".to_string());
+        }
+
+        if self.socket_config.keepalive_enabled
+            && (self.socket_config.keepalive_time.is_zero()
+                || self.socket_config.keepalive_interval.is_zero()
+                || self.socket_config.keepalive_retries == 0)
+        {
+            problems.push(
+                "keepalive time, interval and retries must be non-zero when keepalive is enabled"
+                    .to_string(),
+            );
+        }
+
+        for policy in [&self.socket_config.app, &self.socket_config.mesh] {
+            if policy.send_buffer_size == Some(0) || policy.recv_buffer_size == Some(0) {
+                problems.push(
+                    "socket send and receive buffer sizes must be non-zero if set".to_string(),
+                );
+            }
+        }
+
+        if self.federated_trust_bundle_refresh.is_zero() {
+            problems.push("federated trust bundle refresh interval must be non-zero".to_string());
+        }
+
+        if let KeyType::Rsa { bits } = self.key_type {
+            if !cfg!(any(feature = "tls-boring", feature = "rsa-keys")) {
+                problems.push(
+                    "RSA keys require ztunnel to be built with the rsa-keys feature".to_string(),
+                );
+            }
+            if !RSA_KEY_SIZES.contains(&bits) {
+                problems.push(format!(
+                    "RSA key size must be one of {RSA_KEY_SIZES:?}, got {bits}"
+                ));
+            }
+        }
+
+        if let Some(pkcs11) = &self.pkcs11 {
+            if !cfg!(feature = "pkcs11") {
+                problems.push(
+                    "PKCS#11 keys require ztunnel to be built with the pkcs11 feature".to_string(),
+                );
+            }
+            // SPIRE generates keys itself
+            if self.ca_provider != CaProvider::Istiod {
+                problems.push(
+                    "PKCS#11 keys are only supported with the istiod CA provider".to_string(),
+                );
+            }
+            if self.key_type != KeyType::EcdsaP256 {
+                problems.push("PKCS#11 keys are always ECDSA P-256 keys".to_string());
+            }
+            if self.proxy_mode == ProxyMode::Shared && !pkcs11.workload_keys {
+                problems.push(format!(
+                    "PKCS#11 keys in shared mode require {PKCS11_WORKLOAD_KEYS}=true"
+                ));
+            }
+        }
+
+        if self.enable_hbone_quic && !cfg!(feature = "quic") {
+            problems.push(
+                "HBONE over QUIC requires ztunnel to be built with the quic feature".to_string(),
+            );
+        }
+
+        if self.ebpf_redirect {
+            if !cfg!(feature = "ebpf-redirect") {
+                problems.push(
+                    "eBPF redirection requires ztunnel to be built with the ebpf-redirect feature"
+                        .to_string(),
+                );
+            }
+            if !self.inpod_enabled {
+                problems.push("eBPF redirection requires in-pod mode".to_string());
+            }
+            // ztunnel's own connections are told apart by their mark, and would otherwise loop
+            if self.inpod_mark == 0 {
+                problems.push("eBPF redirection requires a non-zero in-pod mark".to_string());
+            }
+        }
+
+        if self.crl_refresh.is_zero() {
+            problems.push("crl refresh interval must be non-zero".to_string());
+        }
+
+        if self.otlp_metrics_period.is_zero() {
+            problems.push("OTLP metrics period must be non-zero".to_string());
+        }
+
+        if self.liveness_stall_threshold < Duration::from_secs(1) {
+            problems.push("liveness stall threshold must be at least 1s".to_string());
+        }
+
+        if self.outlier_consecutive_failures > 0 && self.outlier_ejection_time.is_zero() {
+            problems.push(
+                "outlier ejection time must be non-zero if outlier detection is enabled"
+                    .to_string(),
+            );
+        }
+
+        if !(MIN_RELAY_BUFFER_SIZE..=MAX_RELAY_BUFFER_SIZE).contains(&self.relay_buffer_size) {
+            problems.push(format!(
+                "relay buffer size must be between {} and {} bytes",
+                MIN_RELAY_BUFFER_SIZE, MAX_RELAY_BUFFER_SIZE
+            ));
+        }
+
+        if self.inbound_proxy_protocol && self.proxy_protocol_trusted_cidrs.is_empty() {
+            problems.push("inbound PROXY protocol requires at least one trusted CIDR".to_string());
+        }
+
+        let uds_listeners = self.outbound_uds_path.is_some() || self.socks5_uds_path.is_some();
+        if uds_listeners && !cfg!(unix) {
+            problems.push(
+                "unix domain socket listeners are not supported on this platform".to_string(),
+            );
+        }
+        // Socket paths are not scoped to a pod's network namespace, so per-pod proxies would
+        // conflict
+        if uds_listeners && self.inpod_enabled {
+            problems
+                .push("unix domain socket listeners are not supported in in-pod mode".to_string());
+        }
+
+        if self.inbound_plaintext_addr.is_empty() {
+            problems.push("at least one inbound plaintext address is required".to_string());
+        }
+
+        if self.pool_max_conns_per_destination == Some(0) {
+            problems
+                .push("pool max connections per destination must be non-zero if set".to_string());
+        }
+
+        if self.workload_connection_limits.values().any(|l| *l == 0) {
+            problems.push("workload connection limits must be non-zero".to_string());
+        }
+
+        if self.connection_rate_limit == Some(0) || self.connection_rate_limit_burst == Some(0) {
+            problems.push("connection rate limit and burst must be non-zero if set".to_string());
+        }
+
+        if self.connection_rate_limit_burst.is_some() && self.connection_rate_limit.is_none() {
+            problems
+                .push("connection rate limit burst requires a connection rate limit".to_string());
+        }
+
+        if self.xds_reconnect_initial_backoff.is_zero()
+            || self.xds_reconnect_initial_backoff > self.xds_reconnect_max_backoff
+        {
+            problems.push(
+                "xds reconnect initial backoff must be non-zero and at most the max backoff"
+                    .to_string(),
+            );
+        }
+
+        if !(0.0..=1.0).contains(&self.xds_reconnect_jitter) {
+            problems.push("xds reconnect jitter must be between 0 and 1".to_string());
+        }
+
+        if self.xds_address.is_none() && !self.xds_failover_addresses.is_empty() {
+            problems.push("xds failover addresses require an xds address".to_string());
+        }
+        if !self.xds_failover_addresses.is_empty()
+            && (self.xds_failover_threshold == 0 || self.xds_failback_interval.is_zero())
+        {
+            problems.push(
+                "xds failover threshold and failback interval must be greater than 0".to_string(),
+            );
+        }
+        for address in self.xds_address.iter().chain(&self.xds_failover_addresses) {
+            if let Some(path) = uds_path(address) {
+                if !cfg!(unix) {
+                    problems.push(
+                        "xds over a unix domain socket is not supported on this platform"
+                            .to_string(),
+                    );
+                }
+                if !path.is_absolute() {
+                    problems.push(format!(
+                        "xds unix domain socket path must be absolute, got {}",
+                        path.display()
+                    ));
+                }
+            }
+            if self.xds_plaintext != address.starts_with("http://") {
+                problems.push(
+                    "plaintext xds requires an http:// xds address, and an http:// xds address \
+                     requires plaintext xds"
+                        .to_string(),
+                );
+            }
+        }
+        if self.xds_plaintext && self.xds_address.is_none() {
+            problems.push("plaintext xds requires an http:// xds address".to_string());
+        }
+        if self.ca_address.as_deref().and_then(uds_path).is_some() {
+            problems.push("ca address cannot be a unix domain socket".to_string());
+        }
+
+        self.xds_grpc.validate("xds", &mut problems);
+        self.ca_grpc.validate("ca", &mut problems);
+
+        if !(self.cert_refresh_fraction > 0.0 && self.cert_refresh_fraction < 1.0) {
+            problems.push("cert refresh fraction must be between 0 and 1 (exclusive)".to_string());
+        }
+
+        if self.cert_cache_ttl.is_some_and(|ttl| ttl.is_zero()) {
+            problems.push("cert cache ttl must be greater than 0".to_string());
+        }
+
+        if !self.proxy && !self.dns_proxy {
+            problems.push("ztunnel run without any servers enabled".to_string());
+        }
+
+        if self.xds_initial_sync_timeout.is_some_and(|t| t.is_zero())
+            || self.tls_session_lifetime.is_some_and(|t| t.is_zero())
+        {
+            problems.push(
+                "xds initial sync timeout and tls session lifetime must be non-zero if set"
+                    .to_string(),
+            );
+        }
+        if self.secret_ttl.is_zero() {
+            problems.push("secret ttl must be non-zero".to_string());
+        }
+
+        // In-pod mode serves every pod on the node, each with its own identity.
+        if self.proxy_mode == ProxyMode::Dedicated && self.inpod_enabled {
+            problems.push(format!(
+                "in-pod mode requires the shared proxy mode, set {PROXY_MODE}={PROXY_MODE_SHARED}"
+            ));
+        }
+
+        self.check_listeners(&mut problems);
+
+        for (name, root) in [
+            ("xds root", &self.xds_root_cert),
+            ("ca root", &self.ca_root_cert),
+        ] {
+            check_root_cert(name, root, &mut problems);
+        }
+        if let Some(tls) = &self.admin_tls {
+            check_readable("admin tls certificate", &tls.cert, &mut problems);
+            check_readable("admin tls key", &tls.key, &mut problems);
+            check_root_cert("admin tls client ca", &tls.client_ca, &mut problems);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Invalid(problems))
+        }
     }
 
-    if !cfg.proxy && !cfg.dns_proxy {
-        return Err(Error::ProxyConfig(anyhow!(
-            "ztunnel run without any servers enabled"
-        )));
+    // check_listeners reports listeners that would bind the same port on overlapping addresses.
+    fn check_listeners(&self, problems: &mut Vec<String>) {
+        let mut node = vec![
+            ("admin", self.admin_addr),
+            ("stats", self.stats_addr),
+            ("readiness", self.readiness_addr),
+        ];
+        let mut proxy = Vec::new();
+        if self.proxy {
+            proxy.push(("inbound", self.inbound_addr));
+            proxy.push(("outbound", self.outbound_addr));
+            proxy.extend(
+                self.inbound_plaintext_addr
+                    .iter()
+                    .map(|addr| ("inbound plaintext", *addr)),
+            );
+            proxy.extend(self.socks5_addr.map(|addr| ("socks5", addr)));
+            proxy.extend(self.sni_router_addr.map(|addr| ("sni router", addr)));
+            proxy.extend(self.forward_proxy_addr.map(|addr| ("forward proxy", addr)));
+        }
+        if self.dns_proxy {
+            proxy.push(("dns proxy", self.dns_proxy_addr));
+        }
+        // In in-pod mode, the proxy listeners are bound in each pod's network namespace instead
+        // of ours, so they can only overlap each other.
+        if self.inpod_enabled {
+            check_overlap(&proxy, problems);
+        } else {
+            node.append(&mut proxy);
+        }
+        check_overlap(&node, problems);
     }
+}
 
-    Ok(cfg)
+fn check_overlap(listeners: &[(&str, SocketAddr)], problems: &mut Vec<String>) {
+    for (i, (name, addr)) in listeners.iter().enumerate() {
+        // Port 0 picks a free port when binding.
+        if addr.port() == 0 {
+            continue;
+        }
+        for (other, other_addr) in &listeners[..i] {
+            if addr.port() == other_addr.port() && ips_overlap(addr.ip(), other_addr.ip()) {
+                problems.push(format!(
+                    "{name} address {addr} overlaps the {other} address {other_addr}"
+                ));
+            }
+        }
+    }
+}
+
+fn ips_overlap(a: IpAddr, b: IpAddr) -> bool {
+    let (a, b) = (a.to_canonical(), b.to_canonical());
+    // An unspecified address binds every address of its family, and IPv6 ones are dual stack.
+    let covers =
+        |wide: IpAddr, other: IpAddr| wide.is_unspecified() && (wide.is_ipv6() || other.is_ipv4());
+    a == b || covers(a, b) || covers(b, a)
+}
+
+fn check_root_cert(name: &str, root: &RootCert, problems: &mut Vec<String>) {
+    match root {
+        RootCert::File(path) | RootCert::Directory(path) => check_readable(name, path, problems),
+        RootCert::Static(_) | RootCert::Default => {}
+    }
+}
+
+fn check_readable(name: &str, path: &Path, problems: &mut Vec<String>) {
+    let readable = if path.is_dir() {
+        fs::read_dir(path).map(|_| ())
+    } else {
+        fs::File::open(path).map(|_| ())
+    };
+    if let Err(e) = readable {
+        problems.push(format!("{name} {} is not readable: {e}", path.display()));
+    }
 }
 
 // tries to parse the URI so we can fail early
+/// uds_path returns the socket path of a `unix://` address.
+pub fn uds_path(address: &str) -> Option<&Path> {
+    address.strip_prefix(UNIX_SCHEME).map(Path::new)
+}
+
 fn validate_uri(uri_str: Option<String>) -> Result<Option<String>, Error> {
     let Some(uri_str) = uri_str else {
         return Ok(uri_str);
     };
+    // Not a valid HTTP URI, as it has no authority
+    if uds_path(&uri_str).is_some() {
+        return Ok(Some(uri_str));
+    }
     let uri = Uri::try_from(&uri_str)?;
     if uri.scheme().is_none() {
         return Ok(Some("https://".to_owned() + &uri_str));
@@ -455,6 +2035,8 @@ fn validate_uri(uri_str: Option<String>) -> Result<Option<String>, Error> {
 #[serde(rename_all = "camelCase")]
 pub struct MeshConfig {
     pub default_config: Option<ProxyConfig>,
+    #[serde(default)]
+    pub trust_domain_aliases: Vec<String>,
 }
 
 #[derive(serde::Deserialize, Default, Debug, Clone, PartialEq, Eq)]
@@ -466,6 +2048,9 @@ pub struct ProxyConfig {
     pub concurrency: Option<u16>,
     pub termination_drain_duration: Option<Duration>,
     pub proxy_metadata: HashMap<String, String>,
+    /// Set from the mesh config, since trust domain aliases are not part of the proxy config.
+    #[serde(skip)]
+    pub trust_domain_aliases: Vec<String>,
 }
 
 impl ProxyConfig {
@@ -484,8 +2069,8 @@ impl ProxyConfig {
 
 fn construct_proxy_config(mc_path: &str, pc_env: Option<&str>) -> anyhow::Result<ProxyConfig> {
     let mesh_config = match fs::File::open(mc_path) {
-        Ok(f) => serde_yaml::from_reader(f)
-            .map(|v: MeshConfig| v.default_config)
+        Ok(f) => serde_yaml::from_reader::<_, MeshConfig>(f)
+            .map(Some)
             .map_err(anyhow::Error::new),
         Err(e) => {
             if e.kind() == std::io::ErrorKind::NotFound {
@@ -508,10 +2093,18 @@ fn construct_proxy_config(mc_path: &str, pc_env: Option<&str>) -> anyhow::Result
         .unwrap_or(Ok(None))
         .map_err(|e| anyhow!("failed parsing proxy config env: {}", e))?;
 
-    let mut pc = [mesh_config, proxy_config_env]
-        .into_iter()
-        .flatten()
-        .fold(ProxyConfig::default(), |pc, v| pc.merge(v));
+    let trust_domain_aliases = mesh_config
+        .as_ref()
+        .map(|mc| mc.trust_domain_aliases.clone())
+        .unwrap_or_default();
+    let mut pc = [
+        mesh_config.and_then(|mc| mc.default_config),
+        proxy_config_env,
+    ]
+    .into_iter()
+    .flatten()
+    .fold(ProxyConfig::default(), |pc, v| pc.merge(v));
+    pc.trust_domain_aliases = trust_domain_aliases;
 
     // only include ISTIO_META_ prefixed fields in this map
     // TODO we could use any other items here for the various env vars for construct_config?
@@ -528,15 +2121,12 @@ fn construct_proxy_config(mc_path: &str, pc_env: Option<&str>) -> anyhow::Result
         })
         .collect();
 
-    let istio_env_vars: Vec<(String, String)> = env::vars()
-        .filter(|(key, _)| key.starts_with(ISTIO_META_PREFIX))
+    let istio_env_vars: Vec<(String, String)> = vars_with_prefix(ISTIO_META_PREFIX)
+        .into_iter()
         .map(|(key, val)| (key.trim_start_matches(ISTIO_META_PREFIX).to_string(), val))
         .collect();
     pc.proxy_metadata.extend(istio_env_vars);
 
-    // TODO if certain fields like trustDomainAliases are added, make sure they merge like:
-    // https://github.com/istio/istio/blob/bdd47796d696ea5db604b623c51567d13ff7c11b/pkg/config/mesh/mesh.go#L244
-
     Ok(pc)
 }
 
@@ -553,6 +2143,24 @@ pub fn empty_to_none<A: AsRef<str>>(inp: Option<A>) -> Option<A> {
 pub mod tests {
     use super::*;
 
+    // with_env makes vars visible to the parse functions as if they were set in the environment
+    // while f runs. Unlike env::set_var, they are only visible to the current thread, so tests
+    // running in parallel never observe each other's (possibly invalid) settings.
+    fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        struct Restore(HashMap<String, String>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                TEST_ENV.set(std::mem::take(&mut self.0));
+            }
+        }
+        let vars = vars
+            .iter()
+            .map(|(key, val)| (key.to_string(), val.to_string()))
+            .collect();
+        let _restore = Restore(TEST_ENV.replace(vars));
+        f()
+    }
+
     #[test]
     fn config_from_proxyconfig() {
         let default_config = construct_config(ProxyConfig::default())
@@ -567,6 +2175,7 @@ pub mod tests {
         // TODO remove prefix
         assert_eq!(cfg.proxy_metadata["FOO"], "foo");
         assert_eq!(cfg.cluster_id, "Kubernetes");
+        assert_eq!(cfg.trust_domain_aliases, vec![Strng::from("old.td")]);
 
         // env only
         let pc_env = Some(
@@ -613,4 +2222,747 @@ pub mod tests {
         assert_eq!(cfg.proxy_metadata["NO_PREFIX"], "no-prefix");
         assert_eq!(cfg.proxy_metadata["INCLUDE_THIS"], "foobar-env");
     }
+
+    #[test]
+    fn config_parse_list() {
+        let env = "ZTUNNEL_TEST_PARSE_LIST";
+        assert_eq!(parse_list::<SocketAddr>(env).unwrap(), None);
+
+        assert_eq!(
+            with_env(&[(env, "127.0.0.1:15006, [::1]:15006")], || {
+                parse_list::<SocketAddr>(env).unwrap()
+            }),
+            Some(vec![
+                "127.0.0.1:15006".parse().unwrap(),
+                "[::1]:15006".parse().unwrap()
+            ])
+        );
+
+        assert!(with_env(&[(env, "127.0.0.1:15006,not-an-address")], || {
+            parse_list::<SocketAddr>(env)
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn config_parse_trust_bundle_endpoints() {
+        let env = "ZTUNNEL_TEST_PARSE_TRUST_BUNDLE_ENDPOINTS";
+        assert!(parse_trust_bundle_endpoints(env).unwrap().is_empty());
+
+        let endpoints = with_env(
+            &[(
+                env,
+                "east.example.com=https://east.example.com/bundle, west.example.com = https://10.0.0.1:8443/",
+            )],
+            || parse_trust_bundle_endpoints(env),
+        );
+        let plaintext = with_env(
+            &[(env, "east.example.com=http://east.example.com/bundle")],
+            || parse_trust_bundle_endpoints(env),
+        );
+        let missing_trust_domain = with_env(&[(env, "https://east.example.com/bundle")], || {
+            parse_trust_bundle_endpoints(env)
+        });
+
+        assert_eq!(
+            endpoints.unwrap(),
+            HashMap::from([
+                (
+                    Strng::from("east.example.com"),
+                    "https://east.example.com/bundle".to_string()
+                ),
+                (
+                    Strng::from("west.example.com"),
+                    "https://10.0.0.1:8443/".to_string()
+                ),
+            ])
+        );
+        assert!(plaintext.is_err());
+        assert!(missing_trust_domain.is_err());
+    }
+
+    #[test]
+    fn config_parse_crl_source() {
+        let env = "ZTUNNEL_TEST_PARSE_CRL_SOURCE";
+        assert_eq!(parse_crl_source(env).unwrap(), None);
+
+        let file = with_env(&[(env, "/etc/crl/ca.crl")], || parse_crl_source(env));
+        let url = with_env(&[(env, "http://crl.example.com/ca.crl")], || {
+            parse_crl_source(env)
+        });
+        let missing_host = with_env(&[(env, "https://")], || parse_crl_source(env));
+
+        assert_eq!(
+            file.unwrap(),
+            Some(CrlSource::File(PathBuf::from("/etc/crl/ca.crl")))
+        );
+        assert_eq!(
+            url.unwrap(),
+            Some(CrlSource::Url("http://crl.example.com/ca.crl".to_string()))
+        );
+        assert!(missing_host.is_err());
+    }
+
+    #[test]
+    fn config_parse_connection_limits() {
+        let env = "ZTUNNEL_TEST_PARSE_CONNECTION_LIMITS";
+        assert!(parse_connection_limits(env).unwrap().is_empty());
+
+        assert_eq!(
+            with_env(&[(env, "default/web=100, other/db=5")], || {
+                parse_connection_limits(env).unwrap()
+            }),
+            HashMap::from([
+                ("default/web".to_string(), 100),
+                ("other/db".to_string(), 5)
+            ])
+        );
+
+        assert!(with_env(&[(env, "web=100")], || parse_connection_limits(env)).is_err());
+        assert!(
+            with_env(&[(env, "default/web=many")], || parse_connection_limits(
+                env
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn config_keepalive_validation() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.socket_config, SocketConfig::default());
+
+        let invalid = Config {
+            socket_config: SocketConfig {
+                keepalive_retries: 0,
+                ..Default::default()
+            },
+            ..cfg.clone()
+        };
+        assert!(validate_config(invalid).is_err());
+
+        // Zero values are fine if keepalive is disabled entirely
+        let disabled = Config {
+            socket_config: SocketConfig {
+                keepalive_enabled: false,
+                keepalive_retries: 0,
+                ..Default::default()
+            },
+            ..cfg
+        };
+        assert!(validate_config(disabled).is_ok());
+    }
+
+    #[test]
+    fn config_cert_refresh_fraction_validation() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.cert_refresh_fraction, 0.5);
+
+        for invalid in [0.0, 1.0, 1.5] {
+            let invalid = Config {
+                cert_refresh_fraction: invalid,
+                ..cfg.clone()
+            };
+            assert!(validate_config(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn config_grpc() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.xds_grpc, GrpcConfig::default());
+
+        let cfg = with_env(
+            &[(CA_KEEPALIVE_INTERVAL, "5s"), (CA_REQUEST_TIMEOUT, "2s")],
+            || construct_config(ProxyConfig::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.ca_grpc,
+            GrpcConfig {
+                keepalive_interval: Duration::from_secs(5),
+                request_timeout: Duration::from_secs(2),
+                ..Default::default()
+            }
+        );
+        assert_eq!(cfg.xds_grpc, GrpcConfig::default());
+
+        let invalid = Config {
+            xds_grpc: GrpcConfig {
+                keepalive_timeout: Duration::ZERO,
+                ..Default::default()
+            },
+            ..cfg
+        };
+        assert!(validate_config(invalid).is_err());
+    }
+
+    #[test]
+    fn config_xds_plaintext() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(!cfg.xds_plaintext);
+
+        let cfg = with_env(
+            &[
+                (XDS_PLAINTEXT, "true"),
+                (XDS_ADDRESS, "http://istiod.dev:15010"),
+            ],
+            || construct_config(ProxyConfig::default()),
+        )
+        .unwrap();
+        assert!(cfg.xds_plaintext);
+
+        // Plaintext must be explicitly opted in to, and is only used when the address says so
+        let invalid = Config {
+            xds_plaintext: false,
+            ..cfg.clone()
+        };
+        assert!(validate_config(invalid).is_err());
+        let invalid = Config {
+            xds_address: Some("https://istiod.dev:15012".to_string()),
+            ..cfg
+        };
+        assert!(validate_config(invalid).is_err());
+    }
+
+    #[test]
+    fn config_xds_failover() {
+        let cfg = with_env(
+            &[(XDS_ADDRESS, "https://istiod-a:15012, istiod-b:15012")],
+            || construct_config(ProxyConfig::default()),
+        )
+        .unwrap();
+        assert_eq!(cfg.xds_address.as_deref(), Some("https://istiod-a:15012"));
+        assert_eq!(
+            cfg.xds_failover_addresses,
+            vec!["https://istiod-b:15012".to_string()]
+        );
+        assert_eq!(cfg.xds_failover_threshold, DEFAULT_XDS_FAILOVER_THRESHOLD);
+        assert!(validate_config(cfg.clone()).is_ok());
+
+        let invalid = Config {
+            xds_failover_threshold: 0,
+            ..cfg.clone()
+        };
+        assert!(validate_config(invalid).is_err());
+        let invalid = Config {
+            xds_failover_addresses: vec!["http://istiod-b:15010".to_string()],
+            ..cfg
+        };
+        assert!(validate_config(invalid).is_err());
+    }
+
+    #[test]
+    fn config_xds_uds() {
+        let cfg = with_env(&[(XDS_ADDRESS, "unix:///var/run/agent/xds.sock")], || {
+            construct_config(ProxyConfig::default())
+        })
+        .unwrap();
+        assert_eq!(
+            cfg.xds_address.as_deref().and_then(uds_path),
+            Some(Path::new("/var/run/agent/xds.sock"))
+        );
+        #[cfg(unix)]
+        assert!(validate_config(cfg.clone()).is_ok());
+
+        let invalid = Config {
+            xds_address: Some("unix://xds.sock".to_string()),
+            ..cfg.clone()
+        };
+        assert!(validate_config(invalid).is_err());
+        let invalid = Config {
+            ca_address: Some("unix:///var/run/agent/ca.sock".to_string()),
+            ..cfg
+        };
+        assert!(validate_config(invalid).is_err());
+    }
+
+    #[test]
+    fn config_inbound_proxy_protocol() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(!cfg.inbound_proxy_protocol);
+        assert!(cfg.proxy_protocol_trusted_cidrs.is_empty());
+
+        let cfg = with_env(
+            &[(PROXY_PROTOCOL_TRUSTED_CIDRS, "10.0.0.0/8,fd00::/8")],
+            || construct_config(ProxyConfig::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.proxy_protocol_trusted_cidrs,
+            vec![
+                "10.0.0.0/8".parse::<ipnet::IpNet>().unwrap(),
+                "fd00::/8".parse().unwrap()
+            ]
+        );
+
+        // Enabling PROXY protocol requires trusting someone
+        let invalid = Config {
+            inbound_proxy_protocol: true,
+            proxy_protocol_trusted_cidrs: vec![],
+            ..cfg.clone()
+        };
+        assert!(validate_config(invalid).is_err());
+        let valid = Config {
+            inbound_proxy_protocol: true,
+            ..cfg
+        };
+        assert!(validate_config(valid).is_ok());
+    }
+
+    #[test]
+    fn config_uds_listeners() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.outbound_uds_path, None);
+        assert_eq!(cfg.socks5_uds_path, None);
+        assert!(cfg.uds_allowed_uids.is_empty());
+
+        let cfg = with_env(
+            &[(OUTBOUND_UDS_PATH, "/var/run/ztunnel/outbound.sock")],
+            || construct_config(ProxyConfig::default()),
+        );
+        assert_eq!(
+            cfg.unwrap().outbound_uds_path,
+            Some(PathBuf::from("/var/run/ztunnel/outbound.sock"))
+        );
+
+        let cfg = with_env(&[(UDS_ALLOWED_UIDS, "1000, 1337")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert_eq!(cfg.unwrap().uds_allowed_uids, vec![1000, 1337]);
+    }
+
+    #[test]
+    fn config_socket_policy_validation() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        let valid = Config {
+            socket_config: SocketConfig {
+                app: SocketPolicy {
+                    nodelay: Some(true),
+                    ..Default::default()
+                },
+                mesh: SocketPolicy {
+                    send_buffer_size: Some(4 * 1024 * 1024),
+                    recv_buffer_size: Some(4 * 1024 * 1024),
+                    tos: Some(0xb8),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..cfg.clone()
+        };
+        assert!(validate_config(valid).is_ok());
+
+        let invalid = Config {
+            socket_config: SocketConfig {
+                mesh: SocketPolicy {
+                    recv_buffer_size: Some(0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..cfg
+        };
+        assert!(validate_config(invalid).is_err());
+    }
+
+    #[test]
+    fn config_metrics_dropped_labels() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(cfg.metrics_dropped_labels.is_empty());
+
+        let cfg = with_env(
+            &[(
+                METRICS_DROPPED_LABELS,
+                "destination_workload, source_workload",
+            )],
+            || construct_config(ProxyConfig::default()),
+        );
+        let invalid = with_env(&[(METRICS_DROPPED_LABELS, "reporter")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert_eq!(
+            cfg.unwrap().metrics_dropped_labels,
+            vec!["destination_workload", "source_workload"]
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn config_reloadable() {
+        // Settings from the mesh config's proxy metadata are used, since they can change at runtime.
+        let pc = ProxyConfig {
+            proxy_metadata: HashMap::from([
+                (IDLE_TIMEOUT.to_string(), "5m".to_string()),
+                (ACCESS_LOG_FORMAT.to_string(), "json".to_string()),
+                (LOG_LEVEL.to_string(), "ztunnel::proxy=debug".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let cfg = construct_config(pc).unwrap();
+        let settings = cfg.reloadable.get();
+        assert_eq!(settings.idle_timeout, Some(Duration::from_secs(300)));
+        assert_eq!(settings.access_log_format, AccessLogFormat::Json);
+        assert_eq!(settings.log_level.as_deref(), Some("ztunnel::proxy=debug"));
+
+        // Clones of the config observe updates, and subscribers are notified.
+        let clone = cfg.clone();
+        let mut reloads = cfg.reloadable.subscribe();
+        let prev = cfg.reloadable.set(Reloadable {
+            log_level: None,
+            idle_timeout: None,
+            access_log_format: AccessLogFormat::Text,
+        });
+        assert_eq!(prev, settings);
+        assert_eq!(clone.reloadable.access_log_format(), AccessLogFormat::Text);
+        assert_eq!(clone.reloadable.idle_timeout(), None);
+        assert!(reloads.has_changed().unwrap());
+
+        let invalid = ProxyConfig {
+            proxy_metadata: HashMap::from([(IDLE_TIMEOUT.to_string(), "0s".to_string())]),
+            ..Default::default()
+        };
+        assert!(parse_reloadable(&invalid).is_err());
+    }
+
+    #[test]
+    fn config_relay_buffer_size() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.relay_buffer_size, copy::DEFAULT_BUFFER_SIZE);
+
+        let cfg = with_env(&[(RELAY_BUFFER_SIZE, "65536")], || {
+            construct_config(ProxyConfig::default())
+        });
+        let invalid = with_env(&[(RELAY_BUFFER_SIZE, "16")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert_eq!(cfg.unwrap().relay_buffer_size, 65536);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn config_outbound_unknown_destination() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(
+            cfg.outbound_unknown_destination,
+            UnknownDestinationPolicy::Passthrough
+        );
+
+        let cfg = with_env(&[(OUTBOUND_UNKNOWN_DESTINATION, "deny")], || {
+            construct_config(ProxyConfig::default())
+        });
+        let invalid = with_env(&[(OUTBOUND_UNKNOWN_DESTINATION, "drop")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert_eq!(
+            cfg.unwrap().outbound_unknown_destination,
+            UnknownDestinationPolicy::Deny
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn config_dns_tls_upstream() {
+        assert_eq!(
+            "1.1.1.1#cloudflare-dns.com"
+                .parse::<DnsTlsUpstream>()
+                .unwrap(),
+            DnsTlsUpstream {
+                addr: "1.1.1.1:853".parse().unwrap(),
+                tls_name: "cloudflare-dns.com".to_string(),
+            }
+        );
+        assert_eq!(
+            "[2606:4700::1111]:8853#one.one.one.one"
+                .parse::<DnsTlsUpstream>()
+                .unwrap()
+                .addr,
+            "[2606:4700::1111]:8853".parse().unwrap()
+        );
+        for invalid in ["1.1.1.1", "1.1.1.1#", "dns.example.com#dns.example.com"] {
+            assert!(invalid.parse::<DnsTlsUpstream>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn config_tls_settings() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.tls_settings.min_version(), TlsVersion::Tls13);
+
+        let cfg = with_env(&[(TLS_MIN_VERSION, "1.2")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert_eq!(cfg.unwrap().tls_settings.min_version(), TlsVersion::Tls12);
+
+        let cfg = with_env(&[(TLS_CIPHER_SUITES, "TLS_NOPE")], || {
+            construct_config(ProxyConfig::default())
+        });
+        assert!(cfg.is_err());
+    }
+
+    #[test]
+    fn config_trust_domain_aliases() {
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(cfg.trust_domain_aliases.is_empty());
+
+        // Aliases from the environment are merged with those from the mesh config
+        let cfg = with_env(&[(TRUST_DOMAIN_ALIASES, "new.td, old.td")], || {
+            let pc = construct_proxy_config("./src/test_helpers/mesh_config.yaml", None).unwrap();
+            construct_config(pc)
+        });
+        assert_eq!(
+            cfg.unwrap().trust_domain_aliases,
+            vec![Strng::from("new.td"), Strng::from("old.td")]
+        );
+    }
+
+    #[test]
+    fn config_validate_key_type() {
+        let base = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(base.key_type, KeyType::EcdsaP256);
+        let with_key_type = |key_type| {
+            let mut cfg = base.clone();
+            cfg.key_type = key_type;
+            validate_config(cfg).is_ok()
+        };
+
+        let rsa_supported = cfg!(any(feature = "tls-boring", feature = "rsa-keys"));
+        assert_eq!(with_key_type(KeyType::Rsa { bits: 2048 }), rsa_supported);
+        assert_eq!(with_key_type(KeyType::Rsa { bits: 4096 }), rsa_supported);
+        assert!(!with_key_type(KeyType::Rsa { bits: 1024 }));
+    }
+
+    #[test]
+    fn config_validate_pkcs11() {
+        let base = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(base.pkcs11, None);
+        let with_pkcs11 = |proxy_mode, workload_keys| {
+            let mut cfg = base.clone();
+            cfg.proxy_mode = proxy_mode;
+            cfg.pkcs11 = Some(Pkcs11Config {
+                module: "/usr/lib/softhsm/libsofthsm2.so".into(),
+                slot: 0,
+                pin_file: "/etc/pkcs11/pin".into(),
+                workload_keys,
+            });
+            validate_config(cfg).is_ok()
+        };
+
+        assert_eq!(
+            with_pkcs11(ProxyMode::Dedicated, false),
+            cfg!(feature = "pkcs11")
+        );
+        assert_eq!(
+            with_pkcs11(ProxyMode::Shared, true),
+            cfg!(feature = "pkcs11")
+        );
+        // Every workload's key would be in memory
+        assert!(!with_pkcs11(ProxyMode::Shared, false));
+    }
+
+    #[test]
+    fn config_validate_hbone_quic() {
+        let mut cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(!cfg.enable_hbone_quic);
+        cfg.enable_hbone_quic = true;
+        assert_eq!(validate_config(cfg).is_ok(), cfg!(feature = "quic"));
+    }
+
+    #[test]
+    fn config_validate_ebpf_redirect() {
+        let mut cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert!(!cfg.ebpf_redirect);
+        cfg.ebpf_redirect = true;
+        assert!(validate_config(cfg.clone()).is_err());
+        cfg.inpod_enabled = true;
+        cfg.inpod_mark = 0;
+        assert!(validate_config(cfg.clone()).is_err());
+        cfg.inpod_mark = DEFAULT_INPOD_MARK;
+        assert_eq!(
+            validate_config(cfg).is_ok(),
+            cfg!(feature = "ebpf-redirect")
+        );
+    }
+
+    #[test]
+    fn config_admin_tls() {
+        let tls_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/tls");
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.admin_tls, None);
+
+        let file = |name: &str| tls_dir.join(name).to_str().unwrap().to_string();
+        let (cert, key, root) = (file("cert.pem"), file("key.pem"), file("root-cert.pem"));
+        let invalid = with_env(&[(ADMIN_TLS_CERT, &cert)], || {
+            construct_config(ProxyConfig::default())
+        });
+        let cfg = with_env(&[(ADMIN_TLS_CERT, &cert), (ADMIN_TLS_KEY, &key)], || {
+            construct_config(ProxyConfig::default())
+        });
+        let custom_ca = with_env(
+            &[
+                (ADMIN_TLS_CERT, &cert),
+                (ADMIN_TLS_KEY, &key),
+                (ADMIN_TLS_CLIENT_CA, &root),
+            ],
+            || construct_config(ProxyConfig::default()),
+        );
+        // The files must be readable
+        let unreadable = with_env(
+            &[
+                (ADMIN_TLS_CERT, &cert),
+                (ADMIN_TLS_KEY, &file("missing.pem")),
+                (ADMIN_TLS_CLIENT_CA, &root),
+            ],
+            || construct_config(ProxyConfig::default()),
+        );
+
+        assert!(invalid.is_err());
+        assert!(unreadable.is_err());
+        let cfg = cfg.unwrap();
+        let tls = cfg.admin_tls.unwrap();
+        assert_eq!(tls.cert, tls_dir.join("cert.pem"));
+        assert_eq!(tls.key, tls_dir.join("key.pem"));
+        // The mesh root is trusted by default
+        assert_eq!(tls.client_ca, cfg.ca_root_cert);
+        assert_eq!(
+            custom_ca.unwrap().admin_tls.unwrap().client_ca,
+            RootCert::File(tls_dir.join("root-cert.pem"))
+        );
+    }
+
+    #[test]
+    fn config_validate_listeners() {
+        let base = construct_config(ProxyConfig::default()).unwrap();
+        let valid = |f: fn(&mut Config)| {
+            let mut cfg = base.clone();
+            f(&mut cfg);
+            cfg.validate().is_ok()
+        };
+
+        assert!(valid(|_| {}));
+        assert!(!valid(|cfg| cfg.stats_addr = cfg.outbound_addr));
+        // Unspecified IPv6 addresses also bind IPv4
+        assert!(!valid(|cfg| cfg
+            .admin_addr
+            .set_port(cfg.inbound_addr.port())));
+        assert!(valid(|cfg| {
+            cfg.admin_addr = "127.0.0.1:15001".parse().unwrap();
+            cfg.outbound_addr = "127.0.0.2:15001".parse().unwrap();
+        }));
+        // Disabled listeners are not bound
+        assert!(!valid(|cfg| {
+            cfg.dns_proxy = true;
+            cfg.dns_proxy_addr = cfg.stats_addr;
+        }));
+        assert!(valid(|cfg| cfg.dns_proxy_addr = cfg.stats_addr));
+        // In-pod listeners are bound in the pod's network namespace
+        assert!(valid(|cfg| {
+            cfg.inpod_enabled = true;
+            cfg.inbound_addr = cfg.stats_addr;
+        }));
+        // Port 0 picks a free port
+        assert!(valid(|cfg| {
+            cfg.admin_addr.set_port(0);
+            cfg.stats_addr.set_port(0);
+        }));
+    }
+
+    #[test]
+    fn config_validate_aggregates() {
+        let mut cfg = construct_config(ProxyConfig::default()).unwrap();
+        cfg.proxy_mode = ProxyMode::Dedicated;
+        cfg.inpod_enabled = true;
+        cfg.secret_ttl = Duration::ZERO;
+        cfg.xds_root_cert = RootCert::File("/nonexistent/root-cert.pem".into());
+
+        let Err(Error::Invalid(problems)) = cfg.validate() else {
+            panic!("expected the config to be invalid");
+        };
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        let err = Error::Invalid(problems).to_string();
+        assert!(err.starts_with("invalid config:\n  - "), "{err}");
+        assert!(err.contains("/nonexistent/root-cert.pem"), "{err}");
+    }
+
+    #[test]
+    fn config_file() {
+        let args = |a: &[&str]| {
+            a.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+        assert_eq!(
+            config_file_path(args(&[
+                "proxy",
+                "--config-file",
+                "/etc/ztunnel/config.yaml"
+            ])),
+            Some(PathBuf::from("/etc/ztunnel/config.yaml"))
+        );
+        assert_eq!(
+            config_file_path(args(&["--config-file=/etc/ztunnel/config.json"])),
+            Some(PathBuf::from("/etc/ztunnel/config.json"))
+        );
+
+        let settings = parse_config_file(
+            r#"
+MAX_WORKLOADS: 100
+MAX_SERVICES: "10"
+REJECT_OVER_STATE_LIMITS: true
+INBOUND_PLAINTEXT_ADDRESSES: ["[::]:15006", "127.0.0.1:15007"]
+STATE_SNAPSHOT_PATH:
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.len(), 4);
+        assert_eq!(
+            parse_config_file(r#"{"MAX_POLICIES": 5}"#).unwrap(),
+            HashMap::from([(MAX_POLICIES.to_string(), "5".to_string())])
+        );
+        assert!(parse_config_file("MAX_POLICIES: {nested: true}").is_err());
+        assert!(parse_config_file("- not a map").is_err());
+
+        // The environment takes precedence over the file
+        let cfg = with_env(&[(MAX_SERVICES, "20")], || {
+            with_config_file(settings, || {
+                construct_config(ProxyConfig::default()).unwrap()
+            })
+        });
+        assert_eq!(cfg.max_workloads, 100);
+        assert_eq!(cfg.max_services, 20);
+        assert!(cfg.reject_over_state_limits);
+        assert_eq!(
+            cfg.inbound_plaintext_addr,
+            vec![
+                "[::]:15006".parse::<SocketAddr>().unwrap(),
+                "127.0.0.1:15007".parse().unwrap()
+            ]
+        );
+        // The file only applies while parsing it
+        let cfg = construct_config(ProxyConfig::default()).unwrap();
+        assert_eq!(cfg.max_workloads, 0);
+
+        // Node metadata is read from the file too
+        let settings = parse_config_file(
+            r#"
+ISTIO_META_FROM_FILE: file
+ISTIO_METAJSON_FROM_FILE: '{"a": 1}'
+POD_NAME: ztunnel-abc
+"#,
+        )
+        .unwrap();
+        let cfg = with_config_file(settings, || {
+            construct_config(construct_proxy_config("", None).unwrap()).unwrap()
+        });
+        assert_eq!(cfg.proxy_metadata["FROM_FILE"], "file");
+        assert_eq!(cfg.proxy_metadata_json["FROM_FILE"], r#"{"a": 1}"#);
+        assert_eq!(cfg.pod_name.as_deref(), Some("ztunnel-abc"));
+
+        // The previous settings are restored even if parsing panics
+        let settings = HashMap::from([(MAX_WORKLOADS.to_string(), "100".to_string())]);
+        let res = std::panic::catch_unwind(|| with_config_file(settings, || panic!("parsing")));
+        assert!(res.is_err());
+        assert_eq!(var(MAX_WORKLOADS), None);
+    }
 }
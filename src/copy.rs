@@ -18,10 +18,15 @@ use std::cmp;
 use std::future::Future;
 use std::io::IoSlice;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::task::{ready, Context, Poll};
+use std::time::Duration;
 use tokio::io;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::trace;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::{debug, trace};
 
 // BufferedSplitter is a trait to expose splitting an IO object into a buffered reader and a writer
 pub trait BufferedSplitter: Unpin {
@@ -48,24 +53,135 @@ where
 pub trait ResizeBufRead {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>>;
     fn consume(self: Pin<&mut Self>, amt: usize);
-    fn resize(self: Pin<&mut Self>);
+    // resize grows the buffer to the given size, if it is smaller.
+    fn resize(self: Pin<&mut Self>, size: usize);
 }
 
 // Initially we create a 1k buffer for each connection. Note currently there are 3 buffers per connection.
 // Outbound: downstream to app. Upstream HBONE is optimized to avoid.
 // Inbound: downstream HBONE, upstream to app. Downstream HBONE can be optimized, but is not yet.
 const INITIAL_BUFFER_SIZE: usize = 1024;
-// We increase up to 16k for high traffic connections.
+// By default, we increase up to 16k for high traffic connections.
 // TLS record size max is 16k. But we also have an H2 frame header, so leave a bit of room for that.
-const LARGE_BUFFER_SIZE: usize = 16_384 - 64;
-// After 128k of data we will trigger a resize from INITIAL to LARGE
+pub const DEFAULT_BUFFER_SIZE: usize = 16_384 - 64;
+// After 128k of data we will trigger a resize from INITIAL to the configured buffer size
 // Loosely inspired by https://github.com/golang/go/blame/5122a6796ef98e3453c994c95abd640596540bea/src/crypto/tls/conn.go#L873
 const RESIZE_THRESHOLD: u64 = 128 * 1024;
+// Resized buffers are returned to a pool when their connection closes, and reused by the next
+// connection to resize, so high traffic connections don't each allocate their own. At most this many
+// buffers are kept.
+const MAX_POOLED_BUFFERS: usize = 256;
 
+static BUFFER_POOL: Mutex<Vec<Box<[u8]>>> = Mutex::new(Vec::new());
+
+// take_buffer returns a buffer of the given size, from the pool if possible. A pooled buffer may
+// hold stale data, which is fine as BufReader only reads back bytes it has filled.
+fn take_buffer(size: usize) -> Box<[u8]> {
+    let pooled = BUFFER_POOL.lock().expect("mutex").pop();
+    match pooled {
+        Some(buf) if buf.len() == size => buf,
+        _ => vec![0u8; size].into_boxed_slice(),
+    }
+}
+
+// release_buffer returns a resized buffer to the pool.
+fn release_buffer(buf: Box<[u8]>) {
+    if buf.len() <= INITIAL_BUFFER_SIZE {
+        return;
+    }
+    let mut pool = BUFFER_POOL.lock().expect("mutex");
+    if pool.len() < MAX_POOLED_BUFFERS {
+        pool.push(buf);
+    }
+}
+
+/// pooled_buffer_bytes returns the total size of the relay buffers held for reuse.
+pub fn pooled_buffer_bytes() -> usize {
+    BUFFER_POOL
+        .lock()
+        .expect("mutex")
+        .iter()
+        .map(|buf| buf.len())
+        .sum()
+}
+
+/// CopyConfig configures how data is relayed between the two sides of a connection.
+#[derive(Clone, Copy, Debug)]
+pub struct CopyConfig {
+    /// If set, the connection is terminated once no bytes have been transferred in either
+    /// direction for this duration.
+    pub idle_timeout: Option<Duration>,
+    /// The size each direction's buffer grows to for high traffic connections.
+    pub buffer_size: usize,
+    /// Whether TCP to TCP copies use splice, where supported. See copy_bidirectional_tcp.
+    pub splice: bool,
+}
+
+impl Default for CopyConfig {
+    fn default() -> Self {
+        CopyConfig {
+            idle_timeout: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            splice: false,
+        }
+    }
+}
+
+impl From<&crate::config::Config> for CopyConfig {
+    fn from(cfg: &crate::config::Config) -> Self {
+        CopyConfig {
+            idle_timeout: cfg.reloadable.idle_timeout(),
+            buffer_size: cfg.relay_buffer_size,
+            splice: cfg.enable_splice,
+        }
+    }
+}
+
+// IdleTracker records the last time any bytes were transferred, in either direction, on a connection.
+struct IdleTracker {
+    start: Instant,
+    // last_activity is the time of the last transfer, stored as millis since start so it can be atomic.
+    last_activity: AtomicU64,
+}
+
+impl IdleTracker {
+    fn new() -> Self {
+        IdleTracker {
+            start: Instant::now(),
+            last_activity: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_activity.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    fn last_activity(&self) -> Instant {
+        self.start + Duration::from_millis(self.last_activity.load(Ordering::Relaxed))
+    }
+
+    // idle_timeout resolves once no bytes have been transferred for at least the timeout.
+    async fn idle_timeout(&self, timeout: Duration) {
+        loop {
+            let deadline = self.last_activity() + timeout;
+            tokio::time::sleep_until(deadline).await;
+            // There may have been activity while we slept; if so, sleep until the new deadline.
+            if self.last_activity() + timeout <= Instant::now() {
+                return;
+            }
+        }
+    }
+}
+
+// copy_bidirectional copies data between downstream and upstream until both sides are closed.
+// If an idle timeout is configured, the connection is terminated once no bytes have been
+// transferred in either direction for that duration.
 pub async fn copy_bidirectional<A, B>(
     downstream: A,
     upstream: B,
     stats: &ConnectionResult,
+    cfg: CopyConfig,
 ) -> Result<(), crate::proxy::Error>
 where
     A: BufferedSplitter,
@@ -76,27 +192,229 @@ where
     let (mut ru, mut wu) = upstream.split_into_buffered_reader();
 
     let (mut sent, mut received): (u64, u64) = (0, 0);
+    let idle = IdleTracker::new();
 
     let downstream_to_upstream = async {
-        let res = copy_buf(&mut rd, &mut wu, stats, false).await;
+        let res = copy_buf(&mut rd, &mut wu, stats, &idle, false, cfg.buffer_size).await;
         trace!(?res, "send");
         sent = res?;
         wu.shutdown().await
     };
 
     let upstream_to_downstream = async {
-        let res = copy_buf(&mut ru, &mut wd, stats, true).await;
+        let res = copy_buf(&mut ru, &mut wd, stats, &idle, true, cfg.buffer_size).await;
         trace!(?res, "recieve");
         received = res?;
         wd.shutdown().await
     };
 
-    tokio::try_join!(downstream_to_upstream, upstream_to_downstream)?;
+    let copy = async { tokio::try_join!(downstream_to_upstream, upstream_to_downstream) };
+    run_with_idle_timeout(copy, &idle, cfg.idle_timeout).await?;
 
     trace!(sent, received, "copy complete");
     Ok(())
 }
 
+// AsTcpStream exposes the TCP connection underlying a stream, if any, so it can be relayed with
+// splice.
+pub trait AsTcpStream {
+    fn as_tcp_stream(&self) -> Option<&TcpStream>;
+}
+
+impl AsTcpStream for TcpStream {
+    fn as_tcp_stream(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+#[cfg(unix)]
+impl AsTcpStream for tokio::net::UnixStream {
+    fn as_tcp_stream(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+// copy_bidirectional_tcp copies data between a downstream and a TCP upstream, like
+// copy_bidirectional. It is meant for plaintext connections, where we do not need to look at the
+// data: if splice is enabled and the downstream is also TCP, on Linux the data is moved between the
+// sockets with splice(2), without copying it to userspace. Otherwise, this falls back to
+// copy_bidirectional.
+pub async fn copy_bidirectional_tcp<A>(
+    downstream: &mut A,
+    upstream: &mut TcpStream,
+    stats: &ConnectionResult,
+    cfg: CopyConfig,
+) -> Result<(), crate::proxy::Error>
+where
+    A: AsTcpStream + AsyncRead + AsyncWrite + Unpin,
+{
+    #[cfg(target_os = "linux")]
+    if let (true, Some(tcp)) = (cfg.splice, downstream.as_tcp_stream()) {
+        match (splice::Pipe::new(), splice::Pipe::new()) {
+            (Ok(send), Ok(recv)) => {
+                return Box::pin(splice_bidirectional(
+                    tcp,
+                    upstream,
+                    (send, recv),
+                    stats,
+                    cfg.idle_timeout,
+                ))
+                .await;
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                debug!("failed to create pipe, falling back to buffered copy: {e}");
+            }
+        }
+    }
+    copy_bidirectional(downstream, upstream, stats, cfg).await
+}
+
+// splice_bidirectional is copy_bidirectional for two TCP connections, using a pipe per direction
+// to splice data between them.
+#[cfg(target_os = "linux")]
+async fn splice_bidirectional(
+    downstream: &TcpStream,
+    upstream: &TcpStream,
+    (send_pipe, recv_pipe): (splice::Pipe, splice::Pipe),
+    stats: &ConnectionResult,
+    idle_timeout: Option<Duration>,
+) -> Result<(), crate::proxy::Error> {
+    let idle = IdleTracker::new();
+    // Bytes are reported as in copy_bidirectional
+    let downstream_to_upstream = splice::copy(downstream, upstream, &send_pipe, |n| {
+        idle.touch();
+        stats.increment_recv(n as u64);
+    });
+    let upstream_to_downstream = splice::copy(upstream, downstream, &recv_pipe, |n| {
+        idle.touch();
+        stats.increment_send(n as u64);
+    });
+    let copy = async {
+        let (sent, received) = tokio::try_join!(downstream_to_upstream, upstream_to_downstream)?;
+        trace!(sent, received, "splice complete");
+        Ok::<_, std::io::Error>(())
+    };
+    run_with_idle_timeout(copy, &idle, idle_timeout).await
+}
+
+// run_with_idle_timeout runs a copy to completion, unless the connection is idle for longer than
+// the timeout.
+async fn run_with_idle_timeout<F, T>(
+    copy: F,
+    idle: &IdleTracker,
+    idle_timeout: Option<Duration>,
+) -> Result<(), crate::proxy::Error>
+where
+    F: Future<Output = std::io::Result<T>>,
+{
+    match idle_timeout {
+        Some(timeout) => {
+            tokio::select! {
+                res = copy => { res?; }
+                _ = idle.idle_timeout(timeout) => {
+                    debug!(?timeout, "connection idle timeout exceeded");
+                    return Err(crate::proxy::Error::IdleTimeout);
+                }
+            }
+        }
+        None => {
+            copy.await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+mod splice {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+    use tokio::io::{self, Interest};
+    use tokio::net::TcpStream;
+
+    // The most data moved by a single splice; this is the default capacity of a pipe.
+    const PIPE_SIZE: usize = 64 * 1024;
+
+    // Pipe is a non-blocking pipe, used as the intermediate buffer when splicing between sockets.
+    pub struct Pipe {
+        read: OwnedFd,
+        write: OwnedFd,
+    }
+
+    impl Pipe {
+        pub fn new() -> io::Result<Pipe> {
+            let mut fds: [libc::c_int; 2] = [0; 2];
+            let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Safety: pipe2 succeeded, so these are new file descriptors that we own.
+            let (read, write) =
+                unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+            Ok(Pipe { read, write })
+        }
+    }
+
+    fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::splice(
+                from,
+                std::ptr::null_mut(),
+                to,
+                std::ptr::null_mut(),
+                len,
+                libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    // copy splices data from one socket to the other until the source is closed, then shuts down
+    // writes to the destination. `transferred` is called with the number of bytes written each time
+    // data is written. Returns the total number of bytes copied.
+    //
+    // The pipe is always drained before reading more, so a splice into it only fails with EAGAIN
+    // when the source has no data, and a splice out of it only when the destination is full. This
+    // lets us rely on socket readiness alone.
+    pub async fn copy(
+        from: &TcpStream,
+        to: &TcpStream,
+        pipe: &Pipe,
+        mut transferred: impl FnMut(usize),
+    ) -> io::Result<u64> {
+        let mut total = 0;
+        loop {
+            let n = from
+                .async_io(Interest::READABLE, || {
+                    splice(from.as_raw_fd(), pipe.write.as_raw_fd(), PIPE_SIZE)
+                })
+                .await?;
+            if n == 0 {
+                break;
+            }
+            let mut pending = n;
+            while pending > 0 {
+                let written = to
+                    .async_io(Interest::WRITABLE, || {
+                        splice(pipe.read.as_raw_fd(), to.as_raw_fd(), pending)
+                    })
+                    .await?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                pending -= written;
+                total += written as u64;
+                transferred(written);
+            }
+        }
+        socket2::SockRef::from(to).shutdown(std::net::Shutdown::Write)?;
+        Ok(total)
+    }
+}
+
 // CopyBuf is a fork of Tokio's same struct, with additional support for resizing and metrics reporting.
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 struct CopyBuf<'a, R: ?Sized, W: ?Sized> {
@@ -104,6 +422,8 @@ struct CopyBuf<'a, R: ?Sized, W: ?Sized> {
     reader: &'a mut R,
     writer: &'a mut W,
     metrics: &'a ConnectionResult,
+    idle: &'a IdleTracker,
+    buffer_size: usize,
     amt: u64,
 }
 
@@ -111,7 +431,9 @@ async fn copy_buf<'a, R, W>(
     reader: &'a mut R,
     writer: &'a mut W,
     metrics: &ConnectionResult,
+    idle: &IdleTracker,
     is_send: bool,
+    buffer_size: usize,
 ) -> std::io::Result<u64>
 where
     R: ResizeBufRead + Unpin + ?Sized,
@@ -122,6 +444,8 @@ where
         reader,
         writer,
         metrics,
+        idle,
+        buffer_size,
         amt: 0,
     }
     .await
@@ -147,6 +471,7 @@ where
             if i == 0 {
                 return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
             }
+            me.idle.touch();
             if me.send {
                 me.metrics.increment_send(i as u64);
             } else {
@@ -157,7 +482,8 @@ where
 
             // If we were below the resize threshold before but are now above it, trigger the buffer to resize
             if old < RESIZE_THRESHOLD && RESIZE_THRESHOLD <= self.amt {
-                Pin::new(&mut *self.reader).resize();
+                let size = self.buffer_size;
+                Pin::new(&mut *self.reader).resize(size);
             }
             Pin::new(&mut *self.reader).consume(i);
         }
@@ -173,6 +499,13 @@ pin_project! {
         pos: usize,
         cap: usize,
     }
+
+    impl<R> PinnedDrop for BufReader<R> {
+        fn drop(this: Pin<&mut Self>) {
+            let me = this.project();
+            release_buffer(std::mem::take(me.buf));
+        }
+    }
 }
 
 impl<R: AsyncRead> BufReader<R> {
@@ -219,16 +552,17 @@ impl<R: AsyncRead> ResizeBufRead for BufReader<R> {
         *me.pos = cmp::min(*me.pos + amt, *me.cap);
     }
 
-    fn resize(self: Pin<&mut Self>) {
+    fn resize(self: Pin<&mut Self>, size: usize) {
         let me = self.project();
-        // If we don't hit this, we somehow called resize twice unexpectedly
-        debug_assert_eq!(me.buf.len(), INITIAL_BUFFER_SIZE);
-        // Make a new buffer of the large size, and swap it into place
-        let mut now = vec![0u8; LARGE_BUFFER_SIZE].into_boxed_slice();
+        if size <= me.buf.len() {
+            return;
+        }
+        // Take a buffer of the new size, and swap it into place
+        let mut now = take_buffer(size);
         std::mem::swap(me.buf, &mut now);
         // Now copy over any data from the old buffer.
         me.buf[0..now.len()].copy_from_slice(&now);
-        trace!("resized buffer to {}", LARGE_BUFFER_SIZE)
+        trace!("resized buffer to {}", size)
     }
 }
 
@@ -261,3 +595,154 @@ impl<R: AsyncRead + AsyncWrite> AsyncWrite for BufReader<R> {
         self.get_ref().is_write_vectored()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::metrics::{ConnectionOpen, Reporter, SecurityPolicy};
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use tokio::io::AsyncWriteExt;
+
+    fn test_connection_result() -> ConnectionResult {
+        ConnectionResult::new(
+            crate::proxy::ConnectionId::new(),
+            "127.0.0.1:1000".parse().unwrap(),
+            "127.0.0.2:80".parse().unwrap(),
+            None,
+            std::time::Instant::now(),
+            ConnectionOpen {
+                reporter: Reporter::source,
+                source: None,
+                derived_source: None,
+                destination: None,
+                destination_service: None,
+                connection_security_policy: SecurityPolicy::unknown,
+            },
+            test_proxy_metrics(),
+            Default::default(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let (downstream, _client) = tokio::io::duplex(1024);
+        let (upstream, _server) = tokio::io::duplex(1024);
+        let stats = test_connection_result();
+
+        let res = copy_bidirectional(
+            downstream,
+            upstream,
+            &stats,
+            CopyConfig {
+                idle_timeout: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(matches!(res, Err(crate::proxy::Error::IdleTimeout)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout_reset_by_activity() {
+        let (downstream, mut client) = tokio::io::duplex(1024);
+        let (upstream, _server) = tokio::io::duplex(1024);
+        let stats = test_connection_result();
+
+        let start = Instant::now();
+        let writer = async move {
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                client.write_all(b"hello").await.unwrap();
+            }
+            // Hold the connection open, but idle
+            std::future::pending::<()>().await;
+        };
+        let copy = copy_bidirectional(
+            downstream,
+            upstream,
+            &stats,
+            CopyConfig {
+                idle_timeout: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        );
+        let res = tokio::select! {
+            res = copy => res,
+            _ = writer => unreachable!(),
+        };
+        assert!(matches!(res, Err(crate::proxy::Error::IdleTimeout)));
+        // 5 writes 5s apart, then 10s of idleness
+        assert!(start.elapsed() >= Duration::from_secs(35));
+    }
+
+    #[tokio::test]
+    async fn resize_keeps_buffered_data() {
+        let (mut client, server) = tokio::io::duplex(64);
+        client.write_all(b"hello world").await.unwrap();
+        let mut reader = BufReader::new(server);
+
+        let buf = std::future::poll_fn(|cx| Pin::new(&mut reader).poll_fill_buf(cx))
+            .await
+            .unwrap();
+        assert_eq!(buf, b"hello world");
+        Pin::new(&mut reader).consume(6);
+
+        Pin::new(&mut reader).resize(64 * 1024);
+        assert_eq!(reader.buf.len(), 64 * 1024);
+        // Shrinking is not supported
+        Pin::new(&mut reader).resize(INITIAL_BUFFER_SIZE);
+        assert_eq!(reader.buf.len(), 64 * 1024);
+
+        let buf = std::future::poll_fn(|cx| Pin::new(&mut reader).poll_fill_buf(cx))
+            .await
+            .unwrap();
+        assert_eq!(buf, b"world");
+    }
+
+    #[tokio::test]
+    async fn relay_tcp() {
+        use tokio::io::AsyncReadExt;
+
+        async fn tcp_pair() -> (TcpStream, TcpStream) {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+            (client.unwrap(), server.unwrap().0)
+        }
+
+        for splice in [false, true] {
+            let (mut client, mut downstream) = tcp_pair().await;
+            let (mut upstream, mut server) = tcp_pair().await;
+            let stats = test_connection_result();
+            let request = vec![1u8; 1024 * 1024];
+
+            let client_io = async {
+                client.write_all(&request).await.unwrap();
+                client.shutdown().await.unwrap();
+                let mut response = Vec::new();
+                client.read_to_end(&mut response).await.unwrap();
+                response
+            };
+            let server_io = async {
+                let mut received = Vec::new();
+                server.read_to_end(&mut received).await.unwrap();
+                server.write_all(b"done").await.unwrap();
+                server.shutdown().await.unwrap();
+                received
+            };
+            let relay = copy_bidirectional_tcp(
+                &mut downstream,
+                &mut upstream,
+                &stats,
+                CopyConfig {
+                    splice,
+                    ..Default::default()
+                },
+            );
+            let (response, received, res) = tokio::join!(client_io, server_io, relay);
+            res.unwrap();
+            assert_eq!(received, request, "splice={splice}");
+            assert_eq!(response, b"done", "splice={splice}");
+        }
+    }
+}
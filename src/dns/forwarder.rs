@@ -12,13 +12,30 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::config::DnsTlsUpstream;
 use crate::dns::resolver::{Answer, Resolver};
-use hickory_resolver::config::{ResolverConfig, ResolverOpts};
-use hickory_resolver::error::ResolveError;
-use hickory_resolver::name_server::TokioConnectionProvider;
-use hickory_resolver::TokioAsyncResolver;
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
+use hickory_proto::TokioTime;
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::name_server::{
+    GenericConnector, RuntimeProvider, TokioConnectionProvider, TokioHandle, TokioRuntimeProvider,
+};
+use hickory_resolver::{AsyncResolver, TokioAsyncResolver};
 use hickory_server::authority::LookupError;
 use hickory_server::server::Request;
+use rustls::pki_types::ServerName;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tracing::warn;
 
 /// A forwarding [Resolver] that delegates requests to an upstream [TokioAsyncResolver].
 pub struct Forwarder(TokioAsyncResolver);
@@ -31,6 +48,121 @@ impl Forwarder {
     }
 }
 
+/// A [Resolver] that delegates requests to upstreams over DNS-over-TLS. Connections to the
+/// upstreams are kept open and reused across requests.
+///
+/// If a fallback is set, requests are sent there instead when none of the upstreams could provide
+/// an answer, for example because they are unreachable.
+pub struct TlsForwarder {
+    resolver: AsyncResolver<GenericConnector<TlsRuntimeProvider>>,
+    fallback: Option<Arc<dyn Resolver>>,
+}
+
+impl TlsForwarder {
+    /// Creates a new [TlsForwarder], connecting to the upstreams with the given TLS config.
+    pub fn new(
+        upstreams: &[DnsTlsUpstream],
+        client_config: Arc<rustls::ClientConfig>,
+        opts: ResolverOpts,
+        fallback: Option<Arc<dyn Resolver>>,
+    ) -> Result<Self, ResolveError> {
+        let server_names = upstreams
+            .iter()
+            .map(|u| {
+                ServerName::try_from(u.tls_name.clone())
+                    .map(|name| (u.addr, name))
+                    .map_err(|_| format!("invalid DNS-over-TLS server name {}", u.tls_name))
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        // The resolver frames messages as for plain TCP; the runtime provider establishes TLS on
+        // each connection.
+        let name_servers = upstreams
+            .iter()
+            .map(|u| NameServerConfig::new(u.addr, Protocol::Tcp))
+            .collect::<Vec<_>>();
+        let cfg = ResolverConfig::from_parts(None, vec![], name_servers);
+        let runtime = TlsRuntimeProvider {
+            runtime: TokioRuntimeProvider::new(),
+            connector: TlsConnector::from(client_config),
+            server_names: Arc::new(server_names),
+        };
+        let resolver = AsyncResolver::new(cfg, opts, GenericConnector::new(runtime));
+        Ok(Self { resolver, fallback })
+    }
+}
+
+/// TlsRuntimeProvider runs the resolver on tokio, like [TokioRuntimeProvider], except that its TCP
+/// connections are wrapped in TLS. This lets DNS-over-TLS use our own TLS stack, and so the
+/// configured TLS versions, cipher suites and crypto provider, rather than the resolver's.
+#[derive(Clone)]
+struct TlsRuntimeProvider {
+    runtime: TokioRuntimeProvider,
+    connector: TlsConnector,
+    // The name each upstream's certificate is verified against, by address
+    server_names: Arc<HashMap<SocketAddr, ServerName<'static>>>,
+}
+
+impl RuntimeProvider for TlsRuntimeProvider {
+    type Handle = TokioHandle;
+    type Timer = TokioTime;
+    type Udp = UdpSocket;
+    type Tcp = AsyncIoTokioAsStd<TlsStream<TcpStream>>;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.runtime.create_handle()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        let connector = self.connector.clone();
+        let server_name = self.server_names.get(&server_addr).cloned();
+        Box::pin(async move {
+            let server_name = server_name.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{server_addr} is not a DNS-over-TLS upstream"),
+                )
+            })?;
+            let stream = TcpStream::connect(server_addr).await?;
+            let stream = connector.connect(server_name, stream).await?;
+            Ok(AsyncIoTokioAsStd(stream))
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        local_addr: SocketAddr,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        // Upstreams are only reached over TCP, so this is never used
+        self.runtime.bind_udp(local_addr, server_addr)
+    }
+}
+
+#[async_trait::async_trait]
+impl Resolver for TlsForwarder {
+    async fn lookup(&self, request: &Request) -> Result<Answer, LookupError> {
+        let name = request.query().name();
+        let rr_type = request.query().query_type();
+        match self.resolver.lookup(name, rr_type).await {
+            Ok(answer) => Ok(Answer::from(answer)),
+            // The upstream answered; there is just nothing to return
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                Err(LookupError::from(e))
+            }
+            Err(e) => match &self.fallback {
+                Some(fallback) => {
+                    warn!("DNS-over-TLS lookup failed, falling back to plaintext: {e}");
+                    fallback.lookup(request).await
+                }
+                None => Err(LookupError::from(e)),
+            },
+        }
+    }
+}
+
 #[async_trait::async_trait]
 impl Resolver for Forwarder {
     async fn lookup(&self, request: &Request) -> Result<Answer, LookupError> {
@@ -48,13 +180,19 @@ impl Resolver for Forwarder {
 #[cfg(test)]
 #[cfg(any(unix, target_os = "windows"))]
 mod tests {
-    use crate::dns::resolver::Resolver;
-    use crate::test_helpers::dns::{a_request, n, socket_addr, system_forwarder};
+    use std::sync::Arc;
+
+    use crate::config::DnsTlsUpstream;
+    use crate::dns::forwarder::TlsForwarder;
+    use crate::dns::resolver::{Answer, Resolver};
+    use crate::test_helpers::dns::{a, a_request, ipv4, n, socket_addr, system_forwarder};
     use crate::test_helpers::helpers::initialize_telemetry;
     use hickory_proto::op::ResponseCode;
     use hickory_proto::rr::RecordType;
+    use hickory_resolver::config::ResolverOpts;
     use hickory_resolver::error::ResolveErrorKind;
-    use hickory_server::server::Protocol;
+    use hickory_server::authority::LookupError;
+    use hickory_server::server::{Protocol, Request};
 
     #[tokio::test]
     async fn found() {
@@ -106,4 +244,48 @@ mod tests {
             _ => panic!("unexpected error kind {kind}"),
         }
     }
+
+    struct StaticResolver;
+
+    #[async_trait::async_trait]
+    impl Resolver for StaticResolver {
+        async fn lookup(&self, request: &Request) -> Result<Answer, LookupError> {
+            let name = request.query().original().name().clone();
+            Ok(Answer::new(vec![a(name, ipv4("10.0.0.1"))], false))
+        }
+    }
+
+    #[tokio::test]
+    async fn tls_fallback() {
+        initialize_telemetry();
+
+        // Nothing is listening on this upstream, so lookups fail.
+        let upstreams = [DnsTlsUpstream {
+            addr: socket_addr("127.0.0.1:1"),
+            tls_name: "dns.example.com".to_string(),
+        }];
+        let req = a_request(n("example.com"), socket_addr("1.1.1.1:80"), Protocol::Udp);
+
+        let client_config =
+            Arc::new(crate::tls::dns_client_config(&crate::tls::TlsSettings::default()).unwrap());
+        let f = TlsForwarder::new(
+            &upstreams,
+            client_config.clone(),
+            ResolverOpts::default(),
+            None,
+        )
+        .unwrap();
+        assert!(f.lookup(&req).await.is_err());
+
+        let f = TlsForwarder::new(
+            &upstreams,
+            client_config,
+            ResolverOpts::default(),
+            Some(Arc::new(StaticResolver)),
+        )
+        .unwrap();
+        let answer = f.lookup(&req).await.unwrap();
+        let record = answer.record_iter().next().unwrap();
+        assert_eq!(n("example.com"), *record.name());
+    }
 }
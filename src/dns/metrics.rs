@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use hickory_proto::op::ResponseCode;
 use hickory_server::server::Request;
 use prometheus_client::encoding::EncodeLabelSet;
 use prometheus_client::metrics::counter::Counter;
@@ -30,8 +31,14 @@ pub struct Metrics {
     pub forwarded_requests: Family<DnsLabels, Counter>,
     pub forwarded_failures: Family<DnsLabels, Counter>,
     pub forwarded_duration: Family<DnsLabels, Histogram>,
+    pub responses: Family<DnsResponseLabels, Counter>,
+    pub mesh_nxdomain: Family<DnsLabels, Counter>,
 }
 
+// Buckets for the upstream request duration, in seconds.
+const FORWARDED_DURATION_BUCKETS: [f64; 10] =
+    [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
 impl Metrics {
     pub fn new(registry: &mut Registry) -> Self {
         let requests = Family::default();
@@ -56,7 +63,7 @@ impl Metrics {
         );
 
         let forwarded_duration = Family::<DnsLabels, Histogram>::new_with_constructor(|| {
-            Histogram::new(vec![0.005f64, 0.001, 0.01, 0.1, 1.0, 5.0].into_iter())
+            Histogram::new(FORWARDED_DURATION_BUCKETS.into_iter())
         });
         registry.register_with_unit(
             "dns_upstream_request_duration",
@@ -65,11 +72,27 @@ impl Metrics {
             forwarded_duration.clone(),
         );
 
+        let responses = Family::default();
+        registry.register(
+            "dns_responses",
+            "Total number of DNS responses, by response code (unstable)",
+            responses.clone(),
+        );
+
+        let mesh_nxdomain = Family::default();
+        registry.register(
+            "dns_mesh_nxdomain",
+            "Total number of NXDOMAIN responses for names in the mesh service domain (unstable)",
+            mesh_nxdomain.clone(),
+        );
+
         Self {
             requests,
             forwarded_requests,
             forwarded_failures,
             forwarded_duration,
+            responses,
+            mesh_nxdomain,
         }
     }
 }
@@ -199,3 +222,64 @@ impl From<&ForwardedDuration<'_>> for DnsLabels {
         labels
     }
 }
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct DnsResponseLabels {
+    #[prometheus(flatten)]
+    request: DnsLabels,
+    response_code: RichStrng,
+}
+
+#[derive(Clone)]
+pub struct DnsResponse<'a> {
+    pub request: &'a Request,
+    pub source: Option<&'a Workload>,
+    pub response_code: ResponseCode,
+}
+
+impl Recorder<DnsResponse<'_>, u64> for Metrics {
+    fn record(&self, reason: &DnsResponse, count: u64) {
+        self.responses
+            .get_or_create(&DnsResponseLabels::from(reason))
+            .inc_by(count);
+    }
+}
+
+impl From<&DnsResponse<'_>> for DnsResponseLabels {
+    fn from(value: &DnsResponse) -> Self {
+        let mut request = DnsLabels::new(value.request);
+        if let Some(source) = &value.source {
+            request = request.with_source(source)
+        }
+        Self {
+            request,
+            // Debug gives the short form of the code (e.g. "NXDomain"), rather than the
+            // human readable description from Display.
+            response_code: format!("{:?}", value.response_code).to_lowercase().into(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MeshNxDomain<'a> {
+    pub request: &'a Request,
+    pub source: Option<&'a Workload>,
+}
+
+impl Recorder<MeshNxDomain<'_>, u64> for Metrics {
+    fn record(&self, reason: &MeshNxDomain, count: u64) {
+        self.mesh_nxdomain
+            .get_or_create(&DnsLabels::from(reason))
+            .inc_by(count);
+    }
+}
+
+impl From<&MeshNxDomain<'_>> for DnsLabels {
+    fn from(value: &MeshNxDomain) -> Self {
+        let mut labels = Self::new(value.request);
+        if let Some(source) = &value.source {
+            labels = labels.with_source(source)
+        }
+        labels
+    }
+}
@@ -25,7 +25,8 @@ use hickory_proto::error::ProtoErrorKind;
 use hickory_proto::op::ResponseCode;
 use hickory_proto::rr::rdata::{A, AAAA, CNAME};
 use hickory_proto::rr::{Name, RData, Record, RecordType};
-use hickory_resolver::config::ResolverConfig;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
 use hickory_resolver::system_conf::read_system_conf;
 use hickory_server::authority::LookupError;
 use hickory_server::server::Request;
@@ -34,14 +35,16 @@ use itertools::Itertools;
 use once_cell::sync::Lazy;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{debug, info, instrument, trace, warn};
 
 use crate::proxy::SocketFactory;
 
-use crate::config::ProxyMode;
+use crate::config::{Config, DnsTlsUpstream, ProxyMode};
 use crate::dns;
 use crate::dns::metrics::{
-    DnsRequest, ForwardedDuration, ForwardedFailure, ForwardedRequest, Metrics,
+    DnsRequest, DnsResponse, ForwardedDuration, ForwardedFailure, ForwardedRequest, MeshNxDomain,
+    Metrics,
 };
 use crate::dns::name_util::{has_domain, trim_domain};
 use crate::dns::resolver::{Answer, Resolver};
@@ -49,7 +52,7 @@ use crate::metrics::{DeferRecorder, IncrementRecorder, Recorder};
 use crate::proxy::Error;
 use crate::socket::to_canonical;
 use crate::state::workload::address::Address;
-use crate::state::workload::{NetworkAddress, Workload};
+use crate::state::workload::{NamespacedHostname, NetworkAddress, Workload};
 use crate::state::DemandProxyState;
 use crate::strng::Strng;
 
@@ -64,6 +67,7 @@ pub struct Server {
     tcp_addr: SocketAddr,
     udp_addr: SocketAddr,
     server: ServerFuture<dns::handler::Handler>,
+    state: DemandProxyState,
     drain: Watch,
 }
 
@@ -91,7 +95,7 @@ impl Server {
         let handler = dns::handler::Handler::new(Arc::new(Store::new(
             domain,
             network.as_ref().to_string(),
-            state,
+            state.clone(),
             forwarder,
             metrics,
         )));
@@ -123,6 +127,7 @@ impl Server {
             tcp_addr,
             udp_addr,
             server,
+            state,
             drain,
         })
     }
@@ -139,6 +144,7 @@ impl Server {
 
     /// Runs this DNS server to completion.
     pub async fn run(mut self) {
+        tokio::spawn(evict_cache(self.state.clone(), self.drain.clone()));
         tokio::select! {
             res = self.server.block_until_done() =>{
                 if let Err(e) = res {
@@ -157,6 +163,24 @@ impl Server {
     }
 }
 
+// evict_cache drops cached DNS state as the workloads and services it is for are removed.
+async fn evict_cache(state: DemandProxyState, drain: Watch) {
+    let mut events = state.read().events.subscribe();
+    let stop = drain.signaled();
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            _ = &mut stop => return,
+            event = events.recv() => match event {
+                Ok(event) => state.evict_dns(&event),
+                // Cached entries expire on their own, so missing some evictions is harmless.
+                Err(RecvError::Lagged(n)) => debug!("missed {n} state events, skipping evictions"),
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
 /// A DNS [Resolver] backed by the ztunnel [DemandProxyState].
 struct Store {
     network: Strng,
@@ -442,6 +466,158 @@ impl Store {
         addrs
     }
 
+    /// Resolves the request for a known client, forwarding to upstream if the
+    /// requested host is not in the mesh.
+    async fn resolve(&self, client: &Workload, request: &Request) -> Result<Answer, LookupError> {
+        // Make sure the request is for IP records. Anything else, we forward.
+        let record_type = request.query().query_type();
+        if !is_record_type_supported(record_type) {
+            debug!("unknown record type");
+            return self.forward(Some(client), request).await;
+        }
+
+        // Find the service for the requested host.
+        let requested_name = Name::from(request.query().name().clone());
+        let Some(service_match) = self.find_server(client, &requested_name) else {
+            trace!("unknown host, forwarding");
+            // Unknown host. Forward to the upstream resolver.
+            let answer = self.forward(Some(client), request).await?;
+            self.capture_hostname(client, &requested_name, &answer);
+            return Ok(answer);
+        };
+
+        // Increment counter for all requests.
+        self.metrics.increment(&DnsRequest {
+            request,
+            source: Some(client),
+        });
+
+        // Get the addresses for the service.
+        let addresses = self.get_addresses(client, &service_match.server, record_type);
+
+        // From this point on, we are the authority for the response.
+        let is_authoritative = true;
+
+        if addresses.is_empty() {
+            debug!(alias=%service_match.alias, name=%service_match.name, "no records");
+            // Lookup succeeded, but no records were returned. This is not NXDOMAIN, since we
+            // found the host. Just return an empty set of records.
+            return Ok(Answer::new(Vec::default(), is_authoritative));
+        }
+
+        // Create a vec to hold the output records.
+        let mut records = Vec::new();
+
+        // Assume that we'll just use the requested name as the record name.
+        let mut ip_record_name = requested_name.clone();
+
+        debug!(alias=%service_match.alias, name=%service_match.name, "success");
+        // If the service was found by stripping off one of the search domains, create a
+        // CNAME record to map to the appropriate canonical name.
+        if let Some(stripped) = service_match.alias.stripped {
+            if service_match.name.is_wildcard() {
+                // The match is a wildcard...
+
+                // Create a CNAME record that maps from the wildcard with the search domain to
+                // the wildcard without it.
+                let cname_record_name = service_match
+                    .name
+                    .clone()
+                    .append_domain(&stripped.search_domain)
+                    .unwrap();
+                let canonical_name = service_match.name;
+                records.push(cname_record(cname_record_name, canonical_name));
+
+                // For wildcards, continue using the original requested hostname for IP records.
+            } else {
+                // The match is NOT a wildcard...
+
+                // Create a CNAME record to map from the requested name -> stripped name.
+                let canonical_name = stripped.name;
+                records.push(cname_record(requested_name.clone(), canonical_name.clone()));
+
+                // Also use the stripped name as the IP record name.
+                ip_record_name = canonical_name;
+            }
+        }
+
+        // Add the IP records.
+        ip_records(ip_record_name, addresses, &mut records);
+
+        Ok(Answer::new(records, is_authoritative))
+    }
+
+    /// If the requested name belongs to a service without VIPs, such as a ServiceEntry resolved
+    /// with DNS, records the addresses upstream DNS returned for it. Traffic the client then sends
+    /// to those addresses can be matched to the service.
+    fn capture_hostname(&self, client: &Workload, requested_name: &Name, answer: &Answer) {
+        let hostname = {
+            let state = self.state.read();
+            get_wildcards(requested_name)
+                .into_iter()
+                .find_map(|mut name| {
+                    name.set_fqdn(false);
+                    state
+                        .services
+                        .get_by_host(&name.to_string().into())
+                        .into_iter()
+                        .flatten()
+                        .filter(|service| service.vips.is_empty())
+                        .find_or_first(|service| service.namespace == client.namespace)
+                        .map(|service| NamespacedHostname {
+                            namespace: service.namespace,
+                            hostname: service.hostname,
+                        })
+                })
+        };
+        let Some(hostname) = hostname else {
+            return;
+        };
+        for record in answer.record_iter() {
+            let addr = match record.data() {
+                Some(RData::A(a)) => IpAddr::V4(a.0),
+                Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+                _ => continue,
+            };
+            self.state.capture_hostname(
+                NetworkAddress {
+                    network: client.network.clone(),
+                    address: addr,
+                },
+                hostname.clone(),
+                Duration::from_secs(u64::from(record.ttl())),
+            );
+        }
+    }
+
+    /// Records the outcome of a lookup.
+    fn record_response(
+        &self,
+        client: Option<&Workload>,
+        request: &Request,
+        result: &Result<Answer, LookupError>,
+    ) {
+        let response_code = lookup_response_code(result);
+        self.metrics.increment(&DnsResponse {
+            request,
+            source: client,
+            response_code,
+        });
+
+        // An NXDOMAIN for a name in the service domain typically means a client is
+        // referencing a mesh service that doesn't exist (or we don't know about yet).
+        if response_code == ResponseCode::NXDomain
+            && self
+                .svc_domain
+                .zone_of(&Name::from(request.query().name().clone()))
+        {
+            self.metrics.increment(&MeshNxDomain {
+                request,
+                source: client,
+            });
+        }
+    }
+
     async fn forward(
         &self,
         client: Option<&Workload>,
@@ -508,85 +684,16 @@ impl Resolver for Store {
                     source: None,
                 });
                 debug!("unknown source");
-                return Err(LookupError::ResponseCode(ResponseCode::ServFail));
+                let result = Err(LookupError::ResponseCode(ResponseCode::ServFail));
+                self.record_response(None, request, &result);
+                return result;
             }
             Some(client) => client,
         };
 
-        // Make sure the request is for IP records. Anything else, we forward.
-        let record_type = request.query().query_type();
-        if !is_record_type_supported(record_type) {
-            debug!("unknown record type");
-            return self.forward(Some(&client), request).await;
-        }
-
-        // Find the service for the requested host.
-        let requested_name = Name::from(request.query().name().clone());
-        let Some(service_match) = self.find_server(&client, &requested_name) else {
-            trace!("unknown host, forwarding");
-            // Unknown host. Forward to the upstream resolver.
-            return self.forward(Some(&client), request).await;
-        };
-
-        // Increment counter for all requests.
-        self.metrics.increment(&DnsRequest {
-            request,
-            source: Some(&client),
-        });
-
-        // Get the addresses for the service.
-        let addresses = self.get_addresses(&client, &service_match.server, record_type);
-
-        // From this point on, we are the authority for the response.
-        let is_authoritative = true;
-
-        if addresses.is_empty() {
-            debug!(alias=%service_match.alias, name=%service_match.name, "no records");
-            // Lookup succeeded, but no records were returned. This is not NXDOMAIN, since we
-            // found the host. Just return an empty set of records.
-            return Ok(Answer::new(Vec::default(), is_authoritative));
-        }
-
-        // Create a vec to hold the output records.
-        let mut records = Vec::new();
-
-        // Assume that we'll just use the requested name as the record name.
-        let mut ip_record_name = requested_name.clone();
-
-        debug!(alias=%service_match.alias, name=%service_match.name, "success");
-        // If the service was found by stripping off one of the search domains, create a
-        // CNAME record to map to the appropriate canonical name.
-        if let Some(stripped) = service_match.alias.stripped {
-            if service_match.name.is_wildcard() {
-                // The match is a wildcard...
-
-                // Create a CNAME record that maps from the wildcard with the search domain to
-                // the wildcard without it.
-                let cname_record_name = service_match
-                    .name
-                    .clone()
-                    .append_domain(&stripped.search_domain)
-                    .unwrap();
-                let canonical_name = service_match.name;
-                records.push(cname_record(cname_record_name, canonical_name));
-
-                // For wildcards, continue using the original requested hostname for IP records.
-            } else {
-                // The match is NOT a wildcard...
-
-                // Create a CNAME record to map from the requested name -> stripped name.
-                let canonical_name = stripped.name;
-                records.push(cname_record(requested_name.clone(), canonical_name.clone()));
-
-                // Also use the stripped name as the IP record name.
-                ip_record_name = canonical_name;
-            }
-        }
-
-        // Add the IP records.
-        ip_records(ip_record_name, addresses, &mut records);
-
-        Ok(Answer::new(records, is_authoritative))
+        let result = self.resolve(&client, request).await;
+        self.record_response(Some(&client), request, &result);
+        result
     }
 }
 
@@ -628,6 +735,19 @@ struct ServerMatch {
     server: Address,
 }
 
+/// Returns the response code that will be sent to the client for the given lookup result.
+fn lookup_response_code(result: &Result<Answer, LookupError>) -> ResponseCode {
+    match result {
+        Ok(_) | Err(LookupError::NameExists) => ResponseCode::NoError,
+        Err(LookupError::ResponseCode(code)) => *code,
+        Err(LookupError::ResolveError(e)) => match e.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. } => *response_code,
+            _ => ResponseCode::ServFail,
+        },
+        Err(_) => ResponseCode::ServFail,
+    }
+}
+
 fn as_name<T: AsRef<str>>(name: T) -> Name {
     Name::from_str(name.as_ref()).unwrap()
 }
@@ -698,30 +818,101 @@ pub trait Forwarder: Send + Sync {
     ) -> Result<Answer, LookupError>;
 }
 
-/// Creates the appropriate DNS forwarder for the proxy mode.
-pub fn forwarder_for_mode(proxy_mode: ProxyMode) -> Result<Arc<dyn Forwarder>, Error> {
-    Ok(match proxy_mode {
+/// Creates the appropriate DNS forwarder for the proxy mode and upstream configuration.
+/// Up to `dns_proxy_cache_size` upstream answers are cached, for as long as their TTL allows.
+/// The forwarder is rebuilt on every configuration reload, picking up changes to the system
+/// resolver config.
+pub fn forwarder_for_mode(cfg: &Config) -> Result<Arc<dyn Forwarder>, Error> {
+    let forwarder = ReloadingForwarder {
+        current: Arc::new(std::sync::RwLock::new(build_forwarder(cfg)?)),
+    };
+    let current = Arc::downgrade(&forwarder.current);
+    let mut reloads = cfg.reloadable.subscribe();
+    let cfg = cfg.clone();
+    tokio::spawn(async move {
+        while reloads.changed().await.is_ok() {
+            let Some(current) = current.upgrade() else {
+                // The forwarder is gone.
+                return;
+            };
+            match build_forwarder(&cfg) {
+                Ok(f) => {
+                    info!("reloaded DNS forwarder");
+                    *current.write().unwrap() = f;
+                }
+                Err(e) => warn!("failed to reload DNS forwarder, keeping the previous one: {e}"),
+            }
+        }
+    });
+    Ok(Arc::new(forwarder))
+}
+
+/// Forwards to a forwarder that is replaced on configuration reload.
+struct ReloadingForwarder {
+    current: Arc<std::sync::RwLock<Arc<dyn Forwarder>>>,
+}
+
+impl ReloadingForwarder {
+    fn current(&self) -> Arc<dyn Forwarder> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Forwarder for ReloadingForwarder {
+    fn search_domains(&self, client: &Workload) -> Vec<Name> {
+        self.current().search_domains(client)
+    }
+
+    async fn forward(
+        &self,
+        client: Option<&Workload>,
+        request: &Request,
+    ) -> Result<Answer, LookupError> {
+        self.current().forward(client, request).await
+    }
+}
+
+fn build_forwarder(cfg: &Config) -> Result<Arc<dyn Forwarder>, Error> {
+    let cache_size = cfg.dns_proxy_cache_size;
+    let forwarder = match cfg.proxy_mode {
         ProxyMode::Shared => {
             // TODO(https://github.com/istio/ztunnel/issues/555): Use pod settings if available.
-            Arc::new(SystemForwarder::new()?)
+            SystemForwarder::new(cache_size)?
         }
-        ProxyMode::Dedicated => Arc::new(SystemForwarder::new()?),
-    })
+        ProxyMode::Dedicated => SystemForwarder::new(cache_size)?,
+    };
+    if cfg.dns_upstream_tls.is_empty() {
+        return Ok(Arc::new(forwarder));
+    }
+    let client_config = crate::tls::dns_client_config(&cfg.tls_settings)
+        .map_err(|e| Error::Generic(Box::new(e)))?;
+    Ok(Arc::new(forwarder.with_tls_upstreams(
+        &cfg.dns_upstream_tls,
+        Arc::new(client_config),
+        cfg.dns_upstream_tls_fallback,
+    )?))
 }
 
 /// DNS forwarder that uses the system resolver config in `/etc/resolv.conf`.
 /// When running in dedicated (sidecar) proxy mode, this will be the same resolver configuration
 /// that would have been used by the client. For shared proxy mode, this will be the resolver
 /// configuration for the ztunnel DaemonSet (i.e. node-level resolver settings).
+///
+/// If DNS-over-TLS upstreams are configured, only the search domains are taken from the system
+/// config, and queries are sent to those upstreams instead.
 struct SystemForwarder {
     search_domains: Vec<Name>,
     resolver: Arc<dyn Resolver>,
+    opts: ResolverOpts,
 }
 
 impl SystemForwarder {
-    fn new() -> Result<Self, Error> {
+    fn new(cache_size: usize) -> Result<Self, Error> {
         // Get the resolver config from /etc/resolv.conf.
-        let (cfg, opts) = read_system_conf().map_err(|e| Error::Generic(Box::new(e)))?;
+        let (cfg, mut opts) = read_system_conf().map_err(|e| Error::Generic(Box::new(e)))?;
+        // The resolver caches answers according to their TTL.
+        opts.cache_size = cache_size;
 
         // Extract the parts.
         let domain = cfg.domain().cloned();
@@ -735,12 +926,35 @@ impl SystemForwarder {
 
         // Create the resolver.
         let resolver = Arc::new(
-            dns::forwarder::Forwarder::new(cfg, opts).map_err(|e| Error::Generic(Box::new(e)))?,
+            dns::forwarder::Forwarder::new(cfg, opts.clone())
+                .map_err(|e| Error::Generic(Box::new(e)))?,
         );
 
         Ok(Self {
             search_domains,
             resolver,
+            opts,
+        })
+    }
+
+    // with_tls_upstreams sends queries to the given upstreams over DNS-over-TLS instead, optionally
+    // falling back to the system resolvers.
+    fn with_tls_upstreams(
+        self,
+        upstreams: &[DnsTlsUpstream],
+        client_config: Arc<rustls::ClientConfig>,
+        fallback: bool,
+    ) -> Result<Self, Error> {
+        let resolver = dns::forwarder::TlsForwarder::new(
+            upstreams,
+            client_config,
+            self.opts.clone(),
+            fallback.then_some(self.resolver),
+        )
+        .map_err(|e| Error::Generic(Box::new(e)))?;
+        Ok(Self {
+            resolver: Arc::new(resolver),
+            ..self
         })
     }
 }
@@ -1262,6 +1476,43 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn forwarded_service_hostnames_are_captured() {
+        initialize_telemetry();
+
+        let state = state();
+        let forwarder = Arc::new(FakeForwarder {
+            search_domains: vec![],
+            ips: HashMap::from([
+                (n("headless.example.com."), vec![ip("40.40.40.40")]),
+                (n("www.bing.com."), vec![ip("41.41.41.41")]),
+            ]),
+        });
+        let store = Store {
+            domain: as_name("cluster.local"),
+            svc_domain: as_name("svc.cluster.local."),
+            network: NW1,
+            state: state.clone(),
+            forwarder,
+            metrics: test_metrics(),
+        };
+
+        let client_ip = local_ips()[0];
+        for host in ["headless.example.com.", "www.bing.com."] {
+            let req = req(n(host), client_ip, RecordType::A);
+            store.lookup(&req).await.unwrap();
+        }
+
+        // Only hosts of services without VIPs are captured
+        let svc = state
+            .find_captured_service(&na(NW1, "40.40.40.40"))
+            .unwrap();
+        assert_eq!(svc.hostname.as_str(), "headless.example.com");
+        assert!(state
+            .find_captured_service(&na(NW1, "41.41.41.41"))
+            .is_none());
+    }
+
     #[tokio::test]
     async fn system_forwarder() {
         initialize_telemetry();
@@ -1288,7 +1539,7 @@ mod tests {
         // Create and start the server.
         let domain = "cluster.local".to_string();
         let state = state();
-        let forwarder = Arc::new(SystemForwarder::new().unwrap());
+        let forwarder = Arc::new(SystemForwarder::new(32).unwrap());
         let (_signal, drain) = drain::channel();
         let factory = crate::proxy::DefaultSocketFactory;
         let server = Server::new(
@@ -1353,6 +1604,60 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn response_metrics() {
+        initialize_telemetry();
+        let client_ips = vec![ip("2.2.2.2")];
+        let wls = vec![xds_workload("client", NS1, "", &NW1, &[], &client_ips)];
+
+        let mut registry = Registry::default();
+        let metrics = Arc::new(Metrics::new(metrics::sub_registry(&mut registry)));
+        let store = Store {
+            network: NW1,
+            state: new_proxy_state(&wls, &[], &[]),
+            forwarder: forwarder(),
+            domain: n("cluster.local"),
+            svc_domain: n("svc.cluster.local."),
+            metrics: metrics.clone(),
+        };
+
+        let client_ip = ip("2.2.2.2");
+        for host in [
+            "www.bing.com.",
+            "unknown.example.com.",
+            "missing.ns1.svc.cluster.local.",
+        ] {
+            let _ = store.lookup(&req(n(host), client_ip, RecordType::A)).await;
+        }
+        let _ = store
+            .lookup(&req(n("www.bing.com."), ip("5.5.5.5"), RecordType::A))
+            .await;
+
+        let mut buf = String::new();
+        prometheus_client::encoding::text::encode(&mut buf, &registry).unwrap();
+        // Sums the samples of the given metric whose labels contain `filter`.
+        let count = |prefix: &str, filter: &str| -> u64 {
+            buf.lines()
+                .filter(|l| l.starts_with(prefix) && l.contains(filter))
+                .map(|l| l.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+                .sum()
+        };
+        assert_eq!(
+            count("istio_dns_responses_total", "response_code=\"noerror\""),
+            1
+        );
+        assert_eq!(
+            count("istio_dns_responses_total", "response_code=\"nxdomain\""),
+            2
+        );
+        assert_eq!(
+            count("istio_dns_responses_total", "response_code=\"servfail\""),
+            1
+        );
+        assert_eq!(count("istio_dns_mesh_nxdomain_total", ""), 1);
+    }
+
     // #[tokio::test]
     // async fn large_response() {
     //     // Create and start the proxy with an an empty state. The forwarder is configured to
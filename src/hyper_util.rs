@@ -35,7 +35,9 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::Stream;
 use tracing::{debug, info, warn};
 
-use crate::tls::ServerCertProvider;
+use crate::proxy::sniff::ProtocolDetector;
+use crate::proxy::{PrefaceStage, PrefaceTimeouts};
+use crate::tls::{InboundAcceptor, ServerCertProvider, TlsError};
 
 pub fn tls_server<T: ServerCertProvider + Clone + 'static>(
     cert_provider: T,
@@ -64,6 +66,108 @@ pub fn tls_server<T: ServerCertProvider + Clone + 'static>(
         })
 }
 
+// tls_server_proxy_protocol is like tls_server, but connections from the trusted networks must start
+// with a PROXY protocol header, which is read before the TLS handshake. Each connection is returned
+// along with the source address conveyed by its header, if any. With a detector, connections that do
+// not start a TLS handshake are rejected up front. Connections that are too slow to send their first
+// byte, or to complete the handshake, are closed.
+pub fn tls_server_proxy_protocol<T: ServerCertProvider + Clone + 'static>(
+    cert_provider: T,
+    listener: TcpListener,
+    trusted: Vec<ipnet::IpNet>,
+    detector: Option<ProtocolDetector>,
+    preface: PrefaceTimeouts,
+) -> impl Stream<
+    Item = (
+        tokio_rustls::server::TlsStream<TcpStream>,
+        Option<SocketAddr>,
+    ),
+> {
+    use tokio_stream::StreamExt;
+
+    // The acceptor enforces the preface timeouts itself, so the listener's own handshake timeout
+    // only needs to cover them.
+    let handshake_timeout = preface.handshake_limit().unwrap_or(Duration::MAX);
+    let acceptor = ProxyProtocolAcceptor {
+        inner: InboundAcceptor::new(cert_provider),
+        trusted: Arc::new(trusted),
+        detector,
+        preface,
+    };
+    tls_listener::builder(acceptor)
+        .handshake_timeout(handshake_timeout)
+        .listen(listener)
+        .filter_map(|conn| match conn {
+            Err(err) => {
+                warn!("TLS handshake error: {}", err);
+                None
+            }
+            Ok(s) => {
+                debug!("TLS handshake succeeded");
+                Some(s)
+            }
+        })
+        .map(|((conn, src), _)| {
+            conn.get_ref().0.set_nodelay(true).unwrap();
+            (conn, src)
+        })
+}
+
+#[derive(Clone)]
+struct ProxyProtocolAcceptor<F: ServerCertProvider> {
+    inner: InboundAcceptor<F>,
+    trusted: Arc<Vec<ipnet::IpNet>>,
+    detector: Option<ProtocolDetector>,
+    preface: PrefaceTimeouts,
+}
+
+impl<F> tls_listener::AsyncTls<TcpStream> for ProxyProtocolAcceptor<F>
+where
+    F: ServerCertProvider + 'static,
+{
+    type Stream = (
+        tokio_rustls::server::TlsStream<TcpStream>,
+        Option<SocketAddr>,
+    );
+    type Error = TlsError;
+    type AcceptFuture = Pin<Box<dyn Future<Output = Result<Self::Stream, Self::Error>> + Send>>;
+
+    fn accept(&self, mut conn: TcpStream) -> Self::AcceptFuture {
+        let inner = self.inner.clone();
+        let trusted = self.trusted.clone();
+        let detector = self.detector.clone();
+        let preface = self.preface.clone();
+        Box::pin(async move {
+            let peer = crate::socket::to_canonical(conn.peer_addr().map_err(TlsError::Handshake)?);
+            preface
+                .run(PrefaceStage::first_byte, conn.peek(&mut [0u8; 1]))
+                .await
+                .ok_or_else(|| TlsError::PrefaceTimeout(PrefaceStage::first_byte.to_string()))?
+                .map_err(TlsError::Handshake)?;
+            let handshake = async move {
+                let src = if trusted.iter().any(|net| net.contains(&peer.ip())) {
+                    let src = crate::proxy::read_proxy_protocol(&mut conn)
+                        .await
+                        .map_err(TlsError::ProxyProtocol)?;
+                    debug!(%peer, ?src, "read proxy protocol header");
+                    src.map(|(src, _)| crate::socket::to_canonical(src))
+                } else {
+                    None
+                };
+                if let Some(detector) = detector {
+                    detector.expect_tls(&conn).await?;
+                }
+                let tls = tls_listener::AsyncTls::accept(&inner, conn).await?;
+                Ok::<_, TlsError>((tls, src))
+            };
+            preface
+                .run(PrefaceStage::tls_handshake, handshake)
+                .await
+                .ok_or_else(|| TlsError::PrefaceTimeout(PrefaceStage::tls_handshake.to_string()))?
+        })
+    }
+}
+
 #[derive(Clone)]
 /// An Executor that uses the tokio runtime.
 pub struct TokioExecutor;
@@ -174,13 +278,17 @@ pub fn plaintext_response(code: hyper::StatusCode, body: String) -> Response<Ful
         .unwrap()
 }
 
+// How long a client of a Server may take to complete the TLS handshake, if TLS is enabled.
+const SERVER_TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Server implements a generic HTTP server with the follow behavior:
-/// * HTTP/1.1 plaintext only
+/// * HTTP/1.1 only, over plaintext or TLS
 /// * Draining
 pub struct Server<S> {
     name: String,
     bind: TcpListener,
     drain_rx: Watch,
+    tls: Option<tokio_rustls::TlsAcceptor>,
     state: S,
 }
 
@@ -191,10 +299,16 @@ impl<S> Server<S> {
             name: name.to_string(),
             bind,
             drain_rx,
+            tls: None,
             state: s,
         })
     }
 
+    /// set_tls serves connections over TLS with the given config, rather than plaintext.
+    pub fn set_tls(&mut self, config: rustls::ServerConfig) {
+        self.tls = Some(tokio_rustls::TlsAcceptor::from(Arc::new(config)));
+    }
+
     pub fn address(&self) -> SocketAddr {
         self.bind.local_addr().expect("local address must be ready")
     }
@@ -222,6 +336,7 @@ impl<S> Server<S> {
             component=self.name,
             "listener established",
         );
+        let tls = self.tls;
         tokio::spawn(async move {
             let stream = tokio_stream::wrappers::TcpListenerStream::new(self.bind);
             let mut stream = stream.take_until(Box::pin(drain_stream.signaled()));
@@ -230,36 +345,17 @@ impl<S> Server<S> {
                 let drain = drain_connections.clone();
                 let f = f.clone();
                 let state = state.clone();
+                let tls = tls.clone();
                 tokio::spawn(async move {
-                    let serve =
-                        http1_server()
-                            .half_close(true)
-                            .header_read_timeout(Duration::from_secs(2))
-                            .max_buf_size(8 * 1024)
-                            .serve_connection(
-                                hyper_util::rt::TokioIo::new(socket),
-                                hyper::service::service_fn(move |req| {
-                                    let state = state.clone();
-
-                                    // Failures would abort the whole connection; we just want to return an HTTP error
-                                    f(state, req).or_else(|err| async move {
-                                        Ok::<Response<Full<Bytes>>, Infallible>(Response::builder()
-                                        .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-                                        .body(err.to_string().into())
-                                        .expect("builder with known status code should not fail"))
-                                    })
-                                }),
-                            );
-                    // Wait for drain to signal or connection serving to complete
-                    match futures_util::future::select(Box::pin(drain.signaled()), serve).await {
-                        // We got a shutdown request. Start gracful shutdown and wait for the pending requests to complete.
-                        futures_util::future::Either::Left((_shutdown, mut serve)) => {
-                            let drain = std::pin::Pin::new(&mut serve);
-                            drain.graceful_shutdown();
-                            serve.await
-                        }
-                        // Serving finished, just return the result.
-                        futures_util::future::Either::Right((serve, _shutdown)) => serve,
+                    let Some(tls) = tls else {
+                        return serve_connection(socket, drain, f, state).await;
+                    };
+                    match tokio::time::timeout(SERVER_TLS_HANDSHAKE_TIMEOUT, tls.accept(socket))
+                        .await
+                    {
+                        Ok(Ok(socket)) => serve_connection(socket, drain, f, state).await,
+                        Ok(Err(e)) => debug!("TLS handshake failed: {e}"),
+                        Err(_) => debug!("TLS handshake timed out"),
                     }
                 });
             }
@@ -271,3 +367,48 @@ impl<S> Server<S> {
         });
     }
 }
+
+// serve_connection serves HTTP/1.1 requests on a connection accepted by a Server, until it closes or
+// the server drains.
+async fn serve_connection<IO, S, F, R>(socket: IO, drain: Watch, f: Arc<F>, state: Arc<S>)
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    S: Send + Sync + 'static,
+    F: Fn(Arc<S>, Request<hyper::body::Incoming>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<Response<Full<Bytes>>, anyhow::Error>> + Send + Sync + 'static,
+{
+    let serve = http1_server()
+        .half_close(true)
+        .header_read_timeout(Duration::from_secs(2))
+        .max_buf_size(8 * 1024)
+        .serve_connection(
+            hyper_util::rt::TokioIo::new(socket),
+            hyper::service::service_fn(move |req| {
+                let state = state.clone();
+
+                // Failures would abort the whole connection; we just want to return an HTTP error
+                f(state, req).or_else(|err| async move {
+                    Ok::<Response<Full<Bytes>>, Infallible>(
+                        Response::builder()
+                            .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(err.to_string().into())
+                            .expect("builder with known status code should not fail"),
+                    )
+                })
+            }),
+        );
+    // Wait for drain to signal or connection serving to complete
+    let res = match futures_util::future::select(Box::pin(drain.signaled()), serve).await {
+        // We got a shutdown request. Start gracful shutdown and wait for the pending requests to complete.
+        futures_util::future::Either::Left((_shutdown, mut serve)) => {
+            let drain = std::pin::Pin::new(&mut serve);
+            drain.graceful_shutdown();
+            serve.await
+        }
+        // Serving finished, just return the result.
+        futures_util::future::Either::Right((serve, _shutdown)) => serve,
+    };
+    if let Err(e) = res {
+        debug!("connection error: {e}");
+    }
+}
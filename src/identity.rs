@@ -19,6 +19,11 @@ use std::sync::Arc;
 mod caclient;
 pub use caclient::*;
 
+#[cfg(unix)]
+mod spire;
+#[cfg(unix)]
+pub use spire::SpireClient;
+
 pub mod manager;
 pub use manager::*;
 
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use prost_types::value::Kind;
@@ -21,6 +22,7 @@ use tonic::codegen::InterceptedService;
 
 use tracing::{error, instrument, warn};
 
+use crate::config::{GrpcConfig, KeyType};
 use crate::identity::auth::AuthSource;
 use crate::identity::manager::Identity;
 use crate::identity::Error;
@@ -32,6 +34,13 @@ pub struct CaClient {
     pub client: IstioCertificateServiceClient<InterceptedService<TlsGrpcChannel, AuthSource>>,
     pub enable_impersonated_identity: bool,
     pub secret_ttl: i64,
+    // How long a certificate request may take.
+    request_timeout: Duration,
+    // The type of the keys generated.
+    key_type: KeyType,
+    // If set, keys are generated in this PKCS#11 token.
+    #[cfg(feature = "pkcs11")]
+    key_token: Option<tls::Token>,
 }
 
 impl CaClient {
@@ -41,8 +50,9 @@ impl CaClient {
         auth: AuthSource,
         enable_impersonated_identity: bool,
         secret_ttl: i64,
+        grpc: &GrpcConfig,
     ) -> Result<CaClient, Error> {
-        let svc = tls::grpc_connector(address, cert_provider.fetch_cert().await?)?;
+        let svc = tls::grpc_connector(address, cert_provider.fetch_cert().await?, grpc)?;
         // let client = IstioCertificateServiceClient::new(svc);
         // let svc =
         //     tower_hyper_http_body_compat::Hyper1HttpServiceAsTowerService03HttpService::new(svc);
@@ -51,19 +61,52 @@ impl CaClient {
             client,
             enable_impersonated_identity,
             secret_ttl,
+            request_timeout: grpc.request_timeout,
+            key_type: KeyType::default(),
+            #[cfg(feature = "pkcs11")]
+            key_token: None,
         })
     }
+
+    /// with_key_type generates certificate keys of the given type.
+    pub fn with_key_type(mut self, key_type: KeyType) -> Self {
+        self.key_type = key_type;
+        self
+    }
+
+    /// with_key_token generates certificate keys in the given PKCS#11 token.
+    #[cfg(feature = "pkcs11")]
+    pub fn with_key_token(mut self, token: tls::Token) -> Self {
+        self.key_token = Some(token);
+        self
+    }
 }
 
 impl CaClient {
+    async fn generate_csr(&self, id: &Identity) -> Result<(String, tls::PrivateKey), Error> {
+        let options = tls::csr::CsrOptions {
+            san: id.to_string(),
+            key_type: self.key_type,
+        };
+        #[cfg(feature = "pkcs11")]
+        let token = self.key_token.clone();
+        // Generating RSA keys, or keys in a token, can take a while
+        tokio::task::spawn_blocking(move || -> Result<_, Error> {
+            #[cfg(feature = "pkcs11")]
+            if let Some(token) = token {
+                let (csr, key) = token.generate_csr(&options)?;
+                return Ok((csr, key.into()));
+            }
+            let cs = options.generate()?;
+            Ok((cs.csr, tls::PrivateKey::from_pem(&cs.private_key)?))
+        })
+        .await
+        .expect("key generation must not panic")
+    }
+
     #[instrument(skip_all)]
     async fn fetch_certificate(&self, id: &Identity) -> Result<tls::WorkloadCertificate, Error> {
-        let cs = tls::csr::CsrOptions {
-            san: id.to_string(),
-        }
-        .generate()?;
-        let csr = cs.csr;
-        let private_key = cs.private_key;
+        let (csr, private_key) = self.generate_csr(id).await?;
 
         let req = IstioCertificateRequest {
             csr,
@@ -83,11 +126,13 @@ impl CaClient {
                 }
             },
         };
-        let resp = self
-            .client
-            .clone()
-            .create_certificate(req)
-            .await?
+        let mut req = tonic::Request::new(req);
+        // Also tells the CA when to give up
+        req.set_timeout(self.request_timeout);
+        let mut client = self.client.clone();
+        let resp = tokio::time::timeout(self.request_timeout, client.create_certificate(req))
+            .await
+            .map_err(|_| tonic::Status::deadline_exceeded("certificate request timed out"))??
             .into_inner();
         let leaf = resp
             .cert_chain
@@ -100,7 +145,7 @@ impl CaClient {
             warn!("no chain certs for: {}", id);
             vec![]
         };
-        let certs = tls::WorkloadCertificate::new(&private_key, leaf, chain)?;
+        let certs = tls::WorkloadCertificate::with_key(private_key, leaf, chain)?;
         // Make the certificate actually matches the identity we requested.
         if self.enable_impersonated_identity && certs.cert.identity().as_ref() != Some(id) {
             error!(
@@ -20,10 +20,14 @@ use std::hash::{Hash, RandomState};
 use std::str::FromStr;
 use std::sync::Arc;
 
-use crate::config::ProxyMode;
+use crate::config::{CaProvider, KeyStorage, ProxyMode};
 use async_trait::async_trait;
 
-use prometheus_client::encoding::{EncodeLabelValue, LabelValueEncoder};
+use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Registry;
 use tokio::sync::{mpsc, watch, Mutex};
 use tokio::time::{sleep_until, Duration, Instant};
 
@@ -31,6 +35,8 @@ use crate::{strng, tls};
 
 use super::CaClient;
 use super::Error::{self, Spiffe};
+#[cfg(unix)]
+use super::SpireClient;
 
 use crate::strng::Strng;
 use backoff::{backoff::Backoff, ExponentialBackoff};
@@ -111,6 +117,32 @@ impl Identity {
             } => strng::format!("spiffe://{trust_domain}/ns/{namespace}/sa/{service_account}"),
         }
     }
+
+    /// with_trust_domain_aliases returns this identity, followed by the same identity in every
+    /// trust domain equivalent to its own. Trust domains are equivalent if both are the local
+    /// trust domain or one of its aliases.
+    pub fn with_trust_domain_aliases(&self, local: &Strng, aliases: &[Strng]) -> Vec<Identity> {
+        let Identity::Spiffe {
+            trust_domain,
+            namespace,
+            service_account,
+        } = self;
+        let equivalent = std::iter::once(local).chain(aliases);
+        if !equivalent.clone().any(|td| td == trust_domain) {
+            return vec![self.clone()];
+        }
+        std::iter::once(self.clone())
+            .chain(
+                equivalent
+                    .filter(|td| *td != trust_domain)
+                    .map(|td| Identity::Spiffe {
+                        trust_domain: td.clone(),
+                        namespace: namespace.clone(),
+                        service_account: service_account.clone(),
+                    }),
+            )
+            .collect()
+    }
 }
 
 #[cfg(any(test, feature = "testing"))]
@@ -169,6 +201,8 @@ struct CertChannel {
     // While this makes the code simpler, do note that it makes it impossible to use sender closure
     // as an indication of the background task failing.
     tx: watch::Sender<CertState>,
+    // When the certificate was last requested, to pick identities to evict.
+    last_used: Instant,
 }
 
 #[derive(Eq, PartialEq)]
@@ -203,6 +237,49 @@ struct Worker {
     certs: Mutex<HashMap<Identity, CertChannel>>,
     // How many concurrent fetch_certificate calls can be pending at a time.
     concurrency: u16,
+    // Fraction of a certificate's lifetime after which it is refreshed.
+    refresh_fraction: f64,
+    // Where the private keys of fetched certificates are kept.
+    key_storage: KeyStorage,
+    // Expiry time (in seconds since the unix epoch) of the current certificate of each identity.
+    cert_expiry: Family<CertLabels, Gauge>,
+    // Maximum number of identities in the `certs` map, 0 if unbounded.
+    cache_max_size: usize,
+    // How long an identity may go unused before it is evicted.
+    cache_ttl: Option<Duration>,
+    // Number of identities in the `certs` map.
+    cache_size: Gauge,
+    cache_evictions: Family<EvictionLabels, Counter>,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+struct CertLabels {
+    identity: Identity,
+}
+
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+enum EvictionReason {
+    // The cache was full when another identity was requested.
+    Size,
+    // The identity was not used for the cache TTL.
+    Ttl,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+struct EvictionLabels {
+    reason: EvictionReason,
+}
+
+/// PrefetchResult is the outcome of fetching a certificate ahead of its first use.
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum PrefetchResult {
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+struct PrefetchLabels {
+    result: PrefetchResult,
 }
 
 impl Worker {
@@ -218,6 +295,13 @@ impl Worker {
             client,
             time_conv: cfg.time_conv,
             concurrency: cfg.concurrency,
+            refresh_fraction: cfg.refresh_fraction,
+            key_storage: cfg.key_storage,
+            cert_expiry: Default::default(),
+            cache_max_size: cfg.cache_max_size,
+            cache_ttl: cfg.cache_ttl,
+            cache_size: Default::default(),
+            cache_evictions: Default::default(),
             certs: Default::default(),
         });
 
@@ -231,6 +315,78 @@ impl Worker {
         self.certs.lock().await.contains_key(id)
     }
 
+    // Removes an identity from the `certs` map. Returns whether it was present.
+    fn remove_cert(&self, certs: &mut HashMap<Identity, CertChannel>, id: &Identity) -> bool {
+        if certs.remove(id).is_none() {
+            return false;
+        }
+        self.cert_expiry.remove(&CertLabels {
+            identity: id.clone(),
+        });
+        self.cache_size.set(certs.len() as i64);
+        true
+    }
+
+    // Makes room for a new identity if the cache is full, by evicting the least recently used
+    // identity. Returns the evicted identity, which the worker must be told to forget.
+    fn evict_lru(&self, certs: &mut HashMap<Identity, CertChannel>) -> Option<Identity> {
+        if self.cache_max_size == 0 || certs.len() < self.cache_max_size {
+            return None;
+        }
+        // Identities still waiting for their first certificate are never evicted, so that callers
+        // waiting for them don't fail. The cache may briefly exceed its size as a result.
+        let id = certs
+            .iter()
+            .filter(|(_, chan)| init_pri(&chan.rx).is_none())
+            .min_by_key(|(_, chan)| chan.last_used)
+            .map(|(id, _)| id.clone())?;
+        tracing::debug!(%id, "evicting least recently used certificate");
+        self.remove_cert(certs, &id);
+        self.cache_evictions
+            .get_or_create(&EvictionLabels {
+                reason: EvictionReason::Size,
+            })
+            .inc();
+        Some(id)
+    }
+
+    // Evicts identities not used for the cache TTL, returning them.
+    // An identity whose certificate is still referenced outside the cache, or that a caller is
+    // waiting on, is in use, so it is marked as used instead. Established connections don't keep
+    // the certificate once their handshake completes, so an identity only serving such
+    // connections can still be evicted; new connections for it fetch the certificate again.
+    async fn evict_idle(&self) -> Vec<Identity> {
+        let Some(ttl) = self.cache_ttl else {
+            return Vec::new();
+        };
+        let mut certs = self.certs.lock().await;
+        let now = Instant::now();
+        let mut idle = Vec::new();
+        for (id, chan) in certs.iter_mut() {
+            if now.duration_since(chan.last_used) < ttl || init_pri(&chan.rx).is_some() {
+                continue;
+            }
+            if in_use(chan) {
+                chan.last_used = now;
+                continue;
+            }
+            idle.push(id.clone());
+        }
+        if idle.is_empty() {
+            return idle;
+        }
+        for id in &idle {
+            tracing::debug!(%id, "evicting idle certificate");
+            self.remove_cert(&mut certs, id);
+        }
+        self.cache_evictions
+            .get_or_create(&EvictionLabels {
+                reason: EvictionReason::Ttl,
+            })
+            .inc_by(idle.len() as u64);
+        idle
+    }
+
     // Manages certificate updates. Since all the work is done in a single task, the code is
     // lock-free. This is OK as the code is I/O bound so we don't need the extra parallelism.
     async fn run(&self, mut requests: mpsc::Receiver<Request>) {
@@ -243,6 +399,23 @@ impl Worker {
             Forgetting,
         }
 
+        // Stops refreshing the certificate of an identity no longer in the `certs` map.
+        fn forget(
+            processing: &mut HashMap<Identity, Fetch>,
+            pending: &mut KeyedPriorityQueue<Identity, PendingPriority>,
+            id: Identity,
+        ) {
+            match processing.get(&id) {
+                None => {
+                    pending.remove(&id);
+                }
+                Some(Fetch::Processing) => {
+                    processing.insert(id, Fetch::Forgetting);
+                }
+                Some(Fetch::Forgetting) => (),
+            }
+        }
+
         // A set of futures refreshing the certificates. Each future completes with the identity for
         // which it was invoked and a resulting certificate or error.
         let mut fetches = FuturesUnordered::new();
@@ -271,6 +444,9 @@ impl Worker {
             randomization_factor: 0.2,
             ..Default::default()
         };
+        // Idle identities are looked for twice per TTL, so none outlives it by more than half.
+        let sweep_interval = self.cache_ttl.map(|ttl| ttl / 2);
+        let mut next_sweep = sweep_interval.map(|i| Instant::now() + i);
 
         'main: loop {
             let next = pending.peek().map(|(_, PendingPriority(_, ts))| *ts);
@@ -314,15 +490,7 @@ impl Worker {
                             // managing the Identity. Do nothing.
                             continue 'main;
                         }
-                        match processing.get(&id) {
-                            None => {
-                                pending.remove(&id);
-                            },
-                            Some(Fetch::Processing) => {
-                                processing.insert(id, Fetch::Forgetting);
-                            },
-                            Some(Fetch::Forgetting) => (),
-                        }
+                        forget(&mut processing, &mut pending, id);
                     },
                     None => break 'main,
                 },
@@ -361,7 +529,7 @@ impl Worker {
                             // [`reset`](https://docs.rs/backoff/0.4.0/backoff/backoff/trait.Backoff.html#method.reset)
                             cert_backoff.reset();
                             let certs: tls::WorkloadCertificate = certs; // Type annotation.
-                            let refresh_at = self.time_conv.system_time_to_instant(certs.refresh_at());
+                            let refresh_at = self.time_conv.system_time_to_instant(certs.refresh_at(self.refresh_fraction));
                             let refresh_at = if let Some(t) = refresh_at {
                                 t.into()
                             } else {
@@ -382,12 +550,21 @@ impl Worker {
                         push_increase(&mut pending, id, PendingPriority(Priority::Background, refresh_at));
                     }
                 },
+                // Evict idle identities. Identities in use are fetched again on demand.
+                true = maybe_sleep_until(next_sweep) => {
+                    for id in self.evict_idle().await {
+                        forget(&mut processing, &mut pending, id);
+                    }
+                    next_sweep = sweep_interval.map(|i| Instant::now() + i);
+                },
                 // Initiate the next fetch.
                 true = maybe_sleep_until(next), if fetches.len() < self.concurrency as usize => {
                     let (id, _) = pending.pop().expect("pending should always have an element at this point");
                     processing.insert(id.to_owned(), Fetch::Processing);
                     fetches.push(async move {
-                        let res = self.client.fetch_certificate(&id).await;
+                        let res = self.client.fetch_certificate(&id).await.and_then(|certs| {
+                            certs.protect_key(self.key_storage).map_err(Error::from)
+                        });
                         (id, res)
                     });
                 },
@@ -405,6 +582,17 @@ impl Worker {
         // finished just after the lock was released (but before certs was sent)
         match self.certs.lock().await.get(id) {
             Some(state) => {
+                if let CertState::Available(certs) = &certs {
+                    let not_after = certs.cert.expiration().not_after;
+                    let expiry = not_after
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default();
+                    self.cert_expiry
+                        .get_or_create(&CertLabels {
+                            identity: id.clone(),
+                        })
+                        .set(expiry.as_secs() as i64);
+                }
                 state.tx.send(certs).expect("state.rx cannot be gone");
                 true
             }
@@ -433,6 +621,10 @@ pub enum Request {
 pub struct SecretManagerConfig {
     time_conv: crate::time::Converter,
     concurrency: u16,
+    refresh_fraction: f64,
+    key_storage: KeyStorage,
+    cache_max_size: usize,
+    cache_ttl: Option<Duration>,
 }
 
 // push_increase pushes an item onto the queue if its not present, otherwise updates the priority to the
@@ -455,6 +647,11 @@ pub struct SecretManager {
     // sent for must have a corresponding entry in the worker's certs map (which is where the
     // result can be read from).
     requests: mpsc::Sender<Request>,
+    // Roots of federated trust domains, used alongside those of our certificates.
+    trust_bundles: tls::TrustBundles,
+    // CRLs that inbound peer certificates are checked against.
+    revocation_lists: tls::RevocationLists,
+    prefetches: Family<PrefetchLabels, Counter>,
 }
 
 impl fmt::Debug for SecretManager {
@@ -465,19 +662,48 @@ impl fmt::Debug for SecretManager {
 
 impl SecretManager {
     pub async fn new(cfg: Arc<crate::config::Config>) -> Result<Self, Error> {
-        let caclient = CaClient::new(
-            cfg.ca_address
-                .clone()
-                .expect("ca_address must be set to use CA"),
-            Box::new(tls::ControlPlaneAuthentication::RootCert(
-                cfg.ca_root_cert.clone(),
-            )),
-            cfg.auth.clone(),
-            cfg.proxy_mode == ProxyMode::Shared,
-            cfg.secret_ttl.as_secs().try_into().unwrap_or(60 * 60 * 24),
+        tls::check_key_storage(cfg.private_key_storage)?;
+        let caclient: Box<dyn CaClientTrait> = match cfg.ca_provider {
+            CaProvider::Istiod => {
+                let client = CaClient::new(
+                    cfg.ca_address
+                        .clone()
+                        .expect("ca_address must be set to use CA"),
+                    Box::new(tls::ControlPlaneAuthentication::RootCert(
+                        cfg.ca_root_cert.clone(),
+                        cfg.tls_settings.clone(),
+                    )),
+                    cfg.auth.clone(),
+                    cfg.proxy_mode == ProxyMode::Shared,
+                    cfg.secret_ttl.as_secs().try_into().unwrap_or(60 * 60 * 24),
+                    &cfg.ca_grpc,
+                )
+                .await?
+                .with_key_type(cfg.key_type);
+                #[cfg(feature = "pkcs11")]
+                let client = match &cfg.pkcs11 {
+                    Some(pkcs11) => client.with_key_token(tls::Token::open(pkcs11)?),
+                    None => client,
+                };
+                Box::new(client)
+            }
+            #[cfg(unix)]
+            CaProvider::Spire => Box::new(SpireClient::new(cfg.spire_admin_socket.clone())),
+            #[cfg(not(unix))]
+            CaProvider::Spire => unreachable!("spire is not supported on this platform"),
+        };
+        Ok(Self::new_internal(
+            caclient,
+            SecretManagerConfig {
+                time_conv: crate::time::Converter::new(),
+                concurrency: 8,
+                refresh_fraction: cfg.cert_refresh_fraction,
+                key_storage: cfg.private_key_storage,
+                cache_max_size: cfg.cert_cache_max_size,
+                cache_ttl: cfg.cert_cache_ttl,
+            },
         )
-        .await?;
-        Ok(Self::new_with_client(caclient))
+        .0)
     }
 
     pub fn new_with_client<C: 'static + CaClientTrait>(client: C) -> Self {
@@ -486,11 +712,63 @@ impl SecretManager {
             SecretManagerConfig {
                 time_conv: crate::time::Converter::new(),
                 concurrency: 8,
+                refresh_fraction: 0.5,
+                key_storage: KeyStorage::Heap,
+                cache_max_size: 0,
+                cache_ttl: None,
             },
         )
         .0
     }
 
+    /// register_metrics registers the `cert_expiry_seconds` gauge, reporting the expiry time of
+    /// the current certificate of each identity, along with certificate cache metrics.
+    pub fn register_metrics(&self, registry: &mut Registry) {
+        registry.register(
+            "cert_expiry_seconds",
+            "The expiry time of the current certificate, in seconds since the unix epoch (unstable)",
+            self.worker.cert_expiry.clone(),
+        );
+        registry.register(
+            "cert_cache_size",
+            "The number of identities with a cached certificate (unstable)",
+            self.worker.cache_size.clone(),
+        );
+        registry.register(
+            "cert_cache_evictions",
+            "The total number of identities evicted from the certificate cache (unstable)",
+            self.worker.cache_evictions.clone(),
+        );
+        registry.register(
+            "cert_prefetches",
+            "The total number of certificates fetched ahead of their first use (unstable)",
+            self.prefetches.clone(),
+        );
+        registry.register(
+            "revoked_certificate_rejections",
+            "The total number of peers rejected because their certificate is revoked (unstable)",
+            self.revocation_lists.revoked_count(),
+        );
+    }
+
+    /// trust_bundles returns the bundles of federated trust domains, which peers from those trust
+    /// domains are verified against.
+    pub fn trust_bundles(&self) -> &tls::TrustBundles {
+        &self.trust_bundles
+    }
+
+    /// record_prefetch counts a certificate fetched ahead of its first use.
+    pub fn record_prefetch(&self, result: PrefetchResult) {
+        self.prefetches
+            .get_or_create(&PrefetchLabels { result })
+            .inc();
+    }
+
+    /// revocation_lists returns the CRLs inbound peer certificates are checked against.
+    pub fn revocation_lists(&self) -> &tls::RevocationLists {
+        &self.revocation_lists
+    }
+
     fn new_internal(
         client: Box<dyn CaClientTrait>,
         cfg: SecretManagerConfig,
@@ -501,6 +779,9 @@ impl SecretManager {
             Self {
                 worker,
                 requests: tx,
+                trust_bundles: Default::default(),
+                revocation_lists: Default::default(),
+                prefetches: Default::default(),
             },
             handle,
         )
@@ -518,9 +799,10 @@ impl SecretManager {
         pri: Priority,
     ) -> Result<watch::Receiver<CertState>, Error> {
         let mut certs = self.worker.certs.lock().await;
-        match certs.get(id) {
+        match certs.get_mut(id) {
             // Identity found in cache and is already being refreshed. Bump the priority if needed.
             Some(st) => {
+                st.last_used = Instant::now();
                 let rx = st.rx.clone();
                 drop(certs);
 
@@ -533,9 +815,21 @@ impl SecretManager {
             }
             // New identity, start managing it and return the newly created channel.
             None => {
+                let evicted = self.worker.evict_lru(&mut certs);
                 let (tx, rx) = watch::channel(CertState::Initializing(pri));
-                certs.insert(id.to_owned(), CertChannel { rx: rx.clone(), tx });
+                certs.insert(
+                    id.to_owned(),
+                    CertChannel {
+                        rx: rx.clone(),
+                        tx,
+                        last_used: Instant::now(),
+                    },
+                );
+                self.worker.cache_size.set(certs.len() as i64);
                 drop(certs);
+                if let Some(evicted) = evicted {
+                    self.post(Request::Forget(evicted)).await;
+                }
                 // Notify the background worker to start refreshing the certificate.
                 self.post(Request::Fetch(id.to_owned(), pri)).await;
                 Ok(rx)
@@ -589,7 +883,11 @@ impl SecretManager {
     pub async fn forget_certificate(&self, id: &Identity) {
         // TODO: consider keeping the cert around for a minute or so to avoid churn
         // We would ideally drop any pending or new requests to rotate.
-        if self.worker.certs.lock().await.remove(id).is_some() {
+        let removed = {
+            let mut certs = self.worker.certs.lock().await;
+            self.worker.remove_cert(&mut certs, id)
+        };
+        if removed {
             self.post(Request::Forget(id.clone())).await;
         }
     }
@@ -614,6 +912,18 @@ fn init_pri(rx: &watch::Receiver<CertState>) -> Option<Priority> {
     }
 }
 
+// in_use returns true if the certificate is referenced by anything other than the cache, or a
+// caller holds a receiver waiting for it.
+fn in_use(chan: &CertChannel) -> bool {
+    if chan.tx.receiver_count() > 1 {
+        return true;
+    }
+    match *chan.rx.borrow() {
+        CertState::Available(ref certs) => Arc::strong_count(certs) > 1,
+        _ => false,
+    }
+}
+
 #[cfg(any(test, feature = "testing"))]
 pub mod mock {
     use std::{
@@ -654,6 +964,10 @@ pub mod mock {
                 super::SecretManagerConfig {
                     time_conv,
                     concurrency: 2,
+                    refresh_fraction: 0.5,
+                    key_storage: crate::config::KeyStorage::Heap,
+                    cache_max_size: 0,
+                    cache_ttl: None,
                 },
             )
             .0,
@@ -804,6 +1118,10 @@ mod tests {
     }
 
     fn setup(concurrency: u16) -> Test {
+        setup_cache(concurrency, 0, None)
+    }
+
+    fn setup_cache(concurrency: u16, cache_max_size: usize, cache_ttl: Option<Duration>) -> Test {
         // Tests that use this function rely on Tokio's test time pause and auto-advance. It gets a
         // bit tricky so a few things to remember:
         //  - When *all* futures are blocked waiting for a specific time, the runtime will
@@ -824,6 +1142,10 @@ mod tests {
             SecretManagerConfig {
                 time_conv,
                 concurrency,
+                refresh_fraction: 0.5,
+                key_storage: KeyStorage::Heap,
+                cache_max_size,
+                cache_ttl,
             },
         );
         Test {
@@ -1061,6 +1383,108 @@ mod tests {
         test.tear_down().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_evict_lru() {
+        let test = setup_cache(1, 2, None);
+        let mut registry = Registry::default();
+        test.secret_manager.register_metrics(&mut registry);
+        let sm = &test.secret_manager;
+
+        sm.fetch_certificate(&identity("a")).await.unwrap();
+        sm.fetch_certificate(&identity("b")).await.unwrap();
+        // Makes b the least recently used.
+        sm.fetch_certificate(&identity("a")).await.unwrap();
+        sm.fetch_certificate(&identity("c")).await.unwrap();
+
+        let mut cached = sm.collect_certs(|id, _| id.to_string()).await;
+        cached.sort();
+        assert_eq!(cached, collect_strings([identity("a"), identity("c")]));
+
+        // An evicted identity is fetched again on demand.
+        sm.fetch_certificate(&identity("b")).await.unwrap();
+        assert_eq!(
+            collect_strings(test.caclient.fetches().await),
+            collect_strings([identity("a"), identity("b"), identity("c"), identity("b")]),
+        );
+        assert_eq!(sm.cache_len().await, 2);
+
+        let mut buf = String::new();
+        prometheus_client::encoding::text::encode(&mut buf, &registry).unwrap();
+        assert!(buf.contains("cert_cache_size 2"));
+        assert!(buf.contains("cert_cache_evictions_total{reason=\"Size\"} 2"));
+        test.tear_down().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_evict_idle() {
+        let ttl = 10 * SEC;
+        let test = setup_cache(1, 0, Some(ttl));
+        let sm = &test.secret_manager;
+
+        sm.fetch_certificate(&identity("idle")).await.unwrap();
+        sm.fetch_certificate(&identity("used")).await.unwrap();
+        for _ in 0..3 {
+            tokio::time::sleep(ttl / 2).await;
+            sm.fetch_certificate(&identity("used")).await.unwrap();
+        }
+        assert_eq!(
+            sm.collect_certs(|id, _| id.to_string()).await,
+            collect_strings([identity("used")]),
+        );
+
+        // An identity whose certificate is still held is not evicted, even if not fetched again.
+        let held = sm.fetch_certificate(&identity("held")).await.unwrap();
+        tokio::time::sleep(ttl * 2).await;
+        assert!(sm
+            .collect_certs(|id, _| id.to_string())
+            .await
+            .contains(&identity("held").to_string()));
+        drop(held);
+
+        // An evicted identity is fetched again on demand.
+        test.caclient.clear_fetches().await;
+        sm.fetch_certificate(&identity("idle")).await.unwrap();
+        assert_eq!(
+            collect_strings(test.caclient.fetches().await),
+            collect_strings([identity("idle")]),
+        );
+        test.tear_down().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cert_expiry_metric() {
+        let test = setup(1);
+        let mut registry = Registry::default();
+        test.secret_manager.register_metrics(&mut registry);
+        let encode = |registry: &Registry| {
+            let mut buf = String::new();
+            prometheus_client::encoding::text::encode(&mut buf, registry).unwrap();
+            buf
+        };
+
+        let certs = test
+            .secret_manager
+            .fetch_certificate(&identity("test"))
+            .await
+            .unwrap();
+        let not_after = certs
+            .cert
+            .expiration()
+            .not_after
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(encode(&registry).contains(&format!(
+            "cert_expiry_seconds{{identity=\"spiffe://test/ns/test/sa/test\"}} {not_after}"
+        )));
+
+        test.secret_manager
+            .forget_certificate(&identity("test"))
+            .await;
+        assert!(!encode(&registry).contains("identity="));
+        test.tear_down().await;
+    }
+
     #[tokio::test(start_paused = true)]
     async fn test_backoff_resets_on_successful_fetch_after_failure() {
         let mut test = setup(1);
@@ -1113,4 +1537,36 @@ mod tests {
         assert_matches!(Identity::from_str("spiffe://td/ns/ns/sa/sa/"), Err(_));
         assert_matches!(Identity::from_str("spiffe://td/ns/ns/foobar/sa/"), Err(_));
     }
+
+    #[test]
+    fn identity_trust_domain_aliases() {
+        let id = |s: &str| Identity::from_str(s).unwrap();
+        let local: Strng = "cluster.local".into();
+        let aliases: Vec<Strng> = vec!["old.td".into(), "other.td".into()];
+        assert_eq!(
+            id("spiffe://old.td/ns/ns/sa/sa").with_trust_domain_aliases(&local, &aliases),
+            vec![
+                id("spiffe://old.td/ns/ns/sa/sa"),
+                id("spiffe://cluster.local/ns/ns/sa/sa"),
+                id("spiffe://other.td/ns/ns/sa/sa"),
+            ]
+        );
+        assert_eq!(
+            id("spiffe://cluster.local/ns/ns/sa/sa").with_trust_domain_aliases(&local, &aliases),
+            vec![
+                id("spiffe://cluster.local/ns/ns/sa/sa"),
+                id("spiffe://old.td/ns/ns/sa/sa"),
+                id("spiffe://other.td/ns/ns/sa/sa"),
+            ]
+        );
+        // Unrelated trust domains are never aliased
+        assert_eq!(
+            id("spiffe://foreign.td/ns/ns/sa/sa").with_trust_domain_aliases(&local, &aliases),
+            vec![id("spiffe://foreign.td/ns/ns/sa/sa")]
+        );
+        assert_eq!(
+            id("spiffe://cluster.local/ns/ns/sa/sa").with_trust_domain_aliases(&local, &[]),
+            vec![id("spiffe://cluster.local/ns/ns/sa/sa")]
+        );
+    }
 }
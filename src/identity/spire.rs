@@ -0,0 +1,254 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tracing::{error, instrument};
+
+use crate::identity::manager::Identity;
+use crate::identity::Error;
+use crate::tls::{self, UdsGrpcChannel};
+
+use proto::api::agent::delegatedidentity::v1::delegated_identity_client::DelegatedIdentityClient;
+use proto::api::agent::delegatedidentity::v1::{
+    SubscribeToX509BundlesRequest, SubscribeToX509sviDsRequest, X509svidWithKey,
+};
+use proto::api::types::{Selector, Spiffeid};
+
+// We don't control the codegen, so disable any code warnings in the
+// proto modules.
+#[allow(warnings)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+pub mod proto {
+    pub mod api {
+        pub mod types {
+            tonic::include_proto!("spire.api.types");
+        }
+        pub mod agent {
+            pub mod delegatedidentity {
+                pub mod v1 {
+                    tonic::include_proto!("spire.api.agent.delegatedidentity.v1");
+                }
+            }
+        }
+    }
+}
+
+/// SpireClient fetches certificates from a SPIRE agent, using its Delegated Identity API.
+///
+/// Workloads are identified to SPIRE by their Kubernetes namespace and service account selectors,
+/// so registration entries must be keyed on those. The agent must also list ztunnel as an
+/// authorized delegate.
+pub struct SpireClient {
+    client: DelegatedIdentityClient<UdsGrpcChannel>,
+}
+
+impl SpireClient {
+    pub fn new(socket: PathBuf) -> SpireClient {
+        SpireClient {
+            client: DelegatedIdentityClient::new(tls::grpc_uds_connector(socket)),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn fetch_certificate(&self, id: &Identity) -> Result<tls::WorkloadCertificate, Error> {
+        let Identity::Spiffe {
+            trust_domain,
+            namespace,
+            service_account,
+        } = id;
+        let req = SubscribeToX509sviDsRequest {
+            selectors: vec![
+                k8s_selector(format!("ns:{namespace}")),
+                k8s_selector(format!("sa:{service_account}")),
+            ],
+            pid: 0,
+        };
+        let mut client = self.client.clone();
+        // Both APIs are subscriptions; we only need the current state, so the stream is dropped
+        // (ending the subscription) once the first response arrives. Refreshes are driven by the
+        // SecretManager, like for any other CA.
+        let svids = client
+            .subscribe_to_x509svi_ds(req)
+            .await?
+            .into_inner()
+            .message()
+            .await?
+            .ok_or_else(|| Error::EmptyResponse(id.to_owned()))?;
+        let svid = svids
+            .x509_svids
+            .into_iter()
+            .find(|s| {
+                s.x509_svid
+                    .as_ref()
+                    .and_then(|s| s.id.as_ref())
+                    .map(spiffe_id)
+                    == Some(id.to_string())
+            })
+            .ok_or_else(|| {
+                error!("no SVID returned by SPIRE matches identity {id}");
+                Error::SanError(id.to_owned())
+            })?;
+
+        let bundles = client
+            .subscribe_to_x509_bundles(SubscribeToX509BundlesRequest {})
+            .await?
+            .into_inner()
+            .message()
+            .await?
+            .ok_or_else(|| Error::EmptyResponse(id.to_owned()))?;
+        let roots = bundles
+            .ca_certificates
+            .get(trust_domain.as_str())
+            .or_else(|| {
+                bundles
+                    .ca_certificates
+                    .get(&format!("spiffe://{trust_domain}"))
+            })
+            .ok_or_else(|| Error::EmptyResponse(id.to_owned()))?;
+
+        to_workload_certificate(id, svid, roots)
+    }
+}
+
+#[async_trait]
+impl crate::identity::CaClientTrait for SpireClient {
+    async fn fetch_certificate(&self, id: &Identity) -> Result<tls::WorkloadCertificate, Error> {
+        self.fetch_certificate(id).await
+    }
+}
+
+fn k8s_selector(value: String) -> Selector {
+    Selector {
+        r#type: "k8s".to_string(),
+        value,
+    }
+}
+
+fn spiffe_id(id: &Spiffeid) -> String {
+    format!("spiffe://{}{}", id.trust_domain, id.path)
+}
+
+// to_workload_certificate converts a DER encoded SVID, along with the DER encoded (and
+// concatenated) roots of its trust domain, into a WorkloadCertificate.
+fn to_workload_certificate(
+    id: &Identity,
+    svid: X509svidWithKey,
+    roots: &[u8],
+) -> Result<tls::WorkloadCertificate, Error> {
+    let chain = svid.x509_svid.map(|s| s.cert_chain).unwrap_or_default();
+    let (leaf, intermediates) = chain
+        .split_first()
+        .ok_or_else(|| Error::EmptyResponse(id.to_owned()))?;
+    let leaf = tls::der_to_pem(leaf, "CERTIFICATE");
+    let key = tls::der_to_pem(&svid.x509_svid_key, "PRIVATE KEY");
+    let chain = intermediates
+        .iter()
+        .map(|c| c.as_slice())
+        .chain(split_der_certificates(roots)?)
+        .map(|c| tls::der_to_pem(c, "CERTIFICATE"))
+        .collect::<Vec<_>>();
+    let certs = tls::WorkloadCertificate::new(
+        key.as_bytes(),
+        leaf.as_bytes(),
+        chain.iter().map(|c| c.as_bytes()).collect(),
+    )?;
+    if certs.cert.identity().as_ref() != Some(id) {
+        error!(
+            "expected identity {:?}, got {:?}",
+            id,
+            certs.cert.identity()
+        );
+        return Err(Error::SanError(id.to_owned()));
+    }
+    Ok(certs)
+}
+
+// split_der_certificates splits concatenated DER encoded certificates.
+fn split_der_certificates(mut der: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut certs = Vec::new();
+    while !der.is_empty() {
+        let (rest, _) = x509_parser::parse_x509_certificate(der).map_err(tls::Error::from)?;
+        let (cert, _) = der.split_at(der.len() - rest.len());
+        certs.push(cert);
+        der = rest;
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::identity::Identity;
+    use crate::tls::mock::{generate_test_certs, TestIdentity, TEST_PKEY, TEST_ROOT};
+
+    use super::proto::api::types::X509svid;
+    use super::*;
+
+    fn pem_to_der(pem: &[u8]) -> Vec<u8> {
+        let mut reader = std::io::BufReader::new(pem);
+        rustls_pemfile::certs(&mut reader)
+            .map(|c| c.unwrap().to_vec())
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    #[test]
+    fn svid_to_workload_certificate() {
+        let id = Identity::default();
+        let certs = generate_test_certs(
+            &TestIdentity::Identity(id.clone()),
+            Duration::ZERO,
+            Duration::from_secs(60),
+        );
+        let key = rustls_pemfile::pkcs8_private_keys(&mut std::io::BufReader::new(TEST_PKEY))
+            .next()
+            .unwrap()
+            .unwrap();
+        let svid = X509svidWithKey {
+            x509_svid: Some(X509svid {
+                id: Some(Spiffeid {
+                    trust_domain: "cluster.local".to_string(),
+                    path: "/ns/istio-system/sa/ztunnel".to_string(),
+                }),
+                cert_chain: vec![pem_to_der(certs.cert.as_pem().as_bytes())],
+                expires_at: 0,
+                hint: String::new(),
+            }),
+            x509_svid_key: key.secret_pkcs8_der().to_vec(),
+        };
+        assert_eq!(
+            spiffe_id(svid.x509_svid.as_ref().unwrap().id.as_ref().unwrap()),
+            id.to_string()
+        );
+
+        // Roots are concatenated DER; use the root twice to exercise splitting.
+        let roots = [pem_to_der(TEST_ROOT), pem_to_der(TEST_ROOT)].concat();
+        assert_eq!(split_der_certificates(&roots).unwrap().len(), 2);
+
+        let got = to_workload_certificate(&id, svid.clone(), &roots).unwrap();
+        assert_eq!(got.cert.identity(), Some(id));
+        assert_eq!(got.cert.as_pem(), certs.cert.as_pem());
+
+        // An SVID for a different identity must be rejected
+        let other = Identity::Spiffe {
+            trust_domain: "cluster.local".into(),
+            namespace: "other".into(),
+            service_account: "default".into(),
+        };
+        assert!(to_workload_certificate(&other, svid, &roots).is_err());
+    }
+}
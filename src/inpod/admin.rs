@@ -152,6 +152,17 @@ impl crate::admin::AdminHandler2 for WorkloadManagerAdminHandler {
     }
 }
 
+impl crate::admin::ConnectionManagerSource for WorkloadManagerAdminHandler {
+    fn connection_managers(&self) -> Vec<ConnectionManager> {
+        self.state
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|s| s.connections.clone())
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
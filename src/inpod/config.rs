@@ -21,6 +21,8 @@ pub struct InPodConfig {
     cur_netns: Arc<std::os::fd::OwnedFd>,
     mark: Option<std::num::NonZeroU32>,
     reuse_port: bool,
+    #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+    redirector: Option<Arc<crate::redirect::Redirector>>,
 }
 
 impl InPodConfig {
@@ -29,6 +31,14 @@ impl InPodConfig {
             cur_netns: Arc::new(InpodNetns::current()?),
             mark: std::num::NonZeroU32::new(cfg.inpod_mark),
             reuse_port: cfg.inpod_port_reuse,
+            #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+            redirector: if cfg.ebpf_redirect {
+                let redirector =
+                    crate::redirect::Redirector::new(cfg).map_err(std::io::Error::other)?;
+                Some(Arc::new(redirector))
+            } else {
+                None
+            },
         })
     }
     pub fn socket_factory(
@@ -43,6 +53,18 @@ impl InPodConfig {
         }
     }
 
+    /// redirect enrolls the pod in the network namespace for eBPF redirection, if enabled.
+    #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+    pub fn redirect(
+        &self,
+        netns: &InpodNetns,
+    ) -> Result<Option<Arc<crate::redirect::PodRedirect>>, crate::redirect::Error> {
+        self.redirector
+            .as_ref()
+            .map(|redirector| redirector.enroll(netns))
+            .transpose()
+    }
+
     pub fn cur_netns(&self) -> Arc<std::os::fd::OwnedFd> {
         self.cur_netns.clone()
     }
@@ -87,16 +109,30 @@ impl crate::proxy::SocketFactory for InPodSocketFactory {
     }
 
     fn tcp_bind(&self, addr: std::net::SocketAddr) -> std::io::Result<tokio::net::TcpListener> {
-        let std_sock = self.configure(|| std::net::TcpListener::bind(addr))?;
-        std_sock.set_nonblocking(true)?;
+        let std_sock = self.configure(|| crate::socket::tcp_bind(addr))?;
         tokio::net::TcpListener::from_std(std_sock)
     }
 
     fn udp_bind(&self, addr: std::net::SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
-        let std_sock = self.configure(|| std::net::UdpSocket::bind(addr))?;
-        std_sock.set_nonblocking(true)?;
+        let std_sock = self.configure(|| crate::socket::udp_bind(addr))?;
+        tokio::net::UdpSocket::from_std(std_sock)
+    }
+
+    fn udp_bind_transparent(
+        &self,
+        addr: std::net::SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket> {
+        let std_sock = self.configure(|| crate::socket::udp_bind_transparent(addr))?;
         tokio::net::UdpSocket::from_std(std_sock)
     }
+
+    fn uds_bind(&self, _: &std::path::Path) -> std::io::Result<tokio::net::UnixListener> {
+        // Socket paths are not scoped to the pod's network namespace
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix domain socket listeners are not supported in in-pod mode",
+        ))
+    }
 }
 
 // Same as socket factory, but sets SO_REUSEPORT
@@ -128,6 +164,7 @@ impl crate::proxy::SocketFactory for InPodSocketPortReuseFactory {
         if let Err(e) = sock.set_reuseport(true) {
             tracing::warn!("setting set_reuseport failed: {} addr: {}", e, addr);
         }
+        crate::socket::set_dual_stack(&socket2::SockRef::from(&sock), addr)?;
 
         sock.bind(addr)?;
         sock.listen(128)
@@ -158,6 +195,7 @@ impl crate::proxy::SocketFactory for InPodSocketPortReuseFactory {
 
         // important to set SO_REUSEPORT before binding!
         socket_ref.set_reuse_port(true)?;
+        crate::socket::set_dual_stack(&socket_ref, addr)?;
         let addr = socket2::SockAddr::from(addr);
         socket_ref.bind(&addr)?;
 
@@ -166,6 +204,17 @@ impl crate::proxy::SocketFactory for InPodSocketPortReuseFactory {
         std_sock.set_nonblocking(true)?;
         tokio::net::UdpSocket::from_std(std_sock)
     }
+
+    fn udp_bind_transparent(
+        &self,
+        addr: std::net::SocketAddr,
+    ) -> std::io::Result<tokio::net::UdpSocket> {
+        self.sf.udp_bind_transparent(addr)
+    }
+
+    fn uds_bind(&self, path: &std::path::Path) -> std::io::Result<tokio::net::UnixListener> {
+        self.sf.uds_bind(path)
+    }
 }
 
 #[cfg(test)]
@@ -30,6 +30,10 @@ use super::WorkloadUid;
 pub(super) struct WorkloadState {
     drain: Signal,
     workload_netns_inode: libc::ino_t,
+    // Redirection of the workload's traffic, which stops once the state is dropped
+    #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+    #[allow(dead_code)]
+    redirect: Option<Arc<crate::redirect::PodRedirect>>,
 }
 
 #[derive(Default)]
@@ -39,7 +43,11 @@ struct DrainingTasks {
 
 impl DrainingTasks {
     fn drain_workload(&mut self, workload_state: WorkloadState) {
-        let handle = tokio::spawn(workload_state.drain.drain());
+        // the state is dropped only once drained, so the workload's traffic is redirected until then
+        let handle = tokio::spawn(async move {
+            let WorkloadState { drain, .. } = workload_state;
+            drain.drain().await;
+        });
         // before we push to draining, try to clear done entries, so the vector doesn't grow too much
         self.draining.retain(|x| !x.is_finished());
         // add deleted pod to draining. we do this so we make sure to wait for it incase we
@@ -244,15 +252,35 @@ impl WorkloadProxyManagerState {
         let workload_netns_inode = netns.workload_inode();
         let (drain_tx, drain_rx) = drain::channel();
 
+        #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+        let redirect = self
+            .inpod_config
+            .redirect(&netns)
+            .map_err(|e| crate::proxy::Error::Io(std::io::Error::other(e)))?;
+        let socket_factory = self.inpod_config.socket_factory(netns);
+        #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+        let socket_factory = match &redirect {
+            Some(redirect) => redirect.socket_factory(socket_factory),
+            None => socket_factory,
+        };
+
         let proxies = self
             .proxy_gen
             .new_proxies_from_factory(
                 Some(drain_rx),
                 workload_info.clone(),
-                Arc::from(self.inpod_config.socket_factory(netns)),
+                Arc::from(socket_factory),
             )
             .await?;
 
+        // the proxies are listening, so the workload's traffic can be redirected to them
+        #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+        if let Some(redirect) = &redirect {
+            redirect
+                .start()
+                .map_err(|e| crate::proxy::Error::Io(std::io::Error::other(e)))?;
+        }
+
         let uid = workload_uid.clone();
 
         self.admin_handler
@@ -283,6 +311,8 @@ impl WorkloadProxyManagerState {
             WorkloadState {
                 drain: drain_tx,
                 workload_netns_inode,
+                #[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+                redirect,
             },
         );
 
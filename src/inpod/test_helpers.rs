@@ -16,11 +16,12 @@ use super::config::InPodConfig;
 use super::netns::InpodNetns;
 
 use crate::proxyfactory::ProxyFactory;
-use crate::state::{DemandProxyState, ProxyState};
+use crate::state::cell::ProxyStateCell;
+use crate::state::DemandProxyState;
 use nix::sched::{unshare, CloneFlags};
 use prometheus_client::registry::Registry;
 
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use tokio::net::UnixStream;
 
 use hickory_resolver::config::{ResolverConfig, ResolverOpts};
@@ -66,7 +67,7 @@ impl Default for Fixture {
             inpod_mark: 1,
             ..crate::config::construct_config(Default::default()).unwrap()
         };
-        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let cert_manager: Arc<crate::identity::SecretManager> =
             crate::identity::mock::new_secret_manager(std::time::Duration::from_secs(10));
         let metrics = crate::proxy::Metrics::new(&mut registry);
@@ -29,6 +29,8 @@ pub mod proxy;
 pub mod proxyfactory;
 pub mod rbac;
 pub mod readiness;
+#[cfg(all(target_os = "linux", feature = "ebpf-redirect"))]
+pub mod redirect;
 pub mod signal;
 pub mod socket;
 pub mod state;
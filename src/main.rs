@@ -29,12 +29,13 @@ static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 pub static malloc_conf: &[u8] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
 
 fn main() -> anyhow::Result<()> {
-    telemetry::setup_logging();
     let config = Arc::new(config::parse_config()?);
 
     // For now we don't need a complex CLI, so rather than pull in dependencies just use basic argv[1]
     match std::env::args().nth(1).as_deref() {
+        // Flags, such as --config-file, apply to the default command
         None | Some("proxy") => (),
+        Some(flag) if flag.starts_with("--") => (),
         Some("version") => return version(),
         Some("help") => return help(),
         Some(unknown) => {
@@ -48,7 +49,11 @@ fn main() -> anyhow::Result<()> {
         .enable_all()
         .build()
         .unwrap()
-        .block_on(async move { proxy(config).await })
+        .block_on(async move {
+            // The OTLP exporter runs on this runtime, so logging is set up within it.
+            telemetry::setup_logging(config.otlp_endpoint.as_deref())?;
+            proxy(config).await
+        })
 }
 
 fn help() -> anyhow::Result<()> {
@@ -60,7 +65,10 @@ Istio Ztunnel ({version})
 Commands:
 proxy (default) - Start the ztunnel proxy
 version         - Print the version of ztunnel
-help            - Print commands and version of ztunnel"
+help            - Print commands and version of ztunnel
+
+Flags:
+--config-file <path> - Read settings from a YAML or JSON file. The environment takes precedence"
     );
     Ok(())
 }
@@ -73,5 +81,7 @@ fn version() -> anyhow::Result<()> {
 async fn proxy(cfg: Arc<config::Config>) -> anyhow::Result<()> {
     info!("version: {}", version::BuildInfo::new());
     info!("running with config: {}", serde_yaml::to_string(&cfg)?);
-    app::build(cfg).await?.wait_termination().await
+    let res = app::build(cfg).await?.wait_termination().await;
+    telemetry::shutdown_tracing().await;
+    res
 }
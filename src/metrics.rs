@@ -23,6 +23,10 @@ use tracing::field::{display, DisplayValue};
 use crate::identity::Identity;
 
 pub mod meta;
+pub mod otlp;
+pub mod process;
+#[cfg(feature = "tokio-metrics")]
+mod runtime;
 pub mod server;
 
 use crate::strng::{RichStrng, Strng};
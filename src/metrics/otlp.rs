@@ -0,0 +1,323 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use drain::Watch;
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, number_data_point, AggregationTemporality, Gauge, Histogram, HistogramDataPoint,
+    Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prometheus_client::encoding::protobuf::{self, openmetrics_data_model as om};
+use prometheus_client::registry::Registry;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{debug, warn};
+
+use crate::telemetry::APPLICATION_START_TIME;
+
+// The OpenTelemetry SDKs' default export timeout.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Exporter periodically pushes the metrics registry to an OTLP collector, for environments where
+/// it cannot be scraped. It runs alongside the Prometheus endpoint, which serves the same registry.
+pub struct Exporter {
+    client: MetricsServiceClient<Channel>,
+    registry: Arc<Mutex<Registry>>,
+    period: Duration,
+    // Counters and histograms are cumulative since the process started.
+    start: SystemTime,
+}
+
+impl Exporter {
+    pub fn new(
+        endpoint: &str,
+        period: Duration,
+        registry: Arc<Mutex<Registry>>,
+    ) -> anyhow::Result<Self> {
+        let channel = Endpoint::from_shared(endpoint.to_string())?
+            .timeout(EXPORT_TIMEOUT)
+            .connect_lazy();
+        Ok(Exporter {
+            client: MetricsServiceClient::new(channel),
+            registry,
+            period,
+            start: SystemTime::now() - APPLICATION_START_TIME.elapsed(),
+        })
+    }
+
+    /// spawn pushes the metrics every period until drained, and once more then so the final values
+    /// are not lost.
+    pub fn spawn(mut self, drain_rx: Watch) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.period);
+            // The first tick completes immediately, when there is little to report.
+            interval.tick().await;
+            let drained = drain_rx.signaled();
+            tokio::pin!(drained);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.export().await,
+                    release = &mut drained => {
+                        self.export().await;
+                        drop(release);
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn export(&mut self) {
+        let metrics = {
+            let registry = self.registry.lock().expect("mutex");
+            protobuf::encode(&registry)
+        };
+        let metrics = match metrics {
+            Ok(metrics) => metrics,
+            Err(e) => {
+                warn!("failed to encode metrics: {e}");
+                return;
+            }
+        };
+        let request = export_request(metrics, self.start, SystemTime::now());
+        match self.client.export(request).await {
+            Ok(_) => debug!("exported metrics"),
+            Err(e) => warn!("failed to export metrics: {e}"),
+        }
+    }
+}
+
+fn export_request(
+    metrics: om::MetricSet,
+    start: SystemTime,
+    now: SystemTime,
+) -> ExportMetricsServiceRequest {
+    let (start, now) = (unix_nanos(start), unix_nanos(now));
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(Resource {
+                attributes: vec![attribute("service.name", "ztunnel")],
+                dropped_attributes_count: 0,
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: "ztunnel".to_string(),
+                    ..Default::default()
+                }),
+                metrics: metrics
+                    .metric_families
+                    .into_iter()
+                    .filter_map(|family| metric(family, start, now))
+                    .collect(),
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    }
+}
+
+// metric converts an OpenMetrics family to an OTLP metric. Counters and histograms are cumulative;
+// everything else, including info metrics, is reported as a gauge. Families without any values
+// are skipped.
+fn metric(family: om::MetricFamily, start: u64, now: u64) -> Option<Metric> {
+    use om::metric_point::Value;
+
+    let mut numbers = Vec::new();
+    let mut histograms = Vec::new();
+    for m in family.metrics {
+        for point in m.metric_points {
+            let mut attributes: Vec<_> = m
+                .labels
+                .iter()
+                .map(|l| attribute(&l.name, &l.value))
+                .collect();
+            let value = match point.value {
+                Some(Value::CounterValue(c)) => match c.total {
+                    Some(om::counter_value::Total::DoubleValue(v)) => {
+                        number_data_point::Value::AsDouble(v)
+                    }
+                    Some(om::counter_value::Total::IntValue(v)) => {
+                        number_data_point::Value::AsInt(v as i64)
+                    }
+                    None => continue,
+                },
+                Some(Value::GaugeValue(g)) => match g.value {
+                    Some(om::gauge_value::Value::DoubleValue(v)) => {
+                        number_data_point::Value::AsDouble(v)
+                    }
+                    Some(om::gauge_value::Value::IntValue(v)) => number_data_point::Value::AsInt(v),
+                    None => continue,
+                },
+                Some(Value::UnknownValue(u)) => match u.value {
+                    Some(om::unknown_value::Value::DoubleValue(v)) => {
+                        number_data_point::Value::AsDouble(v)
+                    }
+                    Some(om::unknown_value::Value::IntValue(v)) => {
+                        number_data_point::Value::AsInt(v)
+                    }
+                    None => continue,
+                },
+                Some(Value::InfoValue(i)) => {
+                    attributes.extend(i.info.iter().map(|l| attribute(&l.name, &l.value)));
+                    number_data_point::Value::AsInt(1)
+                }
+                Some(Value::HistogramValue(h)) => {
+                    // The encoder gives the count of each bucket rather than cumulative counts,
+                    // as OTLP expects, and the last bucket is unbounded.
+                    let bounds = &h.buckets[..h.buckets.len().saturating_sub(1)];
+                    histograms.push(HistogramDataPoint {
+                        attributes,
+                        start_time_unix_nano: start,
+                        time_unix_nano: now,
+                        count: h.count,
+                        sum: match h.sum {
+                            Some(om::histogram_value::Sum::DoubleValue(v)) => Some(v),
+                            Some(om::histogram_value::Sum::IntValue(v)) => Some(v as f64),
+                            None => None,
+                        },
+                        bucket_counts: h.buckets.iter().map(|b| b.count).collect(),
+                        explicit_bounds: bounds.iter().map(|b| b.upper_bound).collect(),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+                // Not used by ztunnel.
+                Some(Value::StateSetValue(_)) | Some(Value::SummaryValue(_)) | None => continue,
+            };
+            numbers.push(NumberDataPoint {
+                attributes,
+                start_time_unix_nano: start,
+                time_unix_nano: now,
+                value: Some(value),
+                ..Default::default()
+            });
+        }
+    }
+    if numbers.is_empty() && histograms.is_empty() {
+        return None;
+    }
+
+    let data = match om::MetricType::from_i32(family.r#type) {
+        Some(om::MetricType::Counter) => metric::Data::Sum(Sum {
+            data_points: numbers,
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            is_monotonic: true,
+        }),
+        Some(om::MetricType::Histogram) => metric::Data::Histogram(Histogram {
+            data_points: histograms,
+            aggregation_temporality: AggregationTemporality::Cumulative as i32,
+        }),
+        _ => metric::Data::Gauge(Gauge {
+            data_points: numbers,
+        }),
+    };
+    Some(Metric {
+        name: family.name,
+        description: family.help,
+        unit: family.unit,
+        data: Some(data),
+    })
+}
+
+fn attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::metrics::counter::Counter;
+    use prometheus_client::metrics::family::Family;
+    use prometheus_client::metrics::gauge::Gauge as PromGauge;
+    use prometheus_client::metrics::histogram::Histogram as PromHistogram;
+
+    use super::*;
+
+    #[test]
+    fn convert_registry() {
+        let mut registry = Registry::default();
+        let sub = crate::metrics::sub_registry(&mut registry);
+        let requests = Family::<Vec<(String, String)>, Counter>::default();
+        sub.register("requests", "Requests served", requests.clone());
+        let connections = PromGauge::<i64>::default();
+        sub.register("connections", "Open connections", connections.clone());
+        let latency = PromHistogram::new([0.1, 1.0].into_iter());
+        sub.register("latency", "Request latency", latency.clone());
+
+        requests
+            .get_or_create(&vec![("code".to_string(), "200".to_string())])
+            .inc_by(3);
+        connections.set(2);
+        latency.observe(0.0625);
+        latency.observe(0.5);
+        latency.observe(0.75);
+        latency.observe(5.0);
+
+        let start = UNIX_EPOCH + Duration::from_secs(1);
+        let now = UNIX_EPOCH + Duration::from_secs(2);
+        let request = export_request(protobuf::encode(&registry).unwrap(), start, now);
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+        let find = |name: &str| {
+            metrics
+                .iter()
+                .find(|m| m.name == name)
+                .and_then(|m| m.data.clone())
+                .unwrap()
+        };
+
+        let metric::Data::Sum(sum) = find("istio_requests") else {
+            panic!("requests should be a sum");
+        };
+        assert!(sum.is_monotonic);
+        assert_eq!(
+            sum.aggregation_temporality,
+            AggregationTemporality::Cumulative as i32
+        );
+        let point = &sum.data_points[0];
+        assert_eq!(point.attributes, vec![attribute("code", "200")]);
+        assert_eq!(point.value, Some(number_data_point::Value::AsInt(3)));
+        assert_eq!(point.start_time_unix_nano, 1_000_000_000);
+        assert_eq!(point.time_unix_nano, 2_000_000_000);
+
+        let metric::Data::Gauge(gauge) = find("istio_connections") else {
+            panic!("connections should be a gauge");
+        };
+        assert_eq!(
+            gauge.data_points[0].value,
+            Some(number_data_point::Value::AsInt(2))
+        );
+
+        let metric::Data::Histogram(histogram) = find("istio_latency") else {
+            panic!("latency should be a histogram");
+        };
+        let point = &histogram.data_points[0];
+        assert_eq!(point.count, 4);
+        assert_eq!(point.sum, Some(6.3125));
+        assert_eq!(point.explicit_bounds, vec![0.1, 1.0]);
+        assert_eq!(point.bucket_counts, vec![1, 2, 1]);
+    }
+}
@@ -0,0 +1,197 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::{Registry, Unit};
+use tokio::runtime::Handle;
+use tracing::debug;
+
+use crate::copy;
+
+// How often resource usage is sampled.
+const COLLECT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct RuntimeLabels {
+    runtime: &'static str,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct BufferPoolLabels {
+    pool: &'static str,
+}
+
+/// Metrics reports ztunnel's own resource usage, so operators can alert before it runs out of
+/// memory or file descriptors.
+#[derive(Clone)]
+pub struct Metrics {
+    resident_memory: Gauge,
+    open_fds: Gauge,
+    tokio_tasks: Family<RuntimeLabels, Gauge>,
+    buffer_pool_bytes: Family<BufferPoolLabels, Gauge>,
+    // The runtimes whose tasks are counted, by name
+    runtimes: Arc<Mutex<Vec<(&'static str, Handle)>>>,
+    #[cfg(feature = "tokio-metrics")]
+    scheduler: super::runtime::Metrics,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let resident_memory = Gauge::default();
+        registry.register_with_unit(
+            "process_resident_memory",
+            "The resident set size of the ztunnel process",
+            Unit::Bytes,
+            resident_memory.clone(),
+        );
+        let open_fds = Gauge::default();
+        registry.register(
+            "process_open_fds",
+            "The number of file descriptors the ztunnel process has open",
+            open_fds.clone(),
+        );
+        let tokio_tasks = Family::default();
+        registry.register(
+            "tokio_tasks",
+            "The number of live tasks, by tokio runtime",
+            tokio_tasks.clone(),
+        );
+        let buffer_pool_bytes = Family::default();
+        registry.register_with_unit(
+            "buffer_pool",
+            "The size of the buffers held for reuse, by pool",
+            Unit::Bytes,
+            buffer_pool_bytes.clone(),
+        );
+        Metrics {
+            resident_memory,
+            open_fds,
+            tokio_tasks,
+            buffer_pool_bytes,
+            runtimes: Default::default(),
+            #[cfg(feature = "tokio-metrics")]
+            scheduler: super::runtime::Metrics::new(registry),
+        }
+    }
+
+    /// register_runtime reports on the runtime it is called from, under the given name.
+    pub fn register_runtime(&self, name: &'static str) {
+        self.runtimes
+            .lock()
+            .expect("mutex")
+            .push((name, Handle::current()));
+    }
+
+    /// run samples resource usage periodically, forever.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(COLLECT_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.collect();
+        }
+    }
+
+    fn collect(&self) {
+        match resident_memory() {
+            Ok(rss) => {
+                self.resident_memory.set(rss as i64);
+            }
+            Err(e) => debug!("failed to read resident memory: {e}"),
+        }
+        match open_fds() {
+            Ok(fds) => {
+                self.open_fds.set(fds as i64);
+            }
+            Err(e) => debug!("failed to count open file descriptors: {e}"),
+        }
+        for (runtime, handle) in self.runtimes.lock().expect("mutex").iter() {
+            let labels = RuntimeLabels { runtime: *runtime };
+            self.tokio_tasks
+                .get_or_create(&labels)
+                .set(handle.metrics().num_alive_tasks() as i64);
+            #[cfg(feature = "tokio-metrics")]
+            self.scheduler.collect(&labels, handle);
+        }
+        self.buffer_pool_bytes
+            .get_or_create(&BufferPoolLabels { pool: "relay" })
+            .set(copy::pooled_buffer_bytes() as i64);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn resident_memory() -> std::io::Result<u64> {
+    // The second field of statm is the resident set size, in pages
+    let statm = std::fs::read_to_string("/proc/self/statm")?;
+    let pages: u64 = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed statm"))?;
+    // Safety: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Ok(pages * page_size as u64)
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> std::io::Result<usize> {
+    // The count includes the descriptor used to read the directory itself, which is closed again
+    Ok(std::fs::read_dir("/proc/self/fd")?
+        .count()
+        .saturating_sub(1))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> std::io::Result<u64> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds() -> std::io::Result<usize> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_usage() {
+        assert!(resident_memory().unwrap() > 0);
+        // At least stdin, stdout and stderr
+        assert!(open_fds().unwrap() >= 3);
+    }
+
+    #[tokio::test]
+    async fn collect() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        metrics.register_runtime("test");
+        metrics.collect();
+        let mut out = String::new();
+        prometheus_client::encoding::text::encode(&mut out, &registry).unwrap();
+        assert!(out.contains("tokio_tasks{runtime=\"test\"}"), "{out}");
+        assert!(out.contains("buffer_pool_bytes{pool=\"relay\"}"), "{out}");
+        #[cfg(feature = "tokio-metrics")]
+        assert!(
+            out.contains("tokio_worker_busy_seconds_total{runtime=\"test\"}"),
+            "{out}"
+        );
+    }
+}
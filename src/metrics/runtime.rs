@@ -0,0 +1,127 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The scheduler metrics used here are only exposed by tokio when built with the unstable cfg.
+#[cfg(not(tokio_unstable))]
+compile_error!(
+    "the tokio-metrics feature requires building with RUSTFLAGS=\"--cfg tokio_unstable\""
+);
+
+use std::sync::atomic::AtomicU64;
+
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::{Registry, Unit};
+use tokio::runtime::Handle;
+
+use crate::metrics::process::RuntimeLabels;
+
+/// Metrics reports the health of the tokio schedulers, to diagnose tasks starving the event loop.
+#[derive(Clone)]
+pub struct Metrics {
+    workers: Family<RuntimeLabels, Gauge>,
+    worker_busy: Family<RuntimeLabels, Counter<f64, AtomicU64>>,
+    global_queue_depth: Family<RuntimeLabels, Gauge>,
+    local_queue_depth: Family<RuntimeLabels, Gauge>,
+    blocking_threads: Family<RuntimeLabels, Gauge>,
+    idle_blocking_threads: Family<RuntimeLabels, Gauge>,
+    blocking_queue_depth: Family<RuntimeLabels, Gauge>,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let workers = Family::default();
+        registry.register(
+            "tokio_workers",
+            "The number of worker threads, by tokio runtime",
+            workers.clone(),
+        );
+        let worker_busy = Family::default();
+        registry.register_with_unit(
+            "tokio_worker_busy",
+            "The time worker threads have spent running tasks, summed over workers, by tokio runtime",
+            Unit::Seconds,
+            worker_busy.clone(),
+        );
+        let global_queue_depth = Family::default();
+        registry.register(
+            "tokio_global_queue_depth",
+            "The number of tasks waiting in the shared scheduler queue, by tokio runtime",
+            global_queue_depth.clone(),
+        );
+        let local_queue_depth = Family::default();
+        registry.register(
+            "tokio_local_queue_depth",
+            "The number of tasks waiting in the worker-local queues, summed over workers, by tokio runtime",
+            local_queue_depth.clone(),
+        );
+        let blocking_threads = Family::default();
+        registry.register(
+            "tokio_blocking_threads",
+            "The number of threads in the blocking pool, by tokio runtime",
+            blocking_threads.clone(),
+        );
+        let idle_blocking_threads = Family::default();
+        registry.register(
+            "tokio_idle_blocking_threads",
+            "The number of idle threads in the blocking pool, by tokio runtime",
+            idle_blocking_threads.clone(),
+        );
+        let blocking_queue_depth = Family::default();
+        registry.register(
+            "tokio_blocking_queue_depth",
+            "The number of tasks waiting for a blocking pool thread, by tokio runtime",
+            blocking_queue_depth.clone(),
+        );
+        Metrics {
+            workers,
+            worker_busy,
+            global_queue_depth,
+            local_queue_depth,
+            blocking_threads,
+            idle_blocking_threads,
+            blocking_queue_depth,
+        }
+    }
+
+    pub fn collect(&self, labels: &RuntimeLabels, handle: &Handle) {
+        let m = handle.metrics();
+        let workers = m.num_workers();
+        let busy: f64 = (0..workers)
+            .map(|w| m.worker_total_busy_duration(w).as_secs_f64())
+            .sum();
+        let local_depth: usize = (0..workers).map(|w| m.worker_local_queue_depth(w)).sum();
+
+        self.workers.get_or_create(labels).set(workers as i64);
+        // Tokio reports a running total; only the growth since the last sample is added
+        let counter = self.worker_busy.get_or_create(labels);
+        counter.inc_by((busy - counter.get()).max(0.0));
+        self.global_queue_depth
+            .get_or_create(labels)
+            .set(m.global_queue_depth() as i64);
+        self.local_queue_depth
+            .get_or_create(labels)
+            .set(local_depth as i64);
+        self.blocking_threads
+            .get_or_create(labels)
+            .set(m.num_blocking_threads() as i64);
+        self.idle_blocking_threads
+            .get_or_create(labels)
+            .set(m.num_idle_blocking_threads() as i64);
+        self.blocking_queue_depth
+            .get_or_create(labels)
+            .set(m.blocking_queue_depth() as i64);
+    }
+}
@@ -27,23 +27,26 @@ use crate::config::Config;
 use crate::hyper_util;
 
 pub struct Server {
-    s: hyper_util::Server<Mutex<Registry>>,
+    s: hyper_util::Server<Arc<Mutex<Registry>>>,
 }
 
 impl Server {
     pub async fn new(
         config: Arc<Config>,
         drain_rx: Watch,
-        registry: Registry,
+        registry: Arc<Mutex<Registry>>,
     ) -> anyhow::Result<Self> {
-        hyper_util::Server::<Mutex<Registry>>::bind(
+        let mut s = hyper_util::Server::<Arc<Mutex<Registry>>>::bind(
             "stats",
             config.stats_addr,
             drain_rx,
-            Mutex::new(registry),
+            registry,
         )
-        .await
-        .map(|s| Server { s })
+        .await?;
+        if let Some(tls) = &config.admin_tls {
+            s.set_tls(crate::tls::admin_server_config(&config.tls_settings, tls).await?);
+        }
+        Ok(Server { s })
     }
 
     pub fn address(&self) -> SocketAddr {
@@ -53,17 +56,14 @@ impl Server {
     pub fn spawn(self) {
         self.s.spawn(|registry, req| async move {
             match req.uri().path() {
-                "/metrics" | "/stats/prometheus" => Ok(handle_metrics(registry, req).await),
+                "/metrics" | "/stats/prometheus" => Ok(handle_metrics(&registry, req).await),
                 _ => Ok(hyper_util::empty_response(hyper::StatusCode::NOT_FOUND)),
             }
         })
     }
 }
 
-async fn handle_metrics(
-    reg: Arc<Mutex<Registry>>,
-    _req: Request<Incoming>,
-) -> Response<Full<Bytes>> {
+async fn handle_metrics(reg: &Mutex<Registry>, _req: Request<Incoming>) -> Response<Full<Bytes>> {
     let mut buf = String::new();
     let reg = reg.lock().expect("mutex");
     if let Err(err) = encode(&mut buf, &reg) {
@@ -14,15 +14,20 @@
 
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, io};
 
 use drain::Watch;
 
+use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
 use rand::Rng;
 
+use tokio::io::AsyncRead;
 use tokio::net::{TcpListener, TcpSocket, TcpStream};
 use tokio::time::timeout;
 use tracing::{debug, trace, warn, Instrument};
@@ -32,26 +37,50 @@ pub use metrics::*;
 
 use crate::identity::{Identity, SecretManager};
 
-use crate::proxy::connection_manager::{ConnectionManager, PolicyWatcher};
+use crate::proxy::budget::{BudgetPermit, ConnectionBudget};
+use crate::proxy::connection_manager::{ConnectionManager, PolicyWatcher, WorkloadConnectionGuard};
+use crate::proxy::forward_proxy::ForwardProxy;
 use crate::proxy::inbound_passthrough::InboundPassthrough;
 use crate::proxy::outbound::Outbound;
+use crate::proxy::outbound_udp::OutboundUdp;
+use crate::proxy::outlier::OutlierDetector;
+use crate::proxy::rate_limit::TokenBucket;
+use crate::proxy::sni_router::SniRouter;
 use crate::proxy::socks5::Socks5;
+#[cfg(unix)]
+use crate::proxy::uds::UdsListener;
 use crate::rbac::Connection;
 use crate::state::service::{endpoint_uid, Service, ServiceDescription};
 use crate::state::workload::address::Address;
 use crate::state::workload::{network_addr, GatewayAddress, Workload};
 use crate::state::{DemandProxyState, WorkloadInfo};
-use crate::{config, identity, socket, tls};
+use crate::strng::Strng;
+use crate::{config, identity, socket, strng, telemetry, tls};
 
+pub mod access_log;
+mod budget;
+mod connect_udp;
 pub mod connection_manager;
+mod forward_proxy;
 mod h2;
+#[cfg(feature = "quic")]
+mod h3;
 mod inbound;
 mod inbound_passthrough;
 #[allow(non_camel_case_types)]
 pub mod metrics;
+mod mirror;
+mod originate;
 mod outbound;
+mod outbound_udp;
+mod outlier;
 pub mod pool;
+mod rate_limit;
+mod sni_router;
+pub mod sniff;
 mod socks5;
+#[cfg(unix)]
+mod uds;
 mod util;
 
 pub trait SocketFactory {
@@ -62,6 +91,11 @@ pub trait SocketFactory {
     fn tcp_bind(&self, addr: SocketAddr) -> std::io::Result<TcpListener>;
 
     fn udp_bind(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket>;
+
+    fn udp_bind_transparent(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket>;
+
+    #[cfg(unix)]
+    fn uds_bind(&self, path: &Path) -> std::io::Result<tokio::net::UnixListener>;
 }
 
 #[derive(Clone, Copy, Default)]
@@ -77,15 +111,20 @@ impl SocketFactory for DefaultSocketFactory {
     }
 
     fn tcp_bind(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
-        let std_sock = std::net::TcpListener::bind(addr)?;
-        std_sock.set_nonblocking(true)?;
-        TcpListener::from_std(std_sock)
+        TcpListener::from_std(socket::tcp_bind(addr)?)
     }
 
     fn udp_bind(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
-        let std_sock = std::net::UdpSocket::bind(addr)?;
-        std_sock.set_nonblocking(true)?;
-        tokio::net::UdpSocket::from_std(std_sock)
+        tokio::net::UdpSocket::from_std(socket::udp_bind(addr)?)
+    }
+
+    fn udp_bind_transparent(&self, addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
+        tokio::net::UdpSocket::from_std(socket::udp_bind_transparent(addr)?)
+    }
+
+    #[cfg(unix)]
+    fn uds_bind(&self, path: &Path) -> std::io::Result<tokio::net::UnixListener> {
+        tokio::net::UnixListener::from_std(socket::uds_bind(path)?)
     }
 }
 
@@ -93,7 +132,12 @@ pub struct Proxy {
     inbound: Inbound,
     inbound_passthrough: InboundPassthrough,
     outbound: Outbound,
+    outbound_udp: Option<OutboundUdp>,
     socks5: Option<Socks5>,
+    sni_router: Option<SniRouter>,
+    forward_proxy: Option<ForwardProxy>,
+    #[cfg(unix)]
+    uds: Vec<UdsListener>,
     policy_watcher: PolicyWatcher,
     illegal_ports: Arc<HashSet<u16>>,
 }
@@ -108,6 +152,12 @@ pub(super) struct ProxyInputs {
     metrics: Arc<Metrics>,
     socket_factory: Arc<dyn SocketFactory + Send + Sync>,
     proxy_workload_info: Option<Arc<WorkloadInfo>>,
+    // accept_limiter limits the rate of new connections across all listeners, if configured
+    accept_limiter: Option<Arc<TokenBucket>>,
+    // outlier ejects failing endpoints from outbound endpoint selection, if enabled
+    outlier: Option<Arc<OutlierDetector>>,
+    // connection_budget limits the concurrent connections across all listeners, if configured
+    connection_budget: Option<Arc<ConnectionBudget>>,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -122,6 +172,9 @@ impl ProxyInputs {
         proxy_workload_info: Option<WorkloadInfo>,
     ) -> Self {
         Self {
+            accept_limiter: accept_limiter(&cfg),
+            outlier: outlier_detector(&cfg, &metrics),
+            connection_budget: connection_budget(&cfg, &metrics),
             cfg,
             state,
             cert_manager,
@@ -146,6 +199,9 @@ impl Proxy {
         let socket_factory = Arc::new(DefaultSocketFactory);
 
         let pi = ProxyInputs {
+            accept_limiter: accept_limiter(&cfg),
+            outlier: outlier_detector(&cfg, &metrics),
+            connection_budget: connection_budget(&cfg, &metrics),
             cfg,
             state,
             cert_manager,
@@ -168,9 +224,16 @@ impl Proxy {
         illegal_ports.insert(inbound.address().port());
 
         let inbound_passthrough = InboundPassthrough::new(pi.clone(), drain.clone()).await?;
-        illegal_ports.insert(inbound_passthrough.address().port());
+        illegal_ports.extend(inbound_passthrough.addresses().iter().map(|a| a.port()));
         let outbound = Outbound::new(pi.clone(), drain.clone()).await?;
         illegal_ports.insert(outbound.address().port());
+        let outbound_udp = if pi.cfg.enable_connect_udp {
+            let outbound_udp = OutboundUdp::new(pi.clone(), drain.clone()).await?;
+            illegal_ports.insert(outbound_udp.address().port());
+            Some(outbound_udp)
+        } else {
+            None
+        };
         let socks5 = if pi.cfg.socks5_addr.is_some() {
             let socks5 = Socks5::new(pi.clone(), drain.clone()).await?;
             illegal_ports.insert(socks5.address().port());
@@ -178,13 +241,34 @@ impl Proxy {
         } else {
             None
         };
+        let sni_router = if pi.cfg.sni_router_addr.is_some() {
+            let sni_router = SniRouter::new(pi.clone(), drain.clone()).await?;
+            illegal_ports.insert(sni_router.address().port());
+            Some(sni_router)
+        } else {
+            None
+        };
+        let forward_proxy = if pi.cfg.forward_proxy_addr.is_some() {
+            let forward_proxy = ForwardProxy::new(pi.clone(), drain.clone()).await?;
+            illegal_ports.insert(forward_proxy.address().port());
+            Some(forward_proxy)
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        let uds = UdsListener::from_config(&pi, &drain)?;
         let policy_watcher = PolicyWatcher::new(pi.state, drain, pi.connection_manager);
 
         Ok(Proxy {
             inbound,
             inbound_passthrough,
             outbound,
+            outbound_udp,
             socks5,
+            sni_router,
+            forward_proxy,
+            #[cfg(unix)]
+            uds,
             policy_watcher,
             illegal_ports: Arc::new(illegal_ports),
         })
@@ -205,9 +289,22 @@ impl Proxy {
             tokio::spawn(self.outbound.run().in_current_span()),
             tokio::spawn(self.policy_watcher.run().in_current_span()),
         ];
+        if let Some(outbound_udp) = self.outbound_udp {
+            tasks.push(tokio::spawn(outbound_udp.run().in_current_span()));
+        }
         if let Some(socks5) = self.socks5 {
             tasks.push(tokio::spawn(socks5.run().in_current_span()));
         }
+        if let Some(sni_router) = self.sni_router {
+            tasks.push(tokio::spawn(sni_router.run().in_current_span()));
+        }
+        if let Some(forward_proxy) = self.forward_proxy {
+            tasks.push(tokio::spawn(forward_proxy.run().in_current_span()));
+        }
+        #[cfg(unix)]
+        for uds in self.uds {
+            tasks.push(tokio::spawn(uds.run().in_current_span()));
+        }
 
         futures::future::join_all(tasks).await;
     }
@@ -217,6 +314,8 @@ impl Proxy {
             outbound: self.outbound.address(),
             inbound: self.inbound.address(),
             socks5: self.socks5.as_ref().map(|s| s.address()),
+            sni_router: self.sni_router.as_ref().map(|s| s.address()),
+            forward_proxy: self.forward_proxy.as_ref().map(|s| s.address()),
         }
     }
 }
@@ -226,6 +325,8 @@ pub struct Addresses {
     pub outbound: SocketAddr,
     pub inbound: SocketAddr,
     pub socks5: Option<SocketAddr>,
+    pub sni_router: Option<SocketAddr>,
+    pub forward_proxy: Option<SocketAddr>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -233,6 +334,9 @@ pub enum Error {
     #[error("failed to bind to address {0}: {1}")]
     Bind(SocketAddr, io::Error),
 
+    #[error("failed to bind to unix socket {}: {1}", .0.display())]
+    BindUds(std::path::PathBuf, io::Error),
+
     #[error("io error: {0}")]
     Io(#[from] io::Error),
 
@@ -242,8 +346,8 @@ pub enum Error {
     #[error("connection closed due to policy change")]
     AuthorizationPolicyLateRejection,
 
-    #[error("connection closed due to policy rejection")]
-    AuthorizationPolicyRejection,
+    #[error("connection closed due to policy rejection: {0}")]
+    AuthorizationPolicyRejection(String),
 
     #[error("pool is already connecting")]
     WorkloadHBONEPoolAlreadyConnecting,
@@ -263,6 +367,18 @@ pub enum Error {
     #[error("h2 failed: {0}")]
     H2(#[from] ::h2::Error),
 
+    #[cfg(feature = "quic")]
+    #[error("quic connection failed: {0}")]
+    QuicConnect(#[from] quinn::ConnectionError),
+
+    #[cfg(feature = "quic")]
+    #[error("h3 connection failed: {0}")]
+    H3Connection(#[source] ::h3::error::ConnectionError),
+
+    #[cfg(feature = "quic")]
+    #[error("h3 failed: {0}")]
+    H3(#[from] ::h3::error::StreamError),
+
     #[error("http status: {0}")]
     HttpStatus(http::StatusCode),
 
@@ -287,9 +403,15 @@ pub enum Error {
     #[error("unknown waypoint: {0}")]
     UnknownWaypoint(String),
 
+    #[error("unknown network gateway: {0}")]
+    UnknownNetworkGateway(String),
+
     #[error("unknown destination: {0}")]
     UnknownDestination(IpAddr),
 
+    #[error("unknown destination denied by policy: {0}")]
+    UnknownDestinationDenied(IpAddr),
+
     #[error("no valid routing destination for workload: {0}")]
     NoValidDestination(Box<Workload>),
 
@@ -318,6 +440,113 @@ pub enum Error {
 
     #[error("connection failed to drain within the timeout")]
     DrainTimeOut,
+
+    #[error("connection closed after exceeding the idle timeout")]
+    IdleTimeout,
+
+    #[error("connection limit reached for workload: {0}")]
+    WorkloadConnectionLimit(Strng),
+
+    #[error("port {0} is excluded from capture for the destination workload")]
+    ExcludedPort(u16),
+
+    #[error("no server name in TLS ClientHello")]
+    MissingSni,
+
+    #[error("unknown server name: {0}")]
+    UnknownServerName(Strng),
+
+    #[error("unknown hostname: {0}")]
+    UnknownHostname(Strng),
+
+    #[error("connection shed: the proxy is at its connection limit")]
+    ConnectionShed,
+
+    #[error("connection preface timed out during {0}")]
+    PrefaceTimeout(PrefaceStage),
+
+    #[error("tls origination to {0} failed: {1}")]
+    TlsOrigination(Strng, io::Error),
+}
+
+impl Error {
+    // is_endpoint_failure returns whether connecting to an upstream failed because of the endpoint
+    // itself, rather than, for example, being rejected by policy.
+    pub(crate) fn is_endpoint_failure(&self) -> bool {
+        match self {
+            Error::Io(_)
+            | Error::ConnectionFailed(_)
+            | Error::Http2Handshake(_)
+            | Error::H2(_)
+            | Error::Tls(_)
+            | Error::TlsOrigination(..) => true,
+            #[cfg(feature = "quic")]
+            Error::QuicConnect(_) | Error::H3Connection(_) | Error::H3(_) => true,
+            Error::HttpStatus(status) => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// code categorizes the error, so failures can be aggregated in metrics and access logs.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Bind(..) | Error::BindUds(..) => ErrorCode::bind,
+
+            Error::Io(_)
+            | Error::ConnectionFailed(_)
+            | Error::Generic(_)
+            | Error::WorkloadHBONEPoolAlreadyConnecting
+            | Error::WorkloadHBONEPoolConnStreamsMaxed
+            | Error::WorkloadHBONEPoolDraining
+            | Error::DrainTimeOut
+            | Error::IdleTimeout
+            | Error::WorkloadConnectionLimit(_)
+            | Error::ConnectionShed => ErrorCode::connect,
+
+            Error::Tls(_) | Error::Identity(_) | Error::TlsOrigination(..) => ErrorCode::tls,
+
+            Error::AuthorizationPolicyLateRejection
+            | Error::AuthorizationPolicyRejection(_)
+            | Error::UnknownDestinationDenied(_)
+            | Error::MismatchedSource(..) => ErrorCode::rbac,
+            // The peer rejected us, typically because of its own policy
+            Error::HttpStatus(status)
+                if *status == http::StatusCode::UNAUTHORIZED
+                    || *status == http::StatusCode::FORBIDDEN =>
+            {
+                ErrorCode::rbac
+            }
+
+            Error::UnknownSource(_)
+            | Error::UnknownWaypoint(_)
+            | Error::UnknownNetworkGateway(_)
+            | Error::UnknownDestination(_)
+            | Error::NoValidDestination(_)
+            | Error::NoResolvedAddresses(_)
+            | Error::EmptyResolvedAddresses(_)
+            | Error::NoGatewayAddress(_)
+            | Error::UnknownServerName(_)
+            | Error::UnknownHostname(_)
+            | Error::ExcludedPort(_) => ErrorCode::xds,
+
+            Error::Http2Handshake(_)
+            | Error::H2(_)
+            | Error::HttpStatus(_)
+            | Error::NonConnectMethod(_)
+            | Error::ConnectAddress(_)
+            | Error::SelfCall
+            | Error::UnsupportedFeature(_)
+            | Error::IPMismatch(..)
+            | Error::DoubleConnection
+            | Error::MissingSni
+            | Error::PrefaceTimeout(_) => ErrorCode::protocol,
+
+            #[cfg(feature = "quic")]
+            Error::QuicConnect(_) => ErrorCode::connect,
+            #[cfg(feature = "quic")]
+            Error::H3Connection(_) | Error::H3(_) => ErrorCode::protocol,
+        }
+    }
 }
 
 const PROXY_PROTOCOL_AUTHORITY_TLV: u8 = 0xD0;
@@ -345,6 +574,67 @@ where
     stream.write_all(&header).await
 }
 
+const PROXY_PROTOCOL_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const PROXY_PROTOCOL_V1_MAX_LEN: usize = 107;
+
+// read_proxy_protocol reads a PROXY protocol (v1 or v2) header from the start of the stream, and
+// returns the source and destination addresses it conveys. Exactly the header is read, leaving the
+// rest of the stream intact. Headers that do not convey addresses, such as health checks from the
+// load balancer itself, return None.
+pub async fn read_proxy_protocol<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    use ppp::{v1, v2, HeaderResult};
+    use tokio::io::AsyncReadExt;
+
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    // The shortest v1 header, "PROXY UNKNOWN\r\n", is 15 bytes. A v2 header is 16 bytes followed by
+    // its length, so we read up to that first.
+    let mut header = vec![0; 16];
+    stream.read_exact(&mut header[..15]).await?;
+    if header.starts_with(PROXY_PROTOCOL_V2_SIGNATURE) {
+        stream.read_exact(&mut header[15..]).await?;
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        header.resize(16 + len, 0);
+        stream.read_exact(&mut header[16..]).await?;
+    } else {
+        header.truncate(15);
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= PROXY_PROTOCOL_V1_MAX_LEN {
+                return Err(invalid("PROXY protocol header too long".to_string()));
+            }
+            header.push(stream.read_u8().await?);
+        }
+    }
+
+    match HeaderResult::parse(&header) {
+        HeaderResult::V1(Ok(h)) => Ok(match h.addresses {
+            v1::Addresses::Tcp4(a) => Some((
+                SocketAddr::from((a.source_address, a.source_port)),
+                SocketAddr::from((a.destination_address, a.destination_port)),
+            )),
+            v1::Addresses::Tcp6(a) => Some((
+                SocketAddr::from((a.source_address, a.source_port)),
+                SocketAddr::from((a.destination_address, a.destination_port)),
+            )),
+            v1::Addresses::Unknown => None,
+        }),
+        HeaderResult::V2(Ok(h)) => Ok(match (h.command, h.addresses) {
+            (v2::Command::Proxy, v2::Addresses::IPv4(a)) => Some((
+                SocketAddr::from((a.source_address, a.source_port)),
+                SocketAddr::from((a.destination_address, a.destination_port)),
+            )),
+            (v2::Command::Proxy, v2::Addresses::IPv6(a)) => Some((
+                SocketAddr::from((a.source_address, a.source_port)),
+                SocketAddr::from((a.destination_address, a.destination_port)),
+            )),
+            _ => None,
+        }),
+        HeaderResult::V1(Err(e)) => Err(invalid(e.to_string())),
+        HeaderResult::V2(Err(e)) => Err(invalid(e.to_string())),
+    }
+}
+
 /// Represents a traceparent, as defined by https://www.w3.org/TR/trace-context/
 #[derive(Eq, PartialEq)]
 pub struct TraceParent {
@@ -361,6 +651,35 @@ impl TraceParent {
     pub fn header(&self) -> hyper::header::HeaderValue {
         hyper::header::HeaderValue::from_bytes(format!("{self:?}").as_bytes()).unwrap()
     }
+
+    // child returns a new TraceParent in the same trace, identifying a new span.
+    // This should be used when propagating the trace to another hop, so the next hop's spans are
+    // parented to ours.
+    pub fn child(&self) -> Self {
+        Self {
+            version: self.version,
+            trace_id: self.trace_id,
+            parent_id: rand::thread_rng().gen(),
+            flags: self.flags,
+        }
+    }
+
+    // span_id returns the hex encoded id of the span this TraceParent identifies.
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.parent_id)
+    }
+
+    // span_context returns the OpenTelemetry span context this TraceParent identifies. It is
+    // marked remote, as it may have been received from another hop.
+    pub fn span_context(&self) -> SpanContext {
+        SpanContext::new(
+            TraceId::from(self.trace_id),
+            SpanId::from(self.parent_id),
+            TraceFlags::new(self.flags),
+            true,
+            TraceState::default(),
+        )
+    }
 }
 impl TraceParent {
     fn new() -> Self {
@@ -369,7 +688,8 @@ impl TraceParent {
             version: 0,
             trace_id: rng.gen(),
             parent_id: rng.gen(),
-            flags: 0,
+            // Traces we start are only recorded when they are exported.
+            flags: telemetry::exporting_traces().into(),
         }
     }
 }
@@ -409,6 +729,47 @@ impl TryFrom<&str> for TraceParent {
     }
 }
 
+/// ConnectionId uniquely identifies a single proxied connection, from when it is accepted until it
+/// completes. It is formatted as a random (version 4) UUID.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ConnectionId(u128);
+
+impl ConnectionId {
+    pub fn new() -> Self {
+        let id: u128 = rand::thread_rng().gen();
+        // Set the version (4) and variant (RFC 4122) bits
+        let id = (id & !(0xf_u128 << 76) & !(0x3_u128 << 62)) | (0x4_u128 << 76) | (0x2_u128 << 62);
+        Self(id)
+    }
+}
+
+impl Default for ConnectionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let id = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            id >> 96,
+            (id >> 80) & 0xffff,
+            (id >> 64) & 0xffff,
+            (id >> 48) & 0xffff,
+            id & 0xffff_ffff_ffff,
+        )
+    }
+}
+
+impl serde::Serialize for ConnectionId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 pub(super) fn maybe_set_transparent(
     pi: &ProxyInputs,
     listener: &TcpListener,
@@ -430,10 +791,240 @@ pub(super) fn maybe_set_transparent(
     })
 }
 
-pub fn get_original_src_from_stream(stream: &TcpStream) -> Option<IpAddr> {
-    stream
-        .peer_addr()
-        .map_or(None, |sa| Some(socket::to_canonical(sa).ip()))
+// SocketClass is what a proxied connection's socket faces, which selects the socket policy applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum SocketClass {
+    // Sockets to and from workloads, without HBONE.
+    App,
+    // HBONE sockets to and from other ztunnels or waypoints.
+    Mesh,
+}
+
+impl SocketClass {
+    fn policy(self, cfg: &config::SocketConfig) -> &config::SocketPolicy {
+        match self {
+            SocketClass::App => &cfg.app,
+            SocketClass::Mesh => &cfg.mesh,
+        }
+    }
+}
+
+// set_socket_options applies the configured options to a proxied connection.
+// Failures are not fatal; the connection proceeds with the kernel defaults.
+pub(super) fn set_socket_options(
+    stream: &TcpStream,
+    cfg: &config::SocketConfig,
+    class: SocketClass,
+) {
+    if let Err(e) = socket::set_keepalive(stream, cfg) {
+        warn!("failed to set keepalive: {e}");
+    }
+    set_socket_policy(stream, cfg, class);
+}
+
+// set_socket_policy applies the socket policy for the connection's class, without the other
+// socket options.
+pub(super) fn set_socket_policy(
+    stream: &TcpStream,
+    cfg: &config::SocketConfig,
+    class: SocketClass,
+) {
+    if let Err(e) = socket::set_policy(stream, class.policy(cfg)) {
+        warn!(?class, "failed to apply socket policy: {e}");
+    }
+}
+
+// received_dscp returns the DSCP mark of a downstream connection, to propagate to its upstream.
+// Returns None if propagation is disabled or the connection is unmarked.
+pub(super) fn received_dscp(stream: &TcpStream, cfg: &config::SocketConfig) -> Option<u8> {
+    if !cfg.propagate_dscp {
+        return None;
+    }
+    match socket::received_dscp(stream) {
+        Ok(0) => None,
+        Ok(dscp) => Some(dscp),
+        Err(e) => {
+            debug!("failed to read DSCP mark: {e}");
+            None
+        }
+    }
+}
+
+// upstream_dscp returns the downstream DSCP mark to apply to an upstream socket of the given class.
+// A fixed TOS for the class takes precedence over the propagated mark.
+pub(super) fn upstream_dscp(
+    dscp: Option<u8>,
+    cfg: &config::SocketConfig,
+    class: SocketClass,
+) -> Option<u8> {
+    dscp.filter(|_| class.policy(cfg).tos.is_none())
+}
+
+// set_dscp marks an upstream socket with a propagated DSCP mark, if any.
+pub(super) fn set_dscp(stream: &TcpStream, dscp: Option<u8>) {
+    if let Some(dscp) = dscp {
+        if let Err(e) = socket::set_tos(stream, dscp) {
+            warn!("failed to propagate DSCP mark: {e}");
+        }
+    }
+}
+
+fn accept_limiter(cfg: &config::Config) -> Option<Arc<TokenBucket>> {
+    cfg.connection_rate_limit.map(|rate| {
+        let burst = cfg.connection_rate_limit_burst.unwrap_or(rate);
+        Arc::new(TokenBucket::new(rate, burst))
+    })
+}
+
+fn connection_budget(
+    cfg: &config::Config,
+    metrics: &Arc<Metrics>,
+) -> Option<Arc<ConnectionBudget>> {
+    cfg.max_connections
+        .map(|limit| Arc::new(ConnectionBudget::new(limit, metrics.clone())))
+}
+
+fn outlier_detector(cfg: &config::Config, metrics: &Metrics) -> Option<Arc<OutlierDetector>> {
+    (cfg.outlier_consecutive_failures > 0).then(|| {
+        Arc::new(OutlierDetector::new(
+            cfg.outlier_consecutive_failures,
+            cfg.outlier_ejection_time,
+            metrics,
+        ))
+    })
+}
+
+// throttle_accept waits until the connection rate limit allows accepting another connection.
+pub(super) async fn throttle_accept(pi: &ProxyInputs) {
+    if let Some(limiter) = &pi.accept_limiter {
+        if limiter.acquire().await {
+            pi.metrics.accepts_throttled.inc();
+        }
+    }
+}
+
+/// PrefaceTimeouts bound how long a client may take over each stage of setting up an inbound
+/// connection, before any traffic flows. Stages that time out are counted.
+#[derive(Clone)]
+pub struct PrefaceTimeouts {
+    first_byte: Duration,
+    tls_handshake: Duration,
+    hbone_connect: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl PrefaceTimeouts {
+    pub fn new(cfg: &config::Config, metrics: Arc<Metrics>) -> Self {
+        PrefaceTimeouts {
+            first_byte: cfg.inbound_first_byte_timeout,
+            tls_handshake: cfg.inbound_tls_handshake_timeout,
+            hbone_connect: cfg.inbound_hbone_connect_timeout,
+            metrics,
+        }
+    }
+
+    /// limit returns the timeout for a stage, or None if it is disabled.
+    pub fn limit(&self, stage: PrefaceStage) -> Option<Duration> {
+        let limit = match stage {
+            PrefaceStage::first_byte => self.first_byte,
+            PrefaceStage::tls_handshake => self.tls_handshake,
+            PrefaceStage::hbone_connect => self.hbone_connect,
+        };
+        (!limit.is_zero()).then_some(limit)
+    }
+
+    /// handshake_limit returns the time to allow for a connection to get through both the first
+    /// byte and TLS handshake stages, or None if either is unbounded.
+    pub fn handshake_limit(&self) -> Option<Duration> {
+        Some(self.limit(PrefaceStage::first_byte)? + self.limit(PrefaceStage::tls_handshake)?)
+    }
+
+    /// run completes a stage, returning None, and counting the timeout, if it takes too long.
+    pub async fn run<F: Future>(&self, stage: PrefaceStage, fut: F) -> Option<F::Output> {
+        let Some(limit) = self.limit(stage) else {
+            return Some(fut.await);
+        };
+        match timeout(limit, fut).await {
+            Ok(res) => Some(res),
+            Err(_) => {
+                self.timed_out(stage);
+                None
+            }
+        }
+    }
+
+    /// timed_out counts a stage that did not complete in time.
+    pub fn timed_out(&self, stage: PrefaceStage) {
+        debug!(%stage, "connection preface timed out");
+        self.metrics
+            .preface_timeouts
+            .get_or_create(&PrefaceTimeoutLabels { stage })
+            .inc();
+    }
+}
+
+// admit_connection counts a newly accepted connection against the connection budget, until the
+// returned permit is dropped. Once the budget is exhausted, connections are shed: reset right away,
+// so clients fail fast rather than pile up on an overloaded proxy.
+pub(super) fn admit_connection<S>(
+    pi: &ProxyInputs,
+    stream: &S,
+) -> Result<Option<BudgetPermit>, Error>
+where
+    for<'a> socket2::SockRef<'a>: From<&'a S>,
+{
+    let Some(budget) = &pi.connection_budget else {
+        return Ok(None);
+    };
+    if let Some(permit) = budget.try_admit() {
+        return Ok(Some(permit));
+    }
+    pi.metrics.connections_shed.inc();
+    let _ = socket2::SockRef::from(stream).set_linger(Some(Duration::ZERO));
+    Err(Error::ConnectionShed)
+}
+
+// check_inbound_port refuses inbound connections to ports the destination workload excludes from
+// capture. Such traffic is meant to bypass ztunnel, so it only arrives here if the redirection
+// layer is out of sync with the workload's configuration.
+pub(super) fn check_inbound_port(
+    pi: &ProxyInputs,
+    workload: &Workload,
+    port: u16,
+) -> Result<(), Error> {
+    if workload.captures_inbound_port(port) {
+        return Ok(());
+    }
+    pi.metrics.connections_excluded_port.inc();
+    Err(Error::ExcludedPort(port))
+}
+
+// track_workload_connection counts an inbound connection against the destination workload's
+// connection limit, if it has one. Locally configured limits take precedence over those from XDS.
+pub(super) fn track_workload_connection(
+    pi: &ProxyInputs,
+    workload: &Workload,
+) -> Result<Option<WorkloadConnectionGuard>, Error> {
+    let name = strng::format!("{}/{}", workload.namespace, workload.name);
+    let Some(limit) = pi
+        .cfg
+        .workload_connection_limits
+        .get(name.as_str())
+        .copied()
+        .or(workload.connection_limit)
+    else {
+        return Ok(None);
+    };
+    match pi
+        .connection_manager
+        .try_track_workload(name.clone(), limit)
+    {
+        Some(guard) => Ok(Some(guard)),
+        None => {
+            pi.metrics.connections_rejected_limit.inc();
+            Err(Error::WorkloadConnectionLimit(name))
+        }
+    }
 }
 
 const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
@@ -608,7 +1199,124 @@ mod tests {
             workload::gatewayaddress::Destination,
         },
     };
-    use std::{collections::HashMap, net::Ipv4Addr, sync::RwLock};
+    use std::{collections::HashMap, net::Ipv4Addr};
+
+    #[tokio::test]
+    async fn read_proxy_protocol_headers() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let src: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dst: SocketAddr = "10.0.0.2:15008".parse().unwrap();
+        let connect = || async {
+            let client = TcpStream::connect(addr);
+            let (client, server) = tokio::join!(client, listener.accept());
+            (client.unwrap(), server.unwrap().0)
+        };
+
+        // v2, with the rest of the stream left intact
+        let (mut client, mut server) = connect().await;
+        write_proxy_protocol(&mut client, (src, dst), None)
+            .await
+            .unwrap();
+        client.write_all(b"hello").await.unwrap();
+        assert_eq!(
+            read_proxy_protocol(&mut server).await.unwrap(),
+            Some((src, dst))
+        );
+        let mut rest = [0; 5];
+        server.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"hello");
+
+        // v1
+        let (mut client, mut server) = connect().await;
+        client
+            .write_all(b"PROXY TCP6 2001:db8::1 2001:db8::2 1234 15008\r\nhello")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_proxy_protocol(&mut server).await.unwrap(),
+            Some((
+                "[2001:db8::1]:1234".parse().unwrap(),
+                "[2001:db8::2]:15008".parse().unwrap()
+            ))
+        );
+        server.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"hello");
+
+        // No address conveyed
+        let (mut client, mut server) = connect().await;
+        client.write_all(b"PROXY UNKNOWN\r\n").await.unwrap();
+        assert_eq!(read_proxy_protocol(&mut server).await.unwrap(), None);
+
+        // Missing header
+        let (mut client, mut server) = connect().await;
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(read_proxy_protocol(&mut server).await.is_err());
+    }
+
+    #[test]
+    fn fixed_tos_overrides_propagated_dscp() {
+        let cfg = config::SocketConfig {
+            mesh: config::SocketPolicy {
+                tos: Some(0x20),
+                ..Default::default()
+            },
+            propagate_dscp: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            upstream_dscp(Some(0xb8), &cfg, SocketClass::App),
+            Some(0xb8)
+        );
+        assert_eq!(upstream_dscp(Some(0xb8), &cfg, SocketClass::Mesh), None);
+        assert_eq!(upstream_dscp(None, &cfg, SocketClass::App), None);
+    }
+
+    #[test]
+    fn traceparent_child() {
+        let parent =
+            TraceParent::try_from("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+                .unwrap();
+        let child = parent.child();
+        assert_eq!(child.to_string(), "0af7651916cd43dd8448eb211c80319c");
+        assert_ne!(child.span_id(), parent.span_id());
+        assert_eq!(parent.span_id(), "b7ad6b7169203331");
+        let header = child.header();
+        let header = header.to_str().unwrap();
+        assert!(header.starts_with("00-0af7651916cd43dd8448eb211c80319c-"));
+        assert!(header.ends_with("-01"));
+        assert_eq!(TraceParent::try_from(header).unwrap(), child);
+
+        let sc = child.span_context();
+        assert_eq!(
+            sc.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_eq!(sc.span_id().to_string(), child.span_id());
+        assert!(sc.is_sampled());
+    }
+
+    #[test]
+    fn connection_id() {
+        let id = ConnectionId::new();
+        let formatted = id.to_string();
+        let segs: Vec<&str> = formatted.split('-').collect();
+        assert_eq!(
+            segs.iter().map(|s| s.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(segs[2].starts_with('4'), "version 4: {formatted}");
+        assert!(
+            matches!(segs[3].chars().next(), Some('8' | '9' | 'a' | 'b')),
+            "RFC 4122 variant: {formatted}"
+        );
+        assert_ne!(id, ConnectionId::new());
+    }
 
     #[tokio::test]
     async fn check_gateway() {
@@ -618,7 +1326,7 @@ mod tests {
         state.workloads.insert(Arc::new(w), true);
         state.services.insert(s);
         let state = state::DemandProxyState::new(
-            Arc::new(RwLock::new(state)),
+            state::cell::ProxyStateCell::new(state),
             None,
             ResolverConfig::default(),
             ResolverOpts::default(),
@@ -682,6 +1390,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            connection_limit: None,
         }
     }
 
@@ -711,6 +1420,7 @@ mod tests {
             native_tunnel: false,
             application_tunnel: None,
             locality: Default::default(),
+            connection_limit: None,
         }
     }
 
@@ -749,9 +1459,49 @@ mod tests {
             subject_alt_names: vec![],
             waypoint: None,
             load_balancer: None,
+            session_affinity: Default::default(),
+            tls_origination: None,
         }
     }
 
+    #[tokio::test]
+    async fn preface_timeouts() {
+        let metrics = crate::test_helpers::helpers::test_proxy_metrics();
+        let cfg = config::Config {
+            inbound_first_byte_timeout: Duration::from_millis(10),
+            inbound_tls_handshake_timeout: Duration::ZERO,
+            ..crate::test_helpers::test_config()
+        };
+        let preface = PrefaceTimeouts::new(&cfg, metrics.clone());
+        let count = |stage| {
+            metrics
+                .preface_timeouts
+                .get_or_create(&PrefaceTimeoutLabels { stage })
+                .get()
+        };
+
+        assert_eq!(
+            preface.run(PrefaceStage::first_byte, async { 1 }).await,
+            Some(1)
+        );
+        assert_eq!(
+            preface
+                .run(PrefaceStage::first_byte, futures::future::pending::<()>())
+                .await,
+            None
+        );
+        assert_eq!(count(PrefaceStage::first_byte), 1);
+
+        // A disabled timeout never fires
+        let slow = tokio::time::sleep(Duration::from_millis(50));
+        assert_eq!(
+            preface.run(PrefaceStage::tls_handshake, slow).await,
+            Some(())
+        );
+        assert_eq!(count(PrefaceStage::tls_handshake), 0);
+        assert_eq!(preface.handshake_limit(), None);
+    }
+
     fn mock_default_gateway_address() -> GatewayAddress {
         GatewayAddress {
             destination: Destination::Address(NetworkAddress {
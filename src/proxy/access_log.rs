@@ -0,0 +1,248 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::{Duration, SystemTime};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::config::AccessLogFormat;
+use crate::proxy::metrics::{ConnectionCloseReason, ErrorCode, ResponseFlags};
+use crate::proxy::ConnectionId;
+
+// Access logs are written to stdout from a dedicated thread, so a slow reader of stdout never blocks
+// the proxy's worker threads. Entries beyond QUEUE_SIZE waiting to be written are dropped.
+const QUEUE_SIZE: usize = 4096;
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+static WRITER: Lazy<SyncSender<String>> = Lazy::new(|| {
+    let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+    std::thread::Builder::new()
+        .name("access-log".to_string())
+        .spawn(move || {
+            while let Ok(line) = rx.recv() {
+                let mut out = std::io::stdout().lock();
+                let _ = writeln!(out, "{line}");
+                // Write whatever else queued up while we waited, under the same lock
+                for line in rx.try_iter() {
+                    let _ = writeln!(out, "{line}");
+                }
+                drop(out);
+                let dropped = DROPPED.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    tracing::warn!(
+                        "dropped {dropped} access log entries; stdout is not keeping up"
+                    );
+                }
+            }
+        })
+        .expect("access log writer thread should start");
+    tx
+});
+
+/// AccessLogEntry holds everything we log about a connection once it completes.
+#[derive(Serialize, Debug, Clone)]
+pub struct AccessLogEntry<'a> {
+    #[serde(serialize_with = "serialize_rfc3339")]
+    pub start_time: SystemTime,
+    pub connection_id: ConnectionId,
+    pub direction: &'static str,
+
+    #[serde(rename = "src.addr")]
+    pub src_addr: SocketAddr,
+    #[serde(rename = "src.workload", skip_serializing_if = "Option::is_none")]
+    pub src_workload: Option<&'a str>,
+    #[serde(rename = "src.namespace", skip_serializing_if = "Option::is_none")]
+    pub src_namespace: Option<&'a str>,
+    #[serde(rename = "src.identity", skip_serializing_if = "Option::is_none")]
+    pub src_identity: Option<String>,
+
+    #[serde(rename = "dst.addr")]
+    pub dst_addr: SocketAddr,
+    #[serde(rename = "dst.hbone_addr", skip_serializing_if = "Option::is_none")]
+    pub dst_hbone_addr: Option<SocketAddr>,
+    #[serde(rename = "dst.service", skip_serializing_if = "Option::is_none")]
+    pub dst_service: Option<&'a str>,
+    #[serde(rename = "dst.workload", skip_serializing_if = "Option::is_none")]
+    pub dst_workload: Option<&'a str>,
+    #[serde(rename = "dst.namespace", skip_serializing_if = "Option::is_none")]
+    pub dst_namespace: Option<&'a str>,
+    #[serde(rename = "dst.identity", skip_serializing_if = "Option::is_none")]
+    pub dst_identity: Option<String>,
+
+    pub bytes_sent: u64,
+    pub bytes_recv: u64,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    #[serde(serialize_with = "serialize_display")]
+    pub response_flags: ResponseFlags,
+    #[serde(rename = "reason", serialize_with = "serialize_display")]
+    pub close_reason: ConnectionCloseReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(
+        rename = "error.code",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_display_opt"
+    )]
+    pub error_code: Option<ErrorCode>,
+}
+
+impl AccessLogEntry<'_> {
+    /// write queues the entry to be written to stdout in the given format, without blocking.
+    /// The default format is handled by the standard logger, so this is a no-op for it.
+    pub fn write(&self, format: AccessLogFormat) {
+        let line = match format {
+            AccessLogFormat::Default => return,
+            AccessLogFormat::Json => match serde_json::to_string(self) {
+                Ok(line) => line,
+                Err(e) => {
+                    tracing::warn!("failed to encode access log: {e}");
+                    return;
+                }
+            },
+            AccessLogFormat::Text => self.to_string(),
+        };
+        if let Err(TrySendError::Full(_)) = WRITER.try_send(line) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The text format mirrors Envoy's default TCP format, with identities added:
+// [START_TIME] DIRECTION RESPONSE_FLAGS BYTES_RECEIVED BYTES_SENT DURATION "DST_SERVICE"
+// SRC_ADDR "SRC_IDENTITY" DST_ADDR "DST_IDENTITY" "ERROR" CLOSE_REASON CONNECTION_ID
+impl fmt::Display for AccessLogEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn or_dash<T: fmt::Display>(v: &Option<T>) -> String {
+            v.as_ref()
+                .map_or_else(|| "-".to_string(), |v| v.to_string())
+        }
+        write!(
+            f,
+            "[{}] {} {} {} {} {}ms \"{}\" {} \"{}\" {} \"{}\" \"{}\" {} {}",
+            rfc3339(self.start_time),
+            self.direction,
+            self.response_flags,
+            self.bytes_recv,
+            self.bytes_sent,
+            self.duration.as_millis(),
+            or_dash(&self.dst_service),
+            self.src_addr,
+            or_dash(&self.src_identity),
+            self.dst_addr,
+            or_dash(&self.dst_identity),
+            or_dash(&self.error),
+            self.close_reason,
+            self.connection_id,
+        )
+    }
+}
+
+fn rfc3339(t: SystemTime) -> String {
+    use chrono::prelude::{DateTime, Utc};
+    let dt: DateTime<Utc> = t.into();
+    dt.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn serialize_rfc3339<S: serde::Serializer>(t: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&rfc3339(*t))
+}
+
+fn serialize_millis<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u64(d.as_millis() as u64)
+}
+
+fn serialize_display<S: serde::Serializer, T: fmt::Display>(
+    t: &T,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    s.collect_str(t)
+}
+
+fn serialize_display_opt<S: serde::Serializer, T: fmt::Display>(
+    t: &Option<T>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match t {
+        Some(t) => s.collect_str(t),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> AccessLogEntry<'static> {
+        AccessLogEntry {
+            start_time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            connection_id: ConnectionId(0x9b2f6c1e_3d4a_4f5b_8c7d_0e1f2a3b4c5d),
+            direction: "inbound",
+            src_addr: "10.0.0.1:34567".parse().unwrap(),
+            src_workload: Some("client"),
+            src_namespace: Some("default"),
+            src_identity: Some("spiffe://cluster.local/ns/default/sa/client".to_string()),
+            dst_addr: "10.0.0.2:8080".parse().unwrap(),
+            dst_hbone_addr: Some("10.0.0.2:8080".parse().unwrap()),
+            dst_service: Some("server.default.svc.cluster.local"),
+            dst_workload: Some("server"),
+            dst_namespace: Some("default"),
+            dst_identity: None,
+            bytes_sent: 100,
+            bytes_recv: 200,
+            duration: Duration::from_millis(12),
+            response_flags: ResponseFlags::AuthorizationPolicyDenied,
+            close_reason: ConnectionCloseReason::error,
+            error: Some("policy rejection".to_string()),
+            error_code: Some(ErrorCode::rbac),
+        }
+    }
+
+    #[test]
+    fn text_format() {
+        assert_eq!(
+            entry().to_string(),
+            "[2023-11-14T22:13:20.000Z] inbound DENY 200 100 12ms \"server.default.svc.cluster.local\" \
+            10.0.0.1:34567 \"spiffe://cluster.local/ns/default/sa/client\" 10.0.0.2:8080 \"-\" \"policy rejection\" error 9b2f6c1e-3d4a-4f5b-8c7d-0e1f2a3b4c5d"
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        let v = serde_json::to_value(entry()).unwrap();
+        assert_eq!(v["start_time"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(v["connection_id"], "9b2f6c1e-3d4a-4f5b-8c7d-0e1f2a3b4c5d");
+        assert_eq!(v["direction"], "inbound");
+        assert_eq!(v["src.addr"], "10.0.0.1:34567");
+        assert_eq!(
+            v["src.identity"],
+            "spiffe://cluster.local/ns/default/sa/client"
+        );
+        assert!(v.get("dst.identity").is_none());
+        assert_eq!(v["bytes_sent"], 100);
+        assert_eq!(v["bytes_recv"], 200);
+        assert_eq!(v["duration_ms"], 12);
+        assert_eq!(v["response_flags"], "DENY");
+        assert_eq!(v["reason"], "error");
+        assert_eq!(v["error"], "policy rejection");
+        assert_eq!(v["error.code"], "rbac");
+    }
+}
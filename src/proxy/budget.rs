@@ -0,0 +1,78 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use crate::proxy::Metrics;
+
+/// ConnectionBudget bounds the number of connections handled at once, across all listeners.
+pub struct ConnectionBudget {
+    permits: Semaphore,
+    metrics: Arc<Metrics>,
+}
+
+impl ConnectionBudget {
+    pub fn new(limit: usize, metrics: Arc<Metrics>) -> Self {
+        metrics.connection_budget_limit.set(limit as i64);
+        ConnectionBudget {
+            permits: Semaphore::new(limit),
+            metrics,
+        }
+    }
+
+    /// try_admit takes a slot in the budget, held until the returned permit is dropped. Returns None
+    /// if the budget is exhausted.
+    pub fn try_admit(self: &Arc<Self>) -> Option<BudgetPermit> {
+        // The permit is returned by hand when the BudgetPermit is dropped, which keeps the
+        // BudgetPermit, carried by every connection future, as small as possible.
+        self.permits.try_acquire().ok()?.forget();
+        self.metrics.connection_budget_used.inc();
+        Some(BudgetPermit(self.clone()))
+    }
+}
+
+/// BudgetPermit counts a connection against the connection budget until dropped.
+pub struct BudgetPermit(Arc<ConnectionBudget>);
+
+impl Drop for BudgetPermit {
+    fn drop(&mut self) {
+        self.0.permits.add_permits(1);
+        self.0.metrics.connection_budget_used.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+
+    #[test]
+    fn budget() {
+        let metrics = test_proxy_metrics();
+        let budget = Arc::new(ConnectionBudget::new(2, metrics.clone()));
+        assert_eq!(metrics.connection_budget_limit.get(), 2);
+
+        let first = budget.try_admit().unwrap();
+        let _second = budget.try_admit().unwrap();
+        assert!(budget.try_admit().is_none());
+        assert_eq!(metrics.connection_budget_used.get(), 2);
+
+        // Closing a connection frees its slot
+        drop(first);
+        assert_eq!(metrics.connection_budget_used.get(), 1);
+        assert!(budget.try_admit().is_some());
+    }
+}
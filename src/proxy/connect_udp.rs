@@ -0,0 +1,338 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for tunneling UDP over HBONE with CONNECT-UDP (RFC 9298).
+//!
+//! A CONNECT-UDP request is an HTTP/2 extended CONNECT (RFC 8441) with the `connect-udp` protocol.
+//! The target is encoded in the request path, and datagrams are carried on the stream as
+//! DATAGRAM capsules (RFC 9297).
+
+use std::future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::trace;
+
+use crate::copy::{BufferedSplitter, ResizeBufRead};
+use crate::proxy::metrics::ConnectionResult;
+use crate::proxy::Error;
+
+pub const PROTOCOL: &str = "connect-udp";
+pub const CAPSULE_PROTOCOL_HEADER: &str = "capsule-protocol";
+pub const CAPSULE_PROTOCOL_ENABLED: &str = "?1";
+
+// Flows are closed after this long without traffic in either direction, unless an idle timeout is
+// configured. Unlike TCP, there is no way to tell when a UDP flow is done.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const PATH_PREFIX: &str = "/.well-known/masque/udp/";
+const DATAGRAM_CAPSULE: u64 = 0x00;
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// target_path returns the CONNECT-UDP request path for the target address.
+pub fn target_path(target: SocketAddr) -> String {
+    let host = match target.ip() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => ip.to_string().replace(':', "%3A"),
+    };
+    format!("{PATH_PREFIX}{host}/{}/", target.port())
+}
+
+/// parse_target_path returns the target address of a CONNECT-UDP request path, if it is valid.
+pub fn parse_target_path(path: &str) -> Option<SocketAddr> {
+    let rest = path.strip_prefix(PATH_PREFIX)?;
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (host, port) = rest.split_once('/')?;
+    let ip = host
+        .replace("%3A", ":")
+        .replace("%3a", ":")
+        .parse::<IpAddr>()
+        .ok()?;
+    Some(SocketAddr::new(ip, port.parse().ok()?))
+}
+
+// encode_varint writes a QUIC variable-length integer (RFC 9000, section 16).
+fn encode_varint(v: u64, buf: &mut BytesMut) {
+    if v < 1 << 6 {
+        buf.put_u8(v as u8);
+    } else if v < 1 << 14 {
+        buf.put_u16(0x4000 | v as u16);
+    } else if v < 1 << 30 {
+        buf.put_u32(0x8000_0000 | v as u32);
+    } else {
+        buf.put_u64(0xc000_0000_0000_0000 | v);
+    }
+}
+
+// decode_varint reads a QUIC variable-length integer, returning it and its encoded length, or
+// None if the buffer is too short.
+fn decode_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let mut v = u64::from(first & 0x3f);
+    for b in &buf[1..len] {
+        v = (v << 8) | u64::from(*b);
+    }
+    Some((v, len))
+}
+
+/// encode_datagram frames a UDP payload as a DATAGRAM capsule with a context ID of zero.
+pub fn encode_datagram(payload: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(payload.len() + 10);
+    encode_varint(DATAGRAM_CAPSULE, &mut buf);
+    // The context ID is part of the capsule payload
+    encode_varint(payload.len() as u64 + 1, &mut buf);
+    encode_varint(0, &mut buf);
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+/// decode_datagram removes the next UDP payload from the buffered capsules, or returns None if
+/// more data is needed. Unknown capsules, and datagrams with a non-zero context ID, are skipped.
+pub fn decode_datagram(buf: &mut BytesMut) -> io::Result<Option<Bytes>> {
+    loop {
+        let Some((kind, kind_len)) = decode_varint(buf) else {
+            return Ok(None);
+        };
+        let Some((len, len_len)) = decode_varint(&buf[kind_len..]) else {
+            return Ok(None);
+        };
+        let header = kind_len + len_len;
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|l| *l <= MAX_DATAGRAM_SIZE + 8)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "capsule too large"))?;
+        if buf.len() < header + len {
+            return Ok(None);
+        }
+        buf.advance(header);
+        let mut capsule = buf.split_to(len).freeze();
+        if kind != DATAGRAM_CAPSULE {
+            trace!(kind, "skipping unknown capsule");
+            continue;
+        }
+        let Some((context, context_len)) = decode_varint(&capsule) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "datagram capsule missing context id",
+            ));
+        };
+        if context != 0 {
+            trace!(context, "skipping datagram with unknown context");
+            continue;
+        }
+        capsule.advance(context_len);
+        return Ok(Some(capsule));
+    }
+}
+
+/// DatagramSocket is one side of a UDP flow, such as a connected UDP socket.
+pub trait DatagramSocket {
+    async fn recv_datagram(&mut self) -> io::Result<Bytes>;
+
+    async fn send_datagram(&mut self, datagram: &[u8]) -> io::Result<()>;
+}
+
+impl DatagramSocket for UdpSocket {
+    async fn recv_datagram(&mut self) -> io::Result<Bytes> {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let len = self.recv(&mut buf).await?;
+        buf.truncate(len);
+        Ok(buf.into())
+    }
+
+    async fn send_datagram(&mut self, datagram: &[u8]) -> io::Result<()> {
+        self.send(datagram).await.map(|_| ())
+    }
+}
+
+/// ClientFlow is the client side of a UDP flow received on a shared listener. Datagrams from the
+/// client are dispatched to the flow over a channel, and replies are sent to the client from
+/// `reply`.
+pub struct ClientFlow {
+    pub datagrams: mpsc::Receiver<Bytes>,
+    pub reply: Arc<UdpSocket>,
+    pub client: SocketAddr,
+}
+
+impl DatagramSocket for ClientFlow {
+    async fn recv_datagram(&mut self) -> io::Result<Bytes> {
+        self.datagrams
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::BrokenPipe.into())
+    }
+
+    async fn send_datagram(&mut self, datagram: &[u8]) -> io::Result<()> {
+        self.reply.send_to(datagram, self.client).await.map(|_| ())
+    }
+}
+
+/// relay_tunnel relays datagrams between a CONNECT-UDP stream and a socket until the stream is
+/// closed, or the flow is idle for `idle_timeout`. `tunnel_downstream` is whether the stream is the
+/// downstream side of the flow, which determines how bytes are reported.
+pub async fn relay_tunnel<S: BufferedSplitter, D: DatagramSocket>(
+    stream: S,
+    socket: &mut D,
+    stats: &ConnectionResult,
+    idle_timeout: Duration,
+    tunnel_downstream: bool,
+) -> Result<(), Error> {
+    let (mut read, mut write) = stream.split_into_buffered_reader();
+    let mut buf = BytesMut::new();
+    loop {
+        tokio::select! {
+            len = future::poll_fn(|cx| {
+                let data = ready!(Pin::new(&mut read).poll_fill_buf(cx))?;
+                buf.extend_from_slice(data);
+                let len = data.len();
+                Pin::new(&mut read).consume(len);
+                Poll::Ready(Ok::<_, io::Error>(len))
+            }) => {
+                if len? == 0 {
+                    trace!("tunnel closed");
+                    return Ok(());
+                }
+                while let Some(datagram) = decode_datagram(&mut buf)? {
+                    record_bytes(stats, datagram.len(), tunnel_downstream);
+                    socket.send_datagram(&datagram).await?;
+                }
+            }
+            datagram = socket.recv_datagram() => {
+                let datagram = datagram?;
+                record_bytes(stats, datagram.len(), !tunnel_downstream);
+                write.write_all(&encode_datagram(&datagram)).await?;
+            }
+            _ = tokio::time::sleep(idle_timeout) => {
+                return Err(Error::IdleTimeout);
+            }
+        }
+    }
+}
+
+/// relay relays datagrams between two sockets until the flow is idle for `idle_timeout`.
+pub async fn relay<A: DatagramSocket, B: DatagramSocket>(
+    downstream: &mut A,
+    upstream: &mut B,
+    stats: &ConnectionResult,
+    idle_timeout: Duration,
+) -> Result<(), Error> {
+    loop {
+        tokio::select! {
+            datagram = downstream.recv_datagram() => {
+                let datagram = datagram?;
+                record_bytes(stats, datagram.len(), true);
+                upstream.send_datagram(&datagram).await?;
+            }
+            datagram = upstream.recv_datagram() => {
+                let datagram = datagram?;
+                record_bytes(stats, datagram.len(), false);
+                downstream.send_datagram(&datagram).await?;
+            }
+            _ = tokio::time::sleep(idle_timeout) => {
+                return Err(Error::IdleTimeout);
+            }
+        }
+    }
+}
+
+// record_bytes reports a datagram relayed from the downstream (`from_downstream`) or upstream side.
+// Matching copy_bidirectional, bytes received from upstream are reported as sent.
+fn record_bytes(stats: &ConnectionResult, len: usize, from_downstream: bool) {
+    if from_downstream {
+        stats.increment_recv(len as u64);
+    } else {
+        stats.increment_send(len as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_paths() {
+        for addr in ["10.0.0.1:53", "[2001:db8::1]:443"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            assert_eq!(parse_target_path(&target_path(addr)), Some(addr));
+        }
+        assert_eq!(
+            target_path("[2001:db8::1]:443".parse().unwrap()),
+            "/.well-known/masque/udp/2001%3Adb8%3A%3A1/443/"
+        );
+        assert_eq!(
+            parse_target_path("/.well-known/masque/udp/10.0.0.1/53"),
+            Some("10.0.0.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_target_path("/.well-known/masque/udp/example.com/53/"),
+            None
+        );
+        assert_eq!(parse_target_path("/.well-known/masque/udp/10.0.0.1/"), None);
+        assert_eq!(parse_target_path("/10.0.0.1/53/"), None);
+    }
+
+    #[test]
+    fn varints() {
+        for v in [
+            0,
+            63,
+            64,
+            16383,
+            16384,
+            (1 << 30) - 1,
+            1 << 30,
+            (1 << 62) - 1,
+        ] {
+            let mut buf = BytesMut::new();
+            encode_varint(v, &mut buf);
+            assert_eq!(decode_varint(&buf), Some((v, buf.len())));
+            assert_eq!(decode_varint(&buf[..buf.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn datagram_capsules() {
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&encode_datagram(b"hello"));
+        // An unknown capsule type is skipped
+        buf.extend_from_slice(&[0x40, 0xff, 2, 0xaa, 0xbb]);
+        // As is a datagram for another context
+        buf.extend_from_slice(&[0x00, 2, 1, 0xcc]);
+        let large = vec![7u8; 1000];
+        buf.extend_from_slice(&encode_datagram(&large));
+
+        assert_eq!(
+            decode_datagram(&mut buf).unwrap(),
+            Some(Bytes::from_static(b"hello"))
+        );
+        // Partial capsules wait for more data
+        let mut partial = buf.split_to(buf.len() - 10);
+        assert_eq!(decode_datagram(&mut partial).unwrap(), None);
+        partial.extend_from_slice(&buf);
+        assert_eq!(decode_datagram(&mut partial).unwrap(), Some(large.into()));
+        assert!(partial.is_empty());
+    }
+}
@@ -12,34 +12,48 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::proxy::Error;
+use crate::identity::Identity;
+use crate::proxy::metrics::{Metrics, RbacDeniedLabels};
+use crate::proxy::{ConnectionId, Error};
 
+use crate::state::events::{self, StateEvent};
+use crate::state::workload::network_addr;
 use crate::state::DemandProxyState;
+use crate::state::EndpointLoad;
 use crate::state::ProxyRbacContext;
+use crate::strng::Strng;
 use drain;
 use serde::{Serialize, Serializer};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
-use tracing::{debug, error, info, warn};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, trace_span, warn, Instrument};
 
 struct ConnectionDrain {
     // TODO: this should almost certainly be changed to a type which has counted references exposed.
     // tokio::sync::watch can be subscribed without taking a write lock and exposes references
     // and also a receiver_count method
     tx: drain::Signal,
-    rx: drain::Watch,
+    rx: ConnectionWatch,
     count: usize,
 }
 
 impl ConnectionDrain {
     fn new() -> Self {
-        let (tx, rx) = drain::channel();
+        let (tx, watch) = drain::channel();
+        let rx = ConnectionWatch {
+            watch,
+            drained: Arc::new(AtomicBool::new(false)),
+        };
         ConnectionDrain { tx, rx, count: 1 }
     }
 
@@ -55,9 +69,18 @@ impl ConnectionDrain {
 #[derive(Clone)]
 pub struct ConnectionManager {
     drains: Arc<RwLock<HashMap<InboundConnection, ConnectionDrain>>>,
-    outbound_connections: Arc<RwLock<HashSet<OutboundConnection>>>,
+    outbound_connections: Arc<RwLock<OutboundConnections>>,
+    // released is notified whenever an inbound connection is no longer tracked
+    released: Arc<Notify>,
+    // live tracks all connections actively being proxied, keyed by a unique id
+    live: Arc<RwLock<HashMap<u64, LiveConnection>>>,
+    // workload_connections counts the connections to each destination workload with a connection limit
+    workload_connections: Arc<Mutex<HashMap<Strng, usize>>>,
 }
 
+// Live connection ids are unique across all connection managers, so they can be reported together.
+static NEXT_LIVE_ID: AtomicU64 = AtomicU64::new(0);
+
 impl std::fmt::Debug for ConnectionManager {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ConnectionManager").finish()
@@ -68,15 +91,104 @@ impl Default for ConnectionManager {
     fn default() -> Self {
         ConnectionManager {
             drains: Arc::new(RwLock::new(HashMap::new())),
-            outbound_connections: Arc::new(RwLock::new(HashSet::new())),
+            outbound_connections: Arc::new(RwLock::new(OutboundConnections::default())),
+            released: Arc::new(Notify::new()),
+            live: Arc::new(RwLock::new(HashMap::new())),
+            workload_connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// ConnectionStats holds the bytes transferred on a single connection.
+/// It is updated by the relay as data is copied, so it can be inspected while the connection is live.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    // sent records the number of bytes sent on this connection
+    pub sent: AtomicU64,
+    // recv records the number of bytes received on this connection
+    pub recv: AtomicU64,
+}
+
+/// LiveConnectionInfo describes a connection actively being proxied.
+#[derive(Debug, Clone)]
+pub struct LiveConnectionInfo {
+    pub connection_id: ConnectionId,
+    pub direction: &'static str,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub hbone_target: Option<SocketAddr>,
+    pub src_identity: Option<String>,
+    pub dst_identity: Option<String>,
+    pub start: Instant,
+}
+
+struct LiveConnection {
+    info: LiveConnectionInfo,
+    stats: Arc<ConnectionStats>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveConnectionDump {
+    pub id: u64,
+    pub connection_id: ConnectionId,
+    pub direction: &'static str,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hbone_target: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_identity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_identity: Option<String>,
+    pub age_seconds: u64,
+    pub bytes_sent: u64,
+    pub bytes_recv: u64,
+}
+
+/// LiveConnectionGuard stops reporting a connection as live once dropped.
+pub struct LiveConnectionGuard {
+    cm: ConnectionManager,
+    id: u64,
+}
+
+impl Drop for LiveConnectionGuard {
+    fn drop(&mut self) {
+        self.cm.live.write().expect("mutex").remove(&self.id);
+    }
+}
+
+/// WorkloadConnectionGuard counts a connection against its destination workload's limit until dropped.
+pub struct WorkloadConnectionGuard {
+    cm: ConnectionManager,
+    workload: Strng,
+}
+
+impl Drop for WorkloadConnectionGuard {
+    fn drop(&mut self) {
+        let mut conns = self.cm.workload_connections.lock().expect("mutex");
+        if let Entry::Occupied(mut e) = conns.entry(self.workload.clone()) {
+            *e.get_mut() -= 1;
+            if *e.get() == 0 {
+                e.remove();
+            }
         }
     }
 }
 
+// ConnectionWatch is signaled when the connection manager closes a connection.
+#[derive(Clone)]
+struct ConnectionWatch {
+    watch: drain::Watch,
+    // drained is set when the connection was closed because its listener's drain timed out,
+    // rather than because policy no longer allows it.
+    drained: Arc<AtomicBool>,
+}
+
 pub struct ConnectionGuard {
     cm: ConnectionManager,
     conn: InboundConnection,
-    watch: Option<drain::Watch>,
+    watch: Option<ConnectionWatch>,
 }
 
 impl ConnectionGuard {
@@ -90,7 +202,13 @@ impl ConnectionGuard {
                 self.cm.release(&self.conn);
                 res
             }
-            _signaled = watch.signaled() => Err(Error::AuthorizationPolicyLateRejection)
+            _signaled = watch.watch.signaled() => {
+                if watch.drained.load(Ordering::Relaxed) {
+                    Err(Error::DrainTimeOut)
+                } else {
+                    Err(Error::AuthorizationPolicyLateRejection)
+                }
+            }
         }
     }
 }
@@ -104,6 +222,14 @@ impl Drop for ConnectionGuard {
     }
 }
 
+// OutboundConnections tracks outbound connections, along with how many there are to each destination
+// address so load balancing can look them up without walking every connection.
+#[derive(Default)]
+struct OutboundConnections {
+    connections: HashSet<OutboundConnection>,
+    per_destination: HashMap<IpAddr, usize>,
+}
+
 pub struct OutboundConnectionGuard {
     cm: ConnectionManager,
     conn: OutboundConnection,
@@ -131,12 +257,23 @@ pub struct InboundConnectionDump {
     pub actual_dst: SocketAddr,
 }
 
+/// InboundListener identifies the listener that accepted an inbound connection.
+#[derive(Debug, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InboundListener {
+    Hbone,
+    Passthrough,
+}
+
 #[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InboundConnection {
     #[serde(flatten)]
     pub ctx: ProxyRbacContext,
     pub dest_service: Option<String>,
+    // dest_identity is the identity of the destination workload when the connection was accepted.
+    pub dest_identity: Option<Identity>,
+    pub listener: InboundListener,
 }
 
 impl ConnectionManager {
@@ -152,10 +289,11 @@ impl ConnectionManager {
             actual_dst,
         };
 
-        self.outbound_connections
-            .write()
-            .expect("mutex")
-            .insert(c.clone());
+        let mut outbound = self.outbound_connections.write().expect("mutex");
+        if outbound.connections.insert(c.clone()) {
+            *outbound.per_destination.entry(actual_dst.ip()).or_default() += 1;
+        }
+        drop(outbound);
 
         OutboundConnectionGuard {
             cm: self.clone(),
@@ -166,23 +304,57 @@ impl ConnectionManager {
     pub async fn assert_rbac(
         &self,
         state: &DemandProxyState,
+        metrics: &Metrics,
         ctx: &ProxyRbacContext,
         dest_service: Option<String>,
+        listener: InboundListener,
     ) -> Result<ConnectionGuard, Error> {
+        let dest_identity = state
+            .fetch_workload(&network_addr(
+                ctx.conn.dst_network.clone(),
+                ctx.conn.dst.ip(),
+            ))
+            .await
+            .map(|wl| wl.identity());
         // Register before our initial assert. This prevents a race if policy changes between assert() and
         // track()
         let conn = InboundConnection {
             ctx: ctx.clone(),
             dest_service,
+            dest_identity,
+            listener,
         };
         let Some(watch) = self.register(&conn) else {
             warn!("failed to track {conn:?}");
             debug_assert!(false, "failed to track {conn:?}");
-            return Err(Error::AuthorizationPolicyRejection);
+            return Err(Error::AuthorizationPolicyRejection(
+                "connection could not be tracked".to_string(),
+            ));
         };
-        if !state.assert_rbac(ctx).await {
+        let decision = state
+            .evaluate_rbac(ctx)
+            .instrument(trace_span!("rbac"))
+            .await;
+        if decision.shadow_denied {
+            metrics.rbac_shadow_denied.inc();
+            info!("connection {ctx} would be denied by dry-run authorization policies");
+        }
+        if !decision.allowed {
             self.release(&conn);
-            return Err(Error::AuthorizationPolicyRejection);
+            let labels = RbacDeniedLabels {
+                policy: decision
+                    .policy
+                    .as_ref()
+                    .map(|p| p.policy.clone())
+                    .unwrap_or_default()
+                    .into(),
+            };
+            metrics.rbac_denied.get_or_create(&labels).inc();
+            let reason = match decision.policy {
+                Some(p) => format!("denied by policy {p}"),
+                None => "not allowed by any policy".to_string(),
+            };
+            return Err(Error::AuthorizationPolicyRejection(reason));
         }
         Ok(ConnectionGuard {
             cm: self.clone(),
@@ -194,7 +366,7 @@ impl ConnectionManager {
     // this must be done before a connection can be tracked
     // allows policy to be asserted against the connection
     // even no tasks have a receiver channel yet
-    fn register(&self, c: &InboundConnection) -> Option<drain::Watch> {
+    fn register(&self, c: &InboundConnection) -> Option<ConnectionWatch> {
         match self.drains.write().expect("mutex").entry(c.clone()) {
             Entry::Occupied(mut cd) => {
                 cd.get_mut().count += 1;
@@ -221,10 +393,21 @@ impl ConnectionManager {
                 drains.insert(k, v);
             }
         }
+        drop(drains);
+        self.released.notify_waiters();
     }
 
     fn release_outbound(&self, c: &OutboundConnection) {
-        self.outbound_connections.write().expect("mutex").remove(c);
+        let mut outbound = self.outbound_connections.write().expect("mutex");
+        if !outbound.connections.remove(c) {
+            return;
+        }
+        if let Entry::Occupied(mut e) = outbound.per_destination.entry(c.actual_dst.ip()) {
+            *e.get_mut() -= 1;
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
     }
 
     // signal all connections listening to this channel to take action (typically terminate traffic)
@@ -236,6 +419,113 @@ impl ConnectionManager {
             // this is bad, possibly drain called twice
             error!("requested drain on a Connection which wasn't initialized");
         }
+        self.released.notify_waiters();
+    }
+
+    // wait_for_drain resolves once there are no longer any tracked inbound connections accepted by
+    // the given listener.
+    pub async fn wait_for_drain(&self, listener: InboundListener) {
+        loop {
+            // Register for notification before checking, so we cannot miss a release in between.
+            let released = self.released.notified();
+            if !self
+                .drains
+                .read()
+                .expect("mutex")
+                .keys()
+                .any(|c| c.listener == listener)
+            {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    // close_all forcibly closes all tracked inbound connections accepted by the given listener,
+    // returning how many were closed.
+    // This is intended to be used once that listener's drain has exceeded its deadline.
+    pub async fn close_all(&self, listener: InboundListener) -> u64 {
+        let drains: Vec<_> = {
+            let mut drains = self.drains.write().expect("mutex");
+            let keys: Vec<_> = drains
+                .keys()
+                .filter(|c| c.listener == listener)
+                .cloned()
+                .collect();
+            keys.iter().filter_map(|c| drains.remove(c)).collect()
+        };
+        let closed = drains.iter().map(|cd| cd.count as u64).sum();
+        for cd in &drains {
+            cd.rx.drained.store(true, Ordering::Relaxed);
+        }
+        futures::future::join_all(drains.into_iter().map(|cd| cd.drain())).await;
+        self.released.notify_waiters();
+        closed
+    }
+
+    // track_live reports a connection, and its transfer stats, as live until the returned guard is dropped.
+    pub fn track_live(
+        &self,
+        info: LiveConnectionInfo,
+        stats: Arc<ConnectionStats>,
+    ) -> LiveConnectionGuard {
+        let id = NEXT_LIVE_ID.fetch_add(1, Ordering::Relaxed);
+        self.live
+            .write()
+            .expect("mutex")
+            .insert(id, LiveConnection { info, stats });
+        LiveConnectionGuard {
+            cm: self.clone(),
+            id,
+        }
+    }
+
+    // get a snapshot of all live connections, ordered from oldest to newest
+    pub fn live_connection_count(&self) -> usize {
+        self.live.read().expect("mutex").len()
+    }
+
+    pub fn live_connections(&self) -> Vec<LiveConnectionDump> {
+        let mut dump: Vec<_> = self
+            .live
+            .read()
+            .expect("mutex")
+            .iter()
+            .map(|(id, c)| LiveConnectionDump {
+                id: *id,
+                connection_id: c.info.connection_id,
+                direction: c.info.direction,
+                src: c.info.src,
+                dst: c.info.dst,
+                hbone_target: c.info.hbone_target,
+                src_identity: c.info.src_identity.clone(),
+                dst_identity: c.info.dst_identity.clone(),
+                age_seconds: c.info.start.elapsed().as_secs(),
+                bytes_sent: c.stats.sent.load(Ordering::Relaxed),
+                bytes_recv: c.stats.recv.load(Ordering::Relaxed),
+            })
+            .collect();
+        dump.sort_by_key(|c| c.id);
+        dump
+    }
+
+    // try_track_workload counts a connection against the limit of the given workload.
+    // Returns None if the workload already has `limit` connections.
+    pub fn try_track_workload(
+        &self,
+        workload: Strng,
+        limit: usize,
+    ) -> Option<WorkloadConnectionGuard> {
+        let mut conns = self.workload_connections.lock().expect("mutex");
+        let count = conns.entry(workload.clone()).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(WorkloadConnectionGuard {
+            cm: self.clone(),
+            workload,
+        })
     }
 
     //  get a list of all connections being tracked
@@ -245,6 +535,18 @@ impl ConnectionManager {
     }
 }
 
+impl EndpointLoad for ConnectionManager {
+    fn active_connections(&self, ip: IpAddr) -> usize {
+        self.outbound_connections
+            .read()
+            .expect("mutex")
+            .per_destination
+            .get(&ip)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
 #[derive(serde::Serialize)]
 struct ConnectionManagerDump {
     inbound: Vec<InboundConnectionDump>,
@@ -272,6 +574,7 @@ impl Serialize for ConnectionManager {
             .outbound_connections
             .read()
             .expect("mutex")
+            .connections
             .iter()
             .cloned()
             .collect();
@@ -300,40 +603,157 @@ impl PolicyWatcher {
     }
 
     pub async fn run(self) {
-        let mut policies_changed = self.state.read().policies.subscribe();
+        let (mut events, mut identities_changed) = {
+            let state = self.state.read();
+            (
+                state.events.subscribe(),
+                state.workloads.subscribe_identity_changes(),
+            )
+        };
         loop {
             tokio::select! {
                 _ = self.stop.clone().signaled() => {
                     break;
                 }
-                _ = policies_changed.changed() => {
-                    let connections = self.connection_manager.connections();
-                    for conn in connections {
-                        if !self.state.assert_rbac(&conn.ctx).await {
-                            self.connection_manager.close(&conn).await;
-                            info!("connection {} closed because it's no longer allowed after a policy update", conn.ctx);
-                        }
+                event = events.recv() => match event {
+                    // If we missed events, some of them may have been policy changes.
+                    Ok(StateEvent::PolicyChanged(_)) | Err(RecvError::Lagged(_)) => {
+                        // XDS changes policies in batches; re-evaluate once for the whole batch.
+                        events::drain(&mut events);
+                        self.reevaluate("a policy update").await;
                     }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                // A workload's identity determines which policies apply to it, and must match the
+                // identity we accepted connections for, so re-evaluate when it changes.
+                _ = identities_changed.changed() => {
+                    self.reevaluate("a workload identity change").await;
                 }
             }
         }
     }
+
+    async fn reevaluate(&self, cause: &str) {
+        let connections = self.connection_manager.connections();
+        for conn in connections {
+            // The connection was accepted for a specific identity; if the destination no longer
+            // has it, the connection must not outlive the change, whatever policy says.
+            let dst = network_addr(conn.ctx.conn.dst_network.clone(), conn.ctx.conn.dst.ip());
+            let identity = self
+                .state
+                .fetch_workload(&dst)
+                .await
+                .map(|wl| wl.identity());
+            if identity != conn.dest_identity {
+                self.connection_manager.close(&conn).await;
+                info!(
+                    "connection {} closed because the destination identity changed after {cause}",
+                    conn.ctx
+                );
+                continue;
+            }
+            if !self.state.assert_rbac(&conn.ctx).await {
+                self.connection_manager.close(&conn).await;
+                info!(
+                    "connection {} closed because it's no longer allowed after {cause}",
+                    conn.ctx
+                );
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use drain::Watch;
     use hickory_resolver::config::{ResolverConfig, ResolverOpts};
     use std::net::{Ipv4Addr, SocketAddrV4};
-    use std::sync::{Arc, RwLock};
+    use std::sync::Arc;
     use std::time::Duration;
 
+    use crate::proxy::{ConnectionId, Error};
     use crate::rbac::Connection;
-    use crate::state::{DemandProxyState, ProxyState};
+    use crate::state::cell::ProxyStateCell;
+    use crate::state::workload::Workload;
+    use crate::state::{DemandProxyState, ProxyState, WorkloadInfo};
+    use crate::test_helpers;
     use crate::xds::istio::security::{Action, Authorization, Scope};
     use crate::xds::ProxyStateUpdateMutator;
 
-    use super::{ConnectionGuard, ConnectionManager, InboundConnection, PolicyWatcher};
+    use super::{
+        ConnectionGuard, ConnectionManager, ConnectionStats, ConnectionWatch, InboundConnection,
+        InboundListener, LiveConnectionInfo, PolicyWatcher,
+    };
+
+    #[test]
+    fn test_connection_manager_live() {
+        let cm = ConnectionManager::default();
+        assert!(cm.live_connections().is_empty());
+
+        let info = |port: u16| LiveConnectionInfo {
+            connection_id: ConnectionId::new(),
+            direction: "inbound",
+            src: std::net::SocketAddr::new(
+                std::net::IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+                port,
+            ),
+            dst: std::net::SocketAddr::new(
+                std::net::IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)),
+                8080,
+            ),
+            hbone_target: None,
+            src_identity: Some("spiffe://cluster.local/ns/default/sa/src".to_string()),
+            dst_identity: None,
+            start: std::time::Instant::now(),
+        };
+        let stats1 = Arc::new(ConnectionStats::default());
+        let guard1 = cm.track_live(info(1000), stats1.clone());
+        let stats2 = Arc::new(ConnectionStats::default());
+        let guard2 = cm.track_live(info(1001), stats2.clone());
+
+        // stats are reflected while the connection is live
+        stats1
+            .sent
+            .fetch_add(10, std::sync::atomic::Ordering::Relaxed);
+        stats1
+            .recv
+            .fetch_add(20, std::sync::atomic::Ordering::Relaxed);
+        let live = cm.live_connections();
+        assert_eq!(live.len(), 2);
+        assert_ne!(live[0].connection_id, live[1].connection_id);
+        assert_eq!(live[0].src.port(), 1000);
+        assert_eq!((live[0].bytes_sent, live[0].bytes_recv), (10, 20));
+        assert_eq!(live[1].src.port(), 1001);
+        assert_eq!((live[1].bytes_sent, live[1].bytes_recv), (0, 0));
+
+        // dropping the guard stops reporting the connection
+        drop(guard1);
+        let live = cm.live_connections();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].src.port(), 1001);
+        drop(guard2);
+        assert!(cm.live_connections().is_empty());
+    }
+
+    #[test]
+    fn test_connection_manager_workload_limit() {
+        let cm = ConnectionManager::default();
+        let wl = crate::strng::new("default/web");
+
+        let g1 = cm.try_track_workload(wl.clone(), 2).expect("under limit");
+        let g2 = cm.try_track_workload(wl.clone(), 2).expect("under limit");
+        assert!(cm.try_track_workload(wl.clone(), 2).is_none());
+        // limits are tracked per workload
+        let other = cm
+            .try_track_workload(crate::strng::new("default/other"), 1)
+            .expect("under limit");
+
+        // releasing a connection frees up a slot
+        drop(g1);
+        let g3 = cm.try_track_workload(wl.clone(), 2).expect("under limit");
+        drop((g2, g3, other));
+        assert!(cm.workload_connections.lock().unwrap().is_empty());
+    }
 
     #[tokio::test]
     async fn test_connection_manager_close() {
@@ -373,6 +793,8 @@ mod tests {
                 dest_workload_info: None,
             },
             dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Hbone,
         };
 
         // ensure drains contains exactly 1 item
@@ -407,6 +829,8 @@ mod tests {
                 dest_workload_info: None,
             },
             dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Hbone,
         };
 
         let mut close2 = register(&cm, &rbac_ctx2);
@@ -474,6 +898,8 @@ mod tests {
                 dest_workload_info: None,
             },
             dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Hbone,
         };
 
         // create a second connection
@@ -494,6 +920,8 @@ mod tests {
                 dest_workload_info: None,
             },
             dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Hbone,
         };
         let another_conn1 = conn1.clone();
 
@@ -547,7 +975,7 @@ mod tests {
     #[tokio::test]
     async fn test_policy_watcher_lifecycle() {
         // preamble: setup an environment
-        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let dstate = DemandProxyState::new(
             state.clone(),
             None,
@@ -586,6 +1014,8 @@ mod tests {
                 dest_workload_info: None,
             },
             dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Hbone,
         };
         // watch the connection
         let close1 = connection_manager
@@ -599,29 +1029,231 @@ mod tests {
             scope: Scope::Global as i32,
             namespace: "default".to_string(),
             rules: vec![],
+            dry_run: false,
         };
 
         // spawn an assertion that our connection close is received
-        tokio::spawn(assert_close(close1));
+        let closed = tokio::spawn(assert_close(close1));
 
         // this block will scope our guard appropriately
         {
             // update our state
-            let mut s = state
-                .write()
-                .expect("test fails if we're unable to get a write lock on state");
+            let mut s = state.write();
             let res = state_mutator.insert_authorization(&mut s, auth);
             // assert that the update was OK
             assert!(res.is_ok());
-        } // release lock
+        } // publish the update
+
+        // the policy change is published to the watcher, which closes the connection
+        closed.await.unwrap();
 
         // send the signal which stops policy watcher
         tx.drain().await;
     }
 
+    #[tokio::test]
+    async fn test_policy_watcher_identity_change() {
+        let wl = Workload {
+            name: "test".into(),
+            namespace: "default".into(),
+            service_account: "default".into(),
+            workload_ips: vec![Ipv4Addr::new(192, 168, 0, 2).into()],
+            ..test_helpers::test_default_workload()
+        };
+        let mut ps = ProxyState::default();
+        ps.workloads.insert(Arc::new(wl.clone()), true);
+        let state = ProxyStateCell::new(ps);
+        let dstate = DemandProxyState::new(
+            state.clone(),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        let connection_manager = ConnectionManager::default();
+        let (tx, stop) = drain::channel();
+        let pw = PolicyWatcher::new(dstate, stop, connection_manager.clone());
+        tokio::spawn(pw.run());
+
+        let conn = InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src_identity: None,
+                    src: std::net::SocketAddr::new(
+                        std::net::Ipv4Addr::new(192, 168, 0, 1).into(),
+                        80,
+                    ),
+                    dst_network: "".into(),
+                    dst: std::net::SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::new(192, 168, 0, 2),
+                        8080,
+                    )),
+                },
+                dest_workload_info: Some(Arc::new(WorkloadInfo {
+                    name: "test".into(),
+                    namespace: "default".into(),
+                    trust_domain: "cluster.local".into(),
+                    service_account: "default".into(),
+                })),
+            },
+            dest_service: None,
+            dest_identity: Some(wl.identity()),
+            listener: InboundListener::Hbone,
+        };
+        let close = connection_manager
+            .register(&conn)
+            .expect("should not be None");
+        // In shared mode there is no proxy workload info; the accepted identity must still be enforced
+        let shared_conn = InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src: std::net::SocketAddr::new(
+                        std::net::Ipv4Addr::new(192, 168, 0, 1).into(),
+                        81,
+                    ),
+                    ..conn.ctx.conn.clone()
+                },
+                dest_workload_info: None,
+            },
+            ..conn.clone()
+        };
+        let shared_close = connection_manager
+            .register(&shared_conn)
+            .expect("should not be None");
+
+        // Updates that keep the identity don't affect the connection
+        state.write().workloads.insert(Arc::new(wl.clone()), true);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), close.watch.clone().signaled())
+                .await
+                .is_err()
+        );
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            shared_close.watch.clone().signaled()
+        )
+        .await
+        .is_err());
+
+        // Changing the service account closes both
+        state.write().workloads.insert(
+            Arc::new(Workload {
+                service_account: "other".into(),
+                ..wl
+            }),
+            true,
+        );
+        assert_close(close).await;
+        assert_close(shared_close).await;
+        assert_eq!(connection_manager.connections().len(), 0);
+
+        tx.drain().await;
+    }
+
+    #[tokio::test]
+    async fn test_connection_manager_drain() {
+        let cm = ConnectionManager::default();
+        // nothing tracked, so drain completes immediately
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            cm.wait_for_drain(InboundListener::Passthrough),
+        )
+        .await
+        .expect("drain should complete with no connections");
+
+        let conn = InboundConnection {
+            ctx: crate::state::ProxyRbacContext {
+                conn: Connection {
+                    src_identity: None,
+                    src: std::net::SocketAddr::new(
+                        std::net::Ipv4Addr::new(192, 168, 0, 1).into(),
+                        80,
+                    ),
+                    dst_network: "".into(),
+                    dst: std::net::SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::new(192, 168, 0, 2),
+                        8080,
+                    )),
+                },
+                dest_workload_info: None,
+            },
+            dest_service: None,
+            dest_identity: None,
+            listener: InboundListener::Passthrough,
+        };
+        let watch = cm.register(&conn).unwrap();
+        let guard = ConnectionGuard {
+            cm: cm.clone(),
+            conn: conn.clone(),
+            watch: Some(watch),
+        };
+
+        // connections accepted by other listeners don't take part in the drain
+        let hbone_conn = InboundConnection {
+            listener: InboundListener::Hbone,
+            ..conn.clone()
+        };
+        let hbone_guard = ConnectionGuard {
+            cm: cm.clone(),
+            conn: hbone_conn.clone(),
+            watch: Some(cm.register(&hbone_conn).unwrap()),
+        };
+
+        // a tracked connection blocks the drain
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            cm.wait_for_drain(InboundListener::Passthrough)
+        )
+        .await
+        .is_err());
+
+        // once released, the drain completes
+        let waiter = tokio::spawn({
+            let cm = cm.clone();
+            async move { cm.wait_for_drain(InboundListener::Passthrough).await }
+        });
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("drain should complete once connection is released")
+            .unwrap();
+
+        // forcibly closed connections are reported with a drain error
+        let watch = cm.register(&conn).unwrap();
+        let guard = ConnectionGuard {
+            cm: cm.clone(),
+            conn: conn.clone(),
+            watch: Some(watch),
+        };
+        let handle =
+            tokio::spawn(guard.handle_connection(std::future::pending::<Result<(), Error>>()));
+        // give the connection a chance to start waiting on the watch
+        tokio::task::yield_now().await;
+        assert_eq!(cm.close_all(InboundListener::Passthrough).await, 1);
+        let res = tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(res, Err(Error::DrainTimeOut)));
+
+        // connections accepted by other listeners are left alone, and a later close is still
+        // reported as a policy rejection
+        assert_eq!(cm.connections(), vec![hbone_conn.clone()]);
+        let handle = tokio::spawn(
+            hbone_guard.handle_connection(std::future::pending::<Result<(), Error>>()),
+        );
+        tokio::task::yield_now().await;
+        cm.close(&hbone_conn).await;
+        let res = tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(res, Err(Error::AuthorizationPolicyLateRejection)));
+        assert_eq!(cm.connections().len(), 0);
+    }
+
     // small helper to assert that the Watches are working in a timely manner
-    async fn assert_close(c: Watch) {
-        let result = tokio::time::timeout(Duration::from_secs(1), c.signaled()).await;
+    async fn assert_close(c: ConnectionWatch) {
+        let result = tokio::time::timeout(Duration::from_secs(1), c.watch.signaled()).await;
         assert!(result.is_ok())
     }
 }
@@ -0,0 +1,303 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use drain::Watch;
+use http::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, info_span, Instrument};
+
+use crate::proxy::metrics::Reporter;
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::{metrics, util, ConnectionId, Error, ProxyInputs, TraceParent};
+use crate::{socket, strng, telemetry};
+
+// How long a client may take to send its CONNECT request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The largest CONNECT request head we accept, including headers.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+const ESTABLISHED_RESPONSE: &[u8] = b"HTTP/1.1 200 Connection established\r\n\r\n";
+
+/// ForwardProxy accepts HTTP CONNECT requests from applications configured to use it as their
+/// HTTP_PROXY, and tunnels them to the requested destination like captured outbound traffic. This
+/// lets clients without traffic redirection, such as VMs, enter the mesh.
+pub(super) struct ForwardProxy {
+    pi: ProxyInputs,
+    listener: TcpListener,
+    drain: Watch,
+}
+
+impl ForwardProxy {
+    pub(super) async fn new(pi: ProxyInputs, drain: Watch) -> Result<ForwardProxy, Error> {
+        let addr = pi
+            .cfg
+            .forward_proxy_addr
+            .expect("forward proxy address configured");
+        let listener: TcpListener = pi
+            .socket_factory
+            .tcp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
+
+        info!(
+            address=%listener.local_addr().expect("local_addr available"),
+            component="forward proxy",
+            "listener established",
+        );
+
+        Ok(ForwardProxy {
+            pi,
+            listener,
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.listener.local_addr().expect("local_addr available")
+    }
+
+    pub(super) async fn run(self) {
+        let inner_drain = self.drain.clone();
+        let inpod = self.pi.cfg.inpod_enabled;
+        let pi = Arc::new(self.pi);
+        let listener = self.listener;
+        let pool = crate::proxy::pool::WorkloadHBONEPool::new(
+            pi.cfg.clone(),
+            pi.socket_factory.clone(),
+            pi.cert_manager.clone(),
+            pi.metrics.clone(),
+        );
+        let accept = async move {
+            loop {
+                super::throttle_accept(&pi).await;
+                let socket = listener.accept().await;
+                match socket {
+                    Ok((stream, remote)) => {
+                        let Ok(permit) = super::admit_connection(&pi, &stream) else {
+                            continue;
+                        };
+                        let oc = OutboundConnection {
+                            pi: pi.clone(),
+                            id: TraceParent::new(),
+                            connection_id: ConnectionId::new(),
+                            pool: pool.clone(),
+                        };
+                        let span =
+                            info_span!("forward proxy", id=%oc.id, connection_id=%oc.connection_id);
+                        telemetry::set_trace_context(&span, &oc.id, None);
+                        // For inpod, connections must terminate when we drain - the workload is gone.
+                        let drain = inpod.then(|| inner_drain.clone());
+                        tokio::spawn(
+                            async move {
+                                let _permit = permit;
+                                handle(oc, stream, socket::to_canonical(remote), drain).await
+                            }
+                            .instrument(span),
+                        );
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("Failed TCP handshake {}", e);
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!("forward proxy drained");
+            }
+        }
+    }
+}
+
+// handle serves a single CONNECT request, then tunnels the rest of the connection to its target.
+async fn handle(
+    mut oc: OutboundConnection,
+    mut stream: TcpStream,
+    remote_addr: SocketAddr,
+    out_drain: Option<Watch>,
+) {
+    let local_addr = socket::to_canonical(stream.local_addr().expect("local_addr available"));
+    let target = match negotiate(&oc.pi, &mut stream).await {
+        Ok(target) => target,
+        Err((status, err)) => {
+            let _ = stream.write_all(error_response(status).as_bytes()).await;
+            metrics::log_early_deny(
+                &oc.pi.metrics,
+                remote_addr,
+                local_addr,
+                Reporter::source,
+                err,
+            );
+            return;
+        }
+    };
+    if let Err(e) = stream.write_all(ESTABLISHED_RESPONSE).await {
+        debug!("failed to respond to CONNECT: {e}");
+        return;
+    }
+    super::set_socket_options(&stream, &oc.pi.cfg.socket_config, super::SocketClass::App);
+
+    info!("accepted connection from {remote_addr} to {target}");
+    oc.proxy_to_cancellable(stream, remote_addr, target, true, out_drain)
+        .await;
+}
+
+// negotiate reads the CONNECT request and resolves its target. On failure, the status to respond
+// with is returned alongside the error.
+async fn negotiate(
+    pi: &ProxyInputs,
+    stream: &mut TcpStream,
+) -> Result<SocketAddr, (StatusCode, Error)> {
+    let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(stream)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(e)) => return Err((StatusCode::BAD_REQUEST, Error::Io(e))),
+        Err(_) => {
+            return Err((
+                StatusCode::REQUEST_TIMEOUT,
+                Error::Io(io::ErrorKind::TimedOut.into()),
+            ))
+        }
+    };
+    let (host, port) = parse_connect(&head)?;
+    let ip = match host.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => pi
+            .state
+            .find_hostname_ip(&pi.cfg.network, &strng::new(host))
+            .ok_or_else(|| {
+                (
+                    StatusCode::BAD_GATEWAY,
+                    Error::UnknownHostname(strng::new(host)),
+                )
+            })?,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+// read_request_head reads up to and including the blank line ending the request head. Exactly the
+// head is read, so any bytes the client sends ahead of our response are left to be tunneled.
+async fn read_request_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::with_capacity(256);
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too long",
+            ));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    String::from_utf8(head).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// parse_connect parses the request line of a CONNECT request, returning the host and port of its
+// authority. Headers are ignored.
+fn parse_connect(head: &str) -> Result<(&str, u16), (StatusCode, Error)> {
+    let line = head.lines().next().unwrap_or_default();
+    let mut parts = line.split(' ');
+    let (Some(method), Some(authority), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Error::ConnectAddress(line.to_string()),
+        ));
+    };
+    if method != "CONNECT" {
+        return Err((
+            StatusCode::METHOD_NOT_ALLOWED,
+            Error::NonConnectMethod(method.to_string()),
+        ));
+    }
+    if !version.starts_with("HTTP/1.") {
+        return Err((
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Error::UnsupportedFeature(version.to_string()),
+        ));
+    }
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Error::ConnectAddress(authority.to_string()),
+        )
+    };
+    let (host, port) = authority.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    // IPv6 addresses are bracketed
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host, port))
+}
+
+fn error_response(status: StatusCode) -> String {
+    format!(
+        "HTTP/1.1 {} {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_target() {
+        let parse = |line: &str| parse_connect(&format!("{line}\r\nHost: ignored\r\n\r\n"));
+        assert_eq!(
+            parse("CONNECT reviews.default.svc.cluster.local:9080 HTTP/1.1").unwrap(),
+            ("reviews.default.svc.cluster.local", 9080)
+        );
+        assert_eq!(
+            parse("CONNECT 10.0.0.1:443 HTTP/1.0").unwrap(),
+            ("10.0.0.1", 443)
+        );
+        assert_eq!(
+            parse("CONNECT [2001:db8::1]:443 HTTP/1.1").unwrap(),
+            ("2001:db8::1", 443)
+        );
+
+        let status = |line: &str| parse(line).unwrap_err().0;
+        assert_eq!(
+            status("GET http://example.com/ HTTP/1.1"),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status("CONNECT example.com:443 HTTP/2"),
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED
+        );
+        assert_eq!(
+            status("CONNECT example.com HTTP/1.1"),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status("CONNECT :443 HTTP/1.1"), StatusCode::BAD_REQUEST);
+        assert_eq!(status("CONNECT"), StatusCode::BAD_REQUEST);
+    }
+}
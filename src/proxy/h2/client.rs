@@ -110,10 +110,13 @@ impl H2ConnectClient {
     }
 }
 
-pub async fn spawn_connection(
+// spawn_connection establishes a new HTTP/2 connection over the provided stream.
+// The provided guard is held until the connection is closed.
+pub async fn spawn_connection<G: Send + 'static>(
     cfg: Arc<config::Config>,
     s: TlsStream<TcpStream>,
     driver_drain: Receiver<bool>,
+    guard: G,
 ) -> Result<H2ConnectClient, Error> {
     let mut builder = h2::client::Builder::new();
     builder
@@ -143,6 +146,7 @@ pub async fn spawn_connection(
     // it is important to have a drain here, or this connection will never terminate
     tokio::spawn(async move {
         drive_connection(connection, driver_drain).await;
+        drop(guard);
     });
 
     let c = H2ConnectClient {
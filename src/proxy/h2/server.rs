@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use crate::config;
-use crate::proxy::Error;
+use crate::proxy::{Error, PrefaceStage, PrefaceTimeouts};
 use futures_util::FutureExt;
 use http::request::Parts;
 use http::Response;
@@ -47,11 +47,24 @@ impl H2Request {
         &self.request.headers
     }
 
+    /// The protocol of an extended CONNECT request (RFC 8441), if any
+    pub fn protocol(&self) -> Option<&str> {
+        self.request
+            .extensions
+            .get::<h2::ext::Protocol>()
+            .map(|p| p.as_str())
+    }
+
     pub fn send_error(mut self, resp: Response<()>) -> Result<(), Error> {
         let _ = self.send.send_response(resp, true)?;
         Ok(())
     }
 
+    /// Reset the stream without sending a response
+    pub fn send_reset(mut self, reason: h2::Reason) {
+        self.send.send_reset(reason)
+    }
+
     pub async fn send_response(
         self,
         resp: Response<()>,
@@ -76,10 +89,13 @@ impl H2Request {
     }
 }
 
+// serve_connection serves the HBONE requests of an inbound connection. The client must complete the
+// HTTP/2 handshake and send its first request within the preface's HBONE connect timeout.
 pub async fn serve_connection<F, Fut>(
     cfg: Arc<config::Config>,
     s: tokio_rustls::server::TlsStream<TcpStream>,
     drain: drain::Watch,
+    preface: PrefaceTimeouts,
     handler: F,
 ) -> Result<(), Error>
 where
@@ -87,8 +103,16 @@ where
     Fut: Future + Send + 'static,
 {
     let mut builder = h2::server::Builder::new();
+    if cfg.enable_connect_udp {
+        // Required to accept CONNECT-UDP requests
+        builder.enable_connect_protocol();
+    }
     let drain_deadline = cfg.self_termination_deadline;
-    let mut conn = builder
+    // Cleared once the first request arrives
+    let mut connect_timer = preface
+        .limit(PrefaceStage::hbone_connect)
+        .map(|limit| Box::pin(tokio::time::sleep(limit)));
+    let handshake = builder
         .initial_window_size(cfg.window_size)
         .initial_connection_window_size(cfg.connection_window_size)
         .max_frame_size(cfg.frame_size)
@@ -99,8 +123,17 @@ where
         .max_send_buffer_size(1024 * 400)
         // default from hyper
         .max_concurrent_streams(200)
-        .handshake(s)
-        .await?;
+        .handshake(s);
+    let mut conn = match connect_timer.as_mut() {
+        Some(timer) => tokio::select! {
+            conn = handshake => conn?,
+            _ = timer => {
+                preface.timed_out(PrefaceStage::hbone_connect);
+                return Err(Error::PrefaceTimeout(PrefaceStage::hbone_connect));
+            }
+        },
+        None => handshake.await?,
+    };
 
     let ping_pong = conn
         .ping_pong()
@@ -127,6 +160,7 @@ where
                     return Ok(());
                 };
                 let (request, send) = request?;
+                connect_timer = None;
                 let (request, recv) = request.into_parts();
                 let req = H2Request {
                     request,
@@ -140,6 +174,11 @@ where
             _ = &mut ping_drop_rx => {
                 warn!("HBONE ping timeout/error");
             }
+            _ = async { connect_timer.as_mut().expect("timer set").await }, if connect_timer.is_some() => {
+                preface.timed_out(PrefaceStage::hbone_connect);
+                dropped.store(true, Ordering::Relaxed);
+                return Err(Error::PrefaceTimeout(PrefaceStage::hbone_connect));
+            }
             _shutdown = drain.signaled() => {
                 debug!("starting graceful drain...");
                 conn.graceful_shutdown();
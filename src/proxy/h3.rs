@@ -0,0 +1,396 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HBONE over HTTP/3 (QUIC). Experimental.
+//!
+//! The requests are the same CONNECT requests as over HTTP/2, sent to the UDP port of the HBONE
+//! address. Each tunnel is a QUIC stream, so a lost packet only holds back the tunnel it belongs to.
+//! Sessions are resumed where TLS session resumption is enabled, but 0-RTT data is never sent or
+//! accepted: a replayed CONNECT would open a new upstream connection, and replay the data sent
+//! over it.
+
+use crate::copy;
+use ::h3::error::{Code, StreamError};
+use bytes::{Buf, Bytes};
+use futures::future::BoxFuture;
+use futures_core::ready;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+
+pub mod client;
+pub mod server;
+
+// The ALPN protocol of HTTP/3 (RFC 9114, section 3.1).
+const ALPN: &[u8] = b"h3";
+
+// Matches the HTTP/2 pings: peers are sent a keep-alive every 10s, and considered gone after 30s
+// without hearing from them.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Matches the HTTP/2 server's limit.
+const MAX_CONCURRENT_STREAMS: u32 = 200;
+
+// transport_config returns the QUIC transport settings shared by clients and servers.
+fn transport_config(cfg: &crate::config::Config) -> Arc<quinn::TransportConfig> {
+    let mut transport = quinn::TransportConfig::default();
+    transport
+        .max_concurrent_bidi_streams(MAX_CONCURRENT_STREAMS.into())
+        .stream_receive_window(cfg.window_size.into())
+        .receive_window(cfg.connection_window_size.into())
+        .keep_alive_interval(Some(KEEP_ALIVE_INTERVAL))
+        .max_idle_timeout(Some(
+            IDLE_TIMEOUT.try_into().expect("idle timeout must be valid"),
+        ));
+    Arc::new(transport)
+}
+
+// H3Stream represents an active HTTP/3 request stream. Consumers can only Read/Write
+pub struct H3Stream {
+    pub read: H3StreamReadHalf,
+    pub write: H3StreamWriteHalf,
+}
+
+impl H3Stream {
+    fn new(recv: RecvHalf, send: SendHalf, active: Arc<()>) -> H3Stream {
+        H3Stream {
+            read: H3StreamReadHalf {
+                recv,
+                buf: Bytes::new(),
+                _active: active.clone(),
+            },
+            write: H3StreamWriteHalf {
+                state: WriteState::Ready(send),
+                _active: active,
+            },
+        }
+    }
+}
+
+impl copy::BufferedSplitter for H3Stream {
+    type R = H3StreamReadHalf;
+    type W = H3StreamWriteHalf;
+    fn split_into_buffered_reader(self) -> (H3StreamReadHalf, H3StreamWriteHalf) {
+        let H3Stream { read, write } = self;
+        (read, write)
+    }
+}
+
+type ClientStream<S> = ::h3::client::RequestStream<S, Bytes>;
+type ServerStream<S> = ::h3::server::RequestStream<S, Bytes>;
+
+// The halves of client and server request streams are different types, with the same methods.
+enum RecvHalf {
+    Client(ClientStream<h3_quinn::RecvStream>),
+    Server(ServerStream<h3_quinn::RecvStream>),
+}
+
+enum SendHalf {
+    Client(ClientStream<h3_quinn::SendStream<Bytes>>),
+    Server(ServerStream<h3_quinn::SendStream<Bytes>>),
+}
+
+impl RecvHalf {
+    fn poll_recv_data(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Bytes>, StreamError>> {
+        let data = match self {
+            RecvHalf::Client(s) => ready!(s.poll_recv_data(cx)).map(|d| d.map(into_bytes)),
+            RecvHalf::Server(s) => ready!(s.poll_recv_data(cx)).map(|d| d.map(into_bytes)),
+        };
+        Poll::Ready(data)
+    }
+}
+
+fn into_bytes(mut buf: impl Buf) -> Bytes {
+    buf.copy_to_bytes(buf.remaining())
+}
+
+impl SendHalf {
+    async fn send_data(&mut self, data: Bytes) -> Result<(), StreamError> {
+        match self {
+            SendHalf::Client(s) => s.send_data(data).await,
+            SendHalf::Server(s) => s.send_data(data).await,
+        }
+    }
+
+    async fn finish(&mut self) -> Result<(), StreamError> {
+        match self {
+            SendHalf::Client(s) => s.finish().await,
+            SendHalf::Server(s) => s.finish().await,
+        }
+    }
+}
+
+pub struct H3StreamReadHalf {
+    recv: RecvHalf,
+    buf: Bytes,
+    // Held by both halves while the stream is open, so the connection knows it is in use.
+    _active: Arc<()>,
+}
+
+impl copy::ResizeBufRead for H3StreamReadHalf {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        const EOF: Poll<io::Result<&[u8]>> = Poll::Ready(Ok(&[]));
+        let this = self.get_mut();
+        loop {
+            if !this.buf.is_empty() {
+                return Poll::Ready(Ok(this.buf.chunk()));
+            }
+            match ready!(this.recv.poll_recv_data(cx)) {
+                Ok(None) => return EOF,
+                Ok(Some(buf)) => this.buf = buf,
+                Err(StreamError::RemoteTerminate {
+                    code: Code::H3_NO_ERROR | Code::H3_REQUEST_CANCELLED,
+                    ..
+                }) => return EOF,
+                Err(e) if e.is_h3_no_error() => return EOF,
+                Err(e) => return Poll::Ready(Err(h3_to_io_error(e))),
+            }
+        }
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.as_mut().buf.advance(amt)
+    }
+
+    fn resize(self: Pin<&mut Self>, _size: usize) {
+        // NOP, we don't need to resize as we are abstracting the h3 buffer
+    }
+}
+
+// H3StreamWriteHalf writes to a request stream. HTTP/3 only exposes async sends, so a write is
+// accepted once the previous one is sent, and sent in the background; errors surface on the next
+// write, flush or shutdown.
+pub struct H3StreamWriteHalf {
+    state: WriteState,
+    _active: Arc<()>,
+}
+
+enum WriteState {
+    Ready(SendHalf),
+    Sending(BoxFuture<'static, (SendHalf, Result<(), StreamError>)>),
+    Finishing(BoxFuture<'static, Result<(), StreamError>>),
+    Closed,
+}
+
+impl H3StreamWriteHalf {
+    // poll_ready completes the write in progress, if any.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            match &mut self.state {
+                WriteState::Ready(_) => return Poll::Ready(Ok(())),
+                WriteState::Sending(fut) => {
+                    let (send, res) = ready!(fut.as_mut().poll(cx));
+                    if let Err(e) = res {
+                        self.state = WriteState::Closed;
+                        return Poll::Ready(Err(h3_to_io_error(e)));
+                    }
+                    self.state = WriteState::Ready(send);
+                }
+                WriteState::Finishing(_) | WriteState::Closed => {
+                    return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for H3StreamWriteHalf {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.poll_ready(cx))?;
+        let WriteState::Ready(mut send) = std::mem::replace(&mut self.state, WriteState::Closed)
+        else {
+            unreachable!("poll_ready returned ready");
+        };
+        let data = Bytes::copy_from_slice(buf);
+        let mut fut: BoxFuture<'static, _> = Box::pin(async move {
+            let res = send.send_data(data).await;
+            (send, res)
+        });
+        // Start sending right away; most sends complete without waiting
+        self.state = match fut.as_mut().poll(cx) {
+            Poll::Ready((send, Ok(()))) => WriteState::Ready(send),
+            Poll::Ready((_, Err(e))) => return Poll::Ready(Err(h3_to_io_error(e))),
+            Poll::Pending => WriteState::Sending(fut),
+        };
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match self.state {
+            // Nothing left to flush once finished
+            WriteState::Finishing(_) | WriteState::Closed => Poll::Ready(Ok(())),
+            _ => self.poll_ready(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        loop {
+            match &mut self.state {
+                WriteState::Finishing(fut) => {
+                    let res = ready!(fut.as_mut().poll(cx));
+                    self.state = WriteState::Closed;
+                    return Poll::Ready(match res {
+                        Ok(()) => Ok(()),
+                        Err(e) if e.is_h3_no_error() => Ok(()),
+                        Err(e) => Err(h3_to_io_error(e)),
+                    });
+                }
+                WriteState::Closed => return Poll::Ready(Ok(())),
+                _ => {
+                    ready!(self.poll_ready(cx))?;
+                    let WriteState::Ready(mut send) =
+                        std::mem::replace(&mut self.state, WriteState::Closed)
+                    else {
+                        unreachable!("poll_ready returned ready");
+                    };
+                    self.state =
+                        WriteState::Finishing(Box::pin(async move { send.finish().await }));
+                }
+            }
+        }
+    }
+}
+
+fn h3_to_io_error(e: StreamError) -> io::Error {
+    match e {
+        StreamError::RemoteTerminate { .. } => io::Error::new(io::ErrorKind::ConnectionReset, e),
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::copy::{BufferedSplitter, ResizeBufRead};
+    use crate::identity::Identity;
+    use crate::proxy::pool::WorkloadKey;
+    use crate::test_helpers::helpers::{initialize_telemetry, test_proxy_metrics};
+    use crate::tls::mock::generate_test_certs;
+
+    async fn read_to_end(mut read: H3StreamReadHalf) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let chunk = futures::future::poll_fn(|cx| Pin::new(&mut read).poll_fill_buf(cx))
+                .await
+                .unwrap()
+                .to_vec();
+            if chunk.is_empty() {
+                return out;
+            }
+            Pin::new(&mut read).consume(chunk.len());
+            out.extend(chunk);
+        }
+    }
+
+    // A CONNECT request is sent over QUIC to the HTTP/3 server, and data flows both ways.
+    #[tokio::test]
+    async fn round_trip() {
+        initialize_telemetry();
+        let cfg = Arc::new(crate::test_helpers::test_config());
+        let cert_manager = crate::identity::mock::new_secret_manager(Duration::from_secs(10));
+        let id: Identity = "spiffe://cluster.local/ns/default/sa/server"
+            .parse()
+            .unwrap();
+        let cert = generate_test_certs(
+            &id.clone().into(),
+            Duration::from_secs(0),
+            Duration::from_secs(100),
+        );
+        let tls = cert
+            .server_config(
+                &cfg.tls_settings,
+                None,
+                &[],
+                cert_manager.trust_bundles(),
+                cert_manager.revocation_lists(),
+            )
+            .unwrap();
+        let server_config = Arc::new(server::server_config(&cfg, tls).unwrap());
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = socket.local_addr().unwrap();
+        let endpoint = server::endpoint(&cfg, socket).unwrap();
+
+        let (drain_tx, drain_rx) = drain::channel();
+        let server_cfg = cfg.clone();
+        let server = tokio::spawn(async move {
+            let incoming = endpoint.accept().await.unwrap();
+            let conn = incoming.accept_with(server_config).unwrap().await.unwrap();
+            assert_eq!(
+                server::peer_identity(&conn).unwrap().to_string(),
+                "spiffe://cluster.local/ns/default/sa/client"
+            );
+            let preface = crate::proxy::PrefaceTimeouts::new(&server_cfg, test_proxy_metrics());
+            server::serve_connection(server_cfg, conn, drain_rx, preface, |req| async move {
+                assert_eq!(req.method(), http::Method::CONNECT);
+                assert_eq!(req.uri().to_string(), "127.0.0.1:8080");
+                let resp = http::Response::builder().status(200).body(()).unwrap();
+                let stream = req.send_response(resp).await.unwrap();
+                let (read, mut write) = stream.split_into_buffered_reader();
+                assert_eq!(read_to_end(read).await, b"hello");
+                write.write_all(b"world").await.unwrap();
+                write.shutdown().await.unwrap();
+            })
+            .await
+        });
+
+        let pool = client::QuicPool::new(
+            cfg.clone(),
+            Arc::new(crate::proxy::DefaultSocketFactory),
+            cert_manager,
+            test_proxy_metrics(),
+        );
+        let key = WorkloadKey {
+            src_id: "spiffe://cluster.local/ns/default/sa/client"
+                .parse()
+                .unwrap(),
+            dst_id: vec![id],
+            src: "127.0.0.1".parse().unwrap(),
+            dst: addr,
+            dscp: None,
+        };
+        let mut client = pool.connect(&key).await.expect("quic connection");
+        let req = http::Request::builder()
+            .method(http::Method::CONNECT)
+            .uri("127.0.0.1:8080")
+            .body(())
+            .unwrap();
+        let stream = client.send_request(req).await.unwrap();
+        let (read, mut write) = stream.split_into_buffered_reader();
+        write.write_all(b"hello").await.unwrap();
+        write.shutdown().await.unwrap();
+        assert_eq!(read_to_end(read).await, b"world");
+
+        drain_tx.drain().await;
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::identity::SecretManager;
+use crate::proxy::h3::{transport_config, H3Stream, RecvHalf, SendHalf, ALPN};
+use crate::proxy::metrics::{self, Metrics, Reporter};
+use crate::proxy::pool::WorkloadKey;
+use crate::proxy::{Error, SocketFactory};
+use crate::tls;
+use ::h3::error::Code;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace_span, warn, Instrument};
+
+// Destinations that do not answer by then are unlikely to be serving QUIC at all. The handshake is
+// abandoned, so the request can fall back to HTTP/2 without waiting for the idle timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long a destination that could not be reached over QUIC is sent HTTP/2 only.
+const FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+type SendRequest = ::h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+#[derive(Clone)]
+// H3ConnectClient sends requests over a QUIC connection. The connection is shared by all of its
+// clones.
+pub struct H3ConnectClient {
+    sender: SendRequest,
+    conn: quinn::Connection,
+    // Held by the client and its streams, so the connection knows when it is in use.
+    active: Arc<()>,
+}
+
+impl H3ConnectClient {
+    fn is_open(&self) -> bool {
+        self.conn.close_reason().is_none()
+    }
+
+    pub async fn send_request(&mut self, req: http::Request<()>) -> Result<H3Stream, Error> {
+        let mut stream = self.sender.send_request(req).await?;
+        let response = stream.recv_response().await?;
+        if response.status() != 200 {
+            return Err(Error::HttpStatus(response.status()));
+        }
+        let (send, recv) = stream.split();
+        Ok(H3Stream::new(
+            RecvHalf::Client(recv),
+            SendHalf::Client(send),
+            self.active.clone(),
+        ))
+    }
+}
+
+// QuicPool keeps a QUIC connection to each destination, as HTTP/3 multiplexes all of the requests
+// to a peer over one connection (RFC 9114, section 3.3). Connections are closed once they are unused
+// for the pool's unused release timeout, or when the pool is dropped.
+#[derive(Clone)]
+pub struct QuicPool {
+    state: Arc<PoolState>,
+}
+
+struct PoolState {
+    cfg: Arc<config::Config>,
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+    cert_manager: Arc<SecretManager>,
+    metrics: Arc<Metrics>,
+    // Connections are made under a per-key lock, so concurrent requests share the first one.
+    conns: Mutex<HashMap<WorkloadKey, Arc<tokio::sync::Mutex<Option<H3ConnectClient>>>>>,
+    // When QUIC connections to a destination last failed
+    failed: Mutex<HashMap<SocketAddr, Instant>>,
+}
+
+impl QuicPool {
+    pub fn new(
+        cfg: Arc<config::Config>,
+        socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+        cert_manager: Arc<SecretManager>,
+        metrics: Arc<Metrics>,
+    ) -> QuicPool {
+        QuicPool {
+            state: Arc::new(PoolState {
+                cfg,
+                socket_factory,
+                cert_manager,
+                metrics,
+                conns: Default::default(),
+                failed: Default::default(),
+            }),
+        }
+    }
+
+    // connect returns a client for the destination, connecting to it if needed. None is returned if
+    // the destination cannot be reached over QUIC, so the caller should use HTTP/2 instead.
+    pub async fn connect(&self, key: &WorkloadKey) -> Option<H3ConnectClient> {
+        let backing_off = self
+            .state
+            .failed
+            .lock()
+            .expect("mutex")
+            .get(&key.dst)
+            .is_some_and(|at| at.elapsed() < FAILURE_BACKOFF);
+        if backing_off {
+            return None;
+        }
+
+        let slot = self
+            .state
+            .conns
+            .lock()
+            .expect("mutex")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(client) = slot.as_ref().filter(|c| c.is_open()) {
+            return Some(client.clone());
+        }
+        match self.new_connection(key).await {
+            Ok(client) => {
+                self.state.failed.lock().expect("mutex").remove(&key.dst);
+                *slot = Some(client.clone());
+                Some(client)
+            }
+            Err(e) => {
+                debug!(dst=%key.dst, "failed to connect over QUIC, using HTTP/2: {e}");
+                self.state
+                    .failed
+                    .lock()
+                    .expect("mutex")
+                    .insert(key.dst, Instant::now());
+                *slot = None;
+                None
+            }
+        }
+    }
+
+    async fn new_connection(&self, key: &WorkloadKey) -> Result<H3ConnectClient, Error> {
+        debug!("spawning new QUIC conn for {}", key);
+        let cfg = &self.state.cfg;
+        let cert = self
+            .state
+            .cert_manager
+            .fetch_certificate(&key.src_id)
+            .await?;
+        let connector = cert.outbound_connector(
+            &cfg.tls_settings,
+            key.dst_id.clone(),
+            cfg.tls_session_lifetime,
+            &cfg.trust_domain_aliases,
+            self.state.cert_manager.trust_bundles(),
+        )?;
+        let mut crypto = (*connector.client_config()).clone();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+            .map_err(|e| tls::Error::InvalidTlsSettings(e.to_string()))?;
+        let mut client_config = quinn::ClientConfig::new(Arc::new(crypto));
+        client_config.transport_config(transport_config(cfg));
+
+        let endpoint = quinn::Endpoint::new(
+            quinn::EndpointConfig::default(),
+            None,
+            self.bind(key)?.into_std()?,
+            Arc::new(quinn::TokioRuntime),
+        )?;
+        let connecting = endpoint
+            .connect_with(client_config, key.dst, &key.dst.ip().to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let latency = self.state.metrics.latency_labels(Reporter::source, None);
+        let start = Instant::now();
+        let conn = tokio::time::timeout(CONNECT_TIMEOUT, connecting)
+            .instrument(trace_span!("quic handshake", dst = %key.dst))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        metrics::observe_duration(
+            &self.state.metrics.tls_handshake_duration,
+            &latency,
+            start.elapsed(),
+        );
+
+        let (driver, sender) = ::h3::client::builder()
+            .build::<_, _, Bytes>(h3_quinn::Connection::new(conn.clone()))
+            .await
+            .map_err(Error::H3Connection)?;
+        let client = H3ConnectClient {
+            sender,
+            conn,
+            active: Arc::new(()),
+        };
+        tokio::spawn(drive_connection(
+            driver,
+            &client,
+            self.state.cfg.pool_unused_release_timeout,
+            Arc::downgrade(&self.state),
+            key.clone(),
+        ));
+        Ok(client)
+    }
+
+    // bind returns the socket of a connection, bound to the source workload's address where the
+    // original source is kept, as TCP connections are.
+    fn bind(&self, key: &WorkloadKey) -> io::Result<tokio::net::UdpSocket> {
+        let unspecified: IpAddr = match key.dst {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket_factory = &self.state.socket_factory;
+        match self.state.cfg.enable_original_source {
+            Some(true) if key.src.is_ipv4() == key.dst.is_ipv4() => socket_factory
+                .udp_bind_transparent(SocketAddr::new(key.src, 0))
+                .or_else(|err| {
+                    warn!("failed to bind original source: {:?}", err);
+                    socket_factory.udp_bind(SocketAddr::new(unspecified, 0))
+                }),
+            _ => socket_factory.udp_bind(SocketAddr::new(unspecified, 0)),
+        }
+    }
+}
+
+// drive_connection drives the HTTP/3 state of a connection until it closes, closing it once it has
+// been unused for the release timeout. Connections also close once the pool, and every client of
+// theirs, is dropped.
+fn drive_connection(
+    mut driver: ::h3::client::Connection<h3_quinn::Connection, Bytes>,
+    client: &H3ConnectClient,
+    release_timeout: Duration,
+    pool: Weak<PoolState>,
+    key: WorkloadKey,
+) -> impl Future<Output = ()> + Send + 'static {
+    let conn = client.conn.clone();
+    let active = Arc::downgrade(&client.active);
+    async move {
+        let mut idle = tokio::time::interval(release_timeout);
+        idle.tick().await;
+        // Unused connections are closed at the second check that finds them unused.
+        let mut was_unused = false;
+        let close = |reason: &[u8]| {
+            let code = quinn::VarInt::from_u64(Code::H3_NO_ERROR.value()).expect("valid code");
+            conn.close(code, reason)
+        };
+        loop {
+            tokio::select! {
+                err = futures::future::poll_fn(|cx| driver.poll_close(cx)) => {
+                    if !err.is_h3_no_error() {
+                        debug!("QUIC connection closed: {err}");
+                    }
+                    break;
+                }
+                _ = idle.tick() => {
+                    // Only the pooled client remains
+                    let unused = active.strong_count() <= 1;
+                    if unused && was_unused {
+                        debug!("closing unused QUIC connection");
+                        close(b"unused");
+                    }
+                    was_unused = unused;
+                }
+            }
+        }
+
+        // Forget the connection, unless it has already been replaced
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        let mut conns = pool.conns.lock().expect("mutex");
+        let replaced = match conns.get(&key).map(|slot| slot.try_lock()) {
+            Some(Ok(slot)) => slot
+                .as_ref()
+                .is_some_and(|c| c.conn.stable_id() != conn.stable_id()),
+            // Being reconnected
+            Some(Err(_)) => true,
+            None => true,
+        };
+        if !replaced {
+            conns.remove(&key);
+        }
+    }
+}
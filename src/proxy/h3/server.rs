@@ -0,0 +1,249 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config;
+use crate::proxy::h3::{transport_config, H3Stream, RecvHalf, SendHalf, ALPN};
+use crate::proxy::{Error, PrefaceStage, PrefaceTimeouts};
+use crate::tls;
+use ::h3::error::Code;
+use bytes::Bytes;
+use futures_util::FutureExt;
+use http::request::Parts;
+use http::{Response, Uri};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::time::timeout;
+use tracing::debug;
+
+type RequestStream = ::h3::server::RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+pub struct H3Request {
+    request: Parts,
+    stream: RequestStream,
+}
+
+impl H3Request {
+    fn new(mut request: Parts, stream: RequestStream) -> H3Request {
+        // HTTP/3 clients send the scheme and path of CONNECT requests; present them in
+        // authority-form, as they are over HTTP/2.
+        if request.method == http::Method::CONNECT
+            && request.extensions.get::<::h3::ext::Protocol>().is_none()
+        {
+            if let Some(uri) = request
+                .uri
+                .authority()
+                .and_then(|a| a.as_str().parse::<Uri>().ok())
+            {
+                request.uri = uri;
+            }
+        }
+        H3Request { request, stream }
+    }
+
+    /// The request's method
+    pub fn method(&self) -> &http::Method {
+        &self.request.method
+    }
+
+    /// The request's URI
+    pub fn uri(&self) -> &http::Uri {
+        &self.request.uri
+    }
+
+    /// The request's headers
+    pub fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        &self.request.headers
+    }
+
+    /// The protocol of an extended CONNECT request (RFC 9220), if any
+    pub fn protocol(&self) -> Option<&str> {
+        self.request
+            .extensions
+            .get::<::h3::ext::Protocol>()
+            .map(|p| p.as_str())
+    }
+
+    pub async fn send_error(mut self, resp: Response<()>) -> Result<(), Error> {
+        self.stream.send_response(resp).await?;
+        self.stream.finish().await?;
+        Ok(())
+    }
+
+    /// Reset the stream without sending a response
+    pub fn send_reset(mut self) {
+        self.stream.stop_sending(Code::H3_REQUEST_REJECTED);
+        self.stream.stop_stream(Code::H3_REQUEST_REJECTED);
+    }
+
+    pub async fn send_response(mut self, resp: Response<()>) -> Result<H3Stream, Error> {
+        self.stream.send_response(resp).await?;
+        let (send, recv) = self.stream.split();
+        Ok(H3Stream::new(
+            RecvHalf::Server(recv),
+            SendHalf::Server(send),
+            Arc::new(()),
+        ))
+    }
+}
+
+/// server_config returns the QUIC config of inbound connections, given their TLS config.
+pub fn server_config(
+    cfg: &config::Config,
+    mut tls: rustls::ServerConfig,
+) -> Result<quinn::ServerConfig, tls::Error> {
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(tls)
+        .map_err(|e| tls::Error::InvalidTlsSettings(e.to_string()))?;
+    let mut server = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    server.transport_config(transport_config(cfg));
+    Ok(server)
+}
+
+/// endpoint serves QUIC on the socket. Connections are not accepted unless they are given a config,
+/// as each destination has its own certificate.
+pub fn endpoint(
+    cfg: &config::Config,
+    socket: std::net::UdpSocket,
+) -> Result<quinn::Endpoint, Error> {
+    let unresolved = tls::unresolved_server_config(&cfg.tls_settings);
+    let default = server_config(cfg, unresolved)?;
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(default),
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+    Ok(endpoint)
+}
+
+/// peer_identity returns the identity of the client certificate of a connection.
+pub fn peer_identity(conn: &quinn::Connection) -> Option<crate::identity::Identity> {
+    let certs = conn
+        .peer_identity()?
+        .downcast::<Vec<rustls::pki_types::CertificateDer<'static>>>()
+        .ok()?;
+    tls::identity_from_certificates(&certs)
+}
+
+// serve_connection serves the HBONE requests of an inbound QUIC connection. The client must complete
+// the HTTP/3 handshake and send its first request within the preface's HBONE connect timeout.
+pub async fn serve_connection<F, Fut>(
+    cfg: Arc<config::Config>,
+    conn: quinn::Connection,
+    drain: drain::Watch,
+    preface: PrefaceTimeouts,
+    handler: F,
+) -> Result<(), Error>
+where
+    F: Fn(H3Request) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+{
+    let drain_deadline = cfg.self_termination_deadline;
+    // Cleared once the first request arrives
+    let mut connect_timer = preface
+        .limit(PrefaceStage::hbone_connect)
+        .map(|limit| Box::pin(tokio::time::sleep(limit)));
+    let mut builder = ::h3::server::builder();
+    builder
+        // Required to accept CONNECT-UDP requests
+        .enable_extended_connect(cfg.enable_connect_udp)
+        // 64KB max, as over HTTP/2
+        .max_field_section_size(65536);
+    let handshake = builder.build::<_, Bytes>(h3_quinn::Connection::new(conn));
+    let mut conn = match connect_timer.as_mut() {
+        Some(timer) => tokio::select! {
+            conn = handshake => conn.map_err(Error::H3Connection)?,
+            _ = timer => {
+                preface.timed_out(PrefaceStage::hbone_connect);
+                return Err(Error::PrefaceTimeout(PrefaceStage::hbone_connect));
+            }
+        },
+        None => handshake.await.map_err(Error::H3Connection)?,
+    };
+
+    let handler = Arc::new(move |req| handler(req).map(|_| ()));
+    // Held by each request until it is served, so draining can wait for them
+    let (served, serving) = drain::channel();
+    loop {
+        let drain = drain.clone();
+        tokio::select! {
+            request = conn.accept() => {
+                let Some(resolver) = closed_ok(request)? else {
+                    // done!
+                    return Ok(());
+                };
+                connect_timer = None;
+                tokio::task::spawn(serve_request(resolver, handler.clone(), serving.clone()));
+            }
+            _ = async { connect_timer.as_mut().expect("timer set").await }, if connect_timer.is_some() => {
+                preface.timed_out(PrefaceStage::hbone_connect);
+                return Err(Error::PrefaceTimeout(PrefaceStage::hbone_connect));
+            }
+            _shutdown = drain.signaled() => {
+                debug!("starting graceful drain...");
+                conn.shutdown(0).await.map_err(Error::H3Connection)?;
+                break;
+            }
+        }
+    }
+    // Requests already accepted are served until they complete; the connection is closed once it
+    // is dropped.
+    drop(serving);
+    let drained = async {
+        let served = served.drain();
+        tokio::pin!(served);
+        loop {
+            tokio::select! {
+                _ = &mut served => return Ok(()),
+                // Requests sent after the GOAWAY are refused
+                request = conn.accept() => {
+                    if closed_ok(request)?.is_none() {
+                        return Ok::<_, Error>(());
+                    }
+                }
+            }
+        }
+    };
+    timeout(drain_deadline, drained)
+        .await
+        .map_err(|_| Error::DrainTimeOut)??;
+    drop(drain);
+    Ok(())
+}
+
+type Resolver = ::h3::server::RequestResolver<h3_quinn::Connection, Bytes>;
+
+async fn serve_request<H, Fut>(resolver: Resolver, handler: Arc<H>, _serving: drain::Watch)
+where
+    H: Fn(H3Request) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    match resolver.resolve_request().await {
+        Ok((request, stream)) => {
+            let (request, ()) = request.into_parts();
+            handler(H3Request::new(request, stream)).await
+        }
+        Err(e) => debug!("failed to read request: {e}"),
+    }
+}
+
+// closed_ok treats connections closed without an error, or that went idle, as closed cleanly.
+fn closed_ok<T>(res: Result<Option<T>, ::h3::error::ConnectionError>) -> Result<Option<T>, Error> {
+    match res {
+        Ok(res) => Ok(res),
+        Err(e) if e.is_h3_no_error() => Ok(None),
+        Err(::h3::error::ConnectionError::Timeout { .. }) => Ok(None),
+        Err(e) => Err(Error::H3Connection(e)),
+    }
+}
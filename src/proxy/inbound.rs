@@ -15,35 +15,42 @@
 use std::collections::HashSet;
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use drain::Watch;
 use futures::stream::StreamExt;
 
 use http::{Method, Response, StatusCode};
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 
-use tracing::{debug, info, instrument, trace_span, Instrument};
+use tracing::{debug, info, instrument, trace_span, warn, Instrument};
 
-use super::connection_manager::ConnectionManager;
+use super::connection_manager::{ConnectionManager, InboundListener};
 use super::Error;
 use crate::baggage::parse_baggage_header;
 use crate::identity::{Identity, SecretManager};
 
+use crate::proxy::connect_udp;
 use crate::proxy::h2::server::H2Request;
-use crate::proxy::metrics::{ConnectionOpen, Reporter};
-use crate::proxy::{metrics, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
+use crate::proxy::metrics::{ConnectionOpen, Reporter, TlsHandshakeKind, TlsHandshakeLabels};
+use crate::proxy::sniff::ProtocolDetector;
+use crate::proxy::{
+    metrics, ConnectionId, PrefaceStage, PrefaceTimeouts, ProxyInputs, TraceParent, BAGGAGE_HEADER,
+    TRACEPARENT_HEADER,
+};
 use crate::rbac::Connection;
 use crate::socket::to_canonical;
 use crate::state::service::Service;
 use crate::state::workload::address::Address;
 use crate::state::workload::application_tunnel::Protocol as AppProtocol;
-use crate::{assertions, copy, proxy, strng, tls};
+use crate::{assertions, copy, proxy, strng, telemetry, tls};
 
 use crate::proxy::h2;
+#[cfg(feature = "quic")]
+use crate::proxy::h3;
 use crate::state::workload::{self, NetworkAddress, Workload};
 use crate::state::DemandProxyState;
 use crate::strng::Strng;
@@ -51,6 +58,9 @@ use crate::tls::TlsError;
 
 pub(super) struct Inbound {
     listener: TcpListener,
+    // Serves HBONE over QUIC on the UDP port of the listener's address, where enabled
+    #[cfg(feature = "quic")]
+    quic: Option<std::net::UdpSocket>,
     drain: Watch,
     pi: ProxyInputs,
 }
@@ -74,8 +84,26 @@ impl Inbound {
             transparent,
             "listener established",
         );
+        #[cfg(feature = "quic")]
+        let quic = if pi.cfg.enable_hbone_quic {
+            let addr = listener.local_addr().expect("local_addr available");
+            let socket = if transparent {
+                pi.socket_factory.udp_bind_transparent(addr)
+            } else {
+                pi.socket_factory.udp_bind(addr)
+            };
+            let socket = socket
+                .and_then(|s| s.into_std())
+                .map_err(|e| Error::Bind(addr, e))?;
+            info!(address=%addr, component="inbound", "quic listener established");
+            Some(socket)
+        } else {
+            None
+        };
         Ok(Inbound {
             listener,
+            #[cfg(feature = "quic")]
+            quic,
             drain,
             pi,
         })
@@ -86,28 +114,96 @@ impl Inbound {
     }
 
     pub(super) async fn run(self, illegal_ports: Arc<HashSet<u16>>) {
-        let acceptor = InboundCertProvider {
+        let certs = InboundCertProvider {
             state: self.pi.state.clone(),
             cert_manager: self.pi.cert_manager.clone(),
             network: strng::new(&self.pi.cfg.network),
+            tls_settings: self.pi.cfg.tls_settings.clone(),
+            session_lifetime: self.pi.cfg.tls_session_lifetime,
+            trust_domain_aliases: self.pi.cfg.trust_domain_aliases.clone(),
         };
-        let stream = crate::hyper_util::tls_server(acceptor, self.listener);
+        // Without PROXY protocol, no one is trusted to send it
+        let trusted = if self.pi.cfg.inbound_proxy_protocol {
+            self.pi.cfg.proxy_protocol_trusted_cidrs.clone()
+        } else {
+            Vec::new()
+        };
+        let detector = (!self.pi.cfg.inbound_protocol_detection_timeout.is_zero()).then(|| {
+            ProtocolDetector::new(
+                self.pi.cfg.inbound_protocol_detection_timeout,
+                self.pi.metrics.clone(),
+            )
+        });
+        let stream = crate::hyper_util::tls_server_proxy_protocol(
+            certs.clone(),
+            self.listener,
+            trusted,
+            detector,
+            PrefaceTimeouts::new(&self.pi.cfg, self.pi.metrics.clone()),
+        );
+        #[cfg(feature = "quic")]
+        let quic_drain = self.drain.clone();
         let mut stream = stream.take_until(Box::pin(self.drain.signaled()));
 
         let (sub_drain_signal, sub_drain) = drain::channel();
 
         let pi = Arc::new(self.pi);
-        while let Some(tls) = stream.next().await {
+        #[cfg(feature = "quic")]
+        if let Some(socket) = self.quic {
+            match h3::server::endpoint(&pi.cfg, socket) {
+                Ok(endpoint) => {
+                    tokio::task::spawn(Self::run_quic(
+                        endpoint,
+                        pi.clone(),
+                        certs.clone(),
+                        quic_drain,
+                        sub_drain.clone(),
+                        illegal_ports.clone(),
+                    ));
+                }
+                Err(e) => warn!("failed to serve HBONE over QUIC: {e}"),
+            }
+        }
+        loop {
+            // The listener is only polled while we wait on the stream, so throttling here also
+            // holds back new TCP accepts (beyond any handshakes already in progress).
+            super::throttle_accept(&pi).await;
+            let Some((tls, proxied_src)) = stream.next().await else {
+                break;
+            };
             let pi = pi.clone();
             let (raw_socket, ssl) = tls.get_ref();
+            let Ok(permit) = super::admit_connection(&pi, raw_socket) else {
+                continue;
+            };
+            super::set_socket_policy(raw_socket, &pi.cfg.socket_config, super::SocketClass::Mesh);
+            // The HBONE connection carries the mark of the connections tunneled over it
+            let dscp = super::upstream_dscp(
+                super::received_dscp(raw_socket, &pi.cfg.socket_config),
+                &pi.cfg.socket_config,
+                super::SocketClass::App,
+            );
             let src_identity: Option<Identity> = tls::identity_from_connection(ssl);
+            let kind = if ssl.received_resumption_data().is_some() {
+                TlsHandshakeKind::Resumed
+            } else {
+                TlsHandshakeKind::Full
+            };
+            pi.metrics
+                .tls_handshakes
+                .get_or_create(&TlsHandshakeLabels { kind })
+                .inc();
             let dst = crate::socket::orig_dst_addr_or_default(raw_socket);
-            let src = to_canonical(raw_socket.peer_addr().expect("peer_addr available"));
+            // If a trusted load balancer conveyed the source, use it instead of the load balancer
+            let src = proxied_src.unwrap_or_else(|| {
+                to_canonical(raw_socket.peer_addr().expect("peer_addr available"))
+            });
             let connection_manager = pi.connection_manager.clone();
             let drain = sub_drain.clone();
             let network = pi.cfg.network.clone();
             let illegal_ports = illegal_ports.clone();
             let serve_client = async move {
+                let _permit = permit;
                 let conn = Connection {
                     src_identity,
                     src,
@@ -117,10 +213,12 @@ impl Inbound {
                 debug!(%conn, "accepted connection");
                 let enable_original_source = pi.cfg.enable_original_source;
                 let cfg = pi.cfg.clone();
+                let preface = PrefaceTimeouts::new(&cfg, pi.metrics.clone());
                 let request_handler = move |req| {
                     Self::serve_connect(
                         pi.clone(),
                         conn.clone(),
+                        dscp,
                         enable_original_source.unwrap_or_default(),
                         req,
                         illegal_ports.clone(),
@@ -131,6 +229,7 @@ impl Inbound {
                     cfg,
                     tls,
                     drain,
+                    preface,
                     request_handler,
                 ));
                 serve.await
@@ -144,46 +243,171 @@ impl Inbound {
         info!("all inbound connections drained");
     }
 
-    fn extract_traceparent(req: &H2Request) -> TraceParent {
+    // run_quic serves HBONE over QUIC until drained. Each connection is served with the certificate
+    // of the workload it is addressed to, as over TCP.
+    #[cfg(feature = "quic")]
+    async fn run_quic(
+        endpoint: quinn::Endpoint,
+        pi: Arc<ProxyInputs>,
+        certs: InboundCertProvider,
+        drain: Watch,
+        sub_drain: Watch,
+        illegal_ports: Arc<HashSet<u16>>,
+    ) {
+        let local = endpoint.local_addr().expect("local_addr available");
+        let accept = async {
+            loop {
+                super::throttle_accept(&pi).await;
+                let Some(incoming) = endpoint.accept().await else {
+                    return;
+                };
+                let permit = match &pi.connection_budget {
+                    None => None,
+                    Some(budget) => match budget.try_admit() {
+                        Some(permit) => Some(permit),
+                        None => {
+                            pi.metrics.connections_shed.inc();
+                            incoming.refuse();
+                            continue;
+                        }
+                    },
+                };
+                let pi = pi.clone();
+                let certs = certs.clone();
+                let drain = sub_drain.clone();
+                let illegal_ports = illegal_ports.clone();
+                tokio::task::spawn(async move {
+                    let _permit = permit;
+                    let src = to_canonical(incoming.remote_address());
+                    let dst = SocketAddr::new(
+                        incoming
+                            .local_ip()
+                            .map(|ip| ip.to_canonical())
+                            .unwrap_or(local.ip()),
+                        local.port(),
+                    );
+                    let preface = PrefaceTimeouts::new(&pi.cfg, pi.metrics.clone());
+                    let handshake = async {
+                        let tls = certs.server_config(dst.ip()).await?;
+                        let server_config = h3::server::server_config(&pi.cfg, tls)?;
+                        let conn = incoming.accept_with(Arc::new(server_config))?.await?;
+                        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(conn)
+                    };
+                    let conn = match preface.run(PrefaceStage::tls_handshake, handshake).await {
+                        Some(Ok(conn)) => conn,
+                        Some(Err(e)) => {
+                            debug!(%src, %dst, "quic handshake failed: {e}");
+                            return;
+                        }
+                        None => return,
+                    };
+                    pi.metrics
+                        .tls_handshakes
+                        .get_or_create(&TlsHandshakeLabels {
+                            kind: TlsHandshakeKind::Full,
+                        })
+                        .inc();
+                    let conn = Connection {
+                        src_identity: h3::server::peer_identity(&conn),
+                        src,
+                        dst_network: strng::new(&pi.cfg.network), // inbound request must be on our network
+                        dst,
+                    };
+                    debug!(%conn, "accepted quic connection");
+                    let cfg = pi.cfg.clone();
+                    let enable_original_source = pi.cfg.enable_original_source;
+                    let connection_manager = pi.connection_manager.clone();
+                    let request_handler = move |req| {
+                        Self::serve_connect(
+                            pi.clone(),
+                            conn.clone(),
+                            // QUIC connections carry no DSCP mark
+                            None,
+                            enable_original_source.unwrap_or_default(),
+                            req,
+                            illegal_ports.clone(),
+                            connection_manager.clone(),
+                        )
+                    };
+                    if let Err(e) =
+                        h3::server::serve_connection(cfg, conn, drain, preface, request_handler)
+                            .await
+                    {
+                        debug!("quic connection failed: {e}");
+                    }
+                });
+            }
+        };
+        tokio::select! {
+            _ = accept => {}
+            _ = drain.signaled() => {}
+        }
+        // Stop accepting new connections, while the ones already accepted drain
+        endpoint.set_server_config(None);
+    }
+
+    fn traceparent_header<R: HboneRequest>(req: &R) -> Option<TraceParent> {
         req.headers()
             .get(TRACEPARENT_HEADER)
             .and_then(|b| b.to_str().ok())
             .and_then(|b| TraceParent::try_from(b).ok())
-            .unwrap_or_else(TraceParent::new)
+    }
+
+    fn extract_traceparent<R: HboneRequest>(req: &R) -> TraceParent {
+        Self::traceparent_header(req).unwrap_or_else(TraceParent::new)
     }
 
     #[allow(clippy::too_many_arguments)]
     #[instrument(name="inbound", skip_all, fields(
         id=%Self::extract_traceparent(&req),
+        connection_id=tracing::field::Empty,
         peer=%conn.src,
         peer_id=%OptionDisplay(&conn.src_identity)
     ))]
-    async fn serve_connect(
+    async fn serve_connect<R: HboneRequest>(
         pi: Arc<ProxyInputs>,
         conn: Connection,
+        dscp: Option<u8>,
         enable_original_source: bool,
-        req: H2Request,
+        req: R,
         illegal_ports: Arc<HashSet<u16>>,
         connection_manager: ConnectionManager,
     ) -> Result<(), Error> {
+        let connection_id = ConnectionId::new();
+        tracing::Span::current().record("connection_id", tracing::field::display(connection_id));
+        // The request is exported as a child of the peer's span, so both hops are in one trace.
+        if let Some(parent) = Self::traceparent_header(&req) {
+            telemetry::set_trace_context(&tracing::Span::current(), &parent.child(), Some(&parent));
+        }
         if req.method() != Method::CONNECT {
             metrics::log_early_deny(
+                &pi.metrics,
                 conn.src,
                 conn.dst,
                 Reporter::destination,
                 Error::NonConnectMethod(req.method().to_string()),
             );
-            return req.send_error(build_response(StatusCode::NOT_FOUND));
+            return req.send_error(build_response(StatusCode::NOT_FOUND)).await;
         }
         let start = Instant::now();
-        let Ok(hbone_addr) = req.uri().to_string().as_str().parse::<SocketAddr>() else {
+        // CONNECT-UDP requests carry the target in the path, rather than the authority
+        let udp = req.protocol() == Some(connect_udp::PROTOCOL);
+        let hbone_addr = match req.protocol() {
+            None => req.uri().to_string().as_str().parse::<SocketAddr>().ok(),
+            Some(connect_udp::PROTOCOL) => connect_udp::parse_target_path(req.uri().path()),
+            Some(_) => None,
+        };
+        let Some(hbone_addr) = hbone_addr else {
             metrics::log_early_deny(
+                &pi.metrics,
                 conn.src,
                 conn.dst,
                 Reporter::destination,
                 Error::ConnectAddress(req.uri().to_string()),
             );
-            return req.send_error(build_response(StatusCode::BAD_REQUEST));
+            return req
+                .send_error(build_response(StatusCode::BAD_REQUEST))
+                .await;
         };
 
         // Determine the next hop.
@@ -191,8 +415,16 @@ impl Inbound {
             match Self::find_inbound_upstream(&pi.state, &conn, hbone_addr).await {
                 Ok(res) => res,
                 Err(e) => {
-                    metrics::log_early_deny(conn.src, conn.dst, Reporter::destination, e);
-                    return req.send_error(build_response(StatusCode::BAD_REQUEST));
+                    metrics::log_early_deny(
+                        &pi.metrics,
+                        conn.src,
+                        conn.dst,
+                        Reporter::destination,
+                        e,
+                    );
+                    return req
+                        .send_error(build_response(StatusCode::BAD_REQUEST))
+                        .await;
                 }
             };
         let illegal_call = if pi.cfg.inpod_enabled {
@@ -205,13 +437,40 @@ impl Inbound {
         };
         if illegal_call {
             metrics::log_early_deny(
+                &pi.metrics,
                 conn.src,
                 upstream_addr,
                 Reporter::destination,
                 Error::SelfCall,
             );
-            return req.send_error(build_response(StatusCode::BAD_REQUEST));
+            return req
+                .send_error(build_response(StatusCode::BAD_REQUEST))
+                .await;
+        }
+        if let Err(e) = proxy::check_inbound_port(&pi, &upstream, hbone_addr.port()) {
+            metrics::log_early_deny(
+                &pi.metrics,
+                conn.src,
+                upstream_addr,
+                Reporter::destination,
+                e,
+            );
+            return req.send_error(build_response(StatusCode::FORBIDDEN)).await;
         }
+        let _workload_guard = match proxy::track_workload_connection(&pi, &upstream) {
+            Ok(guard) => guard,
+            Err(e) => {
+                metrics::log_early_deny(
+                    &pi.metrics,
+                    conn.src,
+                    upstream_addr,
+                    Reporter::destination,
+                    e,
+                );
+                req.refuse();
+                return Ok(());
+            }
+        };
         // Connection has 15008, swap with the real port
         let conn = Connection {
             dst: upstream_addr,
@@ -254,29 +513,44 @@ impl Inbound {
             cluster_id: baggage.cluster_id,
             namespace: baggage.namespace,
             workload_name: baggage.workload_name,
+            app: baggage.service_name,
             revision: baggage.revision,
             ..Default::default()
         };
         let ds =
             proxy::guess_inbound_service(&rbac_ctx.conn, &for_host, upstream_service, &upstream);
-        let result_tracker = Arc::new(metrics::ConnectionResult::new(
-            rbac_ctx.conn.src,
-            rbac_ctx.conn.dst,
-            Some(hbone_addr),
-            start,
-            ConnectionOpen {
-                reporter: Reporter::destination,
-                source,
-                derived_source: Some(derived_source),
-                destination: Some(upstream),
-                connection_security_policy: metrics::SecurityPolicy::mutual_tls,
-                destination_service: ds,
-            },
-            pi.metrics.clone(),
-        ));
+        let latency = pi
+            .metrics
+            .latency_labels(Reporter::destination, ds.as_ref());
+        let result_tracker = Arc::new(
+            metrics::ConnectionResult::new(
+                connection_id,
+                rbac_ctx.conn.src,
+                rbac_ctx.conn.dst,
+                Some(hbone_addr),
+                start,
+                ConnectionOpen {
+                    reporter: Reporter::destination,
+                    source,
+                    derived_source: Some(derived_source),
+                    destination: Some(upstream),
+                    connection_security_policy: metrics::SecurityPolicy::mutual_tls,
+                    destination_service: ds,
+                },
+                pi.metrics.clone(),
+                pi.cfg.reloadable.access_log_format(),
+            )
+            .track(&connection_manager),
+        );
 
         let conn_guard = match connection_manager
-            .assert_rbac(&pi.state, &rbac_ctx, for_host)
+            .assert_rbac(
+                &pi.state,
+                &pi.metrics,
+                &rbac_ctx,
+                for_host,
+                InboundListener::Hbone,
+            )
             .await
         {
             Ok(cg) => cg,
@@ -284,28 +558,70 @@ impl Inbound {
                 Arc::into_inner(result_tracker)
                     .expect("arc is not shared yet")
                     .record_with_flag(Err(e), metrics::ResponseFlags::AuthorizationPolicyDenied);
-                return req.send_error(build_response(StatusCode::UNAUTHORIZED));
+                return req
+                    .send_error(build_response(StatusCode::UNAUTHORIZED))
+                    .await;
             }
         };
 
         let orig_src = enable_original_source.then_some(source_ip);
+        if udp {
+            // The PROXY protocol is not supported for UDP, so any application tunnel is ignored
+            let mut socket = match Self::connect_udp(&pi, orig_src, upstream_addr).await {
+                Err(err) => {
+                    result_tracker.record(Err(err));
+                    return req
+                        .send_error(build_response(StatusCode::SERVICE_UNAVAILABLE))
+                        .await;
+                }
+                Ok(socket) => socket,
+            };
+            debug!("connected udp to: {upstream_addr}");
+            let mut resp = build_response(StatusCode::OK);
+            resp.headers_mut().insert(
+                connect_udp::CAPSULE_PROTOCOL_HEADER,
+                http::HeaderValue::from_static(connect_udp::CAPSULE_PROTOCOL_ENABLED),
+            );
+            let stream = req.send_response(resp).await?;
+            let idle_timeout = pi
+                .cfg
+                .reloadable
+                .idle_timeout()
+                .unwrap_or(connect_udp::DEFAULT_IDLE_TIMEOUT);
+            let relay =
+                connect_udp::relay_tunnel(stream, &mut socket, &result_tracker, idle_timeout, true)
+                    .instrument(trace_span!("hbone udp server"));
+            let res = conn_guard.handle_connection(relay).await;
+            result_tracker.record(res);
+            return Ok(());
+        }
+        let connect_start = Instant::now();
         let stream = super::freebind_connect(orig_src, upstream_addr, pi.socket_factory.as_ref())
             .await
             .and_then(|s| {
+                metrics::observe_duration(
+                    &pi.metrics.tcp_connect_duration,
+                    &latency,
+                    connect_start.elapsed(),
+                );
                 s.set_nodelay(true)?;
+                super::set_socket_options(&s, &pi.cfg.socket_config, super::SocketClass::App);
+                super::set_dscp(&s, dscp);
                 Ok(s)
             });
         let mut stream = match stream {
             Err(err) => {
                 result_tracker.record(Err(err));
-                return req.send_error(build_response(StatusCode::SERVICE_UNAVAILABLE));
+                return req
+                    .send_error(build_response(StatusCode::SERVICE_UNAVAILABLE))
+                    .await;
             }
             Ok(stream) => stream,
         };
 
         debug!("connected to: {upstream_addr}");
 
-        let h2_stream = req.send_response(build_response(StatusCode::OK)).await?;
+        let upgraded = req.send_response(build_response(StatusCode::OK)).await?;
 
         let send = async {
             let result_tracker = result_tracker.clone();
@@ -317,7 +633,7 @@ impl Inbound {
                     .instrument(trace_span!("proxy protocol"))
                     .await?;
             }
-            copy::copy_bidirectional(h2_stream, stream, &result_tracker)
+            copy::copy_bidirectional(upgraded, stream, &result_tracker, pi.cfg.as_ref().into())
                 .instrument(trace_span!("hbone server"))
                 .await
         };
@@ -326,6 +642,32 @@ impl Inbound {
         Ok(())
     }
 
+    // connect_udp creates a UDP socket connected to the upstream, sending from the original source
+    // if requested and possible.
+    async fn connect_udp(
+        pi: &ProxyInputs,
+        orig_src: Option<IpAddr>,
+        addr: SocketAddr,
+    ) -> std::io::Result<UdpSocket> {
+        let unspecified: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let unspecified = SocketAddr::new(unspecified, 0);
+        let socket = match orig_src {
+            Some(src) if src != addr.ip() && src.is_ipv4() == addr.is_ipv4() => pi
+                .socket_factory
+                .udp_bind_transparent(SocketAddr::new(src, 0))
+                .or_else(|err| {
+                    warn!("failed to bind original source: {:?}", err);
+                    pi.socket_factory.udp_bind(unspecified)
+                })?,
+            _ => pi.socket_factory.udp_bind(unspecified)?,
+        };
+        socket.connect(addr).await?;
+        Ok(socket)
+    }
+
     async fn find_inbound_upstream(
         state: &DemandProxyState,
         conn: &Connection,
@@ -468,16 +810,18 @@ struct InboundCertProvider {
     cert_manager: Arc<SecretManager>,
     state: DemandProxyState,
     network: Strng,
+    tls_settings: crate::tls::TlsSettings,
+    session_lifetime: Option<Duration>,
+    trust_domain_aliases: Vec<Strng>,
 }
 
-#[async_trait::async_trait]
-impl crate::tls::ServerCertProvider for InboundCertProvider {
-    async fn fetch_cert(&mut self, fd: &TcpStream) -> Result<Arc<rustls::ServerConfig>, TlsError> {
-        let orig_dst_addr = crate::socket::orig_dst_addr_or_default(fd);
+impl InboundCertProvider {
+    // server_config returns the TLS config to serve connections to the workload at `orig_dst_addr`.
+    async fn server_config(&self, orig_dst_addr: IpAddr) -> Result<rustls::ServerConfig, TlsError> {
         let identity = {
             let wip = NetworkAddress {
                 network: self.network.clone(), // inbound cert provider gets cert for the dest, which must be on our network
-                address: orig_dst_addr.ip(),
+                address: orig_dst_addr,
             };
             self.state
                 .fetch_workload(&wip)
@@ -491,11 +835,110 @@ impl crate::tls::ServerCertProvider for InboundCertProvider {
             "fetching cert"
         );
         let cert = self.cert_manager.fetch_certificate(&identity).await?;
-        Ok(Arc::new(cert.server_config()?))
+        Ok(cert.server_config(
+            &self.tls_settings,
+            self.session_lifetime,
+            &self.trust_domain_aliases,
+            self.cert_manager.trust_bundles(),
+            self.cert_manager.revocation_lists(),
+        )?)
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::tls::ServerCertProvider for InboundCertProvider {
+    async fn fetch_cert(&mut self, fd: &TcpStream) -> Result<Arc<rustls::ServerConfig>, TlsError> {
+        let orig_dst_addr = crate::socket::orig_dst_addr_or_default(fd);
+        Ok(Arc::new(self.server_config(orig_dst_addr.ip()).await?))
     }
 }
 
-pub fn parse_forwarded_host(req: &H2Request) -> Option<String> {
+/// HboneRequest is an HBONE request, received over HTTP/2 or HTTP/3.
+pub(super) trait HboneRequest: Send + 'static {
+    type Stream: copy::BufferedSplitter + Send + 'static;
+
+    fn method(&self) -> &Method;
+
+    fn uri(&self) -> &http::Uri;
+
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue>;
+
+    fn protocol(&self) -> Option<&str>;
+
+    async fn send_error(self, resp: Response<()>) -> Result<(), Error>;
+
+    /// refuse resets the stream without sending a response, so the client may retry it elsewhere.
+    fn refuse(self);
+
+    async fn send_response(self, resp: Response<()>) -> Result<Self::Stream, Error>;
+}
+
+impl HboneRequest for H2Request {
+    type Stream = h2::H2Stream;
+
+    fn method(&self) -> &Method {
+        H2Request::method(self)
+    }
+
+    fn uri(&self) -> &http::Uri {
+        H2Request::uri(self)
+    }
+
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        H2Request::headers(self)
+    }
+
+    fn protocol(&self) -> Option<&str> {
+        H2Request::protocol(self)
+    }
+
+    async fn send_error(self, resp: Response<()>) -> Result<(), Error> {
+        H2Request::send_error(self, resp)
+    }
+
+    fn refuse(self) {
+        self.send_reset(::h2::Reason::REFUSED_STREAM)
+    }
+
+    async fn send_response(self, resp: Response<()>) -> Result<h2::H2Stream, Error> {
+        H2Request::send_response(self, resp).await
+    }
+}
+
+#[cfg(feature = "quic")]
+impl HboneRequest for h3::server::H3Request {
+    type Stream = h3::H3Stream;
+
+    fn method(&self) -> &Method {
+        h3::server::H3Request::method(self)
+    }
+
+    fn uri(&self) -> &http::Uri {
+        h3::server::H3Request::uri(self)
+    }
+
+    fn headers(&self) -> &http::HeaderMap<http::HeaderValue> {
+        h3::server::H3Request::headers(self)
+    }
+
+    fn protocol(&self) -> Option<&str> {
+        h3::server::H3Request::protocol(self)
+    }
+
+    async fn send_error(self, resp: Response<()>) -> Result<(), Error> {
+        h3::server::H3Request::send_error(self, resp).await
+    }
+
+    fn refuse(self) {
+        self.send_reset()
+    }
+
+    async fn send_response(self, resp: Response<()>) -> Result<h3::H3Stream, Error> {
+        h3::server::H3Request::send_response(self, resp).await
+    }
+}
+
+pub fn parse_forwarded_host<R: HboneRequest>(req: &R) -> Option<String> {
     req.headers()
         .get(http::header::FORWARDED)
         .and_then(|rh| rh.to_str().ok())
@@ -515,10 +958,7 @@ mod tests {
     use super::Inbound;
     use crate::strng;
 
-    use std::{
-        net::SocketAddr,
-        sync::{Arc, RwLock},
-    };
+    use std::{net::SocketAddr, sync::Arc};
 
     use crate::{
         rbac::Connection,
@@ -647,6 +1087,8 @@ mod tests {
                 subject_alt_names: vec![strng::format!("{name}.default.svc.cluster.local")],
                 waypoint: waypoint.service_attached(),
                 load_balancer: None,
+                session_affinity: Default::default(),
+                tls_origination: None,
             }
         });
 
@@ -682,7 +1124,7 @@ mod tests {
         }
 
         Ok(DemandProxyState::new(
-            Arc::new(RwLock::new(state)),
+            state::cell::ProxyStateCell::new(state),
             None,
             ResolverConfig::default(),
             ResolverOpts::default(),
@@ -15,24 +15,25 @@
 use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use drain::Watch;
 use tokio::net::{TcpListener, TcpStream};
 
-use tracing::{error, info, trace, Instrument};
+use tracing::{error, info, info_span, trace, warn, Instrument};
 
 use crate::config::ProxyMode;
-use crate::proxy::connection_manager::ConnectionManager;
+use crate::proxy::connection_manager::{ConnectionManager, InboundListener};
 use crate::proxy::metrics::Reporter;
-use crate::proxy::Error;
+use crate::proxy::mirror::{self, MirroredStream};
 use crate::proxy::{metrics, util, ProxyInputs};
+use crate::proxy::{ConnectionId, Error};
 use crate::state::workload::NetworkAddress;
 use crate::{assertions, copy, rbac, strng};
 use crate::{proxy, socket};
 
 pub(super) struct InboundPassthrough {
-    listener: TcpListener,
+    listeners: Vec<TcpListener>,
     pi: ProxyInputs,
     drain: Watch,
 }
@@ -42,50 +43,98 @@ impl InboundPassthrough {
         mut pi: ProxyInputs,
         drain: Watch,
     ) -> Result<InboundPassthrough, Error> {
-        let listener: TcpListener = pi
-            .socket_factory
-            .tcp_bind(pi.cfg.inbound_plaintext_addr)
-            .map_err(|e| Error::Bind(pi.cfg.inbound_plaintext_addr, e))?;
+        let mut listeners = Vec::with_capacity(pi.cfg.inbound_plaintext_addr.len());
+        // Original source is only usable if every listener supports it.
+        let mut all_transparent = true;
+        for addr in pi.cfg.inbound_plaintext_addr.iter().copied() {
+            let listener: TcpListener = pi
+                .socket_factory
+                .tcp_bind(addr)
+                .map_err(|e| Error::Bind(addr, e))?;
 
-        let transparent = super::maybe_set_transparent(&pi, &listener)?;
+            let transparent = super::maybe_set_transparent(&pi, &listener)?;
+            all_transparent &= transparent;
+
+            info!(
+                address=%listener.local_addr().expect("local_addr available"),
+                component="inbound plaintext",
+                transparent,
+                "listener established",
+            );
+            listeners.push(listener);
+        }
         // Override with our explicitly configured setting
         if pi.cfg.enable_original_source.is_none() {
             let mut cfg = (*pi.cfg).clone();
-            cfg.enable_original_source = Some(transparent);
+            cfg.enable_original_source = Some(all_transparent);
             pi.cfg = Arc::new(cfg);
         }
 
-        info!(
-            address=%listener.local_addr().expect("local_addr available"),
-            component="inbound plaintext",
-            transparent,
-            "listener established",
-        );
         Ok(InboundPassthrough {
-            listener,
+            listeners,
             pi,
             drain,
         })
     }
 
-    pub(super) fn address(&self) -> SocketAddr {
-        self.listener.local_addr().expect("local_addr available")
+    pub(super) fn addresses(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .map(|l| l.local_addr().expect("local_addr available"))
+            .collect()
     }
 
     pub(super) async fn run(self, illegal_ports: Arc<HashSet<u16>>) {
-        let accept = async move {
+        let drain_connection_manager = self.pi.connection_manager.clone();
+        let drain_metrics = self.pi.metrics.clone();
+        let drain_timeout = self.pi.cfg.drain_timeout;
+        let accepts = self.listeners.into_iter().map(|listener| {
+            let pi = self.pi.clone();
+            let illegal_ports = illegal_ports.clone();
+            Self::accept(listener, pi, illegal_ports)
+        });
+        let accept = futures::future::join_all(accepts);
+        // Stop accepting once we drain, then give in-flight connections up to the drain timeout
+        // to complete on their own before forcibly closing them.
+        tokio::select! {
+            _ = accept => {}
+            release = self.drain.signaled() => {
+                info!("inbound passthrough draining");
+                let cm = drain_connection_manager;
+                let drained = cm.wait_for_drain(InboundListener::Passthrough);
+                if tokio::time::timeout(drain_timeout, drained).await.is_err() {
+                    let closed = cm.close_all(InboundListener::Passthrough).await;
+                    drain_metrics.connections_force_closed.inc_by(closed);
+                    warn!(closed, "inbound passthrough drain timed out, closed remaining connections");
+                }
+                info!("inbound passthrough drained");
+                drop(release);
+            }
+        }
+    }
+
+    // accept serves connections from a single listener until the runtime shuts down.
+    async fn accept(listener: TcpListener, pi: ProxyInputs, illegal_ports: Arc<HashSet<u16>>) {
+        async move {
             loop {
+                proxy::throttle_accept(&pi).await;
                 // Asynchronously wait for an inbound socket.
-                let socket = self.listener.accept().await;
-                let pi = self.pi.clone();
+                let socket = listener.accept().await;
                 let illegal_ports = illegal_ports.clone();
-
-                let connection_manager = self.pi.connection_manager.clone();
+                let connection_manager = pi.connection_manager.clone();
+                let pi = pi.clone();
                 match socket {
                     Ok((stream, remote)) => {
+                        let Ok(permit) = proxy::admit_connection(&pi, &stream) else {
+                            continue;
+                        };
+                        let connection_id = ConnectionId::new();
+                        let span = info_span!("inbound passthrough", connection_id=%connection_id);
                         let serve_client = async move {
+                            let _permit = permit;
                             Self::proxy_inbound_plaintext(
                                 pi, // pi cloned above; OK to move
+                                connection_id,
                                 socket::to_canonical(remote),
                                 stream,
                                 illegal_ports,
@@ -93,7 +142,7 @@ impl InboundPassthrough {
                             )
                             .await
                         }
-                        .in_current_span();
+                        .instrument(span);
 
                         // This is pretty large right now. Fortunately with pooling this is less problematic than outbound.
                         assertions::size_between_ref(3000, 5000, &serve_client);
@@ -108,26 +157,24 @@ impl InboundPassthrough {
                 }
             }
         }
-        .in_current_span();
-        // Stop accepting once we drain.
-        // Note: we are *not* waiting for all connections to be closed. In the future, we may consider
-        // this, but will need some timeout period, as we have no back-pressure mechanism on connections.
-        tokio::select! {
-            res = accept => { res }
-            _ = self.drain.signaled() => {
-                info!("inbound passthrough drained");
-            }
-        }
+        .in_current_span()
+        .await
     }
 
     async fn proxy_inbound_plaintext(
         pi: ProxyInputs,
+        connection_id: ConnectionId,
         source_addr: SocketAddr,
         mut inbound_stream: TcpStream,
         illegal_ports: Arc<HashSet<u16>>,
         connection_manager: ConnectionManager,
     ) {
         let start = Instant::now();
+        super::set_socket_options(
+            &inbound_stream,
+            &pi.cfg.socket_config,
+            super::SocketClass::App,
+        );
         let dest_addr = socket::orig_dst_addr_or_default(&inbound_stream);
         // Check if it is an illegal call to ourself, which could trampoline to illegal addresses or
         // lead to infinite loops
@@ -140,6 +187,7 @@ impl InboundPassthrough {
         };
         if illegal_call {
             metrics::log_early_deny(
+                &pi.metrics,
                 source_addr,
                 dest_addr,
                 Reporter::destination,
@@ -155,6 +203,7 @@ impl InboundPassthrough {
             pi.state.fetch_workload_services(&network_addr).await
         else {
             metrics::log_early_deny(
+                &pi.metrics,
                 source_addr,
                 dest_addr,
                 Reporter::destination,
@@ -162,6 +211,32 @@ impl InboundPassthrough {
             );
             return;
         };
+        if let Err(e) = proxy::check_inbound_port(&pi, &upstream, dest_addr.port()) {
+            metrics::log_early_deny(
+                &pi.metrics,
+                source_addr,
+                dest_addr,
+                Reporter::destination,
+                e,
+            );
+            return;
+        }
+        let _workload_guard = match proxy::track_workload_connection(&pi, &upstream) {
+            Ok(guard) => guard,
+            Err(e) => {
+                metrics::log_early_deny(
+                    &pi.metrics,
+                    source_addr,
+                    dest_addr,
+                    Reporter::destination,
+                    e,
+                );
+                // Reset the connection rather than closing it gracefully, so the client does not
+                // mistake the rejection for a response.
+                let _ = socket2::SockRef::from(&inbound_stream).set_linger(Some(Duration::ZERO));
+                return;
+            }
+        };
 
         let rbac_ctx = crate::state::ProxyRbacContext {
             conn: rbac::Connection {
@@ -191,25 +266,40 @@ impl InboundPassthrough {
             identity: rbac_ctx.conn.src_identity.clone(),
             ..Default::default()
         };
+        let mirror_address = upstream.mirror_address;
         let ds = proxy::guess_inbound_service(&rbac_ctx.conn, &None, upstream_service, &upstream);
-        let result_tracker = Arc::new(metrics::ConnectionResult::new(
-            source_addr,
-            dest_addr,
-            None,
-            start,
-            metrics::ConnectionOpen {
-                reporter: Reporter::destination,
-                source: source_workload,
-                derived_source: Some(derived_source),
-                destination: Some(upstream),
-                connection_security_policy: metrics::SecurityPolicy::unknown,
-                destination_service: ds,
-            },
-            pi.metrics,
-        ));
+        let latency = pi
+            .metrics
+            .latency_labels(Reporter::destination, ds.as_ref());
+        let result_tracker = Arc::new(
+            metrics::ConnectionResult::new(
+                connection_id,
+                source_addr,
+                dest_addr,
+                None,
+                start,
+                metrics::ConnectionOpen {
+                    reporter: Reporter::destination,
+                    source: source_workload,
+                    derived_source: Some(derived_source),
+                    destination: Some(upstream),
+                    connection_security_policy: metrics::SecurityPolicy::unknown,
+                    destination_service: ds,
+                },
+                pi.metrics.clone(),
+                pi.cfg.reloadable.access_log_format(),
+            )
+            .track(&connection_manager),
+        );
 
         let conn_guard = match connection_manager
-            .assert_rbac(&pi.state, &rbac_ctx, None)
+            .assert_rbac(
+                &pi.state,
+                &pi.metrics,
+                &rbac_ctx,
+                None,
+                InboundListener::Passthrough,
+            )
             .await
         {
             Ok(cg) => cg,
@@ -227,17 +317,50 @@ impl InboundPassthrough {
             None
         };
 
+        let dscp = super::upstream_dscp(
+            super::received_dscp(&inbound_stream, &pi.cfg.socket_config),
+            &pi.cfg.socket_config,
+            super::SocketClass::App,
+        );
         let send = async {
             let result_tracker = result_tracker.clone();
             trace!(%source_addr, %dest_addr, component="inbound plaintext", "connecting...");
 
+            let connect_start = Instant::now();
             let mut outbound =
                 super::freebind_connect(orig_src, dest_addr, pi.socket_factory.as_ref())
                     .await
                     .map_err(Error::ConnectionFailed)?;
+            metrics::observe_duration(
+                &pi.metrics.tcp_connect_duration,
+                &latency,
+                connect_start.elapsed(),
+            );
+            super::set_socket_options(&outbound, &pi.cfg.socket_config, super::SocketClass::App);
+            super::set_dscp(&outbound, dscp);
 
             trace!(%source_addr, destination=%dest_addr, component="inbound plaintext", "connected");
-            copy::copy_bidirectional(&mut inbound_stream, &mut outbound, &result_tracker).await
+            match mirror_address {
+                Some(addr) => {
+                    let mirror = mirror::spawn(addr, pi.socket_factory.clone());
+                    copy::copy_bidirectional_tcp(
+                        &mut MirroredStream::new(&mut inbound_stream, mirror),
+                        &mut outbound,
+                        &result_tracker,
+                        pi.cfg.as_ref().into(),
+                    )
+                    .await
+                }
+                None => {
+                    copy::copy_bidirectional_tcp(
+                        &mut inbound_stream,
+                        &mut outbound,
+                        &result_tracker,
+                        pi.cfg.as_ref().into(),
+                    )
+                    .await
+                }
+            }
         };
 
         let res = conn_guard.handle_connection(send).await;
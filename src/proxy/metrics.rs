@@ -12,21 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::fmt::Write;
+use std::io;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{atomic, Arc};
-use std::time::Instant;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder};
 use prometheus_client::metrics::counter::{Atomic, Counter};
 use prometheus_client::metrics::family::Family;
-use prometheus_client::registry::Registry;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::Histogram;
+use prometheus_client::registry::{Registry, Unit};
 
 use tracing::event;
 
+use crate::config::AccessLogFormat;
 use crate::identity::Identity;
 use crate::metrics::{DefaultedUnknown, DeferRecorder, Deferred, IncrementRecorder};
+use crate::proxy::access_log::AccessLogEntry;
+use crate::proxy::connection_manager::{
+    ConnectionManager, ConnectionStats, LiveConnectionGuard, LiveConnectionInfo,
+};
+use crate::proxy::{ConnectionId, Error};
 
 use crate::state::service::ServiceDescription;
 use crate::state::workload::Workload;
@@ -34,13 +44,39 @@ use crate::strng::{RichStrng, Strng};
 
 pub struct Metrics {
     pub connection_opens: Family<CommonTrafficLabels, Counter>,
-    pub connection_close: Family<CommonTrafficLabels, Counter>,
+    pub connection_close: Family<ConnectionCloseLabels, Counter>,
     pub received_bytes: Family<CommonTrafficLabels, Counter>,
     pub sent_bytes: Family<CommonTrafficLabels, Counter>,
+    pub connections_force_closed: Counter,
+    pub connections_rejected_limit: Counter,
+    pub accepts_throttled: Counter,
+    pub connections_shed: Counter,
+    pub connection_budget_limit: Gauge,
+    pub connection_budget_used: Gauge,
+    pub tls_handshakes: Family<TlsHandshakeLabels, Counter>,
+    pub protocol_mismatches: Family<ProtocolMismatchLabels, Counter>,
+    pub preface_timeouts: Family<PrefaceTimeoutLabels, Counter>,
+    pub errors: Family<ErrorLabels, Counter>,
+    pub connections_denied_unknown_destination: Counter,
+    pub connections_excluded_port: Counter,
+    pub rbac_shadow_denied: Counter,
+    pub rbac_denied: Family<RbacDeniedLabels, Counter>,
+    pub connect_retries: Counter,
+    pub connect_retries_exhausted: Counter,
+    pub outlier_ejections: Counter,
+    pub outlier_ejected_endpoints: Gauge,
+
+    pub tcp_connect_duration: Family<LatencyLabels, Histogram>,
+    pub tls_handshake_duration: Family<LatencyLabels, Histogram>,
+    pub hbone_stream_duration: Family<LatencyLabels, Histogram>,
+    pub connection_duration: Family<LatencyLabels, Histogram>,
 
     // on-demand DNS is not a part of DNS proxy, but part of ztunnel proxy itself
     pub on_demand_dns: Family<OnDemandDnsLabels, Counter>,
     pub on_demand_dns_cache_misses: Family<OnDemandDnsLabels, Counter>,
+
+    // label_filter drops configured labels from traffic metrics before they are recorded
+    label_filter: LabelFilter,
 }
 
 impl Metrics {
@@ -75,6 +111,90 @@ impl Metrics {
 
 impl DeferRecorder for Metrics {}
 
+/// LabelFilter drops high cardinality labels from traffic metrics. Dropped labels are reported as
+/// "unknown", so series which only differ by them are aggregated.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct LabelFilter {
+    // Bit i is set if DROPPABLE_LABELS[i] is dropped
+    dropped: u32,
+}
+
+macro_rules! droppable_labels {
+    ($($label:ident),* $(,)?) => {
+        /// The traffic metric labels that LabelFilter can drop.
+        pub const DROPPABLE_LABELS: &[&str] = &[$(stringify!($label)),*];
+
+        impl LabelFilter {
+            fn drop_labels(&self, tl: &mut CommonTrafficLabels) {
+                let mut bit = 1;
+                $(
+                    if self.dropped & bit != 0 {
+                        tl.$label = Default::default();
+                    }
+                    bit <<= 1;
+                )*
+                let _ = bit;
+            }
+        }
+    };
+}
+
+droppable_labels!(
+    source_workload,
+    source_canonical_service,
+    source_canonical_revision,
+    source_workload_namespace,
+    source_principal,
+    source_app,
+    source_version,
+    source_cluster,
+    destination_service,
+    destination_service_namespace,
+    destination_service_name,
+    destination_workload,
+    destination_canonical_service,
+    destination_canonical_revision,
+    destination_workload_namespace,
+    destination_principal,
+    destination_app,
+    destination_version,
+    destination_cluster,
+);
+
+impl LabelFilter {
+    /// Creates a filter dropping the given labels, which must be in DROPPABLE_LABELS.
+    pub fn new<S: AsRef<str>>(labels: &[S]) -> Result<Self, String> {
+        let mut dropped = 0;
+        for label in labels {
+            let label = label.as_ref();
+            let i = DROPPABLE_LABELS
+                .iter()
+                .position(|l| *l == label)
+                .ok_or_else(|| label.to_string())?;
+            dropped |= 1 << i;
+        }
+        Ok(LabelFilter { dropped })
+    }
+
+    fn drops(&self, label: &str) -> bool {
+        self.dropped != 0
+            && DROPPABLE_LABELS
+                .iter()
+                .position(|l| *l == label)
+                .is_some_and(|i| self.dropped & (1 << i) != 0)
+    }
+
+    // apply returns the labels to record metrics with.
+    fn apply<'a>(&self, tl: &'a CommonTrafficLabels) -> Cow<'a, CommonTrafficLabels> {
+        if self.dropped == 0 {
+            return Cow::Borrowed(tl);
+        }
+        let mut tl = tl.clone();
+        self.drop_labels(&mut tl);
+        Cow::Owned(tl)
+    }
+}
+
 #[derive(Clone, Copy, Default, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum Reporter {
     #[default]
@@ -99,6 +219,15 @@ pub enum ResponseFlags {
     AuthorizationPolicyDenied,
 }
 
+impl std::fmt::Display for ResponseFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseFlags::None => f.write_str("-"),
+            ResponseFlags::AuthorizationPolicyDenied => f.write_str("DENY"),
+        }
+    }
+}
+
 impl EncodeLabelValue for ResponseFlags {
     fn encode(&self, writer: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
         match self {
@@ -108,6 +237,130 @@ impl EncodeLabelValue for ResponseFlags {
     }
 }
 
+/// ConnectionCloseReason describes why a connection was closed.
+#[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ConnectionCloseReason {
+    /// Both sides closed the connection normally.
+    #[default]
+    completed,
+    /// The connection was closed by a drain, for example on shutdown.
+    drained,
+    /// The connection was closed because authorization policy no longer allows it.
+    policy_revoked,
+    /// The connection was closed after transferring no data for the idle timeout.
+    idle_timeout,
+    /// The connection was reset by one of the peers.
+    peer_reset,
+    /// The connection failed for any other reason.
+    error,
+}
+
+impl ConnectionCloseReason {
+    pub fn from_result<E: std::error::Error + 'static>(res: &Result<(), E>) -> Self {
+        let Err(err) = res else {
+            return ConnectionCloseReason::completed;
+        };
+        let err: &(dyn std::error::Error + 'static) = err;
+        if let Some(err) = err.downcast_ref::<Error>() {
+            return match err {
+                Error::DrainTimeOut => ConnectionCloseReason::drained,
+                Error::AuthorizationPolicyLateRejection => ConnectionCloseReason::policy_revoked,
+                Error::IdleTimeout => ConnectionCloseReason::idle_timeout,
+                Error::Io(e) | Error::ConnectionFailed(e) if is_reset(e) => {
+                    ConnectionCloseReason::peer_reset
+                }
+                Error::H2(e) if e.is_reset() => ConnectionCloseReason::peer_reset,
+                _ => ConnectionCloseReason::error,
+            };
+        }
+        match err.downcast_ref::<io::Error>() {
+            Some(e) if is_reset(e) => ConnectionCloseReason::peer_reset,
+            _ => ConnectionCloseReason::error,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionCloseReason::completed => "completed",
+            ConnectionCloseReason::drained => "drained",
+            ConnectionCloseReason::policy_revoked => "policy_revoked",
+            ConnectionCloseReason::idle_timeout => "idle_timeout",
+            ConnectionCloseReason::peer_reset => "peer_reset",
+            ConnectionCloseReason::error => "error",
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionCloseReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// ErrorCode is a coarse category of connection failure, suitable for aggregation.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
+pub enum ErrorCode {
+    /// A listener or local socket could not be bound.
+    bind,
+    /// Connecting to, or exchanging data with, a peer failed.
+    connect,
+    /// A TLS handshake failed, or no certificate was available for it.
+    tls,
+    /// The connection was denied by authorization policy.
+    rbac,
+    /// The configuration received over XDS had no usable route for the connection.
+    xds,
+    /// A peer did not speak the expected protocol.
+    protocol,
+}
+
+impl ErrorCode {
+    pub fn from_result<E: std::error::Error + 'static>(res: &Result<(), E>) -> Option<Self> {
+        res.as_ref().err().map(|err| Self::from_error(err))
+    }
+
+    pub fn from_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(err) = err.downcast_ref::<Error>() {
+            return err.code();
+        }
+        if err.is::<io::Error>() {
+            return ErrorCode::connect;
+        }
+        ErrorCode::protocol
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::bind => "bind",
+            ErrorCode::connect => "connect",
+            ErrorCode::tls => "tls",
+            ErrorCode::rbac => "rbac",
+            ErrorCode::xds => "xds",
+            ErrorCode::protocol => "protocol",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// is_reset checks if an IO error was caused by the peer resetting the connection, either directly
+// or through an HTTP/2 stream reset.
+fn is_reset(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    ) || e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<::h2::Error>())
+        .is_some_and(|e| e.is_reset())
+}
+
 #[derive(Default, Copy, Clone, Debug, Hash, PartialEq, Eq, EncodeLabelValue)]
 pub enum SecurityPolicy {
     #[default]
@@ -205,6 +458,13 @@ impl From<ConnectionOpen> for CommonTrafficLabels {
     }
 }
 
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ConnectionCloseLabels {
+    #[prometheus(flatten)]
+    common: CommonTrafficLabels,
+    reason: ConnectionCloseReason,
+}
+
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
 pub struct CommonTrafficLabels {
     reporter: Reporter,
@@ -236,6 +496,116 @@ pub struct CommonTrafficLabels {
     connection_security_policy: SecurityPolicy,
 }
 
+// Buckets, in seconds, for connection setup latencies: TCP connects, TLS handshakes and HBONE streams.
+const SETUP_DURATION_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// Buckets, in seconds, for the lifetime of connections, from short requests to long-lived streams.
+const CONNECTION_DURATION_BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 1800.0, 3600.0,
+];
+
+#[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct LatencyLabels {
+    pub reporter: Reporter,
+    // Unknown for pooled HBONE connections, which are shared by all services of a workload
+    pub destination_service: DefaultedUnknown<RichStrng>,
+}
+
+impl LatencyLabels {
+    pub fn new(reporter: Reporter, destination_service: Option<&ServiceDescription>) -> Self {
+        LatencyLabels {
+            reporter,
+            destination_service: destination_service.map(|s| s.hostname.clone()).into(),
+        }
+    }
+}
+
+// observe_duration records a duration in a latency histogram.
+pub fn observe_duration(
+    family: &Family<LatencyLabels, Histogram>,
+    labels: &LatencyLabels,
+    elapsed: Duration,
+) {
+    family.get_or_create(labels).observe(elapsed.as_secs_f64());
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ProtocolMismatchLabels {
+    pub detected: DetectedProtocol,
+}
+
+/// DetectedProtocol is the protocol a client appears to speak, judged from the first bytes it sends.
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum DetectedProtocol {
+    /// The client started a TLS handshake.
+    tls,
+    /// The client sent something other than a TLS handshake.
+    plaintext,
+    /// The client sent nothing before the detection timeout. It is likely waiting for the server to
+    /// speak first, as clients of protocols like MySQL and SMTP do.
+    server_first,
+}
+
+impl std::fmt::Display for DetectedProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DetectedProtocol::tls => "tls",
+            DetectedProtocol::plaintext => "plaintext",
+            DetectedProtocol::server_first => "server_first",
+        })
+    }
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct ErrorLabels {
+    pub reporter: Reporter,
+    pub code: ErrorCode,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct PrefaceTimeoutLabels {
+    pub stage: PrefaceStage,
+}
+
+/// PrefaceStage is a step in setting up an inbound HBONE connection, before any traffic flows.
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum PrefaceStage {
+    /// Waiting for the client to send anything after the TCP connection is accepted.
+    first_byte,
+    /// Reading the PROXY protocol header, if any, and completing the TLS handshake.
+    tls_handshake,
+    /// Completing the HTTP/2 handshake and receiving the first CONNECT request.
+    hbone_connect,
+}
+
+impl std::fmt::Display for PrefaceStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PrefaceStage::first_byte => "first_byte",
+            PrefaceStage::tls_handshake => "tls_handshake",
+            PrefaceStage::hbone_connect => "hbone_connect",
+        })
+    }
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct TlsHandshakeLabels {
+    pub kind: TlsHandshakeKind,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct RbacDeniedLabels {
+    // Unknown if the connection was denied because no ALLOW policy matched it
+    pub policy: DefaultedUnknown<RichStrng>,
+}
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum TlsHandshakeKind {
+    Full,
+    Resumed,
+}
+
 #[derive(Clone, Hash, Default, Debug, PartialEq, Eq, EncodeLabelSet)]
 pub struct OnDemandDnsLabels {
     // on-demand DNS client information is just nice-to-have
@@ -275,7 +645,30 @@ impl OnDemandDnsLabels {
     }
 }
 
+fn setup_duration_family() -> Family<LatencyLabels, Histogram> {
+    Family::new_with_constructor(|| Histogram::new(SETUP_DURATION_BUCKETS.into_iter()))
+}
+
 impl Metrics {
+    // with_label_filter drops the filtered labels from traffic metrics recorded from now on.
+    pub fn with_label_filter(mut self, label_filter: LabelFilter) -> Self {
+        self.label_filter = label_filter;
+        self
+    }
+
+    // latency_labels returns the labels to record a latency histogram with.
+    pub fn latency_labels(
+        &self,
+        reporter: Reporter,
+        destination_service: Option<&ServiceDescription>,
+    ) -> LatencyLabels {
+        let mut labels = LatencyLabels::new(reporter, destination_service);
+        if self.label_filter.drops("destination_service") {
+            labels.destination_service = Default::default();
+        }
+        labels
+    }
+
     pub fn new(registry: &mut Registry) -> Self {
         let connection_opens = Family::default();
         registry.register(
@@ -302,6 +695,144 @@ impl Metrics {
             "The size of total bytes sent during response in case of a TCP connection",
             sent_bytes.clone(),
         );
+        let connections_force_closed = Counter::default();
+        registry.register(
+            "connections_force_closed",
+            "The total number of connections forcibly closed because they did not complete within the drain timeout",
+            connections_force_closed.clone(),
+        );
+        let connections_rejected_limit = Counter::default();
+        registry.register(
+            "connections_rejected_limit",
+            "The total number of inbound connections rejected because the destination workload reached its connection limit",
+            connections_rejected_limit.clone(),
+        );
+        let accepts_throttled = Counter::default();
+        registry.register(
+            "accepts_throttled",
+            "The total number of connection accepts delayed by the connection rate limit",
+            accepts_throttled.clone(),
+        );
+        let connections_shed = Counter::default();
+        registry.register(
+            "connections_shed",
+            "The total number of accepted connections reset immediately because the proxy was at its connection limit",
+            connections_shed.clone(),
+        );
+        let connection_budget_limit = Gauge::default();
+        registry.register(
+            "connection_budget_limit",
+            "The maximum number of connections the proxy handles at once, across all listeners",
+            connection_budget_limit.clone(),
+        );
+        let connection_budget_used = Gauge::default();
+        registry.register(
+            "connection_budget_used",
+            "The number of connections currently counted against the connection limit",
+            connection_budget_used.clone(),
+        );
+        let connections_denied_unknown_destination = Counter::default();
+        registry.register(
+            "connections_denied_unknown_destination",
+            "The total number of outbound connections denied because the destination is not a known workload",
+            connections_denied_unknown_destination.clone(),
+        );
+        let connections_excluded_port = Counter::default();
+        registry.register(
+            "connections_excluded_port",
+            "The total number of inbound connections refused because the destination port is excluded from capture for the workload",
+            connections_excluded_port.clone(),
+        );
+        let rbac_shadow_denied = Counter::default();
+        registry.register(
+            "rbac_shadow_denied",
+            "The total number of inbound connections that dry-run authorization policies would have denied",
+            rbac_shadow_denied.clone(),
+        );
+        let rbac_denied = Family::default();
+        registry.register(
+            "rbac_denied",
+            "The total number of inbound connections denied by authorization policies, by the DENY policy responsible (unstable)",
+            rbac_denied.clone(),
+        );
+        let connect_retries = Counter::default();
+        registry.register(
+            "outbound_connect_retries",
+            "The total number of outbound connects retried against another endpoint after the selected endpoint failed",
+            connect_retries.clone(),
+        );
+        let connect_retries_exhausted = Counter::default();
+        registry.register(
+            "outbound_connect_retries_exhausted",
+            "The total number of outbound connections that failed after using their whole retry budget",
+            connect_retries_exhausted.clone(),
+        );
+        let outlier_ejections = Counter::default();
+        registry.register(
+            "outlier_ejections",
+            "The total number of endpoints ejected from outbound endpoint selection after consecutive connect failures",
+            outlier_ejections.clone(),
+        );
+        let outlier_ejected_endpoints = Gauge::default();
+        registry.register(
+            "outlier_ejected_endpoints",
+            "The number of endpoints currently ejected from outbound endpoint selection",
+            outlier_ejected_endpoints.clone(),
+        );
+        let tls_handshakes = Family::default();
+        registry.register(
+            "tls_handshakes",
+            "The total number of completed inbound HBONE TLS handshakes, by whether the session was resumed (unstable)",
+            tls_handshakes.clone(),
+        );
+        let protocol_mismatches = Family::default();
+        registry.register(
+            "inbound_protocol_mismatches",
+            "The total number of connections to the inbound HBONE port that did not start a TLS handshake, by the protocol detected instead",
+            protocol_mismatches.clone(),
+        );
+        let preface_timeouts = Family::default();
+        registry.register(
+            "inbound_preface_timeouts",
+            "The total number of inbound HBONE connections closed for not completing a stage of connection setup in time, by stage",
+            preface_timeouts.clone(),
+        );
+        let errors = Family::default();
+        registry.register(
+            "proxy_errors",
+            "The total number of connections that failed, by error code",
+            errors.clone(),
+        );
+        let tcp_connect_duration = setup_duration_family();
+        registry.register_with_unit(
+            "tcp_connect_duration",
+            "The time taken to establish upstream TCP connections (unstable)",
+            Unit::Seconds,
+            tcp_connect_duration.clone(),
+        );
+        let tls_handshake_duration = setup_duration_family();
+        registry.register_with_unit(
+            "tls_handshake_duration",
+            "The time taken to complete outbound HBONE TLS handshakes (unstable)",
+            Unit::Seconds,
+            tls_handshake_duration.clone(),
+        );
+        let hbone_stream_duration = setup_duration_family();
+        registry.register_with_unit(
+            "hbone_stream_duration",
+            "The time taken to establish outbound HBONE streams, including any new connection they required (unstable)",
+            Unit::Seconds,
+            hbone_stream_duration.clone(),
+        );
+        let connection_duration = Family::<LatencyLabels, Histogram>::new_with_constructor(|| {
+            Histogram::new(CONNECTION_DURATION_BUCKETS.into_iter())
+        });
+        registry.register_with_unit(
+            "tcp_connection_duration",
+            "The total duration of TCP connections, from accept to close (unstable)",
+            Unit::Seconds,
+            connection_duration.clone(),
+        );
         let on_demand_dns = Family::default();
         registry.register(
             "on_demand_dns",
@@ -320,14 +851,39 @@ impl Metrics {
             connection_close,
             received_bytes,
             sent_bytes,
+            connections_force_closed,
+            connections_rejected_limit,
+            accepts_throttled,
+            connections_shed,
+            connection_budget_limit,
+            connection_budget_used,
+            tls_handshakes,
+            protocol_mismatches,
+            preface_timeouts,
+            errors,
+            connections_denied_unknown_destination,
+            connections_excluded_port,
+            rbac_shadow_denied,
+            rbac_denied,
+            connect_retries,
+            connect_retries_exhausted,
+            outlier_ejections,
+            outlier_ejected_endpoints,
+            tcp_connect_duration,
+            tls_handshake_duration,
+            hbone_stream_duration,
+            connection_duration,
             on_demand_dns,
             on_demand_dns_cache_misses,
+            label_filter: LabelFilter::default(),
         }
     }
 }
 
 /// ConnectionResult abstracts recording a metric and emitting an access log upon a connection completion
 pub struct ConnectionResult {
+    // id uniquely identifies this connection in logs and debug output
+    id: ConnectionId,
     // Src address and name
     src: (SocketAddr, Option<RichStrng>),
     // Dst address and name
@@ -342,24 +898,34 @@ pub struct ConnectionResult {
     tl: CommonTrafficLabels,
     metrics: Arc<Metrics>,
 
-    // sent records the number of bytes sent on this connection
-    sent: AtomicU64,
+    // stats records the number of bytes sent and received on this connection
+    stats: Arc<ConnectionStats>,
     // sent_metric records the number of bytes sent on this connection to the aggregated metric counter
     sent_metric: Counter,
-    // recv records the number of bytes received on this connection
-    recv: AtomicU64,
     // recv_metric records the number of bytes received on this connection to the aggregated metric counter
     recv_metric: Counter,
+
+    // live reports this connection to the connection manager for as long as it is open, if tracked
+    live: Option<LiveConnectionGuard>,
+
+    // access_log is the format the access log is written in once the connection completes
+    access_log: AccessLogFormat,
 }
 
 // log_early_deny allows logging a connection is denied before we have enough information to emit proper
-// access logs/metrics
-pub fn log_early_deny<E: std::error::Error>(
+// access logs/metrics. Only the error code is recorded in metrics.
+pub fn log_early_deny<E: std::error::Error + 'static>(
+    metrics: &Metrics,
     src: SocketAddr,
     dst: SocketAddr,
     reporter: Reporter,
     err: E,
 ) {
+    let code = ErrorCode::from_error(&err);
+    metrics
+        .errors
+        .get_or_create(&ErrorLabels { reporter, code })
+        .inc();
     event!(
             target: "access",
             parent: None,
@@ -375,6 +941,7 @@ pub fn log_early_deny<E: std::error::Error>(
             },
 
             error = %err,
+            error.code = %code,
 
             "connection failed"
     );
@@ -407,7 +974,9 @@ macro_rules! access_log {
     };
 }
 impl ConnectionResult {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        id: ConnectionId,
         src: SocketAddr,
         dst: SocketAddr,
         // If using hbone, the inner HBONE address
@@ -416,6 +985,7 @@ impl ConnectionResult {
         start: Instant,
         conn: ConnectionOpen,
         metrics: Arc<Metrics>,
+        access_log: AccessLogFormat,
     ) -> Self {
         // for src and dest, try to get pod name but fall back to "canonical service"
         let mut src = (src, conn.source.as_ref().map(|wl| wl.name.clone().into()));
@@ -424,7 +994,8 @@ impl ConnectionResult {
             conn.destination.as_ref().map(|wl| wl.name.clone().into()),
         );
         let tl = CommonTrafficLabels::from(conn);
-        metrics.connection_opens.get_or_create(&tl).inc();
+        let metric_labels = metrics.label_filter.apply(&tl);
+        metrics.connection_opens.get_or_create(&metric_labels).inc();
 
         let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
 
@@ -435,6 +1006,8 @@ impl ConnectionResult {
             parent: None,
             tracing::Level::DEBUG,
 
+            connection_id = %id,
+
             src.addr = %src.0,
             src.workload = src.1.as_deref().map(display),
             src.namespace = tl.source_workload_namespace.display(),
@@ -460,11 +1033,10 @@ impl ConnectionResult {
         // With the raw Counter, we increment is a simple atomic add operation (~1ns).
         // Fetching the metric itself is ~300ns; fast, but we call it on each read/write so it would
         // add up.
-        let sent_metric = metrics.sent_bytes.get_or_create(&tl).clone();
-        let recv_metric = metrics.received_bytes.get_or_create(&tl).clone();
-        let sent = atomic::AtomicU64::new(0);
-        let recv = atomic::AtomicU64::new(0);
+        let sent_metric = metrics.sent_bytes.get_or_create(&metric_labels).clone();
+        let recv_metric = metrics.received_bytes.get_or_create(&metric_labels).clone();
         Self {
+            id,
             src,
             dst,
             hbone_target,
@@ -472,24 +1044,56 @@ impl ConnectionResult {
             tl,
             metrics,
 
-            sent,
+            stats: Default::default(),
             sent_metric,
-            recv,
             recv_metric,
+            live: None,
+            access_log,
         }
     }
 
+    // track reports this connection, along with the bytes transferred, as live in the connection manager
+    // until the result is dropped.
+    pub fn track(mut self, cm: &ConnectionManager) -> Self {
+        let tl = &self.tl;
+        let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
+        let info = LiveConnectionInfo {
+            connection_id: self.id,
+            direction: if tl.reporter == Reporter::source {
+                "outbound"
+            } else {
+                "inbound"
+            },
+            src: self.src.0,
+            dst: self.dst.0,
+            hbone_target: self.hbone_target,
+            src_identity: tl
+                .source_principal
+                .as_ref()
+                .filter(|_| mtls)
+                .map(|id| id.to_string()),
+            dst_identity: tl
+                .destination_principal
+                .as_ref()
+                .filter(|_| mtls)
+                .map(|id| id.to_string()),
+            start: self.start,
+        };
+        self.live = Some(cm.track_live(info, self.stats.clone()));
+        self
+    }
+
     pub fn increment_send(&self, res: u64) {
-        self.sent.inc_by(res);
+        self.stats.sent.inc_by(res);
         self.sent_metric.inc_by(res);
     }
 
     pub fn increment_recv(&self, res: u64) {
-        self.recv.inc_by(res);
+        self.stats.recv.inc_by(res);
         self.recv_metric.inc_by(res);
     }
 
-    pub fn record_with_flag<E: std::error::Error>(
+    pub fn record_with_flag<E: std::error::Error + 'static>(
         mut self,
         res: Result<(), E>,
         flag: ResponseFlags,
@@ -500,24 +1104,122 @@ impl ConnectionResult {
 
     // Record our final result.
     // Ideally, we would save and report from the increment_ functions instead of requiring a report here.
-    pub fn record<E: std::error::Error>(&self, res: Result<(), E>) {
+    pub fn record<E: std::error::Error + 'static>(&self, res: Result<(), E>) {
+        let reason = ConnectionCloseReason::from_result(&res);
+        self.record_with_reason(res, reason)
+    }
+
+    // Record the connection was closed by a drain, rather than by either peer. This is not an
+    // error, so only the close reason tells it apart from a completed connection.
+    pub fn record_drained(&self) {
+        self.record_with_reason(Ok::<(), Error>(()), ConnectionCloseReason::drained)
+    }
+
+    fn record_with_reason<E: std::error::Error + 'static>(
+        &self,
+        res: Result<(), E>,
+        reason: ConnectionCloseReason,
+    ) {
         let tl = &self.tl;
+        let code = ErrorCode::from_result(&res);
+
+        let metric_labels = self.metrics.label_filter.apply(tl);
+        observe_duration(
+            &self.metrics.connection_duration,
+            &LatencyLabels {
+                reporter: metric_labels.reporter,
+                destination_service: metric_labels.destination_service.clone(),
+            },
+            self.start.elapsed(),
+        );
 
         // Unconditionally record the connection was closed
-        self.metrics.connection_close.get_or_create(tl).inc();
+        self.metrics
+            .connection_close
+            .get_or_create(&ConnectionCloseLabels {
+                common: metric_labels.into_owned(),
+                reason,
+            })
+            .inc();
+        if let Some(code) = code {
+            self.metrics
+                .errors
+                .get_or_create(&ErrorLabels {
+                    reporter: tl.reporter,
+                    code,
+                })
+                .inc();
+        }
 
         // Unconditionally write out an access log
         let mtls = tl.connection_security_policy == SecurityPolicy::mutual_tls;
         let bytes = (
-            self.recv.load(Ordering::SeqCst),
-            self.sent.load(Ordering::SeqCst),
+            self.stats.recv.load(Ordering::SeqCst),
+            self.stats.sent.load(Ordering::SeqCst),
         );
+        // Istio flips the metric for source: https://github.com/istio/istio/issues/32399
+        // Unflip for logs
+        let (bytes_sent, bytes_recv) = if tl.reporter == Reporter::source {
+            bytes
+        } else {
+            (bytes.1, bytes.0)
+        };
+
+        if self.access_log != AccessLogFormat::Default {
+            // Still respect the log level for access logs, so they can be turned off dynamically
+            if !tracing::enabled!(target: "access", tracing::Level::INFO) {
+                return;
+            }
+            let duration = self.start.elapsed();
+            AccessLogEntry {
+                start_time: SystemTime::now() - duration,
+                connection_id: self.id,
+                direction: if tl.reporter == Reporter::source {
+                    "outbound"
+                } else {
+                    "inbound"
+                },
+                src_addr: self.src.0,
+                src_workload: self.src.1.as_ref().map(|s| s.as_str()),
+                src_namespace: tl.source_workload_namespace.as_ref().map(|s| s.as_str()),
+                src_identity: tl
+                    .source_principal
+                    .as_ref()
+                    .filter(|_| mtls)
+                    .map(|id| id.to_string()),
+                dst_addr: self.dst.0,
+                dst_hbone_addr: self.hbone_target,
+                dst_service: tl.destination_service.as_ref().map(|s| s.as_str()),
+                dst_workload: self.dst.1.as_ref().map(|s| s.as_str()),
+                dst_namespace: tl
+                    .destination_workload_namespace
+                    .as_ref()
+                    .map(|s| s.as_str()),
+                dst_identity: tl
+                    .destination_principal
+                    .as_ref()
+                    .filter(|_| mtls)
+                    .map(|id| id.to_string()),
+                bytes_sent,
+                bytes_recv,
+                duration,
+                response_flags: tl.response_flags,
+                close_reason: reason,
+                error: res.as_ref().err().map(|e| e.to_string()),
+                error_code: code,
+            }
+            .write(self.access_log);
+            return;
+        }
+
         let dur = format!("{}ms", self.start.elapsed().as_millis());
 
         // We use our own macro to allow setting the level dynamically
         access_log!(
             res,
 
+            connection_id = %self.id,
+
             src.addr = %self.src.0,
             src.workload = self.src.1.as_deref().map(display),
             src.namespace = tl.source_workload_namespace.display(),
@@ -536,11 +1238,143 @@ impl ConnectionResult {
                 "inbound"
             },
 
-            // Istio flips the metric for source: https://github.com/istio/istio/issues/32399
-            // Unflip for logs
-            bytes_sent = if tl.reporter == Reporter::source {bytes.0} else {bytes.1},
-            bytes_recv = if tl.reporter == Reporter::source {bytes.1} else {bytes.0},
+            bytes_sent = bytes_sent,
+            bytes_recv = bytes_recv,
             duration = dur,
+            reason = %reason,
+            error.code = code.map(display),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_reason() {
+        let reason = |err: Error| ConnectionCloseReason::from_result(&Err(err));
+        assert_eq!(
+            ConnectionCloseReason::from_result::<Error>(&Ok(())),
+            ConnectionCloseReason::completed
+        );
+        assert_eq!(reason(Error::DrainTimeOut), ConnectionCloseReason::drained);
+        assert_eq!(
+            reason(Error::AuthorizationPolicyLateRejection),
+            ConnectionCloseReason::policy_revoked
+        );
+        assert_eq!(
+            reason(Error::IdleTimeout),
+            ConnectionCloseReason::idle_timeout
+        );
+        assert_eq!(
+            reason(Error::Io(io::ErrorKind::ConnectionReset.into())),
+            ConnectionCloseReason::peer_reset
+        );
+        assert_eq!(
+            reason(Error::Io(io::ErrorKind::TimedOut.into())),
+            ConnectionCloseReason::error
+        );
+        // Errors other than our own are classified too
+        assert_eq!(
+            ConnectionCloseReason::from_result(&Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+            ConnectionCloseReason::peer_reset
+        );
+    }
+
+    #[test]
+    fn error_code() {
+        let code = |err: Error| ErrorCode::from_result(&Err(err));
+        assert_eq!(ErrorCode::from_result::<Error>(&Ok(())), None);
+        assert_eq!(
+            code(Error::Bind(
+                "127.0.0.1:15001".parse().unwrap(),
+                io::ErrorKind::AddrInUse.into()
+            )),
+            Some(ErrorCode::bind)
+        );
+        assert_eq!(
+            code(Error::ConnectionFailed(
+                io::ErrorKind::ConnectionRefused.into()
+            )),
+            Some(ErrorCode::connect)
+        );
+        assert_eq!(
+            code(Error::AuthorizationPolicyRejection("deny".to_string())),
+            Some(ErrorCode::rbac)
+        );
+        assert_eq!(
+            code(Error::HttpStatus(http::StatusCode::UNAUTHORIZED)),
+            Some(ErrorCode::rbac)
+        );
+        assert_eq!(
+            code(Error::HttpStatus(http::StatusCode::BAD_GATEWAY)),
+            Some(ErrorCode::protocol)
         );
+        assert_eq!(
+            code(Error::UnknownDestination("10.0.0.1".parse().unwrap())),
+            Some(ErrorCode::xds)
+        );
+        assert_eq!(code(Error::MissingSni), Some(ErrorCode::protocol));
+        // Errors other than our own are classified too
+        assert_eq!(
+            ErrorCode::from_result(&Err(io::Error::from(io::ErrorKind::TimedOut))),
+            Some(ErrorCode::connect)
+        );
+    }
+
+    #[test]
+    fn label_filter() {
+        assert!(LabelFilter::new(&["reporter"]).is_err());
+
+        let tl = CommonTrafficLabels {
+            reporter: Reporter::destination,
+            source_workload: RichStrng::from("client").into(),
+            destination_workload: RichStrng::from("server").into(),
+            destination_service: RichStrng::from("server.ns.svc.cluster.local").into(),
+            ..Default::default()
+        };
+        assert_eq!(LabelFilter::default().apply(&tl).as_ref(), &tl);
+
+        let filter = LabelFilter::new(&["destination_workload", "destination_service"]).unwrap();
+        let filtered = filter.apply(&tl);
+        assert_eq!(
+            filtered.as_ref(),
+            &CommonTrafficLabels {
+                reporter: Reporter::destination,
+                source_workload: RichStrng::from("client").into(),
+                ..Default::default()
+            }
+        );
+        assert!(filter.drops("destination_service"));
+        assert!(!filter.drops("source_workload"));
+    }
+
+    #[test]
+    fn latency_histograms() {
+        let mut registry = Registry::default();
+        let metrics = Metrics::new(&mut registry);
+        observe_duration(
+            &metrics.tcp_connect_duration,
+            &LatencyLabels::new(Reporter::source, None),
+            Duration::from_millis(3),
+        );
+
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &registry).unwrap();
+        let bucket = |le: &str| {
+            encoded
+                .lines()
+                .find(|l| {
+                    l.starts_with("tcp_connect_duration_seconds_bucket{")
+                        && l.contains(r#"reporter="source""#)
+                        && l.contains(r#"destination_service="unknown""#)
+                        && l.contains(&format!(r#"le="{le}""#))
+                })
+                .and_then(|l| l.rsplit(' ').next())
+                .map(str::to_owned)
+        };
+        assert_eq!(bucket("0.0025").as_deref(), Some("0"));
+        assert_eq!(bucket("0.005").as_deref(), Some("1"));
     }
 }
@@ -0,0 +1,203 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::io::IoSlice;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, Instrument};
+
+use crate::copy::AsTcpStream;
+use crate::proxy::SocketFactory;
+
+// The number of reads buffered for a mirror before it is considered too slow and abandoned.
+// Each read is at most the relay buffer size.
+const MIRROR_BUFFER: usize = 64;
+
+/// spawn starts a task copying everything sent on the returned channel to a new connection to
+/// `addr`. Mirroring is fire-and-forget: failures end the mirror, but are not reported back.
+pub(super) fn spawn(
+    addr: SocketAddr,
+    socket_factory: Arc<dyn SocketFactory + Send + Sync>,
+) -> mpsc::Sender<Bytes> {
+    let (tx, mut rx) = mpsc::channel::<Bytes>(MIRROR_BUFFER);
+    let mirror = async move {
+        let mut conn = match super::freebind_connect(None, addr, socket_factory.as_ref()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                debug!(%addr, "failed to connect to mirror: {e}");
+                return;
+            }
+        };
+        while let Some(data) = rx.recv().await {
+            if let Err(e) = conn.write_all(&data).await {
+                debug!(%addr, "failed to write to mirror: {e}");
+                return;
+            }
+        }
+        let _ = conn.shutdown().await;
+    };
+    tokio::spawn(mirror.in_current_span());
+    tx
+}
+
+/// MirroredStream copies everything read from the inner stream to a mirror.
+pub(super) struct MirroredStream<S> {
+    inner: S,
+    // mirror is dropped once the mirror falls behind or goes away, after which reads are no
+    // longer copied.
+    mirror: Option<mpsc::Sender<Bytes>>,
+}
+
+impl<S> MirroredStream<S> {
+    pub(super) fn new(inner: S, mirror: mpsc::Sender<Bytes>) -> Self {
+        MirroredStream {
+            inner,
+            mirror: Some(mirror),
+        }
+    }
+
+    fn mirror(&mut self, data: &[u8]) {
+        let Some(tx) = &self.mirror else {
+            return;
+        };
+        if let Err(e) = tx.try_send(Bytes::copy_from_slice(data)) {
+            let reason = match e {
+                TrySendError::Full(_) => "mirror is too slow",
+                TrySendError::Closed(_) => "mirror closed",
+            };
+            debug!("stopped mirroring: {reason}");
+            self.mirror = None;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MirroredStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            let read = &buf.filled()[before..];
+            if !read.is_empty() {
+                this.mirror(read);
+            }
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MirroredStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+// Mirroring needs to see the data, so mirrored streams are never spliced.
+impl<S> AsTcpStream for MirroredStream<S> {
+    fn as_tcp_stream(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::DefaultSocketFactory;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn mirrors_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tx = spawn(addr, Arc::new(DefaultSocketFactory));
+
+        let (client, server) = tokio::io::duplex(1024);
+        let mut stream = MirroredStream::new(server, tx);
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        client_write.write_all(b"hello").await.unwrap();
+        drop(client_write);
+        let mut read = Vec::new();
+        stream.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"hello");
+
+        // Writes are passed through, and not mirrored
+        stream.write_all(b"world").await.unwrap();
+        let mut written = [0u8; 5];
+        client_read.read_exact(&mut written).await.unwrap();
+        assert_eq!(&written, b"world");
+        drop(stream);
+
+        let (mut mirror, _) = listener.accept().await.unwrap();
+        let mut mirrored = Vec::new();
+        mirror.read_to_end(&mut mirrored).await.unwrap();
+        assert_eq!(mirrored, b"hello");
+    }
+
+    #[tokio::test]
+    async fn abandons_slow_mirror() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = MirroredStream::new(server, tx);
+        let mut buf = [0u8; 5];
+
+        client.write_all(b"first").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        client.write_all(b"again").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(stream.mirror.is_none());
+
+        // The original stream is unaffected
+        assert_eq!(&buf, b"again");
+        assert_eq!(rx.recv().await.unwrap(), Bytes::from_static(b"first"));
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::config::RootCert;
+use crate::proxy::Error;
+use crate::state::service::TlsOrigination;
+use crate::strng::Strng;
+use crate::tls;
+
+// Client configs, by the PEM roots they verify with. Building a config may load the system roots,
+// so configs are shared by all connections; there are only as many as distinct roots in XDS.
+static CLIENT_CONFIGS: Lazy<Mutex<HashMap<Strng, Arc<ClientConfig>>>> = Lazy::new(Default::default);
+
+async fn client_config(
+    settings: &tls::TlsSettings,
+    root_certificates: &Strng,
+) -> Result<Arc<ClientConfig>, Error> {
+    if let Some(cfg) = CLIENT_CONFIGS.lock().unwrap().get(root_certificates) {
+        return Ok(cfg.clone());
+    }
+    let roots = match root_certificates.as_str() {
+        "" => RootCert::Default,
+        pem => RootCert::Static(Bytes::copy_from_slice(pem.as_bytes())),
+    };
+    let cfg = Arc::new(tls::origination_client_config(settings, &roots).await?);
+    CLIENT_CONFIGS
+        .lock()
+        .unwrap()
+        .insert(root_certificates.clone(), cfg.clone());
+    Ok(cfg)
+}
+
+/// originate performs a TLS handshake over an established connection to a service outside the
+/// mesh, so a plaintext client's traffic is sent to it encrypted.
+pub(super) async fn originate(
+    settings: &tls::TlsSettings,
+    stream: TcpStream,
+    origination: &TlsOrigination,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let cfg = client_config(settings, &origination.root_certificates).await?;
+    let server_name = ServerName::try_from(origination.sni.to_string()).map_err(|e| {
+        Error::TlsOrigination(
+            origination.sni.clone(),
+            io::Error::new(io::ErrorKind::InvalidInput, e),
+        )
+    })?;
+    TlsConnector::from(cfg)
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| Error::TlsOrigination(origination.sni.clone(), e))
+}
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::{IpAddr, SocketAddr};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -22,23 +23,30 @@ use drain::Watch;
 
 use hyper::header::FORWARDED;
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 
 use tracing::{debug, error, info, info_span, trace_span, warn, Instrument};
 
-use crate::config::ProxyMode;
+use crate::baggage::baggage_header;
+use crate::config::{ProxyMode, UnknownDestinationPolicy};
 use crate::identity::Identity;
 
 use crate::proxy::metrics::Reporter;
-use crate::proxy::{metrics, pool, ConnectionOpen, ConnectionResult};
+use crate::proxy::{metrics, pool, ConnectionId, ConnectionOpen, ConnectionResult};
 use crate::proxy::{util, Error, ProxyInputs, TraceParent, BAGGAGE_HEADER, TRACEPARENT_HEADER};
 
+use crate::proxy::connect_udp::{self, DatagramSocket};
+use crate::proxy::connection_manager::ConnectionManager;
 use crate::proxy::h2::H2Stream;
-use crate::state::service::ServiceDescription;
+use crate::proxy::originate;
+use crate::proxy::outlier::OutlierDetector;
+use crate::state::service::{ServiceDescription, TlsOrigination};
 use crate::state::workload::gatewayaddress::Destination;
 use crate::state::workload::{address::Address, NetworkAddress, Protocol, Workload};
+use crate::state::EndpointLoad;
 use crate::strng::Strng;
-use crate::{assertions, copy, proxy, socket, strng};
+use crate::{assertions, copy, proxy, socket, strng, telemetry};
 
 pub struct Outbound {
     pi: ProxyInputs,
@@ -90,30 +98,33 @@ impl Outbound {
             pi.cfg.clone(),
             pi.socket_factory.clone(),
             pi.cert_manager.clone(),
+            pi.metrics.clone(),
         );
         let accept = async move {
             loop {
+                proxy::throttle_accept(&pi).await;
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
                 let start_outbound_instant = Instant::now();
                 let outbound_drain = sub_drain.clone();
                 match socket {
                     Ok((stream, _remote)) => {
+                        let Ok(permit) = proxy::admit_connection(&pi, &stream) else {
+                            continue;
+                        };
                         let mut oc = OutboundConnection {
                             pi: pi.clone(),
                             id: TraceParent::new(),
+                            connection_id: ConnectionId::new(),
                             pool: pool.clone(),
                         };
-                        let span = info_span!("outbound", id=%oc.id);
+                        let span = info_span!("outbound", id=%oc.id, connection_id=%oc.connection_id);
+                        telemetry::set_trace_context(&span, &oc.id, None);
                         let serve_outbound_connection = (async move {
+                            let _permit = permit;
                             debug!(dur=?start_outbound_instant.elapsed(), id=%oc.id, "outbound spawn START");
                             // Since this task is spawned, make sure we are guaranteed to terminate
-                            tokio::select! {
-                                _ = outbound_drain.signaled() => {
-                                    debug!("outbound drain signaled");
-                                }
-                                _ = oc.proxy(stream) => {}
-                            }
+                            oc.proxy(stream, outbound_drain).await;
                             debug!(dur=?start_outbound_instant.elapsed(), id=%oc.id, "outbound spawn DONE");
                         }).instrument(span);
 
@@ -148,15 +159,21 @@ impl Outbound {
 pub(super) struct OutboundConnection {
     pub(super) pi: Arc<ProxyInputs>,
     pub(super) id: TraceParent,
+    pub(super) connection_id: ConnectionId,
     pub(super) pool: proxy::pool::WorkloadHBONEPool,
 }
 
 impl OutboundConnection {
-    async fn proxy(&mut self, source_stream: TcpStream) {
+    async fn proxy(&mut self, source_stream: TcpStream, out_drain: Watch) {
         let source_addr =
             socket::to_canonical(source_stream.peer_addr().expect("must receive peer addr"));
         let dst_addr = socket::orig_dst_addr_or_default(&source_stream);
-        self.proxy_to(source_stream, source_addr, dst_addr, false)
+        super::set_socket_options(
+            &source_stream,
+            &self.pi.cfg.socket_config,
+            super::SocketClass::App,
+        );
+        self.proxy_to_cancellable(source_stream, source_addr, dst_addr, false, Some(out_drain))
             .await;
     }
 
@@ -167,37 +184,50 @@ impl OutboundConnection {
     //
     // If using `proxy_to` in `tokio::spawn` tasks, it is recommended to use a drain, to guarantee termination
     // and prevent "zombie" outbound tasks.
-    pub async fn proxy_to_cancellable(
+    pub async fn proxy_to_cancellable<S>(
         &mut self,
-        stream: TcpStream,
+        stream: S,
         remote_addr: SocketAddr,
         orig_dst_addr: SocketAddr,
         block_passthrough: bool,
         out_drain: Option<Watch>,
-    ) {
+    ) where
+        S: copy::AsTcpStream + AsyncRead + AsyncWrite + Unpin + Send,
+    {
         match out_drain {
             Some(drain) => {
+                // Once the connection is established, proxy_to watches the drain itself, so it
+                // can record the connection was drained. Before that, nothing is recorded, and
+                // the connection is dropped here.
                 tokio::select! {
+                        biased;
+                        res = self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough, Some(drain.clone())) => res,
                         _ = drain.signaled() => {
                             info!("drain signaled");
                         }
-                        res = self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough) => res
                 }
             }
             None => {
-                self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough)
+                self.proxy_to(stream, remote_addr, orig_dst_addr, block_passthrough, None)
                     .await;
             }
         }
     }
 
-    async fn proxy_to(
+    // proxy_to proxies a downstream connection from the source to the destination. The downstream
+    // is usually a TCP connection, but may be any stream, such as one accepted over a unix socket.
+    // If `drain` is signaled once the connection is established, the connection is closed and
+    // recorded as drained.
+    async fn proxy_to<S>(
         &mut self,
-        mut source_stream: TcpStream,
+        mut source_stream: S,
         source_addr: SocketAddr,
         dest_addr: SocketAddr,
         block_passthrough: bool,
-    ) {
+        drain: Option<Watch>,
+    ) where
+        S: copy::AsTcpStream + AsyncRead + AsyncWrite + Unpin + Send,
+    {
         let start = Instant::now();
 
         // Block calls to ztunnel directly, unless we are in "in-pod".
@@ -206,13 +236,31 @@ impl OutboundConnection {
             && Some(dest_addr.ip()) == self.pi.cfg.local_ip
             && !self.pi.cfg.inpod_enabled
         {
-            metrics::log_early_deny(source_addr, dest_addr, Reporter::source, Error::SelfCall);
+            metrics::log_early_deny(
+                &self.pi.metrics,
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::SelfCall,
+            );
             return;
         }
-        let req = match Box::pin(self.build_request(source_addr.ip(), dest_addr)).await {
+        let req = match Box::pin(self.build_request(
+            source_addr.ip(),
+            dest_addr,
+            &self.endpoint_load(None),
+        ))
+        .await
+        {
             Ok(req) => req,
             Err(err) => {
-                metrics::log_early_deny(source_addr, dest_addr, Reporter::source, err);
+                metrics::log_early_deny(
+                    &self.pi.metrics,
+                    source_addr,
+                    dest_addr,
+                    Reporter::source,
+                    err,
+                );
                 return;
             }
         };
@@ -220,17 +268,40 @@ impl OutboundConnection {
             "request from {} to {} via {} type {:#?}",
             req.source.name, dest_addr, req.gateway, req.request_type
         );
-        if block_passthrough && req.destination_workload.is_none() {
-            // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
-            // domains. But for socks5
-            metrics::log_early_deny(
-                source_addr,
-                dest_addr,
-                Reporter::source,
-                Error::UnknownDestination(req.destination.ip()),
-            );
-            return;
+        if req.destination_workload.is_none() {
+            if block_passthrough {
+                // This is mostly used by socks5. For typical outbound calls, we need to allow calls to arbitrary
+                // domains. But for socks5
+                metrics::log_early_deny(
+                    &self.pi.metrics,
+                    source_addr,
+                    dest_addr,
+                    Reporter::source,
+                    Error::UnknownDestination(req.destination.ip()),
+                );
+                return;
+            }
+            // Services matched by hostname are registered with the mesh, even without workloads
+            if req.destination_service.is_none()
+                && self.pi.cfg.outbound_unknown_destination == UnknownDestinationPolicy::Deny
+            {
+                self.pi.metrics.connections_denied_unknown_destination.inc();
+                metrics::log_early_deny(
+                    &self.pi.metrics,
+                    source_addr,
+                    dest_addr,
+                    Reporter::source,
+                    Error::UnknownDestinationDenied(req.destination.ip()),
+                );
+                return;
+            }
         }
+        let dscp = source_stream
+            .as_tcp_stream()
+            .and_then(|s| super::received_dscp(s, &self.pi.cfg.socket_config));
+        let (req, upstream) =
+            Box::pin(self.connect_with_retries(source_addr, dest_addr, dscp, req)).await;
+
         // TODO: should we use the original address or the actual address? Both seems nice!
         let _conn_guard =
             self.pi
@@ -243,50 +314,266 @@ impl OutboundConnection {
         } else {
             None
         };
-        let result_tracker = Box::new(ConnectionResult::new(
-            source_addr,
-            req.gateway,
-            hbone_target,
-            start,
-            Self::conn_metrics_from_request(&req),
-            metrics,
-        ));
+        let result_tracker = Box::new(
+            ConnectionResult::new(
+                self.connection_id,
+                source_addr,
+                req.gateway,
+                hbone_target,
+                start,
+                Self::conn_metrics_from_request(&req),
+                metrics,
+                self.pi.cfg.reloadable.access_log_format(),
+            )
+            .track(&self.pi.connection_manager),
+        );
 
-        let res = match req.protocol {
-            Protocol::HBONE => {
-                self.proxy_to_hbone(source_stream, source_addr, &req, &result_tracker)
+        let copy = async {
+            match upstream {
+                Ok(UpstreamStream::Hbone(upgraded)) => {
+                    copy::copy_bidirectional(
+                        source_stream,
+                        upgraded,
+                        &result_tracker,
+                        self.pi.cfg.as_ref().into(),
+                    )
                     .await
-            }
-            Protocol::TCP => {
-                self.proxy_to_tcp(&mut source_stream, &req, &result_tracker)
+                }
+                #[cfg(feature = "quic")]
+                Ok(UpstreamStream::Quic(upgraded)) => {
+                    copy::copy_bidirectional(
+                        source_stream,
+                        upgraded,
+                        &result_tracker,
+                        self.pi.cfg.as_ref().into(),
+                    )
                     .await
+                }
+                Ok(UpstreamStream::Tcp(mut outbound)) => {
+                    copy::copy_bidirectional_tcp(
+                        &mut source_stream,
+                        &mut outbound,
+                        &result_tracker,
+                        self.pi.cfg.as_ref().into(),
+                    )
+                    .await
+                }
+                Ok(UpstreamStream::Tls(outbound)) => {
+                    copy::copy_bidirectional(
+                        source_stream,
+                        outbound,
+                        &result_tracker,
+                        self.pi.cfg.as_ref().into(),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            }
+        };
+        let res = match drain {
+            Some(drain) => {
+                tokio::select! {
+                    biased;
+                    res = copy => res,
+                    _ = drain.signaled() => {
+                        info!("drain signaled");
+                        result_tracker.record_drained();
+                        return;
+                    }
+                }
             }
+            None => copy.await,
         };
         result_tracker.record(res)
     }
 
-    async fn proxy_to_hbone(
+    // connect_with_retries connects to the upstream of the request. If the endpoint fails and the
+    // request was addressed to a service, it retries against other endpoints of the service, up to
+    // the configured number of retries. Returns the request that was used for the last attempt.
+    // `dscp` is the downstream's DSCP mark, to propagate to the upstream.
+    async fn connect_with_retries(
         &mut self,
-        stream: TcpStream,
-        remote_addr: SocketAddr,
-        req: &Request,
-        connection_stats: &ConnectionResult,
-    ) -> Result<(), Error> {
-        debug!(
-            "proxy to {} using HBONE via {} type {:#?}",
-            req.destination, req.gateway, req.request_type
-        );
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        dscp: Option<u8>,
+        mut req: Box<Request>,
+    ) -> (Box<Request>, Result<UpstreamStream, Error>) {
+        let mut failed = HashSet::new();
+        loop {
+            let res = self.connect(source_addr, dscp, &req).await;
+            let upstream = req.selected_upstream();
+            if let (Some(outlier), Some(_)) = (&self.pi.outlier, &req.destination_workload) {
+                match &res {
+                    Ok(_) => outlier.record_success(upstream),
+                    Err(e) if e.is_endpoint_failure() => outlier.record_failure(upstream),
+                    Err(_) => {}
+                }
+            }
+            let err = match res {
+                Ok(upstream) => return (req, Ok(upstream)),
+                Err(err) => err,
+            };
+            if req.destination_service.is_none() || !err.is_endpoint_failure() {
+                return (req, Err(err));
+            }
+            if failed.len() >= self.pi.cfg.outbound_connect_retries {
+                if !failed.is_empty() {
+                    self.pi.metrics.connect_retries_exhausted.inc();
+                }
+                return (req, Err(err));
+            }
+            failed.insert(upstream);
+            let load = self.endpoint_load(Some(&failed));
+            let next = match Box::pin(self.build_request(source_addr.ip(), dest_addr, &load)).await
+            {
+                // Only retry against another endpoint; if there are none left, the request would
+                // fall back to passthrough.
+                Ok(next) if next.destination_workload.is_some() => next,
+                _ => {
+                    debug!(gateway=%req.gateway, "no other endpoints to retry: {}", err);
+                    return (req, Err(err));
+                }
+            };
+            debug!(
+                failed=%upstream,
+                next=%next.selected_upstream(),
+                "connect failed, retrying with another endpoint: {}",
+                err
+            );
+            self.pi.metrics.connect_retries.inc();
+            req = next;
+        }
+    }
 
-        let upgraded = Box::pin(self.build_hbone_request(remote_addr, &req)).await?;
+    // endpoint_load reports the load used to select endpoints for a connection, given the addresses
+    // that already failed for it.
+    fn endpoint_load<'a>(&'a self, failed: Option<&'a HashSet<IpAddr>>) -> OutboundLoad<'a> {
+        OutboundLoad {
+            connection_manager: &self.pi.connection_manager,
+            outlier: self.pi.outlier.as_deref(),
+            failed,
+        }
+    }
 
-        copy::copy_bidirectional(stream, upgraded, connection_stats).await
+    async fn connect(
+        &mut self,
+        source_addr: SocketAddr,
+        dscp: Option<u8>,
+        req: &Request,
+    ) -> Result<UpstreamStream, Error> {
+        let socket_config = &self.pi.cfg.socket_config;
+        let latency = self
+            .pi
+            .metrics
+            .latency_labels(Reporter::source, req.destination_service.as_ref());
+        let start = Instant::now();
+        match req.protocol {
+            Protocol::HBONE => {
+                debug!(
+                    "proxy to {} using HBONE via {} type {:#?}",
+                    req.destination, req.gateway, req.request_type
+                );
+                let dscp = super::upstream_dscp(dscp, socket_config, super::SocketClass::Mesh);
+                #[cfg(feature = "quic")]
+                if self.pi.cfg.enable_hbone_quic
+                    && req.request_type == RequestType::Direct
+                    && req
+                        .destination_workload
+                        .as_ref()
+                        .is_some_and(|w| w.hbone_quic)
+                {
+                    if let Some(upgraded) =
+                        Box::pin(self.build_hbone_quic_request(source_addr, &req, dscp)).await?
+                    {
+                        metrics::observe_duration(
+                            &self.pi.metrics.hbone_stream_duration,
+                            &latency,
+                            start.elapsed(),
+                        );
+                        return Ok(UpstreamStream::Quic(upgraded));
+                    }
+                }
+                let upgraded =
+                    Box::pin(self.build_hbone_request(source_addr, &req, false, dscp)).await?;
+                metrics::observe_duration(
+                    &self.pi.metrics.hbone_stream_duration,
+                    &latency,
+                    start.elapsed(),
+                );
+                Ok(UpstreamStream::Hbone(upgraded))
+            }
+            Protocol::TCP => {
+                let dscp = super::upstream_dscp(dscp, socket_config, super::SocketClass::App);
+                let outbound = self.connect_tcp(source_addr, dscp, req).await?;
+                metrics::observe_duration(
+                    &self.pi.metrics.tcp_connect_duration,
+                    &latency,
+                    start.elapsed(),
+                );
+                match &req.tls_origination {
+                    Some(origination) => {
+                        debug!(sni=%origination.sni, "originating tls to {}", req.gateway);
+                        let tls =
+                            originate::originate(&self.pi.cfg.tls_settings, outbound, origination)
+                                .await?;
+                        Ok(UpstreamStream::Tls(Box::new(tls)))
+                    }
+                    None => Ok(UpstreamStream::Tcp(outbound)),
+                }
+            }
+        }
     }
 
+    // build_hbone_request opens an HBONE stream to the upstream of the request. If `udp` is set, the
+    // stream is a CONNECT-UDP tunnel. If `dscp` is set, the stream is sent over an HBONE connection
+    // with that mark.
     async fn build_hbone_request(
         &mut self,
         remote_addr: SocketAddr,
         req: &&Request,
+        udp: bool,
+        dscp: Option<u8>,
     ) -> Result<H2Stream, Error> {
+        let (pool_key, request, span) = self.hbone_request(remote_addr, req, udp, dscp);
+        let upgraded = Box::pin(self.pool.send_request_pooled(&pool_key, request))
+            .instrument(span)
+            .await?;
+        Ok(upgraded)
+    }
+
+    // build_hbone_quic_request opens an HBONE stream to the upstream of the request over QUIC.
+    // Returns None if the upstream cannot be reached over QUIC, so HTTP/2 should be used instead.
+    // QUIC connections are not marked with `dscp`.
+    #[cfg(feature = "quic")]
+    async fn build_hbone_quic_request(
+        &mut self,
+        remote_addr: SocketAddr,
+        req: &&Request,
+        dscp: Option<u8>,
+    ) -> Result<Option<proxy::h3::H3Stream>, Error> {
+        let (pool_key, request, span) = self.hbone_request(remote_addr, req, false, dscp);
+        let Some(mut client) = self
+            .pool
+            .quic()
+            .connect(&pool_key)
+            .instrument(span.clone())
+            .await
+        else {
+            return Ok(None);
+        };
+        let upgraded = client.send_request(request).instrument(span).await?;
+        Ok(Some(upgraded))
+    }
+
+    // hbone_request builds the HBONE request for the upstream of the request, along with the key of
+    // the connections it can be sent over, and its span.
+    fn hbone_request(
+        &self,
+        remote_addr: SocketAddr,
+        req: &Request,
+        udp: bool,
+        dscp: Option<u8>,
+    ) -> (pool::WorkloadKey, http::Request<()>, tracing::Span) {
         let mut allowed_sans: Vec<Identity> = Vec::new();
         for san in req.upstream_sans.iter() {
             match Identity::from_str(san) {
@@ -304,12 +591,13 @@ impl OutboundConnection {
         );
         let dst_identity = allowed_sans;
 
-        let pool_key = Box::new(pool::WorkloadKey {
+        let pool_key = pool::WorkloadKey {
             src_id: req.source.identity(),
             dst_id: dst_identity.clone(),
             src: remote_addr.ip(),
             dst: req.gateway,
-        });
+            dscp,
+        };
 
         let mut f = http_types::proxies::Forwarded::new();
         f.add_for(remote_addr.to_string());
@@ -317,43 +605,188 @@ impl OutboundConnection {
             f.set_host(svc.hostname.as_str());
         }
 
-        let request = http::Request::builder()
-            .uri(&req.destination.to_string())
+        // Each HBONE request is its own span within the connection's trace, so the next hop can
+        // parent its spans to this request.
+        let trace = self.id.child();
+        let request = http::Request::builder();
+        let request = if udp {
+            request
+                .uri(format!(
+                    "https://{}{}",
+                    req.gateway,
+                    connect_udp::target_path(req.destination)
+                ))
+                .extension(::h2::ext::Protocol::from_static(connect_udp::PROTOCOL))
+                .header(
+                    connect_udp::CAPSULE_PROTOCOL_HEADER,
+                    connect_udp::CAPSULE_PROTOCOL_ENABLED,
+                )
+        } else {
+            request.uri(&req.destination.to_string())
+        };
+        let request = request
             .method(hyper::Method::CONNECT)
             .version(hyper::Version::HTTP_2)
-            .header(BAGGAGE_HEADER, baggage(req, self.pi.cfg.cluster_id.clone()))
+            .header(
+                BAGGAGE_HEADER,
+                baggage_header(&req.source, &self.pi.cfg.cluster_id),
+            )
             .header(FORWARDED, f.value().expect("Forwarded value is infallible"))
-            .header(TRACEPARENT_HEADER, self.id.header())
+            .header(TRACEPARENT_HEADER, trace.header())
             .body(())
             .expect("builder with known status code should not fail");
 
-        let upgraded = Box::pin(self.pool.send_request_pooled(&pool_key, request))
-            .instrument(trace_span!("outbound connect"))
-            .await?;
-        Ok(upgraded)
+        let span = trace_span!("outbound connect", span_id = trace.span_id());
+        telemetry::set_trace_context(&span, &trace, Some(&self.id));
+        (pool_key, request, span)
     }
 
-    async fn proxy_to_tcp(
+    async fn connect_tcp(
         &mut self,
-        stream: &mut TcpStream,
+        source_addr: SocketAddr,
+        dscp: Option<u8>,
         req: &Request,
-        connection_stats: &ConnectionResult,
-    ) -> Result<(), Error> {
+    ) -> Result<TcpStream, Error> {
         debug!(
             "Proxying to {} using TCP via {} type {:?}",
             req.destination, req.gateway, req.request_type
         );
         // Create a TCP connection to upstream
         let local = if self.pi.cfg.enable_original_source.unwrap_or_default() {
-            super::get_original_src_from_stream(stream)
+            Some(source_addr.ip())
         } else {
             None
         };
-        let mut outbound =
+        let outbound =
             super::freebind_connect(local, req.gateway, self.pi.socket_factory.as_ref()).await?;
+        super::set_socket_options(
+            &outbound,
+            &self.pi.cfg.socket_config,
+            super::SocketClass::App,
+        );
+        super::set_dscp(&outbound, dscp);
+        Ok(outbound)
+    }
+
+    // proxy_udp proxies a UDP flow from the source to the destination. Flows to HBONE upstreams are
+    // tunneled with CONNECT-UDP; other flows are sent to the upstream directly, unless
+    // `block_passthrough` is set, as for proxy_to.
+    pub(super) async fn proxy_udp<D: DatagramSocket>(
+        &mut self,
+        mut flow: D,
+        source_addr: SocketAddr,
+        dest_addr: SocketAddr,
+        block_passthrough: bool,
+    ) {
+        let start = Instant::now();
+        let req = match Box::pin(self.build_request(
+            source_addr.ip(),
+            dest_addr,
+            &self.endpoint_load(None),
+        ))
+        .await
+        {
+            Ok(req) => req,
+            Err(err) => {
+                metrics::log_early_deny(
+                    &self.pi.metrics,
+                    source_addr,
+                    dest_addr,
+                    Reporter::source,
+                    err,
+                );
+                return;
+            }
+        };
+        debug!(
+            "udp flow from {} to {} via {} type {:#?}",
+            req.source.name, dest_addr, req.gateway, req.request_type
+        );
+        if req.destination_workload.is_none() && block_passthrough {
+            metrics::log_early_deny(
+                &self.pi.metrics,
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::UnknownDestination(req.destination.ip()),
+            );
+            return;
+        }
+        if req.destination_workload.is_none()
+            && req.destination_service.is_none()
+            && self.pi.cfg.outbound_unknown_destination == UnknownDestinationPolicy::Deny
+        {
+            self.pi.metrics.connections_denied_unknown_destination.inc();
+            metrics::log_early_deny(
+                &self.pi.metrics,
+                source_addr,
+                dest_addr,
+                Reporter::source,
+                Error::UnknownDestinationDenied(req.destination.ip()),
+            );
+            return;
+        }
 
-        // Proxying data between downstream and upstream
-        copy::copy_bidirectional(stream, &mut outbound, connection_stats).await
+        let _conn_guard =
+            self.pi
+                .connection_manager
+                .track_outbound(source_addr, dest_addr, req.gateway);
+        let hbone_target = (req.protocol == Protocol::HBONE).then_some(req.destination);
+        let result_tracker = Box::new(
+            ConnectionResult::new(
+                self.connection_id,
+                source_addr,
+                req.gateway,
+                hbone_target,
+                start,
+                Self::conn_metrics_from_request(&req),
+                self.pi.metrics.clone(),
+                self.pi.cfg.reloadable.access_log_format(),
+            )
+            .track(&self.pi.connection_manager),
+        );
+
+        let idle_timeout = self
+            .pi
+            .cfg
+            .reloadable
+            .idle_timeout()
+            .unwrap_or(connect_udp::DEFAULT_IDLE_TIMEOUT);
+        let res = match req.protocol {
+            Protocol::HBONE => {
+                match Box::pin(self.build_hbone_request(source_addr, &&*req, true, None)).await {
+                    Ok(tunnel) => {
+                        connect_udp::relay_tunnel(
+                            tunnel,
+                            &mut flow,
+                            &result_tracker,
+                            idle_timeout,
+                            false,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Protocol::TCP => match self.connect_udp_direct(req.gateway).await {
+                Ok(mut upstream) => {
+                    connect_udp::relay(&mut flow, &mut upstream, &result_tracker, idle_timeout)
+                        .await
+                }
+                Err(e) => Err(e.into()),
+            },
+        };
+        result_tracker.record(res)
+    }
+
+    async fn connect_udp_direct(&self, addr: SocketAddr) -> std::io::Result<UdpSocket> {
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = self.pi.socket_factory.udp_bind(SocketAddr::new(local, 0))?;
+        socket.connect(addr).await?;
+        Ok(socket)
     }
 
     fn conn_metrics_from_request(req: &Request) -> ConnectionOpen {
@@ -375,6 +808,7 @@ impl OutboundConnection {
         &self,
         downstream: IpAddr,
         target: SocketAddr,
+        load: &dyn EndpointLoad,
     ) -> Result<Box<Request>, Error> {
         let downstream_network_addr = NetworkAddress {
             network: strng::new(&self.pi.cfg.network),
@@ -391,32 +825,46 @@ impl OutboundConnection {
             }
         }
 
-        // If this is to-service traffic check for a service waypoint
-        // Capture result of whether or not this is svc addressed
-        let svc_addressed = if let Some(Address::Service(s)) = self
+        let target_addr = NetworkAddress {
+            network: strng::new(&self.pi.cfg.network),
+            address: target.ip(),
+        };
+        let target_service = match self
             .pi
             .state
-            .fetch_destination(&Destination::Address(NetworkAddress {
-                network: strng::new(&self.pi.cfg.network),
-                address: target.ip(),
-            }))
+            .fetch_destination(&Destination::Address(target_addr.clone()))
             .await
         {
+            Some(Address::Service(s)) => Some(s),
+            Some(Address::Workload(_)) => None,
+            // Services without VIPs, such as DNS-resolved ServiceEntries, are matched by the
+            // hostname the client resolved the address from.
+            None => self.pi.state.find_captured_service(&target_addr),
+        };
+
+        // Services outside the mesh may have us originate TLS to them, when connecting directly
+        let tls_origination = target_service
+            .as_ref()
+            .and_then(|s| s.tls_origination.clone());
+
+        // If this is to-service traffic check for a service waypoint
+        // Capture result of whether or not this is svc addressed
+        let svc_addressed = if let Some(s) = &target_service {
             // if we have a waypoint for this svc, use it; otherwise route traffic normally
             if let Some(wp) = s.waypoint.clone() {
-                let waypoint_vip = match wp.destination {
-                    Destination::Address(a) => a.address,
-                    Destination::Hostname(_) => {
-                        return Err(proxy::Error::UnknownWaypoint(
-                            "hostname lookup not supported yet".to_string(),
-                        ));
-                    }
-                };
-                let waypoint_vip = SocketAddr::new(waypoint_vip, wp.hbone_mtls_port);
+                let waypoint_vip = self.pi.state.fetch_gateway_address(&wp).await.ok_or(
+                    proxy::Error::UnknownWaypoint("unable to resolve waypoint address".to_string()),
+                )?;
+                let waypoint_vip = SocketAddr::new(waypoint_vip.address, wp.hbone_mtls_port);
                 let waypoint_us = self
                     .pi
                     .state
-                    .fetch_upstream(self.pi.cfg.network.clone(), &source_workload, waypoint_vip)
+                    .fetch_upstream(
+                        self.pi.cfg.network.clone(),
+                        &source_workload,
+                        waypoint_vip,
+                        load,
+                    )
                     .await
                     .ok_or(proxy::Error::UnknownWaypoint(
                         "unable to determine waypoint upstream".to_string(),
@@ -440,11 +888,12 @@ impl OutboundConnection {
                     source: source_workload,
                     destination: target,
                     destination_workload: Some(waypoint_workload),
-                    destination_service: Some(ServiceDescription::from(&*s)),
+                    destination_service: Some(ServiceDescription::from(&**s)),
                     expected_identity: Some(id),
                     gateway: waypoint_socket_address,
                     request_type: RequestType::ToServerWaypoint,
                     upstream_sans: waypoint_us.sans,
+                    tls_origination: None,
                 }));
             }
             // this was service addressed but we did not find a waypoint
@@ -458,7 +907,12 @@ impl OutboundConnection {
         let us = match self
             .pi
             .state
-            .fetch_upstream(source_workload.network.clone(), &source_workload, target)
+            .fetch_upstream(
+                source_workload.network.clone(),
+                &source_workload,
+                target,
+                load,
+            )
             .await
         {
             Some(us) => us,
@@ -469,11 +923,12 @@ impl OutboundConnection {
                     source: source_workload,
                     destination: target,
                     destination_workload: None,
-                    destination_service: None,
+                    destination_service: target_service.as_deref().map(ServiceDescription::from),
                     expected_identity: None,
                     gateway: target,
                     request_type: RequestType::Passthrough,
                     upstream_sans: vec![],
+                    tls_origination,
                 }));
             }
         };
@@ -499,7 +954,7 @@ impl OutboundConnection {
             match self
                 .pi
                 .state
-                .fetch_waypoint(&us.workload, &source_workload, workload_ip)
+                .fetch_waypoint(&us.workload, &source_workload, workload_ip, load)
                 .await
             {
                 Ok(None) => {} // workload doesn't have a waypoint; this is fine
@@ -528,6 +983,7 @@ impl OutboundConnection {
                         gateway: waypoint_socket_address,
                         request_type: RequestType::ToServerWaypoint,
                         upstream_sans: us.sans,
+                        tls_origination: None,
                     }));
                 }
                 // we expected the workload to have a waypoint, but could not find one
@@ -535,12 +991,69 @@ impl OutboundConnection {
             }
         }
 
+        // Workloads on other networks are not directly reachable, so tunnel through the network
+        // gateway they advertise. The gateway terminates our HBONE connection, so it is the
+        // gateway's identity we verify; it then forwards the CONNECT to the workload.
+        if us.workload.network.as_str() != self.pi.cfg.network {
+            let Some(gw) = us.workload.network_gateway.as_ref() else {
+                return Err(Error::UnknownNetworkGateway(format!(
+                    "workload {} is on network {:?}, which has no network gateway",
+                    us.workload.name, us.workload.network
+                )));
+            };
+            let gw_addr = self.pi.state.fetch_gateway_address(gw).await.ok_or(
+                Error::UnknownNetworkGateway(
+                    "unable to resolve network gateway address".to_string(),
+                ),
+            )?;
+            let gw_us = self
+                .pi
+                .state
+                .fetch_upstream(
+                    gw_addr.network.clone(),
+                    &source_workload,
+                    SocketAddr::new(gw_addr.address, gw.hbone_mtls_port),
+                    load,
+                )
+                .await
+                .ok_or(Error::UnknownNetworkGateway(
+                    "unable to determine network gateway upstream".to_string(),
+                ))?;
+            let gw_ip = self
+                .pi
+                .state
+                .pick_workload_destination(
+                    &gw_us.workload,
+                    &source_workload,
+                    self.pi.metrics.clone(),
+                )
+                .await?;
+            return Ok(Box::new(Request {
+                protocol: Protocol::HBONE,
+                source: source_workload,
+                destination: SocketAddr::from((workload_ip, us.port)),
+                destination_workload: Some(us.workload.clone()),
+                destination_service: us.destination_service.clone(),
+                expected_identity: Some(gw_us.workload.identity()),
+                gateway: SocketAddr::new(gw_ip, gw_us.port),
+                request_type: RequestType::ToNetworkGateway,
+                upstream_sans: gw_us.sans,
+                tls_origination: None,
+            }));
+        }
+
         // only change the port if we're sending HBONE
         let gw_addr = match us.workload.protocol {
             Protocol::HBONE => SocketAddr::from((workload_ip, self.pi.hbone_port)),
             Protocol::TCP => SocketAddr::from((workload_ip, us.port)),
         };
 
+        // Mesh workloads are reached over HBONE, which is already encrypted
+        let direct_tls_origination = match us.workload.protocol {
+            Protocol::HBONE => None,
+            Protocol::TCP => tls_origination,
+        };
+
         // For case no waypoint for both side and direct to remote node proxy
         Ok(Box::new(Request {
             protocol: us.workload.protocol,
@@ -552,18 +1065,42 @@ impl OutboundConnection {
             gateway: gw_addr,
             request_type: RequestType::Direct,
             upstream_sans: us.sans,
+            tls_origination: direct_tls_origination,
         }))
     }
 }
 
-fn baggage(r: &Request, cluster: String) -> String {
-    format!("k8s.cluster.name={cluster},k8s.namespace.name={namespace},k8s.{workload_type}.name={workload_name},service.name={name},service.version={version}",
-            namespace = r.source.namespace,
-            workload_type = r.source.workload_type,
-            workload_name = r.source.workload_name,
-            name = r.source.canonical_name,
-            version = r.source.canonical_revision,
-    )
+// UpstreamStream is an established connection to the upstream of a request.
+enum UpstreamStream {
+    Hbone(H2Stream),
+    #[cfg(feature = "quic")]
+    Quic(proxy::h3::H3Stream),
+    Tcp(TcpStream),
+    Tls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+// OutboundLoad reports the proxy's connection load and ejected endpoints, along with the addresses
+// that already failed to connect while retrying a connection, so endpoint selection avoids them.
+struct OutboundLoad<'a> {
+    connection_manager: &'a ConnectionManager,
+    outlier: Option<&'a OutlierDetector>,
+    failed: Option<&'a HashSet<IpAddr>>,
+}
+
+impl EndpointLoad for OutboundLoad<'_> {
+    fn active_connections(&self, ip: IpAddr) -> usize {
+        self.connection_manager.active_connections(ip)
+    }
+
+    fn failed_addresses(&self) -> Option<&HashSet<IpAddr>> {
+        self.failed
+    }
+
+    fn ejected_addresses(&self) -> HashSet<IpAddr> {
+        self.outlier
+            .map(OutlierDetector::ejected)
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug)]
@@ -582,12 +1119,30 @@ struct Request {
     request_type: RequestType,
 
     upstream_sans: Vec<Strng>,
+    // If set, TLS is originated to the destination, on behalf of the plaintext client.
+    tls_origination: Option<TlsOrigination>,
+}
+
+impl Request {
+    // selected_upstream returns the address load balancing picked for this request: the waypoint
+    // when going through one, and otherwise the destination endpoint. Requests to other networks
+    // dial the network gateway, which is shared by all of that network's endpoints, so failures
+    // are attributed to the endpoint behind it.
+    fn selected_upstream(&self) -> IpAddr {
+        match self.request_type {
+            RequestType::ToServerWaypoint => self.gateway.ip(),
+            _ => self.destination.ip(),
+        }
+    }
 }
 
 #[derive(PartialEq, Debug)]
 enum RequestType {
     /// ToServerWaypoint refers to requests targeting a server waypoint proxy
     ToServerWaypoint,
+    /// ToNetworkGateway refers to requests to a workload on another network, sent through that
+    /// network's gateway
+    ToNetworkGateway,
     /// Direct requests are made directly to a intended backend pod
     Direct,
     /// Passthrough refers to requests with an unknown target
@@ -599,15 +1154,22 @@ mod tests {
     use std::time::Duration;
 
     use bytes::Bytes;
+    use prometheus_client::registry::Registry;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     use super::*;
     use crate::config::Config;
+    use crate::metrics::sub_registry;
     use crate::proxy::connection_manager::ConnectionManager;
-    use crate::test_helpers::helpers::test_proxy_metrics;
+    use crate::state::workload::NamespacedHostname;
+    use crate::state::DemandProxyState;
+    use crate::test_helpers::helpers::{self, test_proxy_metrics};
     use crate::test_helpers::new_proxy_state;
+    use crate::test_helpers::tcp;
     use crate::xds::istio::workload::address::Type as XdsAddressType;
     use crate::xds::istio::workload::NetworkAddress as XdsNetworkAddress;
     use crate::xds::istio::workload::Port;
+    use crate::xds::istio::workload::PortList as XdsPortList;
     use crate::xds::istio::workload::Service as XdsService;
     use crate::xds::istio::workload::TunnelProtocol as XdsProtocol;
     use crate::xds::istio::workload::Workload as XdsWorkload;
@@ -619,6 +1181,38 @@ mod tests {
         xds: XdsAddressType,
         expect: Option<ExpectedRequest<'_>>,
     ) {
+        let req = build_test_request(from, to, vec![xds]).await;
+        if let Some(r) = req {
+            assert_eq!(
+                expect,
+                Some(ExpectedRequest {
+                    protocol: r.protocol,
+                    destination: &r.destination.to_string(),
+                    gateway: &r.gateway.to_string(),
+                    request_type: r.request_type,
+                })
+            );
+        } else {
+            assert_eq!(expect, None);
+        }
+    }
+
+    // build_test_request builds the request for a connection from `from` to `to`, with the given
+    // addresses known alongside a source and waypoint workload.
+    async fn build_test_request(
+        from: &str,
+        to: &str,
+        xds: Vec<XdsAddressType>,
+    ) -> Option<Box<Request>> {
+        build_test_request_with(from, to, xds, |_| {}).await
+    }
+
+    async fn build_test_request_with(
+        from: &str,
+        to: &str,
+        xds: Vec<XdsAddressType>,
+        prepare: impl FnOnce(&DemandProxyState),
+    ) -> Option<Box<Request>> {
         let cfg = Arc::new(Config {
             local_node: Some("local-node".to_string()),
             ..crate::config::parse_config().unwrap()
@@ -638,47 +1232,130 @@ mod tests {
             addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 10])],
             node: "local-node".to_string(),
             service_account: "waypoint-sa".to_string(),
+            services: HashMap::from([(
+                "ns/waypoint.ns.svc.cluster.local".to_string(),
+                XdsPortList {
+                    ports: vec![Port {
+                        service_port: 15008,
+                        target_port: 15008,
+                    }],
+                },
+            )]),
             ..Default::default()
         };
-        let state = match xds {
-            XdsAddressType::Workload(wl) => new_proxy_state(&[source, waypoint, wl], &[], &[]),
-            XdsAddressType::Service(svc) => new_proxy_state(&[source, waypoint], &[svc], &[]),
+        // The waypoint is also reachable through a service, for waypoints referenced by hostname
+        let waypoint_svc = XdsService {
+            name: "waypoint".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "waypoint.ns.svc.cluster.local".to_string(),
+            addresses: vec![XdsNetworkAddress {
+                network: "".to_string(),
+                address: vec![127, 0, 0, 11],
+            }],
+            ports: vec![Port {
+                service_port: 15008,
+                target_port: 15008,
+            }],
+            ..Default::default()
         };
+        let mut workloads = vec![source, waypoint];
+        let mut services = vec![waypoint_svc];
+        for address in xds {
+            match address {
+                XdsAddressType::Workload(wl) => workloads.push(wl),
+                XdsAddressType::Service(svc) => services.push(svc),
+            }
+        }
+        let state = new_proxy_state(&workloads, &services, &[]);
+        prepare(&state);
+
+        let outbound = test_outbound(cfg, state, test_proxy_metrics());
+        outbound
+            .build_request(
+                from.parse().unwrap(),
+                to.parse().unwrap(),
+                &outbound.endpoint_load(None),
+            )
+            .await
+            .ok()
+    }
 
+    fn test_outbound(
+        cfg: Arc<Config>,
+        state: DemandProxyState,
+        metrics: Arc<crate::proxy::Metrics>,
+    ) -> OutboundConnection {
         let sock_fact = std::sync::Arc::new(crate::proxy::DefaultSocketFactory);
         let cert_mgr = identity::mock::new_secret_manager(Duration::from_secs(10));
-        let outbound = OutboundConnection {
+        OutboundConnection {
             pi: Arc::new(ProxyInputs {
                 cert_manager: identity::mock::new_secret_manager(Duration::from_secs(10)),
                 state,
                 hbone_port: 15008,
                 cfg: cfg.clone(),
-                metrics: test_proxy_metrics(),
+                metrics,
                 socket_factory: sock_fact.clone(),
                 proxy_workload_info: None,
                 connection_manager: ConnectionManager::default(),
+                accept_limiter: None,
+                outlier: None,
+                connection_budget: None,
             }),
             id: TraceParent::new(),
-            pool: pool::WorkloadHBONEPool::new(cfg, sock_fact, cert_mgr.clone()),
+            connection_id: ConnectionId::new(),
+            pool: pool::WorkloadHBONEPool::new(
+                cfg,
+                sock_fact,
+                cert_mgr.clone(),
+                test_proxy_metrics(),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn drained_connection_close_reason() {
+        let echo = tcp::TestServer::new(tcp::Mode::ReadWrite, 0).await;
+        // An address outside the mesh, so the connection is passed through
+        let echo_addr = helpers::with_ip(echo.address(), "127.0.0.2".parse().unwrap());
+        tokio::spawn(echo.run());
+
+        let source = XdsWorkload {
+            uid: "cluster1//v1/Pod/ns/source-workload".to_string(),
+            name: "source-workload".to_string(),
+            namespace: "ns".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 1])],
+            ..Default::default()
         };
+        let mut registry = Registry::default();
+        let metrics = Arc::new(crate::proxy::Metrics::new(sub_registry(&mut registry)));
+        let mut outbound = test_outbound(
+            Arc::new(crate::config::parse_config().unwrap()),
+            new_proxy_state(&[source], &[], &[]),
+            metrics,
+        );
 
-        let req = outbound
-            .build_request(from.parse().unwrap(), to.parse().unwrap())
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
             .await
-            .ok();
-        if let Some(r) = req {
-            assert_eq!(
-                expect,
-                Some(ExpectedRequest {
-                    protocol: r.protocol,
-                    destination: &r.destination.to_string(),
-                    gateway: &r.gateway.to_string(),
-                    request_type: r.request_type,
-                })
-            );
-        } else {
-            assert_eq!(expect, None);
-        }
+            .unwrap();
+        let (stream, source_addr) = listener.accept().await.unwrap();
+        let (drain_signal, drain) = drain::channel();
+        let proxy = tokio::spawn(async move {
+            outbound
+                .proxy_to_cancellable(stream, source_addr, echo_addr, false, Some(drain))
+                .await
+        });
+
+        // The connection is established once data makes a round trip
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).await.unwrap();
+
+        drain_signal.drain().await;
+        proxy.await.unwrap();
+        let mut buf = String::new();
+        prometheus_client::encoding::text::encode(&mut buf, &registry).unwrap();
+        assert!(buf.contains("reason=\"drained\""), "{buf}");
     }
 
     #[tokio::test]
@@ -910,6 +1587,315 @@ mod tests {
         .await;
     }
 
+    fn hostname_waypoint() -> xds::istio::workload::GatewayAddress {
+        xds::istio::workload::GatewayAddress {
+            destination: Some(
+                xds::istio::workload::gateway_address::Destination::Hostname(
+                    xds::istio::workload::NamespacedHostname {
+                        namespace: "ns".to_string(),
+                        hostname: "waypoint.ns.svc.cluster.local".to_string(),
+                    },
+                ),
+            ),
+            hbone_mtls_port: 15008,
+            hbone_single_tls_port: 15003,
+        }
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/default/my-pod".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                waypoint: Some(hostname_waypoint()),
+                ..Default::default()
+            }),
+            // Should tunnel to a waypoint endpoint, addressed to the original destination
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.2:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn build_request_destination_svc_hostname_waypoint() {
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.3:80",
+            XdsAddressType::Service(XdsService {
+                addresses: vec![XdsNetworkAddress {
+                    network: "".to_string(),
+                    address: vec![127, 0, 0, 3],
+                }],
+                ports: vec![Port {
+                    service_port: 80,
+                    target_port: 8080,
+                }],
+                waypoint: Some(hostname_waypoint()),
+                ..Default::default()
+            }),
+            // Should tunnel to a waypoint endpoint, addressed to the service
+            Some(ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "127.0.0.3:80",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn build_request_unknown_hostname_waypoint() {
+        let mut waypoint = hostname_waypoint();
+        if let Some(xds::istio::workload::gateway_address::Destination::Hostname(h)) =
+            waypoint.destination.as_mut()
+        {
+            h.hostname = "missing.ns.svc.cluster.local".to_string();
+        }
+        run_build_request(
+            "127.0.0.1",
+            "127.0.0.2:80",
+            XdsAddressType::Workload(XdsWorkload {
+                uid: "cluster1//v1/Pod/default/my-pod".to_string(),
+                addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 2])],
+                waypoint: Some(waypoint),
+                ..Default::default()
+            }),
+            // The waypoint cannot be found, so the request fails rather than bypassing it
+            None,
+        )
+        .await;
+    }
+
+    // remote_service returns a service with a single endpoint on the "remote" network, which
+    // advertises the given network gateway, along with that endpoint.
+    fn remote_service(
+        network_gateway: Option<xds::istio::workload::GatewayAddress>,
+    ) -> Vec<XdsAddressType> {
+        let svc = XdsService {
+            name: "svc".to_string(),
+            namespace: "default".to_string(),
+            hostname: "svc.default.svc.cluster.local".to_string(),
+            addresses: vec![XdsNetworkAddress {
+                network: "".to_string(),
+                address: vec![127, 0, 0, 3],
+            }],
+            ports: vec![Port {
+                service_port: 80,
+                target_port: 8080,
+            }],
+            ..Default::default()
+        };
+        let remote = XdsWorkload {
+            uid: "cluster2//v1/Pod/default/remote-pod".to_string(),
+            name: "remote-pod".to_string(),
+            namespace: "default".to_string(),
+            network: "remote".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[10, 0, 0, 5])],
+            tunnel_protocol: XdsProtocol::Hbone as i32,
+            network_gateway,
+            services: HashMap::from([(
+                "default/svc.default.svc.cluster.local".to_string(),
+                XdsPortList {
+                    ports: vec![Port {
+                        service_port: 80,
+                        target_port: 8080,
+                    }],
+                },
+            )]),
+            ..Default::default()
+        };
+        vec![
+            XdsAddressType::Service(svc),
+            XdsAddressType::Workload(remote),
+        ]
+    }
+
+    #[tokio::test]
+    async fn build_request_network_gateway() {
+        let gateway = XdsWorkload {
+            uid: "cluster2//v1/Pod/istio-system/eastwest-gateway".to_string(),
+            name: "eastwest-gateway".to_string(),
+            namespace: "istio-system".to_string(),
+            network: "remote".to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, 20])],
+            service_account: "eastwest-gateway-sa".to_string(),
+            ..Default::default()
+        };
+        let mut xds = remote_service(Some(xds::istio::workload::GatewayAddress {
+            destination: Some(xds::istio::workload::gateway_address::Destination::Address(
+                XdsNetworkAddress {
+                    network: "remote".to_string(),
+                    address: vec![127, 0, 0, 20],
+                },
+            )),
+            hbone_mtls_port: 15008,
+            hbone_single_tls_port: 15003,
+        }));
+        xds.push(XdsAddressType::Workload(gateway));
+
+        let req = build_test_request("127.0.0.1", "127.0.0.3:80", xds)
+            .await
+            .expect("request should be built");
+        // The connection is sent to the gateway, addressed to the remote workload
+        assert_eq!(req.protocol, Protocol::HBONE);
+        assert_eq!(req.request_type, RequestType::ToNetworkGateway);
+        assert_eq!(req.gateway.to_string(), "127.0.0.20:15008");
+        assert_eq!(req.destination.to_string(), "10.0.0.5:8080");
+        // Connect failures count against the remote endpoint, not the shared gateway
+        assert_eq!(req.selected_upstream().to_string(), "10.0.0.5");
+        assert_eq!(
+            req.destination_workload.map(|w| w.name),
+            Some(strng::new("remote-pod"))
+        );
+        // The gateway terminates the tunnel, so it is the identity we verify
+        assert_eq!(
+            req.expected_identity.unwrap().to_string(),
+            "spiffe://cluster.local/ns/istio-system/sa/eastwest-gateway-sa"
+        );
+    }
+
+    #[tokio::test]
+    async fn build_request_network_gateway_missing() {
+        // Without a gateway, the remote workload is unreachable
+        let req = build_test_request("127.0.0.1", "127.0.0.3:80", remote_service(None)).await;
+        assert!(req.is_none());
+
+        // The gateway is advertised, but unknown, so we cannot verify its identity
+        let xds = remote_service(Some(xds::istio::workload::GatewayAddress {
+            destination: Some(xds::istio::workload::gateway_address::Destination::Address(
+                XdsNetworkAddress {
+                    network: "remote".to_string(),
+                    address: vec![127, 0, 0, 20],
+                },
+            )),
+            hbone_mtls_port: 15008,
+            hbone_single_tls_port: 15003,
+        }));
+        let req = build_test_request("127.0.0.1", "127.0.0.3:80", xds).await;
+        assert!(req.is_none());
+    }
+
+    fn external_service(waypoint: Option<xds::istio::workload::GatewayAddress>) -> XdsService {
+        XdsService {
+            name: "api".to_string(),
+            namespace: "ns".to_string(),
+            hostname: "api.example.com".to_string(),
+            ports: vec![Port {
+                service_port: 443,
+                target_port: 443,
+            }],
+            waypoint,
+            ..Default::default()
+        }
+    }
+
+    fn capture_external_service(state: &DemandProxyState) {
+        state.capture_hostname(
+            NetworkAddress {
+                network: "".into(),
+                address: "1.2.3.4".parse().unwrap(),
+            },
+            NamespacedHostname {
+                namespace: "ns".into(),
+                hostname: "api.example.com".into(),
+            },
+            Duration::from_secs(30),
+        );
+    }
+
+    #[tokio::test]
+    async fn build_request_captured_hostname_waypoint() {
+        let xds = vec![XdsAddressType::Service(external_service(Some(
+            hostname_waypoint(),
+        )))];
+        let req = build_test_request_with("127.0.0.1", "1.2.3.4:443", xds, |state| {
+            capture_external_service(state)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            ExpectedRequest {
+                protocol: req.protocol,
+                destination: &req.destination.to_string(),
+                gateway: &req.gateway.to_string(),
+                request_type: req.request_type,
+            },
+            ExpectedRequest {
+                protocol: Protocol::HBONE,
+                destination: "1.2.3.4:443",
+                gateway: "127.0.0.10:15008",
+                request_type: RequestType::ToServerWaypoint,
+            }
+        );
+        assert_eq!(req.selected_upstream().to_string(), "127.0.0.10");
+        assert_eq!(
+            req.destination_service.unwrap().hostname.as_str(),
+            "api.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn build_request_captured_hostname_passthrough() {
+        let xds = || vec![XdsAddressType::Service(external_service(None))];
+        let req = build_test_request_with("127.0.0.1", "1.2.3.4:443", xds(), |state| {
+            capture_external_service(state)
+        })
+        .await
+        .unwrap();
+        assert_eq!(req.request_type, RequestType::Passthrough);
+        assert_eq!(
+            req.destination_service.unwrap().hostname.as_str(),
+            "api.example.com"
+        );
+
+        // Without a captured hostname, the address is unknown
+        let req = build_test_request("127.0.0.1", "1.2.3.4:443", xds())
+            .await
+            .unwrap();
+        assert_eq!(req.request_type, RequestType::Passthrough);
+        assert!(req.destination_service.is_none());
+    }
+
+    #[tokio::test]
+    async fn build_request_tls_origination() {
+        let mut svc = external_service(None);
+        svc.tls_origination = Some(xds::istio::workload::TlsOrigination {
+            sni: "".to_string(),
+            root_certificates: "".to_string(),
+        });
+        let req = build_test_request_with(
+            "127.0.0.1",
+            "1.2.3.4:80",
+            vec![XdsAddressType::Service(svc)],
+            |state| capture_external_service(state),
+        )
+        .await
+        .unwrap();
+        assert_eq!(req.request_type, RequestType::Passthrough);
+        // An unset SNI defaults to the service hostname
+        assert_eq!(req.tls_origination.unwrap().sni.as_str(), "api.example.com");
+
+        // Services without origination are proxied as-is
+        let req = build_test_request_with(
+            "127.0.0.1",
+            "1.2.3.4:443",
+            vec![XdsAddressType::Service(external_service(None))],
+            |state| capture_external_service(state),
+        )
+        .await
+        .unwrap();
+        assert!(req.tls_origination.is_none());
+    }
+
     #[derive(PartialEq, Debug)]
     struct ExpectedRequest<'a> {
         protocol: Protocol,
@@ -0,0 +1,174 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use drain::Watch;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::proxy::connect_udp::ClientFlow;
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::{util, ConnectionId, Error, ProxyInputs, TraceParent};
+use crate::{proxy, socket, telemetry};
+
+// Datagrams queued for a flow beyond this are dropped, as UDP would.
+const FLOW_BUFFER: usize = 128;
+
+type FlowKey = (SocketAddr, SocketAddr);
+
+/// OutboundUdp receives redirected outbound UDP traffic. Each flow, identified by its source and
+/// original destination, is proxied like an outbound TCP connection; to HBONE upstreams, it is
+/// tunneled with CONNECT-UDP.
+pub(super) struct OutboundUdp {
+    pi: ProxyInputs,
+    drain: Watch,
+    socket: Arc<UdpSocket>,
+}
+
+impl OutboundUdp {
+    pub(super) async fn new(pi: ProxyInputs, drain: Watch) -> Result<OutboundUdp, Error> {
+        let socket = pi
+            .socket_factory
+            .udp_bind(pi.cfg.outbound_addr)
+            .map_err(|e| Error::Bind(pi.cfg.outbound_addr, e))?;
+        let transparent = match pi.cfg.enable_original_source {
+            // Explicitly disabled, don't even attempt to set it.
+            Some(false) => false,
+            _ => socket::set_recv_orig_dst(&socket).is_ok(),
+        };
+        info!(
+            address=%socket.local_addr().expect("local_addr available"),
+            component="outbound udp",
+            transparent,
+            "listener established",
+        );
+        Ok(OutboundUdp {
+            pi,
+            drain,
+            socket: Arc::new(socket),
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.socket.local_addr().expect("local_addr available")
+    }
+
+    pub(super) async fn run(self) {
+        let (sub_drain_signal, sub_drain) = drain::channel();
+        let pi = Arc::new(self.pi);
+        let listener = self.socket;
+        let local_addr = socket::to_canonical(listener.local_addr().expect("local_addr available"));
+
+        let pool = proxy::pool::WorkloadHBONEPool::new(
+            pi.cfg.clone(),
+            pi.socket_factory.clone(),
+            pi.cert_manager.clone(),
+            pi.metrics.clone(),
+        );
+        let flows: Arc<Mutex<HashMap<FlowKey, mpsc::Sender<Bytes>>>> = Default::default();
+        let accept = async move {
+            let mut buf = vec![0; u16::MAX as usize];
+            loop {
+                let (len, src, dst) = match socket::recv_orig_dst(&listener, &mut buf).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("failed to receive datagram: {}", e);
+                        continue;
+                    }
+                };
+                let mut datagram = Bytes::copy_from_slice(&buf[..len]);
+                let key = (src, dst);
+                let existing = flows.lock().expect("mutex").get(&key).cloned();
+                if let Some(tx) = existing {
+                    match tx.try_send(datagram) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            debug!(%src, %dst, "udp flow is backed up, dropping datagram");
+                            continue;
+                        }
+                        // The flow has ended; start a new one
+                        Err(TrySendError::Closed(d)) => datagram = d,
+                    }
+                }
+
+                // Replies must come from the original destination. If we did not receive the
+                // traffic transparently, that is the listener itself.
+                let reply = if dst == local_addr {
+                    listener.clone()
+                } else {
+                    match pi.socket_factory.udp_bind_transparent(dst) {
+                        Ok(reply) => Arc::new(reply),
+                        Err(e) => {
+                            warn!(%dst, "failed to bind reply socket, dropping flow: {}", e);
+                            continue;
+                        }
+                    }
+                };
+                let (tx, rx) = mpsc::channel(FLOW_BUFFER);
+                tx.try_send(datagram).expect("new channel has capacity");
+                flows.lock().expect("mutex").insert(key, tx);
+
+                let flow = ClientFlow {
+                    datagrams: rx,
+                    reply,
+                    client: src,
+                };
+                let mut oc = OutboundConnection {
+                    pi: pi.clone(),
+                    id: TraceParent::new(),
+                    connection_id: ConnectionId::new(),
+                    pool: pool.clone(),
+                };
+                let flows = flows.clone();
+                let outbound_drain = sub_drain.clone();
+                let span = info_span!("outbound udp", id=%oc.id, connection_id=%oc.connection_id);
+                telemetry::set_trace_context(&span, &oc.id, None);
+                let serve_flow = async move {
+                    tokio::select! {
+                        _ = outbound_drain.signaled() => {
+                            debug!("outbound udp drain signaled");
+                        }
+                        _ = oc.proxy_udp(flow, src, dst, false) => {}
+                    }
+                    // Forget the flow, unless a new one has already replaced it
+                    let mut flows = flows.lock().expect("mutex");
+                    if flows.get(&key).is_some_and(|tx| tx.is_closed()) {
+                        flows.remove(&key);
+                    }
+                }
+                .instrument(span);
+                tokio::spawn(serve_flow);
+            }
+        }
+        .in_current_span();
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                debug!("outbound udp drained, dropping any flows");
+                sub_drain_signal.drain().await;
+                info!("outbound udp drained");
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::proxy::metrics::Metrics;
+
+/// OutlierDetector passively tracks connect failures to upstream endpoints.
+/// Endpoints that fail to connect `consecutive_failures` times in a row are ejected from outbound
+/// endpoint selection for `ejection_time`, so traffic avoids them until XDS catches up.
+pub struct OutlierDetector {
+    consecutive_failures: u32,
+    ejection_time: Duration,
+    ejections: Counter,
+    ejected_endpoints: Gauge,
+    state: Mutex<OutlierState>,
+}
+
+#[derive(Default)]
+struct OutlierState {
+    // failures tracks the consecutive connect failures to each endpoint, and when the last one was
+    failures: HashMap<IpAddr, (u32, Instant)>,
+    // ejected tracks when each ejected endpoint may be selected again
+    ejected: HashMap<IpAddr, Instant>,
+}
+
+impl OutlierDetector {
+    pub fn new(consecutive_failures: u32, ejection_time: Duration, metrics: &Metrics) -> Self {
+        OutlierDetector {
+            consecutive_failures,
+            ejection_time,
+            ejections: metrics.outlier_ejections.clone(),
+            ejected_endpoints: metrics.outlier_ejected_endpoints.clone(),
+            state: Mutex::new(OutlierState::default()),
+        }
+    }
+
+    /// record_failure records a failed connect to the endpoint, ejecting it once it has failed
+    /// too many times in a row.
+    pub fn record_failure(&self, addr: IpAddr) {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("mutex");
+        self.expire(&mut state, now);
+        if state.ejected.contains_key(&addr) {
+            return;
+        }
+        let (failures, last) = state.failures.entry(addr).or_insert((0, now));
+        *failures += 1;
+        *last = now;
+        if *failures >= self.consecutive_failures {
+            debug!(
+                %addr,
+                failures,
+                ejection_time=?self.ejection_time,
+                "ejecting endpoint after consecutive connect failures"
+            );
+            state.failures.remove(&addr);
+            state.ejected.insert(addr, now + self.ejection_time);
+            self.ejections.inc();
+            self.ejected_endpoints.inc();
+        }
+    }
+
+    /// record_success records a successful connect to the endpoint, resetting its failures.
+    pub fn record_success(&self, addr: IpAddr) {
+        self.state.lock().expect("mutex").failures.remove(&addr);
+    }
+
+    /// ejected returns the endpoints that are currently ejected.
+    pub fn ejected(&self) -> HashSet<IpAddr> {
+        let mut state = self.state.lock().expect("mutex");
+        self.expire(&mut state, Instant::now());
+        state.ejected.keys().copied().collect()
+    }
+
+    // expire returns endpoints whose ejection is over to selection, and forgets failures that
+    // are too old to be considered consecutive with new ones.
+    fn expire(&self, state: &mut OutlierState, now: Instant) {
+        let before = state.ejected.len();
+        state.ejected.retain(|_, until| *until > now);
+        self.ejected_endpoints
+            .dec_by((before - state.ejected.len()) as i64);
+        state
+            .failures
+            .retain(|_, (_, last)| now.duration_since(*last) < self.ejection_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+
+    #[tokio::test(start_paused = true)]
+    async fn eject_after_consecutive_failures() {
+        let metrics = test_proxy_metrics();
+        let od = OutlierDetector::new(3, Duration::from_secs(30), &metrics);
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // A success resets the consecutive failures
+        od.record_failure(addr);
+        od.record_failure(addr);
+        od.record_success(addr);
+        od.record_failure(addr);
+        od.record_failure(addr);
+        assert!(od.ejected().is_empty());
+
+        od.record_failure(addr);
+        assert_eq!(od.ejected(), HashSet::from([addr]));
+        assert_eq!(metrics.outlier_ejections.get(), 1);
+        assert_eq!(metrics.outlier_ejected_endpoints.get(), 1);
+
+        // Failures while ejected do not extend the ejection
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        od.record_failure(addr);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(od.ejected().is_empty());
+        assert_eq!(metrics.outlier_ejected_endpoints.get(), 0);
+
+        // Failures spread out further than the ejection time are not consecutive
+        for _ in 0..3 {
+            od.record_failure(addr);
+            tokio::time::sleep(Duration::from_secs(31)).await;
+        }
+        assert!(od.ejected().is_empty());
+        assert_eq!(metrics.outlier_ejections.get(), 1);
+    }
+}
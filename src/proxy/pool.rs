@@ -15,9 +15,10 @@
 #![warn(clippy::cast_lossless)]
 use super::h2;
 use super::{Error, SocketFactory};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use std::collections::hash_map::DefaultHasher;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
@@ -32,7 +33,7 @@ use std::sync::Arc;
 use tokio::sync::watch;
 
 use tokio::sync::Mutex;
-use tracing::{debug, trace};
+use tracing::{debug, trace, trace_span, Instrument};
 
 use crate::config;
 use crate::identity::{Identity, SecretManager};
@@ -43,6 +44,7 @@ use pingora_pool;
 
 use crate::proxy::h2::client::H2ConnectClient;
 use crate::proxy::h2::H2Stream;
+use crate::proxy::metrics::{self, Metrics, Reporter};
 
 // A relatively nonstandard HTTP/2 connection pool designed to allow multiplexing proxied workload connections
 // over a (smaller) number of HTTP/2 mTLS tunnels.
@@ -50,12 +52,17 @@ use crate::proxy::h2::H2Stream;
 // The following invariants apply to this pool:
 // - Every workload (inpod mode) gets its own connpool.
 // - Every unique src/dest key gets their own dedicated connections inside the pool.
-// - Every unique src/dest key gets 1-n dedicated connections, where N is (currently) unbounded but practically limited
+// - Every unique src/dest key gets 1-n dedicated connections, where N is unbounded but practically limited
 //   by flow control throttling.
+// - Every unique src/dest key keeps at most `pool_max_conns_per_destination` connections in the pool, if set.
+//   Connections beyond that limit are still created on demand, but are never pooled.
 #[derive(Clone)]
 pub struct WorkloadHBONEPool {
     state: Arc<PoolState>,
     pool_watcher: watch::Receiver<bool>,
+    // Connections to destinations that accept HBONE over QUIC
+    #[cfg(feature = "quic")]
+    quic: super::h3::client::QuicPool,
 }
 
 // PoolState is effectively the gnarly inner state stuff that needs thread/task sync, and should be wrapped in a Mutex.
@@ -71,20 +78,50 @@ struct PoolState {
     // This is merely a counter to track the overall number of conns this pool spawns
     // to ensure we get unique poolkeys-per-new-conn, it is not a limit
     pool_global_conn_count: AtomicI32,
+    // Tracks the number of open, poolable connections per key, to enforce the per-destination limit.
+    // Keys are removed once their last connection closes.
+    conn_slots: Arc<std::sync::Mutex<HashMap<u64, usize>>>,
     spawner: ConnSpawner,
 }
 
+// ConnSlot represents a connection counted against the per-destination pooled connection limit.
+// The slot is released when the connection is closed.
+struct ConnSlot {
+    key: u64,
+    slots: Arc<std::sync::Mutex<HashMap<u64, usize>>>,
+}
+
+impl Drop for ConnSlot {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().unwrap();
+        if let Entry::Occupied(mut count) = slots.entry(self.key) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+}
+
 struct ConnSpawner {
     cfg: Arc<config::Config>,
     socket_factory: Arc<dyn SocketFactory + Send + Sync>,
     cert_manager: Arc<SecretManager>,
+    metrics: Arc<Metrics>,
     timeout_rx: watch::Receiver<bool>,
 }
 
 // Does nothing but spawn new conns when asked
 impl ConnSpawner {
-    async fn new_pool_conn(&self, key: WorkloadKey) -> Result<ConnClient, Error> {
+    // Spawns a new connection. If no slot is provided, the connection exceeds the per-destination
+    // limit and will not be pooled.
+    async fn new_pool_conn(
+        &self,
+        key: WorkloadKey,
+        slot: Option<ConnSlot>,
+    ) -> Result<ConnClient, Error> {
         debug!("spawning new pool conn for {}", key);
+        let pooled = slot.is_some();
 
         let local = self
             .cfg
@@ -92,18 +129,51 @@ impl ConnSpawner {
             .unwrap_or_default()
             .then_some(key.src);
         let cert = self.cert_manager.fetch_certificate(&key.src_id).await?;
-        let connector = cert.outbound_connector(key.dst_id.clone())?;
+        let connector = cert.outbound_connector(
+            &self.cfg.tls_settings,
+            key.dst_id.clone(),
+            self.cfg.tls_session_lifetime,
+            &self.cfg.trust_domain_aliases,
+            self.cert_manager.trust_bundles(),
+        )?;
+        let latency = self.metrics.latency_labels(Reporter::source, None);
+        let start = Instant::now();
         let tcp_stream =
             super::freebind_connect(local, key.dst, self.socket_factory.as_ref()).await?;
+        metrics::observe_duration(
+            &self.metrics.tcp_connect_duration,
+            &latency,
+            start.elapsed(),
+        );
         tcp_stream.set_nodelay(true)?;
-        let tls_stream = connector.connect(tcp_stream).await?;
+        super::set_socket_options(
+            &tcp_stream,
+            &self.cfg.socket_config,
+            super::SocketClass::Mesh,
+        );
+        super::set_dscp(&tcp_stream, key.dscp);
+        let start = Instant::now();
+        let tls_stream = connector
+            .connect(tcp_stream)
+            .instrument(trace_span!("tls handshake", dst = %key.dst))
+            .await?;
+        metrics::observe_duration(
+            &self.metrics.tls_handshake_duration,
+            &latency,
+            start.elapsed(),
+        );
         trace!("connector connected, handshaking");
-        let sender =
-            h2::client::spawn_connection(self.cfg.clone(), tls_stream, self.timeout_rx.clone())
-                .await?;
+        let sender = h2::client::spawn_connection(
+            self.cfg.clone(),
+            tls_stream,
+            self.timeout_rx.clone(),
+            slot,
+        )
+        .await?;
         let client = ConnClient {
             sender,
             wl_key: key,
+            pooled,
         };
         Ok(client)
     }
@@ -131,6 +201,15 @@ impl PoolState {
     // Note that this simply removes the client ref from this pool - if other things hold client/streamrefs refs,
     // they must also drop those before the underlying connection is fully closed.
     fn maybe_checkin_conn(&self, conn: ConnClient, pool_key: pingora_pool::ConnectionMeta) {
+        if !conn.pooled {
+            debug!(
+                "connection for {:?} exceeds the per-destination connection limit; not pooling",
+                pool_key
+            );
+            // Wake up anyone waiting on a connection for this key, so they can create their own.
+            let _ = self.pool_notifier.send(true);
+            return;
+        }
         if conn.sender.will_be_at_max_streamcount() {
             debug!(
                 "checked out connection for {:?} is now at max streamcount; removing from pool",
@@ -156,6 +235,26 @@ impl PoolState {
         let _ = self.pool_notifier.send(true);
     }
 
+    // Attempts to claim a slot for a new connection for the given key. Returns None if the key is
+    // already at the per-destination connection limit.
+    fn claim_conn_slot(&self, hash_key: u64) -> Option<ConnSlot> {
+        let max = self
+            .spawner
+            .cfg
+            .pool_max_conns_per_destination
+            .unwrap_or(usize::MAX);
+        let mut slots = self.conn_slots.lock().unwrap();
+        let count = slots.get(&hash_key).copied().unwrap_or_default();
+        if count >= max {
+            return None;
+        }
+        slots.insert(hash_key, count + 1);
+        Some(ConnSlot {
+            key: hash_key,
+            slots: self.conn_slots.clone(),
+        })
+    }
+
     // Since we are using a hash key to do lookup on the inner pingora pool, do a get guard
     // to make sure what we pull out actually deep-equals the workload_key, to avoid *sigh* crossing the streams.
     fn guarded_get(
@@ -229,7 +328,10 @@ impl PoolState {
             Ok(_guard) => {
                 // BEGIN take inner writelock
                 debug!("nothing else is creating a conn and we won the lock, make one");
-                let client = self.spawner.new_pool_conn(workload_key.clone()).await?;
+                let client = self
+                    .spawner
+                    .new_pool_conn(workload_key.clone(), self.claim_conn_slot(pool_key.key))
+                    .await?;
 
                 debug!(
                     "checking in new conn for {} with pk {:?}",
@@ -311,7 +413,10 @@ impl PoolState {
                 }
                 None => {
                     debug!("new connection needed for {}", workload_key);
-                    break self.spawner.new_pool_conn(workload_key.clone()).await?;
+                    break self
+                        .spawner
+                        .new_pool_conn(workload_key.clone(), self.claim_conn_slot(pool_key.key))
+                        .await?;
                 }
             };
         };
@@ -340,15 +445,24 @@ impl WorkloadHBONEPool {
         cfg: Arc<crate::config::Config>,
         socket_factory: Arc<dyn SocketFactory + Send + Sync>,
         cert_manager: Arc<SecretManager>,
+        metrics: Arc<Metrics>,
     ) -> WorkloadHBONEPool {
         let (timeout_tx, timeout_rx) = watch::channel(false);
         let (timeout_send, timeout_recv) = watch::channel(false);
         let pool_duration = cfg.pool_unused_release_timeout;
 
+        #[cfg(feature = "quic")]
+        let quic = super::h3::client::QuicPool::new(
+            cfg.clone(),
+            socket_factory.clone(),
+            cert_manager.clone(),
+            metrics.clone(),
+        );
         let spawner = ConnSpawner {
             cfg,
             socket_factory,
             cert_manager,
+            metrics,
             timeout_rx: timeout_recv.clone(),
         };
 
@@ -363,12 +477,21 @@ impl WorkloadHBONEPool {
                 established_conn_writelock: flurry::HashMap::new(),
                 pool_unused_release_timeout: pool_duration,
                 pool_global_conn_count: AtomicI32::new(0),
+                conn_slots: Default::default(),
                 spawner,
             }),
             pool_watcher: timeout_rx,
+            #[cfg(feature = "quic")]
+            quic,
         }
     }
 
+    /// quic returns the pool of QUIC connections, which are kept apart from the HTTP/2 ones.
+    #[cfg(feature = "quic")]
+    pub fn quic(&self) -> &super::h3::client::QuicPool {
+        &self.quic
+    }
+
     pub async fn send_request_pooled(
         &mut self,
         workload_key: &WorkloadKey,
@@ -513,6 +636,8 @@ struct ConnClient {
     sender: H2ConnectClient,
     // A WL key may have many clients, but every client has no more than one WL key
     wl_key: WorkloadKey, // the WL key associated with this client.
+    // Whether this client may be returned to the pool.
+    pooled: bool,
 }
 
 impl ConnClient {
@@ -544,6 +669,9 @@ pub struct WorkloadKey {
     // Because we spoof the source IP, we need to key on this as well. Note: for in-pod its already per-pod
     // pools anyways.
     pub src: IpAddr,
+    // The DSCP mark propagated from the downstream, if any. Connections with different marks
+    // are not shared, so each HBONE connection carries a single mark.
+    pub dscp: Option<u8>,
 }
 
 impl Display for WorkloadKey {
@@ -580,7 +708,7 @@ mod test {
 
     use tracing::{error, Instrument};
 
-    use crate::test_helpers::helpers::initialize_telemetry;
+    use crate::test_helpers::helpers::{initialize_telemetry, test_proxy_metrics};
 
     use ztunnel::test_helpers::*;
 
@@ -669,6 +797,28 @@ mod test {
         assert_opens_drops!(srv, 5, 1);
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn per_destination_connection_limits() {
+        let (pool, mut srv) = setup_test_with_config(2, Duration::from_secs(100), Some(1)).await;
+
+        let key = key(&srv, 1);
+
+        // Pool allows 2 streams per conn, and 1 pooled conn per destination. The first conn serves
+        // 2 streams; the overflow streams each get their own connection, since they cannot be pooled.
+        spawn_clients_concurrently(pool.clone(), key.clone(), srv.addr, 4).await;
+        assert_opens_drops!(srv, 3, 3);
+
+        // Once every connection for the destination is closed, its slot is removed.
+        let slots = pool.state.conn_slots.clone();
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !slots.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connection slots should be released");
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn server_goaway() {
         let (pool, mut srv) = setup_test(2).await;
@@ -979,6 +1129,14 @@ mod test {
     async fn setup_test_with_idle(
         max_conns: u16,
         idle: Duration,
+    ) -> (WorkloadHBONEPool, TestServer) {
+        setup_test_with_config(max_conns, idle, None).await
+    }
+
+    async fn setup_test_with_config(
+        max_conns: u16,
+        idle: Duration,
+        max_conns_per_destination: Option<usize>,
     ) -> (WorkloadHBONEPool, TestServer) {
         initialize_telemetry();
         let conn_counter: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
@@ -989,11 +1147,12 @@ mod test {
         let cfg = crate::config::Config {
             pool_max_streams_per_conn: max_conns,
             pool_unused_release_timeout: idle,
+            pool_max_conns_per_destination: max_conns_per_destination,
             ..crate::config::parse_config().unwrap()
         };
         let sock_fact = Arc::new(crate::proxy::DefaultSocketFactory);
         let cert_mgr = identity::mock::new_secret_manager(Duration::from_secs(10));
-        let pool = WorkloadHBONEPool::new(Arc::new(cfg), sock_fact, cert_mgr);
+        let pool = WorkloadHBONEPool::new(Arc::new(cfg), sock_fact, cert_mgr, test_proxy_metrics());
         let server = TestServer {
             conn_counter,
             drop_rx,
@@ -1016,6 +1175,7 @@ mod test {
             dst_id: vec![Identity::default()],
             src: IpAddr::from([127, 0, 0, ip]),
             dst: srv.addr,
+            dscp: None,
         }
     }
 }
@@ -0,0 +1,100 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// TokenBucket is a token bucket rate limiter.
+/// Tokens are replenished continuously at `rate` per second, up to a maximum of `burst`.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new bucket, which starts out full.
+    pub fn new(rate: u32, burst: u32) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            state: Mutex::new(BucketState {
+                tokens: burst as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    // try_acquire takes a token if one is available, otherwise returns how long until one will be.
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().expect("mutex");
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.burst);
+        state.last = now;
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - state.tokens) / self.rate))
+        }
+    }
+
+    /// acquire waits until a token is available and takes it.
+    /// Returns true if the caller was throttled, i.e. had to wait for the token.
+    pub async fn acquire(&self) -> bool {
+        let mut throttled = false;
+        loop {
+            match self.try_acquire() {
+                Ok(()) => return throttled,
+                Err(wait) => {
+                    throttled = true;
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_then_rate() {
+        let bucket = TokenBucket::new(10, 3);
+        // The full burst is available immediately
+        for _ in 0..3 {
+            assert!(!bucket.acquire().await);
+        }
+        // Then we are limited to the rate
+        let start = Instant::now();
+        assert!(bucket.acquire().await);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Tokens accumulate while idle, but never beyond the burst
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        for _ in 0..3 {
+            assert!(!bucket.acquire().await);
+        }
+        assert!(bucket.acquire().await);
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use drain::Watch;
+use rand::seq::SliceRandom;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, info_span, trace, Instrument};
+
+use crate::proxy::metrics::Reporter;
+use crate::proxy::sniff::{self, ClientHelloSni};
+use crate::proxy::{metrics, util, ConnectionId, Error, ProxyInputs};
+use crate::strng::Strng;
+use crate::{copy, socket, strng};
+
+// How long a client may take to send its ClientHello.
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The port a plain hostname SNI routes to. Other ports are selected with an Istio style SNI.
+const DEFAULT_SNI_PORT: u16 = 443;
+
+/// SniRouter accepts TLS connections and routes them to an endpoint of the service named by their
+/// SNI, without terminating TLS. This lets ztunnel act as a simple east-west TLS passthrough
+/// gateway.
+pub(super) struct SniRouter {
+    pi: ProxyInputs,
+    listener: TcpListener,
+    drain: Watch,
+}
+
+impl SniRouter {
+    pub(super) async fn new(pi: ProxyInputs, drain: Watch) -> Result<SniRouter, Error> {
+        let addr = pi
+            .cfg
+            .sni_router_addr
+            .expect("sni router address configured");
+        let listener: TcpListener = pi
+            .socket_factory
+            .tcp_bind(addr)
+            .map_err(|e| Error::Bind(addr, e))?;
+
+        info!(
+            address=%listener.local_addr().expect("local_addr available"),
+            component="sni router",
+            "listener established",
+        );
+
+        Ok(SniRouter {
+            pi,
+            listener,
+            drain,
+        })
+    }
+
+    pub(super) fn address(&self) -> SocketAddr {
+        self.listener.local_addr().expect("local_addr available")
+    }
+
+    pub(super) async fn run(self) {
+        let pi = Arc::new(self.pi);
+        let listener = self.listener;
+        let accept = async move {
+            loop {
+                super::throttle_accept(&pi).await;
+                let socket = listener.accept().await;
+                match socket {
+                    Ok((stream, remote)) => {
+                        let Ok(permit) = super::admit_connection(&pi, &stream) else {
+                            continue;
+                        };
+                        let connection_id = ConnectionId::new();
+                        let span = info_span!("sni router", connection_id=%connection_id);
+                        let pi = pi.clone();
+                        tokio::spawn(
+                            async move {
+                                let _permit = permit;
+                                route(pi, connection_id, socket::to_canonical(remote), stream).await
+                            }
+                            .instrument(span),
+                        );
+                    }
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("Failed TCP handshake {}", e);
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            res = accept => { res }
+            _ = self.drain.signaled() => {
+                info!("sni router drained");
+            }
+        }
+    }
+}
+
+async fn route(
+    pi: Arc<ProxyInputs>,
+    connection_id: ConnectionId,
+    source_addr: SocketAddr,
+    mut stream: TcpStream,
+) {
+    let start = Instant::now();
+    super::set_socket_options(&stream, &pi.cfg.socket_config, super::SocketClass::App);
+    let local_addr = socket::to_canonical(stream.local_addr().expect("local_addr available"));
+    let (client_hello, sni) = match read_client_hello(&mut stream).await {
+        Ok(res) => res,
+        Err(e) => {
+            metrics::log_early_deny(
+                &pi.metrics,
+                source_addr,
+                local_addr,
+                Reporter::destination,
+                e,
+            );
+            return;
+        }
+    };
+    let (hostname, port) = parse_sni(&sni);
+    let Some(upstream) = pi
+        .state
+        .find_sni_upstream(&hostname, port, &pi.connection_manager)
+    else {
+        metrics::log_early_deny(
+            &pi.metrics,
+            source_addr,
+            local_addr,
+            Reporter::destination,
+            Error::UnknownServerName(strng::new(sni)),
+        );
+        return;
+    };
+    let Some(ip) = upstream
+        .workload
+        .workload_ips
+        .choose(&mut rand::thread_rng())
+    else {
+        metrics::log_early_deny(
+            &pi.metrics,
+            source_addr,
+            local_addr,
+            Reporter::destination,
+            Error::NoValidDestination(Box::new(upstream.workload)),
+        );
+        return;
+    };
+    let dest_addr = SocketAddr::new(*ip, upstream.port);
+    let _conn_guard = pi
+        .connection_manager
+        .track_outbound(source_addr, local_addr, dest_addr);
+
+    let result_tracker = metrics::ConnectionResult::new(
+        connection_id,
+        source_addr,
+        dest_addr,
+        None,
+        start,
+        metrics::ConnectionOpen {
+            reporter: Reporter::destination,
+            source: None,
+            derived_source: None,
+            destination: Some(upstream.workload),
+            // The client's TLS is passed through untouched, so it is opaque to us.
+            connection_security_policy: metrics::SecurityPolicy::unknown,
+            destination_service: upstream.destination_service,
+        },
+        pi.metrics.clone(),
+        pi.cfg.reloadable.access_log_format(),
+    );
+
+    let send = async {
+        trace!(%source_addr, %dest_addr, %sni, component="sni router", "connecting...");
+        let mut outbound = super::freebind_connect(None, dest_addr, pi.socket_factory.as_ref())
+            .await
+            .map_err(Error::ConnectionFailed)?;
+        super::set_socket_options(&outbound, &pi.cfg.socket_config, super::SocketClass::App);
+        // Replay the ClientHello we read to route the connection, then relay the rest.
+        outbound.write_all(&client_hello).await?;
+        result_tracker.increment_send(client_hello.len() as u64);
+        copy::copy_bidirectional_tcp(
+            &mut stream,
+            &mut outbound,
+            &result_tracker,
+            pi.cfg.as_ref().into(),
+        )
+        .await
+    };
+    let res = send.await;
+    result_tracker.record(res);
+}
+
+// read_client_hello reads from the stream until the client's ClientHello has been received,
+// returning everything read along with the SNI.
+async fn read_client_hello(stream: &mut TcpStream) -> Result<(BytesMut, String), Error> {
+    let read = async {
+        let mut buf = BytesMut::with_capacity(1024);
+        loop {
+            if let ClientHelloSni::Parsed(sni) = sniff::client_hello_sni(&buf)? {
+                return sni.map(|sni| (buf, sni)).ok_or(Error::MissingSni);
+            }
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    };
+    tokio::time::timeout(CLIENT_HELLO_TIMEOUT, read)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+// parse_sni extracts the service hostname and port from an SNI. Besides plain hostnames, which
+// route to port 443, this accepts the `outbound_.<port>_.<subset>_.<hostname>` form Istio east-west
+// gateways use. Subsets are not supported, and ignored.
+fn parse_sni(sni: &str) -> (Strng, u16) {
+    let mut parts = sni.splitn(4, '.');
+    if let (Some("outbound_"), Some(port), Some(_subset), Some(hostname)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    {
+        if let Some(Ok(port)) = port.strip_suffix('_').map(str::parse) {
+            return (strng::new(hostname), port);
+        }
+    }
+    (strng::new(sni), DEFAULT_SNI_PORT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sni_targets() {
+        let cases = [
+            ("api.example.com", ("api.example.com", 443)),
+            (
+                "outbound_.9080_._.reviews.default.svc.cluster.local",
+                ("reviews.default.svc.cluster.local", 9080),
+            ),
+            (
+                "outbound_.9080_.v1_.reviews.default.svc.cluster.local",
+                ("reviews.default.svc.cluster.local", 9080),
+            ),
+            // Not a valid port, so treated as a plain hostname
+            ("outbound_.http_._.foo", ("outbound_.http_._.foo", 443)),
+        ];
+        for (sni, (hostname, port)) in cases {
+            assert_eq!(parse_sni(sni), (strng::new(hostname), port), "{sni}");
+        }
+    }
+}
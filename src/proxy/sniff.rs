@@ -0,0 +1,304 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+use crate::proxy::metrics::{DetectedProtocol, Metrics, ProtocolMismatchLabels};
+use crate::tls::TlsError;
+
+// The first byte of every TLS connection: a handshake record, carrying the ClientHello.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// detect peeks at the first byte a client sends to judge whether it is starting a TLS handshake,
+/// without consuming it. A client that sends nothing within `timeout` is assumed to be waiting for
+/// the server to speak first.
+pub async fn detect(conn: &TcpStream, timeout: Duration) -> io::Result<DetectedProtocol> {
+    let mut buf = [0u8; 1];
+    match tokio::time::timeout(timeout, conn.peek(&mut buf)).await {
+        Err(_) => Ok(DetectedProtocol::server_first),
+        Ok(Err(e)) => Err(e),
+        Ok(Ok(0)) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Ok(Ok(_)) if buf[0] == TLS_HANDSHAKE_RECORD => Ok(DetectedProtocol::tls),
+        Ok(Ok(_)) => Ok(DetectedProtocol::plaintext),
+    }
+}
+
+// The largest TLS record; a ClientHello must fit in the first one for its SNI to be read.
+pub const MAX_TLS_RECORD: usize = 16384;
+const TLS_RECORD_HEADER: usize = 5;
+const CLIENT_HELLO: u8 = 0x01;
+const SERVER_NAME_EXTENSION: u16 = 0x0000;
+const HOST_NAME: u8 = 0x00;
+
+/// ClientHelloSni is the result of reading the SNI from the start of a TLS connection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientHelloSni {
+    /// The first TLS record has not been fully received yet.
+    Incomplete,
+    /// The ClientHello was read. It does not always carry a server name.
+    Parsed(Option<String>),
+}
+
+/// client_hello_sni reads the server name from a TLS ClientHello, without decrypting or consuming
+/// anything. Only a ClientHello contained in the first TLS record is supported.
+pub fn client_hello_sni(buf: &[u8]) -> io::Result<ClientHelloSni> {
+    if buf.len() < TLS_RECORD_HEADER {
+        return Ok(ClientHelloSni::Incomplete);
+    }
+    if buf[0] != TLS_HANDSHAKE_RECORD {
+        return Err(malformed("not a TLS handshake"));
+    }
+    let len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    if len > MAX_TLS_RECORD {
+        return Err(malformed("TLS record too large"));
+    }
+    let Some(record) = buf.get(TLS_RECORD_HEADER..TLS_RECORD_HEADER + len) else {
+        return Ok(ClientHelloSni::Incomplete);
+    };
+
+    let mut record = Reader(record);
+    if record.u8()? != CLIENT_HELLO {
+        return Err(malformed("not a ClientHello"));
+    }
+    let hello_len = record.u24()?;
+    let mut hello = Reader(record.take(hello_len)?);
+    // legacy_version, random
+    hello.take(2 + 32)?;
+    // legacy_session_id
+    hello.vec8()?;
+    // cipher_suites
+    hello.vec16()?;
+    // legacy_compression_methods
+    hello.vec8()?;
+    if hello.0.is_empty() {
+        // No extensions at all
+        return Ok(ClientHelloSni::Parsed(None));
+    }
+    let mut extensions = hello.vec16()?;
+    while !extensions.0.is_empty() {
+        let ext = extensions.u16()?;
+        let mut data = extensions.vec16()?;
+        if ext != SERVER_NAME_EXTENSION {
+            continue;
+        }
+        let mut names = data.vec16()?;
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name = names.vec16()?;
+            if name_type == HOST_NAME {
+                let name = std::str::from_utf8(name.0).map_err(|_| malformed("invalid SNI"))?;
+                return Ok(ClientHelloSni::Parsed(Some(name.to_string())));
+            }
+        }
+    }
+    Ok(ClientHelloSni::Parsed(None))
+}
+
+fn malformed(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Reader consumes the fields of a TLS message, failing on truncated ones.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(malformed("truncated ClientHello"));
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> io::Result<usize> {
+        let b = self.take(3)?;
+        Ok(u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    fn vec8(&mut self) -> io::Result<Reader<'a>> {
+        let len = self.u8()? as usize;
+        Ok(Reader(self.take(len)?))
+    }
+
+    fn vec16(&mut self) -> io::Result<Reader<'a>> {
+        let len = self.u16()? as usize;
+        Ok(Reader(self.take(len)?))
+    }
+}
+
+/// ProtocolDetector rejects connections to a TLS listener that are not speaking TLS, such as
+/// plaintext traffic misrouted to the HBONE port. Without it, the TLS handshake of a client waiting
+/// for the server to speak first would only fail once it times out.
+#[derive(Clone)]
+pub struct ProtocolDetector {
+    timeout: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl ProtocolDetector {
+    pub fn new(timeout: Duration, metrics: Arc<Metrics>) -> Self {
+        ProtocolDetector { timeout, metrics }
+    }
+
+    /// expect_tls returns an error, and records the mismatch, unless the client starts a TLS
+    /// handshake.
+    pub async fn expect_tls(&self, conn: &TcpStream) -> Result<(), TlsError> {
+        match detect(conn, self.timeout).await {
+            Ok(DetectedProtocol::tls) => Ok(()),
+            Ok(detected) => {
+                self.metrics
+                    .protocol_mismatches
+                    .get_or_create(&ProtocolMismatchLabels { detected })
+                    .inc();
+                Err(TlsError::ProtocolMismatch(detected.to_string()))
+            }
+            Err(e) => Err(TlsError::Handshake(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::helpers::test_proxy_metrics;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), server.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn detect_protocols() {
+        let timeout = Duration::from_secs(5);
+
+        let (mut client, server) = tcp_pair().await;
+        client.write_all(&[0x16, 0x03, 0x01]).await.unwrap();
+        assert_eq!(
+            detect(&server, timeout).await.unwrap(),
+            DetectedProtocol::tls
+        );
+        // Peeking leaves the data for the TLS handshake
+        let mut buf = [0u8; 3];
+        assert_eq!(server.try_read(&mut buf).unwrap(), 3);
+
+        let (mut client, server) = tcp_pair().await;
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert_eq!(
+            detect(&server, timeout).await.unwrap(),
+            DetectedProtocol::plaintext
+        );
+
+        let (client, server) = tcp_pair().await;
+        drop(client);
+        assert!(detect(&server, timeout).await.is_err());
+    }
+
+    // client_hello builds a minimal ClientHello record, with an SNI extension if a name is given.
+    fn client_hello(sni: Option<&str>) -> Vec<u8> {
+        let mut extensions = Vec::new();
+        // An unrelated extension first: supported_versions
+        extensions.extend_from_slice(&[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]);
+        if let Some(name) = sni {
+            let name = name.as_bytes();
+            let list_len = name.len() as u16 + 3;
+            extensions.extend_from_slice(&[0x00, 0x00]);
+            extensions.extend_from_slice(&(list_len + 2).to_be_bytes());
+            extensions.extend_from_slice(&list_len.to_be_bytes());
+            extensions.push(HOST_NAME);
+            extensions.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(name);
+        }
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0u8; 32]);
+        // Empty session id, one cipher suite, null compression
+        hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut handshake = vec![CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![TLS_HANDSHAKE_RECORD, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn parse_client_hello_sni() {
+        let hello = client_hello(Some("reviews.default.svc.cluster.local"));
+        assert_eq!(
+            client_hello_sni(&hello).unwrap(),
+            ClientHelloSni::Parsed(Some("reviews.default.svc.cluster.local".to_string()))
+        );
+        // Trailing application data does not matter
+        let mut more = hello.clone();
+        more.extend_from_slice(b"data");
+        assert_eq!(
+            client_hello_sni(&more).unwrap(),
+            ClientHelloSni::Parsed(Some("reviews.default.svc.cluster.local".to_string()))
+        );
+        for n in [0, 3, 5, hello.len() - 1] {
+            assert_eq!(
+                client_hello_sni(&hello[..n]).unwrap(),
+                ClientHelloSni::Incomplete
+            );
+        }
+
+        assert_eq!(
+            client_hello_sni(&client_hello(None)).unwrap(),
+            ClientHelloSni::Parsed(None)
+        );
+        assert!(client_hello_sni(b"GET / HTTP/1.1\r\n").is_err());
+
+        // A record claiming more data than the ClientHello holds is malformed
+        let mut truncated = hello.clone();
+        truncated[8] = truncated[8].wrapping_add(1);
+        assert!(client_hello_sni(&truncated).is_err());
+    }
+
+    #[tokio::test]
+    async fn server_first_mismatch() {
+        let metrics = test_proxy_metrics();
+        let detector = ProtocolDetector::new(Duration::from_millis(50), metrics.clone());
+        let (_client, server) = tcp_pair().await;
+        assert!(matches!(
+            detector.expect_tls(&server).await,
+            Err(TlsError::ProtocolMismatch(_))
+        ));
+        let labels = ProtocolMismatchLabels {
+            detected: DetectedProtocol::server_first,
+        };
+        assert_eq!(metrics.protocol_mismatches.get_or_create(&labels).get(), 1);
+    }
+}
@@ -16,17 +16,23 @@ use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder};
 use drain::Watch;
 
+use std::collections::HashMap;
+use std::io;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{error, info};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, info_span, Instrument};
 
+use crate::proxy::connect_udp::DatagramSocket;
 use crate::proxy::outbound::OutboundConnection;
-use crate::proxy::{util, Error, ProxyInputs, TraceParent};
-use crate::socket;
+use crate::proxy::{util, ConnectionId, Error, ProxyInputs, TraceParent};
+use crate::{socket, telemetry};
 
 pub(super) struct Socks5 {
     pi: ProxyInputs,
@@ -64,6 +70,7 @@ impl Socks5 {
         let pi = Arc::new(self.pi);
         let accept = async move {
             loop {
+                crate::proxy::throttle_accept(&pi).await;
                 // Asynchronously wait for an inbound socket.
                 let socket = self.listener.accept().await;
                 let stream_drain = inner_drain.clone();
@@ -73,20 +80,31 @@ impl Socks5 {
                     pi.cfg.clone(),
                     pi.socket_factory.clone(),
                     pi.cert_manager.clone(),
+                    pi.metrics.clone(),
                 );
                 match socket {
                     Ok((stream, remote)) => {
+                        let Ok(permit) = crate::proxy::admit_connection(&pi, &stream) else {
+                            continue;
+                        };
                         info!("accepted outbound connection from {}", remote);
                         let oc = OutboundConnection {
                             pi: pi.clone(),
                             id: TraceParent::new(),
+                            connection_id: ConnectionId::new(),
                             pool,
                         };
-                        tokio::spawn(async move {
-                            if let Err(err) = handle(oc, stream, stream_drain, inpod).await {
-                                log::error!("handshake error: {}", err);
+                        let span = info_span!("socks5", id=%oc.id, connection_id=%oc.connection_id);
+                        telemetry::set_trace_context(&span, &oc.id, None);
+                        tokio::spawn(
+                            async move {
+                                let _permit = permit;
+                                if let Err(err) = handle(oc, stream, stream_drain, inpod).await {
+                                    log::error!("handshake error: {}", err);
+                                }
                             }
-                        });
+                            .instrument(span),
+                        );
                     }
                     Err(e) => {
                         if util::is_runtime_shutdown(&e) {
@@ -108,16 +126,42 @@ impl Socks5 {
     }
 }
 
-// handle will process a SOCKS5 connection. This supports a minimal subset of the protocol,
-// sufficient to integrate with common clients:
+const CMD_CONNECT: u8 = 0x01;
+const CMD_UDP_ASSOCIATE: u8 = 0x03;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+// The reply to a successful CONNECT. The bound address is not meaningful to us, so send dummy
+// values - the client generally ignores it.
+const CONNECT_REPLY: [u8; 10] = [
+    0x05, // version
+    0x00, 0x00, // success, rsv
+    0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
+    0x00, 0x00, // port
+];
+// The reply to a command we do not support on the connection.
+#[cfg(unix)]
+const COMMAND_NOT_SUPPORTED_REPLY: [u8; 10] = [
+    0x05, // version
+    0x07, 0x00, // command not supported, rsv
+    0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
+    0x00, 0x00, // port
+];
+
+// Max size of a UDP datagram payload.
+const MAX_UDP_DATAGRAM: usize = 65535;
+// Datagrams queued for a UDP flow beyond this are dropped, as UDP would.
+const FLOW_BUFFER: usize = 128;
+
+// negotiate performs the SOCKS5 handshake, returning the requested command and its address. This
+// supports a minimal subset of the protocol, sufficient to integrate with common clients:
 // - only unauthenticated requests
-// - only CONNECT, with IPv4 or IPv6
-async fn handle(
-    mut oc: OutboundConnection,
-    mut stream: TcpStream,
-    out_drain: Watch,
-    is_inpod: bool,
-) -> Result<(), anyhow::Error> {
+// - only CONNECT and UDP ASSOCIATE, with IPv4 or IPv6
+async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<(u8, SocketAddr), anyhow::Error> {
     // Version(5), Number of auth methods
     let mut version = [0u8; 2];
     stream.read_exact(&mut version).await?;
@@ -144,7 +188,7 @@ async fn handle(
     // Select 'unauthenticated' (0).
     stream.write_all(&[0x05, 0x00]).await?;
 
-    // Version(5), Command - only support CONNECT (1)
+    // Version(5), Command - only support CONNECT (1) and UDP ASSOCIATE (3)
     let mut version_command = [0u8; 2];
     stream.read_exact(&mut version_command).await?;
     let version = version_command[0];
@@ -153,7 +197,8 @@ async fn handle(
         return Err(anyhow::anyhow!("unsupported version"));
     }
 
-    if version_command[1] != 1 {
+    let command = version_command[1];
+    if command != CMD_CONNECT && command != CMD_UDP_ASSOCIATE {
         return Err(anyhow::anyhow!("unsupported command"));
     }
 
@@ -167,17 +212,17 @@ async fn handle(
     let ip;
 
     match atyp[0] {
-        0x01 => {
+        ATYP_IPV4 => {
             let mut hostb = [0u8; 4];
             stream.read_exact(&mut hostb).await?;
             ip = IpAddr::V4(hostb.into());
         }
-        0x04 => {
+        ATYP_IPV6 => {
             let mut hostb = [0u8; 16];
             stream.read_exact(&mut hostb).await?;
             ip = IpAddr::V6(hostb.into());
         }
-        0x03 => {
+        ATYP_DOMAIN => {
             let mut domain_length = [0u8];
             stream.read_exact(&mut domain_length).await?;
             let mut domain = vec![0u8; domain_length[0] as usize];
@@ -195,18 +240,50 @@ async fn handle(
     stream.read_exact(&mut port).await?;
     let port = BigEndian::read_u16(&port);
 
-    let host = SocketAddr::new(ip, port);
+    Ok((command, SocketAddr::new(ip, port)))
+}
+
+// handle will process a SOCKS5 connection. See negotiate for the supported subset of the protocol.
+async fn handle(
+    mut oc: OutboundConnection,
+    mut stream: TcpStream,
+    out_drain: Watch,
+    is_inpod: bool,
+) -> Result<(), anyhow::Error> {
+    let (command, host) = negotiate(&mut stream).await?;
 
     let remote_addr = socket::to_canonical(stream.peer_addr().expect("must receive peer addr"));
 
-    // Send dummy values - the client generally ignores it.
-    let buf = [
-        0x05u8, // versuib
-        0x00, 0x00, // success, rsv
-        0x01, 0x00, 0x00, 0x00, 0x00, // IPv4
-        0x00, 0x00, // port
-    ];
-    stream.write_all(&buf).await?;
+    if command == CMD_UDP_ASSOCIATE {
+        // For UDP ASSOCIATE, the address is the one the client expects to send datagrams from.
+        let relay = udp_associate_bind(&oc.pi, &stream)?;
+        let relay_addr = relay.local_addr()?;
+        let mut buf = vec![0x05u8, 0x00, 0x00]; // version, success, rsv
+        write_address(socket::to_canonical(relay_addr), &mut buf);
+        stream.write_all(&buf).await?;
+
+        info!("accepted udp associate from {remote_addr} via {relay_addr}");
+        tokio::spawn(async move {
+            let associate = udp_associate(oc, stream, relay, host);
+            let res = match is_inpod {
+                true => tokio::select! {
+                    _ = out_drain.signaled() => {
+                        info!("drain signaled");
+                        Ok(())
+                    }
+                    res = associate => res,
+                },
+                false => associate.await,
+            };
+            if let Err(err) = res {
+                info!("udp associate from {remote_addr} failed: {err}");
+            }
+        });
+        return Ok(());
+    }
+
+    stream.write_all(&CONNECT_REPLY).await?;
+    super::set_socket_options(&stream, &oc.pi.cfg.socket_config, super::SocketClass::App);
 
     info!("accepted connection from {remote_addr} to {host}");
     // For inpod, we want this `spawn` to guaranteed-terminate when we drain - the workload is gone.
@@ -222,3 +299,239 @@ async fn handle(
     });
     Ok(())
 }
+
+// handle_uds processes a SOCKS5 connection accepted over a unix domain socket, from the source
+// conveyed when the connection was handed off. Only CONNECT is supported: without an address for
+// the client, we cannot tell which datagrams belong to a UDP association.
+#[cfg(unix)]
+pub(super) async fn handle_uds(
+    mut oc: OutboundConnection,
+    mut stream: tokio::net::UnixStream,
+    source: SocketAddr,
+) -> Result<(), anyhow::Error> {
+    let (command, host) = negotiate(&mut stream).await?;
+    if command != CMD_CONNECT {
+        stream.write_all(&COMMAND_NOT_SUPPORTED_REPLY).await?;
+        return Err(anyhow::anyhow!("unsupported command over unix socket"));
+    }
+    stream.write_all(&CONNECT_REPLY).await?;
+
+    info!("accepted connection from {source} to {host}");
+    oc.proxy_to_cancellable(stream, source, host, true, None)
+        .await;
+    Ok(())
+}
+
+// udp_associate_bind binds the UDP relay socket the client will send datagrams to. We bind to the same
+// IP the client reached us on for the TCP control connection, so it is reachable by the client.
+fn udp_associate_bind(pi: &ProxyInputs, control: &TcpStream) -> std::io::Result<UdpSocket> {
+    let local = control.local_addr()?;
+    pi.socket_factory.udp_bind(SocketAddr::new(local.ip(), 0))
+}
+
+// udp_associate relays datagrams between the client and its destinations, for as long as the
+// control connection is open. Each destination is proxied as its own flow, as the outbound UDP
+// listener does, so datagrams are tunneled and subject to policy like SOCKS5 connections are.
+async fn udp_associate(
+    oc: OutboundConnection,
+    mut control: TcpStream,
+    relay: UdpSocket,
+    expected_client: SocketAddr,
+) -> Result<(), anyhow::Error> {
+    let client_ip = socket::to_canonical(control.peer_addr()?).ip();
+    let relay = Arc::new(relay);
+    let mut flows: HashMap<SocketAddr, mpsc::Sender<Bytes>> = HashMap::new();
+    // Flows are aborted when the association ends.
+    let mut tasks = JoinSet::new();
+    let mut client_buf = vec![0u8; MAX_UDP_DATAGRAM];
+    let mut control_buf = [0u8; 1];
+    loop {
+        tokio::select! {
+            // The association terminates when the control connection does.
+            res = control.read(&mut control_buf) => {
+                match res {
+                    Ok(0) | Err(_) => return Ok(()),
+                    // Clients should not send anything else on the control connection; ignore it.
+                    Ok(_) => {}
+                }
+            }
+            res = relay.recv_from(&mut client_buf) => {
+                let (n, from) = res?;
+                let from = socket::to_canonical(from);
+                // Only accept datagrams from the client that setup the association. If the client told
+                // us its source port, enforce that as well.
+                if from.ip() != client_ip
+                    || (expected_client.port() != 0 && from.port() != expected_client.port())
+                {
+                    debug!(%from, "dropping datagram from unexpected source");
+                    continue;
+                }
+                let (dst, payload) = match parse_udp_header(&client_buf[..n]) {
+                    Ok((dst, payload)) => (socket::to_canonical(dst), Bytes::copy_from_slice(payload)),
+                    Err(e) => {
+                        debug!(%from, "dropping invalid datagram: {e}");
+                        continue;
+                    }
+                };
+                let payload = match flows.get(&dst).map(|tx| tx.try_send(payload)) {
+                    None => payload,
+                    Some(Ok(())) => continue,
+                    Some(Err(TrySendError::Full(_))) => {
+                        debug!(%dst, "udp flow is backed up, dropping datagram");
+                        continue;
+                    }
+                    // The flow has ended; start a new one
+                    Some(Err(TrySendError::Closed(payload))) => payload,
+                };
+                let (tx, rx) = mpsc::channel(FLOW_BUFFER);
+                tx.try_send(payload).expect("new channel has capacity");
+                flows.insert(dst, tx);
+
+                let flow = Socks5Flow {
+                    datagrams: rx,
+                    relay: relay.clone(),
+                    client: from,
+                    destination: dst,
+                };
+                let mut flow_oc = OutboundConnection {
+                    pi: oc.pi.clone(),
+                    id: TraceParent::new(),
+                    connection_id: ConnectionId::new(),
+                    pool: oc.pool.clone(),
+                };
+                let span = info_span!("socks5 udp", id=%flow_oc.id, connection_id=%flow_oc.connection_id);
+                telemetry::set_trace_context(&span, &flow_oc.id, None);
+                tasks.spawn(async move { flow_oc.proxy_udp(flow, from, dst, true).await }.instrument(span));
+            }
+            Some(_) = tasks.join_next() => {
+                flows.retain(|_, tx| !tx.is_closed());
+            }
+        }
+    }
+}
+
+// Socks5Flow is the client side of the flow to one destination of a UDP association. Replies are
+// sent to the client with a header identifying the destination they came from.
+struct Socks5Flow {
+    datagrams: mpsc::Receiver<Bytes>,
+    relay: Arc<UdpSocket>,
+    client: SocketAddr,
+    destination: SocketAddr,
+}
+
+impl DatagramSocket for Socks5Flow {
+    async fn recv_datagram(&mut self) -> io::Result<Bytes> {
+        self.datagrams
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::BrokenPipe.into())
+    }
+
+    async fn send_datagram(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut datagram = Vec::with_capacity(payload.len() + 22);
+        datagram.extend_from_slice(&[0x00, 0x00, 0x00]); // rsv, frag
+        write_address(self.destination, &mut datagram);
+        datagram.extend_from_slice(payload);
+        self.relay.send_to(&datagram, self.client).await.map(|_| ())
+    }
+}
+
+// write_address encodes a SOCKS5 address (ATYP, DST.ADDR, DST.PORT)
+fn write_address(addr: SocketAddr, buf: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// parse_udp_header decodes a SOCKS5 UDP request header, returning the destination and the payload.
+// Fragmentation is not supported; as allowed by the spec, fragmented datagrams are rejected.
+fn parse_udp_header(datagram: &[u8]) -> Result<(SocketAddr, &[u8]), anyhow::Error> {
+    let [_, _, frag, atyp, rest @ ..] = datagram else {
+        anyhow::bail!("datagram too short");
+    };
+    if *frag != 0 {
+        anyhow::bail!("fragmented datagrams are not supported");
+    }
+    let (ip, rest) = match *atyp {
+        ATYP_IPV4 if rest.len() >= 4 => {
+            let octets: [u8; 4] = rest[..4].try_into().expect("length checked");
+            (IpAddr::V4(octets.into()), &rest[4..])
+        }
+        ATYP_IPV6 if rest.len() >= 16 => {
+            let octets: [u8; 16] = rest[..16].try_into().expect("length checked");
+            (IpAddr::V6(octets.into()), &rest[16..])
+        }
+        ATYP_IPV4 | ATYP_IPV6 => anyhow::bail!("datagram too short"),
+        _ => anyhow::bail!("unsupported host"),
+    };
+    if rest.len() < 2 {
+        anyhow::bail!("datagram too short");
+    }
+    let port = BigEndian::read_u16(&rest[..2]);
+    Ok((SocketAddr::new(ip, port), &rest[2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn negotiate_connect() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        // Offer no auth and username/password; connect to 10.0.0.1:80
+        client.write_all(&[0x05, 0x02, 0x02, 0x00]).await.unwrap();
+        client
+            .write_all(&[0x05, CMD_CONNECT, 0x00, ATYP_IPV4, 10, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+        let (command, host) = negotiate(&mut server).await.unwrap();
+        assert_eq!(command, CMD_CONNECT);
+        assert_eq!(host, "10.0.0.1:80".parse::<SocketAddr>().unwrap());
+        // No auth is selected
+        let mut selected = [0u8; 2];
+        client.read_exact(&mut selected).await.unwrap();
+        assert_eq!(selected, [0x05, 0x00]);
+
+        // Clients must allow no auth
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+        assert!(negotiate(&mut server).await.is_err());
+    }
+
+    #[test]
+    fn udp_header_roundtrip() {
+        for addr in ["127.0.0.1:53", "[::1]:8080"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let mut datagram = vec![0x00, 0x00, 0x00];
+            write_address(addr, &mut datagram);
+            datagram.extend_from_slice(b"hello");
+            let (dst, payload) = parse_udp_header(&datagram).unwrap();
+            assert_eq!(dst, addr);
+            assert_eq!(payload, b"hello");
+        }
+    }
+
+    #[test]
+    fn udp_header_invalid() {
+        // too short
+        assert!(parse_udp_header(&[0x00, 0x00, 0x00]).is_err());
+        assert!(parse_udp_header(&[0x00, 0x00, 0x00, ATYP_IPV4, 127, 0]).is_err());
+        // fragmented
+        assert!(parse_udp_header(&[0x00, 0x00, 0x01, ATYP_IPV4, 127, 0, 0, 1, 0, 53]).is_err());
+        // domain names are not supported
+        assert!(parse_udp_header(&[0x00, 0x00, 0x00, ATYP_DOMAIN, 1, b'a', 0, 53]).is_err());
+        // empty payload is fine
+        let (dst, payload) =
+            parse_udp_header(&[0x00, 0x00, 0x00, ATYP_IPV4, 127, 0, 0, 1, 0, 53]).unwrap();
+        assert_eq!(dst, "127.0.0.1:53".parse::<SocketAddr>().unwrap());
+        assert!(payload.is_empty());
+    }
+}
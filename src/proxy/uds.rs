@@ -0,0 +1,272 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use drain::Watch;
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::proxy::outbound::OutboundConnection;
+use crate::proxy::{socks5, util, ConnectionId, Error, ProxyInputs, TraceParent};
+use crate::{proxy, socket, telemetry};
+
+// How long a client has to send the PROXY protocol header after connecting.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+pub(super) enum UdsKind {
+    Outbound,
+    Socks5,
+}
+
+impl UdsKind {
+    fn component(&self) -> &'static str {
+        match self {
+            UdsKind::Outbound => "outbound uds",
+            UdsKind::Socks5 => "socks5 uds",
+        }
+    }
+}
+
+/// UdsListener accepts connections handed off to ztunnel over a unix domain socket. The socket
+/// carries no addresses, so each connection must start with a PROXY protocol header conveying its
+/// original source and destination. Connections are otherwise served like those accepted over TCP.
+pub(super) struct UdsListener {
+    pi: ProxyInputs,
+    drain: Watch,
+    listener: UnixListener,
+    path: PathBuf,
+    kind: UdsKind,
+}
+
+impl UdsListener {
+    pub(super) fn new(
+        pi: ProxyInputs,
+        drain: Watch,
+        path: &Path,
+        kind: UdsKind,
+    ) -> Result<UdsListener, Error> {
+        let listener = pi
+            .socket_factory
+            .uds_bind(path)
+            .map_err(|e| Error::BindUds(path.to_owned(), e))?;
+        // The socket is only accessible to ztunnel's own user, unless other users are allowed to
+        // hand off connections. Either way, peers are authorized by their credentials on accept.
+        if !pi.cfg.uds_allowed_uids.is_empty() {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))
+                .map_err(|e| Error::BindUds(path.to_owned(), e))?;
+        }
+        info!(
+            path=%path.display(),
+            component=kind.component(),
+            "listener established",
+        );
+        Ok(UdsListener {
+            pi,
+            drain,
+            listener,
+            path: path.to_owned(),
+            kind,
+        })
+    }
+
+    // from_config creates the unix domain socket listeners that are configured.
+    pub(super) fn from_config(pi: &ProxyInputs, drain: &Watch) -> Result<Vec<UdsListener>, Error> {
+        let mut listeners = Vec::new();
+        if let Some(path) = &pi.cfg.outbound_uds_path {
+            listeners.push(Self::new(
+                pi.clone(),
+                drain.clone(),
+                path,
+                UdsKind::Outbound,
+            )?);
+        }
+        if let Some(path) = &pi.cfg.socks5_uds_path {
+            listeners.push(Self::new(pi.clone(), drain.clone(), path, UdsKind::Socks5)?);
+        }
+        Ok(listeners)
+    }
+
+    pub(super) async fn run(self) {
+        let UdsListener {
+            pi,
+            drain: proxy_drain,
+            listener,
+            path,
+            kind,
+        } = self;
+        let (sub_drain_signal, sub_drain) = drain::channel();
+        // Safety: geteuid is always successful
+        let mut allowed_uids = vec![unsafe { libc::geteuid() }];
+        allowed_uids.extend(&pi.cfg.uds_allowed_uids);
+        let allowed_uids: Arc<[u32]> = allowed_uids.into();
+        let pi = Arc::new(pi);
+
+        let pool = proxy::pool::WorkloadHBONEPool::new(
+            pi.cfg.clone(),
+            pi.socket_factory.clone(),
+            pi.cert_manager.clone(),
+            pi.metrics.clone(),
+        );
+        let accept = async move {
+            loop {
+                proxy::throttle_accept(&pi).await;
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        if util::is_runtime_shutdown(&e) {
+                            return;
+                        }
+                        error!("failed to accept unix socket connection: {}", e);
+                        continue;
+                    }
+                };
+                let Ok(permit) = proxy::admit_connection(&pi, &stream) else {
+                    continue;
+                };
+                let oc = OutboundConnection {
+                    pi: pi.clone(),
+                    id: TraceParent::new(),
+                    connection_id: ConnectionId::new(),
+                    pool: pool.clone(),
+                };
+                let conn_drain = sub_drain.clone();
+                let allowed_uids = allowed_uids.clone();
+                let span = info_span!("uds", id=%oc.id, connection_id=%oc.connection_id);
+                telemetry::set_trace_context(&span, &oc.id, None);
+                let serve = async move {
+                    let _permit = permit;
+                    tokio::select! {
+                        _ = conn_drain.signaled() => {
+                            debug!("{} drain signaled", kind.component());
+                        }
+                        res = serve_connection(oc, stream, kind, &allowed_uids) => {
+                            if let Err(e) = res {
+                                warn!("{} connection failed: {}", kind.component(), e);
+                            }
+                        }
+                    }
+                }
+                .instrument(span);
+                tokio::spawn(serve);
+            }
+        }
+        .in_current_span();
+
+        tokio::select! {
+            res = accept => { res }
+            _ = proxy_drain.signaled() => {
+                debug!("{} drained, dropping any connections", kind.component());
+                sub_drain_signal.drain().await;
+                info!("{} drained", kind.component());
+            }
+        }
+        // Don't leave the socket behind for clients to connect to
+        if let Err(e) = std::fs::remove_file(&path) {
+            debug!(path=%path.display(), "failed to remove unix socket: {}", e);
+        }
+    }
+}
+
+async fn serve_connection(
+    mut oc: OutboundConnection,
+    mut stream: UnixStream,
+    kind: UdsKind,
+    allowed_uids: &[u32],
+) -> Result<(), anyhow::Error> {
+    authorize_peer(&stream, allowed_uids)?;
+    let (src, dst) = read_addresses(&mut stream).await?;
+    match kind {
+        UdsKind::Outbound => {
+            oc.proxy_to_cancellable(stream, src, dst, false, None).await;
+            Ok(())
+        }
+        UdsKind::Socks5 => socks5::handle_uds(oc, stream, src).await,
+    }
+}
+
+// authorize_peer checks that the process that handed off the connection is allowed to. The PROXY
+// header it sends is trusted to convey the connection's source, so an arbitrary local process must
+// not be able to send one.
+fn authorize_peer(stream: &UnixStream, allowed_uids: &[u32]) -> Result<(), anyhow::Error> {
+    let cred = stream
+        .peer_cred()
+        .map_err(|e| anyhow::anyhow!("failed to read peer credentials: {e}"))?;
+    debug!(
+        uid = cred.uid(),
+        gid = cred.gid(),
+        pid = ?cred.pid(),
+        "accepted unix socket connection"
+    );
+    if !allowed_uids.contains(&cred.uid()) {
+        anyhow::bail!(
+            "peer uid {} is not allowed to hand off connections",
+            cred.uid()
+        );
+    }
+    Ok(())
+}
+
+// read_addresses reads the source and destination of a connection from its PROXY protocol header.
+async fn read_addresses(
+    stream: &mut UnixStream,
+) -> Result<(SocketAddr, SocketAddr), anyhow::Error> {
+    let header = tokio::time::timeout(PROXY_HEADER_TIMEOUT, proxy::read_proxy_protocol(stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out reading PROXY protocol header"))??;
+    let (src, dst) =
+        header.ok_or_else(|| anyhow::anyhow!("PROXY protocol header must convey addresses"))?;
+    Ok((socket::to_canonical(src), socket::to_canonical(dst)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn read_header_addresses() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let src: SocketAddr = "[::ffff:10.0.0.1]:1234".parse().unwrap();
+        let dst: SocketAddr = "[::ffff:10.0.0.2]:80".parse().unwrap();
+        client
+            .write_all(b"PROXY TCP6 ::ffff:10.0.0.1 ::ffff:10.0.0.2 1234 80\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_addresses(&mut server).await.unwrap(),
+            (socket::to_canonical(src), socket::to_canonical(dst))
+        );
+
+        // Addresses are required
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(b"PROXY UNKNOWN\r\n").await.unwrap();
+        assert!(read_addresses(&mut server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn authorize_peer_uid() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        let uid = unsafe { libc::geteuid() };
+        authorize_peer(&server, &[uid]).unwrap();
+        assert!(authorize_peer(&server, &[uid.wrapping_add(1)]).is_err());
+        assert!(authorize_peer(&server, &[]).is_err());
+    }
+}
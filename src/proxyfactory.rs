@@ -113,7 +113,7 @@ impl ProxyFactory {
                     self.config.dns_proxy_addr,
                     self.config.network.clone(),
                     self.state.clone(),
-                    dns::forwarder_for_mode(self.config.proxy_mode)?,
+                    dns::forwarder_for_mode(&self.config)?,
                     self.dns_metrics.clone().unwrap(),
                     drain,
                     socket_factory.as_ref(),
@@ -16,19 +16,20 @@ use ipnet::IpNet;
 
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use tracing::{instrument, trace};
 use xds::istio::security::string_match::MatchType;
 use xds::istio::security::Address as XdsAddress;
 use xds::istio::security::Authorization as XdsRbac;
 use xds::istio::security::Match;
+use xds::istio::security::PortRange as XdsPortRange;
 use xds::istio::security::StringMatch as XdsStringMatch;
 
 use crate::identity::Identity;
 
 use crate::state::workload::{byte_to_ip, WorkloadError};
 use crate::strng::Strng;
-use crate::{strng, xds};
+use crate::{socket, strng, xds};
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
@@ -38,6 +39,9 @@ pub struct Authorization {
     pub scope: RbacScope,
     pub action: RbacAction,
     pub rules: Vec<Vec<Vec<RbacMatch>>>,
+    /// A dry-run policy is evaluated, but its result is only reported and never enforced.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd, serde::Serialize)]
@@ -80,13 +84,22 @@ impl Authorization {
         res.into()
     }
 
-    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
     pub fn matches(&self, conn: &Connection) -> bool {
-        let id = conn
-            .src_identity
-            .as_ref()
-            .map(|i| i.to_strng())
-            .unwrap_or_default();
+        self.matching_rule(conn, &[]).is_some()
+    }
+
+    /// matching_rule returns the index of the first rule matching the connection, if any.
+    /// src_aliases are other identities the source is known by, such as its identity in an aliased
+    /// trust domain; principals matching any of them match the source.
+    #[instrument(level = "trace", skip_all, fields(policy=self.to_key().as_str()))]
+    pub fn matching_rule(&self, conn: &Connection, src_aliases: &[Identity]) -> Option<usize> {
+        let ids: Vec<Strng> = match &conn.src_identity {
+            Some(i) => std::iter::once(i)
+                .chain(src_aliases)
+                .map(Identity::to_strng)
+                .collect(),
+            None => vec![Strng::default()],
+        };
         let ns = conn
             .src_identity
             .as_ref()
@@ -94,13 +107,17 @@ impl Authorization {
                 Identity::Spiffe { namespace, .. } => namespace.to_owned(), // may be more clear if we use to_owned() to denote change from borrowed to owned
             })
             .unwrap_or_default();
+        // Dual-stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses, which IPv4 blocks would
+        // not otherwise contain.
+        let src_ip = socket::to_canonical(conn.src).ip();
+        let dst_ip = socket::to_canonical(conn.dst).ip();
         if self.rules.is_empty() {
             trace!(matches = false, "empty rules");
-            return false;
+            return None;
         }
         // An Authorization Policy can have multiple rules
         // If ANY rule matches it's a match...
-        for rule in self.rules.iter() {
+        for (index, rule) in self.rules.iter().enumerate() {
             // Rule typically has 1-3 clauses (from,to,when)
             // If ALL clauses match, it is a match...
             let mut rule_match = true;
@@ -119,25 +136,20 @@ impl Authorization {
                         "destination_ip",
                         &mg.destination_ips,
                         &mg.not_destination_ips,
-                        |i| i.contains(&conn.dst.ip()),
+                        |i| i.contains(&dst_ip),
                     );
                     m &= Self::matches_internal(
                         "source_ips",
                         &mg.source_ips,
                         &mg.not_source_ips,
-                        |i| i.contains(&conn.src.ip()),
-                    );
-                    m &= Self::matches_internal(
-                        "destination_ports",
-                        &mg.destination_ports,
-                        &mg.not_destination_ports,
-                        |p| *p == conn.dst.port(),
+                        |i| i.contains(&src_ip),
                     );
+                    m &= Self::matches_ports(mg, conn.dst.port());
                     m &= Self::matches_internal(
                         "principals",
                         &mg.principals,
                         &mg.not_principals,
-                        |p| p.matches_principal(&id),
+                        |p| ids.iter().any(|id| p.matches_principal(id)),
                     );
                     m &= Self::matches_internal(
                         "namespaces",
@@ -166,10 +178,34 @@ impl Authorization {
             }
             trace!(matches = rule_match, "rule");
             if rule_match {
-                return true;
+                return Some(index);
             }
         }
-        false
+        None
+    }
+
+    // matches_ports is like matches_internal, but a port may be matched exactly or by a range, and
+    // either suffices.
+    #[instrument(
+        name = "match",
+        level = "trace",
+        skip_all,
+        fields(desc = "destination_ports")
+    )]
+    fn matches_ports(mg: &RbacMatch, port: u16) -> bool {
+        let pm = if mg.destination_ports.is_empty() && mg.destination_port_ranges.is_empty() {
+            true
+        } else {
+            mg.destination_ports.contains(&port)
+                || mg.destination_port_ranges.iter().any(|r| r.contains(port))
+        };
+        let nm = !mg.not_destination_ports.contains(&port)
+            && !mg
+                .not_destination_port_ranges
+                .iter()
+                .any(|r| r.contains(port));
+        trace!(positive = pm, negative = nm, "ports");
+        pm && nm
     }
 
     #[instrument(name= "match", level = "trace", skip_all, fields(%desc))]
@@ -222,6 +258,10 @@ pub struct RbacMatch {
     pub destination_ports: Vec<u16>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub not_destination_ports: Vec<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub destination_port_ranges: Vec<PortRange>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub not_destination_port_ranges: Vec<PortRange>,
 }
 
 impl RbacMatch {
@@ -236,6 +276,21 @@ impl RbacMatch {
             && self.not_destination_ips.is_empty()
             && self.destination_ports.is_empty()
             && self.not_destination_ports.is_empty()
+            && self.destination_port_ranges.is_empty()
+            && self.not_destination_port_ranges.is_empty()
+    }
+}
+
+/// PortRange matches the ports from start to end, inclusive.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        self.start <= port && port <= self.end
     }
 }
 
@@ -325,6 +380,7 @@ impl TryFrom<XdsRbac> for Authorization {
             scope: RbacScope::from(xds::istio::security::Scope::try_from(resource.scope)?),
             action: RbacAction::from(xds::istio::security::Action::try_from(resource.action)?),
             rules,
+            dry_run: resource.dry_run,
         })
     }
 }
@@ -346,47 +402,67 @@ impl TryFrom<&Match> for RbacMatch {
                 .iter()
                 .filter_map(From::from)
                 .collect(),
-            source_ips: resource
-                .source_ips
-                .iter()
-                .filter_map(|a| a.try_into().ok())
-                .collect(),
-            not_source_ips: resource
-                .not_source_ips
-                .iter()
-                .filter_map(|a| a.try_into().ok())
-                .collect(),
-            destination_ips: resource
-                .destination_ips
-                .iter()
-                .filter_map(|a| a.try_into().ok())
-                .collect(),
-            not_destination_ips: resource
-                .not_destination_ips
+            source_ips: ip_blocks(&resource.source_ips)?,
+            not_source_ips: ip_blocks(&resource.not_source_ips)?,
+            destination_ips: ip_blocks(&resource.destination_ips)?,
+            not_destination_ips: ip_blocks(&resource.not_destination_ips)?,
+            destination_ports: ports(&resource.destination_ports)?,
+            not_destination_ports: ports(&resource.not_destination_ports)?,
+            destination_port_ranges: resource
+                .destination_port_ranges
                 .iter()
-                .filter_map(|a| a.try_into().ok())
-                .collect(),
-            destination_ports: resource
-                .destination_ports
-                .iter()
-                .map(|p| *p as u16)
-                .collect(),
-            not_destination_ports: resource
-                .not_destination_ports
+                .map(PortRange::try_from)
+                .collect::<Result<_, _>>()?,
+            not_destination_port_ranges: resource
+                .not_destination_port_ranges
                 .iter()
-                .map(|p| *p as u16)
-                .collect(),
+                .map(PortRange::try_from)
+                .collect::<Result<_, _>>()?,
         })
     }
 }
 
+// ports converts the ports of a match, rejecting any that are out of range rather than matching a
+// truncated port.
+fn ports(ports: &[u32]) -> Result<Vec<u16>, WorkloadError> {
+    ports
+        .iter()
+        .map(|p| u16::try_from(*p).map_err(|_| WorkloadError::PortParse(*p)))
+        .collect()
+}
+
+impl TryFrom<&XdsPortRange> for PortRange {
+    type Error = WorkloadError;
+    fn try_from(resource: &XdsPortRange) -> Result<Self, Self::Error> {
+        let invalid = || WorkloadError::PortRangeParse(resource.start, resource.end);
+        let start = u16::try_from(resource.start).map_err(|_| invalid())?;
+        let end = u16::try_from(resource.end).map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(PortRange { start, end })
+    }
+}
+
+// ip_blocks converts the CIDR blocks of a match. An invalid block fails the whole policy: skipping
+// it could leave the match empty, which would match every connection.
+fn ip_blocks(addresses: &[XdsAddress]) -> Result<Vec<IpNet>, WorkloadError> {
+    addresses.iter().map(IpNet::try_from).collect()
+}
+
 impl TryFrom<&XdsAddress> for IpNet {
     type Error = WorkloadError;
     fn try_from(resource: &XdsAddress) -> Result<Self, Self::Error> {
-        Ok(IpNet::new(
-            byte_to_ip(&resource.address)?,
-            resource.length as u8,
-        )?)
+        let ip = byte_to_ip(&resource.address)?;
+        let len = u8::try_from(resource.length).map_err(|_| ipnet::PrefixLenError)?;
+        // An IPv4-mapped IPv6 block is treated as the IPv4 block it maps, to match the canonical
+        // IPv4 addresses it is compared against.
+        if let IpAddr::V6(v6) = ip {
+            if let (Some(v4), Some(len)) = (v6.to_ipv4_mapped(), len.checked_sub(96)) {
+                return Ok(IpNet::new(IpAddr::V4(v4), len)?);
+            }
+        }
+        Ok(IpNet::new(ip, len)?)
     }
 }
 
@@ -433,6 +509,7 @@ mod tests {
             scope: RbacScope::Global,
             action: RbacAction::Allow,
             rules,
+            dry_run: false,
         }
     }
 
@@ -485,6 +562,21 @@ mod tests {
         assert!(!allow_policy("empty", vec![]).matches(&plaintext_conn()));
     }
 
+    #[test]
+    fn rbac_matching_rule() {
+        let port = |port| {
+            vec![vec![RbacMatch {
+                destination_ports: vec![port],
+                ..Default::default()
+            }]]
+        };
+        let pol = allow_policy("rules", vec![port(80), port(8080), port(8080)]);
+        // The first matching rule is reported
+        assert_eq!(pol.matching_rule(&plaintext_conn()), Some(1));
+        let pol = allow_policy("rules", vec![port(80)]);
+        assert_eq!(pol.matching_rule(&plaintext_conn()), None);
+    }
+
     #[test]
     fn rbac_nesting() {
         let pol = allow_policy(
@@ -651,6 +743,49 @@ mod tests {
         &tls_conn() => true,
         &tls_conn_alt() => true);
 
+    rbac_test!(mapped_source_ip, source_ips, vec![IpNet::new("127.0.0.1".parse().unwrap(), 32).unwrap()],
+        &Connection {
+            src: "[::ffff:127.0.0.1]:1234".parse().unwrap(),
+            ..plaintext_conn()
+        } => true,
+        &Connection {
+            src: "[::1]:1234".parse().unwrap(),
+            ..plaintext_conn()
+        } => false);
+
+    #[test]
+    fn rbac_ip_blocks() {
+        let block = |address: &str, length: u32| XdsAddress {
+            address: match address.parse::<IpAddr>().unwrap() {
+                IpAddr::V4(ip) => ip.octets().to_vec().into(),
+                IpAddr::V6(ip) => ip.octets().to_vec().into(),
+            },
+            length,
+        };
+        assert_eq!(
+            IpNet::try_from(&block("10.0.0.0", 8)).unwrap(),
+            "10.0.0.0/8".parse::<IpNet>().unwrap()
+        );
+        assert_eq!(
+            IpNet::try_from(&block("2001:db8::", 32)).unwrap(),
+            "2001:db8::/32".parse::<IpNet>().unwrap()
+        );
+        // IPv4-mapped blocks are converted to IPv4
+        assert_eq!(
+            IpNet::try_from(&block("::ffff:10.0.0.0", 104)).unwrap(),
+            "10.0.0.0/8".parse::<IpNet>().unwrap()
+        );
+        assert!(IpNet::try_from(&block("10.0.0.0", 33)).is_err());
+        assert!(IpNet::try_from(&block("10.0.0.0", 264)).is_err());
+
+        // A policy with an invalid block is rejected, rather than matching more than intended
+        let invalid = Match {
+            source_ips: vec![block("10.0.0.0", 8), block("10.0.0.0", 33)],
+            ..Default::default()
+        };
+        assert!(RbacMatch::try_from(&invalid).is_err());
+    }
+
     rbac_test!(destination_ports, vec![8080],
         &plaintext_conn() => true,
         &tls_conn() => true,
@@ -660,6 +795,88 @@ mod tests {
         &tls_conn() => false,
         &tls_conn_alt() => true);
 
+    rbac_test!(destination_port_ranges, vec![PortRange { start: 8000, end: 8080 }],
+        &plaintext_conn() => true,
+        &tls_conn() => true,
+        &tls_conn_alt() => false);
+    rbac_test!(not_destination_port_ranges, vec![PortRange { start: 9000, end: 9999 }],
+        &plaintext_conn() => true,
+        &tls_conn() => true,
+        &tls_conn_alt() => false);
+
+    #[test]
+    fn rbac_principal_aliases() {
+        let m = |principals, not_principals| RbacMatch {
+            principals,
+            not_principals,
+            ..Default::default()
+        };
+        let old_principal = || vec![StringMatch::Exact("old-td/ns/namespace/sa/account".into())];
+        let conn = tls_conn();
+        let alias = Identity::Spiffe {
+            trust_domain: "old-td".into(),
+            namespace: "namespace".into(),
+            service_account: "account".into(),
+        };
+
+        let allow = allow_policy("allow", vec![vec![vec![m(old_principal(), vec![])]]]);
+        assert!(!allow.matches(&conn));
+        assert_eq!(allow.matching_rule(&conn, &[alias.clone()]), Some(0));
+
+        let not = allow_policy("not", vec![vec![vec![m(vec![], old_principal())]]]);
+        assert!(not.matches(&conn));
+        assert_eq!(not.matching_rule(&conn, &[alias.clone()]), None);
+
+        // Aliases never apply to connections without an identity
+        assert_eq!(allow.matching_rule(&plaintext_conn(), &[alias]), None);
+    }
+
+    #[test]
+    fn rbac_port_alternatives() {
+        // Exact ports and ranges are alternatives
+        let pol = allow_policy(
+            "ports",
+            vec![vec![vec![RbacMatch {
+                destination_ports: vec![8080],
+                destination_port_ranges: vec![PortRange {
+                    start: 9000,
+                    end: 9099,
+                }],
+                not_destination_port_ranges: vec![PortRange {
+                    start: 9090,
+                    end: 9090,
+                }],
+                ..Default::default()
+            }]]],
+        );
+        assert!(pol.matches(&plaintext_conn()));
+        let to = |dst: &str| Connection {
+            dst: dst.parse().unwrap(),
+            ..plaintext_conn()
+        };
+        assert!(pol.matches(&to("127.0.0.2:9000")));
+        assert!(!pol.matches(&to("127.0.0.2:9090")));
+        assert!(!pol.matches(&to("127.0.0.2:9100")));
+    }
+
+    #[test]
+    fn rbac_ports() {
+        let range = |start, end| XdsPortRange { start, end };
+        assert_eq!(
+            PortRange::try_from(&range(80, 80)).unwrap(),
+            PortRange { start: 80, end: 80 }
+        );
+        assert!(PortRange::try_from(&range(443, 80)).is_err());
+        assert!(PortRange::try_from(&range(80, 65536)).is_err());
+
+        // Out of range ports are rejected, rather than truncated to a different port
+        let invalid = Match {
+            destination_ports: vec![65536 + 80],
+            ..Default::default()
+        };
+        assert!(RbacMatch::try_from(&invalid).is_err());
+    }
+
     #[test_case(StringMatch::Exact("foo".into()), "foo", true; "exact match")]
     #[test_case(StringMatch::Exact("foo".into()), "not", false; "exact mismatch")]
     #[test_case(StringMatch::Exact("foo".into()), "", false; "exact empty mismatch")]
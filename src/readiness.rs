@@ -16,7 +16,9 @@ use crate::telemetry;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use tracing::info;
+mod liveness;
 mod server;
+pub use liveness::*;
 pub use server::*;
 
 /// Ready tracks whether the process is ready.
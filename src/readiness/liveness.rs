@@ -0,0 +1,173 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+/// Liveness tracks heartbeats from long-running internal loops. The process is live as long as
+/// every registered heartbeat has beaten within its threshold.
+#[derive(Clone, Debug, Default)]
+pub struct Liveness(Arc<Mutex<HashMap<String, Heartbeat>>>);
+
+impl Liveness {
+    pub fn new() -> Liveness {
+        Liveness(Default::default())
+    }
+
+    /// register_heartbeat adds a check that fails if the returned Heartbeat is not beaten at
+    /// least once every `threshold`.
+    pub fn register_heartbeat(&self, name: &str, threshold: Duration) -> Heartbeat {
+        let hb = Heartbeat {
+            start: Instant::now(),
+            last: Default::default(),
+            threshold,
+        };
+        self.0.lock().unwrap().insert(name.to_string(), hb.clone());
+        hb
+    }
+
+    /// stalled returns the checks that have not made progress within their threshold, along with
+    /// the time since they last did.
+    pub fn stalled(&self) -> Vec<(String, Duration)> {
+        let mut stalled: Vec<_> = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, hb)| {
+                let since = hb.since_last();
+                (since > hb.threshold).then(|| (name.clone(), since))
+            })
+            .collect();
+        stalled.sort();
+        stalled
+    }
+}
+
+/// Heartbeat is beaten by a loop each time it makes progress.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    start: Instant,
+    // Time of the last beat, in milliseconds since start.
+    last: Arc<AtomicU64>,
+    threshold: Duration,
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.last
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// The interval at which loops without natural progress points should beat, leaving
+    /// plenty of headroom before the threshold.
+    pub fn interval(&self) -> Duration {
+        self.threshold / 4
+    }
+
+    fn since_last(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+
+    /// beat_forever beats at the heartbeat interval. Run on an async runtime, it detects the
+    /// runtime's workers being wedged, e.g. by a blocking call or a deadlock.
+    pub async fn beat_forever(self) {
+        let mut interval = tokio::time::interval(self.interval());
+        loop {
+            interval.tick().await;
+            self.beat();
+        }
+    }
+
+    /// beat_while beats at the heartbeat interval until `fut` completes. It covers waits that are
+    /// expected to take arbitrarily long, such as reconnect backoff.
+    pub async fn beat_while<F: Future>(&self, fut: F) -> F::Output {
+        tokio::pin!(fut);
+        let mut interval = tokio::time::interval(self.interval());
+        loop {
+            tokio::select! {
+                res = &mut fut => return res,
+                _ = interval.tick() => self.beat(),
+            }
+        }
+    }
+
+    /// spawn_probe runs `probe` on a dedicated thread at the heartbeat interval, beating after
+    /// each run. A probe that never returns, such as one blocked on a deadlocked lock, stalls the
+    /// heartbeat.
+    pub fn spawn_probe<F>(self, name: &str, probe: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        let res = thread::Builder::new()
+            .name(format!("liveness-{name}"))
+            .spawn(move || loop {
+                probe();
+                self.beat();
+                thread::sleep(self.interval());
+            });
+        if let Err(e) = res {
+            warn!("failed to start liveness probe {name}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stalled_heartbeats() {
+        let live = Liveness::new();
+        let fast = live.register_heartbeat("fast", Duration::from_secs(60));
+        let slow = live.register_heartbeat("slow", Duration::from_millis(10));
+        fast.beat();
+        slow.beat();
+        assert!(live.stalled().is_empty());
+
+        thread::sleep(Duration::from_millis(30));
+        let stalled = live.stalled();
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].0, "slow");
+        assert!(stalled[0].1 >= Duration::from_millis(30));
+
+        slow.beat();
+        assert!(live.stalled().is_empty());
+    }
+
+    #[test]
+    fn probe_beats() {
+        let live = Liveness::new();
+        let hb = live.register_heartbeat("probe", Duration::from_millis(40));
+        let lock = Arc::new(Mutex::new(()));
+        let probe_lock = lock.clone();
+        hb.spawn_probe("test", move || {
+            let _guard = probe_lock.lock();
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(live.stalled().is_empty());
+
+        // Holding the lock wedges the probe, which stalls the heartbeat.
+        let _guard = lock.lock().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(live.stalled().len(), 1);
+    }
+}
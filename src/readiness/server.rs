@@ -26,7 +26,7 @@ use crate::hyper_util;
 use crate::{config, readiness};
 
 pub struct Server {
-    s: hyper_util::Server<readiness::Ready>,
+    s: hyper_util::Server<(readiness::Ready, readiness::Liveness)>,
     ready: readiness::Ready,
 }
 
@@ -35,12 +35,13 @@ impl Server {
         config: Arc<config::Config>,
         drain_rx: Watch,
         ready: readiness::Ready,
+        live: readiness::Liveness,
     ) -> anyhow::Result<Self> {
-        hyper_util::Server::<readiness::Ready>::bind(
+        hyper_util::Server::<(readiness::Ready, readiness::Liveness)>::bind(
             "readiness",
             config.readiness_addr,
             drain_rx,
-            ready.clone(),
+            (ready.clone(), live),
         )
         .await
         .map(|s| Server { s, ready })
@@ -55,9 +56,11 @@ impl Server {
     }
 
     pub fn spawn(self) {
-        self.s.spawn(|ready, req| async move {
+        self.s.spawn(|state, req| async move {
+            let (ready, live) = state.as_ref();
             match req.uri().path() {
-                "/healthz/ready" => Ok(handle_ready(&ready, req).await),
+                "/healthz/ready" => Ok(handle_ready(ready, req).await),
+                "/healthz/live" => Ok(handle_live(live, req).await),
                 _ => Ok(hyper_util::empty_response(hyper::StatusCode::NOT_FOUND)),
             }
         })
@@ -82,3 +85,25 @@ async fn handle_ready(ready: &readiness::Ready, req: Request<Incoming>) -> Respo
         _ => hyper_util::empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED),
     }
 }
+
+async fn handle_live(live: &readiness::Liveness, req: Request<Incoming>) -> Response<Full<Bytes>> {
+    match *req.method() {
+        hyper::Method::GET => {
+            let stalled = live.stalled();
+            if stalled.is_empty() {
+                return hyper_util::plaintext_response(hyper::StatusCode::OK, "live\n".into());
+            }
+            hyper_util::plaintext_response(
+                hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "not live, stalled: {}\n",
+                    stalled
+                        .into_iter()
+                        .map(|(name, since)| format!("{name} ({since:?})"))
+                        .join(", ")
+                ),
+            )
+        }
+        _ => hyper_util::empty_response(hyper::StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
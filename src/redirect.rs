@@ -0,0 +1,379 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Redirection of in-pod workloads' traffic to ztunnel with eBPF programs, rather than iptables
+//! rules installed in each pod by the CNI. See redirect/redirect.bpf.c for the programs.
+//!
+//! The programs are attached for as long as ztunnel runs: unlike iptables rules, they do not
+//! outlive it, so traffic is not redirected while ztunnel is restarting. DNS traffic is not
+//! redirected.
+//!
+//! Requires Linux 5.17 or later.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use aya::maps::{HashMap, MapData, SockHash};
+use aya::programs::sk_lookup::SkLookupLink;
+use aya::programs::{CgroupSockAddr, CgroupSockopt, Program, ProgramError, SkLookup, SockOps};
+use aya::{include_bytes_aligned, Bpf, BpfLoader};
+use nix::net::if_::InterfaceFlags;
+use tokio::net::{TcpListener, TcpSocket, UdpSocket, UnixListener};
+use tracing::{debug, info, warn};
+
+use crate::config;
+use crate::inpod::netns::InpodNetns;
+use crate::proxy::SocketFactory;
+
+static PROGRAMS: &[u8] = include_bytes_aligned!(concat!(env!("OUT_DIR"), "/redirect.bpf.o"));
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("failed to load eBPF programs: {0}")]
+    Load(#[from] aya::BpfError),
+    #[error("eBPF program error: {0}")]
+    Program(#[from] ProgramError),
+    #[error("eBPF map error: {0}")]
+    Map(#[from] aya::maps::MapError),
+    #[error("eBPF object has no {0}")]
+    Missing(&'static str),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+// Addr is `struct addr` of the programs: an IP address, IPv4 addresses being mapped to IPv6, in
+// network byte order, and a port.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Addr {
+    ip: [u32; 4],
+    port: u16,
+    pad: u16,
+}
+
+// Safety: Addr is repr(C), and has no implicit padding.
+unsafe impl aya::Pod for Addr {}
+
+impl Addr {
+    fn ip(ip: IpAddr) -> Addr {
+        let octets = match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+            IpAddr::V6(ip) => ip.octets(),
+        };
+        Addr {
+            ip: std::array::from_fn(|i| {
+                u32::from_ne_bytes(octets[i * 4..i * 4 + 4].try_into().expect("4 bytes"))
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+// EnrolledPod is `struct pod` of the programs.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct EnrolledPod {
+    ip4: u32,
+    ip6: [u32; 4],
+    has_ip4: u8,
+    has_ip6: u8,
+    pad: [u8; 2],
+}
+
+// Safety: EnrolledPod is repr(C), and has no implicit padding.
+unsafe impl aya::Pod for EnrolledPod {}
+
+/// Redirector holds the redirection programs. Those acting on outbound connections are attached to
+/// the configured cgroup, and so act in every network namespace, but only redirect the connections
+/// of enrolled pods. The inbound program is attached to each enrolled pod's network namespace.
+pub struct Redirector {
+    inner: Mutex<Inner>,
+    inbound_plaintext_addrs: Vec<SocketAddr>,
+}
+
+struct Inner {
+    bpf: Bpf,
+    pods: HashMap<MapData, u64, EnrolledPod>,
+    inbound_listeners: SockHash<MapData, Addr>,
+}
+
+impl Redirector {
+    pub fn new(cfg: &config::Config) -> Result<Redirector, Error> {
+        let outbound_port = cfg.outbound_addr.port();
+        let inbound_port = cfg.inbound_addr.port();
+        let mut bpf = BpfLoader::new()
+            .set_global("outbound_port", &outbound_port, true)
+            .set_global("inbound_port", &inbound_port, true)
+            .set_global("inpod_mark", &cfg.inpod_mark, true)
+            .load(PROGRAMS)?;
+
+        let cgroup = std::fs::File::open(&cfg.ebpf_cgroup_path)?;
+        for name in ["connect4", "connect6"] {
+            let connect: &mut CgroupSockAddr = program(&mut bpf, name)?;
+            connect.load()?;
+            connect.attach(&cgroup)?;
+        }
+        let sockops: &mut SockOps = program(&mut bpf, "sockops")?;
+        sockops.load()?;
+        sockops.attach(&cgroup)?;
+        let getsockopt: &mut CgroupSockopt = program(&mut bpf, "getsockopt")?;
+        getsockopt.load()?;
+        getsockopt.attach(&cgroup)?;
+        let inbound: &mut SkLookup = program(&mut bpf, "inbound")?;
+        inbound.load()?;
+
+        let pods = HashMap::try_from(take_map(&mut bpf, "pods")?)?;
+        let inbound_listeners = SockHash::try_from(take_map(&mut bpf, "inbound_listeners")?)?;
+        info!(
+            cgroup = %cfg.ebpf_cgroup_path.display(),
+            "redirecting in-pod traffic with eBPF"
+        );
+        Ok(Redirector {
+            inner: Mutex::new(Inner {
+                bpf,
+                pods,
+                inbound_listeners,
+            }),
+            inbound_plaintext_addrs: cfg.inbound_plaintext_addr.clone(),
+        })
+    }
+
+    /// enroll prepares to redirect the traffic of the pod in the network namespace. Redirection
+    /// starts once [PodRedirect::start] is called, and stops when the PodRedirect is dropped.
+    pub fn enroll(self: &Arc<Self>, netns: &InpodNetns) -> Result<Arc<PodRedirect>, Error> {
+        let (cookie, ips) = netns.run(|| Ok::<_, io::Error>((netns_cookie()?, pod_ips()?)))??;
+        if ips.is_empty() {
+            warn!(
+                inode = netns.workload_inode(),
+                "pod has no addresses to redirect"
+            );
+        }
+        Ok(Arc::new(PodRedirect {
+            redirector: self.clone(),
+            netns: netns.clone(),
+            cookie,
+            ips,
+            inbound_link: Default::default(),
+        }))
+    }
+}
+
+fn program<'a, T>(bpf: &'a mut Bpf, name: &'static str) -> Result<&'a mut T, Error>
+where
+    &'a mut T: TryFrom<&'a mut Program, Error = ProgramError>,
+{
+    let program = bpf.program_mut(name).ok_or(Error::Missing(name))?;
+    Ok(program.try_into()?)
+}
+
+fn take_map(bpf: &mut Bpf, name: &'static str) -> Result<aya::maps::Map, Error> {
+    bpf.take_map(name).ok_or(Error::Missing(name))
+}
+
+/// PodRedirect redirects the traffic of a pod, from when it is started until it is dropped.
+pub struct PodRedirect {
+    redirector: Arc<Redirector>,
+    netns: InpodNetns,
+    cookie: u64,
+    ips: Vec<IpAddr>,
+    // Dropping the link detaches the inbound program
+    inbound_link: Mutex<Option<SkLookupLink>>,
+}
+
+impl PodRedirect {
+    /// socket_factory wraps the pod's socket factory, so that inbound connections are steered to
+    /// the inbound plaintext listeners it binds.
+    pub fn socket_factory(
+        self: &Arc<Self>,
+        inner: Box<dyn SocketFactory + Send + Sync>,
+    ) -> Box<dyn SocketFactory + Send + Sync> {
+        Box::new(RedirectSocketFactory {
+            inner,
+            pod: self.clone(),
+        })
+    }
+
+    /// start redirects the pod's traffic. The pod's proxies should be listening by then.
+    pub fn start(&self) -> Result<(), Error> {
+        let mut pod = EnrolledPod::default();
+        for ip in &self.ips {
+            match ip {
+                IpAddr::V4(ip) if pod.has_ip4 == 0 => {
+                    pod.ip4 = u32::from_ne_bytes(ip.octets());
+                    pod.has_ip4 = 1;
+                }
+                IpAddr::V6(_) if pod.has_ip6 == 0 => {
+                    pod.ip6 = Addr::ip(*ip).ip;
+                    pod.has_ip6 = 1;
+                }
+                _ => {}
+            }
+        }
+
+        let mut inner = self.redirector.inner.lock().expect("mutex");
+        inner.pods.insert(self.cookie, pod, 0)?;
+        let inbound: &mut SkLookup = program(&mut inner.bpf, "inbound")?;
+        let link = inbound.attach(self.netns.workload_netns())?;
+        let link = inbound.take_link(link)?;
+        *self.inbound_link.lock().expect("mutex") = Some(link);
+        debug!(ips = ?self.ips, "redirecting pod traffic");
+        Ok(())
+    }
+
+    fn register_inbound_listener(
+        &self,
+        listener: &TcpListener,
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        let mut inner = self.redirector.inner.lock().expect("mutex");
+        for ip in self.ips.iter().filter(|ip| listens_on(addr, **ip)) {
+            // The kernel drops the entry once the listener is closed.
+            inner
+                .inbound_listeners
+                .insert(Addr::ip(*ip), listener.as_raw_fd(), 0)
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PodRedirect {
+    fn drop(&mut self) {
+        self.inbound_link.lock().expect("mutex").take();
+        let mut inner = self.redirector.inner.lock().expect("mutex");
+        if let Err(e) = inner.pods.remove(&self.cookie) {
+            debug!("failed to remove enrolled pod: {e}");
+        }
+        debug!(ips = ?self.ips, "stopped redirecting pod traffic");
+    }
+}
+
+// listens_on returns whether a listener bound to the address accepts connections to the IP.
+fn listens_on(addr: SocketAddr, ip: IpAddr) -> bool {
+    match addr.ip() {
+        // Listeners are dual-stack
+        IpAddr::V6(a) if a.is_unspecified() => true,
+        IpAddr::V4(a) if a.is_unspecified() => ip.is_ipv4(),
+        a => a == ip,
+    }
+}
+
+// netns_cookie returns the cookie of the current network namespace, which the programs identify
+// it by.
+fn netns_cookie() -> io::Result<u64> {
+    const SO_NETNS_COOKIE: libc::c_int = 71;
+    let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None)?;
+    let mut cookie: u64 = 0;
+    let mut len = std::mem::size_of_val(&cookie) as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_NETNS_COOKIE,
+            &mut cookie as *mut u64 as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cookie)
+}
+
+// pod_ips returns the addresses of the interfaces in the current network namespace, other than
+// loopback and link-local ones.
+fn pod_ips() -> io::Result<Vec<IpAddr>> {
+    let ips = nix::ifaddrs::getifaddrs()?
+        .filter(|a| !a.flags.contains(InterfaceFlags::IFF_LOOPBACK))
+        .filter_map(|a| {
+            let addr = a.address?;
+            addr.as_sockaddr_in()
+                .map(|a| IpAddr::V4(a.ip()))
+                .or_else(|| addr.as_sockaddr_in6().map(|a| IpAddr::V6(a.ip())))
+        })
+        .filter(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 != 0xfe80,
+        })
+        .collect();
+    Ok(ips)
+}
+
+// RedirectSocketFactory registers the inbound plaintext listeners of a pod as they are bound.
+struct RedirectSocketFactory {
+    inner: Box<dyn SocketFactory + Send + Sync>,
+    pod: Arc<PodRedirect>,
+}
+
+impl SocketFactory for RedirectSocketFactory {
+    fn new_tcp_v4(&self) -> io::Result<TcpSocket> {
+        self.inner.new_tcp_v4()
+    }
+
+    fn new_tcp_v6(&self) -> io::Result<TcpSocket> {
+        self.inner.new_tcp_v6()
+    }
+
+    fn tcp_bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let listener = self.inner.tcp_bind(addr)?;
+        if self.pod.redirector.inbound_plaintext_addrs.contains(&addr) {
+            self.pod.register_inbound_listener(&listener, addr)?;
+        }
+        Ok(listener)
+    }
+
+    fn udp_bind(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        self.inner.udp_bind(addr)
+    }
+
+    fn udp_bind_transparent(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        self.inner.udp_bind_transparent(addr)
+    }
+
+    fn uds_bind(&self, path: &Path) -> io::Result<UnixListener> {
+        self.inner.uds_bind(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn addr_maps_ipv4() {
+        let v4 = Addr::ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        let mapped = Addr::ip(IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped()));
+        assert_eq!(v4.ip, mapped.ip);
+        assert_eq!(v4.ip[3], u32::from_ne_bytes([10, 0, 0, 1]));
+        assert_eq!(v4.ip[2], u32::from_ne_bytes([0, 0, 0xff, 0xff]));
+    }
+
+    #[test]
+    fn listener_addresses() {
+        let v4: IpAddr = Ipv4Addr::new(10, 0, 0, 1).into();
+        let v6: IpAddr = "fd00::1".parse().unwrap();
+        let any6 = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 15006);
+        let any4 = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 15006);
+        assert!(listens_on(any6, v4));
+        assert!(listens_on(any6, v6));
+        assert!(listens_on(any4, v4));
+        assert!(!listens_on(any4, v6));
+        assert!(listens_on(SocketAddr::new(v6, 15006), v6));
+        assert!(!listens_on(SocketAddr::new(v6, 15006), v4));
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Programs redirecting the traffic of in-pod workloads to ztunnel, loaded by src/redirect.rs.
+//
+// Outbound connections are redirected when they are made: the connect4/connect6 programs point
+// them at the pod's outbound listener, and remember where they were headed. The sockops program
+// indexes that destination by the connection's source address, which the getsockopt program uses
+// to answer SO_ORIGINAL_DST for the connection ztunnel accepts, as it would for iptables REDIRECT.
+// These are attached to a cgroup containing every pod, so only act in enrolled network namespaces.
+//
+// Inbound connections are steered by the inbound program, attached to the network namespace of
+// each enrolled pod, which hands connections to the pod's IPs to its inbound plaintext listener,
+// as iptables TPROXY would. HBONE connections are left to reach the HBONE listener.
+//
+// Built with: clang -O2 -g -target bpf -c redirect.bpf.c
+
+#include <linux/bpf.h>
+#include <linux/in.h>
+#include <linux/in6.h>
+
+#define SEC(name) __attribute__((section(name), used))
+#define __uint(name, val) int(*name)[val]
+#define __type(name, val) typeof(val) *name
+
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+#define bpf_htons(x) __builtin_bswap16(x)
+#define bpf_htonl(x) __builtin_bswap32(x)
+#else
+#define bpf_htons(x) (x)
+#define bpf_htonl(x) (x)
+#endif
+#define bpf_ntohs(x) bpf_htons(x)
+#define bpf_ntohl(x) bpf_htonl(x)
+
+#define AF_INET 2
+#define AF_INET6 10
+#define SOCK_STREAM 1
+#define SOL_IP 0
+#define SOL_IPV6 41
+#define SO_ORIGINAL_DST 80
+#define LOOPBACK_IFINDEX 1
+
+static void *(*bpf_map_lookup_elem)(void *map, const void *key) = (void *)BPF_FUNC_map_lookup_elem;
+static long (*bpf_map_update_elem)(void *map, const void *key, const void *value,
+                                   __u64 flags) = (void *)BPF_FUNC_map_update_elem;
+static long (*bpf_map_delete_elem)(void *map, const void *key) = (void *)BPF_FUNC_map_delete_elem;
+static __u64 (*bpf_get_socket_cookie)(void *ctx) = (void *)BPF_FUNC_get_socket_cookie;
+static __u64 (*bpf_get_netns_cookie)(void *ctx) = (void *)BPF_FUNC_get_netns_cookie;
+static long (*bpf_sk_assign)(void *ctx, void *sk, __u64 flags) = (void *)BPF_FUNC_sk_assign;
+static long (*bpf_sk_release)(void *sk) = (void *)BPF_FUNC_sk_release;
+
+// The kernel only lets GPL-compatible programs use some helpers.
+char LICENSE[] SEC("license") = "Dual BSD/GPL";
+
+// Set by ztunnel when loading the programs.
+const volatile __u16 outbound_port = 15001;
+const volatile __u16 inbound_port = 15008;
+const volatile __u32 inpod_mark = 1337;
+
+// The source address the CNI gives kubelet health probes, which are not redirected.
+#define PROBE_IP4 0xa9fe077f // 169.254.7.127
+static const __u32 probe_ip6[4] = {
+    bpf_htonl(0xfd169254), bpf_htonl(0x71271337), bpf_htonl(0xffffffff), bpf_htonl(0xffffffff)};
+
+// An IP address, with IPv4 addresses mapped to IPv6, and a port. Addresses are in network byte
+// order; the port is in host byte order.
+struct addr {
+    __u32 ip[4];
+    __u16 port;
+    __u16 pad;
+};
+
+// An enrolled pod. Its addresses are in network byte order.
+struct pod {
+    __u32 ip4;
+    __u32 ip6[4];
+    __u8 has_ip4;
+    __u8 has_ip6;
+    __u8 pad[2];
+};
+
+// Enrolled pods, by network namespace cookie
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 4096);
+    __type(key, __u64);
+    __type(value, struct pod);
+} pods SEC(".maps");
+
+// The original destination of redirected connections that are being made, by socket cookie
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, __u64);
+    __type(value, struct addr);
+} connecting SEC(".maps");
+
+// The original destination of redirected connections, by source address
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 65536);
+    __type(key, struct addr);
+    __type(value, struct addr);
+} original_dsts SEC(".maps");
+
+// The inbound plaintext listener of enrolled pods, by pod IP (with a zero port)
+struct {
+    __uint(type, BPF_MAP_TYPE_SOCKHASH);
+    __uint(max_entries, 4096);
+    __type(key, struct addr);
+    __type(value, __u32);
+} inbound_listeners SEC(".maps");
+
+static __always_inline void map_ip4(__u32 *ip, __u32 ip4) {
+    ip[0] = 0;
+    ip[1] = 0;
+    ip[2] = bpf_htonl(0x0000ffff);
+    ip[3] = ip4;
+}
+
+static __always_inline int is_ip4(const __u32 *ip) {
+    return ip[0] == 0 && ip[1] == 0 && ip[2] == bpf_htonl(0x0000ffff);
+}
+
+static __always_inline int is_loopback4(__u32 ip4) {
+    return (bpf_ntohl(ip4) >> 24) == 127;
+}
+
+static __always_inline int is_loopback6(const __u32 *ip) {
+    return ip[0] == 0 && ip[1] == 0 && ip[2] == 0 && ip[3] == bpf_htonl(1);
+}
+
+static __always_inline int ip_eq(const __u32 *a, const __u32 *b) {
+    return a[0] == b[0] && a[1] == b[1] && a[2] == b[2] && a[3] == b[3];
+}
+
+// enrolled_pod returns the pod making a connection, if it is enrolled and the connection is one
+// to redirect: a TCP connection not made by ztunnel itself.
+static __always_inline struct pod *enrolled_pod(struct bpf_sock_addr *ctx) {
+    if (ctx->type != SOCK_STREAM) {
+        return 0;
+    }
+    __u64 netns = bpf_get_netns_cookie(ctx);
+    struct pod *pod = bpf_map_lookup_elem(&pods, &netns);
+    if (!pod) {
+        return 0;
+    }
+    struct bpf_sock *sk = ctx->sk;
+    if (!sk || sk->mark == inpod_mark) {
+        return 0;
+    }
+    return pod;
+}
+
+static __always_inline void remember_dst(struct bpf_sock_addr *ctx, struct addr *dst) {
+    __u64 cookie = bpf_get_socket_cookie(ctx);
+    bpf_map_update_elem(&connecting, &cookie, dst, BPF_ANY);
+}
+
+SEC("cgroup/connect4")
+int connect4(struct bpf_sock_addr *ctx) {
+    struct pod *pod = enrolled_pod(ctx);
+    if (!pod || !pod->has_ip4) {
+        return 1;
+    }
+    __u32 dst = ctx->user_ip4;
+    // Connections within the pod are not redirected
+    if (is_loopback4(dst) || dst == pod->ip4) {
+        return 1;
+    }
+    struct addr orig = {};
+    map_ip4(orig.ip, dst);
+    orig.port = bpf_ntohs(ctx->user_port);
+    remember_dst(ctx, &orig);
+
+    ctx->user_ip4 = pod->ip4;
+    ctx->user_port = bpf_htons(outbound_port);
+    return 1;
+}
+
+SEC("cgroup/connect6")
+int connect6(struct bpf_sock_addr *ctx) {
+    struct pod *pod = enrolled_pod(ctx);
+    if (!pod) {
+        return 1;
+    }
+    struct addr orig = {};
+    orig.ip[0] = ctx->user_ip6[0];
+    orig.ip[1] = ctx->user_ip6[1];
+    orig.ip[2] = ctx->user_ip6[2];
+    orig.ip[3] = ctx->user_ip6[3];
+    orig.port = bpf_ntohs(ctx->user_port);
+
+    __u32 to[4];
+    if (is_ip4(orig.ip)) {
+        // An IPv4 connection from a dual-stack socket
+        if (!pod->has_ip4 || is_loopback4(orig.ip[3]) || orig.ip[3] == pod->ip4) {
+            return 1;
+        }
+        map_ip4(to, pod->ip4);
+    } else {
+        if (!pod->has_ip6 || is_loopback6(orig.ip) || ip_eq(orig.ip, pod->ip6)) {
+            return 1;
+        }
+        to[0] = pod->ip6[0];
+        to[1] = pod->ip6[1];
+        to[2] = pod->ip6[2];
+        to[3] = pod->ip6[3];
+    }
+    remember_dst(ctx, &orig);
+
+    ctx->user_ip6[0] = to[0];
+    ctx->user_ip6[1] = to[1];
+    ctx->user_ip6[2] = to[2];
+    ctx->user_ip6[3] = to[3];
+    ctx->user_port = bpf_htons(outbound_port);
+    return 1;
+}
+
+// Once a redirected connection has a source port, its original destination is indexed by its
+// source address, which is what ztunnel sees of it.
+SEC("sockops")
+int sockops(struct bpf_sock_ops *ctx) {
+    if (ctx->op != BPF_SOCK_OPS_TCP_CONNECT_CB) {
+        return 1;
+    }
+    __u64 cookie = bpf_get_socket_cookie(ctx);
+    struct addr *orig = bpf_map_lookup_elem(&connecting, &cookie);
+    if (!orig) {
+        return 1;
+    }
+    struct addr src = {};
+    if (ctx->family == AF_INET) {
+        map_ip4(src.ip, ctx->local_ip4);
+    } else {
+        src.ip[0] = ctx->local_ip6[0];
+        src.ip[1] = ctx->local_ip6[1];
+        src.ip[2] = ctx->local_ip6[2];
+        src.ip[3] = ctx->local_ip6[3];
+    }
+    src.port = ctx->local_port;
+    struct addr dst = *orig;
+    bpf_map_update_elem(&original_dsts, &src, &dst, BPF_ANY);
+    bpf_map_delete_elem(&connecting, &cookie);
+    return 1;
+}
+
+// Answers SO_ORIGINAL_DST for the connections the outbound listener accepts. The kernel fails the
+// call, as no NAT took place, so the program only runs after its own lookup.
+SEC("cgroup/getsockopt")
+int getsockopt(struct bpf_sockopt *ctx) {
+    if (ctx->optname != SO_ORIGINAL_DST || (ctx->level != SOL_IP && ctx->level != SOL_IPV6)) {
+        return 1;
+    }
+    struct bpf_sock *sk = ctx->sk;
+    if (!sk) {
+        return 1;
+    }
+    struct addr peer = {};
+    if (sk->family == AF_INET) {
+        map_ip4(peer.ip, sk->dst_ip4);
+    } else {
+        peer.ip[0] = sk->dst_ip6[0];
+        peer.ip[1] = sk->dst_ip6[1];
+        peer.ip[2] = sk->dst_ip6[2];
+        peer.ip[3] = sk->dst_ip6[3];
+    }
+    peer.port = bpf_ntohs(sk->dst_port);
+    struct addr *found = bpf_map_lookup_elem(&original_dsts, &peer);
+    if (!found) {
+        return 1;
+    }
+    struct addr orig = *found;
+
+    if (ctx->level == SOL_IP) {
+        struct sockaddr_in *sa = ctx->optval;
+        if (!is_ip4(orig.ip) || (void *)(sa + 1) > ctx->optval_end) {
+            return 1;
+        }
+        sa->sin_family = AF_INET;
+        sa->sin_port = bpf_htons(orig.port);
+        sa->sin_addr.s_addr = orig.ip[3];
+        ctx->optlen = sizeof(*sa);
+    } else {
+        struct sockaddr_in6 *sa = ctx->optval;
+        if (is_ip4(orig.ip) || (void *)(sa + 1) > ctx->optval_end) {
+            return 1;
+        }
+        sa->sin6_family = AF_INET6;
+        sa->sin6_port = bpf_htons(orig.port);
+        sa->sin6_flowinfo = 0;
+        sa->sin6_addr.in6_u.u6_addr32[0] = orig.ip[0];
+        sa->sin6_addr.in6_u.u6_addr32[1] = orig.ip[1];
+        sa->sin6_addr.in6_u.u6_addr32[2] = orig.ip[2];
+        sa->sin6_addr.in6_u.u6_addr32[3] = orig.ip[3];
+        sa->sin6_scope_id = 0;
+        ctx->optlen = sizeof(*sa);
+    }
+    ctx->retval = 0;
+    bpf_map_delete_elem(&original_dsts, &peer);
+    return 1;
+}
+
+SEC("sk_lookup")
+int inbound(struct bpf_sk_lookup *ctx) {
+    // Connections from ztunnel to the application, and within the pod, arrive over loopback.
+    if (ctx->protocol != IPPROTO_TCP || ctx->ingress_ifindex == LOOPBACK_IFINDEX ||
+        ctx->local_port == inbound_port) {
+        return SK_PASS;
+    }
+    struct addr key = {};
+    if (ctx->family == AF_INET) {
+        if (ctx->remote_ip4 == bpf_htonl(PROBE_IP4)) {
+            return SK_PASS;
+        }
+        map_ip4(key.ip, ctx->local_ip4);
+    } else {
+        __u32 remote[4] = {ctx->remote_ip6[0], ctx->remote_ip6[1], ctx->remote_ip6[2],
+                           ctx->remote_ip6[3]};
+        if (ip_eq(remote, probe_ip6)) {
+            return SK_PASS;
+        }
+        key.ip[0] = ctx->local_ip6[0];
+        key.ip[1] = ctx->local_ip6[1];
+        key.ip[2] = ctx->local_ip6[2];
+        key.ip[3] = ctx->local_ip6[3];
+    }
+    struct bpf_sock *sk = bpf_map_lookup_elem(&inbound_listeners, &key);
+    if (!sk) {
+        return SK_PASS;
+    }
+    long err = bpf_sk_assign(ctx, sk, 0);
+    bpf_sk_release(sk);
+    return err ? SK_DROP : SK_PASS;
+}
@@ -17,7 +17,8 @@
 //     async fn shutdown();
 // }
 
-use tokio::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
 
 pub struct Shutdown {
     shutdown_tx: mpsc::Sender<()>,
@@ -63,12 +64,67 @@ impl ShutdownTrigger {
     }
 }
 
+/// DrainTrigger starts a drain, either on request or as part of shutdown. The drain is only
+/// started once, by whichever comes first.
+#[derive(Clone, Debug)]
+pub struct DrainTrigger {
+    signal: Arc<Mutex<Option<drain::Signal>>>,
+    done_tx: Arc<watch::Sender<bool>>,
+}
+
+impl DrainTrigger {
+    pub fn new(signal: drain::Signal) -> Self {
+        let (done_tx, _) = watch::channel(false);
+        DrainTrigger {
+            signal: Arc::new(Mutex::new(Some(signal))),
+            done_tx: Arc::new(done_tx),
+        }
+    }
+
+    /// start_drain starts the drain in the background. Returns false if it was already started.
+    pub fn start_drain(&self) -> bool {
+        let Some(signal) = self.signal.lock().unwrap().take() else {
+            return false;
+        };
+        let done_tx = self.done_tx.clone();
+        tokio::spawn(async move {
+            signal.drain().await;
+            done_tx.send_replace(true);
+        });
+        true
+    }
+
+    /// drain starts the drain, if it hasn't been already, and waits for it to complete.
+    pub async fn drain(&self) {
+        let mut done = self.done_tx.subscribe();
+        self.start_drain();
+        let _ = done.wait_for(|done| *done).await;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.signal.lock().unwrap().is_none()
+    }
+
+    pub fn is_drained(&self) -> bool {
+        *self.done_tx.borrow()
+    }
+}
+
+/// on_reload calls `f` each time a configuration reload is requested, by SIGHUP. Reloads are not
+/// supported on other platforms.
+pub fn on_reload<F>(f: F)
+where
+    F: Fn() + Send + 'static,
+{
+    imp::on_reload(f)
+}
+
 #[cfg(unix)]
 mod imp {
     use std::process;
     use tokio::signal::unix::{signal, SignalKind};
     use tokio::sync::mpsc::Receiver;
-    use tracing::info;
+    use tracing::{info, warn};
 
     pub(super) async fn shutdown(receiver: &mut Receiver<()>) {
         tokio::select! {
@@ -84,6 +140,25 @@ mod imp {
         };
     }
 
+    pub(super) fn on_reload<F>(f: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("failed to register SIGHUP handler, reloads are disabled: {e}");
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("received signal SIGHUP, reloading configuration");
+                f();
+            }
+        });
+    }
+
     async fn watch_signal(kind: SignalKind, name: &'static str) {
         signal(kind)
             .expect("Failed to register signal handler")
@@ -96,12 +171,46 @@ mod imp {
 #[cfg(not(unix))]
 mod imp {
     use tokio::sync::mpsc::Receiver;
+    use tracing::info;
 
-    pub(super) async fn shutdown(receiver: Receiver<()>) {
-        // This isn't quite right, but close enough for windows...
-        tokio::signal::windows::ctrl_c()
-            .expect("Failed to register signal handler")
-            .recv()
-            .await;
+    pub(super) fn on_reload<F>(_f: F)
+    where
+        F: Fn() + Send + 'static,
+    {
+    }
+
+    pub(super) async fn shutdown(receiver: &mut Receiver<()>) {
+        let mut ctrl_c =
+            tokio::signal::windows::ctrl_c().expect("Failed to register signal handler");
+        let mut ctrl_shutdown =
+            tokio::signal::windows::ctrl_shutdown().expect("Failed to register signal handler");
+        tokio::select! {
+            _ = ctrl_c.recv() => { info!("received Ctrl+C, starting shutdown") }
+            _ = ctrl_shutdown.recv() => { info!("received shutdown event, starting shutdown") }
+            _ = receiver.recv() => { info!("received explicit shutdown signal") }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_trigger() {
+        let (signal, watch) = drain::channel();
+        let trigger = DrainTrigger::new(signal);
+        assert!(!trigger.is_draining());
+
+        assert!(trigger.start_drain());
+        assert!(!trigger.start_drain());
+        assert!(trigger.is_draining());
+
+        // The drain completes once the last watcher is released.
+        let released = watch.signaled().await;
+        assert!(!trigger.is_drained());
+        drop(released);
+        trigger.drain().await;
+        assert!(trigger.is_drained());
     }
 }
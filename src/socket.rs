@@ -12,41 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::Error;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::Path;
 
+use socket2::SockRef;
 use tokio::io;
+use tokio::net::{TcpListener, TcpSocket};
+use tracing::warn;
 
-use tokio::net::TcpListener;
-use tokio::net::TcpSocket;
+use crate::config::{SocketConfig, SocketPolicy};
 
+// Platform specific socket options are implemented by `sys`. Linux supports everything ztunnel
+// needs. On Windows, traffic is redirected with WFP, which reports the original destination but
+// does not require (or support) transparent sockets. Elsewhere, only the basics work.
 #[cfg(target_os = "linux")]
-use {
-    socket2::{Domain, SockRef},
-    std::io::ErrorKind,
-    tracing::warn,
-};
+use linux as sys;
+#[cfg(not(any(target_os = "linux", windows)))]
+use unsupported as sys;
+#[cfg(windows)]
+use windows as sys;
 
-#[cfg(target_os = "linux")]
 pub fn set_transparent(l: &TcpListener) -> io::Result<()> {
-    SockRef::from(l).set_ip_transparent(true)
+    sys::set_transparent(&SockRef::from(l))
 }
 
-#[cfg(target_os = "linux")]
 pub fn set_freebind_and_transparent(socket: &TcpSocket) -> io::Result<()> {
-    let socket = SockRef::from(socket);
-    match socket.domain()? {
-        Domain::IPV4 => {
-            socket.set_ip_transparent(true)?;
-            socket.set_freebind(true)?;
-        }
-        Domain::IPV6 => {
-            linux::set_ipv6_transparent(&socket)?;
-            socket.set_freebind_ipv6(true)?
-        }
-        _ => return Err(Error::new(ErrorKind::Unsupported, "unsupported domain")),
-    };
-    Ok(())
+    sys::set_freebind_and_transparent(&SockRef::from(socket))
 }
 
 pub fn to_canonical(addr: SocketAddr) -> SocketAddr {
@@ -64,6 +56,100 @@ pub fn to_canonical(addr: SocketAddr) -> SocketAddr {
     SocketAddr::from((ip, addr.port()))
 }
 
+// set_dual_stack configures a socket about to be bound to the given address. Sockets bound to the
+// unspecified IPv6 address are made dual-stack, so they accept IPv4 traffic as well, regardless of
+// the system default (net.ipv6.bindv6only).
+pub fn set_dual_stack(sock: &socket2::SockRef<'_>, addr: SocketAddr) -> io::Result<()> {
+    match addr.ip() {
+        IpAddr::V6(ip) if ip.is_unspecified() => sock.set_only_v6(false),
+        _ => Ok(()),
+    }
+}
+
+// tcp_bind binds a non-blocking TCP listener on the given address.
+// See set_dual_stack for how the unspecified IPv6 address is handled.
+pub fn tcp_bind(addr: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        None,
+    )?;
+    set_dual_stack(&socket2::SockRef::from(&socket), addr)?;
+    // Match the std library, which sets SO_REUSEADDR on listeners
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// udp_bind binds a non-blocking UDP socket on the given address.
+// See set_dual_stack for how the unspecified IPv6 address is handled.
+pub fn udp_bind(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        None,
+    )?;
+    set_dual_stack(&socket2::SockRef::from(&socket), addr)?;
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// udp_bind_transparent binds a non-blocking UDP socket on a possibly non-local address, so replies to
+// redirected UDP traffic can be sent from the address the client originally sent to.
+pub fn udp_bind_transparent(addr: SocketAddr) -> io::Result<std::net::UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        None,
+    )?;
+    // Many flows may reply from the same original destination
+    socket.set_reuse_address(true)?;
+    sys::set_transparent(&SockRef::from(&socket))?;
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+// uds_bind binds a non-blocking unix domain socket listener at the given path. A socket left behind
+// by a previous instance is replaced, but any other file at the path is not. The socket is only
+// accessible to its owner.
+#[cfg(unix)]
+pub fn uds_bind(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        _ => {}
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+// set_recv_orig_dst makes a UDP socket accept redirected traffic, and report the original
+// destination of each datagram. See recv_orig_dst.
+pub fn set_recv_orig_dst(socket: &tokio::net::UdpSocket) -> io::Result<()> {
+    sys::set_recv_orig_dst(&SockRef::from(socket))
+}
+
+// recv_orig_dst receives a datagram, returning its length, source, and original destination.
+// If the original destination is unknown (see set_recv_orig_dst), the socket's local address is used.
+pub async fn recv_orig_dst(
+    socket: &tokio::net::UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, SocketAddr)> {
+    let (len, src, dst) = sys::recv_orig_dst(socket, buf).await?;
+    let dst = match dst {
+        Some(dst) => dst,
+        None => socket.local_addr()?,
+    };
+    Ok((len, to_canonical(src), to_canonical(dst)))
+}
+
 pub fn orig_dst_addr_or_default(stream: &tokio::net::TcpStream) -> SocketAddr {
     to_canonical(match orig_dst_addr(stream) {
         Ok(addr) => addr,
@@ -71,82 +157,281 @@ pub fn orig_dst_addr_or_default(stream: &tokio::net::TcpStream) -> SocketAddr {
     })
 }
 
-#[cfg(target_os = "linux")]
+type OriginalDstFn = fn(&SockRef) -> io::Result<socket2::SockAddr>;
+
 fn orig_dst_addr(stream: &tokio::net::TcpStream) -> io::Result<SocketAddr> {
     let sock = SockRef::from(stream);
-    // Dual-stack IPv4/IPv6 sockets require us to check both options.
-    match linux::original_dst(&sock) {
+    // Dual-stack IPv4/IPv6 sockets require us to check both options. Check the family the connection
+    // actually uses first: IPv4 connections on a dual-stack socket have an IPv4-mapped local address.
+    let is_ipv4 = stream
+        .local_addr()
+        .map(|a| to_canonical(a).is_ipv4())
+        .unwrap_or(true);
+    let (first, second): (OriginalDstFn, OriginalDstFn) = if is_ipv4 {
+        (sys::original_dst, sys::original_dst_ipv6)
+    } else {
+        (sys::original_dst_ipv6, sys::original_dst)
+    };
+    match first(&sock) {
         Ok(addr) => Ok(addr.as_socket().expect("failed to convert to SocketAddr")),
-        Err(e4) => match linux::original_dst_ipv6(&sock) {
+        Err(e1) => match second(&sock) {
             Ok(addr) => Ok(addr.as_socket().expect("failed to convert to SocketAddr")),
-            Err(e6) => {
-                if !sock.ip_transparent().unwrap_or(false) {
+            Err(e2) => {
+                if !sys::is_transparent(&sock) {
                     // In TPROXY mode, this is normal, so don't bother logging
                     warn!(
                         peer=?stream.peer_addr().unwrap(),
                         local=?stream.local_addr().unwrap(),
-                        "failed to read SO_ORIGINAL_DST: {e4:?}, {e6:?}"
+                        "failed to read SO_ORIGINAL_DST: {e1:?}, {e2:?}"
                     );
                 }
-                Err(e6)
+                Err(e2)
             }
         },
     }
 }
 
-#[cfg(not(target_os = "linux"))]
-fn orig_dst_addr(_: &tokio::net::TcpStream) -> io::Result<SocketAddr> {
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "SO_ORIGINAL_DST not supported on this operating system",
-    ))
+// set_keepalive applies the configured TCP keepalive settings to the stream, if enabled.
+pub fn set_keepalive(stream: &tokio::net::TcpStream, cfg: &SocketConfig) -> io::Result<()> {
+    if !cfg.keepalive_enabled {
+        return Ok(());
+    }
+    let ka = socket2::TcpKeepalive::new().with_time(cfg.keepalive_time);
+    #[cfg(any(target_os = "linux", windows))]
+    let ka = ka.with_interval(cfg.keepalive_interval);
+    #[cfg(target_os = "linux")]
+    let ka = ka.with_retries(cfg.keepalive_retries);
+    socket2::SockRef::from(stream).set_tcp_keepalive(&ka)
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn set_freebind_and_transparent(_: &TcpSocket) -> io::Result<()> {
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "IP_TRANSPARENT and IP_FREEBIND are not supported on this operating system",
-    ))
+// set_policy applies the options set in a socket policy to the stream.
+pub fn set_policy(stream: &tokio::net::TcpStream, policy: &SocketPolicy) -> io::Result<()> {
+    let sock = SockRef::from(stream);
+    if let Some(nodelay) = policy.nodelay {
+        sock.set_nodelay(nodelay)?;
+    }
+    if let Some(size) = policy.send_buffer_size {
+        sock.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = policy.recv_buffer_size {
+        sock.set_recv_buffer_size(size)?;
+    }
+    if let Some(tos) = policy.tos {
+        sys::set_tos(&sock, tos)?;
+    }
+    Ok(())
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn set_transparent(_: &TcpListener) -> io::Result<()> {
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "IP_TRANSPARENT not supported on this operating system",
-    ))
+// The ECN bits are the lower 2 bits of the TOS byte; the rest is the DSCP mark.
+const ECN_MASK: u8 = 0b11;
+
+// received_dscp returns the DSCP mark, as a TOS byte without the ECN bits, of the packets the peer
+// sends on the connection.
+pub fn received_dscp(stream: &tokio::net::TcpStream) -> io::Result<u8> {
+    sys::received_tos(&SockRef::from(stream)).map(|tos| tos & !ECN_MASK)
 }
 
-#[cfg(target_os = "linux")]
-pub fn set_mark<S: std::os::unix::io::AsFd>(socket: &S, mark: u32) -> io::Result<()> {
-    let socket = SockRef::from(socket);
-    socket.set_mark(mark)
+// set_tos sets the TOS byte (or IPv6 traffic class) of the packets sent on the connection.
+pub fn set_tos(stream: &tokio::net::TcpStream, tos: u8) -> io::Result<()> {
+    sys::set_tos(&SockRef::from(stream), tos)
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn set_mark(_socket: &TcpSocket, _mark: u32) -> io::Result<()> {
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "SO_MARK not supported on this operating system",
-    ))
+pub fn set_mark<'s, S>(socket: &'s S, mark: u32) -> io::Result<()>
+where
+    SockRef<'s>: From<&'s S>,
+{
+    sys::set_mark(&SockRef::from(socket), mark)
 }
 
 #[cfg(target_os = "linux")]
 #[allow(unsafe_code)]
 mod linux {
-    use std::os::unix::io::AsRawFd;
+    use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::unix::io::{AsRawFd, RawFd};
 
-    use socket2::{SockAddr, SockRef};
+    use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags, SockaddrStorage};
+    use socket2::{Domain, SockAddr, SockRef};
     use tokio::io;
 
-    pub fn set_ipv6_transparent(sock: &SockRef) -> io::Result<()> {
+    pub fn set_transparent(sock: &SockRef) -> io::Result<()> {
+        match sock.domain()? {
+            Domain::IPV4 => sock.set_ip_transparent(true),
+            Domain::IPV6 => set_ipv6_transparent(sock),
+            _ => Err(unsupported_domain()),
+        }
+    }
+
+    pub fn set_freebind_and_transparent(sock: &SockRef) -> io::Result<()> {
+        match sock.domain()? {
+            Domain::IPV4 => {
+                sock.set_ip_transparent(true)?;
+                sock.set_freebind(true)
+            }
+            Domain::IPV6 => {
+                set_ipv6_transparent(sock)?;
+                sock.set_freebind_ipv6(true)
+            }
+            _ => Err(unsupported_domain()),
+        }
+    }
+
+    pub fn is_transparent(sock: &SockRef) -> bool {
+        sock.ip_transparent().unwrap_or(false)
+    }
+
+    pub fn set_mark(sock: &SockRef, mark: u32) -> io::Result<()> {
+        sock.set_mark(mark)
+    }
+
+    pub fn set_tos(sock: &SockRef, tos: u8) -> io::Result<()> {
+        let tos = libc::c_int::from(tos);
+        match sock.domain()? {
+            Domain::IPV4 => set_option(sock, libc::IPPROTO_IP, libc::IP_TOS, tos),
+            Domain::IPV6 => {
+                set_option(sock, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)?;
+                // IPv4 traffic on dual-stack sockets is marked with IP_TOS instead
+                if !sock.only_v6()? {
+                    set_option(sock, libc::IPPROTO_IP, libc::IP_TOS, tos)?;
+                }
+                Ok(())
+            }
+            _ => Err(unsupported_domain()),
+        }
+    }
+
+    pub fn set_recv_orig_dst(sock: &SockRef) -> io::Result<()> {
+        match sock.domain()? {
+            Domain::IPV4 => {
+                sock.set_ip_transparent(true)?;
+                enable_option(sock, libc::IPPROTO_IP, libc::IP_RECVORIGDSTADDR)
+            }
+            Domain::IPV6 => {
+                set_ipv6_transparent(sock)?;
+                enable_option(sock, libc::IPPROTO_IPV6, libc::IPV6_RECVORIGDSTADDR)?;
+                // Dual-stack sockets also receive IPv4 traffic
+                if !sock.only_v6()? {
+                    enable_option(sock, libc::IPPROTO_IP, libc::IP_RECVORIGDSTADDR)?;
+                }
+                Ok(())
+            }
+            _ => Err(unsupported_domain()),
+        }
+    }
+
+    pub async fn recv_orig_dst(
+        socket: &tokio::net::UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        let fd = socket.as_raw_fd();
+        socket
+            .async_io(tokio::io::Interest::READABLE, || recvmsg_orig_dst(fd, buf))
+            .await
+    }
+
+    pub fn original_dst(sock: &SockRef) -> io::Result<SockAddr> {
+        sock.original_dst()
+    }
+
+    pub fn original_dst_ipv6(sock: &SockRef) -> io::Result<SockAddr> {
+        sock.original_dst_ipv6()
+    }
+
+    // received_tos returns the TOS byte (or IPv6 traffic class) of the packets received on a
+    // connected TCP socket, as recorded by the kernel. The kernel reports it, as ancillary data, for
+    // sockets with IP_RECVTOS (IPV6_RECVTCLASS) set.
+    pub fn received_tos(sock: &SockRef) -> io::Result<u8> {
+        // Constants from linux/in.h and linux/in6.h
+        const IP_PKTOPTIONS: libc::c_int = 9;
+        const IPV6_2292PKTOPTIONS: libc::c_int = 6;
+
+        // IPv4 traffic on dual-stack sockets is handled by the IPv4 stack
+        let ipv4 = match sock.domain()? {
+            Domain::IPV4 => true,
+            Domain::IPV6 => sock
+                .peer_addr()?
+                .as_socket()
+                .is_some_and(|addr| super::to_canonical(addr).is_ipv4()),
+            _ => return Err(unsupported_domain()),
+        };
+        let (level, recv, pktoptions, tos) = if ipv4 {
+            (
+                libc::IPPROTO_IP,
+                libc::IP_RECVTOS,
+                IP_PKTOPTIONS,
+                libc::IP_TOS,
+            )
+        } else {
+            (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_RECVTCLASS,
+                IPV6_2292PKTOPTIONS,
+                libc::IPV6_TCLASS,
+            )
+        };
+        enable_option(sock, level, recv)?;
+
+        // u64 for alignment of the control messages
+        let mut buf = [0u64; 16];
+        let mut len = std::mem::size_of_val(&buf) as libc::socklen_t;
+        unsafe {
+            let ret = libc::getsockopt(
+                sock.as_raw_fd(),
+                level,
+                pktoptions,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            );
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_control = buf.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = len as _;
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let hdr = &*cmsg;
+                if hdr.cmsg_level == level && hdr.cmsg_type == tos {
+                    let data = libc::CMSG_DATA(cmsg);
+                    // The value is an int, except for IPv4 datagrams, where it is a single byte
+                    let data_len = hdr.cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    return Ok(if data_len >= std::mem::size_of::<libc::c_int>() {
+                        std::ptr::read_unaligned(data as *const libc::c_int) as u8
+                    } else {
+                        *data
+                    });
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no TOS recorded for the connection",
+        ))
+    }
+
+    fn set_ipv6_transparent(sock: &SockRef) -> io::Result<()> {
+        enable_option(sock, libc::IPPROTO_IPV6, libc::IPV6_TRANSPARENT)
+    }
+
+    fn unsupported_domain() -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, "unsupported domain")
+    }
+
+    fn enable_option(sock: &SockRef, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+        set_option(sock, level, name, 1)
+    }
+
+    fn set_option(
+        sock: &SockRef,
+        level: libc::c_int,
+        name: libc::c_int,
+        optval: libc::c_int,
+    ) -> io::Result<()> {
         unsafe {
-            let optval: libc::c_int = 1;
             let ret = libc::setsockopt(
                 sock.as_raw_fd(),
-                libc::IPPROTO_IPV6,
-                libc::IPV6_TRANSPARENT,
+                level,
+                name,
                 &optval as *const _ as *const libc::c_void,
                 std::mem::size_of_val(&optval) as libc::socklen_t,
             );
@@ -157,6 +442,104 @@ mod linux {
         Ok(())
     }
 
+    // recvmsg_orig_dst receives a datagram along with the original destination reported by
+    // IP_RECVORIGDSTADDR/IPV6_RECVORIGDSTADDR, if any.
+    fn recvmsg_orig_dst(
+        fd: RawFd,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        let mut iov = [std::io::IoSliceMut::new(buf)];
+        let mut cmsg = nix::cmsg_space!(libc::sockaddr_in6);
+        let msg = recvmsg::<SockaddrStorage>(fd, &mut iov, Some(&mut cmsg), MsgFlags::empty())?;
+        let src = msg
+            .address
+            .and_then(|a| {
+                if let Some(a) = a.as_sockaddr_in() {
+                    Some(SocketAddr::V4(SocketAddrV4::from(*a)))
+                } else {
+                    a.as_sockaddr_in6()
+                        .map(|a| SocketAddr::V6(SocketAddrV6::from(*a)))
+                }
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown source address"))?;
+        let mut dst = None;
+        for c in msg.cmsgs() {
+            match c {
+                ControlMessageOwned::Ipv4OrigDstAddr(a) => {
+                    dst = Some(SocketAddr::V4(SocketAddrV4::new(
+                        u32::from_be(a.sin_addr.s_addr).into(),
+                        u16::from_be(a.sin_port),
+                    )))
+                }
+                ControlMessageOwned::Ipv6OrigDstAddr(a) => {
+                    dst = Some(SocketAddr::V6(SocketAddrV6::new(
+                        a.sin6_addr.s6_addr.into(),
+                        u16::from_be(a.sin6_port),
+                        0,
+                        0,
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok((msg.bytes, src, dst))
+    }
+}
+
+// On Windows, connections are redirected to ztunnel with WFP connect redirection, which records the
+// original destination for SO_ORIGINAL_DST. ztunnel cannot spoof addresses there, so transparent
+// sockets, and thus original source binding, are not supported.
+#[cfg(windows)]
+mod windows {
+    use std::net::SocketAddr;
+
+    use socket2::{SockAddr, SockRef};
+    use tokio::io;
+
+    pub fn set_transparent(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "transparent sockets are not supported on Windows",
+        ))
+    }
+
+    pub fn set_freebind_and_transparent(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "transparent sockets are not supported on Windows",
+        ))
+    }
+
+    pub fn is_transparent(_: &SockRef) -> bool {
+        false
+    }
+
+    pub fn set_mark(_: &SockRef, _: u32) -> io::Result<()> {
+        Err(unsupported("SO_MARK is not supported on Windows"))
+    }
+
+    pub fn set_tos(_: &SockRef, _: u8) -> io::Result<()> {
+        Err(unsupported(
+            "setting IP_TOS is not supported on Windows; use a QoS policy instead",
+        ))
+    }
+
+    pub fn received_tos(_: &SockRef) -> io::Result<u8> {
+        Err(unsupported("IP_RECVTOS is not supported on Windows"))
+    }
+
+    pub fn set_recv_orig_dst(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "UDP original destination is not supported on Windows",
+        ))
+    }
+
+    pub async fn recv_orig_dst(
+        socket: &tokio::net::UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        let (len, src) = socket.recv_from(buf).await?;
+        Ok((len, src, None))
+    }
+
     pub fn original_dst(sock: &SockRef) -> io::Result<SockAddr> {
         sock.original_dst()
     }
@@ -164,4 +547,170 @@ mod linux {
     pub fn original_dst_ipv6(sock: &SockRef) -> io::Result<SockAddr> {
         sock.original_dst_ipv6()
     }
+
+    fn unsupported(msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, msg)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod unsupported {
+    use std::net::SocketAddr;
+
+    use socket2::{SockAddr, SockRef};
+    use tokio::io;
+
+    pub fn set_transparent(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "IP_TRANSPARENT not supported on this operating system",
+        ))
+    }
+
+    pub fn set_freebind_and_transparent(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "IP_TRANSPARENT and IP_FREEBIND are not supported on this operating system",
+        ))
+    }
+
+    pub fn is_transparent(_: &SockRef) -> bool {
+        false
+    }
+
+    pub fn set_mark(_: &SockRef, _: u32) -> io::Result<()> {
+        Err(unsupported(
+            "SO_MARK not supported on this operating system",
+        ))
+    }
+
+    pub fn set_tos(_: &SockRef, _: u8) -> io::Result<()> {
+        Err(unsupported("IP_TOS not supported on this operating system"))
+    }
+
+    pub fn received_tos(_: &SockRef) -> io::Result<u8> {
+        Err(unsupported(
+            "IP_PKTOPTIONS not supported on this operating system",
+        ))
+    }
+
+    pub fn set_recv_orig_dst(_: &SockRef) -> io::Result<()> {
+        Err(unsupported(
+            "IP_RECVORIGDSTADDR not supported on this operating system",
+        ))
+    }
+
+    pub async fn recv_orig_dst(
+        socket: &tokio::net::UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        let (len, src) = socket.recv_from(buf).await?;
+        Ok((len, src, None))
+    }
+
+    pub fn original_dst(_: &SockRef) -> io::Result<SockAddr> {
+        Err(unsupported(
+            "SO_ORIGINAL_DST not supported on this operating system",
+        ))
+    }
+
+    pub fn original_dst_ipv6(_: &SockRef) -> io::Result<SockAddr> {
+        Err(unsupported(
+            "SO_ORIGINAL_DST not supported on this operating system",
+        ))
+    }
+
+    fn unsupported(msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_addresses() {
+        let v4: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        assert_eq!(to_canonical(v4), v4);
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:80".parse().unwrap();
+        assert_eq!(to_canonical(mapped), v4);
+        let v6: SocketAddr = "[2001:db8::1]:80".parse().unwrap();
+        assert_eq!(to_canonical(v6), v6);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn apply_socket_policy() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let policy = SocketPolicy {
+            nodelay: Some(true),
+            send_buffer_size: Some(64 * 1024),
+            tos: Some(0xb8),
+            ..Default::default()
+        };
+        set_policy(&stream, &policy).unwrap();
+        let sock = SockRef::from(&stream);
+        assert!(sock.nodelay().unwrap());
+        assert_eq!(sock.tos().unwrap(), 0xb8);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn receive_dscp() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpSocket::new_v4().unwrap();
+        // DSCP EF, with ECN bits that are not part of the mark
+        SockRef::from(&socket).set_tos(0xb8 | 0b01).unwrap();
+        let (client, server) = tokio::join!(
+            socket.connect(listener.local_addr().unwrap()),
+            listener.accept()
+        );
+        let _client = client.unwrap();
+        let (server, _) = server.unwrap();
+        assert_eq!(received_dscp(&server).unwrap(), 0xb8);
+    }
+
+    #[tokio::test]
+    async fn dual_stack_listener() {
+        let listener = tcp_bind("[::]:0".parse().unwrap());
+        let Ok(listener) = listener else {
+            // IPv6 may be unavailable in the test environment
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let listener = TcpListener::from_std(listener).unwrap();
+        let (client, server) = tokio::join!(
+            tokio::net::TcpStream::connect(SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+            listener.accept()
+        );
+        client.unwrap();
+        let (_, peer) = server.unwrap();
+        assert_eq!(to_canonical(peer).ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn uds_bind_replaces_stale_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("ztunnel-uds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("listener.sock");
+
+        let listener = uds_bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        drop(listener);
+        // The socket file is left behind, but can be bound again
+        uds_bind(&path).unwrap();
+
+        // Other files are never removed
+        let file = dir.join("file");
+        std::fs::write(&file, b"data").unwrap();
+        assert!(uds_bind(&file).is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"data");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
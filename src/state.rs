@@ -12,16 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::identity::SecretManager;
+use crate::identity::{Identity, SecretManager};
 use crate::proxy;
 use crate::proxy::{Error, OnDemandDnsLabels};
 use crate::rbac::Authorization;
+use crate::readiness;
+use crate::state::cell::ProxyStateCell;
+use crate::state::events::{StateEvent, StateEvents};
+use crate::state::fetcher::{FileWorkloadFetcher, WorkloadFetcher};
 use crate::state::policy::PolicyStore;
-use crate::state::service::{Endpoint, LoadBalancerMode, LoadBalancerScopes, ServiceStore};
+use crate::state::service::{
+    Endpoint, LoadBalancerMode, LoadBalancerScopes, LoadBalancerStrategy, ServiceStore,
+    SessionAffinity,
+};
 use crate::state::service::{Service, ServiceDescription};
+use crate::state::snapshot::{Snapshot, StaleEntries};
 use crate::state::workload::{
-    address::Address, gatewayaddress::Destination, network_addr, NamespacedHostname,
-    NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
+    address::Address, gatewayaddress::Destination, network_addr, GatewayAddress,
+    NamespacedHostname, NetworkAddress, Protocol, WaypointError, Workload, WorkloadStore,
 };
 use crate::strng::Strng;
 use crate::tls;
@@ -37,16 +45,22 @@ use hickory_resolver::TokioAsyncResolver;
 use rand::prelude::IteratorRandom;
 use rand::seq::SliceRandom;
 use serde::Serializer;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::convert::Into;
 use std::default::Default;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, SocketAddr};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use tracing::{debug, error, trace, warn};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{debug, error, trace, trace_span, warn};
 
+pub mod cell;
+pub mod events;
+pub mod fetcher;
 pub mod policy;
 pub mod service;
+pub mod snapshot;
 pub mod workload;
 
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize)]
@@ -142,8 +156,30 @@ impl fmt::Display for ProxyRbacContext {
         Ok(())
     }
 }
+/// EndpointLoad reports the load on upstream endpoints, for load balancing strategies that take it
+/// into account.
+pub trait EndpointLoad {
+    /// Returns the number of active connections to the given destination address.
+    fn active_connections(&self, ip: IpAddr) -> usize;
+
+    /// Returns the addresses that already failed to connect for the connection being established,
+    /// which should not be picked again. None if there were no failures.
+    fn failed_addresses(&self) -> Option<&HashSet<IpAddr>> {
+        None
+    }
+
+    /// Returns the addresses currently ejected for failing, which should be avoided while other
+    /// endpoints are available.
+    fn ejected_addresses(&self) -> HashSet<IpAddr> {
+        HashSet::new()
+    }
+}
+
 /// The current state information for this proxy.
-#[derive(Default, Debug)]
+///
+/// Copies share the DNS caches and notifiers, which are written outside of XDS updates, but have
+/// their own copy of everything else.
+#[derive(Default, Debug, Clone)]
 pub struct ProxyState {
     pub workloads: WorkloadStore,
 
@@ -152,6 +188,10 @@ pub struct ProxyState {
     pub policies: PolicyStore,
 
     pub resolved_dns: ResolvedDnsStore,
+
+    pub captured_hostnames: CapturedHostnameStore,
+
+    pub events: StateEvents,
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -180,16 +220,56 @@ impl serde::Serialize for ProxyState {
     }
 }
 
-/// A ResolvedDnsStore encapsulates all resolved DNS information for workloads in the mesh
-#[derive(Default, Debug)]
+/// A ResolvedDnsStore encapsulates all resolved DNS information for workloads in the mesh.
+/// It is written as connections are made, so it has its own lock rather than being copied.
+#[derive(Default, Debug, Clone)]
 pub struct ResolvedDnsStore {
     // by_hostname is a map from hostname to resolved IP addresses for now.
     //
     // in a future with support for per-pod DNS resolv.conf settings we may need
     // to change this to a map from source workload uid to resolved IP addresses.
-    by_hostname: HashMap<Strng, ResolvedDns>,
+    by_hostname: Arc<RwLock<HashMap<Strng, ResolvedDns>>>,
+    // hostnames with a background refresh in progress
+    refreshing: Arc<Mutex<HashSet<Strng>>>,
+}
+
+impl ResolvedDnsStore {
+    /// get returns the resolved DNS for `hostname`, unless it has expired.
+    pub fn get(&self, hostname: &Strng) -> Option<ResolvedDns> {
+        self.by_hostname
+            .read()
+            .unwrap()
+            .get(hostname)
+            .filter(|rdns| {
+                rdns.initial_query.is_some()
+                    && rdns.initial_query.unwrap().elapsed() < rdns.dns_refresh_rate
+            })
+            .cloned()
+    }
+
+    pub fn insert(&self, hostname: Strng, rdns: ResolvedDns) {
+        self.by_hostname.write().unwrap().insert(hostname, rdns);
+    }
+
+    pub fn remove(&self, hostname: &Strng) {
+        self.by_hostname.write().unwrap().remove(hostname);
+    }
+
+    // start_refresh marks a refresh of `hostname` as in progress, returning false if one already
+    // was.
+    fn start_refresh(&self, hostname: Strng) -> bool {
+        self.refreshing.lock().unwrap().insert(hostname)
+    }
+
+    fn finish_refresh(&self, hostname: &Strng) {
+        self.refreshing.lock().unwrap().remove(hostname);
+    }
 }
 
+// Cached DNS results are refreshed in the background once this fraction of their TTL has passed,
+// so connections keep being served from the cache while the refresh is in progress.
+const DNS_BACKGROUND_REFRESH_FRACTION: f64 = 0.75;
+
 #[derive(serde::Serialize, Default, Debug, Clone)]
 pub struct ResolvedDns {
     hostname: Strng,
@@ -202,6 +282,70 @@ pub struct ResolvedDns {
     dns_refresh_rate: std::time::Duration,
 }
 
+impl ResolvedDns {
+    fn needs_refresh(&self) -> bool {
+        self.initial_query.is_some_and(|q| {
+            q.elapsed()
+                >= self
+                    .dns_refresh_rate
+                    .mul_f64(DNS_BACKGROUND_REFRESH_FRACTION)
+        })
+    }
+}
+
+// Captured hostnames are kept for at least this long, regardless of the record TTL, since clients
+// commonly cache DNS answers beyond their TTL.
+const CAPTURED_HOSTNAME_MIN_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// A CapturedHostnameStore maps addresses returned by upstream DNS for the hostnames of services
+/// without VIPs, such as DNS-resolved ServiceEntries, back to those hostnames. This lets outbound
+/// traffic to the addresses be routed as traffic to the service.
+/// It is written as DNS requests are served, so it has its own lock rather than being copied.
+#[derive(Default, Debug, Clone)]
+pub struct CapturedHostnameStore {
+    by_addr: Arc<Mutex<HashMap<NetworkAddress, CapturedHostname>>>,
+}
+
+#[derive(Debug)]
+struct CapturedHostname {
+    hostname: NamespacedHostname,
+    expires: std::time::Instant,
+}
+
+impl CapturedHostnameStore {
+    /// insert records that `addr` was returned for `hostname`, with the given record TTL.
+    pub fn insert(
+        &self,
+        addr: NetworkAddress,
+        hostname: NamespacedHostname,
+        ttl: std::time::Duration,
+    ) {
+        let now = std::time::Instant::now();
+        let mut by_addr = self.by_addr.lock().unwrap();
+        by_addr.retain(|_, c| c.expires > now);
+        let expires = now + ttl.max(CAPTURED_HOSTNAME_MIN_TTL);
+        by_addr.insert(addr, CapturedHostname { hostname, expires });
+    }
+
+    /// get returns the hostname `addr` was last returned for, unless it has expired.
+    pub fn get(&self, addr: &NetworkAddress) -> Option<NamespacedHostname> {
+        self.by_addr
+            .lock()
+            .unwrap()
+            .get(addr)
+            .filter(|c| c.expires > std::time::Instant::now())
+            .map(|c| c.hostname.clone())
+    }
+
+    /// remove_hostname forgets every address captured for `hostname`.
+    pub fn remove_hostname(&self, hostname: &NamespacedHostname) {
+        self.by_addr
+            .lock()
+            .unwrap()
+            .retain(|_, c| &c.hostname != hostname);
+    }
+}
+
 impl ProxyState {
     /// Find either a workload or service by the destination.
     pub fn find_destination(&self, dest: &Destination) -> Option<Address> {
@@ -246,38 +390,13 @@ impl ProxyState {
         network: Strng,
         source_workload: &Workload,
         addr: SocketAddr,
+        load: &dyn EndpointLoad,
     ) -> Option<Upstream> {
         if let Some(svc) = self
             .services
             .get_by_vip(&network_addr(network.clone(), addr.ip()))
         {
-            let Some(target_port) = svc.ports.get(&addr.port()) else {
-                debug!(
-                    "found VIP {}, but port {} was unknown",
-                    addr.ip(),
-                    addr.port()
-                );
-                return None;
-            };
-            // Randomly pick an upstream
-            // TODO: do this more efficiently, and not just randomly
-            let Some(ep) = self.load_balance(source_workload, &svc) else {
-                debug!("VIP {} has no healthy endpoints", addr);
-                return None;
-            };
-            let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
-                debug!("failed to fetch workload for {}", ep.workload_uid);
-                return None;
-            };
-            // If endpoint overrides the target port, use that instead
-            let target_port = ep.port.get(&addr.port()).unwrap_or(target_port);
-            let us = Upstream {
-                workload: wl,
-                port: *target_port,
-                sans: svc.subject_alt_names.clone(),
-                destination_service: Some(ServiceDescription::from(svc.as_ref())),
-            };
-            return Some(us);
+            return self.find_service_upstream(Some(source_workload), &svc, addr.port(), load);
         }
         if let Some(wl) = self
             .workloads
@@ -294,14 +413,122 @@ impl ProxyState {
         None
     }
 
-    fn load_balance<'a>(&self, src: &Workload, svc: &'a Service) -> Option<&'a Endpoint> {
-        match svc.load_balancer {
-            None => svc.endpoints.values().choose(&mut rand::thread_rng()),
-            Some(ref lb) => {
-                let ranks = svc
-                    .endpoints
-                    .iter()
-                    .filter_map(|(_, ep)| {
+    /// find_sni_upstream picks an endpoint of the service with the given hostname and port, for
+    /// traffic routed by SNI rather than by destination address. The source of such traffic is
+    /// generally unknown, so no locality preferences apply.
+    pub fn find_sni_upstream(
+        &self,
+        hostname: &Strng,
+        port: u16,
+        load: &dyn EndpointLoad,
+    ) -> Option<Upstream> {
+        let services = self.services.get_by_host(hostname)?;
+        // ServiceEntry allows the same hostname in several namespaces; take the first exposing
+        // the port.
+        let Some(svc) = services.iter().find(|svc| svc.ports.contains_key(&port)) else {
+            debug!("found service {hostname}, but port {port} was unknown");
+            return None;
+        };
+        self.find_service_upstream(None, svc, port, load)
+    }
+
+    /// find_hostname_ip resolves a hostname to an address on the given network: a VIP of a service
+    /// with the hostname or, failing that, an IP of a workload with it.
+    pub fn find_hostname_ip(&self, network: &Strng, hostname: &Strng) -> Option<IpAddr> {
+        let vip = self
+            .services
+            .get_by_host(hostname)
+            .into_iter()
+            .flatten()
+            .flat_map(|svc| svc.vips)
+            .find(|vip| &vip.network == network);
+        if let Some(vip) = vip {
+            return Some(vip.address);
+        }
+        self.workloads
+            .find_hostname(hostname)
+            .filter(|wl| &wl.network == network)
+            .and_then(|wl| wl.workload_ips.first().copied())
+    }
+
+    fn find_service_upstream(
+        &self,
+        source_workload: Option<&Workload>,
+        svc: &Service,
+        port: u16,
+        load: &dyn EndpointLoad,
+    ) -> Option<Upstream> {
+        let Some(target_port) = svc.ports.get(&port) else {
+            debug!(
+                "found service {}, but port {} was unknown",
+                svc.hostname, port
+            );
+            return None;
+        };
+        let Some(ep) = self.load_balance(source_workload, svc, load) else {
+            debug!("service {}:{} has no healthy endpoints", svc.hostname, port);
+            return None;
+        };
+        let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
+            debug!("failed to fetch workload for {}", ep.workload_uid);
+            return None;
+        };
+        // If endpoint overrides the target port, use that instead
+        let target_port = ep.port.get(&port).unwrap_or(target_port);
+        Some(Upstream {
+            workload: wl,
+            port: *target_port,
+            sans: svc.subject_alt_names.clone(),
+            destination_service: Some(ServiceDescription::from(svc)),
+        })
+    }
+
+    // load_balance picks an endpoint of the service. Locality preferences and client IP affinity
+    // are relative to the source workload, so they only apply when it is known.
+    fn load_balance<'a>(
+        &self,
+        src: Option<&Workload>,
+        svc: &'a Service,
+        load: &dyn EndpointLoad,
+    ) -> Option<&'a Endpoint> {
+        let endpoints = svc
+            .endpoints
+            .iter()
+            .filter(|(_, ep)| match load.failed_addresses() {
+                None => true,
+                Some(failed) => !self.endpoint_ips(ep).iter().any(|ip| failed.contains(ip)),
+            })
+            .collect::<Vec<_>>();
+        // Avoid ejected endpoints. If every endpoint is ejected, ignore ejections rather than
+        // failing the connection outright.
+        let ejected = load.ejected_addresses();
+        let endpoints = if ejected.is_empty() {
+            endpoints
+        } else {
+            let healthy = endpoints
+                .iter()
+                .filter(|(_, ep)| !self.endpoint_ips(ep).iter().any(|ip| ejected.contains(ip)))
+                .copied()
+                .collect::<Vec<_>>();
+            if healthy.is_empty() {
+                endpoints
+            } else {
+                healthy
+            }
+        };
+        let (candidates, strategy) = match (&svc.load_balancer, src) {
+            (None, _) => (endpoints, LoadBalancerStrategy::default()),
+            (Some(lb), None) => {
+                // Without a source, no endpoint can match the required locality
+                if lb.mode == LoadBalancerMode::Strict && !lb.routing_preferences.is_empty() {
+                    return None;
+                }
+                (endpoints, lb.strategy.clone())
+            }
+            (Some(lb), Some(src)) => {
+                let ranks = endpoints
+                    .into_iter()
+                    .filter_map(|(uid, ep)| {
                         let Some(wl) = self.workloads.find_uid(&ep.workload_uid) else {
                             debug!("failed to fetch workload for {}", ep.workload_uid);
                             return None;
@@ -337,18 +564,188 @@ impl ProxyState {
                         {
                             return None;
                         }
-                        Some((rank, ep))
+                        Some((rank, (uid, ep)))
                     })
                     .collect::<Vec<_>>();
                 let max = *ranks.iter().map(|(rank, _ep)| rank).max()?;
-                ranks
+                let candidates = ranks
                     .into_iter()
                     .filter(|(rank, _ep)| *rank == max)
                     .map(|(_, ep)| ep)
+                    .collect::<Vec<_>>();
+                (candidates, lb.strategy.clone())
+            }
+        };
+        if let (SessionAffinity::ClientIp, Some(src)) = (&svc.session_affinity, src) {
+            return consistent_hash(src, candidates);
+        }
+        self.pick_endpoint(svc, candidates, &strategy, load)
+    }
+
+    // pick_endpoint selects one of the candidate endpoints of a service, which are keyed by their
+    // endpoint UID, according to the load balancing strategy.
+    fn pick_endpoint<'a>(
+        &self,
+        svc: &Service,
+        mut candidates: Vec<(&Strng, &'a Endpoint)>,
+        strategy: &LoadBalancerStrategy,
+        load: &dyn EndpointLoad,
+    ) -> Option<&'a Endpoint> {
+        match strategy {
+            LoadBalancerStrategy::Random => candidates
+                .into_iter()
+                .map(|(_, ep)| ep)
+                .choose(&mut rand::thread_rng()),
+            LoadBalancerStrategy::RoundRobin => {
+                if candidates.is_empty() {
+                    return None;
+                }
+                // Endpoints are stored in a map, so order them to keep positions stable.
+                candidates.sort_unstable_by_key(|(uid, _)| *uid);
+                let next = self.services.next_round_robin(&svc.namespaced_hostname());
+                candidates.get(next % candidates.len()).map(|(_, ep)| *ep)
+            }
+            LoadBalancerStrategy::LeastConnections => {
+                let connections = |ep: &Endpoint| -> usize {
+                    self.endpoint_ips(ep)
+                        .into_iter()
+                        .map(|ip| load.active_connections(ip))
+                        .sum()
+                };
+                let counts = candidates
+                    .into_iter()
+                    .map(|(_, ep)| (connections(ep), ep))
+                    .collect::<Vec<_>>();
+                let min = counts.iter().map(|(count, _ep)| *count).min()?;
+                counts
+                    .into_iter()
+                    .filter(|(count, _ep)| *count == min)
+                    .map(|(_, ep)| ep)
                     .choose(&mut rand::thread_rng())
             }
         }
     }
+
+    // endpoint_ips returns the addresses an endpoint can be reached at.
+    fn endpoint_ips(&self, ep: &Endpoint) -> Vec<IpAddr> {
+        match &ep.address {
+            Some(addr) => vec![addr.address],
+            None => self
+                .workloads
+                .find_uid(&ep.workload_uid)
+                .map(|wl| wl.workload_ips)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// consistent_hash picks the candidate endpoint with the highest hash for the source workload, using
+// rendezvous hashing. A source keeps landing on the same endpoint while it is available, and
+// changes to the candidates only move the sources that were mapped to the endpoints that changed.
+fn consistent_hash<'a>(
+    src: &Workload,
+    candidates: Vec<(&Strng, &'a Endpoint)>,
+) -> Option<&'a Endpoint> {
+    candidates
+        .into_iter()
+        .max_by_key(|(uid, _)| {
+            let mut hasher = DefaultHasher::new();
+            match src.workload_ips.first() {
+                Some(ip) => ip.hash(&mut hasher),
+                None => src.uid.hash(&mut hasher),
+            }
+            uid.hash(&mut hasher);
+            hasher.finish()
+        })
+        .map(|(_, ep)| ep)
+}
+
+/// RbacDecision is the result of evaluating authorization policies against a connection.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RbacDecision {
+    /// Whether the enforced policies allow the connection.
+    pub allowed: bool,
+    /// The policy that decided the connection: the DENY policy that rejected it, or the ALLOW
+    /// policy that accepted it. None if the decision was not made by a single policy, for example
+    /// because no ALLOW policy matched.
+    pub policy: Option<RbacPolicyMatch>,
+    /// Whether the dry-run policies would have denied the connection, had they been enforced.
+    pub shadow_denied: bool,
+}
+
+/// RbacPolicyMatch identifies a policy, and the rule within it, that matched a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RbacPolicyMatch {
+    pub policy: Strng,
+    pub rule: usize,
+}
+
+impl fmt::Display for RbacPolicyMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (rule {})", self.policy, self.rule)
+    }
+}
+
+// evaluate_policies applies the allow and deny logic to a set of policies, returning whether the
+// connection is allowed and the policy that decided it, if any.
+fn evaluate_policies(
+    conn: &rbac::Connection,
+    src_aliases: &[Identity],
+    policies: Vec<&Authorization>,
+) -> (bool, Option<RbacPolicyMatch>) {
+    let (allow, deny): (Vec<_>, Vec<_>) = policies
+        .into_iter()
+        .partition(|p| p.action == rbac::RbacAction::Allow);
+
+    trace!(
+        allow = allow.len(),
+        deny = deny.len(),
+        "checking connection"
+    );
+
+    // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
+
+    // "If there are any DENY policies that match the request, deny the request."
+    for pol in deny.iter() {
+        if let Some(rule) = pol.matching_rule(conn, src_aliases) {
+            debug!(policy = pol.to_key().as_str(), rule, "deny policy match");
+            return (
+                false,
+                Some(RbacPolicyMatch {
+                    policy: pol.to_key(),
+                    rule,
+                }),
+            );
+        } else {
+            trace!(policy = pol.to_key().as_str(), "deny policy does not match");
+        }
+    }
+    // "If there are no ALLOW policies for the workload, allow the request."
+    if allow.is_empty() {
+        debug!("no allow policies, allow");
+        return (true, None);
+    }
+    // "If any of the ALLOW policies match the request, allow the request."
+    for pol in allow.iter() {
+        if let Some(rule) = pol.matching_rule(conn, src_aliases) {
+            debug!(policy = pol.to_key().as_str(), rule, "allow policy match");
+            return (
+                true,
+                Some(RbacPolicyMatch {
+                    policy: pol.to_key(),
+                    rule,
+                }),
+            );
+        } else {
+            trace!(
+                policy = pol.to_key().as_str(),
+                "allow policy does not match"
+            );
+        }
+    }
+    // "Deny the request."
+    debug!("no allow policies matched");
+    (false, None)
 }
 
 /// Wrapper around [ProxyState] that provides additional methods for requesting information
@@ -356,10 +753,14 @@ impl ProxyState {
 #[derive(serde::Serialize, Debug, Clone)]
 pub struct DemandProxyState {
     #[serde(flatten)]
-    state: Arc<RwLock<ProxyState>>,
+    state: ProxyStateCell,
 
     /// If present, used to request on-demand updates for workloads.
-    #[serde(skip_serializing)]
+    /// Serialized as the current on-demand subscriptions.
+    #[serde(
+        rename = "onDemandSubscriptions",
+        skip_serializing_if = "Option::is_none"
+    )]
     demand: Option<Demander>,
 
     #[serde(skip_serializing)]
@@ -367,11 +768,19 @@ pub struct DemandProxyState {
 
     #[serde(skip_serializing)]
     dns_resolver_opts: ResolverOpts,
+
+    /// Consulted in order for workloads that are not known locally or from XDS on-demand.
+    #[serde(skip_serializing)]
+    fetchers: Vec<Arc<dyn WorkloadFetcher>>,
+
+    /// Trust domains equivalent to the destination's, when matching policy principals.
+    #[serde(skip_serializing)]
+    trust_domain_aliases: Vec<Strng>,
 }
 
 impl DemandProxyState {
     pub fn new(
-        state: Arc<RwLock<ProxyState>>,
+        state: ProxyStateCell,
         demand: Option<Demander>,
         dns_resolver_cfg: ResolverConfig,
         dns_resolver_opts: ResolverOpts,
@@ -381,36 +790,69 @@ impl DemandProxyState {
             demand,
             dns_resolver_cfg,
             dns_resolver_opts,
+            fetchers: Vec::new(),
+            trust_domain_aliases: Vec::new(),
         }
     }
 
-    pub fn read(&self) -> RwLockReadGuard<'_, ProxyState> {
-        self.state.read().unwrap()
+    /// with_fetcher adds a fallback source of workloads, consulted after any added before it.
+    pub fn with_fetcher(mut self, fetcher: Arc<dyn WorkloadFetcher>) -> Self {
+        self.fetchers.push(fetcher);
+        self
+    }
+
+    /// with_trust_domain_aliases sets the trust domains treated as equivalent to a destination's
+    /// own when evaluating its authorization policies.
+    pub fn with_trust_domain_aliases(mut self, aliases: Vec<Strng>) -> Self {
+        self.trust_domain_aliases = aliases;
+        self
+    }
+
+    /// read returns the current snapshot of the state.
+    pub fn read(&self) -> Arc<ProxyState> {
+        self.state.read()
     }
 
     pub async fn assert_rbac(&self, ctx: &ProxyRbacContext) -> bool {
+        self.evaluate_rbac(ctx).await.allowed
+    }
+
+    /// evaluate_rbac checks a connection against the authorization policies of its destination.
+    /// Enforced policies decide whether the connection is allowed; dry-run policies are evaluated
+    /// separately, as if they were the only policies, and only reported.
+    pub async fn evaluate_rbac(&self, ctx: &ProxyRbacContext) -> RbacDecision {
         let nw_addr = network_addr(ctx.conn.dst_network.clone(), ctx.conn.dst.ip());
         let Some(wl) = self.fetch_workload(&nw_addr).await else {
             debug!("destination workload not found {}", nw_addr);
-            return false;
+            return RbacDecision::default();
         };
         if let Some(ref wl_info) = ctx.dest_workload_info {
             // make sure that the workload we fetched matches the workload info we got over ZDS.
             if !wl_info.matches(&wl) {
                 error!("workload does not match proxy workload uid. this is probably a bug. please report an issue");
-                return false;
+                return RbacDecision::default();
             }
         }
         let conn = &ctx.conn;
-        let state = self.state.read().unwrap();
+        // Principals may name the source in any trust domain equivalent to the destination's.
+        let src_aliases = match &conn.src_identity {
+            Some(id) if !self.trust_domain_aliases.is_empty() => id
+                .with_trust_domain_aliases(&wl.trust_domain, &self.trust_domain_aliases)
+                .into_iter()
+                // The first identity is the source's own
+                .skip(1)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let state = self.state.read();
 
         // We can get policies from namespace, global, and workload...
         let ns = state.policies.get_by_namespace(&wl.namespace);
         let global = state.policies.get_by_namespace(&crate::strng::EMPTY);
         let workload = wl.authorization_policies.iter();
 
-        // Aggregate all of them based on type
-        let (allow, deny): (Vec<_>, Vec<_>) = ns
+        // Aggregate all of them based on whether they are enforced
+        let (dry_run, enforced): (Vec<_>, Vec<_>) = ns
             .iter()
             .chain(global.iter())
             .chain(workload)
@@ -423,45 +865,20 @@ impl DemandProxyState {
                 }
                 pol
             })
-            .partition(|p| p.action == rbac::RbacAction::Allow);
-
-        trace!(
-            allow = allow.len(),
-            deny = deny.len(),
-            "checking connection"
-        );
-
-        // Allow and deny logic follows https://istio.io/latest/docs/reference/config/security/authorization-policy/
+            .partition(|p| p.dry_run);
 
-        // "If there are any DENY policies that match the request, deny the request."
-        for pol in deny.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key().as_str(), "deny policy match");
-                return false;
-            } else {
-                trace!(policy = pol.to_key().as_str(), "deny policy does not match");
-            }
-        }
-        // "If there are no ALLOW policies for the workload, allow the request."
-        if allow.is_empty() {
-            debug!("no allow policies, allow");
-            return true;
-        }
-        // "If any of the ALLOW policies match the request, allow the request."
-        for pol in allow.iter() {
-            if pol.matches(conn) {
-                debug!(policy = pol.to_key().as_str(), "allow policy match");
-                return true;
-            } else {
-                trace!(
-                    policy = pol.to_key().as_str(),
-                    "allow policy does not match"
-                );
-            }
+        let (allowed, policy) = evaluate_policies(conn, &src_aliases, enforced);
+        let shadow_denied = if dry_run.is_empty() {
+            false
+        } else {
+            let _span = trace_span!("dry_run").entered();
+            !evaluate_policies(conn, &src_aliases, dry_run).0
+        };
+        RbacDecision {
+            allowed,
+            policy,
+            shadow_denied,
         }
-        // "Deny the request."
-        debug!("no allow policies matched");
-        false
     }
 
     // this should only be called once per request (for the workload itself and potentially its waypoint)
@@ -503,7 +920,12 @@ impl DemandProxyState {
         let hostname = workload.hostname.to_owned();
         metrics.as_ref().on_demand_dns.get_or_create(&labels).inc();
         let rdns = match self.get_ips_for_hostname(&workload.hostname) {
-            Some(r) => r,
+            Some(r) => {
+                if r.needs_refresh() {
+                    self.refresh_on_demand_dns(workload);
+                }
+                r
+            }
             None => {
                 metrics
                     .as_ref()
@@ -535,6 +957,26 @@ impl DemandProxyState {
         Ok(*ip)
     }
 
+    // refresh_on_demand_dns resolves the workload's hostname again in the background, unless a
+    // refresh is already in progress.
+    fn refresh_on_demand_dns(&self, workload: &Workload) {
+        let hostname = workload.hostname.clone();
+        if !self
+            .state
+            .read()
+            .resolved_dns
+            .start_refresh(hostname.clone())
+        {
+            return;
+        }
+        let state = self.clone();
+        let workload = workload.clone();
+        tokio::spawn(async move {
+            Self::resolve_on_demand_dns(&state, &workload).await;
+            state.state.read().resolved_dns.finish_refresh(&hostname);
+        });
+    }
+
     async fn resolve_on_demand_dns(state: &DemandProxyState, workload: &Workload) {
         let workload_uid = workload.uid.clone();
         let hostname = workload.hostname.clone();
@@ -596,26 +1038,28 @@ impl DemandProxyState {
     }
 
     pub fn set_ips_for_hostname(&self, hostname: Strng, rdns: ResolvedDns) {
-        self.state
-            .write()
-            .unwrap()
-            .resolved_dns
-            .by_hostname
-            .insert(hostname, rdns);
+        self.state.read().resolved_dns.insert(hostname, rdns);
+    }
+
+    /// evict_dns drops the DNS state cached for the workload or service the event removed, rather
+    /// than waiting for it to expire.
+    pub fn evict_dns(&self, event: &StateEvent) {
+        match event {
+            StateEvent::WorkloadRemoved(w) if !w.hostname.is_empty() => {
+                self.state.read().resolved_dns.remove(&w.hostname);
+            }
+            StateEvent::ServiceChanged(name) => {
+                let state = self.state.read();
+                if state.services.get_by_namespaced_host(name).is_none() {
+                    state.captured_hostnames.remove_hostname(name);
+                }
+            }
+            _ => {}
+        }
     }
 
     pub fn get_ips_for_hostname(&self, hostname: &Strng) -> Option<ResolvedDns> {
-        self.state
-            .read()
-            .unwrap()
-            .resolved_dns
-            .by_hostname
-            .get(hostname)
-            .filter(|rdns| {
-                rdns.initial_query.is_some()
-                    && rdns.initial_query.unwrap().elapsed() < rdns.dns_refresh_rate
-            })
-            .cloned()
+        self.state.read().resolved_dns.get(hostname)
     }
 
     pub async fn fetch_workload_services(
@@ -625,7 +1069,7 @@ impl DemandProxyState {
         // Wait for it on-demand, *if* needed
         debug!(%addr, "fetch workload and service");
         let fetch = |addr: &NetworkAddress| {
-            let state = self.state.read().unwrap();
+            let state = self.state.read();
             state.workloads.find_address(addr).map(|wl| {
                 let svc = state.services.get_by_workload(&wl);
                 (wl, svc)
@@ -634,39 +1078,60 @@ impl DemandProxyState {
         if let Some(wl) = fetch(addr) {
             return Some(wl);
         }
-        if !self.supports_on_demand() {
-            return None;
+        if self.supports_on_demand() {
+            self.fetch_on_demand(addr.to_string().into()).await;
+            if let Some(wl) = fetch(addr) {
+                return Some(wl);
+            }
         }
-        self.fetch_on_demand(addr.to_string().into()).await;
-        fetch(addr)
+        // Services are only known from XDS
+        self.fetch_fallback(addr).await.map(|wl| (wl, Vec::new()))
     }
 
     // only support workload
     pub async fn fetch_workload(&self, addr: &NetworkAddress) -> Option<Workload> {
         // Wait for it on-demand, *if* needed
         debug!(%addr, "fetch workload");
-        if let Some(wl) = self.state.read().unwrap().workloads.find_address(addr) {
+        if let Some(wl) = self.state.read().workloads.find_address(addr) {
             return Some(wl);
         }
-        if !self.supports_on_demand() {
-            return None;
+        if self.supports_on_demand() {
+            self.fetch_on_demand(addr.to_string().into()).await;
+            if let Some(wl) = self.state.read().workloads.find_address(addr) {
+                return Some(wl);
+            }
         }
-        self.fetch_on_demand(addr.to_string().into()).await;
-        self.state.read().unwrap().workloads.find_address(addr)
+        self.fetch_fallback(addr).await
     }
 
     // only support workload
     pub async fn fetch_workload_by_uid(&self, uid: &Strng) -> Option<Workload> {
         // Wait for it on-demand, *if* needed
         debug!(%uid, "fetch workload");
-        if let Some(wl) = self.state.read().unwrap().workloads.find_uid(uid) {
+        if let Some(wl) = self.state.read().workloads.find_uid(uid) {
             return Some(wl);
         }
         if !self.supports_on_demand() {
             return None;
         }
         self.fetch_on_demand(uid.clone()).await;
-        self.state.read().unwrap().workloads.find_uid(uid)
+        self.state.read().workloads.find_uid(uid)
+    }
+
+    /// find_workloads_by_service_account returns the known workloads running as the given service
+    /// account. Workloads are not fetched on-demand, as XDS cannot be queried by service account.
+    pub fn find_workloads_by_service_account(
+        &self,
+        namespace: &Strng,
+        service_account: &Strng,
+    ) -> Vec<Workload> {
+        self.state
+            .read()
+            .workloads
+            .find_service_account(namespace, service_account)
+            .iter()
+            .map(|wl| wl.as_ref().clone())
+            .collect()
     }
 
     pub async fn fetch_upstream(
@@ -674,13 +1139,13 @@ impl DemandProxyState {
         network: Strng,
         source_workload: &Workload,
         addr: SocketAddr,
+        load: &dyn EndpointLoad,
     ) -> Option<Upstream> {
         self.fetch_address(&network_addr(network.clone(), addr.ip()))
             .await;
         self.state
             .read()
-            .unwrap()
-            .find_upstream(network, source_workload, addr)
+            .find_upstream(network, source_workload, addr, load)
     }
 
     pub async fn fetch_waypoint(
@@ -688,23 +1153,25 @@ impl DemandProxyState {
         wl: &Workload,
         source_workload: &Workload,
         workload_ip: IpAddr,
+        load: &dyn EndpointLoad,
     ) -> Result<Option<Upstream>, WaypointError> {
         let Some(gw_address) = &wl.waypoint else {
             return Ok(None);
         };
         // Even in this case, we are picking a single upstream pod and deciding if it has a remote proxy.
         // Typically this is all or nothing, but if not we should probably send to remote proxy if *any* upstream has one.
-        let wp_nw_addr = match &gw_address.destination {
-            Destination::Address(ip) => ip,
-            Destination::Hostname(_) => {
-                return Err(WaypointError::UnsupportedFeature(
-                    "hostname lookup not supported yet".to_string(),
-                ));
-            }
+        let Some(wp_nw_addr) = self.fetch_gateway_address(gw_address).await else {
+            debug!(%wl.name, "waypoint hostname not found");
+            return Err(WaypointError::FindWaypointError(wl.name.to_string()));
         };
         let wp_socket_addr = SocketAddr::new(wp_nw_addr.address, gw_address.hbone_mtls_port);
         match self
-            .fetch_upstream(wp_nw_addr.network.clone(), source_workload, wp_socket_addr)
+            .fetch_upstream(
+                wp_nw_addr.network.clone(),
+                source_workload,
+                wp_socket_addr,
+                load,
+            )
             .await
         {
             Some(mut upstream) => {
@@ -724,6 +1191,25 @@ impl DemandProxyState {
         }
     }
 
+    /// Resolves the address a gateway, such as a waypoint, is reached at. A gateway referenced by
+    /// hostname is reached at the VIP of the service with that hostname, or at the address of the
+    /// workload with that hostname.
+    pub async fn fetch_gateway_address(
+        &self,
+        gw_address: &GatewayAddress,
+    ) -> Option<NetworkAddress> {
+        match &gw_address.destination {
+            Destination::Address(addr) => Some(addr.clone()),
+            Destination::Hostname(hostname) => match self.fetch_hostname(hostname).await? {
+                Address::Service(svc) => svc.vips.first().cloned(),
+                Address::Workload(wl) => wl
+                    .workload_ips
+                    .first()
+                    .map(|ip| network_addr(wl.network.clone(), *ip)),
+            },
+        }
+    }
+
     /// Looks for either a workload or service by the destination. If not found locally,
     /// attempts to fetch on-demand.
     pub async fn fetch_destination(&self, dest: &Destination) -> Option<Address> {
@@ -738,15 +1224,19 @@ impl DemandProxyState {
     pub async fn fetch_address(&self, network_addr: &NetworkAddress) -> Option<Address> {
         // Wait for it on-demand, *if* needed
         debug!(%network_addr.address, "fetch address");
-        if let Some(address) = self.state.read().unwrap().find_address(network_addr) {
+        if let Some(address) = self.state.read().find_address(network_addr) {
             return Some(address);
         }
-        if !self.supports_on_demand() {
-            return None;
+        if self.supports_on_demand() {
+            // if both cache not found, start on demand fetch
+            self.fetch_on_demand(network_addr.to_string().into()).await;
+            if let Some(address) = self.state.read().find_address(network_addr) {
+                return Some(address);
+            }
         }
-        // if both cache not found, start on demand fetch
-        self.fetch_on_demand(network_addr.to_string().into()).await;
-        self.state.read().unwrap().find_address(network_addr)
+        self.fetch_fallback(network_addr)
+            .await
+            .map(|wl| Address::Workload(Arc::new(wl)))
     }
 
     /// Looks for the given hostname to find either a workload or service by IP. If not found
@@ -754,7 +1244,7 @@ impl DemandProxyState {
     pub async fn fetch_hostname(&self, hostname: &NamespacedHostname) -> Option<Address> {
         // Wait for it on-demand, *if* needed
         debug!(%hostname, "fetch hostname");
-        if let Some(address) = self.state.read().unwrap().find_hostname(hostname) {
+        if let Some(address) = self.state.read().find_hostname(hostname) {
             return Some(address);
         }
         if !self.supports_on_demand() {
@@ -762,7 +1252,56 @@ impl DemandProxyState {
         }
         // if both cache not found, start on demand fetch
         self.fetch_on_demand(hostname.to_string().into()).await;
-        self.state.read().unwrap().find_hostname(hostname)
+        self.state.read().find_hostname(hostname)
+    }
+
+    /// fetch_fallback looks up a workload unknown to XDS in the fallback fetchers, returning the
+    /// first match.
+    async fn fetch_fallback(&self, addr: &NetworkAddress) -> Option<Workload> {
+        for fetcher in &self.fetchers {
+            if let Some(wl) = fetcher.fetch(addr).await {
+                debug!(%addr, ?fetcher, "found workload in fallback");
+                return Some(wl);
+            }
+        }
+        None
+    }
+
+    /// capture_hostname records that upstream DNS returned `addr` for the service `hostname`.
+    pub fn capture_hostname(
+        &self,
+        addr: NetworkAddress,
+        hostname: NamespacedHostname,
+        ttl: std::time::Duration,
+    ) {
+        trace!(%addr, %hostname, "captured service hostname");
+        self.state
+            .read()
+            .captured_hostnames
+            .insert(addr, hostname, ttl);
+    }
+
+    /// find_captured_service returns the service whose hostname upstream DNS last returned `addr`
+    /// for, if any.
+    pub fn find_captured_service(&self, addr: &NetworkAddress) -> Option<Arc<Service>> {
+        let state = self.state.read();
+        let hostname = state.captured_hostnames.get(addr)?;
+        state.services.get_by_namespaced_host(&hostname)
+    }
+
+    /// find_sni_upstream picks an endpoint of the service with the given hostname and port, for
+    /// traffic routed by SNI.
+    pub fn find_sni_upstream(
+        &self,
+        hostname: &Strng,
+        port: u16,
+        load: &dyn EndpointLoad,
+    ) -> Option<Upstream> {
+        self.state.read().find_sni_upstream(hostname, port, load)
+    }
+
+    pub fn find_hostname_ip(&self, network: &Strng, hostname: &Strng) -> Option<IpAddr> {
+        self.state.read().find_hostname_ip(network, hostname)
     }
 
     pub fn supports_on_demand(&self) -> bool {
@@ -811,6 +1350,10 @@ pub struct ProxyStateManager {
 
     #[serde(skip_serializing)]
     xds_client: Option<AdsClient>,
+
+    // Entries restored from a state snapshot, to remove once XDS synced if it did not send them.
+    #[serde(skip_serializing)]
+    stale: Option<(StaleEntries, ProxyStateUpdater)>,
 }
 
 impl ProxyStateManager {
@@ -818,19 +1361,42 @@ impl ProxyStateManager {
         config: Arc<config::Config>,
         metrics: Metrics,
         awaiting_ready: tokio::sync::watch::Sender<()>,
+        block_ready: &readiness::BlockReady,
         cert_manager: Arc<SecretManager>,
     ) -> anyhow::Result<ProxyStateManager> {
         let cert_fetcher = cert_fetcher::new(&config, cert_manager);
-        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
+        let mut stale = None;
         let xds_client = if config.xds_address.is_some() {
-            let updater = ProxyStateUpdater::new(state.clone(), cert_fetcher.clone());
+            let updater = ProxyStateUpdater::new(state.clone(), cert_fetcher.clone()).with_limits(
+                xds::StateLimits::from(config.as_ref()),
+                metrics.state_limit_exceeded.clone(),
+            );
+            let events = state.read().events.subscribe();
+            tokio::spawn(cert_fetcher::prefetch_on_events(
+                cert_fetcher.clone(),
+                events,
+            ));
+            if let Some(path) = &config.state_snapshot_path {
+                match Snapshot::load(path).await {
+                    Ok(Some(snapshot)) => {
+                        let entries = snapshot.restore(&mut state.write(), cert_fetcher.as_ref());
+                        stale = Some((entries, updater.clone()));
+                    }
+                    Ok(None) => {}
+                    // A bad snapshot only costs us the head start; XDS will fill the state.
+                    Err(e) => warn!("failed to load state snapshot {}: {e:#}", path.display()),
+                }
+            }
             let tls_client_fetcher = Box::new(tls::ControlPlaneAuthentication::RootCert(
                 config.xds_root_cert.clone(),
+                config.tls_settings.clone(),
             ));
             Some(
                 xds::Config::new(config.clone(), tls_client_fetcher)
                     .with_watched_handler::<XdsAddress>(xds::ADDRESS_TYPE, updater.clone())
                     .with_watched_handler::<XdsAuthorization>(xds::AUTHORIZATION_TYPE, updater)
+                    .with_readiness(block_ready)
                     .build(metrics, awaiting_ready),
             )
         } else {
@@ -845,14 +1411,20 @@ impl ProxyStateManager {
             local_client.run().await?;
         }
         let demand = xds_client.as_ref().and_then(AdsClient::demander);
+        let mut state = DemandProxyState::new(
+            state,
+            demand,
+            config.dns_resolver_cfg.clone(),
+            config.dns_resolver_opts.clone(),
+        )
+        .with_trust_domain_aliases(config.trust_domain_aliases.clone());
+        if let Some(cfg) = &config.workload_fallback_config {
+            state = state.with_fetcher(Arc::new(FileWorkloadFetcher::new(cfg.clone())));
+        }
         Ok(ProxyStateManager {
             xds_client,
-            state: DemandProxyState {
-                state,
-                demand,
-                dns_resolver_cfg: config.dns_resolver_cfg.clone(),
-                dns_resolver_opts: config.dns_resolver_opts.clone(),
-            },
+            state,
+            stale,
         })
     }
 
@@ -860,9 +1432,42 @@ impl ProxyStateManager {
         self.state.clone()
     }
 
+    /// xds_rejects returns the XDS resources rejected, if XDS is used.
+    pub fn xds_rejects(&self) -> Option<xds::XdsRejects> {
+        self.xds_client.as_ref().map(AdsClient::rejects)
+    }
+
+    /// xds_versions returns the versions of the XDS resources accepted, if XDS is used.
+    pub fn xds_versions(&self) -> Option<xds::XdsVersions> {
+        self.xds_client.as_ref().map(AdsClient::versions)
+    }
+
+    /// with_liveness registers a heartbeat for the XDS client, if there is one.
+    pub fn with_liveness(
+        mut self,
+        live: &readiness::Liveness,
+        threshold: std::time::Duration,
+    ) -> Self {
+        self.xds_client = self
+            .xds_client
+            .map(|c| c.with_heartbeat(live.register_heartbeat("xds client", threshold)));
+        self
+    }
+
     pub async fn run(self) -> anyhow::Result<()> {
         match self.xds_client {
-            Some(xds) => xds.run().await.map_err(|e| anyhow::anyhow!(e)),
+            Some(xds) => {
+                if let Some((stale, updater)) = self.stale {
+                    let mut synced = xds.synced();
+                    let versions = xds.versions();
+                    tokio::spawn(async move {
+                        if synced.wait_for(|synced| *synced).await.is_ok() {
+                            stale.prune(&updater, &versions);
+                        }
+                    });
+                }
+                xds.run().await.map_err(|e| anyhow::anyhow!(e))
+            }
             None => Ok(()),
         }
     }
@@ -870,6 +1475,7 @@ impl ProxyStateManager {
 
 #[cfg(test)]
 mod tests {
+    use crate::proxy::connection_manager::ConnectionManager;
     use crate::state::service::LoadBalancer;
     use crate::state::workload::Locality;
     use std::{net::Ipv4Addr, net::SocketAddrV4, time::Duration};
@@ -878,6 +1484,66 @@ mod tests {
     use crate::test_helpers::TEST_SERVICE_NAMESPACE;
     use crate::{strng, test_helpers};
 
+    #[derive(Debug)]
+    struct MockFetcher(Option<Workload>);
+
+    #[async_trait::async_trait]
+    impl WorkloadFetcher for MockFetcher {
+        async fn fetch(&self, addr: &NetworkAddress) -> Option<Workload> {
+            self.0
+                .clone()
+                .filter(|wl| wl.workload_ips.contains(&addr.address))
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_fetchers() {
+        let mut known = test_helpers::test_default_workload();
+        known.name = "known".into();
+        let mut fallback = test_helpers::test_default_workload();
+        fallback.name = "fallback".into();
+        fallback.workload_ips = vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))];
+
+        let mut state = ProxyState::default();
+        state.workloads.insert(Arc::new(known.clone()), true);
+        let mock_proxy_state = DemandProxyState::new(
+            ProxyStateCell::new(state),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        )
+        .with_fetcher(Arc::new(MockFetcher(None)))
+        .with_fetcher(Arc::new(MockFetcher(Some(fallback.clone()))));
+
+        let addr = |ip: Ipv4Addr| NetworkAddress {
+            network: strng::EMPTY,
+            address: IpAddr::V4(ip),
+        };
+        // Workloads known from XDS take precedence
+        assert_eq!(
+            mock_proxy_state
+                .fetch_workload(&addr(Ipv4Addr::LOCALHOST))
+                .await,
+            Some(known)
+        );
+        assert_eq!(
+            mock_proxy_state
+                .fetch_workload(&addr(Ipv4Addr::new(10, 0, 0, 1)))
+                .await,
+            Some(fallback.clone())
+        );
+        assert_eq!(
+            mock_proxy_state
+                .fetch_address(&addr(Ipv4Addr::new(10, 0, 0, 1)))
+                .await,
+            Some(Address::Workload(Arc::new(fallback)))
+        );
+        assert!(mock_proxy_state
+            .fetch_workload_services(&addr(Ipv4Addr::new(10, 0, 0, 2)))
+            .await
+            .is_none());
+    }
+
     #[tokio::test]
     async fn lookup_address() {
         let mut state = ProxyState::default();
@@ -887,7 +1553,7 @@ mod tests {
         state.services.insert(test_helpers::mock_default_service());
 
         let mock_proxy_state = DemandProxyState::new(
-            Arc::new(RwLock::new(state)),
+            ProxyStateCell::new(state),
             None,
             ResolverConfig::default(),
             ResolverOpts::default(),
@@ -960,7 +1626,7 @@ mod tests {
         state.workloads.insert(Arc::new(wl), true);
 
         let mock_proxy_state = DemandProxyState::new(
-            Arc::new(RwLock::new(state)),
+            ProxyStateCell::new(state),
             None,
             ResolverConfig::default(),
             ResolverOpts::default(),
@@ -1017,6 +1683,202 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn evaluate_rbac() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        let deny_ports = |name: &str, ports: &[u16], dry_run: bool| rbac::Authorization {
+            name: name.into(),
+            namespace: "default".into(),
+            scope: rbac::RbacScope::Global,
+            action: rbac::RbacAction::Deny,
+            rules: ports
+                .iter()
+                .map(|port| {
+                    vec![vec![rbac::RbacMatch {
+                        destination_ports: vec![*port],
+                        ..Default::default()
+                    }]]
+                })
+                .collect(),
+            dry_run,
+        };
+        state
+            .policies
+            .insert(deny_ports("deny-7070", &[80, 7070], false));
+        state
+            .policies
+            .insert(deny_ports("deny-8080", &[8080], true));
+        state
+            .policies
+            .insert(deny_ports("deny-9090", &[9090], true));
+
+        let mock_proxy_state = DemandProxyState::new(
+            ProxyStateCell::new(state),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        let ctx = |port| crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: None,
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".into(),
+                dst: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), port),
+            },
+            dest_workload_info: None,
+        };
+
+        // A matching dry-run DENY policy is reported, but the connection is still allowed
+        assert_eq!(
+            mock_proxy_state.evaluate_rbac(&ctx(8080)).await,
+            RbacDecision {
+                allowed: true,
+                policy: None,
+                shadow_denied: true,
+            }
+        );
+        assert_eq!(
+            mock_proxy_state.evaluate_rbac(&ctx(8081)).await,
+            RbacDecision {
+                allowed: true,
+                policy: None,
+                shadow_denied: false,
+            }
+        );
+
+        // Denials report the policy and rule responsible
+        assert_eq!(
+            mock_proxy_state.evaluate_rbac(&ctx(7070)).await,
+            RbacDecision {
+                allowed: false,
+                policy: Some(RbacPolicyMatch {
+                    policy: "default/deny-7070".into(),
+                    rule: 1,
+                }),
+                shadow_denied: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn evaluate_rbac_trust_domain_aliases() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            workload_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2))],
+            trust_domain: "cluster.local".into(),
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        state.policies.insert(rbac::Authorization {
+            name: "deny-old".into(),
+            namespace: "default".into(),
+            scope: rbac::RbacScope::Global,
+            action: rbac::RbacAction::Deny,
+            rules: vec![vec![vec![rbac::RbacMatch {
+                principals: vec![rbac::StringMatch::Exact(
+                    "old.td/ns/default/sa/client".into(),
+                )],
+                ..Default::default()
+            }]]],
+            dry_run: false,
+        });
+        let state = ProxyStateCell::new(state);
+        let proxy_state = |aliases: Vec<Strng>| {
+            DemandProxyState::new(
+                state.clone(),
+                None,
+                ResolverConfig::default(),
+                ResolverOpts::default(),
+            )
+            .with_trust_domain_aliases(aliases)
+        };
+        let ctx = |trust_domain: &str| crate::state::ProxyRbacContext {
+            conn: rbac::Connection {
+                src_identity: Some(Identity::Spiffe {
+                    trust_domain: trust_domain.into(),
+                    namespace: "default".into(),
+                    service_account: "client".into(),
+                }),
+                src: "192.168.0.1:1234".parse().unwrap(),
+                dst_network: "".into(),
+                dst: "192.168.0.2:80".parse().unwrap(),
+            },
+            dest_workload_info: None,
+        };
+
+        assert!(proxy_state(vec![]).assert_rbac(&ctx("cluster.local")).await);
+        assert!(!proxy_state(vec![]).assert_rbac(&ctx("old.td")).await);
+        // Once old.td is an alias, the policy also applies to the source in our trust domain
+        let aliased = proxy_state(vec!["old.td".into()]);
+        assert!(!aliased.assert_rbac(&ctx("cluster.local")).await);
+        assert!(!aliased.assert_rbac(&ctx("old.td")).await);
+        assert!(aliased.assert_rbac(&ctx("other.td")).await);
+    }
+
+    #[test]
+    fn resolved_dns_needs_refresh() {
+        let rdns = |ttl| ResolvedDns {
+            initial_query: Some(std::time::Instant::now() - Duration::from_secs(10)),
+            dns_refresh_rate: Duration::from_secs(ttl),
+            ..Default::default()
+        };
+        assert!(rdns(12).needs_refresh());
+        assert!(!rdns(20).needs_refresh());
+        assert!(!ResolvedDns::default().needs_refresh());
+    }
+
+    #[test]
+    fn evict_dns() {
+        let wl = Workload {
+            hostname: "example.com".into(),
+            ..test_helpers::test_default_workload()
+        };
+        let svc = NamespacedHostname {
+            namespace: "ns".into(),
+            hostname: "svc.example.com".into(),
+        };
+        let addr = NetworkAddress {
+            network: "".into(),
+            address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        };
+        let state = DemandProxyState::new(
+            ProxyStateCell::default(),
+            None,
+            ResolverConfig::default(),
+            ResolverOpts::default(),
+        );
+        state.set_ips_for_hostname(wl.hostname.clone(), ResolvedDns::default());
+        state.capture_hostname(addr.clone(), svc.clone(), Duration::from_secs(1));
+
+        // Events for other changes leave the cache alone.
+        state.evict_dns(&StateEvent::WorkloadAdded(Arc::new(wl.clone())));
+        state.evict_dns(&StateEvent::PolicyChanged("ns/policy".into()));
+        assert!(state
+            .read()
+            .resolved_dns
+            .by_hostname
+            .read()
+            .unwrap()
+            .contains_key(&wl.hostname));
+
+        state.evict_dns(&StateEvent::WorkloadRemoved(Arc::new(wl.clone())));
+        assert!(!state
+            .read()
+            .resolved_dns
+            .by_hostname
+            .read()
+            .unwrap()
+            .contains_key(&wl.hostname));
+
+        state.evict_dns(&StateEvent::ServiceChanged(svc));
+        assert!(state.read().captured_hostnames.get(&addr).is_none());
+    }
+
     #[tokio::test]
     async fn test_load_balance() {
         let mut state = ProxyState::default();
@@ -1145,6 +2007,7 @@ mod tests {
                     LoadBalancerScopes::Region,
                     LoadBalancerScopes::Zone,
                 ],
+                strategy: LoadBalancerStrategy::Random,
             }),
             ..test_helpers::mock_default_service()
         };
@@ -1157,6 +2020,7 @@ mod tests {
                     LoadBalancerScopes::Region,
                     LoadBalancerScopes::Zone,
                 ],
+                strategy: LoadBalancerStrategy::Random,
             }),
             ..test_helpers::mock_default_service()
         };
@@ -1170,7 +2034,7 @@ mod tests {
 
         let assert_endpoint = |src: &Workload, svc: &Service, ips: Vec<&str>, desc: &str| {
             let got = state
-                .load_balance(src, svc)
+                .load_balance(Some(src), svc, &ConnectionManager::default())
                 .and_then(|ep| ep.address.clone())
                 .map(|addr| addr.address.to_string());
             if ips.is_empty() {
@@ -1214,4 +2078,298 @@ mod tests {
             "failover full match selects closest match",
         );
     }
+
+    #[tokio::test]
+    async fn test_load_balance_strategy() {
+        let mut state = ProxyState::default();
+        let src = test_helpers::test_default_workload();
+        let ips = ["192.168.0.1", "192.168.0.2", "192.168.0.3"];
+        let endpoints: HashMap<Strng, Endpoint> = ips
+            .iter()
+            .map(|ip| {
+                let uid = strng::format!("cluster1//v1/Pod/default/{ip}");
+                let ep = Endpoint {
+                    workload_uid: uid.clone(),
+                    service: NamespacedHostname {
+                        namespace: TEST_SERVICE_NAMESPACE.into(),
+                        hostname: "example.com".into(),
+                    },
+                    address: Some(NetworkAddress {
+                        address: ip.parse().unwrap(),
+                        network: "".into(),
+                    }),
+                    port: HashMap::from([(80u16, 80u16)]),
+                };
+                (uid, ep)
+            })
+            .collect();
+        let svc = |strategy: LoadBalancerStrategy| Service {
+            endpoints: endpoints.clone(),
+            load_balancer: Some(LoadBalancer {
+                mode: LoadBalancerMode::Failover,
+                routing_preferences: vec![],
+                strategy,
+            }),
+            ..test_helpers::mock_default_service()
+        };
+        let pick = |state: &ProxyState, svc: &Service, load: &ConnectionManager| {
+            state
+                .load_balance(Some(&src), svc, load)
+                .and_then(|ep| ep.address.clone())
+                .map(|addr| addr.address.to_string())
+                .unwrap()
+        };
+
+        // Round robin visits every endpoint once before repeating, and keeps its position when
+        // the service is updated.
+        let round_robin = svc(LoadBalancerStrategy::RoundRobin);
+        state.services.insert(round_robin.clone());
+        let cm = ConnectionManager::default();
+        let first: Vec<String> = (0..ips.len())
+            .map(|_| pick(&state, &round_robin, &cm))
+            .collect();
+        assert_eq!(
+            first.iter().collect::<HashSet<_>>().len(),
+            ips.len(),
+            "round robin should visit every endpoint: {first:?}"
+        );
+        state.services.insert(round_robin.clone());
+        let second: Vec<String> = (0..ips.len())
+            .map(|_| pick(&state, &round_robin, &cm))
+            .collect();
+        assert_eq!(first, second);
+
+        // Least connections picks the endpoint with no active connections.
+        let least_connections = svc(LoadBalancerStrategy::LeastConnections);
+        let src_addr: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dst = |ip: &str| SocketAddr::new(ip.parse().unwrap(), 80);
+        let _g1 = cm.track_outbound(src_addr, dst(ips[0]), dst(ips[0]));
+        let _g2 = cm.track_outbound(src_addr, dst(ips[2]), dst(ips[2]));
+        for _ in 0..10 {
+            assert_eq!(pick(&state, &least_connections, &cm), ips[1]);
+        }
+        let _g3 = cm.track_outbound(src_addr, dst(ips[1]), dst(ips[1]));
+        let _g4 = cm.track_outbound("10.0.0.1:1235".parse().unwrap(), dst(ips[1]), dst(ips[1]));
+        for _ in 0..10 {
+            let got = pick(&state, &least_connections, &cm);
+            assert!(got == ips[0] || got == ips[2], "unexpected pick {got}");
+        }
+
+        // Counts are released along with the connections.
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        assert_eq!(cm.active_connections(ip(ips[1])), 2);
+        drop((_g3, _g4));
+        assert_eq!(cm.active_connections(ip(ips[1])), 0);
+        assert_eq!(cm.active_connections(ip(ips[0])), 1);
+    }
+
+    #[tokio::test]
+    async fn test_load_balance_session_affinity() {
+        let state = ProxyState::default();
+        let endpoint = |ip: &str| {
+            let uid = strng::format!("cluster1//v1/Pod/default/{ip}");
+            let ep = Endpoint {
+                workload_uid: uid.clone(),
+                service: NamespacedHostname {
+                    namespace: TEST_SERVICE_NAMESPACE.into(),
+                    hostname: "example.com".into(),
+                },
+                address: Some(NetworkAddress {
+                    address: ip.parse().unwrap(),
+                    network: "".into(),
+                }),
+                port: HashMap::from([(80u16, 80u16)]),
+            };
+            (uid, ep)
+        };
+        let mut svc = Service {
+            endpoints: (1..=10)
+                .map(|i| endpoint(&format!("192.168.0.{i}")))
+                .collect(),
+            session_affinity: SessionAffinity::ClientIp,
+            ..test_helpers::mock_default_service()
+        };
+        let src = |ip: &str| Workload {
+            workload_ips: vec![ip.parse().unwrap()],
+            ..test_helpers::test_default_workload()
+        };
+        let pick = |src: &Workload, svc: &Service| {
+            state
+                .load_balance(Some(src), svc, &ConnectionManager::default())
+                .map(|ep| ep.workload_uid.clone())
+                .unwrap()
+        };
+
+        // The same source always lands on the same endpoint.
+        let sources: Vec<Workload> = (1..=20).map(|i| src(&format!("10.0.0.{i}"))).collect();
+        let picked: Vec<Strng> = sources.iter().map(|s| pick(s, &svc)).collect();
+        for _ in 0..10 {
+            for (s, want) in sources.iter().zip(&picked) {
+                assert_eq!(&pick(s, &svc), want);
+            }
+        }
+        assert!(
+            picked.iter().collect::<HashSet<_>>().len() > 1,
+            "sources should be spread across endpoints"
+        );
+
+        // Removing an endpoint only moves the sources that were on it.
+        let removed = picked[0].clone();
+        svc.endpoints.remove(&removed);
+        for (s, prev) in sources.iter().zip(&picked) {
+            let got = pick(s, &svc);
+            assert_ne!(got, removed);
+            if *prev != removed {
+                assert_eq!(&got, prev);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_sni_upstream() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            uid: "cluster1//v1/Pod/default/reviews".into(),
+            workload_ips: vec!["192.168.0.1".parse().unwrap()],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl.clone()), true);
+        let ep = Endpoint {
+            workload_uid: wl.uid.clone(),
+            service: NamespacedHostname {
+                namespace: "default".into(),
+                hostname: "reviews.default.svc.cluster.local".into(),
+            },
+            address: None,
+            port: HashMap::from([(9080u16, 9081u16)]),
+        };
+        state.services.insert(Service {
+            hostname: "reviews.default.svc.cluster.local".into(),
+            ports: HashMap::from([(9080u16, 9080u16)]),
+            endpoints: HashMap::from([(wl.uid.clone(), ep)]),
+            // Requires locality, which a client routed by SNI has none of
+            load_balancer: Some(LoadBalancer {
+                routing_preferences: vec![LoadBalancerScopes::Zone],
+                mode: LoadBalancerMode::Failover,
+                strategy: LoadBalancerStrategy::Random,
+            }),
+            ..test_helpers::mock_default_service()
+        });
+
+        let cm = ConnectionManager::default();
+        let host = strng::new("reviews.default.svc.cluster.local");
+        let us = state.find_sni_upstream(&host, 9080, &cm).unwrap();
+        assert_eq!(us.workload.uid, wl.uid);
+        // The endpoint overrides the service's target port
+        assert_eq!(us.port, 9081);
+        assert!(state.find_sni_upstream(&host, 443, &cm).is_none());
+        assert!(state
+            .find_sni_upstream(&strng::new("unknown.example.com"), 9080, &cm)
+            .is_none());
+    }
+
+    #[test]
+    fn test_find_hostname_ip() {
+        let mut state = ProxyState::default();
+        let wl = Workload {
+            uid: "cluster1//v1/Pod/default/vm".into(),
+            hostname: "vm.example.com".into(),
+            workload_ips: vec!["192.168.0.1".parse().unwrap()],
+            ..test_helpers::test_default_workload()
+        };
+        state.workloads.insert(Arc::new(wl), true);
+        state.services.insert(Service {
+            hostname: "reviews.default.svc.cluster.local".into(),
+            vips: vec![
+                NetworkAddress {
+                    network: "remote".into(),
+                    address: "10.0.0.2".parse().unwrap(),
+                },
+                NetworkAddress {
+                    network: strng::EMPTY,
+                    address: "10.0.0.1".parse().unwrap(),
+                },
+            ],
+            ..test_helpers::mock_default_service()
+        });
+
+        let find = |host: &str| state.find_hostname_ip(&strng::EMPTY, &strng::new(host));
+        // Only the VIP on our network is used
+        assert_eq!(
+            find("reviews.default.svc.cluster.local"),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(find("vm.example.com"), Some("192.168.0.1".parse().unwrap()));
+        assert_eq!(find("unknown.example.com"), None);
+        assert_eq!(
+            state.find_hostname_ip(&strng::new("remote"), &strng::new("vm.example.com")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_balance_excludes_failed() {
+        struct Failed {
+            failed: HashSet<IpAddr>,
+            ejected: HashSet<IpAddr>,
+        }
+        impl EndpointLoad for Failed {
+            fn active_connections(&self, _ip: IpAddr) -> usize {
+                0
+            }
+            fn failed_addresses(&self) -> Option<&HashSet<IpAddr>> {
+                Some(&self.failed)
+            }
+            fn ejected_addresses(&self) -> HashSet<IpAddr> {
+                self.ejected.clone()
+            }
+        }
+
+        let state = ProxyState::default();
+        let ips = ["192.168.0.1", "192.168.0.2"];
+        let svc = Service {
+            endpoints: ips
+                .iter()
+                .map(|ip| {
+                    let uid = strng::format!("cluster1//v1/Pod/default/{ip}");
+                    let ep = Endpoint {
+                        workload_uid: uid.clone(),
+                        service: NamespacedHostname {
+                            namespace: TEST_SERVICE_NAMESPACE.into(),
+                            hostname: "example.com".into(),
+                        },
+                        address: Some(NetworkAddress {
+                            address: ip.parse().unwrap(),
+                            network: "".into(),
+                        }),
+                        port: HashMap::from([(80u16, 80u16)]),
+                    };
+                    (uid, ep)
+                })
+                .collect(),
+            ..test_helpers::mock_default_service()
+        };
+        let src = test_helpers::test_default_workload();
+        let pick_with = |failed: &[&str], ejected: &[&str]| {
+            let load = Failed {
+                failed: failed.iter().map(|ip| ip.parse().unwrap()).collect(),
+                ejected: ejected.iter().map(|ip| ip.parse().unwrap()).collect(),
+            };
+            state
+                .load_balance(Some(&src), &svc, &load)
+                .and_then(|ep| ep.address.clone())
+                .map(|addr| addr.address.to_string())
+        };
+
+        for _ in 0..10 {
+            assert_eq!(pick_with(&[ips[0]], &[]), Some(ips[1].to_string()));
+        }
+        assert_eq!(pick_with(&ips, &[]), None);
+
+        // Ejected endpoints are avoided, unless there is nothing else left.
+        for _ in 0..10 {
+            assert_eq!(pick_with(&[], &[ips[1]]), Some(ips[0].to_string()));
+        }
+        assert_eq!(pick_with(&[ips[0]], &[ips[1]]), Some(ips[1].to_string()));
+    }
 }
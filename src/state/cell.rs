@@ -0,0 +1,155 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use crate::state::ProxyState;
+
+/// ProxyStateCell holds the [ProxyState] as an immutable snapshot, which writers replace as a
+/// whole.
+///
+/// Readers get the current snapshot without ever waiting on a writer, so a large XDS push does
+/// not stall the lookups made for every connection. Writers are serialized, and each works on its
+/// own copy of the state, which becomes visible to readers once the write is done. The copy is
+/// made once per write rather than per change, so writers should batch their changes.
+#[derive(Clone, Default)]
+pub struct ProxyStateCell {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Only held long enough to take or replace the snapshot.
+    current: RwLock<Arc<ProxyState>>,
+    // Held for the whole write, so writers don't overwrite each other's changes.
+    writer: Mutex<()>,
+}
+
+impl ProxyStateCell {
+    pub fn new(state: ProxyState) -> Self {
+        ProxyStateCell {
+            inner: Arc::new(Inner {
+                current: RwLock::new(Arc::new(state)),
+                writer: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// read returns the current snapshot of the state. It is not affected by later writes.
+    pub fn read(&self) -> Arc<ProxyState> {
+        self.inner.current.read().unwrap().clone()
+    }
+
+    /// write returns a copy of the state to change, which replaces the current snapshot when the
+    /// guard is dropped. Other writers wait until then.
+    pub fn write(&self) -> ProxyStateWriteGuard<'_> {
+        let writer = self.inner.writer.lock().unwrap();
+        let state = self.read().as_ref().clone();
+        ProxyStateWriteGuard {
+            cell: self,
+            state: Some(state),
+            _writer: writer,
+        }
+    }
+}
+
+impl std::fmt::Debug for ProxyStateCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.read().as_ref(), f)
+    }
+}
+
+impl serde::Serialize for ProxyStateCell {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde::Serialize::serialize(self.read().as_ref(), serializer)
+    }
+}
+
+/// ProxyStateWriteGuard is a writable copy of the state, see [ProxyStateCell::write].
+pub struct ProxyStateWriteGuard<'a> {
+    cell: &'a ProxyStateCell,
+    // Only taken on drop.
+    state: Option<ProxyState>,
+    _writer: MutexGuard<'a, ()>,
+}
+
+impl Deref for ProxyStateWriteGuard<'_> {
+    type Target = ProxyState;
+
+    fn deref(&self) -> &ProxyState {
+        self.state.as_ref().expect("state is only taken on drop")
+    }
+}
+
+impl DerefMut for ProxyStateWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut ProxyState {
+        self.state.as_mut().expect("state is only taken on drop")
+    }
+}
+
+impl Drop for ProxyStateWriteGuard<'_> {
+    fn drop(&mut self) {
+        let Some(mut state) = self.state.take() else {
+            return;
+        };
+        // Subscribers are only notified once the change is visible, so they see it when reading
+        // the state in response.
+        let events = state.events.take_pending();
+        let published = state.events.clone();
+        let identity_changed = state.workloads.take_identity_changed();
+        *self.cell.inner.current.write().unwrap() = Arc::new(state);
+        published.send(events);
+        if let Some(notifier) = identity_changed {
+            notifier.send_replace(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::events::StateEvent;
+    use crate::test_helpers;
+
+    #[test]
+    fn copy_on_write() {
+        let cell = ProxyStateCell::default();
+        let mut events = cell.read().events.subscribe();
+        let before = cell.read();
+
+        let mut state = cell.write();
+        state
+            .workloads
+            .insert(Arc::new(test_helpers::test_default_workload()), false);
+        state
+            .events
+            .publish(StateEvent::PolicyChanged("ns/name".into()));
+        // Neither readers nor subscribers see the write until it is done.
+        assert!(cell.read().workloads.by_uid.is_empty());
+        assert!(events.try_recv().is_err());
+        drop(state);
+
+        assert_eq!(cell.read().workloads.by_uid.len(), 1);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            StateEvent::PolicyChanged("ns/name".into())
+        );
+        // Snapshots taken earlier are unaffected.
+        assert!(before.workloads.by_uid.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::state::workload::{NamespacedHostname, Workload};
+use crate::strng::Strng;
+
+// How many events a slow subscriber may fall behind before it misses some.
+const EVENT_BUFFER: usize = 1024;
+
+/// A StateEvent describes a change made to the [ProxyState](crate::state::ProxyState) by XDS.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateEvent {
+    /// A workload was added, or replaced with a new version.
+    WorkloadAdded(Arc<Workload>),
+    /// A workload was removed.
+    WorkloadRemoved(Arc<Workload>),
+    /// The policy with the given key was added, updated or removed.
+    PolicyChanged(Strng),
+    /// The service was added, updated or removed. Endpoint changes are not included.
+    ServiceChanged(NamespacedHostname),
+}
+
+/// StateEvents broadcasts [StateEvent]s to every subscriber.
+///
+/// Events published while writing the state are only sent once the write is visible to readers,
+/// see [ProxyStateCell](crate::state::cell::ProxyStateCell). Subscribers that fall behind receive
+/// [broadcast::error::RecvError::Lagged], and should then reconcile against the state as a whole,
+/// since some events were missed.
+#[derive(Debug, Clone)]
+pub struct StateEvents {
+    sender: broadcast::Sender<StateEvent>,
+    // Published by the write in progress.
+    pending: Vec<StateEvent>,
+}
+
+impl Default for StateEvents {
+    fn default() -> Self {
+        let (sender, _rx) = broadcast::channel(EVENT_BUFFER);
+        StateEvents {
+            sender,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl StateEvents {
+    /// subscribe returns a receiver of the events sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<StateEvent> {
+        self.sender.subscribe()
+    }
+
+    /// publish queues the event, to be sent once the change it describes is visible.
+    pub fn publish(&mut self, event: StateEvent) {
+        self.pending.push(event);
+    }
+
+    pub(super) fn take_pending(&mut self) -> Vec<StateEvent> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// send sends the events to the current subscribers, if there are any.
+    pub(super) fn send(&self, events: Vec<StateEvent>) {
+        for event in events {
+            // Having no subscribers is not an error; nobody is interested in the event.
+            let _ = self.sender.send(event);
+        }
+    }
+}
+
+/// drain discards the events already queued for the receiver, so a burst of events can be handled
+/// at once.
+pub fn drain(events: &mut broadcast::Receiver<StateEvent>) {
+    use broadcast::error::TryRecvError;
+    loop {
+        match events.try_recv() {
+            Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+            Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use broadcast::error::TryRecvError;
+
+    #[test]
+    fn publish_subscribe() {
+        let mut events = StateEvents::default();
+        // Nobody is subscribed yet, so this is dropped.
+        events.publish(StateEvent::PolicyChanged("ns/dropped".into()));
+        let pending = events.take_pending();
+        events.send(pending);
+
+        let mut rx = events.subscribe();
+        events.publish(StateEvent::PolicyChanged("ns/a".into()));
+        events.publish(StateEvent::PolicyChanged("ns/b".into()));
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+        let pending = events.take_pending();
+        events.send(pending);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            StateEvent::PolicyChanged("ns/a".into())
+        );
+
+        drain(&mut rx);
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+    }
+}
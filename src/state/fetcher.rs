@@ -0,0 +1,124 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config::ConfigSource;
+use crate::state::workload::{NetworkAddress, Workload};
+
+/// WorkloadFetcher is a secondary source of workload information, consulted when a workload is
+/// neither known locally nor available from XDS on-demand.
+#[async_trait]
+pub trait WorkloadFetcher: Debug + Send + Sync {
+    /// fetch returns the workload with the given address, if the fetcher knows of one.
+    async fn fetch(&self, addr: &NetworkAddress) -> Option<Workload>;
+}
+
+#[derive(Default, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct FallbackConfig {
+    #[serde(default)]
+    workloads: Vec<Workload>,
+}
+
+/// FileWorkloadFetcher looks up workloads in a YAML file, in the same format as the workloads of a
+/// local XDS config. The file is read again whenever it changes.
+#[derive(Debug)]
+pub struct FileWorkloadFetcher {
+    source: ConfigSource,
+    // The workloads last read from the source, along with the file's modification time when they
+    // were read.
+    cache: Mutex<Option<(Option<SystemTime>, Arc<Vec<Workload>>)>>,
+}
+
+impl FileWorkloadFetcher {
+    pub fn new(source: ConfigSource) -> Self {
+        FileWorkloadFetcher {
+            source,
+            cache: Mutex::new(None),
+        }
+    }
+
+    async fn workloads(&self) -> anyhow::Result<Arc<Vec<Workload>>> {
+        let modified = match &self.source {
+            ConfigSource::File(path) => tokio::fs::metadata(path)
+                .await
+                .and_then(|m| m.modified())
+                .ok(),
+            _ => None,
+        };
+        let mut cache = self.cache.lock().await;
+        if let Some((at, workloads)) = cache.as_ref() {
+            if *at == modified {
+                return Ok(workloads.clone());
+            }
+        }
+        let cfg: FallbackConfig = serde_yaml::from_str(&self.source.read_to_string().await?)?;
+        let workloads = Arc::new(cfg.workloads);
+        *cache = Some((modified, workloads.clone()));
+        Ok(workloads)
+    }
+}
+
+#[async_trait]
+impl WorkloadFetcher for FileWorkloadFetcher {
+    async fn fetch(&self, addr: &NetworkAddress) -> Option<Workload> {
+        let workloads = match self.workloads().await {
+            Ok(workloads) => workloads,
+            Err(e) => {
+                warn!("failed to read fallback workloads: {e}");
+                return None;
+            }
+        };
+        workloads
+            .iter()
+            .find(|wl| wl.network == addr.network && wl.workload_ips.contains(&addr.address))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn file_fetcher() {
+        let cfg = r#"
+workloads:
+- uid: cluster1//v1/Pod/default/local
+  name: local
+  namespace: default
+  serviceAccount: default
+  workloadIps: ["10.0.0.1"]
+  network: net1
+"#;
+        let fetcher = FileWorkloadFetcher::new(ConfigSource::Static(Bytes::from(cfg)));
+        let addr = |ip: &str, network: &str| NetworkAddress {
+            network: network.into(),
+            address: ip.parse::<IpAddr>().unwrap(),
+        };
+        let wl = fetcher.fetch(&addr("10.0.0.1", "net1")).await.unwrap();
+        assert_eq!(wl.name.as_str(), "local");
+        assert!(fetcher.fetch(&addr("10.0.0.1", "net2")).await.is_none());
+        assert!(fetcher.fetch(&addr("10.0.0.2", "net1")).await.is_none());
+    }
+}
@@ -16,30 +16,15 @@ use crate::rbac::{Authorization, RbacScope};
 use crate::strng;
 use crate::strng::Strng;
 use std::collections::{HashMap, HashSet};
-use tokio::sync::watch;
 
 /// A PolicyStore encapsulates all policy information about workloads in the mesh
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct PolicyStore {
     /// policies maintains a mapping of ns/name to policy.
     pub(super) by_key: HashMap<Strng, Authorization>,
 
     /// policies_by_namespace maintains a mapping of namespace (or "" for global) to policy names
     by_namespace: HashMap<Strng, HashSet<Strng>>,
-
-    notifier: PolicyStoreNotify,
-}
-
-#[derive(Debug)]
-struct PolicyStoreNotify {
-    sender: watch::Sender<()>,
-}
-
-impl Default for PolicyStoreNotify {
-    fn default() -> Self {
-        let (tx, _rx) = watch::channel(());
-        PolicyStoreNotify { sender: tx }
-    }
 }
 
 impl PolicyStore {
@@ -47,6 +32,10 @@ impl PolicyStore {
         self.by_key.get(key)
     }
 
+    pub fn num_policies(&self) -> usize {
+        self.by_key.len()
+    }
+
     pub fn get_by_namespace(&self, namespace: &Strng) -> Vec<Strng> {
         self.by_namespace
             .get(namespace)
@@ -93,14 +82,9 @@ impl PolicyStore {
             }
         }
     }
-    pub fn subscribe(&self) -> watch::Receiver<()> {
-        self.notifier.sender.subscribe()
-    }
-    pub fn send(&mut self) {
-        self.notifier.sender.send_replace(());
-    }
-    pub fn clear_all_policies(&mut self) {
+    /// clear_all_policies removes every policy, returning the keys of those removed.
+    pub fn clear_all_policies(&mut self) -> Vec<Strng> {
         self.by_namespace.clear();
-        self.by_key.clear();
+        self.by_key.drain().map(|(key, _)| key).collect()
     }
 }
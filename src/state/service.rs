@@ -14,7 +14,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use bytes::Bytes;
 use tracing::trace;
@@ -51,6 +51,43 @@ pub struct Service {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub load_balancer: Option<LoadBalancer>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub session_affinity: SessionAffinity,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tls_origination: Option<TlsOrigination>,
+}
+
+/// TlsOrigination configures ztunnel to originate simple TLS to a service outside the mesh, on
+/// behalf of plaintext clients.
+#[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TlsOrigination {
+    /// The SNI to send, and the name the server certificate is verified against.
+    pub sni: Strng,
+    /// PEM encoded roots to verify the server certificate with. If empty, the system roots are
+    /// used.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub root_certificates: Strng,
+}
+
+/// SessionAffinity defines whether connections from the same client should stick to an endpoint.
+#[derive(Debug, Default, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+pub enum SessionAffinity {
+    #[default]
+    None,
+    /// Connections from the same source workload are consistently hashed to the same endpoint.
+    ClientIp,
+}
+
+impl From<xds::istio::workload::SessionAffinity> for SessionAffinity {
+    fn from(value: xds::istio::workload::SessionAffinity) -> Self {
+        match value {
+            xds::istio::workload::SessionAffinity::None => SessionAffinity::None,
+            xds::istio::workload::SessionAffinity::ClientIp => SessionAffinity::ClientIp,
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
@@ -71,6 +108,30 @@ impl From<xds::istio::workload::load_balancing::Mode> for LoadBalancerMode {
     }
 }
 
+/// LoadBalancerStrategy defines how an endpoint is picked among the candidates that best match
+/// the routing preferences.
+#[derive(Debug, Default, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
+pub enum LoadBalancerStrategy {
+    #[default]
+    Random,
+    RoundRobin,
+    LeastConnections,
+}
+
+impl From<xds::istio::workload::load_balancing::Strategy> for LoadBalancerStrategy {
+    fn from(value: xds::istio::workload::load_balancing::Strategy) -> Self {
+        match value {
+            xds::istio::workload::load_balancing::Strategy::Random => LoadBalancerStrategy::Random,
+            xds::istio::workload::load_balancing::Strategy::RoundRobin => {
+                LoadBalancerStrategy::RoundRobin
+            }
+            xds::istio::workload::load_balancing::Strategy::LeastConnections => {
+                LoadBalancerStrategy::LeastConnections
+            }
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 pub enum LoadBalancerScopes {
     Region,
@@ -101,6 +162,8 @@ impl TryFrom<XdsScope> for LoadBalancerScopes {
 pub struct LoadBalancer {
     pub routing_preferences: Vec<LoadBalancerScopes>,
     pub mode: LoadBalancerMode,
+    #[serde(default)]
+    pub strategy: LoadBalancerStrategy,
 }
 
 impl Service {
@@ -187,6 +250,8 @@ impl TryFrom<&XdsService> for Service {
                     })
                     .collect::<Result<Vec<LoadBalancerScopes>, WorkloadError>>()?,
                 mode: xds::istio::workload::load_balancing::Mode::try_from(lb.mode)?.into(),
+                strategy: xds::istio::workload::load_balancing::Strategy::try_from(lb.strategy)?
+                    .into(),
             })
         } else {
             None
@@ -204,13 +269,22 @@ impl TryFrom<&XdsService> for Service {
             subject_alt_names: s.subject_alt_names.iter().map(strng::new).collect(),
             waypoint,
             load_balancer: lb,
+            session_affinity: xds::istio::workload::SessionAffinity::try_from(s.session_affinity)?
+                .into(),
+            tls_origination: s.tls_origination.as_ref().map(|t| TlsOrigination {
+                sni: match t.sni.as_str() {
+                    "" => Strng::from(&s.hostname),
+                    sni => Strng::from(sni),
+                },
+                root_certificates: Strng::from(&t.root_certificates),
+            }),
         };
         Ok(svc)
     }
 }
 
 /// Data store for service information.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct ServiceStore {
     /// Maintains a mapping of service key -> (endpoint UID -> workload endpoint)
     /// this is used to handle ordering issues if workloads are received before services.
@@ -228,6 +302,11 @@ pub struct ServiceStore {
     /// service for a given hostname. However, `ServiceEntry` allows hostnames to be overridden
     /// on a per-namespace basis.
     pub(super) by_host: HashMap<Strng, Vec<Arc<Service>>>,
+
+    /// Tracks the next position for services using the round-robin load balancing strategy.
+    /// Endpoint selection only reads the store, so this is behind its own lock, shared by copies
+    /// of the store.
+    round_robin: Arc<Mutex<HashMap<NamespacedHostname, usize>>>,
 }
 
 impl ServiceStore {
@@ -356,7 +435,19 @@ impl ServiceStore {
         }
 
         // If we're replacing an existing service, remove the old one from all data structures.
+        // Keep the round-robin position, so updates to the service do not reset it.
+        let round_robin = self
+            .round_robin
+            .lock()
+            .expect("mutex")
+            .remove(&namespaced_hostname);
         let _ = self.remove(&namespaced_hostname);
+        if let Some(next) = round_robin {
+            self.round_robin
+                .lock()
+                .expect("mutex")
+                .insert(namespaced_hostname.clone(), next);
+        }
 
         // Save values used for the indexes.
         let vips = service.vips.clone();
@@ -424,6 +515,11 @@ impl ServiceStore {
                 // TODO(nmittler): no endpoints for this service should be staged at this point.
                 self.staged_services.remove(namespaced_host);
 
+                self.round_robin
+                    .lock()
+                    .expect("mutex")
+                    .remove(namespaced_host);
+
                 // Remove mapping from workload to the VIPs for this service.
                 for (ep_ip, _) in prev.endpoints.iter() {
                     // Remove the workload IP mapping for this service.
@@ -442,12 +538,20 @@ impl ServiceStore {
         }
     }
 
+    /// Returns the next round-robin position for the given service, advancing it.
+    pub fn next_round_robin(&self, host: &NamespacedHostname) -> usize {
+        let mut round_robin = self.round_robin.lock().expect("mutex");
+        let next = round_robin.entry(host.clone()).or_default();
+        let current = *next;
+        *next = next.wrapping_add(1);
+        current
+    }
+
     #[cfg(test)]
     pub fn num_vips(&self) -> usize {
         self.by_vip.len()
     }
 
-    #[cfg(test)]
     pub fn num_services(&self) -> usize {
         let mut count = 0;
         for (_, value) in self.by_host.iter() {
@@ -0,0 +1,188 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tracing::info;
+
+use crate::cert_fetcher::CertFetcher;
+use crate::rbac::Authorization;
+use crate::state::service::Service;
+use crate::state::workload::Workload;
+use crate::state::{DemandProxyState, ProxyState};
+use crate::strng::Strng;
+use crate::xds::{self, ProxyStateUpdater, XdsVersions};
+
+/// Snapshot is the part of the proxy state learned from XDS, as persisted across restarts.
+#[derive(Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Snapshot {
+    #[serde(default)]
+    workloads: Vec<Workload>,
+    #[serde(default)]
+    services: Vec<Service>,
+    #[serde(default)]
+    policies: Vec<Authorization>,
+}
+
+impl Snapshot {
+    fn capture(state: &ProxyState) -> Snapshot {
+        Snapshot {
+            workloads: state
+                .workloads
+                .by_uid
+                .values()
+                .map(|w| w.as_ref().clone())
+                .collect(),
+            services: state
+                .services
+                .by_host
+                .values()
+                .flatten()
+                .map(|s| s.as_ref().clone())
+                .collect(),
+            policies: state.policies.by_key.values().cloned().collect(),
+        }
+    }
+
+    /// load reads the snapshot at path, if there is one.
+    pub async fn load(path: &Path) -> anyhow::Result<Option<Snapshot>> {
+        match tokio::fs::read(path).await {
+            Ok(b) => Ok(Some(serde_json::from_slice(&b)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// restore inserts the snapshot into the state. The entries it returns are stale until XDS
+    /// sends them again.
+    pub fn restore(self, state: &mut ProxyState, cert_fetcher: &dyn CertFetcher) -> StaleEntries {
+        let mut stale = StaleEntries::default();
+        for w in self.workloads {
+            cert_fetcher.prefetch_cert(&w);
+            let track = cert_fetcher.should_track_certificates_for_removal(&w);
+            stale.addresses.push(w.uid.clone());
+            state.workloads.insert(Arc::new(w), track);
+        }
+        for s in self.services {
+            stale
+                .addresses
+                .push(s.namespaced_hostname().to_string().into());
+            state.services.insert(s);
+        }
+        for p in self.policies {
+            stale.authorizations.push(p.to_key());
+            state.policies.insert(p);
+        }
+        info!(
+            addresses = stale.addresses.len(),
+            authorizations = stale.authorizations.len(),
+            "restored state snapshot, stale until confirmed by XDS"
+        );
+        stale
+    }
+}
+
+/// StaleEntries are the XDS resources restored from a snapshot, by the name XDS knows them by.
+#[derive(Default, Debug)]
+pub struct StaleEntries {
+    addresses: Vec<Strng>,
+    authorizations: Vec<Strng>,
+}
+
+impl StaleEntries {
+    /// prune removes the entries XDS did not send again. It should be called once XDS synced, so
+    /// whatever XDS still knows of was sent.
+    pub fn prune(self, updater: &ProxyStateUpdater, versions: &XdsVersions) {
+        let addresses: Vec<_> = self
+            .addresses
+            .into_iter()
+            .filter(|name| !versions.contains(&xds::ADDRESS_TYPE, name))
+            .collect();
+        let authorizations: Vec<_> = self
+            .authorizations
+            .into_iter()
+            .filter(|name| !versions.contains(&xds::AUTHORIZATION_TYPE, name))
+            .collect();
+        info!(
+            addresses = addresses.len(),
+            authorizations = authorizations.len(),
+            "removing state snapshot entries not confirmed by XDS"
+        );
+        updater.remove_resources(&addresses, &authorizations);
+    }
+}
+
+/// Snapshotter saves the state to a file, to be restored on the next start.
+pub struct Snapshotter {
+    path: PathBuf,
+    state: DemandProxyState,
+}
+
+impl Snapshotter {
+    pub fn new(path: PathBuf, state: DemandProxyState) -> Self {
+        Snapshotter { path, state }
+    }
+
+    /// save writes the state, replacing the previous snapshot only once it is fully written.
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let snapshot = Snapshot::capture(&self.state.read());
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&snapshot)?).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        info!(
+            workloads = snapshot.workloads.len(),
+            services = snapshot.services.len(),
+            policies = snapshot.policies.len(),
+            "saved state snapshot to {}",
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_fetcher::NoCertFetcher;
+    use crate::test_helpers;
+
+    #[tokio::test]
+    async fn round_trip() {
+        let mut state = ProxyState::default();
+        state
+            .workloads
+            .insert(Arc::new(test_helpers::test_default_workload()), false);
+        let snapshot = Snapshot::capture(&state);
+        assert_eq!(snapshot.workloads.len(), 1);
+
+        let path = std::env::temp_dir().join(format!("ztunnel-snapshot-{}", std::process::id()));
+        tokio::fs::write(&path, serde_json::to_vec(&snapshot).unwrap())
+            .await
+            .unwrap();
+        let loaded = Snapshot::load(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(loaded.as_ref(), Some(&snapshot));
+        assert!(Snapshot::load(&path).await.unwrap().is_none());
+
+        let mut restored = ProxyState::default();
+        let stale = loaded.unwrap().restore(&mut restored, &NoCertFetcher());
+        assert_eq!(
+            stale.addresses,
+            vec![test_helpers::test_default_workload().uid]
+        );
+        assert_eq!(Snapshot::capture(&restored), snapshot);
+    }
+}
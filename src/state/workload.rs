@@ -33,6 +33,7 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::{fmt, net};
 use thiserror::Error;
+use tokio::sync::watch;
 use tracing::{error, trace};
 use xds::istio::workload::ApplicationTunnel as XdsApplicationTunnel;
 use xds::istio::workload::GatewayAddress as XdsGatewayAddress;
@@ -206,6 +207,20 @@ pub struct Workload {
 
     #[serde(default, skip_serializing_if = "is_default")]
     pub locality: Locality,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub connection_limit: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mirror_address: Option<SocketAddr>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub excluded_inbound_ports: Vec<u16>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub included_inbound_ports: Vec<u16>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub hbone_quic: bool,
 }
 
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
@@ -231,6 +246,14 @@ impl Workload {
         }
         Ok(None)
     }
+
+    // captures_inbound_port returns whether inbound traffic to the port is captured, rather than
+    // excluded from the mesh by the workload's included or excluded ports.
+    pub fn captures_inbound_port(&self, port: u16) -> bool {
+        !self.excluded_inbound_ports.contains(&port)
+            && (self.included_inbound_ports.is_empty()
+                || self.included_inbound_ports.contains(&port))
+    }
 }
 
 impl fmt::Display for Workload {
@@ -429,6 +452,28 @@ impl TryFrom<XdsWorkload> for (Workload, HashMap<String, PortList>) {
 
             locality: resource.locality.map(Locality::from).unwrap_or_default(),
 
+            connection_limit: match resource.connection_limit {
+                0 => None,
+                limit => Some(limit as usize),
+            },
+
+            mirror_address: match resource.mirror_address.as_str() {
+                "" => None,
+                addr => Some(addr.parse()?),
+            },
+
+            excluded_inbound_ports: resource
+                .excluded_inbound_ports
+                .iter()
+                .map(|p| *p as u16)
+                .collect(),
+            included_inbound_ports: resource
+                .included_inbound_ports
+                .iter()
+                .map(|p| *p as u16)
+                .collect(),
+            hbone_quic: resource.hbone_quic,
+
             cluster_id: {
                 let result = resource.cluster_id;
                 if result.is_empty() {
@@ -589,22 +634,47 @@ pub fn network_addr(network: Strng, vip: IpAddr) -> NetworkAddress {
 }
 
 /// A WorkloadStore encapsulates all information about workloads in the mesh
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct WorkloadStore {
     /// byAddress maps workload network addresses to workloads
     pub(super) by_addr: HashMap<NetworkAddress, Arc<Workload>>,
     /// byUid maps workload UIDs to workloads
-    by_uid: HashMap<Strng, Arc<Workload>>,
+    pub(super) by_uid: HashMap<Strng, Arc<Workload>>,
     /// byHostname maps workload hostname to workloads.
     by_hostname: HashMap<Strng, Arc<Workload>>,
     // Identity->Set of UIDs. Only stores local nodes
     by_identity: HashMap<Identity, HashSet<Strng>>,
+    /// byServiceAccount maps (namespace, service account) to the UIDs of its workloads.
+    by_service_account: HashMap<(Strng, Strng), HashSet<Strng>>,
+
+    // Notified when an existing workload changes identity. Shared by copies of the store.
+    identity_notifier: WorkloadStoreNotify,
+}
+
+#[derive(Debug, Clone)]
+struct WorkloadStoreNotify {
+    sender: Arc<watch::Sender<()>>,
+    // Set when the write in progress changed an identity.
+    pending: bool,
+}
+
+impl Default for WorkloadStoreNotify {
+    fn default() -> Self {
+        let (tx, _rx) = watch::channel(());
+        WorkloadStoreNotify {
+            sender: Arc::new(tx),
+            pending: false,
+        }
+    }
 }
 
 impl WorkloadStore {
     pub fn insert(&mut self, w: Arc<Workload>, track_identity: bool) {
         // First, remove the entry entirely to make sure things are cleaned up properly.
-        self.remove(&w.uid);
+        let prev = self.remove(&w.uid);
+        if prev.is_some_and(|prev| prev.identity() != w.identity()) {
+            self.identity_notifier.pending = true;
+        }
 
         for ip in &w.workload_ips {
             self.by_addr
@@ -614,6 +684,10 @@ impl WorkloadStore {
             self.by_hostname.insert(w.hostname.clone(), w.clone());
         }
         self.by_uid.insert(w.uid.clone(), w.clone());
+        self.by_service_account
+            .entry((w.namespace.clone(), w.service_account.clone()))
+            .or_default()
+            .insert(w.uid.clone());
         // Only track local nodes to avoid overhead
         if track_identity {
             self.by_identity
@@ -643,6 +717,13 @@ impl WorkloadStore {
                         self.by_identity.remove(&id);
                     }
                 }
+                let sa = (prev.namespace.clone(), prev.service_account.clone());
+                if let Some(set) = self.by_service_account.get_mut(&sa) {
+                    set.remove(&prev.uid);
+                    if set.is_empty() {
+                        self.by_service_account.remove(&sa);
+                    }
+                }
                 Some(prev.deref().clone())
             }
         }
@@ -663,14 +744,70 @@ impl WorkloadStore {
         self.by_hostname.get(hostname).cloned()
     }
 
+    /// Returns the number of workloads in the store.
+    pub fn num_workloads(&self) -> usize {
+        self.by_uid.len()
+    }
+
+    /// Returns true if a workload with the given uid is in the store.
+    pub fn contains_uid(&self, uid: &Strng) -> bool {
+        self.by_uid.contains_key(uid)
+    }
+
     /// Finds the workload by uid.
     pub fn find_uid(&self, uid: &Strng) -> Option<Workload> {
         self.by_uid.get(uid).map(|wl| wl.deref().clone())
     }
 
+    /// Finds the workloads running as the given service account.
+    pub fn find_service_account(
+        &self,
+        namespace: &Strng,
+        service_account: &Strng,
+    ) -> Vec<Arc<Workload>> {
+        self.by_service_account
+            .get(&(namespace.clone(), service_account.clone()))
+            .into_iter()
+            .flatten()
+            .filter_map(|uid| self.by_uid.get(uid).cloned())
+            .collect()
+    }
+
     pub fn has_identity(&self, identity: &Identity) -> bool {
         self.by_identity.contains_key(identity)
     }
+
+    /// subscribe_identity_changes returns a receiver notified whenever an existing workload is
+    /// updated with a different identity (trust domain, namespace or service account).
+    pub fn subscribe_identity_changes(&self) -> watch::Receiver<()> {
+        self.identity_notifier.sender.subscribe()
+    }
+
+    /// replace swaps in the workloads of another store, keeping the identity change subscribers of
+    /// this one. Workloads present in both with a different identity count as changed.
+    pub fn replace(&mut self, other: WorkloadStore) {
+        let changed = self.by_uid.iter().any(|(uid, w)| {
+            other
+                .by_uid
+                .get(uid)
+                .is_some_and(|o| o.identity() != w.identity())
+        });
+        let identity_notifier = WorkloadStoreNotify {
+            sender: self.identity_notifier.sender.clone(),
+            pending: self.identity_notifier.pending || changed,
+        };
+        *self = WorkloadStore {
+            identity_notifier,
+            ..other
+        };
+    }
+
+    /// take_identity_changed returns the notifier to send once the identity changes made since
+    /// the last call are visible, if there were any.
+    pub(super) fn take_identity_changed(&mut self) -> Option<Arc<watch::Sender<()>>> {
+        std::mem::take(&mut self.identity_notifier.pending)
+            .then(|| self.identity_notifier.sender.clone())
+    }
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -684,6 +821,10 @@ pub enum WorkloadError {
     ByteAddressParse(usize),
     #[error("invalid cidr: {0}")]
     PrefixParse(#[from] ipnet::PrefixLenError),
+    #[error("invalid port: {0}")]
+    PortParse(u32),
+    #[error("invalid port range: {0}-{1}")]
+    PortRangeParse(u32, u32),
     #[error("unknown enum: {0}")]
     EnumParse(String),
     #[error("nonempty gateway address is missing address")]
@@ -696,6 +837,8 @@ pub enum WorkloadError {
 mod tests {
     use super::*;
     use crate::config::ConfigSource;
+    use crate::proxy::connection_manager::ConnectionManager;
+    use crate::state::cell::ProxyStateCell;
     use crate::state::{DemandProxyState, ProxyState};
     use crate::test_helpers::helpers::initialize_telemetry;
     use crate::xds::istio::workload::Port as XdsPort;
@@ -709,7 +852,7 @@ mod tests {
     use std::collections::HashSet;
     use std::default::Default;
     use std::net::{Ipv4Addr, Ipv6Addr};
-    use std::sync::RwLock;
+    use std::time::Duration;
     use xds::istio::workload::NetworkAddress as XdsNetworkAddress;
 
     #[test]
@@ -790,10 +933,65 @@ mod tests {
         assert_eq!(maybe_loopback_ip.to_string(), "::1");
     }
 
+    #[test]
+    fn find_service_account() {
+        let wl = |uid: &str, namespace: &str, service_account: &str| {
+            Arc::new(Workload {
+                uid: uid.into(),
+                namespace: namespace.into(),
+                service_account: service_account.into(),
+                ..crate::test_helpers::test_default_workload()
+            })
+        };
+        let uids = |store: &WorkloadStore, namespace: &str, service_account: &str| {
+            let mut uids: Vec<_> = store
+                .find_service_account(&namespace.into(), &service_account.into())
+                .iter()
+                .map(|w| w.uid.to_string())
+                .collect();
+            uids.sort();
+            uids
+        };
+        let mut store = WorkloadStore::default();
+        store.insert(wl("a", "ns1", "sa1"), false);
+        store.insert(wl("b", "ns1", "sa1"), false);
+        store.insert(wl("c", "ns2", "sa1"), false);
+        assert_eq!(uids(&store, "ns1", "sa1"), vec!["a", "b"]);
+        assert_eq!(uids(&store, "ns2", "sa1"), vec!["c"]);
+
+        // Changing service account moves the workload
+        store.insert(wl("b", "ns1", "sa2"), false);
+        assert_eq!(uids(&store, "ns1", "sa1"), vec!["a"]);
+        assert_eq!(uids(&store, "ns1", "sa2"), vec!["b"]);
+
+        store.remove(&"a".into());
+        assert!(uids(&store, "ns1", "sa1").is_empty());
+        assert!(store
+            .by_service_account
+            .get(&("ns1".into(), "sa1".into()))
+            .is_none());
+    }
+
+    #[test]
+    fn captures_inbound_port() {
+        let mut wl = test_helpers::test_default_workload();
+        assert!(wl.captures_inbound_port(8080));
+
+        wl.excluded_inbound_ports = vec![9090];
+        assert!(wl.captures_inbound_port(8080));
+        assert!(!wl.captures_inbound_port(9090));
+
+        // Exclusions apply even to included ports
+        wl.included_inbound_ports = vec![8080, 9090];
+        assert!(wl.captures_inbound_port(8080));
+        assert!(!wl.captures_inbound_port(9090));
+        assert!(!wl.captures_inbound_port(7070));
+    }
+
     #[test]
     fn workload_information() {
         initialize_telemetry();
-        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let demand = DemandProxyState::new(
             state.clone(),
             None,
@@ -828,7 +1026,7 @@ mod tests {
 
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1.clone()],
@@ -837,10 +1035,10 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!(state.read().unwrap().workloads.by_addr.len(), 1);
-        assert_eq!(state.read().unwrap().workloads.by_uid.len(), 1);
+        assert_eq!(state.read().workloads.by_addr.len(), 1);
+        assert_eq!(state.read().workloads.by_uid.len(), 1);
         assert_eq!(
-            state.read().unwrap().workloads.find_address(&nw_addr1),
+            state.read().workloads.find_address(&nw_addr1),
             Some(Workload {
                 uid: uid1.as_str().into(),
                 workload_ips: vec![nw_addr1.address],
@@ -848,13 +1046,13 @@ mod tests {
                 ..test_helpers::test_default_workload()
             })
         );
-        assert_eq!(state.read().unwrap().services.num_vips(), 0);
-        assert_eq!(state.read().unwrap().services.num_services(), 0);
-        assert_eq!(state.read().unwrap().services.num_staged_services(), 0);
+        assert_eq!(state.read().services.num_vips(), 0);
+        assert_eq!(state.read().services.num_services(), 0);
+        assert_eq!(state.read().services.num_staged_services(), 0);
 
-        updater.remove(&mut state.write().unwrap(), &"/invalid".into());
+        updater.remove(&mut state.write(), &"/invalid".into());
         assert_eq!(
-            state.read().unwrap().workloads.find_address(&nw_addr1),
+            state.read().workloads.find_address(&nw_addr1),
             Some(Workload {
                 uid: uid1.as_str().into(),
                 workload_ips: vec![nw_addr1.address],
@@ -863,9 +1061,9 @@ mod tests {
             })
         );
 
-        updater.remove(&mut state.write().unwrap(), &uid2.as_str().into());
+        updater.remove(&mut state.write(), &uid2.as_str().into());
         assert_eq!(
-            state.read().unwrap().workloads.find_address(&nw_addr1),
+            state.read().workloads.find_address(&nw_addr1),
             Some(Workload {
                 uid: uid1.as_str().into(),
                 workload_ips: vec![nw_addr1.address],
@@ -874,18 +1072,15 @@ mod tests {
             })
         );
 
-        updater.remove(&mut state.write().unwrap(), &uid1.as_str().into());
-        assert_eq!(
-            state.read().unwrap().workloads.find_address(&nw_addr1),
-            None
-        );
-        assert_eq!(state.read().unwrap().workloads.by_addr.len(), 0);
-        assert_eq!(state.read().unwrap().workloads.by_uid.len(), 0);
+        updater.remove(&mut state.write(), &uid1.as_str().into());
+        assert_eq!(state.read().workloads.find_address(&nw_addr1), None);
+        assert_eq!(state.read().workloads.by_addr.len(), 0);
+        assert_eq!(state.read().workloads.by_uid.len(), 0);
 
         // Add two workloads into the VIP. Add out of order to further test
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1.clone()],
@@ -895,13 +1090,13 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!(state.read().unwrap().services.num_vips(), 0);
-        assert_eq!(state.read().unwrap().services.num_services(), 0);
-        assert_eq!(state.read().unwrap().services.num_staged_services(), 1);
+        assert_eq!(state.read().services.num_vips(), 0);
+        assert_eq!(state.read().services.num_services(), 0);
+        assert_eq!(state.read().services.num_staged_services(), 1);
 
         updater
             .insert_service(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsService {
                     name: "svc1".to_string(),
                     namespace: "ns".to_string(),
@@ -920,14 +1115,14 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!((state.read().unwrap().services.num_vips()), 1);
-        assert_eq!((state.read().unwrap().services.num_services()), 1);
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 0);
+        assert_eq!((state.read().services.num_vips()), 1);
+        assert_eq!((state.read().services.num_services()), 1);
+        assert_eq!((state.read().services.num_staged_services()), 0);
 
         // upsert the service to ensure the old endpoints (no longer staged) are carried over
         updater
             .insert_service(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsService {
                     name: "svc1".to_string(),
                     namespace: "ns".to_string(),
@@ -953,9 +1148,9 @@ mod tests {
             )
             .unwrap();
 
-        assert_eq!((state.read().unwrap().services.num_vips()), 2); // there are now two addresses on the same service
-        assert_eq!((state.read().unwrap().services.num_services()), 1); // there is still only one service
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 0);
+        assert_eq!((state.read().services.num_vips()), 2); // there are now two addresses on the same service
+        assert_eq!((state.read().services.num_services()), 1); // there is still only one service
+        assert_eq!((state.read().services.num_staged_services()), 0);
 
         // we need to ensure both copies of the service stored are the same.
         // this is important because we mutate the endpoints on a service in place
@@ -963,7 +1158,6 @@ mod tests {
         assert_eq!(
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_namespaced_host(&NamespacedHostname {
                     namespace: "ns".into(),
@@ -972,7 +1166,6 @@ mod tests {
                 .unwrap()),
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_vip(&NetworkAddress {
                     network: strng::EMPTY,
@@ -982,13 +1175,13 @@ mod tests {
         );
 
         // ensure we updated the old service, no duplication
-        assert_eq!((state.read().unwrap().services.num_vips()), 2); // there are now two addresses on the same service
-        assert_eq!((state.read().unwrap().services.num_services()), 1); // there is still only one service
+        assert_eq!((state.read().services.num_vips()), 2); // there are now two addresses on the same service
+        assert_eq!((state.read().services.num_services()), 1); // there is still only one service
 
         // upsert the service to remove an address and ensure services_by_ip map is properly cleaned up
         updater
             .insert_service(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsService {
                     name: "svc1".to_string(),
                     namespace: "ns".to_string(),
@@ -1008,13 +1201,13 @@ mod tests {
             )
             .unwrap();
 
-        assert_eq!(state.read().unwrap().services.num_vips(), 1); // we removed an address in upsert
-        assert_eq!(state.read().unwrap().services.num_services(), 1);
-        assert_eq!(state.read().unwrap().services.num_staged_services(), 0);
+        assert_eq!(state.read().services.num_vips(), 1); // we removed an address in upsert
+        assert_eq!(state.read().services.num_services(), 1);
+        assert_eq!(state.read().services.num_staged_services(), 0);
 
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid2.to_owned(),
                     addresses: vec![xds_ip2.clone()],
@@ -1024,9 +1217,9 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!(state.read().unwrap().services.num_vips(), 1);
-        assert_eq!(state.read().unwrap().services.num_services(), 1);
-        assert_eq!(state.read().unwrap().services.num_staged_services(), 0); // vip already in a service, should not be staged
+        assert_eq!(state.read().services.num_vips(), 1);
+        assert_eq!(state.read().services.num_services(), 1);
+        assert_eq!(state.read().services.num_staged_services(), 0); // vip already in a service, should not be staged
 
         // we need to ensure both copies of the service stored are the same.
         // this is important because we mutate the service endpoints in place
@@ -1034,7 +1227,6 @@ mod tests {
         assert_eq!(
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_namespaced_host(&NamespacedHostname {
                     namespace: "ns".into(),
@@ -1043,7 +1235,6 @@ mod tests {
                 .unwrap()),
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_vip(&NetworkAddress {
                     network: strng::EMPTY,
@@ -1053,7 +1244,7 @@ mod tests {
         );
 
         assert_vips(&demand, vec!["some name", "some name2"]);
-        updater.remove(&mut state.write().unwrap(), &uid2.as_str().into());
+        updater.remove(&mut state.write(), &uid2.as_str().into());
 
         // we need to ensure both copies of the service stored are the same.
         // this is important because we mutate the service endpoints in place
@@ -1061,7 +1252,6 @@ mod tests {
         assert_eq!(
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_namespaced_host(&NamespacedHostname {
                     namespace: "ns".into(),
@@ -1070,7 +1260,6 @@ mod tests {
                 .unwrap()),
             (state
                 .read()
-                .unwrap()
                 .services
                 .get_by_vip(&NetworkAddress {
                     network: strng::EMPTY,
@@ -1080,13 +1269,13 @@ mod tests {
         );
 
         assert_vips(&demand, vec!["some name"]);
-        updater.remove(&mut state.write().unwrap(), &uid1.as_str().into());
+        updater.remove(&mut state.write(), &uid1.as_str().into());
         assert_vips(&demand, vec![]);
 
         // Add 2 workload with VIP
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1.clone()],
@@ -1098,7 +1287,7 @@ mod tests {
             .unwrap();
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid2.to_owned(),
                     addresses: vec![xds_ip2.clone()],
@@ -1112,7 +1301,7 @@ mod tests {
         // now update it without the VIP
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1,
                     addresses: vec![xds_ip1],
@@ -1126,7 +1315,7 @@ mod tests {
         // now update it with unhealthy
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid2,
                     addresses: vec![xds_ip2],
@@ -1141,18 +1330,15 @@ mod tests {
         assert_vips(&demand, vec![]);
 
         // Remove the VIP entirely
-        updater.remove(
-            &mut state.write().unwrap(),
-            &"ns/svc1.ns.svc.cluster.local".into(),
-        );
-        assert_eq!(state.read().unwrap().services.num_vips(), 0);
-        assert_eq!((state.read().unwrap().services.num_services()), 0);
+        updater.remove(&mut state.write(), &"ns/svc1.ns.svc.cluster.local".into());
+        assert_eq!(state.read().services.num_vips(), 0);
+        assert_eq!((state.read().services.num_services()), 0);
     }
 
     #[test]
     fn staged_services_cleanup() {
         initialize_telemetry();
-        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let demand = DemandProxyState::new(
             state.clone(),
             None,
@@ -1160,11 +1346,11 @@ mod tests {
             ResolverOpts::default(),
         );
         let updater = ProxyStateUpdateMutator::new_no_fetch();
-        assert_eq!((state.read().unwrap().workloads.by_addr.len()), 0);
-        assert_eq!((state.read().unwrap().workloads.by_uid.len()), 0);
-        assert_eq!((state.read().unwrap().services.num_vips()), 0);
-        assert_eq!((state.read().unwrap().services.num_services()), 0);
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 0);
+        assert_eq!((state.read().workloads.by_addr.len()), 0);
+        assert_eq!((state.read().workloads.by_uid.len()), 0);
+        assert_eq!((state.read().services.num_vips()), 0);
+        assert_eq!((state.read().services.num_services()), 0);
+        assert_eq!((state.read().services.num_staged_services()), 0);
 
         let xds_ip1 = Bytes::copy_from_slice(&[127, 0, 0, 1]);
         let ip1 = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
@@ -1184,7 +1370,7 @@ mod tests {
         // Add 2 workload with service
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1.clone()],
@@ -1194,12 +1380,12 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 1);
+        assert_eq!((state.read().services.num_staged_services()), 1);
 
         // now update it without the service
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1.clone()],
@@ -1208,12 +1394,12 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 0); // should remove the VIP if no longer needed
+        assert_eq!((state.read().services.num_staged_services()), 0); // should remove the VIP if no longer needed
 
         // Add 2 workload with service again
         updater
             .insert_workload(
-                &mut state.write().unwrap(),
+                &mut state.write(),
                 XdsWorkload {
                     uid: uid1.to_owned(),
                     addresses: vec![xds_ip1],
@@ -1223,10 +1409,10 @@ mod tests {
                 },
             )
             .unwrap();
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 1); // VIP should be staged again
+        assert_eq!((state.read().services.num_staged_services()), 1); // VIP should be staged again
 
-        updater.remove(&mut state.write().unwrap(), &uid1.into());
-        assert_eq!((state.read().unwrap().services.num_staged_services()), 0); // should remove the VIP if no longer needed
+        updater.remove(&mut state.write(), &uid1.into());
+        assert_eq!((state.read().services.num_staged_services()), 0); // should remove the VIP if no longer needed
     }
 
     #[track_caller]
@@ -1242,10 +1428,11 @@ mod tests {
         .try_into()
         .unwrap();
         for _ in 0..1000 {
-            if let Some(us) = state.state.read().unwrap().find_upstream(
+            if let Some(us) = state.state.read().find_upstream(
                 strng::EMPTY,
                 &wl,
                 "127.0.1.1:80".parse().unwrap(),
+                &ConnectionManager::default(),
             ) {
                 let n = &us.workload.name; // borrow name instead of cloning
                 found.insert(n.to_string()); // insert an owned copy of the borrowed n
@@ -1260,6 +1447,65 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[cfg(target_os = "linux")]
+    async fn local_client_reload() {
+        let path = std::env::temp_dir().join(format!("ztunnel-local-{}.yaml", std::process::id()));
+        std::fs::copy(
+            std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("examples")
+                .join("localhost.yaml"),
+            &path,
+        )
+        .unwrap();
+        let state = ProxyStateCell::default();
+        let local_client = LocalClient {
+            cfg: ConfigSource::File(path.clone()),
+            state: state.clone(),
+            cert_fetcher: Arc::new(cert_fetcher::NoCertFetcher()),
+        };
+        local_client.run().await.expect("client should run");
+        let addr = network_addr(strng::EMPTY, "127.0.0.1".parse().unwrap());
+        assert!(state.read().workloads.find_address(&addr).is_some());
+
+        // An invalid update is ignored
+        std::fs::write(&path, "workloads: invalid").unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(state.read().workloads.find_address(&addr).is_some());
+
+        // So is one that only fails partway through loading
+        std::fs::write(
+            &path,
+            r#"
+workloads:
+- uid: cluster1//v1/Pod/default/other
+  name: other
+  namespace: default
+  workloadIps: ["127.0.0.100"]
+  services:
+    "missing-namespace":
+      80: 8080
+"#,
+        )
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(state.read().workloads.find_address(&addr).is_some());
+        let other = network_addr(strng::EMPTY, "127.0.0.100".parse().unwrap());
+        assert!(state.read().workloads.find_address(&other).is_none());
+
+        // A valid update replaces the config
+        std::fs::write(&path, "workloads: []").unwrap();
+        let start = std::time::Instant::now();
+        while state.read().workloads.find_address(&addr).is_some() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "config not reloaded"
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[tokio::test]
     async fn local_client() {
         let cfg = ConfigSource::File(
@@ -1267,7 +1513,7 @@ mod tests {
                 .join("examples")
                 .join("localhost.yaml"),
         );
-        let state = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let demand = DemandProxyState::new(
             state.clone(),
             None,
@@ -1283,16 +1529,16 @@ mod tests {
         let wl = demand
             .state
             .read()
-            .unwrap()
             .workloads
             .find_address(&network_addr(strng::EMPTY, "127.0.0.1".parse().unwrap()));
         // Make sure we get a valid workload
         assert!(wl.is_some());
         assert_eq!(wl.as_ref().unwrap().service_account, "default");
-        let us = demand.state.read().unwrap().find_upstream(
+        let us = demand.state.read().find_upstream(
             strng::EMPTY,
             wl.as_ref().unwrap(),
             "127.10.0.1:80".parse().unwrap(),
+            &ConnectionManager::default(),
         );
         // Make sure we get a valid VIP
         assert!(us.is_some());
@@ -1303,10 +1549,11 @@ mod tests {
         );
 
         // test that we can have a service in another network than workloads it selects
-        let us = demand.state.read().unwrap().find_upstream(
+        let us = demand.state.read().find_upstream(
             "remote".into(),
             wl.as_ref().unwrap(),
             "127.10.0.2:80".parse().unwrap(),
+            &ConnectionManager::default(),
         );
         // Make sure we get a valid VIP
         assert!(us.is_some());
@@ -12,36 +12,126 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::env;
+use std::borrow::Cow;
 use std::fmt::Debug;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
 use once_cell::sync::Lazy;
 use once_cell::sync::OnceCell;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use tracing_opentelemetry::OtelData;
 
 use thiserror::Error;
 use tracing::{error, field, info, warn, Event, Subscriber};
 
 use tracing_subscriber::fmt::format::Writer;
 
-use tracing_subscriber::fmt::{format, FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{filter, prelude::*, reload, Layer, Registry};
 
+use crate::proxy::TraceParent;
+
 pub static APPLICATION_START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
 static LOG_HANDLE: OnceCell<LogHandle> = OnceCell::new();
+static EXPORTING_TRACES: AtomicBool = AtomicBool::new(false);
 
-pub fn setup_logging() {
+/// setup_logging installs the log formatter and, if `otlp_endpoint` is set, exports the spans of
+/// proxied connections to it. The exporter runs on the calling tokio runtime.
+pub fn setup_logging(otlp_endpoint: Option<&str>) -> Result<(), Error> {
     Lazy::force(&APPLICATION_START_TIME);
-    tracing_subscriber::registry().with(fmt_layer()).init();
+    let mut layers = vec![fmt_layer()];
+    if let Some(endpoint) = otlp_endpoint {
+        layers.push(otlp_layer(endpoint)?);
+        EXPORTING_TRACES.store(true, Ordering::Relaxed);
+    }
+    tracing_subscriber::registry().with(layers).init();
+    Ok(())
+}
+
+// otlp_layer exports the spans of the proxy to an OTLP collector. Events are not exported, and
+// nor are spans outside of the proxy, such as the long lived ones of each pod.
+fn otlp_layer(endpoint: &str) -> Result<BoxLayer, Error> {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new([KeyValue::new("service.name", "ztunnel")]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    let filter = filter::filter_fn(|meta: &tracing::Metadata<'_>| {
+        meta.is_span() && meta.target().starts_with("ztunnel::proxy")
+    });
+    Ok(Box::new(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(filter),
+    ))
+}
+
+/// exporting_traces returns whether spans are exported over OTLP, in which case the traces ztunnel
+/// starts are marked as sampled.
+pub fn exporting_traces() -> bool {
+    EXPORTING_TRACES.load(Ordering::Relaxed)
+}
+
+/// set_trace_context makes `span` export over OTLP with the ids of `trace`, as a child of `parent`,
+/// or as the root of the trace if there is none. This way the exported spans match the traceparent
+/// headers ztunnel sends and receives, and correlate with the spans of the other hops.
+/// It must be called before any child of `span` is created.
+pub fn set_trace_context(span: &tracing::Span, trace: &TraceParent, parent: Option<&TraceParent>) {
+    if !exporting_traces() {
+        return;
+    }
+    span.with_subscriber(|(id, dispatch)| {
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(span) = registry.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        // Spans which are not exported have no OTel data.
+        let Some(data) = extensions.get_mut::<OtelData>() else {
+            return;
+        };
+        let trace = trace.span_context();
+        data.parent_cx = match parent {
+            Some(parent) => {
+                opentelemetry::Context::new().with_remote_span_context(parent.span_context())
+            }
+            None => opentelemetry::Context::new(),
+        };
+        data.builder.trace_id = Some(trace.trace_id());
+        data.builder.span_id = Some(trace.span_id());
+        data.builder.sampling_result = None;
+    });
+}
+
+/// shutdown_tracing exports the spans that have not been exported yet.
+pub async fn shutdown_tracing() {
+    if exporting_traces() {
+        // Shutting down blocks until the exporter, which runs on this runtime, is done.
+        if let Err(e) =
+            tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await
+        {
+            warn!("failed to shut down trace export: {e}");
+        }
+    }
 }
 
 fn json_fmt() -> Box<dyn Layer<Registry> + Send + Sync + 'static> {
-    let format = tracing_subscriber::fmt::format().json().flatten_event(true);
     let format = tracing_subscriber::fmt::layer()
-        .event_format(format)
-        .fmt_fields(format::JsonFields::default());
+        .event_format(IstioJsonFormat())
+        .fmt_fields(IstioJsonFormat());
     Box::new(format)
 }
 
@@ -53,7 +143,8 @@ fn plain_fmt() -> Box<dyn Layer<Registry> + Send + Sync + 'static> {
 }
 
 fn fmt_layer() -> Box<dyn Layer<Registry> + Send + Sync + 'static> {
-    let format = if env::var("LOG_FORMAT").unwrap_or("plain".to_string()) == "json" {
+    // LOG_FORMAT=json replaces the human readable format with one JSON object per line.
+    let format = if crate::config::setting("LOG_FORMAT").unwrap_or("plain".to_string()) == "json" {
         json_fmt()
     } else {
         plain_fmt()
@@ -68,8 +159,7 @@ fn fmt_layer() -> Box<dyn Layer<Registry> + Send + Sync + 'static> {
 
 fn default_filter() -> filter::Targets {
     // Read from env var, but prefix with setting DNS logs to warn as they are noisy; they can be explicitly overriden
-    let var: String = env::var("RUST_LOG")
-        .map_err(|_| ())
+    let var: String = crate::config::setting("RUST_LOG")
         .map(|v| "hickory_server::server::server_future=off,".to_string() + v.as_str())
         .unwrap_or("hickory_server::server::server_future=off,info".to_string());
     filter::Targets::from_str(&var).expect("static filter should build")
@@ -130,6 +220,8 @@ pub enum Error {
     Reload(#[from] reload::Error),
     #[error("logging is not initialized")]
     Uninitialized,
+    #[error("failed to set up OTLP export: {0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
 }
 
 // IstioFormat encodes logs in the "standard" Istio formatting used in the rest of the code
@@ -241,6 +333,162 @@ where
     }
 }
 
+// IstioJsonFormat encodes logs as one JSON object per line. Fields of the enclosing spans are
+// flattened into each event, and field names are normalized so the same value always has the
+// same key, regardless of where in the code it was logged.
+struct IstioJsonFormat();
+
+// json_field_name maps a tracing field name to the key it is written under in JSON logs.
+fn json_field_name(name: &str) -> Cow<'static, str> {
+    let normalize = |field: &str| match field {
+        "addr" => "address".to_string(),
+        f => f.to_string(),
+    };
+    match name {
+        "message" => Cow::Borrowed("msg"),
+        "connection_id" => Cow::Borrowed("connection.id"),
+        "peer_id" => Cow::Borrowed("source.identity"),
+        "src" => Cow::Borrowed("source.address"),
+        "dst" => Cow::Borrowed("destination.address"),
+        _ => {
+            if let Some(field) = name.strip_prefix("src.") {
+                Cow::Owned(format!("source.{}", normalize(field)))
+            } else if let Some(field) = name.strip_prefix("dst.") {
+                Cow::Owned(format!("destination.{}", normalize(field)))
+            } else {
+                Cow::Owned(name.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonVisitor {
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JsonVisitor {
+    fn insert(&mut self, field: &field::Field, value: serde_json::Value) {
+        // Skip fields that are actually log metadata that is handled separately
+        if field.name().starts_with("log.") {
+            return;
+        }
+        self.fields
+            .insert(json_field_name(field.name()).into_owned(), value);
+    }
+}
+
+impl field::Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &field::Field, value: f64) {
+        self.insert(field, value.into())
+    }
+
+    fn record_i64(&mut self, field: &field::Field, value: i64) {
+        self.insert(field, value.into())
+    }
+
+    fn record_u64(&mut self, field: &field::Field, value: u64) {
+        self.insert(field, value.into())
+    }
+
+    fn record_bool(&mut self, field: &field::Field, value: bool) {
+        self.insert(field, value.into())
+    }
+
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        self.insert(field, value.into())
+    }
+
+    fn record_debug(&mut self, field: &field::Field, val: &dyn std::fmt::Debug) {
+        self.insert(field, format!("{val:?}").into())
+    }
+}
+
+impl<'writer> FormatFields<'writer> for IstioJsonFormat {
+    fn format_fields<R: tracing_subscriber::field::RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> std::fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        let encoded = serde_json::to_string(&visitor.fields).map_err(|_| std::fmt::Error)?;
+        write!(writer, "{encoded}")
+    }
+
+    // Fields recorded on a span after creation are merged into its JSON object, rather than
+    // appended to it.
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> std::fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        if let Ok(serde_json::Value::Object(existing)) =
+            serde_json::from_str::<serde_json::Value>(&current.fields)
+        {
+            visitor.fields = existing;
+        }
+        fields.record(&mut visitor);
+        current.fields = serde_json::to_string(&visitor.fields).map_err(|_| std::fmt::Error)?;
+        Ok(())
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for IstioJsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        use tracing_log::NormalizeEvent;
+        use tracing_subscriber::fmt::time::FormatTime;
+        use tracing_subscriber::fmt::time::SystemTime;
+        let normalized_meta = event.normalized_metadata();
+        let meta = normalized_meta.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut time = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut time))?;
+        let target = meta.target();
+        let target = target.strip_prefix("ztunnel::").unwrap_or(target);
+
+        let mut log = serde_json::Map::new();
+        log.insert("time".to_string(), time.into());
+        log.insert(
+            "level".to_string(),
+            meta.level().to_string().to_ascii_lowercase().into(),
+        );
+        log.insert("scope".to_string(), target.into());
+
+        // Flatten span fields, outermost first, so inner spans take precedence.
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let ext = span.extensions();
+                let Some(fields) = ext.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                if let Ok(serde_json::Value::Object(fields)) =
+                    serde_json::from_str::<serde_json::Value>(fields)
+                {
+                    log.extend(fields);
+                }
+            }
+        }
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        log.extend(visitor.fields);
+
+        let encoded = serde_json::to_string(&log).map_err(|_| std::fmt::Error)?;
+        writeln!(writer, "{encoded}")
+    }
+}
+
 /// Mod testing gives access to a test logger, which stores logs in memory for querying.
 /// Inspired by https://github.com/dbrgn/tracing-test
 #[cfg(any(test, feature = "testing"))]
@@ -355,3 +603,56 @@ pub mod testing {
             .init();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::testing::MockWriter;
+    use std::sync::Mutex;
+    use tracing::{info, info_span};
+
+    #[test]
+    fn json_format() {
+        let buf = Mutex::new(vec![]);
+        let writer = MockWriter::new(&buf);
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .event_format(IstioJsonFormat())
+                .fmt_fields(IstioJsonFormat())
+                .with_writer(writer),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "outbound",
+                connection_id = "abc",
+                peer_id = tracing::field::Empty
+            );
+            span.record("peer_id", "spiffe://cluster.local/ns/default/sa/client");
+            let _enter = span.enter();
+            info!(
+                src.addr = "10.0.0.1:1234",
+                dst.identity = "spiffe://cluster.local/ns/default/sa/server",
+                bytes_sent = 10u64,
+                "connection complete"
+            );
+        });
+
+        let line = String::from_utf8(buf.into_inner().unwrap()).unwrap();
+        let log: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(log["level"], "info");
+        assert_eq!(log["scope"], "telemetry::tests");
+        assert_eq!(log["msg"], "connection complete");
+        assert_eq!(log["connection.id"], "abc");
+        assert_eq!(
+            log["source.identity"],
+            "spiffe://cluster.local/ns/default/sa/client"
+        );
+        assert_eq!(log["source.address"], "10.0.0.1:1234");
+        assert_eq!(
+            log["destination.identity"],
+            "spiffe://cluster.local/ns/default/sa/server"
+        );
+        assert_eq!(log["bytes_sent"], 10);
+        assert!(log["time"].is_string());
+    }
+}
@@ -14,13 +14,14 @@
 
 use crate::config::ConfigSource;
 use crate::config::{self, RootCert};
+use crate::state::cell::ProxyStateCell;
 use crate::state::service::{Endpoint, Service};
 use crate::state::workload::Protocol;
 use crate::state::workload::Protocol::{HBONE, TCP};
 use crate::state::workload::{
     gatewayaddress, GatewayAddress, NamespacedHostname, NetworkAddress, Workload,
 };
-use crate::state::{DemandProxyState, ProxyState};
+use crate::state::DemandProxyState;
 use crate::xds::istio::security::Authorization as XdsAuthorization;
 use crate::xds::istio::workload::address;
 use crate::xds::istio::workload::Address as XdsAddress;
@@ -40,7 +41,6 @@ use std::fmt::Debug;
 use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Add;
-use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::error::SendError;
 use tokio::time::timeout;
@@ -107,7 +107,7 @@ pub fn test_config_with_port_xds_addr_and_root_cert(
         readiness_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         stats_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         outbound_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
-        inbound_plaintext_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+        inbound_plaintext_addr: vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)],
         dns_proxy_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
         ..config::parse_config().unwrap()
     };
@@ -172,6 +172,8 @@ pub fn mock_default_service() -> Service {
         subject_alt_names: vec![],
         waypoint: None,
         load_balancer: None,
+        session_affinity: Default::default(),
+        tls_origination: None,
     }
 }
 
@@ -201,6 +203,11 @@ pub fn test_default_workload() -> Workload {
         native_tunnel: false,
         application_tunnel: None,
         locality: Default::default(),
+        connection_limit: None,
+        mirror_address: None,
+        excluded_inbound_ports: Default::default(),
+        included_inbound_ports: Default::default(),
+        hbone_quic: false,
     }
 }
 
@@ -278,6 +285,8 @@ fn test_custom_svc(
         subject_alt_names: vec!["spiffe://cluster.local/ns/default/sa/default".into()],
         waypoint: None,
         load_balancer: None,
+        session_affinity: Default::default(),
+        tls_origination: None,
     })
 }
 
@@ -414,7 +423,7 @@ pub fn new_proxy_state(
     xds_services: &[XdsService],
     xds_authorizations: &[XdsAuthorization],
 ) -> DemandProxyState {
-    let state = Arc::new(RwLock::new(ProxyState::default()));
+    let state = ProxyStateCell::default();
     let updater = ProxyStateUpdater::new_no_fetch(state.clone());
 
     for w in xds_workloads {
@@ -77,13 +77,17 @@ impl CaServer {
         });
         let client = CaClient::new(
             "https://".to_string() + &server_addr.to_string(),
-            Box::new(tls::ControlPlaneAuthentication::RootCert(root_cert)),
+            Box::new(tls::ControlPlaneAuthentication::RootCert(
+                root_cert,
+                Default::default(),
+            )),
             AuthSource::Token(
                 PathBuf::from(r"src/test_helpers/fake-jwt"),
                 "Kubernetes".to_string(),
             ),
             true,
             60 * 60 * 24,
+            &Default::default(),
         )
         .await
         .unwrap();
@@ -144,6 +144,8 @@ impl WorkloadManager {
                 inbound: "0.0.0.0:0".parse()?,
                 outbound: "0.0.0.0:0".parse()?,
                 socks5: Some("0.0.0.0:0".parse()?),
+                sni_router: None,
+                forward_proxy: None,
             });
 
             let ta = TestApp {
@@ -155,6 +157,10 @@ impl WorkloadManager {
                     outbound: helpers::with_ip(proxy_addresses.outbound, ip),
                     inbound: helpers::with_ip(proxy_addresses.inbound, ip),
                     socks5: proxy_addresses.socks5.map(|i| helpers::with_ip(i, ip)),
+                    sni_router: proxy_addresses.sni_router.map(|i| helpers::with_ip(i, ip)),
+                    forward_proxy: proxy_addresses
+                        .forward_proxy
+                        .map(|i| helpers::with_ip(i, ip)),
                 },
                 tcp_dns_proxy_address: Some(helpers::with_ip(
                     app.tcp_dns_proxy_address.unwrap_or("0.0.0.0:0".parse()?),
@@ -270,6 +276,8 @@ impl<'a> TestServiceBuilder<'a> {
                 subject_alt_names: vec![],
                 waypoint: None,
                 load_balancer: None,
+                session_affinity: Default::default(),
+                tls_origination: None,
             },
             manager,
         }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::pin::Pin;
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::xds::istio::security::Authorization as XdsAuthorization;
@@ -35,7 +35,8 @@ use super::test_config_with_port_xds_addr_and_root_cert;
 use crate::config::RootCert;
 use crate::hyper_util::TokioExecutor;
 use crate::metrics::sub_registry;
-use crate::state::{DemandProxyState, ProxyState};
+use crate::state::cell::ProxyStateCell;
+use crate::state::DemandProxyState;
 use crate::tls;
 use crate::xds::service::discovery::v3::aggregated_discovery_service_server::{
     AggregatedDiscoveryService, AggregatedDiscoveryServiceServer,
@@ -54,46 +55,57 @@ pub struct AdsConnection {
     pub rx: mpsc::Receiver<DeltaDiscoveryRequest>,
 }
 
+type Spawned = (
+    mpsc::Receiver<AdsConnection>,
+    AdsClient,
+    DemandProxyState,
+    tokio::sync::watch::Receiver<()>,
+);
+
 impl AdsServer {
-    pub async fn spawn(
-        xds_on_demand: bool,
-    ) -> (
-        mpsc::Receiver<AdsConnection>,
-        AdsClient,
-        DemandProxyState,
-        tokio::sync::watch::Receiver<()>,
-    ) {
+    pub async fn spawn(xds_on_demand: bool) -> Spawned {
+        Self::spawn_internal(xds_on_demand, false).await
+    }
+
+    /// spawn_plaintext spawns a server the client connects to without TLS.
+    pub async fn spawn_plaintext(xds_on_demand: bool) -> Spawned {
+        Self::spawn_internal(xds_on_demand, true).await
+    }
+
+    async fn spawn_internal(xds_on_demand: bool, plaintext: bool) -> Spawned {
         let (tx, rx) = mpsc::channel(100);
 
         let server = AdsServer { tx };
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
         let server_addr = listener.local_addr().unwrap();
-        let certs = tls::mock::generate_test_certs(
-            &server_addr.ip().into(),
-            Duration::from_secs(0),
-            Duration::from_secs(100),
-        );
-        let root_cert = RootCert::Static(certs.chain.iter().map(|c| c.as_pem()).join("\n").into());
-        let acceptor = tls::mock::MockServerCertProvider::new(certs);
-        let listener_addr_string = "https://".to_string() + &server_addr.to_string();
-        let mut tls_stream = crate::hyper_util::tls_server(acceptor, listener);
         let srv = AggregatedDiscoveryServiceServer::new(server);
-        tokio::spawn(async move {
-            while let Some(socket) = tls_stream.next().await {
-                let srv = srv.clone();
-                tokio::spawn(async move {
-                    if let Err(err) = http2::Builder::new(TokioExecutor)
-                        .serve_connection(
-                            TokioIo::new(socket),
-                            tower_hyper_http_body_compat::TowerService03HttpServiceAsHyper1HttpService::new(srv)
-                        )
-                        .await
-                    {
-                        error!("Error serving connection: {:?}", err);
-                    }
-                });
-            }
-        });
+        let (listener_addr_string, root_cert) = if plaintext {
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    serve(srv.clone(), socket);
+                }
+            });
+            ("http://".to_string() + &server_addr.to_string(), None)
+        } else {
+            let certs = tls::mock::generate_test_certs(
+                &server_addr.ip().into(),
+                Duration::from_secs(0),
+                Duration::from_secs(100),
+            );
+            let root_cert =
+                RootCert::Static(certs.chain.iter().map(|c| c.as_pem()).join("\n").into());
+            let acceptor = tls::mock::MockServerCertProvider::new(certs);
+            let mut tls_stream = crate::hyper_util::tls_server(acceptor, listener);
+            tokio::spawn(async move {
+                while let Some(socket) = tls_stream.next().await {
+                    serve(srv.clone(), socket);
+                }
+            });
+            (
+                "https://".to_string() + &server_addr.to_string(),
+                Some(root_cert),
+            )
+        };
 
         let mut registry = Registry::default();
         let istio_registry = sub_registry(&mut registry);
@@ -104,12 +116,13 @@ impl AdsServer {
         let mut cfg = test_config_with_port_xds_addr_and_root_cert(
             80,
             Some(listener_addr_string),
-            Some(root_cert),
+            root_cert,
             None,
         );
         cfg.xds_on_demand = xds_on_demand;
+        cfg.xds_plaintext = plaintext;
 
-        let state: Arc<RwLock<ProxyState>> = Arc::new(RwLock::new(ProxyState::default()));
+        let state = ProxyStateCell::default();
         let dstate = DemandProxyState::new(
             state.clone(),
             None,
@@ -119,6 +132,7 @@ impl AdsServer {
         let store_updater = ProxyStateUpdater::new_no_fetch(state);
         let tls_client_fetcher = Box::new(tls::ControlPlaneAuthentication::RootCert(
             cfg.xds_root_cert.clone(),
+            cfg.tls_settings.clone(),
         ));
         let xds_client = xds::Config::new(Arc::new(cfg), tls_client_fetcher)
             .with_watched_handler::<XdsAddress>(xds::ADDRESS_TYPE, store_updater.clone())
@@ -129,6 +143,25 @@ impl AdsServer {
     }
 }
 
+fn serve<S>(srv: AggregatedDiscoveryServiceServer<AdsServer>, socket: S)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(err) = http2::Builder::new(TokioExecutor)
+            .serve_connection(
+                TokioIo::new(socket),
+                tower_hyper_http_body_compat::TowerService03HttpServiceAsHyper1HttpService::new(
+                    srv,
+                ),
+            )
+            .await
+        {
+            error!("Error serving connection: {:?}", err);
+        }
+    });
+}
+
 #[async_trait]
 impl AggregatedDiscoveryService for AdsServer {
     type StreamAggregatedResourcesStream =
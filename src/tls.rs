@@ -14,17 +14,28 @@
 
 mod certificate;
 mod control;
+mod crl;
 pub mod csr;
+mod federation;
 mod lib;
 #[cfg(any(test, feature = "testing"))]
 pub mod mock;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod private_key;
+mod session;
 mod workload;
 
 use std::sync::Arc;
 
 pub use crate::tls::certificate::*;
 pub use crate::tls::control::*;
+pub use crate::tls::crl::*;
+pub use crate::tls::federation::*;
 pub use crate::tls::lib::*;
+#[cfg(feature = "pkcs11")]
+pub use crate::tls::pkcs11::*;
+pub use crate::tls::private_key::*;
 pub use crate::tls::workload::*;
 use hyper::http::uri::InvalidUri;
 use rustls::server::VerifierBuilderError;
@@ -59,6 +70,31 @@ pub enum Error {
 
     #[error("failed to build server verifier: {0}")]
     ServerVerifierBuilderError(#[from] VerifierBuilderError),
+
+    #[error("invalid tls settings: {0}")]
+    InvalidTlsSettings(String),
+
+    #[error("invalid trust bundle: {0}")]
+    InvalidTrustBundle(String),
+
+    #[error("failed to fetch trust bundle: {0}")]
+    TrustBundleFetch(String),
+
+    #[error("invalid crl: {0}")]
+    InvalidCrl(String),
+
+    #[error("failed to fetch crl: {0}")]
+    CrlFetch(String),
+
+    #[error("private key storage: {0}")]
+    KeyStorage(String),
+
+    #[error("key generation: {0}")]
+    KeyGeneration(String),
+
+    #[error("pkcs11: {0}")]
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(String),
 }
 
 impl From<InvalidUri> for Error {
@@ -12,27 +12,34 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::RootCert;
+use crate::config::{AdminTls, GrpcConfig, RootCert};
 use crate::tls::lib::provider;
-use crate::tls::{ClientCertProvider, Error, WorkloadCertificate};
+use crate::tls::{ClientCertProvider, Error, TlsSettings, WorkloadCertificate};
 use bytes::Bytes;
 use http_body_1::{Body, Frame};
 use hyper::body::Incoming;
 use hyper::Uri;
 use hyper_rustls::HttpsConnector;
-use hyper_util::client::legacy::connect::HttpConnector;
-use rustls::ClientConfig;
+use hyper_util::client::legacy::connect::{Connect, Connected, Connection, HttpConnector};
+use hyper_util::rt::TokioIo;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
 use std::future::Future;
 use std::io::Cursor;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::{Arc, RwLock};
 
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use tonic::body::BoxBody;
 use tower_hyper_http_body_compat::{
     http02_request_to_http1, http1_response_to_http02, HttpBody04ToHttpBody1, HttpBody1ToHttpBody04,
 };
+use tracing::{info, warn};
 
 async fn root_to_store(root_cert: &RootCert) -> Result<rustls::RootCertStore, Error> {
     let mut roots = rustls::RootCertStore::empty();
@@ -54,18 +61,201 @@ async fn root_to_store(root_cert: &RootCert) -> Result<rustls::RootCertStore, Er
                 .map_err(|e| Error::InvalidRootCert(e.to_string()))?;
             roots.add_parsable_certificates(certs);
         }
+        RootCert::Directory(dir) => {
+            roots = load_root_dir(dir).await?;
+        }
         RootCert::Default => {
-            let certs = rustls_native_certs::load_native_certs()
-                .map_err(|e| Error::InvalidRootCert(e.to_string()))?;
-            roots.add_parsable_certificates(certs);
+            roots = native_roots()?;
         }
     };
     Ok(roots)
 }
 
+fn native_roots() -> Result<rustls::RootCertStore, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    let certs = rustls_native_certs::load_native_certs()
+        .map_err(|e| Error::InvalidRootCert(e.to_string()))?;
+    roots.add_parsable_certificates(certs);
+    Ok(roots)
+}
+
+/// admin_server_config builds the TLS config for the admin and stats servers. Clients must present a
+/// certificate issued by the configured client CA.
+pub async fn admin_server_config(
+    settings: &TlsSettings,
+    tls: &AdminTls,
+) -> Result<rustls::ServerConfig, Error> {
+    let read = |path: &Path| {
+        std::fs::read(path)
+            .map_err(|e| Error::CertificateParseError(format!("{}: {e}", path.display())))
+    };
+    let chain = rustls_pemfile::certs(&mut Cursor::new(read(&tls.cert)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::CertificateParseError(e.to_string()))?;
+    let key = rustls_pemfile::private_key(&mut Cursor::new(read(&tls.key)?))
+        .map_err(|e| Error::CertificateParseError(e.to_string()))?
+        .ok_or_else(|| Error::CertificateParseError("no private key found".to_string()))?;
+
+    let roots = root_to_store(&tls.client_ca).await?;
+    let verifier =
+        rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()?;
+    let mut sc = rustls::ServerConfig::builder_with_provider(settings.provider())
+        .with_protocol_versions(settings.versions())
+        .expect("server config must be valid")
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(chain, key)?;
+    // The servers only speak HTTP/1.1
+    sc.alpn_protocols = vec![b"http/1.1".into()];
+    Ok(sc)
+}
+
+// How often a root certificate directory is checked for changes
+const ROOT_DIR_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// root_dir_files returns the certificate files in a directory, along with their modification time.
+// Hidden files are skipped; this excludes the "..data" style entries of Kubernetes volume mounts,
+// while still following the symlinks to the actual files.
+async fn root_dir_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        // Follow symlinks
+        let meta = tokio::fs::metadata(&path).await?;
+        if meta.is_file() {
+            files.push((path, meta.modified()?));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+async fn load_root_dir(dir: &Path) -> Result<rustls::RootCertStore, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    let files = root_dir_files(dir)
+        .await
+        .map_err(|e| Error::InvalidRootCert(e.to_string()))?;
+    for (path, _) in files {
+        let certfile = tokio::fs::read(&path)
+            .await
+            .map_err(|e| Error::InvalidRootCert(e.to_string()))?;
+        let mut reader = std::io::BufReader::new(Cursor::new(certfile));
+        let certs = rustls_pemfile::certs(&mut reader)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidRootCert(format!("{}: {e}", path.display())))?;
+        roots.add_parsable_certificates(certs);
+    }
+    if roots.is_empty() {
+        return Err(Error::InvalidRootCert(format!(
+            "no root certificates found in {}",
+            dir.display()
+        )));
+    }
+    Ok(roots)
+}
+
+/// RootDirVerifier verifies server certificates against the roots found in a directory. The
+/// directory is watched, and the roots are reloaded whenever it changes, so root rotation applies
+/// to new connections without a restart.
+#[derive(Debug)]
+struct RootDirVerifier {
+    current: RwLock<Arc<WebPkiServerVerifier>>,
+}
+
+impl RootDirVerifier {
+    async fn new(dir: PathBuf) -> Result<Arc<Self>, Error> {
+        let verifier = Arc::new(Self {
+            current: RwLock::new(Self::build(&dir).await?),
+        });
+        let weak = Arc::downgrade(&verifier);
+        tokio::spawn(async move {
+            let mut last = root_dir_files(&dir).await.ok();
+            let mut interval = tokio::time::interval(ROOT_DIR_POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                // Stop watching once nothing is using the verifier anymore
+                let Some(verifier) = weak.upgrade() else {
+                    return;
+                };
+                let current = root_dir_files(&dir).await.ok();
+                if current == last {
+                    continue;
+                }
+                last = current;
+                match Self::build(&dir).await {
+                    Ok(v) => {
+                        info!("reloaded root certificates from {}", dir.display());
+                        *verifier.current.write().expect("mutex") = v;
+                    }
+                    Err(e) => warn!(
+                        "failed to reload root certificates from {}, keeping previous roots: {e}",
+                        dir.display()
+                    ),
+                }
+            }
+        });
+        Ok(verifier)
+    }
+
+    async fn build(dir: &Path) -> Result<Arc<WebPkiServerVerifier>, Error> {
+        let roots = load_root_dir(dir).await?;
+        Ok(WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider()).build()?)
+    }
+
+    fn current(&self) -> Arc<WebPkiServerVerifier> {
+        self.current.read().expect("mutex").clone()
+    }
+}
+
+impl ServerCertVerifier for RootDirVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.current().verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current().verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.current().verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.current().supported_verify_schemes()
+    }
+}
+
 #[derive(Debug)]
 pub enum ControlPlaneAuthentication {
-    RootCert(RootCert),
+    RootCert(RootCert, TlsSettings),
     ClientBundle(WorkloadCertificate),
 }
 
@@ -73,8 +263,8 @@ pub enum ControlPlaneAuthentication {
 impl ClientCertProvider for ControlPlaneAuthentication {
     async fn fetch_cert(&self) -> Result<ClientConfig, Error> {
         match self {
-            ControlPlaneAuthentication::RootCert(root_cert) => {
-                control_plane_client_config(root_cert).await
+            ControlPlaneAuthentication::RootCert(root_cert, settings) => {
+                control_plane_client_config(settings, root_cert).await
             }
             ControlPlaneAuthentication::ClientBundle(_bundle) => {
                 // TODO: implement this. Its is not currently used so no need.
@@ -84,14 +274,44 @@ impl ClientCertProvider for ControlPlaneAuthentication {
     }
 }
 
-async fn control_plane_client_config(root_cert: &RootCert) -> Result<ClientConfig, Error> {
+/// origination_client_config builds the config used to originate simple TLS to services outside the
+/// mesh, on behalf of plaintext clients. No client certificate is presented.
+pub async fn origination_client_config(
+    settings: &TlsSettings,
+    root_cert: &RootCert,
+) -> Result<ClientConfig, Error> {
     let roots = root_to_store(root_cert).await?;
-    Ok(ClientConfig::builder_with_provider(provider())
-        .with_protocol_versions(crate::tls::TLS_VERSIONS)?
+    Ok(ClientConfig::builder_with_provider(settings.provider())
+        .with_protocol_versions(settings.versions())?
         .with_root_certificates(roots)
         .with_no_client_auth())
 }
 
+/// dns_client_config builds the config used to connect to DNS-over-TLS upstreams, which are
+/// verified against the system roots.
+pub fn dns_client_config(settings: &TlsSettings) -> Result<ClientConfig, Error> {
+    Ok(ClientConfig::builder_with_provider(settings.provider())
+        .with_protocol_versions(settings.versions())?
+        .with_root_certificates(native_roots()?)
+        .with_no_client_auth())
+}
+
+async fn control_plane_client_config(
+    settings: &TlsSettings,
+    root_cert: &RootCert,
+) -> Result<ClientConfig, Error> {
+    let builder = ClientConfig::builder_with_provider(settings.provider())
+        .with_protocol_versions(settings.versions())?;
+    if let RootCert::Directory(dir) = root_cert {
+        return Ok(builder
+            .dangerous()
+            .with_custom_certificate_verifier(RootDirVerifier::new(dir.clone()).await?)
+            .with_no_client_auth());
+    }
+    let roots = root_to_store(root_cert).await?;
+    Ok(builder.with_root_certificates(roots).with_no_client_auth())
+}
+
 #[derive(Clone, Debug)]
 pub struct TlsGrpcChannel {
     uri: Uri,
@@ -99,14 +319,54 @@ pub struct TlsGrpcChannel {
 }
 
 /// grpc_connector provides a client TLS channel for gRPC requests.
-pub async fn grpc_tls_connector(uri: String, root_cert: RootCert) -> Result<TlsGrpcChannel, Error> {
-    grpc_connector(uri, control_plane_client_config(&root_cert).await?)
+pub async fn grpc_tls_connector(
+    settings: &TlsSettings,
+    uri: String,
+    root_cert: RootCert,
+    grpc: &GrpcConfig,
+) -> Result<TlsGrpcChannel, Error> {
+    grpc_connector(
+        uri,
+        control_plane_client_config(settings, &root_cert).await?,
+        grpc,
+    )
 }
 
 /// grpc_connector provides a client TLS channel for gRPC requests.
-pub fn grpc_connector(uri: String, cc: ClientConfig) -> Result<TlsGrpcChannel, Error> {
+pub fn grpc_connector(
+    uri: String,
+    cc: ClientConfig,
+    grpc: &GrpcConfig,
+) -> Result<TlsGrpcChannel, Error> {
+    let uri = Uri::try_from(uri)?;
+    let https: HttpsConnector<HttpConnector> = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(cc)
+        .https_only()
+        .enable_http2()
+        .wrap_connector(grpc_http_connector());
+    let client = grpc_client_builder(grpc).build(https);
+
+    Ok(TlsGrpcChannel { uri, client })
+}
+
+/// PlaintextGrpcChannel is a gRPC channel without TLS, for development control planes.
+#[derive(Clone, Debug)]
+pub struct PlaintextGrpcChannel {
+    uri: Uri,
+    client: hyper_util::client::legacy::Client<HttpConnector, BoxBody1>,
+}
+
+/// grpc_plaintext_connector provides a client channel for gRPC requests without TLS.
+pub fn grpc_plaintext_connector(
+    uri: String,
+    grpc: &GrpcConfig,
+) -> Result<PlaintextGrpcChannel, Error> {
     let uri = Uri::try_from(uri)?;
-    let _is_localhost_call = uri.host() == Some("localhost");
+    let client = grpc_client_builder(grpc).build(grpc_http_connector());
+    Ok(PlaintextGrpcChannel { uri, client })
+}
+
+fn grpc_http_connector() -> HttpConnector {
     let mut http: HttpConnector = HttpConnector::new();
     // Set keepalives to match istio's Envoy bootstrap configuration:
     // https://github.com/istio/istio/blob/a29d5c9c27d80bff31f218936f5a96759d8911c8/tools/packaging/common/envoy_bootstrap.json#L322C14-L322C28
@@ -118,22 +378,112 @@ pub fn grpc_connector(uri: String, cc: ClientConfig) -> Result<TlsGrpcChannel, E
     http.set_keepalive_retries(Some(9));
     http.set_connect_timeout(Some(Duration::from_secs(5)));
     http.enforce_http(false);
-    let https: HttpsConnector<HttpConnector> = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_tls_config(cc)
-        .https_only()
-        .enable_http2()
-        .wrap_connector(http);
+    http
+}
 
-    // Configure hyper's client to be h2 only and build with the
-    // correct https connector.
+// Configures hyper's client to be h2 only. Pings are also sent between requests, so a half-open
+// connection (e.g. dropped by a NAT or load balancer) is detected before the next request hangs
+// on it.
+fn grpc_client_builder(grpc: &GrpcConfig) -> hyper_util::client::legacy::Builder {
+    let mut builder =
+        hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new());
+    builder
+        .http2_only(true)
+        .http2_keep_alive_interval(grpc.keepalive_interval)
+        .http2_keep_alive_timeout(grpc.keepalive_timeout)
+        .http2_keep_alive_while_idle(true)
+        .timer(crate::hyper_util::TokioTimer);
+    builder
+}
+
+#[cfg(unix)]
+/// UdsGrpcChannel is a plaintext gRPC channel over a unix domain socket.
+#[derive(Clone, Debug)]
+pub struct UdsGrpcChannel {
+    uri: Uri,
+    client: hyper_util::client::legacy::Client<UdsConnector, BoxBody1>,
+}
+
+#[cfg(unix)]
+/// grpc_uds_connector provides a client channel for gRPC requests to a server listening on a
+/// unix domain socket.
+pub fn grpc_uds_connector(path: PathBuf) -> UdsGrpcChannel {
     let client = hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
         .http2_only(true)
-        .http2_keep_alive_interval(Duration::from_secs(30))
-        .http2_keep_alive_timeout(Duration::from_secs(10))
         .timer(crate::hyper_util::TokioTimer)
-        .build(https);
+        .build(UdsConnector {
+            path: Arc::new(path),
+        });
+    UdsGrpcChannel {
+        // The authority is meaningless over a unix socket, but HTTP/2 requires one.
+        uri: Uri::from_static("http://localhost"),
+        client,
+    }
+}
 
-    Ok(TlsGrpcChannel { uri, client })
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct UdsConnector {
+    path: Arc<PathBuf>,
+}
+
+#[cfg(unix)]
+impl tower::Service<Uri> for UdsConnector {
+    type Response = UdsStream;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, _: Uri) -> Self::Future {
+        let path = self.path.clone();
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(path.as_path()).await?;
+            Ok(UdsStream(TokioIo::new(stream)))
+        })
+    }
+}
+
+#[cfg(unix)]
+pub struct UdsStream(TokioIo<tokio::net::UnixStream>);
+
+#[cfg(unix)]
+impl Connection for UdsStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+#[cfg(unix)]
+impl hyper::rt::Read for UdsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: hyper::rt::ReadBufCursor<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(unix)]
+impl hyper::rt::Write for UdsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
 }
 
 // Everything here is to hack hyper 1.0 onto tonic.
@@ -175,26 +525,145 @@ impl tower::Service<http_02::Request<BoxBody>> for TlsGrpcChannel {
     }
 
     fn call(&mut self, req: http_02::Request<BoxBody>) -> Self::Future {
-        let mut req = http02_request_to_http1(req.map(HttpBody04ToHttpBody1::new));
-        let mut uri = Uri::builder();
-        if let Some(scheme) = self.uri.scheme() {
-            uri = uri.scheme(scheme.to_owned());
-        }
-        if let Some(authority) = self.uri.authority() {
-            uri = uri.authority(authority.to_owned());
-        }
-        if let Some(path_and_query) = req.uri().path_and_query() {
-            uri = uri.path_and_query(path_and_query.to_owned());
+        grpc_call(&self.uri, &self.client, req)
+    }
+}
+
+#[cfg(unix)]
+impl tower::Service<http_02::Request<BoxBody>> for UdsGrpcChannel {
+    type Response = http_02::Response<HttpBody1ToHttpBody04<DefaultIncoming>>;
+    type Error = hyper_util::client::legacy::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: http_02::Request<BoxBody>) -> Self::Future {
+        grpc_call(&self.uri, &self.client, req)
+    }
+}
+
+/// GrpcChannel is a gRPC channel to the control plane, over TLS or, to a local agent, a unix
+/// domain socket.
+#[derive(Clone, Debug)]
+pub enum GrpcChannel {
+    Tls(TlsGrpcChannel),
+    Plaintext(PlaintextGrpcChannel),
+    #[cfg(unix)]
+    Uds(UdsGrpcChannel),
+}
+
+impl tower::Service<http_02::Request<BoxBody>> for GrpcChannel {
+    type Response = http_02::Response<HttpBody1ToHttpBody04<DefaultIncoming>>;
+    type Error = hyper_util::client::legacy::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: http_02::Request<BoxBody>) -> Self::Future {
+        match self {
+            GrpcChannel::Tls(c) => c.call(req),
+            GrpcChannel::Plaintext(c) => grpc_call(&c.uri, &c.client, req),
+            #[cfg(unix)]
+            GrpcChannel::Uds(c) => c.call(req),
         }
-        let uri = uri.build().expect("uri must be valid");
-        *req.uri_mut() = uri;
-        let future = self.client.request(req);
-        Box::pin(async move {
-            let res = future.await?;
-            Ok(http1_response_to_http02(
-                res.map(DefaultIncoming::Some)
-                    .map(HttpBody1ToHttpBody04::new),
-            ))
-        })
+    }
+}
+
+type GrpcResponseFuture = Pin<
+    Box<
+        dyn Future<
+                Output = Result<
+                    http_02::Response<HttpBody1ToHttpBody04<DefaultIncoming>>,
+                    hyper_util::client::legacy::Error,
+                >,
+            > + Send,
+    >,
+>;
+
+fn grpc_call<C: Connect + Clone + Send + Sync + 'static>(
+    base: &Uri,
+    client: &hyper_util::client::legacy::Client<C, BoxBody1>,
+    req: http_02::Request<BoxBody>,
+) -> GrpcResponseFuture {
+    let mut req = http02_request_to_http1(req.map(HttpBody04ToHttpBody1::new));
+    let mut uri = Uri::builder();
+    if let Some(scheme) = base.scheme() {
+        uri = uri.scheme(scheme.to_owned());
+    }
+    if let Some(authority) = base.authority() {
+        uri = uri.authority(authority.to_owned());
+    }
+    if let Some(path_and_query) = req.uri().path_and_query() {
+        uri = uri.path_and_query(path_and_query.to_owned());
+    }
+    let uri = uri.build().expect("uri must be valid");
+    *req.uri_mut() = uri;
+    let future = client.request(req);
+    Box::pin(async move {
+        let res = future.await?;
+        Ok(http1_response_to_http02(
+            res.map(DefaultIncoming::Some)
+                .map(HttpBody1ToHttpBody04::new),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn root_dir() {
+        let dir = std::env::temp_dir().join(format!("ztunnel-roots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // An empty directory is not a valid set of roots
+        assert!(load_root_dir(&dir).await.is_err());
+
+        std::fs::write(dir.join("root-cert.pem"), crate::tls::mock::TEST_ROOT).unwrap();
+        // Hidden files are ignored
+        std::fs::write(dir.join(".hidden.pem"), "not a certificate").unwrap();
+        let files = root_dir_files(&dir).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(load_root_dir(&dir).await.unwrap().len(), 1);
+
+        // Adding a file is detected as a change
+        std::fs::write(dir.join("other-cert.pem"), crate::tls::mock::TEST_ROOT).unwrap();
+        assert_ne!(root_dir_files(&dir).await.unwrap(), files);
+        assert_eq!(load_root_dir(&dir).await.unwrap().len(), 2);
+
+        assert!(RootDirVerifier::new(dir.clone()).await.is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_tls() {
+        let dir = std::env::temp_dir().join(format!("ztunnel-admin-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cert.pem"), crate::tls::mock::TEST_ROOT).unwrap();
+        std::fs::write(dir.join("key.pem"), crate::tls::mock::TEST_ROOT_KEY).unwrap();
+        let tls = AdminTls {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            client_ca: RootCert::Static(Bytes::from_static(crate::tls::mock::TEST_ROOT)),
+        };
+        let sc = admin_server_config(&TlsSettings::default(), &tls)
+            .await
+            .unwrap();
+        assert_eq!(sc.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        // The key is required
+        let missing_key = AdminTls {
+            key: dir.join("missing.pem"),
+            ..tls
+        };
+        assert!(admin_server_config(&TlsSettings::default(), &missing_key)
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
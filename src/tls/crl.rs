@@ -0,0 +1,218 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::Uri;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use prometheus_client::metrics::counter::Counter;
+use rustls::pki_types::CertificateRevocationListDer;
+use tracing::{debug, warn};
+
+use crate::config::{CrlSource, RootCert};
+use crate::tls::{origination_client_config, Error, TlsSettings};
+
+// How long a CRL distribution point may take to respond.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The largest CRL we accept. CRLs of large CAs can be sizeable, so this is more generous than
+// the limit on trust bundles.
+const MAX_CRL_SIZE: usize = 16 * 1024 * 1024;
+
+/// RevocationLists holds the CRLs peer certificates are checked against. Clones share the lists,
+/// so every copy observes a refresh.
+#[derive(Clone, Debug, Default)]
+pub struct RevocationLists {
+    crls: Arc<RwLock<Vec<CertificateRevocationListDer<'static>>>>,
+    // Counts peers rejected because their certificate is revoked.
+    pub(super) revoked: Counter,
+}
+
+impl RevocationLists {
+    pub fn get(&self) -> Vec<CertificateRevocationListDer<'static>> {
+        self.crls.read().unwrap().clone()
+    }
+
+    /// set replaces the CRLs.
+    pub fn set(&self, crls: Vec<CertificateRevocationListDer<'static>>) {
+        *self.crls.write().unwrap() = crls;
+    }
+
+    /// revoked_count returns a counter of peers rejected because their certificate is revoked.
+    pub fn revoked_count(&self) -> Counter {
+        self.revoked.clone()
+    }
+}
+
+/// parse_crls parses PEM or DER encoded CRLs. Every CRL must be well formed, so a corrupt
+/// download never replaces the CRLs in use.
+pub fn parse_crls(raw: &[u8]) -> Result<Vec<CertificateRevocationListDer<'static>>, Error> {
+    let invalid = Error::InvalidCrl;
+    let crls = if raw.starts_with(b"-----BEGIN") {
+        rustls_pemfile::crls(&mut std::io::Cursor::new(raw))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(e.to_string()))?
+    } else {
+        vec![CertificateRevocationListDer::from(raw.to_vec())]
+    };
+    if crls.is_empty() {
+        return Err(invalid("no CRLs found".to_string()));
+    }
+    for crl in &crls {
+        x509_parser::parse_x509_crl(crl).map_err(|e| invalid(e.to_string()))?;
+    }
+    Ok(crls)
+}
+
+/// CrlLoader periodically loads CRLs from a file or an HTTP distribution point. If a load fails,
+/// the previous CRLs are kept.
+pub struct CrlLoader {
+    source: CrlSource,
+    refresh: Duration,
+    crls: RevocationLists,
+    client: Client<HttpsConnector<HttpConnector>, Empty<Bytes>>,
+}
+
+impl CrlLoader {
+    pub async fn new(
+        settings: &TlsSettings,
+        source: CrlSource,
+        refresh: Duration,
+        crls: RevocationLists,
+    ) -> Result<Self, Error> {
+        // CRLs are signed, so distribution points are commonly served over plain HTTP.
+        let cc = origination_client_config(settings, &RootCert::Default).await?;
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(Some(FETCH_TIMEOUT));
+        http.enforce_http(false);
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(cc)
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+        let client = Client::builder(hyper_util::rt::TokioExecutor::new())
+            .timer(crate::hyper_util::TokioTimer)
+            .build(https);
+        Ok(CrlLoader {
+            source,
+            refresh,
+            crls,
+            client,
+        })
+    }
+
+    /// load loads the CRLs once. Used at startup, so peers are checked from the first connection.
+    pub async fn load(&self) -> Result<(), Error> {
+        let raw = match &self.source {
+            CrlSource::File(path) => Bytes::from(
+                tokio::fs::read(path)
+                    .await
+                    .map_err(|e| Error::InvalidCrl(format!("{}: {e}", path.display())))?,
+            ),
+            CrlSource::Url(url) => self.fetch(url).await?,
+        };
+        let crls = parse_crls(&raw)?;
+        debug!(source=?self.source, crls=crls.len(), "loaded CRLs");
+        self.crls.set(crls);
+        Ok(())
+    }
+
+    pub async fn run(self) {
+        loop {
+            tokio::time::sleep(self.refresh).await;
+            if let Err(e) = self.load().await {
+                warn!(source=?self.source, "failed to load CRLs: {e}");
+            }
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Bytes, Error> {
+        let fetch = async {
+            let uri = Uri::try_from(url)?;
+            let resp = self
+                .client
+                .get(uri)
+                .await
+                .map_err(|e| Error::CrlFetch(e.to_string()))?;
+            if !resp.status().is_success() {
+                return Err(Error::CrlFetch(format!(
+                    "unexpected status {}",
+                    resp.status()
+                )));
+            }
+            Ok(Limited::new(resp.into_body(), MAX_CRL_SIZE)
+                .collect()
+                .await
+                .map_err(|e| Error::CrlFetch(e.to_string()))?
+                .to_bytes())
+        };
+        tokio::time::timeout(FETCH_TIMEOUT, fetch)
+            .await
+            .map_err(|_| Error::CrlFetch("timed out".to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::mock::test_crl;
+
+    #[test]
+    fn parse_pem_and_der_crls() {
+        let crl = test_crl(&[]);
+        let pem = format!(
+            "-----BEGIN X509 CRL-----\n{}\n-----END X509 CRL-----\n",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &crl)
+        );
+        assert_eq!(parse_crls(&crl).unwrap(), vec![crl.clone()]);
+        assert_eq!(parse_crls(pem.as_bytes()).unwrap(), vec![crl]);
+
+        for invalid in [
+            &b"not a crl"[..],
+            b"",
+            b"-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n",
+        ] {
+            assert!(parse_crls(invalid).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn load_crl_file() {
+        let path = std::env::temp_dir().join(format!("ztunnel-crl-{}.der", std::process::id()));
+        std::fs::write(&path, test_crl(&[])).unwrap();
+
+        let crls = RevocationLists::default();
+        let loader = CrlLoader::new(
+            &TlsSettings::default(),
+            CrlSource::File(path.clone()),
+            Duration::from_secs(60),
+            crls.clone(),
+        )
+        .await
+        .unwrap();
+        loader.load().await.unwrap();
+        assert_eq!(crls.get().len(), 1);
+
+        // A corrupt file keeps the previous CRLs
+        std::fs::write(&path, b"corrupt").unwrap();
+        assert!(loader.load().await.is_err());
+        assert_eq!(crls.get().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -12,15 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::KeyType;
 use crate::tls::Error;
+use zeroize::Zeroizing;
 
 pub struct CertSign {
     pub csr: String,
-    pub private_key: Vec<u8>,
+    // PEM encoded PKCS#8
+    pub private_key: Zeroizing<Vec<u8>>,
 }
 
 pub struct CsrOptions {
     pub san: String,
+    pub key_type: KeyType,
 }
 
 impl CsrOptions {
@@ -30,14 +34,19 @@ impl CsrOptions {
         use boring::hash::MessageDigest;
         use boring::nid::Nid;
         use boring::pkey::PKey;
+        use boring::rsa::Rsa;
         use boring::stack::Stack;
         use boring::x509::extension::SubjectAlternativeName;
         use boring::x509::{self};
         // TODO: https://github.com/rustls/rcgen/issues/228 can we always use rcgen?
 
-        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
-        let ec_key = EcKey::generate(&group)?;
-        let pkey = PKey::from_ec_key(ec_key)?;
+        let pkey = match self.key_type {
+            KeyType::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            KeyType::Rsa { bits } => PKey::from_rsa(Rsa::generate(bits)?)?,
+        };
 
         let mut csr = x509::X509ReqBuilder::new()?;
         csr.set_pubkey(&pkey)?;
@@ -59,38 +68,76 @@ impl CsrOptions {
             .to_string();
         Ok(CertSign {
             csr: csr_pem,
-            private_key: pkey_pem,
+            private_key: Zeroizing::new(pkey_pem),
         })
     }
 
     #[cfg(feature = "tls-ring")]
     pub fn generate(&self) -> Result<CertSign, Error> {
+        let kp = match self.key_type {
+            KeyType::EcdsaP256 => rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)?,
+            #[cfg(feature = "rsa-keys")]
+            KeyType::Rsa { bits } => {
+                use rsa::pkcs8::EncodePrivateKey;
+                let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), bits as usize)
+                    .map_err(|e| Error::KeyGeneration(e.to_string()))?;
+                let der = key
+                    .to_pkcs8_der()
+                    .map_err(|e| Error::KeyGeneration(e.to_string()))?;
+                rcgen::KeyPair::from_pkcs8_der_and_sign_algo(
+                    &der.as_bytes().into(),
+                    &rcgen::PKCS_RSA_SHA256,
+                )?
+            }
+            #[cfg(not(feature = "rsa-keys"))]
+            KeyType::Rsa { .. } => {
+                return Err(Error::KeyGeneration(
+                    "RSA keys require the rsa-keys feature".to_string(),
+                ))
+            }
+        };
+        let private_key = Zeroizing::new(kp.serialize_pem().into_bytes());
+        let csr = self.generate_with(&kp)?;
+
+        Ok(CertSign { csr, private_key })
+    }
+
+    /// generate_with returns a CSR for an existing key, which may be held by a PKCS#11 token.
+    #[cfg(feature = "tls-ring")]
+    pub fn generate_with(&self, kp: &rcgen::KeyPair) -> Result<String, Error> {
         use rcgen::{CertificateParams, DistinguishedName, SanType};
-        let kp = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)?;
-        let private_key = kp.serialize_pem();
         let mut params = CertificateParams::default();
         params.subject_alt_names = vec![SanType::URI(self.san.clone().try_into()?)];
         params.key_identifier_method = rcgen::KeyIdMethod::Sha256;
         // Avoid setting CN. rcgen defaults it to "rcgen self signed cert" which we don't want
         params.distinguished_name = DistinguishedName::new();
-        let csr = params.serialize_request(&kp)?.pem()?;
-
-        Ok(CertSign {
-            csr,
-            private_key: private_key.into(),
-        })
+        Ok(params.serialize_request(kp)?.pem()?)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::config::KeyType;
     use crate::tls;
 
     #[test]
     fn test_csr() {
+        check_csr(KeyType::EcdsaP256);
+    }
+
+    #[test]
+    #[cfg(any(feature = "tls-boring", feature = "rsa-keys"))]
+    fn test_csr_rsa() {
+        let key = check_csr(KeyType::Rsa { bits: 2048 });
+        assert!(tls::PrivateKey::from_pem(&key).is_ok());
+    }
+
+    // check_csr generates a CSR with the given key type, checks it, and returns its private key.
+    fn check_csr(key_type: KeyType) -> zeroize::Zeroizing<Vec<u8>> {
         use x509_parser::prelude::FromDer;
         let csr = tls::csr::CsrOptions {
             san: "spiffe://td/ns/ns1/sa/sa1".to_string(),
+            key_type,
         }
         .generate()
         .unwrap();
@@ -108,5 +155,17 @@ mod tests {
         // SAN is encoded in some format I don't understand how to parse; this could be improved.
         // but make sure it's there in a hacky manner
         assert!(attr.value.ends_with(b"spiffe://td/ns/ns1/sa/sa1"));
+        let want_algorithm = match key_type {
+            KeyType::EcdsaP256 => x509_parser::oid_registry::OID_KEY_TYPE_EC_PUBLIC_KEY,
+            KeyType::Rsa { .. } => x509_parser::oid_registry::OID_PKCS1_RSAENCRYPTION,
+        };
+        assert_eq!(
+            cert.certification_request_info
+                .subject_pki
+                .algorithm
+                .algorithm,
+            want_algorithm
+        );
+        csr.private_key
     }
 }
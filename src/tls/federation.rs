@@ -0,0 +1,290 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::Uri;
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use rustls::pki_types::CertificateDer;
+use rustls::RootCertStore;
+use tracing::{debug, warn};
+
+use crate::config::RootCert;
+use crate::identity::Identity;
+use crate::strng::Strng;
+use crate::tls::{origination_client_config, Error, TlsSettings};
+
+// How long a bundle endpoint may take to respond.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The largest bundle we accept from a bundle endpoint.
+const MAX_BUNDLE_SIZE: usize = 1024 * 1024;
+
+// The key use of X.509 roots in a SPIFFE bundle. Keys for JWT-SVIDs are ignored.
+const X509_SVID_USE: &str = "x509-svid";
+
+/// TrustBundles holds the roots of federated trust domains, keyed by trust domain. Clones share
+/// the bundles, so every copy observes a refresh.
+#[derive(Clone, Debug, Default)]
+pub struct TrustBundles(Arc<RwLock<HashMap<Strng, Arc<RootCertStore>>>>);
+
+impl TrustBundles {
+    pub fn get(&self, trust_domain: &Strng) -> Option<Arc<RootCertStore>> {
+        self.0.read().unwrap().get(trust_domain).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+
+    /// set replaces the bundle of a trust domain.
+    pub fn set(&self, trust_domain: Strng, roots: RootCertStore) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(trust_domain, Arc::new(roots));
+    }
+
+    /// roots_for returns the bundle to verify a peer with the given identities against. Peers with
+    /// an identity in a local trust domain are always verified against our own roots, so this is
+    /// only set for peers from a federated trust domain.
+    pub fn roots_for(
+        &self,
+        ids: &[Identity],
+        is_local: impl Fn(&Strng) -> bool,
+    ) -> Option<Arc<RootCertStore>> {
+        let trust_domain = |id: &Identity| match id {
+            Identity::Spiffe { trust_domain, .. } => trust_domain.clone(),
+        };
+        if ids.iter().map(trust_domain).any(|td| is_local(&td)) {
+            return None;
+        }
+        ids.iter().map(trust_domain).find_map(|td| self.get(&td))
+    }
+}
+
+// A SPIFFE bundle is a JWK set, with X.509 roots in the x5c of keys used for X.509-SVIDs.
+// See https://github.com/spiffe/spiffe/blob/main/standards/SPIFFE_Trust_Domain_and_Bundle.md#4-spiffe-bundle-format.
+#[derive(serde::Deserialize)]
+struct SpiffeBundle {
+    keys: Vec<JsonWebKey>,
+    #[serde(default)]
+    spiffe_refresh_hint: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+struct JsonWebKey {
+    #[serde(rename = "use", default)]
+    key_use: String,
+    #[serde(default)]
+    x5c: Vec<String>,
+}
+
+/// parse_bundle parses a SPIFFE bundle, returning its X.509 roots and the refresh interval it
+/// suggests, if any.
+pub fn parse_bundle(bundle: &[u8]) -> Result<(RootCertStore, Option<Duration>), Error> {
+    let invalid = Error::InvalidTrustBundle;
+    let bundle: SpiffeBundle =
+        serde_json::from_slice(bundle).map_err(|e| invalid(e.to_string()))?;
+    let mut roots = RootCertStore::empty();
+    for key in bundle.keys.iter().filter(|k| k.key_use == X509_SVID_USE) {
+        // Each X.509-SVID key holds exactly one root
+        let [cert] = key.x5c.as_slice() else {
+            return Err(invalid(format!(
+                "x509-svid key has {} certificates",
+                key.x5c.len()
+            )));
+        };
+        let der = STANDARD.decode(cert).map_err(|e| invalid(e.to_string()))?;
+        roots.add(CertificateDer::from(der))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid("no x509-svid roots".to_string()));
+    }
+    let refresh_hint = bundle
+        .spiffe_refresh_hint
+        .filter(|h| *h > 0)
+        .map(Duration::from_secs);
+    Ok((roots, refresh_hint))
+}
+
+/// BundleFetcher periodically fetches the bundles of federated trust domains from their bundle
+/// endpoints. If a fetch fails, the previous bundle of the trust domain is kept.
+pub struct BundleFetcher {
+    endpoints: HashMap<Strng, String>,
+    refresh: Duration,
+    bundles: TrustBundles,
+    client: Client<HttpsConnector<HttpConnector>, Empty<Bytes>>,
+}
+
+impl BundleFetcher {
+    pub async fn new(
+        settings: &TlsSettings,
+        endpoints: HashMap<Strng, String>,
+        refresh: Duration,
+        bundles: TrustBundles,
+    ) -> Result<Self, Error> {
+        // Bundle endpoints are authenticated like any web server (the https_web profile).
+        let cc = origination_client_config(settings, &RootCert::Default).await?;
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(Some(FETCH_TIMEOUT));
+        http.enforce_http(false);
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(cc)
+            .https_only()
+            .enable_http1()
+            .wrap_connector(http);
+        let client = Client::builder(hyper_util::rt::TokioExecutor::new())
+            .timer(crate::hyper_util::TokioTimer)
+            .build(https);
+        Ok(BundleFetcher {
+            endpoints,
+            refresh,
+            bundles,
+            client,
+        })
+    }
+
+    pub async fn run(self) {
+        loop {
+            let mut next = self.refresh;
+            for (trust_domain, endpoint) in &self.endpoints {
+                match self.fetch(endpoint).await {
+                    Ok((roots, refresh_hint)) => {
+                        debug!(%trust_domain, endpoint, roots=roots.len(), "fetched trust bundle");
+                        self.bundles.set(trust_domain.clone(), roots);
+                        // Refresh at least as often as any bundle asks
+                        if let Some(hint) = refresh_hint {
+                            next = next.min(hint);
+                        }
+                    }
+                    Err(e) => warn!(%trust_domain, endpoint, "failed to fetch trust bundle: {e}"),
+                }
+            }
+            tokio::time::sleep(next).await;
+        }
+    }
+
+    async fn fetch(&self, endpoint: &str) -> Result<(RootCertStore, Option<Duration>), Error> {
+        let fetch = async {
+            let uri = Uri::try_from(endpoint)?;
+            let resp = self
+                .client
+                .get(uri)
+                .await
+                .map_err(|e| Error::TrustBundleFetch(e.to_string()))?;
+            if !resp.status().is_success() {
+                return Err(Error::TrustBundleFetch(format!(
+                    "unexpected status {}",
+                    resp.status()
+                )));
+            }
+            let body = Limited::new(resp.into_body(), MAX_BUNDLE_SIZE)
+                .collect()
+                .await
+                .map_err(|e| Error::TrustBundleFetch(e.to_string()))?
+                .to_bytes();
+            parse_bundle(&body)
+        };
+        tokio::time::timeout(FETCH_TIMEOUT, fetch)
+            .await
+            .map_err(|_| Error::TrustBundleFetch("timed out".to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::mock::TEST_ROOT;
+
+    fn test_root() -> String {
+        let der = rustls_pemfile::certs(&mut std::io::Cursor::new(TEST_ROOT))
+            .next()
+            .unwrap()
+            .unwrap();
+        STANDARD.encode(der)
+    }
+
+    #[test]
+    fn parse_spiffe_bundle() {
+        let bundle = format!(
+            r#"{{
+                "keys": [
+                    {{"use": "x509-svid", "kty": "EC", "x5c": ["{}"]}},
+                    {{"use": "jwt-svid", "kty": "EC", "kid": "abc"}}
+                ],
+                "spiffe_sequence": 1,
+                "spiffe_refresh_hint": 300
+            }}"#,
+            test_root()
+        );
+        let (roots, refresh_hint) = parse_bundle(bundle.as_bytes()).unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(refresh_hint, Some(Duration::from_secs(300)));
+
+        let no_hint = format!(
+            r#"{{"keys": [{{"use": "x509-svid", "x5c": ["{}"]}}]}}"#,
+            test_root()
+        );
+        assert_eq!(parse_bundle(no_hint.as_bytes()).unwrap().1, None);
+
+        for invalid in [
+            "not json",
+            r#"{"keys": []}"#,
+            r#"{"keys": [{"use": "jwt-svid", "kid": "abc"}]}"#,
+            r#"{"keys": [{"use": "x509-svid", "x5c": []}]}"#,
+            r#"{"keys": [{"use": "x509-svid", "x5c": ["not base64!"]}]}"#,
+        ] {
+            assert!(parse_bundle(invalid.as_bytes()).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn trust_bundle_roots() {
+        let id = |trust_domain: &str| Identity::Spiffe {
+            trust_domain: trust_domain.into(),
+            namespace: "ns".into(),
+            service_account: "sa".into(),
+        };
+        let bundles = TrustBundles::default();
+        let (roots, _) = parse_bundle(
+            format!(
+                r#"{{"keys": [{{"use": "x509-svid", "x5c": ["{}"]}}]}}"#,
+                test_root()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        bundles.set("federated.td".into(), roots);
+        let is_local = |td: &Strng| td.as_str() == "cluster.local";
+
+        assert!(bundles.roots_for(&[id("federated.td")], is_local).is_some());
+        assert!(bundles.roots_for(&[id("unknown.td")], is_local).is_none());
+        assert!(bundles
+            .roots_for(&[id("cluster.local")], is_local)
+            .is_none());
+        // A local identity always takes precedence
+        assert!(bundles
+            .roots_for(&[id("federated.td"), id("cluster.local")], is_local)
+            .is_none());
+    }
+}
@@ -21,8 +21,12 @@ use std::fmt::Debug;
 
 use std::sync::Arc;
 
+use once_cell::sync::Lazy;
 use rustls;
 use rustls::crypto::CryptoProvider;
+use rustls::{CipherSuite, SupportedProtocolVersion};
+
+use crate::config::TlsVersion;
 
 use rustls::ClientConfig;
 use rustls::ServerConfig;
@@ -41,7 +45,116 @@ pub trait ServerCertProvider: Send + Sync + Clone {
     async fn fetch_cert(&mut self, fd: &TcpStream) -> Result<Arc<ServerConfig>, TlsError>;
 }
 
-pub(super) static TLS_VERSIONS: &[&rustls::SupportedProtocolVersion] = &[&rustls::version::TLS13];
+static TLS13: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+static TLS12_AND_TLS13: &[&SupportedProtocolVersion] =
+    &[&rustls::version::TLS12, &rustls::version::TLS13];
+
+// The cipher suites used when none are configured: only those that are FIPS compatible.
+const DEFAULT_CIPHER_SUITES: &[CipherSuite] = &[
+    CipherSuite::TLS13_AES_128_GCM_SHA256,
+    CipherSuite::TLS13_AES_256_GCM_SHA384,
+    CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+];
+
+/// TlsSettings restricts the protocol versions and cipher suites of the TLS configs built with it.
+/// They are part of the Config, and passed to every config builder.
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    min_version: TlsVersion,
+    provider: Arc<CryptoProvider>,
+}
+
+impl TlsSettings {
+    /// new builds settings allowing the given minimum version and cipher suites, by their IANA
+    /// names. Without cipher suites, the FIPS compatible ones are allowed.
+    pub fn new(min_version: TlsVersion, cipher_suites: &[String]) -> Result<Self, Error> {
+        let wanted = if cipher_suites.is_empty() {
+            DEFAULT_CIPHER_SUITES.to_vec()
+        } else {
+            cipher_suites
+                .iter()
+                .map(|name| {
+                    cipher_suite(name).ok_or_else(|| {
+                        Error::InvalidTlsSettings(format!("unsupported cipher suite {name}"))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let base = provider();
+        let suites = wanted
+            .iter()
+            .filter_map(|want| base.cipher_suites.iter().find(|s| s.suite() == *want))
+            .copied()
+            .collect::<Vec<_>>();
+        let settings = TlsSettings {
+            min_version,
+            provider: Arc::new(CryptoProvider {
+                cipher_suites: suites,
+                ..(*base).clone()
+            }),
+        };
+        if !settings.provider.cipher_suites.iter().any(|s| {
+            settings
+                .versions()
+                .iter()
+                .any(|v| v.version == s.version().version)
+        }) {
+            return Err(Error::InvalidTlsSettings(format!(
+                "no cipher suites enabled for TLS {min_version} or later"
+            )));
+        }
+        Ok(settings)
+    }
+
+    pub fn min_version(&self) -> TlsVersion {
+        self.min_version
+    }
+
+    pub(super) fn provider(&self) -> Arc<CryptoProvider> {
+        self.provider.clone()
+    }
+
+    pub(super) fn versions(&self) -> &'static [&'static SupportedProtocolVersion] {
+        match self.min_version {
+            TlsVersion::Tls12 => TLS12_AND_TLS13,
+            TlsVersion::Tls13 => TLS13,
+        }
+    }
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        TlsSettings::new(TlsVersion::default(), &[]).expect("default TLS settings must be valid")
+    }
+}
+
+impl serde::Serialize for TlsSettings {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let suites = self
+            .provider
+            .cipher_suites
+            .iter()
+            .filter_map(|s| s.suite().as_str())
+            .collect::<Vec<_>>();
+        let mut s = serializer.serialize_struct("TlsSettings", 2)?;
+        s.serialize_field("minVersion", &self.min_version)?;
+        s.serialize_field("cipherSuites", &suites)?;
+        s.end()
+    }
+}
+
+// cipher_suite looks up a cipher suite supported by the provider by its IANA name.
+fn cipher_suite(name: &str) -> Option<CipherSuite> {
+    provider()
+        .cipher_suites
+        .iter()
+        .map(|s| s.suite())
+        .find(|s| s.as_str() == Some(name))
+}
 
 // Ztunnel use `rustls` with pluggable crypto modules.
 // All crypto MUST be done via the below providers.
@@ -49,24 +162,24 @@ pub(super) static TLS_VERSIONS: &[&rustls::SupportedProtocolVersion] = &[&rustls
 // One exception is CSR generation which doesn't currently have a plugin mechanism (https://github.com/rustls/rcgen/issues/228);
 // In that case, and any future ones, it is critical to guard the code with appropriate `cfg` guards.
 
-#[cfg(feature = "tls-boring")]
+static PROVIDER: Lazy<Arc<CryptoProvider>> = Lazy::new(|| Arc::new(base_provider()));
+
+// provider returns the provider with all of its cipher suites. It is used where the TLS settings
+// don't apply, like loading keys and verifying signatures; TLS configs are built with the provider
+// of their TlsSettings instead.
 pub(super) fn provider() -> Arc<CryptoProvider> {
-    // Due to 'fips-only' feature on the boring provider, this will use only AES_256_GCM_SHA384
-    // and AES_128_GCM_SHA256
-    // In later code we select to only use TLS 1.3
-    Arc::new(boring_rustls_provider::provider())
+    PROVIDER.clone()
+}
+
+#[cfg(feature = "tls-boring")]
+fn base_provider() -> CryptoProvider {
+    // Due to 'fips-only' feature on the boring provider, this will use only FIPS approved ciphers
+    boring_rustls_provider::provider()
 }
 
 #[cfg(feature = "tls-ring")]
-pub(super) fn provider() -> Arc<CryptoProvider> {
-    Arc::new(CryptoProvider {
-        // Limit to only the subset of ciphers that are FIPS compatible
-        cipher_suites: vec![
-            rustls::crypto::ring::cipher_suite::TLS13_AES_256_GCM_SHA384,
-            rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256,
-        ],
-        ..rustls::crypto::ring::default_provider()
-    })
+fn base_provider() -> CryptoProvider {
+    rustls::crypto::ring::default_provider()
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -89,6 +202,12 @@ pub enum TlsError {
     PeerCertError,
     #[error("ssl error: {0}")]
     SslError(#[from] Error),
+    #[error("proxy protocol error: {0}")]
+    ProxyProtocol(std::io::Error),
+    #[error("protocol mismatch: expected a TLS handshake, but detected {0}")]
+    ProtocolMismatch(String),
+    #[error("connection preface timed out during {0}")]
+    PrefaceTimeout(String),
 }
 
 #[cfg(test)]
@@ -100,12 +219,61 @@ pub mod tests {
 
     use crate::tls::mock::*;
 
+    use super::TlsSettings;
+
     #[test]
     #[cfg(feature = "tls-boring")]
     fn is_fips_enabled() {
         assert!(boring::fips::enabled());
     }
 
+    #[test]
+    fn tls_settings() {
+        use crate::config::TlsVersion;
+        use rustls::CipherSuite;
+
+        // Defaults only allow TLS 1.3 with FIPS compatible suites
+        let default = TlsSettings::default();
+        assert_eq!(default.versions().len(), 1);
+        let suites = default
+            .provider()
+            .cipher_suites
+            .iter()
+            .map(|s| s.suite())
+            .collect::<Vec<_>>();
+        assert!(suites.contains(&CipherSuite::TLS13_AES_128_GCM_SHA256));
+        assert!(!suites.contains(&CipherSuite::TLS13_CHACHA20_POLY1305_SHA256));
+
+        let restricted =
+            TlsSettings::new(TlsVersion::Tls13, &["TLS13_AES_256_GCM_SHA384".to_string()]).unwrap();
+        assert_eq!(
+            restricted
+                .provider()
+                .cipher_suites
+                .iter()
+                .map(|s| s.suite())
+                .collect::<Vec<_>>(),
+            vec![CipherSuite::TLS13_AES_256_GCM_SHA384]
+        );
+
+        assert_eq!(
+            TlsSettings::new(TlsVersion::Tls12, &[])
+                .unwrap()
+                .versions()
+                .len(),
+            2
+        );
+
+        // Unknown suites are rejected
+        assert!(TlsSettings::new(TlsVersion::Tls13, &["TLS_NOPE".to_string()]).is_err());
+        // As are suites which can't be used with the allowed versions
+        assert!(TlsSettings::new(
+            TlsVersion::Tls13,
+            &["TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".to_string()]
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_workload_cert() {
         // note that TEST_CERT contains more than one cert - this is how istiod serves it when
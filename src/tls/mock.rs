@@ -23,7 +23,7 @@ use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
-use crate::tls::TLS_VERSIONS;
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer};
 use rustls::ServerConfig;
 use tokio::net::TcpStream;
 
@@ -158,6 +158,34 @@ pub fn generate_test_certs(
     generate_test_certs_at(id, not_before, not_before + duration_until_expiry, None)
 }
 
+/// test_crl returns a CRL, issued by the test root, revoking the given certificates.
+pub fn test_crl(revoked: &[&CertificateDer<'_>]) -> CertificateRevocationListDer<'static> {
+    use rcgen::*;
+    let now = SystemTime::now();
+    let revoked_certs = revoked
+        .iter()
+        .map(|cert| {
+            let (_, cert) = x509_parser::parse_x509_certificate(cert).unwrap();
+            RevokedCertParams {
+                serial_number: SerialNumber::from_slice(cert.raw_serial()),
+                revocation_time: now.into(),
+                reason_code: Some(RevocationReason::KeyCompromise),
+                invalidity_date: None,
+            }
+        })
+        .collect();
+    let params = CertificateRevocationListParams {
+        this_update: (now - Duration::from_secs(60)).into(),
+        next_update: (now + Duration::from_secs(3600)).into(),
+        crl_number: SerialNumber::from(1u64),
+        issuing_distribution_point: None,
+        revoked_certs,
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+    let ca_kp = KeyPair::from_pem(std::str::from_utf8(TEST_ROOT_KEY).unwrap()).unwrap();
+    params.signed_by(&test_ca(), &ca_kp).unwrap().der().clone()
+}
+
 fn test_ca() -> Certificate {
     let key = KeyPair::from_pem(std::str::from_utf8(TEST_ROOT_KEY).unwrap()).unwrap();
     let ca_param =
@@ -177,15 +205,12 @@ impl MockServerCertProvider {
 #[async_trait::async_trait]
 impl ServerCertProvider for MockServerCertProvider {
     async fn fetch_cert(&mut self, _: &TcpStream) -> Result<Arc<ServerConfig>, TlsError> {
-        let mut sc = ServerConfig::builder_with_provider(crate::tls::lib::provider())
-            .with_protocol_versions(TLS_VERSIONS)
+        let settings = crate::tls::TlsSettings::default();
+        let mut sc = ServerConfig::builder_with_provider(settings.provider())
+            .with_protocol_versions(settings.versions())
             .expect("server config must be valid")
             .with_no_client_auth()
-            .with_single_cert(
-                self.0.cert_and_intermediates(),
-                self.0.private_key.clone_key(),
-            )
-            .unwrap();
+            .with_cert_resolver(self.0.cert_resolver().unwrap());
         sc.alpn_protocols = vec![b"h2".into()];
         Ok(Arc::new(sc))
     }
@@ -0,0 +1,290 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use rustls::sign::{Signer, SigningKey};
+use rustls::{SignatureAlgorithm, SignatureScheme};
+use tracing::warn;
+
+use crate::config::Pkcs11Config;
+use crate::tls::csr::CsrOptions;
+use crate::tls::Error;
+
+// The DER encoded OID of the P-256 curve, the only curve keys are generated on.
+const P256_OID: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+// The length of an uncompressed P-256 point.
+const P256_POINT_LEN: usize = 65;
+
+fn pkcs11_error(e: impl fmt::Display) -> Error {
+    Error::Pkcs11(e.to_string())
+}
+
+/// Token is a logged in session with a PKCS#11 token, in which certificate keys are generated.
+#[derive(Clone)]
+pub struct Token(Arc<Mutex<Session>>);
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token").finish_non_exhaustive()
+    }
+}
+
+impl Token {
+    pub fn open(cfg: &Pkcs11Config) -> Result<Token, Error> {
+        let pin = std::fs::read_to_string(&cfg.pin_file)
+            .map_err(|e| Error::Pkcs11(format!("{}: {e}", cfg.pin_file.display())))?;
+        let ctx = Pkcs11::new(&cfg.module).map_err(pkcs11_error)?;
+        ctx.initialize(CInitializeArgs::OsThreads)
+            .map_err(pkcs11_error)?;
+        let slot = ctx
+            .get_slots_with_token()
+            .map_err(pkcs11_error)?
+            .into_iter()
+            .find(|s| s.id() == cfg.slot)
+            .ok_or_else(|| Error::Pkcs11(format!("no token in slot {}", cfg.slot)))?;
+        let session = ctx.open_rw_session(slot).map_err(pkcs11_error)?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(pin.trim().to_string())))
+            .map_err(pkcs11_error)?;
+        Ok(Token(Arc::new(Mutex::new(session))))
+    }
+
+    /// generate_key generates a P-256 key pair. The private key is a session object, which is
+    /// not extractable and disappears with the session.
+    pub fn generate_key(&self) -> Result<TokenKey, Error> {
+        let (handle, point) = {
+            let session = self.0.lock().unwrap();
+            let (public, private) = session
+                .generate_key_pair(
+                    &Mechanism::EccKeyPairGen,
+                    &[
+                        Attribute::Token(false),
+                        Attribute::Verify(true),
+                        Attribute::EcParams(P256_OID.to_vec()),
+                    ],
+                    &[
+                        Attribute::Token(false),
+                        Attribute::Private(true),
+                        Attribute::Sensitive(true),
+                        Attribute::Extractable(false),
+                        Attribute::Sign(true),
+                    ],
+                )
+                .map_err(pkcs11_error)?;
+            let point = session.get_attributes(public, &[AttributeType::EcPoint]);
+            // Only the private key is used from here on
+            if let Err(e) = session.destroy_object(public) {
+                warn!("failed to destroy public key object: {e}");
+            }
+            (private, point)
+        };
+        // Owned from here, so the key object is destroyed on error
+        let key = Arc::new(KeyObject {
+            token: self.clone(),
+            handle,
+        });
+        let Some(Attribute::EcPoint(point)) = point.map_err(pkcs11_error)?.pop() else {
+            return Err(Error::Pkcs11("token returned no public key".to_string()));
+        };
+        Ok(TokenKey {
+            key,
+            public_key: ec_point(&point)?,
+        })
+    }
+
+    /// generate_csr generates a key in the token and a CSR signed by it.
+    pub fn generate_csr(&self, options: &CsrOptions) -> Result<(String, TokenKey), Error> {
+        let key = self.generate_key()?;
+        let kp = rcgen::KeyPair::from_remote(Box::new(CsrSigner {
+            key: key.key.clone(),
+            public_key: key.public_key.clone(),
+        }))?;
+        let csr = options.generate_with(&kp)?;
+        Ok((csr, key))
+    }
+}
+
+// ec_point extracts the uncompressed point from CKA_EC_POINT. The PKCS#11 spec asks for a DER
+// OCTET STRING, but some tokens return the raw point.
+fn ec_point(point: &[u8]) -> Result<Vec<u8>, Error> {
+    match point {
+        [0x04, len, rest @ ..]
+            if *len as usize == P256_POINT_LEN && rest.len() == P256_POINT_LEN =>
+        {
+            Ok(rest.to_vec())
+        }
+        [0x04, ..] if point.len() == P256_POINT_LEN => Ok(point.to_vec()),
+        _ => Err(Error::Pkcs11("unexpected public key encoding".to_string())),
+    }
+}
+
+// KeyObject is a private key object in the token. It is destroyed once neither the certificate
+// nor an in-flight handshake uses it.
+#[derive(Debug)]
+struct KeyObject {
+    token: Token,
+    handle: ObjectHandle,
+}
+
+impl KeyObject {
+    // sign returns the DER encoded ECDSA signature of the SHA-256 digest of message.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, message);
+        let raw = self
+            .token
+            .0
+            .lock()
+            .unwrap()
+            .sign(&Mechanism::Ecdsa, self.handle, digest.as_ref())
+            .map_err(pkcs11_error)?;
+        der_signature(&raw)
+    }
+}
+
+impl Drop for KeyObject {
+    fn drop(&mut self) {
+        if let Err(e) = self.token.0.lock().unwrap().destroy_object(self.handle) {
+            warn!("failed to destroy private key object: {e}");
+        }
+    }
+}
+
+/// TokenKey is a P-256 private key held by a PKCS#11 token.
+#[derive(Debug)]
+pub struct TokenKey {
+    key: Arc<KeyObject>,
+    // The uncompressed public point.
+    public_key: Vec<u8>,
+}
+
+impl SigningKey for TokenKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        offered
+            .contains(&SignatureScheme::ECDSA_NISTP256_SHA256)
+            .then(|| Box::new(TokenSigner(self.key.clone())) as Box<dyn Signer>)
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ECDSA
+    }
+}
+
+#[derive(Debug)]
+struct TokenSigner(Arc<KeyObject>);
+
+impl Signer for TokenSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        self.0
+            .sign(message)
+            .map_err(|e| rustls::Error::General(e.to_string()))
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::ECDSA_NISTP256_SHA256
+    }
+}
+
+// CsrSigner signs a CSR with a token key.
+struct CsrSigner {
+    key: Arc<KeyObject>,
+    public_key: Vec<u8>,
+}
+
+impl rcgen::RemoteKeyPair for CsrSigner {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> {
+        self.key.sign(msg).map_err(|e| {
+            warn!("failed to sign csr: {e}");
+            rcgen::Error::RemoteKeyError
+        })
+    }
+
+    fn algorithm(&self) -> &'static rcgen::SignatureAlgorithm {
+        &rcgen::PKCS_ECDSA_P256_SHA256
+    }
+}
+
+// der_signature encodes a raw PKCS#11 ECDSA signature (r || s) as the DER ECDSA-Sig-Value
+// TLS and X.509 expect.
+fn der_signature(raw: &[u8]) -> Result<Vec<u8>, Error> {
+    if raw.is_empty() || raw.len() % 2 != 0 {
+        return Err(Error::Pkcs11(format!(
+            "invalid signature length {}",
+            raw.len()
+        )));
+    }
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let mut body = der_integer(r);
+    body.extend(der_integer(s));
+    let mut sig = vec![0x30, body.len() as u8];
+    sig.extend(body);
+    Ok(sig)
+}
+
+// der_integer encodes an unsigned big endian integer, of at most 66 bytes (P-521).
+fn der_integer(mut n: &[u8]) -> Vec<u8> {
+    while n.len() > 1 && n[0] == 0 {
+        n = &n[1..];
+    }
+    // A leading zero keeps the integer positive
+    let pad = n[0] & 0x80 != 0;
+    let mut out = vec![0x02, (n.len() + pad as usize) as u8];
+    if pad {
+        out.push(0);
+    }
+    out.extend_from_slice(n);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_der_signature() {
+        let mut raw = vec![0u8; 64];
+        raw[31] = 0x01; // r = 1
+        raw[32] = 0x80; // s has the high bit set
+        let sig = der_signature(&raw).unwrap();
+        let mut want = vec![0x30, 3 + 35, 0x02, 0x01, 0x01, 0x02, 33, 0x00, 0x80];
+        want.extend([0u8; 31]);
+        assert_eq!(sig, want);
+
+        assert!(der_signature(&[]).is_err());
+        assert!(der_signature(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn parse_ec_point() {
+        let mut point = vec![0x04; P256_POINT_LEN];
+        point[1] = 0xab;
+        let mut octet_string = vec![0x04, P256_POINT_LEN as u8];
+        octet_string.extend(&point);
+
+        assert_eq!(ec_point(&octet_string).unwrap(), point);
+        assert_eq!(ec_point(&point).unwrap(), point);
+        assert!(ec_point(&point[..10]).is_err());
+    }
+}
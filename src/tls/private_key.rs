@@ -0,0 +1,327 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::sign::SigningKey;
+use zeroize::Zeroizing;
+
+use crate::config::KeyStorage;
+use crate::tls::certificate::parse_key;
+use crate::tls::Error;
+
+/// PrivateKey is an opaque handle to a workload private key. The key is only loaded into the TLS
+/// stack once a TLS config is first built with it. Keys in memfd_secret memory are only parsed
+/// out of it for each signature, and keys in a PKCS#11 token are never copied out at all.
+pub struct PrivateKey {
+    storage: Storage,
+    // The key as handed to the TLS stack. It is shared by every config built with the key.
+    signing_key: OnceCell<Arc<dyn SigningKey>>,
+}
+
+enum Storage {
+    // PKCS#8 DER
+    Heap(Zeroizing<Vec<u8>>),
+    #[cfg(all(target_os = "linux", feature = "tls-ring"))]
+    Secret(Arc<secretmem::SecretMemory>),
+    #[cfg(feature = "pkcs11")]
+    Token(Arc<crate::tls::TokenKey>),
+}
+
+impl PrivateKey {
+    fn new(storage: Storage) -> Self {
+        PrivateKey {
+            storage,
+            signing_key: OnceCell::new(),
+        }
+    }
+
+    /// from_pem parses a PEM encoded PKCS#8 key.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, Error> {
+        Ok(PrivateKey::new(Storage::Heap(parse_key(pem)?)))
+    }
+
+    /// protect moves a key held in ordinary memory into the given storage. The heap copy is
+    /// zeroized once it is no longer needed.
+    pub fn protect(self, storage: KeyStorage) -> Result<Self, Error> {
+        let der = match self.storage {
+            Storage::Heap(der) => der,
+            // Already protected
+            _ => return Ok(self),
+        };
+        match storage {
+            KeyStorage::Heap => Ok(PrivateKey::new(Storage::Heap(der))),
+            #[cfg(all(target_os = "linux", feature = "tls-ring"))]
+            KeyStorage::MemfdSecret => {
+                let mem = secretmem::SecretMemory::new(&der)
+                    .map_err(|e| Error::KeyStorage(format!("memfd_secret: {e}")))?;
+                Ok(PrivateKey::new(Storage::Secret(Arc::new(mem))))
+            }
+            #[cfg(not(all(target_os = "linux", feature = "tls-ring")))]
+            KeyStorage::MemfdSecret => Err(memfd_secret_unsupported()),
+        }
+    }
+
+    /// signing_key returns the key, to hand to the TLS stack.
+    pub fn signing_key(&self) -> Result<Arc<dyn SigningKey>, Error> {
+        self.signing_key
+            .get_or_try_init(|| match &self.storage {
+                Storage::Heap(der) => load_pkcs8(der),
+                #[cfg(all(target_os = "linux", feature = "tls-ring"))]
+                Storage::Secret(mem) => SecretSigningKey::new(mem.clone()),
+                #[cfg(feature = "pkcs11")]
+                Storage::Token(key) => Ok(key.clone()),
+            })
+            .cloned()
+    }
+
+    fn storage(&self) -> &'static str {
+        match &self.storage {
+            Storage::Heap(_) => "heap",
+            #[cfg(all(target_os = "linux", feature = "tls-ring"))]
+            Storage::Secret(_) => "memfd_secret",
+            #[cfg(feature = "pkcs11")]
+            Storage::Token(_) => "pkcs11",
+        }
+    }
+}
+
+// load_pkcs8 parses a PKCS#8 key straight from where it is stored, without copying it.
+#[cfg(feature = "tls-ring")]
+fn load_pkcs8(der: &[u8]) -> Result<Arc<dyn SigningKey>, Error> {
+    Ok(rustls::crypto::ring::sign::any_supported_type(
+        &PrivateKeyDer::Pkcs8(der.into()),
+    )?)
+}
+
+// The boring key provider takes ownership of the DER it loads, so the copy handed to it cannot be
+// zeroized. This is why memfd_secret storage requires tls-ring.
+#[cfg(feature = "tls-boring")]
+fn load_pkcs8(der: &[u8]) -> Result<Arc<dyn SigningKey>, Error> {
+    Ok(crate::tls::lib::provider()
+        .key_provider
+        .load_private_key(PrivateKeyDer::Pkcs8(der.to_vec().into()))?)
+}
+
+/// SecretSigningKey signs with a key kept in memfd_secret memory. Rather than keeping a parsed copy
+/// of the key on the heap for as long as the key is in use, the key is parsed out of the mapping
+/// for each handshake that signs with it, and the parsed copy is dropped once the handshake has
+/// signed. This costs a key parse per handshake, which is small next to the signature itself for
+/// ECDSA keys, but noticeable for RSA keys.
+///
+/// ring does not zeroize its parsed keys when they are dropped, so the parsed copy is not
+/// zeroized either; it only lives for the duration of one handshake.
+#[cfg(all(target_os = "linux", feature = "tls-ring"))]
+#[derive(Debug)]
+struct SecretSigningKey {
+    mem: Arc<secretmem::SecretMemory>,
+    algorithm: rustls::SignatureAlgorithm,
+}
+
+#[cfg(all(target_os = "linux", feature = "tls-ring"))]
+impl SecretSigningKey {
+    fn new(mem: Arc<secretmem::SecretMemory>) -> Result<Arc<dyn SigningKey>, Error> {
+        // Parse the key once up front, so an invalid key is reported here rather than on use.
+        let algorithm = load_pkcs8(mem.as_slice())?.algorithm();
+        Ok(Arc::new(SecretSigningKey { mem, algorithm }))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "tls-ring"))]
+impl SigningKey for SecretSigningKey {
+    fn choose_scheme(
+        &self,
+        offered: &[rustls::SignatureScheme],
+    ) -> Option<Box<dyn rustls::sign::Signer>> {
+        // The returned signer holds the parsed key until the handshake drops it.
+        load_pkcs8(self.mem.as_slice()).ok()?.choose_scheme(offered)
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        self.algorithm
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "tls-ring")))]
+fn memfd_secret_unsupported() -> Error {
+    Error::KeyStorage("memfd_secret requires Linux and the tls-ring feature".into())
+}
+
+#[cfg(feature = "pkcs11")]
+impl From<crate::tls::TokenKey> for PrivateKey {
+    fn from(key: crate::tls::TokenKey) -> Self {
+        PrivateKey::new(Storage::Token(Arc::new(key)))
+    }
+}
+
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrivateKey")
+            .field("storage", &self.storage())
+            .finish_non_exhaustive()
+    }
+}
+
+/// check_key_storage verifies that keys can be kept in the given storage, so an unsupported
+/// kernel is reported at startup rather than on the first certificate fetch.
+pub fn check_key_storage(storage: KeyStorage) -> Result<(), Error> {
+    match storage {
+        KeyStorage::Heap => Ok(()),
+        #[cfg(all(target_os = "linux", feature = "tls-ring"))]
+        KeyStorage::MemfdSecret => secretmem::SecretMemory::new(&[0])
+            .map(|_| ())
+            .map_err(|e| Error::KeyStorage(format!("memfd_secret is unavailable: {e}"))),
+        #[cfg(not(all(target_os = "linux", feature = "tls-ring")))]
+        KeyStorage::MemfdSecret => Err(memfd_secret_unsupported()),
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "tls-ring"))]
+mod secretmem {
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::ptr::NonNull;
+
+    /// SecretMemory is a read-only copy of some bytes in a memfd_secret(2) mapping. The pages are
+    /// removed from the kernel direct map and cannot be read by other processes through /proc or ptrace.
+    pub(super) struct SecretMemory {
+        ptr: NonNull<u8>,
+        len: usize,
+    }
+
+    impl std::fmt::Debug for SecretMemory {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SecretMemory").finish_non_exhaustive()
+        }
+    }
+
+    // SAFETY: the mapping is owned by SecretMemory and never written after construction.
+    unsafe impl Send for SecretMemory {}
+    unsafe impl Sync for SecretMemory {}
+
+    impl SecretMemory {
+        pub(super) fn new(data: &[u8]) -> io::Result<Self> {
+            // Zero sized mappings are invalid
+            let len = data.len().max(1);
+            // SAFETY: memfd_secret takes only flags and returns a new fd or -1.
+            let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: fd is a freshly created fd owned by no one else.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+            // SAFETY: fd is valid for the duration of the call.
+            if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: we map a new region backed by fd. The mapping stays valid once fd is closed.
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let ptr = NonNull::new(ptr as *mut u8).expect("mmap never returns null on success");
+            // SAFETY: the mapping is at least data.len() bytes and does not overlap data.
+            unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.as_ptr(), data.len()) };
+            Ok(SecretMemory {
+                ptr,
+                len: data.len(),
+            })
+        }
+
+        pub(super) fn as_slice(&self) -> &[u8] {
+            // SAFETY: the mapping holds len initialized bytes until drop.
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+        }
+    }
+
+    impl Drop for SecretMemory {
+        fn drop(&mut self) {
+            // SAFETY: ptr was returned by mmap with this length and is not used after this.
+            unsafe {
+                libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.len.max(1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::mock::TEST_PKEY;
+
+    fn test_key() -> PrivateKey {
+        PrivateKey::from_pem(TEST_PKEY).unwrap()
+    }
+
+    #[test]
+    fn pem_key() {
+        let want = rustls_pemfile::private_key(&mut std::io::Cursor::new(TEST_PKEY))
+            .unwrap()
+            .unwrap();
+        assert_eq!(parse_key(TEST_PKEY).unwrap().as_slice(), want.secret_der());
+        assert!(PrivateKey::from_pem(b"not a key").is_err());
+        // Keys are loaded once, and shared by every config
+        let key = test_key();
+        assert!(Arc::ptr_eq(
+            &key.signing_key().unwrap(),
+            &key.signing_key().unwrap()
+        ));
+    }
+
+    #[test]
+    fn heap_key() {
+        let key = test_key().protect(KeyStorage::Heap).unwrap();
+        assert_eq!(key.storage(), "heap");
+        assert!(key.signing_key().is_ok());
+    }
+
+    #[test]
+    fn memfd_secret_key() {
+        if check_key_storage(KeyStorage::MemfdSecret).is_err() {
+            // Requires Linux 5.14+, and secretmem enabled
+            return;
+        }
+        let key = test_key().protect(KeyStorage::MemfdSecret).unwrap();
+        assert_eq!(key.storage(), "memfd_secret");
+        let want = test_key().signing_key().unwrap();
+        let signing_key = key.signing_key().unwrap();
+        assert_eq!(signing_key.algorithm(), want.algorithm());
+        // The key is parsed out of the mapping to sign
+        let schemes = [
+            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+            rustls::SignatureScheme::RSA_PSS_SHA256,
+        ];
+        let signer = signing_key.choose_scheme(&schemes).unwrap();
+        assert_eq!(
+            signer.scheme(),
+            want.choose_scheme(&schemes).unwrap().scheme()
+        );
+        assert!(!signer.sign(b"message").unwrap().is_empty());
+        // Protecting again is a no-op
+        let key = key.protect(KeyStorage::Heap).unwrap();
+        assert_eq!(key.storage(), "memfd_secret");
+    }
+}
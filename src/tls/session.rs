@@ -0,0 +1,158 @@
+// Copyright Istio Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustls::client::ClientSessionMemoryCache;
+use rustls::server::StoresServerSessions;
+use tokio::time::Instant;
+
+use crate::identity::Identity;
+
+// Maximum number of sessions kept by a single cache.
+const MAX_SESSIONS: usize = 256;
+
+// Maximum number of distinct destination identity sets we keep client sessions for.
+const MAX_CLIENT_CACHES: usize = 256;
+
+/// SessionCaches holds the TLS session state of a single workload certificate, so that sessions
+/// are never resumed across identities.
+#[derive(Debug, Default)]
+pub(super) struct SessionCaches {
+    server: Mutex<Option<Arc<ServerSessionCache>>>,
+    // Client sessions are additionally keyed by the expected destination identities: a resumed
+    // session skips certificate verification, so it must only be reused with the same expectations.
+    client: Mutex<HashMap<Vec<Identity>, Arc<ClientSessionMemoryCache>>>,
+}
+
+impl SessionCaches {
+    pub(super) fn server(&self, lifetime: Duration) -> Arc<ServerSessionCache> {
+        let mut server = self.server.lock().expect("mutex");
+        match server.as_ref() {
+            Some(cache) if cache.lifetime == lifetime => cache.clone(),
+            _ => server
+                .insert(Arc::new(ServerSessionCache::new(lifetime)))
+                .clone(),
+        }
+    }
+
+    pub(super) fn client(&self, identity: &[Identity]) -> Arc<ClientSessionMemoryCache> {
+        let mut client = self.client.lock().expect("mutex");
+        if let Some(cache) = client.get(identity) {
+            return cache.clone();
+        }
+        if client.len() >= MAX_CLIENT_CACHES {
+            client.clear();
+        }
+        let cache = Arc::new(ClientSessionMemoryCache::new(MAX_SESSIONS));
+        client.insert(identity.to_vec(), cache.clone());
+        cache
+    }
+}
+
+/// ServerSessionCache stores TLS server sessions, which can be resumed up to `lifetime` after they
+/// were established.
+#[derive(Debug)]
+pub(super) struct ServerSessionCache {
+    lifetime: Duration,
+    sessions: Mutex<HashMap<Vec<u8>, (Vec<u8>, Instant)>>,
+}
+
+impl ServerSessionCache {
+    fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            sessions: Default::default(),
+        }
+    }
+
+    fn valid(&self, created: Instant) -> bool {
+        created.elapsed() < self.lifetime
+    }
+}
+
+impl StoresServerSessions for ServerSessionCache {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let mut sessions = self.sessions.lock().expect("mutex");
+        if sessions.len() >= MAX_SESSIONS {
+            sessions.retain(|_, (_, created)| self.valid(*created));
+            if sessions.len() >= MAX_SESSIONS {
+                return false;
+            }
+        }
+        sessions.insert(key, (value, Instant::now()));
+        true
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let sessions = self.sessions.lock().expect("mutex");
+        sessions
+            .get(key)
+            .filter(|(_, created)| self.valid(*created))
+            .map(|(value, _)| value.clone())
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut sessions = self.sessions.lock().expect("mutex");
+        sessions
+            .remove(key)
+            .filter(|(_, created)| self.valid(*created))
+            .map(|(value, _)| value)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn server_session_expiry() {
+        let cache = ServerSessionCache::new(Duration::from_secs(10));
+        assert!(cache.put(b"a".to_vec(), b"1".to_vec()));
+        assert_eq!(cache.get(b"a"), Some(b"1".to_vec()));
+
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert_eq!(cache.get(b"a"), None);
+
+        // Expired entries are evicted to make room once the cache is full
+        for i in 0..MAX_SESSIONS {
+            assert!(cache.put(i.to_be_bytes().to_vec(), vec![]));
+        }
+        assert!(!cache.put(b"b".to_vec(), vec![]));
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(cache.put(b"b".to_vec(), b"2".to_vec()));
+        assert_eq!(cache.take(b"b"), Some(b"2".to_vec()));
+        assert_eq!(cache.take(b"b"), None);
+    }
+
+    #[test]
+    fn caches_per_identity() {
+        let caches = SessionCaches::default();
+        let a = vec![Identity::default()];
+        assert!(Arc::ptr_eq(&caches.client(&a), &caches.client(&a)));
+        assert!(!Arc::ptr_eq(&caches.client(&a), &caches.client(&[])));
+
+        let lifetime = Duration::from_secs(10);
+        assert!(Arc::ptr_eq(
+            &caches.server(lifetime),
+            &caches.server(lifetime)
+        ));
+    }
+}
@@ -15,13 +15,13 @@
 use crate::identity::Identity;
 
 use crate::tls::lib::provider;
-use crate::tls::{ServerCertProvider, TlsError};
+use crate::tls::{RevocationLists, ServerCertProvider, TlsError, TrustBundles};
 use futures_util::TryFutureExt;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
-use rustls::server::ParsedCertificate;
+use rustls::server::{ParsedCertificate, WebPkiClientVerifier};
 use rustls::{
     ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme,
 };
@@ -47,38 +47,64 @@ impl<F: ServerCertProvider> InboundAcceptor<F> {
     }
 }
 
+/// unresolved_server_config returns a config without a certificate, so every handshake made with it
+/// fails. It stands in as the default of listeners, such as QUIC ones, that pick the config of each
+/// connection themselves.
+pub fn unresolved_server_config(settings: &tls::TlsSettings) -> rustls::ServerConfig {
+    rustls::ServerConfig::builder_with_provider(settings.provider())
+        .with_protocol_versions(settings.versions())
+        .expect("server config must be valid")
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(rustls::server::ResolvesServerCertUsingSni::new()))
+}
+
 #[derive(Debug)]
 pub(super) struct TrustDomainVerifier {
     base: Arc<dyn ClientCertVerifier>,
     trust_domain: Option<Strng>,
+    // Trust domains accepted as if they were trust_domain.
+    aliases: Vec<Strng>,
+    // Clients from these trust domains are verified against their own bundle.
+    federated: TrustBundles,
+    // Clients with a revoked certificate are rejected.
+    crls: RevocationLists,
 }
 
 impl TrustDomainVerifier {
-    pub fn new(base: Arc<dyn ClientCertVerifier>, trust_domain: Option<Strng>) -> Arc<Self> {
-        Arc::new(Self { base, trust_domain })
+    pub fn new(
+        base: Arc<dyn ClientCertVerifier>,
+        trust_domain: Option<Strng>,
+        aliases: Vec<Strng>,
+        federated: TrustBundles,
+        crls: RevocationLists,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            base,
+            trust_domain,
+            aliases,
+            federated,
+            crls,
+        })
+    }
+
+    fn is_local(&self, trust_domain: &Strng) -> bool {
+        self.trust_domain.as_ref() == Some(trust_domain) || self.aliases.contains(trust_domain)
     }
 
     fn verify_trust_domain(&self, client_cert: &CertificateDer<'_>) -> Result<(), rustls::Error> {
-        use x509_parser::prelude::*;
         let Some(want_trust_domain) = &self.trust_domain else {
             // No need to verify
             return Ok(());
         };
-        let (_, c) = X509Certificate::from_der(client_cert).map_err(|_e| {
-            rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)
-        })?;
-        let ids = tls::certificate::identities(c).map_err(|_e| {
-            rustls::Error::InvalidCertificate(
-                rustls::CertificateError::ApplicationVerificationFailure,
-            )
-        })?;
+        let ids = peer_identities(client_cert)?;
         trace!(
-            "verifying client identities {ids:?} against trust domain {:?}",
-            want_trust_domain
+            "verifying client identities {ids:?} against trust domain {:?} (aliases {:?})",
+            want_trust_domain,
+            self.aliases
         );
         ids.iter()
             .find(|id| match id {
-                Identity::Spiffe { trust_domain, .. } => trust_domain == want_trust_domain,
+                Identity::Spiffe { trust_domain, .. } => self.is_local(trust_domain),
             })
             .ok_or_else(|| {
                 rustls::Error::InvalidCertificate(rustls::CertificateError::Other(
@@ -105,11 +131,23 @@ impl ClientCertVerifier for TrustDomainVerifier {
         intermediates: &[CertificateDer<'_>],
         now: UnixTime,
     ) -> Result<ClientCertVerified, rustls::Error> {
-        let res = self
-            .base
-            .verify_client_cert(end_entity, intermediates, now)?;
-        self.verify_trust_domain(end_entity)?;
-        Ok(res)
+        let res = match federated_roots(end_entity, &self.federated, |td| self.is_local(td)) {
+            Some(roots) => WebPkiClientVerifier::builder_with_provider(roots, provider())
+                .with_crls(self.crls.get())
+                .allow_unknown_revocation_status()
+                .build()
+                .map_err(|e| rustls::Error::General(e.to_string()))?
+                .verify_client_cert(end_entity, intermediates, now),
+            None => self
+                .base
+                .verify_client_cert(end_entity, intermediates, now)
+                .and_then(|res| self.verify_trust_domain(end_entity).map(|_| res)),
+        };
+        if let Err(rustls::Error::InvalidCertificate(rustls::CertificateError::Revoked)) = res {
+            debug!("rejecting client with a revoked certificate");
+            self.crls.revoked.inc();
+        }
+        res
     }
 
     fn verify_tls12_signature(
@@ -161,6 +199,11 @@ pub struct OutboundConnector {
 }
 
 impl OutboundConnector {
+    /// client_config is the TLS config of the connector, for transports other than TCP.
+    pub fn client_config(&self) -> Arc<ClientConfig> {
+        self.client_config.clone()
+    }
+
     pub async fn connect(
         self,
         stream: TcpStream,
@@ -181,19 +224,15 @@ impl OutboundConnector {
 pub struct IdentityVerifier {
     pub(super) roots: Arc<RootCertStore>,
     pub(super) identity: Vec<Identity>,
+    // Our trust domain and its aliases, whose servers are always verified against roots.
+    pub(super) local_trust_domains: Vec<Strng>,
+    // Servers from these trust domains are verified against their own bundle.
+    pub(super) federated: TrustBundles,
 }
 
 impl IdentityVerifier {
     fn verify_full_san(&self, server_cert: &CertificateDer<'_>) -> Result<(), rustls::Error> {
-        use x509_parser::prelude::*;
-        let (_, c) = X509Certificate::from_der(server_cert).map_err(|_e| {
-            rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)
-        })?;
-        let id = tls::certificate::identities(c).map_err(|_e| {
-            rustls::Error::InvalidCertificate(
-                rustls::CertificateError::ApplicationVerificationFailure,
-            )
-        })?;
+        let id = peer_identities(server_cert)?;
         trace!(
             "verifying server identities {id:?} against {:?}",
             self.identity
@@ -210,6 +249,34 @@ impl IdentityVerifier {
     }
 }
 
+// peer_identities parses the identities in the SAN of a peer certificate.
+fn peer_identities(cert: &CertificateDer<'_>) -> Result<Vec<Identity>, rustls::Error> {
+    use x509_parser::prelude::*;
+    let (_, c) = X509Certificate::from_der(cert)
+        .map_err(|_e| rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding))?;
+    tls::certificate::identities(c).map_err(|_e| {
+        rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)
+    })
+}
+
+// federated_roots returns the bundle of the peer's trust domain, if it is federated. Peers from
+// a local trust domain, and peers whose identity can't be parsed, are left to the usual checks.
+fn federated_roots(
+    cert: &CertificateDer<'_>,
+    federated: &TrustBundles,
+    is_local: impl Fn(&Strng) -> bool,
+) -> Option<Arc<RootCertStore>> {
+    if federated.is_empty() {
+        return None;
+    }
+    let ids = peer_identities(cert).ok()?;
+    let roots = federated.roots_for(&ids, is_local);
+    if roots.is_some() {
+        trace!("verifying peer identities {ids:?} against a federated trust bundle");
+    }
+    roots
+}
+
 // Rustls doesn't natively validate URI SAN.
 // Build our own verifier, inspired by https://github.com/rustls/rustls/blob/ccb79947a4811412ee7dcddcd0f51ea56bccf101/rustls/src/webpki/server_verifier.rs#L239.
 impl ServerCertVerifier for IdentityVerifier {
@@ -226,10 +293,14 @@ impl ServerCertVerifier for IdentityVerifier {
     ) -> Result<ServerCertVerified, rustls::Error> {
         let cert = ParsedCertificate::try_from(end_entity)?;
 
+        let roots = federated_roots(end_entity, &self.federated, |td| {
+            self.local_trust_domains.contains(td)
+        })
+        .unwrap_or_else(|| self.roots.clone());
         let algs = provider().signature_verification_algorithms;
         rustls::client::verify_server_cert_signed_by_trust_anchor(
             &cert,
-            &self.roots,
+            &roots,
             intermediates,
             now,
             algs.all,
@@ -280,3 +351,118 @@ impl ServerCertVerifier for IdentityVerifier {
             .supported_schemes()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tls::mock::{generate_test_certs, test_crl, TestIdentity, TEST_ROOT};
+
+    fn cert(trust_domain: &str) -> CertificateDer<'static> {
+        let id: TestIdentity = Identity::Spiffe {
+            trust_domain: trust_domain.into(),
+            namespace: "ns".into(),
+            service_account: "sa".into(),
+        }
+        .into();
+        generate_test_certs(&id, Duration::ZERO, Duration::from_secs(60))
+            .cert_and_intermediates()
+            .remove(0)
+    }
+
+    #[test]
+    fn trust_domain_verifier_aliases() {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates([cert("cluster.local")]);
+        let base = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .unwrap();
+
+        let strict = TrustDomainVerifier::new(
+            base.clone(),
+            Some("cluster.local".into()),
+            vec![],
+            TrustBundles::default(),
+            RevocationLists::default(),
+        );
+        assert!(strict.verify_trust_domain(&cert("cluster.local")).is_ok());
+        assert!(strict.verify_trust_domain(&cert("old.td")).is_err());
+
+        let aliased = TrustDomainVerifier::new(
+            base,
+            Some("cluster.local".into()),
+            vec!["old.td".into()],
+            TrustBundles::default(),
+            RevocationLists::default(),
+        );
+        assert!(aliased.verify_trust_domain(&cert("cluster.local")).is_ok());
+        assert!(aliased.verify_trust_domain(&cert("old.td")).is_ok());
+        assert!(aliased.verify_trust_domain(&cert("other.td")).is_err());
+    }
+
+    #[test]
+    fn trust_domain_verifier_federated() {
+        let mut test_roots = RootCertStore::empty();
+        test_roots.add_parsable_certificates(
+            rustls_pemfile::certs(&mut std::io::Cursor::new(TEST_ROOT)).map(Result::unwrap),
+        );
+        // Our own roots don't issue the test certificates, so only federated peers are accepted.
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates([cert("cluster.local")]);
+        let base = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .unwrap();
+        let federated = TrustBundles::default();
+        let verifier = TrustDomainVerifier::new(
+            base,
+            Some("cluster.local".into()),
+            vec![],
+            federated.clone(),
+            RevocationLists::default(),
+        );
+        let verify = |td| {
+            verifier
+                .verify_client_cert(&cert(td), &[], UnixTime::now())
+                .is_ok()
+        };
+
+        assert!(!verify("federated.td"));
+        federated.set("federated.td".into(), test_roots);
+        assert!(verify("federated.td"));
+        assert!(!verify("other.td"));
+        // Our trust domain is never verified against a federated bundle
+        assert!(!verify("cluster.local"));
+    }
+
+    #[test]
+    fn trust_domain_verifier_revoked() {
+        let good = cert("cluster.local");
+        let revoked = cert("cluster.local");
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(
+            rustls_pemfile::certs(&mut std::io::Cursor::new(TEST_ROOT)).map(Result::unwrap),
+        );
+        let crls = RevocationLists::default();
+        crls.set(vec![test_crl(&[&revoked])]);
+        let base = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+            .with_crls(crls.get())
+            .build()
+            .unwrap();
+        let verifier = TrustDomainVerifier::new(
+            base,
+            Some("cluster.local".into()),
+            vec![],
+            TrustBundles::default(),
+            crls.clone(),
+        );
+
+        assert!(verifier
+            .verify_client_cert(&good, &[], UnixTime::now())
+            .is_ok());
+        assert!(verifier
+            .verify_client_cert(&revoked, &[], UnixTime::now())
+            .is_err());
+        assert_eq!(crls.revoked_count().get(), 1);
+    }
+}
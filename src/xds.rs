@@ -16,11 +16,15 @@ use std::collections::HashMap;
 use std::error::Error as StdErr;
 use std::fmt;
 use std::fmt::Formatter;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn, Instrument};
 
 pub use client::*;
 pub use metrics::*;
@@ -35,8 +39,12 @@ use xds::istio::workload::Workload as XdsWorkload;
 use crate::cert_fetcher::{CertFetcher, NoCertFetcher};
 use crate::config::ConfigSource;
 use crate::rbac::Authorization;
+use crate::state::cell::ProxyStateCell;
+use crate::state::events::StateEvent;
 use crate::state::service::{endpoint_uid, Endpoint, Service, ServiceStore};
-use crate::state::workload::{network_addr, HealthStatus, NamespacedHostname, Workload};
+use crate::state::workload::{
+    network_addr, HealthStatus, NamespacedHostname, NetworkAddress, Workload, WorkloadStore,
+};
 use crate::state::ProxyState;
 use crate::strng::Strng;
 use crate::{rbac, strng};
@@ -79,6 +87,9 @@ pub enum Error {
     OnDemandSend(),
     #[error("TLS Error: {0}")]
     TLSError(#[from] tls::Error),
+    /// The stream to a failover address was closed, to reconnect to the primary address.
+    #[error("failing back to the primary address")]
+    Failback,
 }
 
 /// Updates the [ProxyState] from XDS.
@@ -87,36 +98,143 @@ pub enum Error {
 #[derive(Clone)]
 pub struct ProxyStateUpdateMutator {
     cert_fetcher: Arc<dyn CertFetcher>,
+    limits: StateLimits,
+    limit_exceeded: Family<StateLimitLabels, Counter>,
+    // The resources found over a limit since they were last logged, by kind. These are logged
+    // once per XDS response rather than per resource, so a large response cannot flood the logs.
+    over_limit: Arc<Mutex<HashMap<StateKind, u64>>>,
+}
+
+/// StateLimits caps the number of resources XDS may add to the [ProxyState], so a runaway control
+/// plane cannot exhaust our memory. A limit of 0 means unbounded.
+#[derive(Clone, Debug, Default)]
+pub struct StateLimits {
+    pub workloads: usize,
+    pub services: usize,
+    pub policies: usize,
+    /// If true, additions over a limit are rejected. Otherwise they are only logged and counted.
+    pub reject: bool,
+}
+
+impl From<&crate::config::Config> for StateLimits {
+    fn from(config: &crate::config::Config) -> Self {
+        StateLimits {
+            workloads: config.max_workloads,
+            services: config.max_services,
+            policies: config.max_policies,
+            reject: config.reject_over_state_limits,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ProxyStateUpdater {
-    state: Arc<RwLock<ProxyState>>,
+    state: ProxyStateCell,
     updater: ProxyStateUpdateMutator,
 }
 
 impl ProxyStateUpdater {
-    /// Creates a new updater for the given stores. Will prefetch certs when workloads are updated.
-    pub fn new(state: Arc<RwLock<ProxyState>>, cert_fetcher: Arc<dyn CertFetcher>) -> Self {
+    /// Creates a new updater for the given stores. Will forget certs when workloads are removed.
+    pub fn new(state: ProxyStateCell, cert_fetcher: Arc<dyn CertFetcher>) -> Self {
         Self {
             state,
-            updater: ProxyStateUpdateMutator { cert_fetcher },
+            updater: ProxyStateUpdateMutator {
+                cert_fetcher,
+                limits: StateLimits::default(),
+                limit_exceeded: Family::default(),
+                over_limit: Default::default(),
+            },
         }
     }
-    /// Creates a new updater that does not prefetch workload certs.
-    pub fn new_no_fetch(state: Arc<RwLock<ProxyState>>) -> Self {
+
+    /// Applies the given limits to the resources XDS adds, counting those over a limit.
+    pub fn with_limits(
+        mut self,
+        limits: StateLimits,
+        exceeded: Family<StateLimitLabels, Counter>,
+    ) -> Self {
+        self.updater = self.updater.with_limits(limits, exceeded);
+        self
+    }
+    /// Creates a new updater that does not forget workload certs.
+    pub fn new_no_fetch(state: ProxyStateCell) -> Self {
         Self {
             state,
             updater: ProxyStateUpdateMutator::new_no_fetch(),
         }
     }
+
+    /// remove_resources removes the given Address and Authorization resources, as if XDS had
+    /// removed them.
+    pub fn remove_resources(&self, addresses: &[Strng], authorizations: &[Strng]) {
+        let mut state = self.state.write();
+        for name in addresses {
+            self.updater.remove(&mut state, name);
+        }
+        for name in authorizations {
+            self.updater.remove_authorization(&mut state, name.clone());
+        }
+    }
 }
 
 impl ProxyStateUpdateMutator {
-    /// Creates a new updater that does not prefetch workload certs.
+    /// Creates a new updater that does not forget workload certs.
     pub fn new_no_fetch() -> Self {
         ProxyStateUpdateMutator {
             cert_fetcher: Arc::new(NoCertFetcher()),
+            limits: StateLimits::default(),
+            limit_exceeded: Family::default(),
+            over_limit: Default::default(),
+        }
+    }
+
+    /// Applies the given limits to the resources added, counting those over a limit.
+    pub fn with_limits(
+        mut self,
+        limits: StateLimits,
+        exceeded: Family<StateLimitLabels, Counter>,
+    ) -> Self {
+        self.limits = limits;
+        self.limit_exceeded = exceeded;
+        self
+    }
+
+    // check_limit is called before adding a new resource of the given kind. Replacing an existing
+    // resource doesn't grow the state, so is never limited.
+    fn check_limit(
+        &self,
+        kind: StateKind,
+        limit: usize,
+        count: impl FnOnce() -> usize,
+    ) -> anyhow::Result<()> {
+        if limit == 0 {
+            return Ok(());
+        }
+        let count = count();
+        if count < limit {
+            return Ok(());
+        }
+        self.limit_exceeded
+            .get_or_create(&StateLimitLabels { kind })
+            .inc();
+        *self.over_limit.lock().unwrap().entry(kind).or_default() += 1;
+        if self.limits.reject {
+            anyhow::bail!("{kind:?} limit of {limit} exceeded");
+        }
+        Ok(())
+    }
+
+    // log_over_limit logs the resources found over a limit since the last call, once per kind.
+    fn log_over_limit(&self) {
+        let over_limit = std::mem::take(&mut *self.over_limit.lock().unwrap());
+        for (kind, resources) in over_limit {
+            let limit = match kind {
+                StateKind::Workload => self.limits.workloads,
+                StateKind::Service => self.limits.services,
+                StateKind::Policy => self.limits.policies,
+            };
+            let rejected = self.limits.reject;
+            warn!(?kind, resources, limit, rejected, "state limit exceeded");
         }
     }
 
@@ -130,13 +248,15 @@ impl ProxyStateUpdateMutator {
         // Convert the workload.
         let (workload, services): (Workload, HashMap<String, PortList>) = w.try_into()?;
         let workload = Arc::new(workload);
+        if !state.workloads.contains_uid(&workload.uid) {
+            self.check_limit(StateKind::Workload, self.limits.workloads, || {
+                state.workloads.num_workloads()
+            })?;
+        }
 
         // First, remove the entry entirely to make sure things are cleaned up properly.
         self.remove_for_insert(state, &workload.uid);
 
-        // Prefetch the cert for the workload.
-        self.cert_fetcher.prefetch_cert(&workload);
-
         // Lock and upstate the stores.
         let track = self
             .cert_fetcher
@@ -147,6 +267,7 @@ impl ProxyStateUpdateMutator {
         if workload.status == HealthStatus::Healthy {
             insert_service_endpoints(&workload, &services, &mut state.services)?;
         }
+        state.events.publish(StateEvent::WorkloadAdded(workload));
 
         Ok(())
     }
@@ -185,10 +306,33 @@ impl ProxyStateUpdateMutator {
             {
                 self.cert_fetcher.clear_cert(&prev.identity());
             }
+            if !for_insert {
+                state
+                    .events
+                    .publish(StateEvent::WorkloadRemoved(Arc::new(prev)));
+            }
             // We removed a workload, no reason to attempt to remove a service with the same name
             return;
         }
 
+        // On-demand lookups may request, and so remove, resources by address rather than UID.
+        if let Some(addr) = parse_network_addr(xds_name) {
+            if for_insert {
+                return;
+            }
+            if let Some(wl) = state.workloads.find_address_arc(&addr) {
+                debug!(%addr, uid=%wl.uid, "removing workload by address");
+                self.remove_internal(state, &wl.uid, false);
+            } else if let Some(svc) = state.services.get_by_vip(&addr) {
+                debug!(%addr, service=%svc.namespaced_hostname(), "removing service by address");
+                state.services.remove(&svc.namespaced_hostname());
+                state
+                    .events
+                    .publish(StateEvent::ServiceChanged(svc.namespaced_hostname()));
+            }
+            return;
+        }
+
         let Ok(name) = NamespacedHostname::from_str(xds_name) else {
             // we don't have namespace/hostname xds primary key for service
             if !for_insert {
@@ -212,7 +356,9 @@ impl ProxyStateUpdateMutator {
             );
             return;
         }
-        if state.services.remove(&name).is_none() && !for_insert {
+        if state.services.remove(&name).is_some() {
+            state.events.publish(StateEvent::ServiceChanged(name));
+        } else if !for_insert {
             warn!("tried to remove service keyed by {name}, but it was not found");
         }
     }
@@ -240,9 +386,15 @@ impl ProxyStateUpdateMutator {
             for (wip, ep) in prev.endpoints.iter() {
                 service.endpoints.insert(wip.clone(), ep.clone());
             }
+        } else {
+            self.check_limit(StateKind::Service, self.limits.services, || {
+                state.services.num_services()
+            })?;
         }
 
+        let name = service.namespaced_hostname();
         state.services.insert(service);
+        state.events.publish(StateEvent::ServiceChanged(name));
         Ok(())
     }
 
@@ -255,13 +407,21 @@ impl ProxyStateUpdateMutator {
 
         let rbac = rbac::Authorization::try_from(r)?;
         trace!("insert policy {}", serde_json::to_string(&rbac)?);
+        let key = rbac.to_key();
+        if state.policies.get(&key).is_none() {
+            self.check_limit(StateKind::Policy, self.limits.policies, || {
+                state.policies.num_policies()
+            })?;
+        }
         state.policies.insert(rbac);
+        state.events.publish(StateEvent::PolicyChanged(key));
         Ok(())
     }
 
     pub fn remove_authorization(&self, state: &mut ProxyState, name: Strng) {
         info!("handling RBAC delete {}", name);
-        state.policies.remove(name);
+        state.policies.remove(name.clone());
+        state.events.publish(StateEvent::PolicyChanged(name));
     }
 }
 
@@ -271,7 +431,7 @@ impl Handler<XdsWorkload> for ProxyStateUpdater {
         updates: Box<&mut dyn Iterator<Item = XdsUpdate<XdsWorkload>>>,
     ) -> Result<(), Vec<RejectedConfig>> {
         // use deepsize::DeepSizeOf;
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write();
         let handle = |res: XdsUpdate<XdsWorkload>| {
             match res {
                 XdsUpdate::Update(w) => self.updater.insert_workload(&mut state, w.resource)?,
@@ -282,7 +442,9 @@ impl Handler<XdsWorkload> for ProxyStateUpdater {
             }
             Ok(())
         };
-        handle_single_resource(updates, handle)
+        let res = handle_single_resource(updates, handle);
+        self.updater.log_over_limit();
+        res
     }
 }
 
@@ -291,7 +453,7 @@ impl Handler<XdsAddress> for ProxyStateUpdater {
         &self,
         updates: Box<&mut dyn Iterator<Item = XdsUpdate<XdsAddress>>>,
     ) -> Result<(), Vec<RejectedConfig>> {
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write();
         let handle = |res: XdsUpdate<XdsAddress>| {
             match res {
                 XdsUpdate::Update(w) => self.updater.insert_address(&mut state, w.resource)?,
@@ -302,10 +464,18 @@ impl Handler<XdsAddress> for ProxyStateUpdater {
             }
             Ok(())
         };
-        handle_single_resource(updates, handle)
+        let res = handle_single_resource(updates, handle);
+        self.updater.log_over_limit();
+        res
     }
 }
 
+// parse_network_addr parses a resource name of the form network/IP, as used by on-demand lookups
+fn parse_network_addr(name: &str) -> Option<NetworkAddress> {
+    let (network, address) = name.split_once('/')?;
+    Some(network_addr(strng::new(network), address.parse().ok()?))
+}
+
 fn insert_service_endpoints(
     workload: &Workload,
     services: &HashMap<String, PortList>,
@@ -355,7 +525,7 @@ impl Handler<XdsAuthorization> for ProxyStateUpdater {
         &self,
         updates: Box<&mut dyn Iterator<Item = XdsUpdate<XdsAuthorization>>>,
     ) -> Result<(), Vec<RejectedConfig>> {
-        let mut state = self.state.write().unwrap();
+        let mut state = self.state.write();
         let handle = |res: XdsUpdate<XdsAuthorization>| {
             match res {
                 XdsUpdate::Update(w) => {
@@ -365,28 +535,36 @@ impl Handler<XdsAuthorization> for ProxyStateUpdater {
             }
             Ok(())
         };
-        let mut len_updates = 0;
-        let updates = updates.inspect(|_| len_updates += 1);
-        match handle_single_resource(updates, handle) {
-            Ok(()) => {
-                state.policies.send();
-                Ok(())
-            }
-            Err(e) => {
-                if e.len() < len_updates {
-                    // not all config was rejected, we have _some_ valide update
-                    state.policies.send();
-                }
-                Err(e)
-            }
-        }
+        let res = handle_single_resource(updates, handle);
+        self.updater.log_over_limit();
+        res
     }
 }
 
-/// LocalClient serves as a local file reader alternative for XDS. This is intended for testing.
+// How long to wait for a burst of changes to the local config file to settle before reloading it.
+// Editors and ConfigMap updates tend to replace a file in several steps.
+const LOCAL_CONFIG_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// watch_dir returns an inotify instance that is notified when files in dir are written or replaced.
+#[cfg(target_os = "linux")]
+fn watch_dir(
+    dir: &std::path::Path,
+) -> anyhow::Result<tokio::io::unix::AsyncFd<nix::sys::inotify::Inotify>> {
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    inotify.add_watch(
+        dir,
+        AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO,
+    )?;
+    Ok(tokio::io::unix::AsyncFd::new(inotify)?)
+}
+
+/// LocalClient serves as a local file reader alternative for XDS. This is intended for testing, or
+/// running without a control plane.
+/// Config read from a file is reloaded whenever the file changes.
 pub struct LocalClient {
     pub cfg: ConfigSource,
-    pub state: Arc<RwLock<ProxyState>>,
+    pub state: ProxyStateCell,
     pub cert_fetcher: Arc<dyn CertFetcher>,
 }
 
@@ -429,62 +607,216 @@ impl LocalClient {
                 self.load_config(r)?;
             }
         };
-        #[cfg(any(test, feature = "testing"))]
-        if let ConfigSource::Dynamic(ref rx) = self.cfg {
-            let rx = rx.clone();
-            tokio::spawn(async move {
-                // Mutex is just for borrow checker; we know we are the only user and can hold the lock forever.
-                let mut rx = rx.lock().await;
-                while let Some(req) = rx.recv().await {
-                    if let Err(e) = self.load_config(req) {
-                        error!("failed to load dynamic config update: {e:?}");
-                    }
-                    if let Err(e) = rx.ack().await {
-                        error!("failed to ack: {}", e);
+        match self.cfg.clone() {
+            #[cfg(any(test, feature = "testing"))]
+            ConfigSource::Dynamic(rx) => {
+                tokio::spawn(async move {
+                    // Mutex is just for borrow checker; we know we are the only user and can hold the lock forever.
+                    let mut rx = rx.lock().await;
+                    while let Some(req) = rx.recv().await {
+                        if let Err(e) = self.load_config(req) {
+                            error!("failed to load dynamic config update: {e:?}");
+                        }
+                        if let Err(e) = rx.ack().await {
+                            error!("failed to ack: {}", e);
+                        }
                     }
-                }
-            });
+                });
+            }
+            ConfigSource::File(path) => {
+                tokio::spawn(self.watch(path).in_current_span());
+            }
+            ConfigSource::Static(_) => {}
         };
         Ok(())
     }
 
+    // watch reloads the config whenever the file at path changes.
+    // If the new config is invalid, the previous config is kept.
+    #[cfg(target_os = "linux")]
+    async fn watch(self, path: PathBuf) {
+        // Watch the directory rather than the file, so the file being replaced (rather than
+        // written in place) is picked up as well.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        let inotify = match watch_dir(dir) {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                error!("failed to watch local config {}: {e}", path.display());
+                return;
+            }
+        };
+        let mut last = tokio::fs::read_to_string(&path).await.ok();
+        loop {
+            let mut ready = match inotify.readable().await {
+                Ok(ready) => ready,
+                Err(e) => {
+                    error!("failed to watch local config {}: {e}", path.display());
+                    return;
+                }
+            };
+            match ready.try_io(|fd| fd.get_ref().read_events().map_err(std::io::Error::from)) {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    error!("failed to watch local config {}: {e}", path.display());
+                    return;
+                }
+                Err(_would_block) => continue,
+            }
+            tokio::time::sleep(LOCAL_CONFIG_DEBOUNCE).await;
+            // Changes made while we waited are covered by the read below
+            let _ = inotify.get_ref().read_events();
+            let contents = match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    // The file may be in the middle of being replaced; it will change again.
+                    debug!("failed to read local config {}: {e}", path.display());
+                    continue;
+                }
+            };
+            // Other files in the directory changed, or the file was rewritten as-is
+            if last.as_ref() == Some(&contents) {
+                continue;
+            }
+            let res = serde_yaml::from_str::<LocalConfig>(&contents)
+                .map_err(anyhow::Error::from)
+                .and_then(|r| self.load_config(r));
+            if let Err(e) = res {
+                error!(
+                    "failed to reload local config from {}: {e:?}",
+                    path.display()
+                );
+            }
+            last = Some(contents);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn watch(self, path: PathBuf) {
+        warn!(
+            "reloading local config is only supported on Linux; {} will not be reloaded",
+            path.display()
+        );
+    }
+
     fn load_config(&self, r: LocalConfig) -> anyhow::Result<()> {
         debug!(
             "load local config: {}",
             serde_yaml::to_string(&r).unwrap_or_default()
         );
-        let mut state = self.state.write().unwrap();
-        // Clear the state
-        state.workloads = Default::default();
-        state.services = Default::default();
-        // Policies have some channels, so we don't want to reset it entirely
-        state.policies.clear_all_policies();
         let num_workloads = r.workloads.len();
         let num_policies = r.policies.len();
+        // Build the new config before touching the state, so a config that fails to load leaves
+        // the current one in place.
+        let mut workloads = WorkloadStore::default();
+        let mut services = ServiceStore::default();
+        let mut loaded = Vec::with_capacity(num_workloads);
         for wl in r.workloads {
             trace!("inserting local workload {}", &wl.workload.uid);
-            self.cert_fetcher.prefetch_cert(&wl.workload);
             let w = Arc::new(wl.workload);
-            state.workloads.insert(
+            workloads.insert(
                 w.clone(),
                 self.cert_fetcher.should_track_certificates_for_removal(&w),
             );
 
-            let services: HashMap<String, PortList> = wl
+            let wl_services: HashMap<String, PortList> = wl
                 .services
                 .into_iter()
                 .map(|(k, v)| (k, PortList::from(v)))
                 .collect();
 
-            insert_service_endpoints(&w, &services, &mut state.services)?;
+            insert_service_endpoints(&w, &wl_services, &mut services)?;
+            loaded.push(w);
         }
+        for svc in r.services {
+            services.insert(svc);
+        }
+
+        let mut state = self.state.write();
+        state.workloads.replace(workloads);
+        state.services = services;
+        // Every policy removed or inserted counts as changed.
+        let mut changed_policies = state.policies.clear_all_policies();
         for rbac in r.policies {
+            changed_policies.push(rbac.to_key());
             state.policies.insert(rbac);
         }
-        for svc in r.services {
-            state.services.insert(svc);
+        for key in changed_policies {
+            state.events.publish(StateEvent::PolicyChanged(key));
+        }
+        drop(state);
+        for w in &loaded {
+            self.cert_fetcher.prefetch_cert(w);
         }
         info!(%num_workloads, %num_policies, "local config initialized");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn state_limits() {
+        let exceeded = Family::default();
+        let limits = StateLimits {
+            workloads: 1,
+            reject: true,
+            ..Default::default()
+        };
+        let updater =
+            ProxyStateUpdateMutator::new_no_fetch().with_limits(limits.clone(), exceeded.clone());
+        let mut state = ProxyState::default();
+        let workload = |uid: &str, ip: u8| XdsWorkload {
+            uid: uid.to_string(),
+            addresses: vec![Bytes::copy_from_slice(&[127, 0, 0, ip])],
+            name: "some name".to_string(),
+            ..Default::default()
+        };
+        let over_limit = StateLimitLabels {
+            kind: StateKind::Workload,
+        };
+
+        updater
+            .insert_workload(&mut state, workload("uid1", 1))
+            .unwrap();
+        // Updating an existing workload doesn't grow the state.
+        updater
+            .insert_workload(&mut state, workload("uid1", 2))
+            .unwrap();
+        assert_eq!(exceeded.get_or_create(&over_limit).get(), 0);
+
+        assert!(updater
+            .insert_workload(&mut state, workload("uid2", 3))
+            .is_err());
+        assert_eq!(state.workloads.num_workloads(), 1);
+        assert_eq!(exceeded.get_or_create(&over_limit).get(), 1);
+
+        // Without rejection, the workload is only counted.
+        let updater = updater.with_limits(
+            StateLimits {
+                reject: false,
+                ..limits
+            },
+            exceeded.clone(),
+        );
+        updater
+            .insert_workload(&mut state, workload("uid2", 3))
+            .unwrap();
+        assert_eq!(state.workloads.num_workloads(), 2);
+        assert_eq!(exceeded.get_or_create(&over_limit).get(), 2);
+
+        // Both workloads over the limit are logged together, after which the tally is reset.
+        assert_eq!(
+            updater.over_limit.lock().unwrap().get(&StateKind::Workload),
+            Some(&2)
+        );
+        updater.log_over_limit();
+        assert!(updater.over_limit.lock().unwrap().is_empty());
+    }
+}
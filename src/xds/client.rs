@@ -12,15 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::sync::Arc;
-use std::time::Duration;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 use std::{fmt, mem};
 
+use itertools::Itertools;
 use prost::{DecodeError, EncodeError};
 use prost_types::value::Kind;
 use prost_types::{Struct, Value};
+use rand::Rng;
 use serde_json;
 use split_iter::Splittable;
 use thiserror::Error;
@@ -34,20 +38,23 @@ use crate::xds::metrics::{ConnectionTerminationReason, Metrics};
 use crate::xds::service::discovery::v3::aggregated_discovery_service_client::AggregatedDiscoveryServiceClient;
 use crate::xds::service::discovery::v3::Resource as ProtoResource;
 use crate::xds::service::discovery::v3::*;
-use crate::{identity, strng, tls};
+use crate::{identity, readiness, strng, tls, version};
 
 use super::Error;
 
-const INSTANCE_IP: &str = "INSTANCE_IP";
 const INSTANCE_IPS: &str = "INSTANCE_IPS";
 const DEFAULT_IP: &str = "1.1.1.1";
-const POD_NAME: &str = "POD_NAME";
-const POD_NAMESPACE: &str = "POD_NAMESPACE";
 const NODE_NAME: &str = "NODE_NAME";
 const NAME: &str = "NAME";
 const NAMESPACE: &str = "NAMESPACE";
+const NETWORK: &str = "NETWORK";
+const CLUSTER_ID: &str = "CLUSTER_ID";
+const ISTIO_VERSION: &str = "ISTIO_VERSION";
+const LABELS: &str = "LABELS";
 const EMPTY_STR: &str = "";
-const ISTIO_METAJSON_PREFIX: &str = "ISTIO_METAJSON_";
+
+// How often the staleness of each type is sampled.
+const STALENESS_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Eq, Hash, PartialEq, Debug, Clone)]
 pub struct ResourceKey {
@@ -79,6 +86,249 @@ impl Display for RejectedConfig {
     }
 }
 
+/// XdsRejects holds the resources the control plane most recently sent that we rejected, until
+/// an update or removal of the resource is accepted. Clones share the rejects.
+#[derive(Clone, Debug, Default)]
+pub struct XdsRejects(Arc<RwLock<HashMap<ResourceKey, RejectDump>>>);
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectDump {
+    type_url: Strng,
+    name: Strng,
+    reason: String,
+    rejected_at: String,
+}
+
+impl XdsRejects {
+    // Records the outcome of a response: resources it updated or removed are no longer rejected,
+    // unless they are among the new rejects.
+    fn record<'a>(
+        &self,
+        type_url: &Strng,
+        resources: impl Iterator<Item = &'a str>,
+        rejects: &[RejectedConfig],
+    ) {
+        let mut all = self.0.write().expect("mutex");
+        for name in resources {
+            all.remove(&ResourceKey {
+                name: name.into(),
+                type_url: type_url.clone(),
+            });
+        }
+        let now = chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now());
+        for reject in rejects {
+            all.insert(
+                ResourceKey {
+                    name: reject.name.clone(),
+                    type_url: type_url.clone(),
+                },
+                RejectDump {
+                    type_url: type_url.clone(),
+                    name: reject.name.clone(),
+                    reason: format!("{:#}", reject.reason),
+                    rejected_at: now.to_rfc3339(),
+                },
+            );
+        }
+    }
+
+    /// dump returns the rejected resources, ordered by type and name.
+    pub fn dump(&self) -> Vec<RejectDump> {
+        let mut rejects: Vec<_> = self.0.read().expect("mutex").values().cloned().collect();
+        rejects.sort_by(|a, b| (&a.type_url, &a.name).cmp(&(&b.type_url, &b.name)));
+        rejects
+    }
+}
+
+/// XdsVersions tracks the version and nonce of each resource we accepted, and when each type
+/// last had a response accepted, so operators can check a given config reached us. Clones share
+/// the versions.
+#[derive(Clone, Debug, Default)]
+pub struct XdsVersions(Arc<RwLock<Versions>>);
+
+#[derive(Debug, Default)]
+struct Versions {
+    resources: HashMap<ResourceKey, ResourceVersion>,
+    last_accepted: HashMap<Strng, SystemTime>,
+}
+
+#[derive(Clone, Debug)]
+struct ResourceVersion {
+    version: String,
+    nonce: String,
+    accepted_at: SystemTime,
+}
+
+#[derive(serde::Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionsDump {
+    types: Vec<TypeVersionDump>,
+    resources: Vec<ResourceVersionDump>,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeVersionDump {
+    type_url: Strng,
+    last_accepted_at: String,
+    staleness_seconds: u64,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceVersionDump {
+    type_url: Strng,
+    name: Strng,
+    version: String,
+    nonce: String,
+    accepted_at: String,
+}
+
+fn rfc3339(t: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339()
+}
+
+impl XdsVersions {
+    // Records the resources a response updated and removed. A response is only accepted as a
+    // whole if nothing in it was rejected; the valid resources of a NACKed response are still
+    // recorded, as they were applied.
+    fn record<'a>(
+        &self,
+        type_url: &Strng,
+        nonce: &str,
+        updated: impl Iterator<Item = (&'a str, &'a str)>,
+        removed: impl Iterator<Item = &'a str>,
+        accepted: bool,
+    ) {
+        let now = SystemTime::now();
+        let mut versions = self.0.write().expect("mutex");
+        for name in removed {
+            versions.resources.remove(&ResourceKey {
+                name: name.into(),
+                type_url: type_url.clone(),
+            });
+        }
+        for (name, version) in updated {
+            versions.resources.insert(
+                ResourceKey {
+                    name: name.into(),
+                    type_url: type_url.clone(),
+                },
+                ResourceVersion {
+                    version: version.to_string(),
+                    nonce: nonce.to_string(),
+                    accepted_at: now,
+                },
+            );
+        }
+        if accepted {
+            versions.last_accepted.insert(type_url.clone(), now);
+        }
+    }
+
+    /// contains returns whether a version of the given resource was accepted.
+    pub fn contains(&self, type_url: &Strng, name: &Strng) -> bool {
+        self.0
+            .read()
+            .expect("mutex")
+            .resources
+            .contains_key(&ResourceKey {
+                name: name.clone(),
+                type_url: type_url.clone(),
+            })
+    }
+
+    /// staleness returns, per type, how long ago a response was last accepted.
+    pub fn staleness(&self) -> Vec<(Strng, Duration)> {
+        let now = SystemTime::now();
+        self.0
+            .read()
+            .expect("mutex")
+            .last_accepted
+            .iter()
+            .map(|(t, at)| (t.clone(), now.duration_since(*at).unwrap_or_default()))
+            .collect()
+    }
+
+    /// dump returns the staleness of each type, and the version of each resource, ordered by
+    /// type and name.
+    pub fn dump(&self) -> VersionsDump {
+        let versions = self.0.read().expect("mutex");
+        let now = SystemTime::now();
+        let mut types: Vec<_> = versions
+            .last_accepted
+            .iter()
+            .map(|(type_url, at)| TypeVersionDump {
+                type_url: type_url.clone(),
+                last_accepted_at: rfc3339(*at),
+                staleness_seconds: now.duration_since(*at).unwrap_or_default().as_secs(),
+            })
+            .collect();
+        types.sort_by(|a, b| a.type_url.cmp(&b.type_url));
+        let mut resources: Vec<_> = versions
+            .resources
+            .iter()
+            .map(|(k, v)| ResourceVersionDump {
+                type_url: k.type_url.clone(),
+                name: k.name.clone(),
+                version: v.version.clone(),
+                nonce: v.nonce.clone(),
+                accepted_at: rfc3339(v.accepted_at),
+            })
+            .collect();
+        resources.sort_by(|a, b| (&a.type_url, &a.name).cmp(&(&b.type_url, &b.name)));
+        VersionsDump { types, resources }
+    }
+}
+
+// The google.rpc.BadRequest error detail, with a violation per rejected resource. It is hand
+// written, as it is the only message of the google.rpc error details we use.
+#[derive(Clone, PartialEq, prost::Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<FieldViolation>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct FieldViolation {
+    #[prost(string, tag = "1")]
+    field: String,
+    #[prost(string, tag = "2")]
+    description: String,
+}
+
+const BAD_REQUEST_TYPE: &str = "type.googleapis.com/google.rpc.BadRequest";
+// google.rpc.Code.INVALID_ARGUMENT
+const INVALID_ARGUMENT: i32 = 3;
+
+// nack_status builds the error detail of a NACK. The message lists every reject, for control
+// planes that only log it, and the details carry them individually.
+fn nack_status(rejects: &[RejectedConfig]) -> Status {
+    let message = rejects
+        .iter()
+        .map(|reject| reject.to_string())
+        .collect::<Vec<String>>()
+        .join("; ");
+    let details = BadRequest {
+        field_violations: rejects
+            .iter()
+            .map(|reject| FieldViolation {
+                field: reject.name.to_string(),
+                description: format!("{:#}", reject.reason),
+            })
+            .collect(),
+    };
+    Status {
+        code: INVALID_ARGUMENT,
+        message,
+        details: vec![prost_types::Any {
+            type_url: BAD_REQUEST_TYPE.to_string(),
+            value: prost::Message::encode_to_vec(&details),
+        }],
+    }
+}
+
 /// handle_single_resource is a helper to process a set of updates with a closure that processes items one-by-one.
 /// It handles aggregating errors as NACKS.
 pub fn handle_single_resource<T: prost::Message, F: FnMut(XdsUpdate<T>) -> anyhow::Result<()>>(
@@ -170,9 +420,9 @@ impl<T: 'static + prost::Message + Default> RawHandler for HandlerWrapper<T> {
 
         // after we update the proxy cache, we can update our xds cache. it's important that we do this after
         // as we make on demand notifications here, so the proxy cache must be updated first.
-        for name in res.removed_resources {
+        for name in &res.removed_resources {
             let k = ResourceKey {
-                name: name.into(),
+                name: name.as_str().into(),
                 type_url: type_url.clone(),
             };
             debug!("received delete resource {k}");
@@ -182,36 +432,86 @@ impl<T: 'static + prost::Message + Default> RawHandler for HandlerWrapper<T> {
             state.notify_on_demand(&k);
         }
 
+        // Either can fail. Merge the results
+        let rejects = match result {
+            Ok(()) => decode_failures,
+            Err(mut rejects) => {
+                rejects.extend(decode_failures);
+                rejects
+            }
+        };
+        state.rejects.record(
+            &type_url,
+            res.resources
+                .iter()
+                .map(|r| r.name.as_str())
+                .chain(res.removed_resources.iter().map(String::as_str)),
+            &rejects,
+        );
+        let rejected: HashSet<&Strng> = rejects.iter().map(|r| &r.name).collect();
+        state.versions.record(
+            &type_url,
+            &res.nonce,
+            res.resources
+                .iter()
+                .filter(|r| !rejected.contains(&strng::new(&r.name)))
+                .map(|r| (r.name.as_str(), r.version.as_str())),
+            res.removed_resources.iter().map(String::as_str),
+            rejects.is_empty(),
+        );
+
         for r in res.resources {
             let key = ResourceKey {
                 name: r.name.into(),
                 type_url: type_url.clone(),
             };
+            // Waiters are notified even of rejects, so they don't block on a resource that
+            // will not come.
             state.notify_on_demand(&key);
-            state.add_resource(key.type_url, key.name);
+            // A rejected resource is not claimed as known on reconnect, so it is sent again.
+            if rejected.contains(&key.name) {
+                if let Some(known) = state.known_resources.get_mut(&key.type_url) {
+                    known.remove(&key.name);
+                }
+            } else {
+                state.add_resource(key.type_url, key.name);
+            }
         }
 
-        // Either can fail. Merge the results
-        match (result, decode_failures.is_empty()) {
-            (Ok(()), true) => Ok(()),
-            (Ok(_), false) => Err(decode_failures),
-            (r @ Err(_), true) => r,
-            (Err(mut rejects), false) => {
-                rejects.extend(decode_failures);
-                Err(rejects)
-            }
+        if rejects.is_empty() {
+            Ok(())
+        } else {
+            Err(rejects)
         }
     }
 }
 
 pub struct Config {
-    address: String,
+    // The addresses of the control plane, by priority. The first is the primary.
+    addresses: Vec<String>,
+    failover_threshold: u32,
+    failback_interval: Duration,
     tls_builder: Box<dyn tls::ClientCertProvider>,
     auth: identity::AuthSource,
     proxy_metadata: HashMap<String, String>,
+    proxy_metadata_json: HashMap<String, String>,
     handlers: HashMap<Strng, Box<dyn RawHandler>>,
     initial_requests: Vec<DeltaDiscoveryRequest>,
     on_demand: bool,
+    backoff: Backoff,
+    // Readiness tasks for the initial sync of each watched type, keyed by type_url.
+    type_ready: HashMap<String, readiness::BlockReady>,
+    initial_sync_timeout: Option<Duration>,
+    grpc: crate::config::GrpcConfig,
+    plaintext: bool,
+    // Node metadata, so istiod can scope what it pushes to us.
+    node_name: Option<String>,
+    instance_ip: Option<IpAddr>,
+    pod_name: Option<String>,
+    pod_namespace: Option<String>,
+    network: Strng,
+    cluster_id: String,
+    pod_labels_path: Option<PathBuf>,
 }
 
 pub struct State {
@@ -221,8 +521,19 @@ pub struct State {
     /// pending stores a list of all resources that are pending and XDS push
     pending: HashMap<ResourceKey, oneshot::Sender<()>>,
 
+    /// Stores all resources subscribed to on-demand, so they can be resubscribed on reconnect.
+    /// Map from type_url to name. This is shared with Demanders, so it can be inspected.
+    subscriptions: Arc<RwLock<HashMap<Strng, HashSet<Strng>>>>,
+
     demand: mpsc::Receiver<(oneshot::Sender<()>, ResourceKey)>,
     demand_tx: mpsc::Sender<(oneshot::Sender<()>, ResourceKey)>,
+
+    /// Resources we rejected. This is shared with the admin server, so it can be inspected.
+    rejects: XdsRejects,
+
+    /// Versions of the resources we accepted. This is shared with the admin server, so it can be
+    /// inspected.
+    versions: XdsVersions,
 }
 
 impl State {
@@ -248,19 +559,57 @@ impl Config {
         tls_builder: Box<dyn tls::ClientCertProvider>,
     ) -> Config {
         Config {
-            address: config
-                .xds_address
-                .clone()
-                .expect("xds_address must be set to use xds"),
+            addresses: std::iter::once(
+                config
+                    .xds_address
+                    .clone()
+                    .expect("xds_address must be set to use xds"),
+            )
+            .chain(config.xds_failover_addresses.iter().cloned())
+            .collect(),
+            failover_threshold: config.xds_failover_threshold,
+            failback_interval: config.xds_failback_interval,
             tls_builder,
             auth: config.auth.clone(),
             handlers: HashMap::new(),
             initial_requests: Vec::new(),
             on_demand: config.xds_on_demand,
+            backoff: Backoff::new(
+                config.xds_reconnect_initial_backoff,
+                config.xds_reconnect_max_backoff,
+                config.xds_reconnect_jitter,
+            ),
             proxy_metadata: config.proxy_metadata.clone(),
+            proxy_metadata_json: config.proxy_metadata_json.clone(),
+            type_ready: HashMap::new(),
+            initial_sync_timeout: config.xds_initial_sync_timeout,
+            grpc: config.xds_grpc,
+            plaintext: config.xds_plaintext,
+            node_name: config.local_node.clone(),
+            instance_ip: config.local_ip,
+            pod_name: config.pod_name.clone(),
+            pod_namespace: config.pod_namespace.clone(),
+            network: config.network.clone(),
+            cluster_id: config.cluster_id.clone(),
+            pod_labels_path: config.pod_labels_path.clone(),
         }
     }
 
+    /// with_readiness blocks readiness on the initial sync of each type watched so far, so it
+    /// should be called after all handlers are registered. Each type is registered as a
+    /// subtask of `block_ready`, so pending types are reported individually.
+    pub fn with_readiness(mut self, block_ready: &readiness::BlockReady) -> Config {
+        for req in &self.initial_requests {
+            if AdsClient::is_initial_request_on_demand(req) {
+                continue;
+            }
+            let name = format!("xds {}", type_name(&req.type_url));
+            self.type_ready
+                .insert(req.type_url.clone(), block_ready.subtask(&name));
+        }
+        self
+    }
+
     pub fn with_watched_handler<F>(self, type_url: Strng, f: impl Handler<F>) -> Config
     where
         F: 'static + prost::Message + Default,
@@ -327,34 +676,39 @@ impl Config {
         }
     }
     fn node(&self) -> Node {
-        let ip = std::env::var(INSTANCE_IP);
+        let ip = self.instance_ip.map(|ip| ip.to_string());
         let ip = ip.as_deref().unwrap_or(DEFAULT_IP);
-        let pod_name = std::env::var(POD_NAME);
-        let pod_name = pod_name.as_deref().unwrap_or(EMPTY_STR);
-        let ns = std::env::var(POD_NAMESPACE);
-        let ns = ns.as_deref().unwrap_or(EMPTY_STR);
-        let node_name = std::env::var(NODE_NAME);
-        let node_name = node_name.as_deref().unwrap_or(EMPTY_STR);
+        let pod_name = self.pod_name.as_deref().unwrap_or(EMPTY_STR);
+        let ns = self.pod_namespace.as_deref().unwrap_or(EMPTY_STR);
+        let node_name = self.node_name.as_deref().unwrap_or(EMPTY_STR);
+        let istio_version = version::BuildInfo::new().istio_version;
         let mut metadata = Self::build_struct([
             (NAME, pod_name),
             (NAMESPACE, ns),
             (INSTANCE_IPS, ip),
             (NODE_NAME, node_name),
+            (NETWORK, self.network.as_str()),
+            (CLUSTER_ID, self.cluster_id.as_str()),
+            (ISTIO_VERSION, istio_version.as_str()),
         ]);
+        if let Some(labels) = self.pod_labels() {
+            metadata.fields.insert(
+                LABELS.to_string(),
+                Value {
+                    kind: Some(Kind::StructValue(Self::build_struct(labels))),
+                },
+            );
+        }
         metadata
             .fields
             .append(&mut Self::build_struct(self.proxy_metadata.clone()).fields);
 
-        // Lookup ISTIO_METAJSON_* environment variables and add them to the node metadata
-        for (key, val) in std::env::vars().filter(|(key, _)| key.starts_with(ISTIO_METAJSON_PREFIX))
-        {
-            if let Ok(v) = serde_json::from_str(&val) {
-                metadata.fields.insert(
-                    key.trim_start_matches(ISTIO_METAJSON_PREFIX).to_string(),
-                    Self::json_to_value(v),
-                );
+        // Add the ISTIO_METAJSON_* settings to the node metadata
+        for (key, val) in &self.proxy_metadata_json {
+            if let Ok(v) = serde_json::from_str(val) {
+                metadata.fields.insert(key.clone(), Self::json_to_value(v));
             } else {
-                error!("failed to parse {}={}", key, val);
+                error!("failed to parse ISTIO_METAJSON_{}={}", key, val);
             }
         }
 
@@ -364,6 +718,19 @@ impl Config {
             ..Default::default()
         }
     }
+
+    // Reads our pod's labels. Labels are optional metadata, so failing to read them is not fatal.
+    fn pod_labels(&self) -> Option<BTreeMap<String, String>> {
+        let path = self.pod_labels_path.as_ref()?;
+        match std::fs::read_to_string(path) {
+            Ok(labels) => Some(parse_downward_api_labels(&labels)),
+            Err(e) => {
+                warn!("failed to read pod labels from {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
     fn construct_initial_request(
         &self,
         request_type: Strng,
@@ -407,11 +774,57 @@ pub struct AdsClient {
 
     state: State,
 
-    pub(crate) metrics: Metrics,
-    block_ready: Option<tokio::sync::watch::Sender<()>>,
+    pub(crate) metrics: Arc<Metrics>,
 
     connection_id: u32,
-    types_to_expect: HashSet<String>,
+    initial_sync: Arc<Mutex<InitialSync>>,
+    heartbeat: Option<readiness::Heartbeat>,
+
+    // The index of the address in use, and how many attempts on it failed in a row.
+    endpoint: usize,
+    failures: u32,
+}
+
+/// InitialSync tracks the watched types that have not yet completed their initial sync. It is
+/// shared with the initial sync timeout, so that readiness is not blocked forever on a type that
+/// never syncs.
+struct InitialSync {
+    // Types awaiting their first ACK, with the readiness task (if any) blocked on each.
+    pending: HashMap<String, Option<readiness::BlockReady>>,
+    block_ready: Option<tokio::sync::watch::Sender<()>>,
+    // Types awaiting their first ACK. Unlike pending, this is not cleared when the sync times
+    // out, so synced only reports a sync that actually happened.
+    unsynced: HashSet<String>,
+    synced: tokio::sync::watch::Sender<bool>,
+}
+
+impl InitialSync {
+    fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn is_synced(&self) -> bool {
+        self.unsynced.is_empty()
+    }
+
+    fn complete(&mut self, type_url: &str) {
+        if self.pending.remove(type_url).is_some() && self.pending.is_empty() {
+            mem::drop(self.block_ready.take());
+        }
+        if self.unsynced.remove(type_url) && self.unsynced.is_empty() {
+            self.synced.send_replace(true);
+        }
+    }
+
+    fn expire(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let pending = self.pending.keys().map(|t| type_name(t)).join(", ");
+        warn!(%pending, "initial XDS sync timed out, no longer blocking readiness");
+        self.pending.clear();
+        mem::drop(self.block_ready.take());
+    }
 }
 
 /// Demanded allows awaiting for an on-demand XDS resource
@@ -432,6 +845,21 @@ impl Demanded {
 #[derive(Debug, Clone)]
 pub struct Demander {
     demand: mpsc::Sender<(oneshot::Sender<()>, ResourceKey)>,
+    subscriptions: Arc<RwLock<HashMap<Strng, HashSet<Strng>>>>,
+}
+
+// Demanders are serialized as the resources subscribed to on-demand, by type.
+impl serde::Serialize for Demander {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let sorted: BTreeMap<Strng, BTreeSet<Strng>> = self
+            .subscriptions
+            .read()
+            .expect("mutex")
+            .iter()
+            .map(|(type_url, names)| (type_url.clone(), names.iter().cloned().collect()))
+            .collect();
+        sorted.serialize(serializer)
+    }
 }
 
 #[derive(Debug)]
@@ -464,66 +892,171 @@ impl Demander {
     }
 }
 
-const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
-const MAX_BACKOFF: Duration = Duration::from_secs(15);
+// The least time to wait before reconnecting once the server ends the stream, such as when istiod
+// restarts. Every client of that server is disconnected at once, so even with a short initial
+// backoff their reconnects are spread out over a further TERMINATION_RECONNECT_DELAY.
+const TERMINATION_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Backoff computes the delay between XDS reconnect attempts.
+/// Delays grow exponentially up to a maximum, and are randomly reduced by up to the jitter fraction
+/// so that clients disconnected at the same time do not all reconnect at the same time. A jitter of
+/// 1 picks delays uniformly between zero and the backoff.
+#[derive(Clone, Debug)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration, jitter: f64) -> Self {
+        Backoff {
+            initial,
+            max,
+            jitter,
+            current: initial,
+        }
+    }
+
+    // reset restarts the backoff from the initial delay
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    // next returns the delay to wait before the next attempt, and grows the delay for the attempt after
+    fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.max, self.current * 2);
+        self.jittered(delay)
+    }
+
+    // after_termination restarts the backoff, and returns the delay to wait before reconnecting
+    // after the server ended the stream.
+    fn after_termination(&mut self) -> Duration {
+        self.reset();
+        let floor = std::cmp::max(self.initial, TERMINATION_RECONNECT_DELAY);
+        floor + self.jittered(floor)
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        let reduction = rand::thread_rng().gen_range(0.0..=self.jitter);
+        delay.mul_f64(1.0 - reduction)
+    }
+}
 
 impl AdsClient {
     fn is_initial_request_on_demand(r: &DeltaDiscoveryRequest) -> bool {
         !r.resource_names_subscribe.is_empty()
     }
 
-    fn new(config: Config, metrics: Metrics, block_ready: tokio::sync::watch::Sender<()>) -> Self {
+    fn new(
+        mut config: Config,
+        metrics: Metrics,
+        block_ready: tokio::sync::watch::Sender<()>,
+    ) -> Self {
         let (tx, rx) = mpsc::channel(100);
         let state = State {
             known_resources: Default::default(),
             pending: Default::default(),
+            subscriptions: Default::default(),
             demand: rx,
             demand_tx: tx,
+            rejects: Default::default(),
+            versions: Default::default(),
         };
-        let types_to_expect: HashSet<String> = config
+        let pending: HashMap<_, _> = config
             .initial_requests
             .iter()
             .filter(|e| !Self::is_initial_request_on_demand(e)) // is_empty implies not ondemand
-            .map(|e| e.type_url.clone())
+            .map(|e| (e.type_url.clone(), config.type_ready.remove(&e.type_url)))
             .collect();
+        let unsynced: HashSet<_> = pending.keys().cloned().collect();
+        let (synced, _) = tokio::sync::watch::channel(unsynced.is_empty());
         AdsClient {
             config,
             state,
-            metrics,
-            block_ready: Some(block_ready),
+            metrics: Arc::new(metrics),
             connection_id: 0,
-            types_to_expect,
+            initial_sync: Arc::new(Mutex::new(InitialSync {
+                pending,
+                block_ready: Some(block_ready),
+                unsynced,
+                synced,
+            })),
+            heartbeat: None,
+            endpoint: 0,
+            failures: 0,
         }
     }
 
+    /// with_heartbeat makes the client beat `heartbeat` as long as it is making progress, whether
+    /// connected or waiting to reconnect.
+    pub fn with_heartbeat(mut self, heartbeat: readiness::Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
     /// demander returns a Demander instance which can be used to request resources on-demand
     pub fn demander(&self) -> Option<Demander> {
         if self.config.on_demand {
             Some(Demander {
                 demand: self.state.demand_tx.clone(),
+                subscriptions: self.state.subscriptions.clone(),
             })
         } else {
             None
         }
     }
 
-    async fn run_loop(&mut self, backoff: Duration) -> Duration {
-        match self.run_internal().await {
+    /// rejects returns the resources the client rejected.
+    pub fn rejects(&self) -> XdsRejects {
+        self.state.rejects.clone()
+    }
+
+    /// synced returns a receiver that turns true once every watched type (except on-demand ones)
+    /// had a response accepted. Unlike readiness, it is not given up on after a timeout.
+    pub fn synced(&self) -> tokio::sync::watch::Receiver<bool> {
+        self.initial_sync.lock().expect("mutex").synced.subscribe()
+    }
+
+    /// versions returns the versions of the resources the client accepted.
+    pub fn versions(&self) -> XdsVersions {
+        self.state.versions.clone()
+    }
+
+    async fn run_loop(&mut self) {
+        let result = self.run_internal().await;
+        self.metrics.set_connected(false);
+        let delay = match result {
             Err(e @ Error::Connection(_)) => {
                 // For connection errors, we add backoff
-                let backoff = std::cmp::min(MAX_BACKOFF, backoff * 2);
+                let delay = self.config.backoff.next();
                 warn!(
                     "XDS client connection error: {}, retrying in {:?}",
-                    e, backoff
+                    e, delay
                 );
                 self.metrics
                     .increment(&ConnectionTerminationReason::ConnectionError);
-                tokio::time::sleep(backoff).await;
-                backoff
+                self.record_failure();
+                Some(delay)
+            }
+            Err(Error::Failback) => {
+                info!(
+                    address = self.address(),
+                    "failing back to the primary XDS address"
+                );
+                self.metrics
+                    .increment(&ConnectionTerminationReason::Reconnect);
+                self.config.backoff.reset();
+                None
             }
             Err(ref e @ Error::GrpcStatus(ref status)) => {
                 let err_detail = e.to_string();
-                let backoff = if status.code() == tonic::Code::Unknown
+                if status.code() == tonic::Code::Unknown
                     || status.code() == tonic::Code::Cancelled
                     || status.code() == tonic::Code::DeadlineExceeded
                     || (status.code() == tonic::Code::Unavailable
@@ -531,24 +1064,23 @@ impl AdsClient {
                     || (status.code() == tonic::Code::Unavailable
                         && status.message().contains("received prior goaway"))
                 {
+                    // Expected terminations, such as the server restarting.
+                    let delay = self.config.backoff.after_termination();
                     debug!(
                         "XDS client terminated: {}, retrying in {:?}",
-                        err_detail, backoff
+                        err_detail, delay
                     );
                     self.metrics
                         .increment(&ConnectionTerminationReason::Reconnect);
-                    INITIAL_BACKOFF
+                    Some(delay)
                 } else {
-                    warn!(
-                        "XDS client error: {}, retrying in {:?}",
-                        err_detail, backoff
-                    );
-                    self.metrics.increment(&ConnectionTerminationReason::Error);
                     // For gRPC errors, we add backoff
-                    std::cmp::min(MAX_BACKOFF, backoff * 2)
-                };
-                tokio::time::sleep(backoff).await;
-                backoff
+                    let delay = self.config.backoff.next();
+                    warn!("XDS client error: {}, retrying in {:?}", err_detail, delay);
+                    self.metrics.increment(&ConnectionTerminationReason::Error);
+                    self.record_failure();
+                    Some(delay)
+                }
             }
             Err(e) => {
                 // For other errors, we connect immediately
@@ -556,28 +1088,80 @@ impl AdsClient {
                 // But we want to reconnect from MaxConnectionAge immediately.
                 warn!("XDS client error: {}, retrying", e);
                 self.metrics.increment(&ConnectionTerminationReason::Error);
-                // Reset backoff
-                INITIAL_BACKOFF
+                self.config.backoff.reset();
+                self.record_failure();
+                None
             }
             Ok(_) => {
                 self.metrics
                     .increment(&ConnectionTerminationReason::Complete);
-                warn!("XDS client complete");
-                // Reset backoff
-                INITIAL_BACKOFF
+                let delay = self.config.backoff.after_termination();
+                warn!("XDS client complete, reconnecting in {:?}", delay);
+                Some(delay)
             }
+        };
+        if let Some(delay) = delay {
+            beat_while(&self.heartbeat, tokio::time::sleep(delay)).await;
         }
     }
 
+    // The address of the control plane in use.
+    fn address(&self) -> &str {
+        &self.config.addresses[self.endpoint]
+    }
+
+    fn set_endpoint(&mut self, endpoint: usize) {
+        self.endpoint = endpoint;
+        self.failures = 0;
+        self.metrics
+            .set_active_address(&self.config.addresses, endpoint);
+    }
+
+    // record_failure counts a failed attempt on the current address, and fails over to the next
+    // address once enough attempts failed in a row.
+    fn record_failure(&mut self) {
+        self.failures += 1;
+        if self.config.addresses.len() < 2 || self.failures < self.config.failover_threshold {
+            return;
+        }
+        let next = (self.endpoint + 1) % self.config.addresses.len();
+        warn!(
+            from = self.address(),
+            to = self.config.addresses[next],
+            failures = self.failures,
+            "failing over to the next XDS address"
+        );
+        self.metrics.failovers.inc();
+        self.config.backoff.reset();
+        self.set_endpoint(next);
+    }
+
     pub async fn run(mut self) -> Result<(), Error> {
-        let mut backoff = INITIAL_BACKOFF;
+        self.set_endpoint(0);
+        if let Some(timeout) = self.config.initial_sync_timeout {
+            let initial_sync = self.initial_sync.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                initial_sync.lock().expect("mutex").expire();
+            });
+        }
+        // Sampled, so staleness keeps growing while we are disconnected.
+        let versions = self.state.versions.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(STALENESS_INTERVAL);
+            loop {
+                tick.tick().await;
+                metrics.record_staleness(versions.staleness());
+            }
+        });
         loop {
             self.connection_id += 1;
             let id = self.connection_id;
-            backoff = self
-                .run_loop(backoff)
-                .instrument(info_span!("xds", id))
-                .await;
+            if id > 1 {
+                self.metrics.reconnect_attempts.inc();
+            }
+            self.run_loop().instrument(info_span!("xds", id)).await;
         }
     }
 
@@ -600,6 +1184,14 @@ impl AdsClient {
                             .collect()
                     })
                     .unwrap_or_default();
+                // On-demand subscriptions are scoped to a stream, so we need to restore them.
+                if Self::is_initial_request_on_demand(&req) {
+                    let subscriptions = self.state.subscriptions.read().expect("mutex");
+                    if let Some(subs) = subscriptions.get(&strng::new(&req.type_url)) {
+                        req.resource_names_subscribe
+                            .extend(subs.iter().map(|n| n.to_string()));
+                    }
+                }
                 req
             })
             .collect();
@@ -616,40 +1208,89 @@ impl AdsClient {
             warn!("outbound stream complete");
         };
 
-        let tls_grpc_channel = tls::grpc_connector(
-            self.config.address.clone(),
-            self.config.tls_builder.fetch_cert().await?,
-        )?;
+        let address = self.address().to_string();
+        debug!(address, "connecting to XDS");
+        let grpc_channel = match crate::config::uds_path(&address) {
+            // A local agent, which forwards to the control plane; there is no need for TLS.
+            #[cfg(unix)]
+            Some(path) => tls::GrpcChannel::Uds(tls::grpc_uds_connector(path.to_path_buf())),
+            #[cfg(not(unix))]
+            Some(_) => unreachable!("validated by config"),
+            None if self.config.plaintext => {
+                warn!("connecting to XDS without TLS");
+                tls::GrpcChannel::Plaintext(tls::grpc_plaintext_connector(
+                    address.clone(),
+                    &self.config.grpc,
+                )?)
+            }
+            None => tls::GrpcChannel::Tls(tls::grpc_connector(
+                address.clone(),
+                beat_while(&self.heartbeat, self.config.tls_builder.fetch_cert()).await?,
+                &self.config.grpc,
+            )?),
+        };
 
         let ads_connection = AggregatedDiscoveryServiceClient::with_interceptor(
-            tls_grpc_channel,
+            grpc_channel,
             self.config.auth.clone(),
         )
         .max_decoding_message_size(200 * 1024 * 1024)
-        .delta_aggregated_resources(tonic::Request::new(outbound))
-        .await;
+        .delta_aggregated_resources(tonic::Request::new(outbound));
+        // The stream itself is long lived; only bound how long establishing it may take.
+        let ads_connection = tokio::time::timeout(self.config.grpc.request_timeout, ads_connection);
+        let ads_connection = beat_while(&self.heartbeat, ads_connection)
+            .await
+            .unwrap_or_else(|_| {
+                Err(tonic::Status::deadline_exceeded(
+                    "timed out establishing the stream",
+                ))
+            });
 
         let mut response_stream = ads_connection.map_err(Error::Connection)?.into_inner();
         debug!("connected established");
 
-        info!("Stream established");
+        info!(address, "Stream established");
+        self.metrics.set_connected(true);
+        self.failures = 0;
+        // While on a failover address, periodically go back to the primary to check whether it
+        // recovered.
+        let failback = tokio::time::sleep(self.config.failback_interval);
+        tokio::pin!(failback);
+        // While connected, the heartbeat is only beaten from the stream loop, so a stream stuck
+        // handling an event is reported as stalled.
+        let mut heartbeat_tick = tokio::time::interval(
+            self.heartbeat
+                .as_ref()
+                .map(readiness::Heartbeat::interval)
+                .unwrap_or(Duration::from_secs(60)),
+        );
         loop {
             tokio::select! {
+                _ = heartbeat_tick.tick(), if self.heartbeat.is_some() => {
+                    if let Some(hb) = &self.heartbeat {
+                        hb.beat();
+                    }
+                }
+                _ = &mut failback, if self.endpoint != 0 => {
+                    self.set_endpoint(0);
+                    return Err(Error::Failback);
+                }
                 _demand_event = self.state.demand.recv() => {
                     self.handle_demand_event(_demand_event, &discovery_req_tx).await?;
                 }
                 msg = response_stream.message() => {
                     let msg = msg?;
                     let mut received_type = None;
-                    if !self.types_to_expect.is_empty() {
+                    let awaiting_sync = {
+                        let sync = self.initial_sync.lock().expect("mutex");
+                        !sync.is_complete() || !sync.is_synced()
+                    };
+                    if awaiting_sync {
                         received_type = msg.as_ref().map(|e| e.type_url.clone());
                     }
                     if let XdsSignal::Ack = self.handle_stream_event(msg, &discovery_req_tx).await? {
                         if let Some(received_type) = received_type {
-                            self.types_to_expect.remove(&received_type);
-                            if self.types_to_expect.is_empty() {
-                                mem::drop(mem::take(&mut self.block_ready));
-                            }
+                            self.initial_sync.lock().expect("mutex").complete(&received_type);
                         }
                     };
                 }
@@ -686,15 +1327,20 @@ impl AdsClient {
 
         let (response_type, error) = match handler_response {
             Err(rejects) => {
-                let error = rejects
-                    .into_iter()
-                    .map(|reject| reject.to_string())
-                    .collect::<Vec<String>>()
-                    .join("; ");
-                (XdsSignal::Nack, Some(error))
+                warn!(%type_url, rejects = rejects.len(), "rejecting resources");
+                (XdsSignal::Nack, Some(nack_status(&rejects)))
             }
             _ => (XdsSignal::Ack, None),
         };
+        self.metrics.record_response(
+            &type_url,
+            self.state
+                .known_resources
+                .get(&strng::new(&type_url))
+                .map(HashSet::len)
+                .unwrap_or_default(),
+            matches!(response_type, XdsSignal::Ack),
+        );
 
         debug!(
             type_url=type_url,
@@ -705,10 +1351,7 @@ impl AdsClient {
         send.send(DeltaDiscoveryRequest {
             type_url,              // this is owned, OK to move
             response_nonce: nonce, // this is owned, OK to move
-            error_detail: error.map(|msg| Status {
-                message: msg,
-                ..Default::default()
-            }),
+            error_detail: error,
             ..Default::default()
         })
         .await
@@ -728,6 +1371,13 @@ impl AdsClient {
         let ResourceKey { type_url, name } = demand_event.clone();
         self.state.pending.insert(demand_event, tx);
         self.state.add_resource(type_url.clone(), name.clone());
+        self.state
+            .subscriptions
+            .write()
+            .expect("mutex")
+            .entry(type_url.clone())
+            .or_default()
+            .insert(name.clone());
         send.send(DeltaDiscoveryRequest {
             type_url: type_url.to_string(),
             resource_names_subscribe: vec![name.to_string()],
@@ -760,6 +1410,42 @@ impl<T: prost::Message> XdsUpdate<T> {
     }
 }
 
+/// Awaits `fut`, keeping the heartbeat (if any) alive while it is pending.
+async fn beat_while<F: std::future::Future>(
+    heartbeat: &Option<readiness::Heartbeat>,
+    fut: F,
+) -> F::Output {
+    match heartbeat {
+        Some(hb) => hb.beat_while(fut).await,
+        None => fut.await,
+    }
+}
+
+// Parses a Downward API labels file, which has a `key="value"` line per label. Values are quoted
+// Go strings; only the escapes valid in label values (quotes and backslashes) are handled.
+fn parse_downward_api_labels(labels: &str) -> BTreeMap<String, String> {
+    labels
+        .lines()
+        .filter_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(v);
+            Some((
+                k.trim().to_string(),
+                v.replace("\\\"", "\"").replace("\\\\", "\\"),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the short name of a type URL, e.g. "Address" for "type.googleapis.com/istio.workload.Address".
+fn type_name(type_url: &str) -> &str {
+    type_url.rsplit(['/', '.']).next().unwrap_or(type_url)
+}
+
 fn decode_proto<T: prost::Message + Default>(
     resource: &ProtoResource,
 ) -> Result<XdsResource<T>, AdsError> {
@@ -808,7 +1494,7 @@ mod tests {
     use crate::state::{workload, DemandProxyState};
     use crate::test_helpers::{
         helpers::{self},
-        xds::AdsServer,
+        xds::{AdsConnection, AdsServer},
     };
 
     use super::*;
@@ -857,6 +1543,7 @@ mod tests {
                     }],
                 }],
             }],
+            dry_run: false,
         };
         ProtoResource {
             name: format!("foo{}", i),
@@ -905,6 +1592,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn initial_sync_readiness() {
+        let ready = readiness::Ready::new();
+        let parent = ready.register_task("state manager");
+        let new_sync = || {
+            let (tx, rx) = tokio::sync::watch::channel(());
+            let pending = [ADDRESS_TYPE, AUTHORIZATION_TYPE]
+                .into_iter()
+                .map(|t| {
+                    let task = parent.subtask(&format!("xds {}", type_name(&t)));
+                    (t.to_string(), Some(task))
+                })
+                .collect();
+            let sync = InitialSync {
+                pending,
+                block_ready: Some(tx),
+                unsynced: HashSet::from([ADDRESS_TYPE.to_string(), AUTHORIZATION_TYPE.to_string()]),
+                synced: tokio::sync::watch::channel(false).0,
+            };
+            (sync, rx)
+        };
+
+        // Each type unblocks readiness independently; the sender is only dropped once all are done.
+        let (mut sync, rx) = new_sync();
+        sync.complete(&ADDRESS_TYPE);
+        assert!(ready.pending().contains("xds Authorization"));
+        assert!(!ready.pending().contains("xds Address"));
+        assert!(rx.has_changed().is_ok());
+        sync.complete(&AUTHORIZATION_TYPE);
+        assert!(sync.is_complete());
+        assert!(sync.is_synced());
+        assert!(*sync.synced.borrow());
+        assert!(!ready.pending().contains("xds Authorization"));
+        assert!(rx.has_changed().is_err());
+
+        // Expiring releases everything still pending.
+        let (mut sync, rx) = new_sync();
+        sync.complete(&ADDRESS_TYPE);
+        sync.expire();
+        assert!(sync.is_complete());
+        // But the sync itself is still awaited.
+        assert!(!sync.is_synced());
+        assert!(!*sync.synced.borrow());
+        assert_eq!(
+            ready.pending(),
+            HashSet::from(["state manager".to_string()])
+        );
+        assert!(rx.has_changed().is_err());
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_that_caches_are_warm_before_unblocked() {
         helpers::initialize_telemetry();
@@ -1045,6 +1782,28 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_plaintext() {
+        helpers::initialize_telemetry();
+
+        let (mut conn_receiver, client, _, _) = AdsServer::spawn_plaintext(false).await;
+        tokio::spawn(async move {
+            if let Err(e) = client.run().await {
+                info!("workload manager: {}", e);
+            }
+        });
+
+        let mut conn = tokio::time::timeout(Duration::from_secs(1), conn_receiver.recv())
+            .await
+            .expect("client should connect")
+            .unwrap();
+        let req = tokio::time::timeout(Duration::from_secs(1), conn.rx.recv())
+            .await
+            .expect("client should send its initial requests")
+            .unwrap();
+        assert!([ADDRESS_TYPE, AUTHORIZATION_TYPE].contains(&strng::new(&req.type_url)));
+    }
+
     // Tests that when the client processes a large response, the on-demand clients are notified
     // after contents of the cache were updated.
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -1116,6 +1875,102 @@ mod tests {
         }
     }
 
+    // Tests that on-demand subscriptions are restored on reconnect, and that removing a resource
+    // requested by address removes the workload from the cache.
+    #[tokio::test]
+    async fn test_on_demand_resubscribe_and_remove() {
+        helpers::initialize_telemetry();
+
+        let (mut conn_receiver, client, state, _) = AdsServer::spawn(true).await;
+        let demander = client.demander().unwrap();
+        tokio::spawn(async move {
+            if let Err(e) = client.run().await {
+                info!("workload manager: {}", e);
+            }
+        });
+        let ip = Ipv4Addr::new(127, 0, 0, 1);
+        let demanded = demander.demand(ADDRESS_TYPE, "/127.0.0.1".into()).await;
+
+        async fn next_address_request(conn: &mut AdsConnection) -> DeltaDiscoveryRequest {
+            let timer = tokio::time::sleep(std::time::Duration::from_secs(1));
+            futures::pin_mut!(timer);
+            loop {
+                let req = tokio::select! {
+                    _ = &mut timer => panic!("expected requests were not received"),
+                    req = conn.rx.recv() => req.unwrap(),
+                };
+                if req.type_url == ADDRESS_TYPE && !req.resource_names_subscribe.is_empty() {
+                    return req;
+                }
+            }
+        }
+
+        let mut conn = conn_receiver.recv().await.unwrap();
+        assert_eq!(
+            next_address_request(&mut conn)
+                .await
+                .resource_names_subscribe,
+            vec!["*"]
+        );
+        assert_eq!(
+            next_address_request(&mut conn)
+                .await
+                .resource_names_subscribe,
+            vec!["/127.0.0.1"]
+        );
+        let mut resource = get_address(0, ip.into());
+        resource.name = "/127.0.0.1".to_string();
+        conn.tx
+            .send(Ok(DeltaDiscoveryResponse {
+                resources: vec![resource],
+                nonce: TextNonce::new().to_string(),
+                type_url: ADDRESS_TYPE.to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        demanded.recv().await;
+        // Subscriptions are reported through the demander, for config dumps
+        assert_eq!(
+            serde_json::to_value(&demander).unwrap(),
+            serde_json::json!({ ADDRESS_TYPE: ["/127.0.0.1"] })
+        );
+        let addr = NetworkAddress {
+            network: strng::EMPTY,
+            address: ip.into(),
+        };
+        assert!(state.read().find_address(&addr).is_some());
+
+        // After a reconnect, the on-demand subscription is restored
+        conn.tx
+            .send(Err(tonic::Status::aborted("Aborting for test.")))
+            .await
+            .unwrap();
+        let mut conn = conn_receiver.recv().await.unwrap();
+        let req = next_address_request(&mut conn).await;
+        assert_eq!(req.resource_names_subscribe, vec!["*", "/127.0.0.1"]);
+        assert_eq!(req.resource_names_unsubscribe, vec!["*"]);
+
+        // Removal is keyed by the name we requested, rather than the workload UID
+        conn.tx
+            .send(Ok(DeltaDiscoveryResponse {
+                nonce: TextNonce::new().to_string(),
+                type_url: ADDRESS_TYPE.to_string(),
+                removed_resources: vec!["/127.0.0.1".into()],
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let start = std::time::Instant::now();
+        while state.read().find_address(&addr).is_some() {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "workload not removed"
+            );
+            sleep(POLL_RATE).await;
+        }
+    }
+
     #[tokio::test]
     async fn test_add_abort_remove() {
         helpers::initialize_telemetry();
@@ -1209,6 +2064,154 @@ mod tests {
         verify_address(IpAddr::V4(ip), None, &state).await;
     }
 
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new(Duration::from_millis(10), Duration::from_millis(50), 0.0);
+        let delays: Vec<_> = (0..5).map(|_| b.next().as_millis()).collect();
+        assert_eq!(delays, vec![10, 20, 40, 50, 50]);
+        b.reset();
+        assert_eq!(b.next(), Duration::from_millis(10));
+
+        let mut b = Backoff::new(Duration::from_secs(10), Duration::from_secs(10), 0.5);
+        for _ in 0..100 {
+            let delay = b.next();
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        }
+
+        // Full jitter spreads delays over the whole backoff
+        let mut b = Backoff::new(Duration::from_secs(10), Duration::from_secs(10), 1.0);
+        let delays: Vec<_> = (0..100).map(|_| b.next()).collect();
+        assert!(delays.iter().all(|d| *d <= Duration::from_secs(10)));
+        assert!(delays.iter().any(|d| *d < Duration::from_secs(5)));
+
+        // Reconnects after the server ends the stream wait at least the floor, however short the
+        // initial backoff is, and restart the backoff.
+        let mut b = Backoff::new(Duration::from_millis(10), Duration::from_secs(10), 1.0);
+        b.next();
+        b.next();
+        for _ in 0..100 {
+            let delay = b.after_termination();
+            assert!(
+                delay >= TERMINATION_RECONNECT_DELAY && delay <= TERMINATION_RECONNECT_DELAY * 2
+            );
+        }
+        assert!(b.next() <= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn test_rejects() {
+        let rejects = XdsRejects::default();
+        let type_url = strng::new(ADDRESS_TYPE);
+        let bad = |name: &str| RejectedConfig::new(name.into(), anyhow::anyhow!("invalid"));
+
+        rejects.record(&type_url, ["a", "b"].into_iter(), &[bad("a"), bad("b")]);
+        let names: Vec<_> = rejects.dump().into_iter().map(|r| r.name).collect();
+        assert_eq!(names, vec![strng::new("a"), strng::new("b")]);
+
+        // An accepted update clears the reject
+        rejects.record(&type_url, ["a"].into_iter(), &[]);
+        let dump = rejects.dump();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].name, "b");
+        assert_eq!(dump[0].reason, "invalid");
+
+        let status = nack_status(&[bad("a"), bad("b")]);
+        assert_eq!(status.code, INVALID_ARGUMENT);
+        assert_eq!(status.message, "a: invalid; b: invalid");
+        let details: BadRequest =
+            prost::Message::decode(status.details[0].value.as_slice()).unwrap();
+        assert_eq!(details.field_violations.len(), 2);
+        assert_eq!(details.field_violations[1].field, "b");
+    }
+
+    #[test]
+    fn test_versions() {
+        let versions = XdsVersions::default();
+        let type_url = strng::new(ADDRESS_TYPE);
+
+        versions.record(
+            &type_url,
+            "n1",
+            [("a", "v1"), ("b", "v1")].into_iter(),
+            std::iter::empty(),
+            false,
+        );
+        // The valid resources of a NACKed response are tracked, but the type is not accepted.
+        let dump = versions.dump();
+        assert_eq!(dump.resources.len(), 2);
+        assert!(dump.types.is_empty());
+
+        versions.record(
+            &type_url,
+            "n2",
+            [("a", "v2")].into_iter(),
+            ["b"].into_iter(),
+            true,
+        );
+        let dump = versions.dump();
+        assert_eq!(dump.resources.len(), 1);
+        assert_eq!(dump.resources[0].name, "a");
+        assert_eq!(dump.resources[0].version, "v2");
+        assert_eq!(dump.resources[0].nonce, "n2");
+        assert_eq!(dump.types.len(), 1);
+        assert_eq!(versions.staleness()[0].0, type_url);
+    }
+
+    #[test]
+    fn test_parse_downward_api_labels() {
+        let labels = parse_downward_api_labels(
+            "app=\"ztunnel\"\nistio.io/rev=\"canary\"\nquoted=\"a\\\"b\"\n\n",
+        );
+        assert_eq!(
+            labels,
+            BTreeMap::from([
+                ("app".to_string(), "ztunnel".to_string()),
+                ("istio.io/rev".to_string(), "canary".to_string()),
+                ("quoted".to_string(), "a\"b".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_node_metadata() {
+        let path = std::env::temp_dir().join(format!("ztunnel-labels-{}", std::process::id()));
+        std::fs::write(&path, "app=\"ztunnel\"\n").unwrap();
+        let cfg = crate::config::Config {
+            xds_address: Some("https://istiod:15012".to_string()),
+            local_node: Some("node-1".to_string()),
+            pod_name: Some("ztunnel-abc".to_string()),
+            pod_namespace: Some("istio-system".to_string()),
+            local_ip: Some("10.0.0.1".parse().unwrap()),
+            network: strng::new("net-1"),
+            cluster_id: "cluster-1".to_string(),
+            pod_labels_path: Some(path.clone()),
+            ..crate::test_helpers::test_config()
+        };
+        let tls = Box::new(tls::ControlPlaneAuthentication::RootCert(
+            cfg.xds_root_cert.clone(),
+            cfg.tls_settings.clone(),
+        ));
+        let metadata = Config::new(Arc::new(cfg), tls).node().metadata.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let string = |key: &str| match &metadata.fields[key].kind {
+            Some(Kind::StringValue(v)) => v.clone(),
+            other => panic!("unexpected {key}: {other:?}"),
+        };
+        assert_eq!(string(NODE_NAME), "node-1");
+        assert_eq!(string(NAME), "ztunnel-abc");
+        assert_eq!(string(NAMESPACE), "istio-system");
+        assert_eq!(string(INSTANCE_IPS), "10.0.0.1");
+        assert_eq!(string(NETWORK), "net-1");
+        assert_eq!(string(CLUSTER_ID), "cluster-1");
+        assert_eq!(
+            metadata.fields[LABELS].kind,
+            Some(Kind::StructValue(Config::build_struct([(
+                "app", "ztunnel"
+            )])))
+        );
+    }
+
     #[test]
     fn test_json_to_value() {
         use prost_types::value::Kind::*;
@@ -15,12 +15,46 @@
 use prometheus_client::encoding::{EncodeLabelSet, EncodeLabelValue};
 use prometheus_client::metrics::counter::Counter;
 use prometheus_client::metrics::family::Family;
-use prometheus_client::registry::Registry;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::{Registry, Unit};
+use std::time::{Duration, SystemTime};
 
 use crate::metrics::Recorder;
+use crate::strng::{RichStrng, Strng};
 
 pub struct Metrics {
     pub connection_terminations: Family<ConnectionTermination, Counter>,
+    pub reconnect_attempts: Counter,
+    pub connected: Gauge,
+    pub resources: Family<TypeLabels, Gauge>,
+    pub last_update: Family<TypeLabels, Gauge>,
+    pub nacks: Family<TypeLabels, Counter>,
+    pub staleness: Family<TypeLabels, Gauge>,
+    pub active_address: Family<AddressLabels, Gauge>,
+    pub failovers: Counter,
+    pub state_limit_exceeded: Family<StateLimitLabels, Counter>,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct TypeLabels {
+    pub type_url: RichStrng,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct AddressLabels {
+    pub address: RichStrng,
+}
+
+#[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
+pub struct StateLimitLabels {
+    pub kind: StateKind,
+}
+
+#[derive(Copy, Clone, Hash, Debug, PartialEq, Eq, EncodeLabelValue)]
+pub enum StateKind {
+    Workload,
+    Service,
+    Policy,
 }
 
 #[derive(Clone, Hash, Debug, PartialEq, Eq, EncodeLabelSet)]
@@ -45,8 +79,127 @@ impl Metrics {
             connection_terminations.clone(),
         );
 
+        let reconnect_attempts = Counter::default();
+        registry.register(
+            "xds_reconnect_attempts",
+            "The total number of attempts to reconnect to xds server (unstable)",
+            reconnect_attempts.clone(),
+        );
+
+        let connected = Gauge::default();
+        registry.register(
+            "xds_connected",
+            "Whether the xds stream is currently established (unstable)",
+            connected.clone(),
+        );
+
+        let resources = Family::default();
+        registry.register(
+            "xds_resources",
+            "The number of resources currently known, by type (unstable)",
+            resources.clone(),
+        );
+
+        let last_update = Family::default();
+        registry.register_with_unit(
+            "xds_last_update_timestamp",
+            "Unix time of the last accepted xds update, by type (unstable)",
+            Unit::Seconds,
+            last_update.clone(),
+        );
+
+        let nacks = Family::default();
+        registry.register(
+            "xds_nacks",
+            "The total number of xds responses rejected, by type (unstable)",
+            nacks.clone(),
+        );
+
+        let staleness = Family::default();
+        registry.register_with_unit(
+            "xds_staleness",
+            "Time since an xds response was last accepted, by type (unstable)",
+            Unit::Seconds,
+            staleness.clone(),
+        );
+
+        let active_address = Family::default();
+        registry.register(
+            "xds_active_address",
+            "Whether the xds address is the one in use, by address (unstable)",
+            active_address.clone(),
+        );
+
+        let failovers = Counter::default();
+        registry.register(
+            "xds_failovers",
+            "The total number of failovers to the next xds address (unstable)",
+            failovers.clone(),
+        );
+
+        let state_limit_exceeded = Family::default();
+        registry.register(
+            "xds_state_limit_exceeded",
+            "The total number of xds resources added over a state limit, by kind (unstable)",
+            state_limit_exceeded.clone(),
+        );
+
         Self {
             connection_terminations,
+            reconnect_attempts,
+            connected,
+            resources,
+            last_update,
+            nacks,
+            staleness,
+            active_address,
+            failovers,
+            state_limit_exceeded,
+        }
+    }
+
+    /// Marks the address at index `active` as the one in use.
+    pub fn set_active_address(&self, addresses: &[String], active: usize) {
+        for (i, address) in addresses.iter().enumerate() {
+            self.active_address
+                .get_or_create(&AddressLabels {
+                    address: address.as_str().into(),
+                })
+                .set((i == active) as i64);
+        }
+    }
+
+    /// Records how long ago a response of each type was last accepted.
+    pub fn record_staleness(&self, staleness: impl IntoIterator<Item = (Strng, Duration)>) {
+        for (type_url, staleness) in staleness {
+            self.staleness
+                .get_or_create(&TypeLabels {
+                    type_url: type_url.into(),
+                })
+                .set(staleness.as_secs() as i64);
+        }
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.set(connected as i64);
+    }
+
+    /// Records the outcome of handling a response of the given type. `resources` is the number
+    /// of resources of that type known after the response was applied.
+    pub fn record_response(&self, type_url: &str, resources: usize, accepted: bool) {
+        let labels = TypeLabels {
+            type_url: type_url.into(),
+        };
+        self.resources.get_or_create(&labels).set(resources as i64);
+        if accepted {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            self.last_update
+                .get_or_create(&labels)
+                .set(now.as_secs() as i64);
+        } else {
+            self.nacks.get_or_create(&labels).inc();
         }
     }
 }
@@ -58,3 +211,60 @@ impl Recorder<ConnectionTerminationReason, u64> for Metrics {
             .inc_by(count);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_metrics() {
+        let mut registry = Registry::default();
+        let m = Metrics::new(&mut registry);
+        let type_url = "type.googleapis.com/istio.workload.Address";
+        let labels = TypeLabels {
+            type_url: type_url.into(),
+        };
+
+        m.set_connected(true);
+        assert_eq!(m.connected.get(), 1);
+
+        m.record_response(type_url, 3, true);
+        assert_eq!(m.resources.get_or_create(&labels).get(), 3);
+        assert!(m.last_update.get_or_create(&labels).get() > 0);
+        assert_eq!(m.nacks.get_or_create(&labels).get(), 0);
+
+        // A rejected response counts as a NACK, and doesn't move the last update time.
+        m.last_update.get_or_create(&labels).set(1);
+        m.record_response(type_url, 2, false);
+        assert_eq!(m.resources.get_or_create(&labels).get(), 2);
+        assert_eq!(m.last_update.get_or_create(&labels).get(), 1);
+        assert_eq!(m.nacks.get_or_create(&labels).get(), 1);
+
+        m.set_connected(false);
+        assert_eq!(m.connected.get(), 0);
+
+        m.record_staleness([(type_url.into(), Duration::from_secs(30))]);
+        assert_eq!(m.staleness.get_or_create(&labels).get(), 30);
+    }
+
+    #[test]
+    fn active_address() {
+        let mut registry = Registry::default();
+        let m = Metrics::new(&mut registry);
+        let addresses = vec!["https://a:15012".to_string(), "https://b:15012".to_string()];
+        let active = |address: &str| {
+            m.active_address
+                .get_or_create(&AddressLabels {
+                    address: address.into(),
+                })
+                .get()
+        };
+
+        m.set_active_address(&addresses, 1);
+        assert_eq!(active("https://a:15012"), 0);
+        assert_eq!(active("https://b:15012"), 1);
+        m.set_active_address(&addresses, 0);
+        assert_eq!(active("https://a:15012"), 1);
+        assert_eq!(active("https://b:15012"), 0);
+    }
+}
@@ -75,7 +75,7 @@ async fn test_conflicting_bind_error_inbound() {
 
 #[tokio::test]
 async fn test_conflicting_bind_error_inbound_plaintext() {
-    test_bind_conflict(|c| &mut c.inbound_plaintext_addr).await;
+    test_bind_conflict(|c| &mut c.inbound_plaintext_addr[0]).await;
 }
 
 #[tokio::test]
@@ -500,6 +500,7 @@ mod namespaced {
                     )],
                     ..Default::default()
                 }]]],
+                dry_run: false,
             })
             .await?;
         let _ = manager
@@ -530,7 +531,15 @@ mod namespaced {
                     identity::Identity::from_str("spiffe://cluster.local/ns/default/sa/server")
                         .unwrap();
                 let cert = zt.cert_manager.fetch_certificate(id).await?;
-                let connector = cert.outbound_connector(vec![dst_id]).unwrap();
+                let connector = cert
+                    .outbound_connector(
+                        &Default::default(),
+                        vec![dst_id],
+                        None,
+                        &[],
+                        &Default::default(),
+                    )
+                    .unwrap();
                 let hbone = SocketAddr::new(srv.ip(), 15008);
                 let tcp_stream = TcpStream::connect(hbone).await.unwrap();
                 let tls_stream = connector.connect(tcp_stream).await.unwrap();
@@ -551,7 +560,10 @@ mod namespaced {
             .unwrap()?;
         telemetry::testing::assert_contains(HashMap::from([
             ("target", "access"),
-            ("error", "connection closed due to policy rejection"),
+            (
+                "error",
+                "connection closed due to policy rejection: not allowed by any policy",
+            ),
         ]));
         Ok(())
     }
@@ -589,7 +601,15 @@ mod namespaced {
                     identity::Identity::from_str("spiffe://cluster.local/ns/default/sa/server")
                         .unwrap();
                 let cert = zt.cert_manager.fetch_certificate(id).await?;
-                let connector = cert.outbound_connector(vec![dst_id]).unwrap();
+                let connector = cert
+                    .outbound_connector(
+                        &Default::default(),
+                        vec![dst_id],
+                        None,
+                        &[],
+                        &Default::default(),
+                    )
+                    .unwrap();
                 let tcp_stream = TcpStream::connect(SocketAddr::from((srv.ip(), 15008)))
                     .await
                     .unwrap();